@@ -0,0 +1,154 @@
+//! # ELF Reader
+//!
+//! Just enough of the ELF64 format to load the kernel: the header checks,
+//! the entry point, and the loadable segments. The kernel is a static PIE
+//! whose relocations are already applied for its link address, so the
+//! segments are loaded where their virtual addresses say, unchanged.
+
+use crate::Error;
+
+/// `\x7fELF`
+const MAGIC: [u8; 4] = *b"\x7fELF";
+/// `EI_CLASS`: 64-bit
+const CLASS_64: u8 = 2;
+/// `EI_DATA`: little-endian
+const DATA_LSB: u8 = 1;
+/// `e_type`: executable
+const TYPE_EXEC: u16 = 2;
+/// `e_type`: position-independent executable
+const TYPE_DYN: u16 = 3;
+/// `e_machine`: x86_64
+const MACHINE_X86_64: u16 = 62;
+
+/// Size of the ELF64 header.
+const HEADER_SIZE: usize = 64;
+/// Size of an ELF64 program header.
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+/// Program header type: loadable segment.
+const PT_LOAD: u32 = 1;
+/// Segment flag: executable.
+const PF_X: u32 = 1 << 0;
+/// Segment flag: writable.
+const PF_W: u32 = 1 << 1;
+
+/// A loadable segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    /// Virtual address of the first byte
+    pub vaddr: u64,
+    /// Offset of its contents in the file
+    pub offset: u64,
+    /// Bytes taken from the file
+    pub file_size: u64,
+    /// Bytes in memory, the rest zeroed
+    pub mem_size: u64,
+    /// The segment is writable
+    pub writable: bool,
+    /// The segment is executable
+    pub executable: bool,
+}
+
+/// A validated ELF64 image.
+pub struct Elf<'a> {
+    /// The whole file
+    data: &'a [u8],
+    /// Entry point
+    entry: u64,
+    /// Offset of the program headers
+    phoff: usize,
+    /// Size of a program header
+    phentsize: usize,
+    /// Number of program headers
+    phnum: usize,
+}
+
+impl<'a> Elf<'a> {
+    /// Checks that `data` is an x86_64 ELF64 executable.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if it is not, or if its program headers or
+    /// segments lie outside `data`.
+    pub fn parse(data: &'a [u8]) -> Result<Self, Error> {
+        let header = data
+            .get(..HEADER_SIZE)
+            .ok_or(Error::new("kernel too small"))?;
+        if header[..4] != MAGIC || header[4] != CLASS_64 || header[5] != DATA_LSB {
+            return Err(Error::new("kernel is not an ELF64 file"));
+        }
+        let kind = read_u16(header, 16);
+        if !matches!(kind, TYPE_EXEC | TYPE_DYN) || read_u16(header, 18) != MACHINE_X86_64 {
+            return Err(Error::new("kernel is not an x86_64 executable"));
+        }
+        let elf = Elf {
+            data,
+            entry: read_u64(header, 24),
+            phoff: read_u64(header, 32) as usize,
+            phentsize: usize::from(read_u16(header, 54)),
+            phnum: usize::from(read_u16(header, 56)),
+        };
+        let table_end = elf
+            .phentsize
+            .checked_mul(elf.phnum)
+            .and_then(|size| size.checked_add(elf.phoff));
+        if elf.phentsize < PROGRAM_HEADER_SIZE || table_end.is_none_or(|end| end > data.len()) {
+            return Err(Error::new("kernel program headers are damaged"));
+        }
+        if elf
+            .segments()
+            .any(|segment| segment.offset.saturating_add(segment.file_size) > data.len() as u64)
+        {
+            return Err(Error::new("kernel segment outside the file"));
+        }
+        Ok(elf)
+    }
+
+    /// The virtual address execution starts at.
+    pub fn entry(&self) -> u64 {
+        self.entry
+    }
+
+    /// Returns the loadable segments.
+    pub fn segments(&self) -> impl Iterator<Item = Segment> + '_ {
+        (0..self.phnum)
+            .map(|index| &self.data[self.phoff + index * self.phentsize..])
+            .filter(|header| read_u32(header, 0) == PT_LOAD)
+            .map(|header| {
+                let flags = read_u32(header, 4);
+                Segment {
+                    vaddr: read_u64(header, 16),
+                    offset: read_u64(header, 8),
+                    file_size: read_u64(header, 32),
+                    mem_size: read_u64(header, 40),
+                    writable: flags & PF_W != 0,
+                    executable: flags & PF_X != 0,
+                }
+            })
+    }
+
+    /// Returns the file contents of `segment`.
+    pub fn contents(&self, segment: &Segment) -> &'a [u8] {
+        let start = segment.offset as usize;
+        &self.data[start..start + segment.file_size as usize]
+    }
+}
+
+/// Reads a little-endian `u16` at `offset`.
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+/// Reads a little-endian `u32` at `offset`.
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(buf)
+}
+
+/// Reads a little-endian `u64` at `offset`.
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(buf)
+}
@@ -0,0 +1,79 @@
+//! # Kernel Handoff
+//!
+//! The boot information passed to the kernel. This mirrors the kernel's
+//! `boot::uefi` module (`packages/espress-os/src/boot/uefi.rs`) and must be
+//! kept in sync with it; [`VERSION`] is bumped on every change.
+
+/// Value passed in `rsi` so the kernel knows who started it.
+pub const MAGIC: u64 = u64::from_le_bytes(*b"EspUEFI\0");
+
+/// Version of the [`BootInfo`] layout.
+pub const VERSION: u32 = 2;
+
+/// Most memory map entries passed.
+pub const MAX_REGIONS: usize = 256;
+
+/// Memory type: the kernel image and its boot stack.
+pub const MEMORY_KERNEL: u32 = 0x8000_0000;
+/// Memory type: the page tables set up for the kernel.
+pub const MEMORY_PAGE_TABLE: u32 = 0x8000_0001;
+/// Memory type: the [`BootInfo`].
+pub const MEMORY_BOOT_INFO: u32 = 0x8000_0002;
+
+/// A memory map entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct MemoryRegion {
+    /// First physical address
+    pub start: u64,
+    /// Length in bytes
+    pub len: u64,
+    /// UEFI memory type, or one of the `MEMORY_*` types
+    pub kind: u32,
+    /// Unused
+    pub reserved: u32,
+}
+
+/// The GOP framebuffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct FramebufferInfo {
+    /// Physical address, 0 if there is no usable framebuffer
+    pub address: u64,
+    /// Size in bytes
+    pub size: u64,
+    /// Visible width in pixels
+    pub width: u32,
+    /// Visible height in pixels
+    pub height: u32,
+    /// Pixels per scan line
+    pub stride: u32,
+    /// GOP pixel format
+    pub format: u32,
+    /// Red, green, and blue masks of the bitmask format
+    pub masks: [u32; 3],
+    /// Unused
+    pub reserved: u32,
+}
+
+/// Everything handed over to the kernel.
+#[derive(Debug)]
+#[repr(C)]
+pub struct BootInfo {
+    /// [`VERSION`]
+    pub version: u32,
+    /// Number of valid entries in [`regions`](Self::regions)
+    pub region_count: u32,
+    /// Virtual address at which all physical memory is mapped
+    pub physical_memory_offset: u64,
+    /// Physical address of the RSDP, 0 if the firmware has none
+    pub rsdp: u64,
+    /// Physical address of the SMBIOS entry point, 0 if the firmware has
+    /// none
+    pub smbios: u64,
+    /// The framebuffer
+    pub framebuffer: FramebufferInfo,
+    /// The firmware memory map at exit from boot services, sorted by
+    /// address
+    pub regions: [MemoryRegion; MAX_REGIONS],
+}
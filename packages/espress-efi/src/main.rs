@@ -0,0 +1,654 @@
+//! # EspressOS UEFI Loader
+//!
+//! A UEFI application that starts the EspressOS kernel on machines without
+//! legacy BIOS. It reads the kernel ELF from [`KERNEL_PATH`] on the volume
+//! it was itself loaded from, and sets up the address space the kernel
+//! expects from the `bootloader` crate:
+//!
+//! - all physical memory mapped at [`PHYS_MAP_BASE`] (and, until the kernel
+//!   drops the lower half, at 0),
+//! - each loadable segment at its link address, with its permissions,
+//! - a boot stack at [`STACK_ADDRESS`] above an unmapped guard page.
+//!
+//! It then records the GOP framebuffer, the ACPI RSDP, and the SMBIOS entry
+//! point, exits boot
+//! services, writes the final memory map into the [`handoff::BootInfo`],
+//! and jumps to the kernel's entry point with a pointer to it in `rdi` and
+//! [`handoff::MAGIC`] in `rsi`.
+//!
+//! Everything the kernel keeps is allocated with the memory types of
+//! [`handoff`], so that the kernel does not mistake it for free memory.
+//!
+//! The kernel command line is fixed when the kernel is built, from the
+//! `ESPRESS_OS_CMDLINE` environment variable. The loader reads the same
+//! variable when it is built and, if it holds a `video=<width>x<height>`
+//! option, switches the GOP to that mode before recording the framebuffer;
+//! the kernel reports if it got a different one.
+//!
+//! To boot, copy the loader to `\EFI\BOOT\BOOTX64.EFI` on a FAT volume and
+//! the kernel ELF next to it as `\espress-os.elf`; `make run-uefi` in the
+//! kernel package does this for QEMU with OVMF.
+
+#![no_std]
+#![no_main]
+
+mod elf;
+mod handoff;
+mod paging;
+
+use core::ffi::c_void;
+use core::mem::size_of;
+use core::ptr;
+use r_efi::efi;
+use r_efi::efi::protocols::{file, graphics_output, loaded_image, simple_file_system};
+
+use elf::Elf;
+use handoff::{BootInfo, FramebufferInfo, MemoryRegion};
+use paging::{PageTables, LARGE_PAGE_SIZE, NO_EXECUTE, PAGE_SIZE, WRITABLE};
+
+/// Path of the kernel ELF on the boot volume.
+const KERNEL_PATH: &str = "\\espress-os.elf";
+
+/// Virtual address of the linear physical memory mapping.
+///
+/// Must match `physical-memory-offset` in the kernel's bootloader
+/// configuration.
+const PHYS_MAP_BASE: u64 = 0xffff_8000_0000_0000;
+
+/// Virtual address of the boot stack's guard page.
+///
+/// Must match `kernel-stack-address` in the kernel's bootloader
+/// configuration.
+const STACK_ADDRESS: u64 = 0xffff_fd00_0000_0000;
+
+/// Pages of boot stack.
+const STACK_PAGES: u64 = 128;
+
+/// Memory map descriptors allowed for beyond the size first reported, to
+/// cover the allocations made before the final map is read.
+const MAP_SLACK: usize = 32;
+
+/// `CR4.LA57`: five-level paging is on.
+const CR4_LA57: u64 = 1 << 12;
+/// `CR0.WP`: write protection applies to the kernel too.
+const CR0_WP: u64 = 1 << 16;
+/// The `IA32_EFER` MSR.
+const MSR_EFER: u32 = 0xc000_0080;
+/// `EFER.NXE`: the no-execute bit is enabled.
+const EFER_NXE: u64 = 1 << 11;
+
+/// Why the kernel could not be started.
+#[derive(Debug, Clone, Copy)]
+pub struct Error {
+    /// What went wrong
+    message: &'static str,
+    /// Status returned to the firmware
+    status: efi::Status,
+}
+
+impl Error {
+    /// An error about the kernel image or its layout.
+    pub fn new(message: &'static str) -> Self {
+        Error {
+            message,
+            status: efi::Status::LOAD_ERROR,
+        }
+    }
+
+    /// An error returned by a firmware service.
+    fn firmware(message: &'static str, status: efi::Status) -> Self {
+        Error { message, status }
+    }
+}
+
+/// The firmware's boot services.
+struct Firmware {
+    /// The loader's image handle
+    image: efi::Handle,
+    /// The system table
+    system_table: *mut efi::SystemTable,
+    /// Its boot services
+    boot_services: *mut efi::BootServices,
+}
+
+/// UEFI entry point.
+///
+/// Starts the kernel, or prints why it cannot and returns to the firmware.
+#[export_name = "efi_main"]
+extern "efiapi" fn efi_main(
+    image: efi::Handle,
+    system_table: *mut efi::SystemTable,
+) -> efi::Status {
+    let firmware = Firmware {
+        image,
+        system_table,
+        boot_services: unsafe { (*system_table).boot_services },
+    };
+    match boot(&firmware) {
+        Ok(never) => match never {},
+        Err(err) => {
+            firmware.print("espress-efi: ");
+            firmware.print(err.message);
+            firmware.print("\r\n");
+            err.status
+        }
+    }
+}
+
+/// Loads the kernel, exits boot services, and jumps to it.
+///
+/// # Errors
+///
+/// Returns an [`Error`] for anything that goes wrong before boot services
+/// are exited.
+fn boot(firmware: &Firmware) -> Result<core::convert::Infallible, Error> {
+    if read_cr4() & CR4_LA57 != 0 {
+        return Err(Error::new("five-level paging is not supported"));
+    }
+    firmware.disable_watchdog();
+
+    let image = firmware.read_kernel()?;
+    let kernel = Elf::parse(image)?;
+
+    // The map grows with every allocation; read its size first so a buffer
+    // large enough for the final map can be set aside.
+    let (map_size, descriptor_size) = firmware.memory_map_size()?;
+    let map_capacity = map_size + MAP_SLACK * descriptor_size;
+    let map = firmware.allocate_pool(map_capacity)?;
+    let (len, _) = firmware.memory_map(map, map_capacity, descriptor_size)?;
+    let phys_end = descriptors(map, len, descriptor_size)
+        .map(|descriptor| descriptor.physical_start + descriptor.number_of_pages * PAGE_SIZE)
+        .max()
+        .unwrap_or(0)
+        .next_multiple_of(LARGE_PAGE_SIZE);
+
+    let kernel_pages: u64 = kernel
+        .segments()
+        .map(|segment| pages(segment.vaddr, segment.mem_size))
+        .sum();
+    let segment_count = kernel.segments().count() as u64;
+    let table_frames =
+        12 + phys_end / (LARGE_PAGE_SIZE * 512) + kernel_pages / 512 + 2 * segment_count;
+    let pool = firmware.allocate_pages(handoff::MEMORY_PAGE_TABLE, table_frames)?;
+    let mut tables = PageTables::new(pool, table_frames as usize)?;
+
+    for segment in kernel.segments() {
+        let first = segment.vaddr & !(PAGE_SIZE - 1);
+        let count = pages(segment.vaddr, segment.mem_size);
+        let frames = firmware.allocate_pages(handoff::MEMORY_KERNEL, count)?;
+        unsafe {
+            ptr::write_bytes(frames as *mut u8, 0, (count * PAGE_SIZE) as usize);
+            let contents = kernel.contents(&segment);
+            let destination = (frames + (segment.vaddr - first)) as *mut u8;
+            ptr::copy_nonoverlapping(contents.as_ptr(), destination, contents.len());
+        }
+        let mut flags = 0;
+        if segment.writable {
+            flags |= WRITABLE;
+        }
+        if !segment.executable {
+            flags |= NO_EXECUTE;
+        }
+        for page in 0..count {
+            tables.map_page(first + page * PAGE_SIZE, frames + page * PAGE_SIZE, flags)?;
+        }
+    }
+
+    let stack = firmware.allocate_pages(handoff::MEMORY_KERNEL, STACK_PAGES)?;
+    for page in 0..STACK_PAGES {
+        let virt = STACK_ADDRESS + (page + 1) * PAGE_SIZE;
+        tables.map_page(virt, stack + page * PAGE_SIZE, WRITABLE | NO_EXECUTE)?;
+    }
+    let stack_top = STACK_ADDRESS + (STACK_PAGES + 1) * PAGE_SIZE;
+    tables.map_physical_memory(PHYS_MAP_BASE, phys_end)?;
+
+    let info_pages = pages(0, size_of::<BootInfo>() as u64);
+    let info = firmware.allocate_pages(handoff::MEMORY_BOOT_INFO, info_pages)? as *mut BootInfo;
+    unsafe {
+        ptr::write_bytes(info as *mut u8, 0, (info_pages * PAGE_SIZE) as usize);
+        (*info).version = handoff::VERSION;
+        (*info).physical_memory_offset = PHYS_MAP_BASE;
+        (*info).rsdp = firmware.config_table(&[efi::ACPI_20_TABLE_GUID, efi::ACPI_10_TABLE_GUID]);
+        (*info).smbios = firmware.config_table(&[efi::SMBIOS3_TABLE_GUID, efi::SMBIOS_TABLE_GUID]);
+        (*info).framebuffer = firmware.framebuffer();
+    }
+
+    firmware.exit_boot_services(map, map_capacity, descriptor_size, |len| unsafe {
+        (*info).region_count =
+            convert_memory_map(map, len, descriptor_size, &mut (*info).regions) as u32;
+    })?;
+
+    unsafe {
+        enter_kernel(
+            tables.root(),
+            stack_top,
+            kernel.entry(),
+            PHYS_MAP_BASE + info as u64,
+        )
+    }
+}
+
+impl Firmware {
+    /// Returns the boot services.
+    fn boot_services(&self) -> &efi::BootServices {
+        unsafe { &*self.boot_services }
+    }
+
+    /// Prints `message` on the firmware console.
+    fn print(&self, message: &str) {
+        let out = unsafe { (*self.system_table).con_out };
+        if out.is_null() {
+            return;
+        }
+        let mut buf = [0u16; 64];
+        let mut len = 0;
+        for unit in message.encode_utf16() {
+            buf[len] = unit;
+            len += 1;
+            if len == buf.len() - 1 {
+                buf[len] = 0;
+                unsafe { ((*out).output_string)(out, buf.as_mut_ptr()) };
+                len = 0;
+            }
+        }
+        buf[len] = 0;
+        unsafe { ((*out).output_string)(out, buf.as_mut_ptr()) };
+    }
+
+    /// Stops the firmware from resetting the machine while the kernel runs.
+    fn disable_watchdog(&self) {
+        (self.boot_services().set_watchdog_timer)(0, 0, 0, ptr::null_mut());
+    }
+
+    /// Returns the protocol `guid` of `handle`.
+    fn protocol<T>(
+        &self,
+        handle: efi::Handle,
+        guid: &efi::Guid,
+        what: &'static str,
+    ) -> Result<*mut T, Error> {
+        let mut guid = *guid;
+        let mut interface = ptr::null_mut();
+        let status = (self.boot_services().handle_protocol)(handle, &mut guid, &mut interface);
+        check(status, what)?;
+        Ok(interface as *mut T)
+    }
+
+    /// Allocates `count` pages of memory type `kind`.
+    fn allocate_pages(&self, kind: efi::MemoryType, count: u64) -> Result<u64, Error> {
+        let mut address = 0;
+        let status = (self.boot_services().allocate_pages)(
+            efi::ALLOCATE_ANY_PAGES,
+            kind,
+            count as usize,
+            &mut address,
+        );
+        check(status, "out of memory")?;
+        Ok(address)
+    }
+
+    /// Allocates `size` bytes of loader data.
+    fn allocate_pool(&self, size: usize) -> Result<*mut u8, Error> {
+        let mut buffer = ptr::null_mut();
+        let status = (self.boot_services().allocate_pool)(efi::LOADER_DATA, size, &mut buffer);
+        check(status, "out of memory")?;
+        Ok(buffer as *mut u8)
+    }
+
+    /// Reads the kernel ELF from the volume the loader came from.
+    ///
+    /// The file is read into boot services memory, which the kernel reuses
+    /// once its segments have been copied out.
+    fn read_kernel(&self) -> Result<&'static [u8], Error> {
+        let image: *mut loaded_image::Protocol = self.protocol(
+            self.image,
+            &loaded_image::PROTOCOL_GUID,
+            "no loaded image protocol",
+        )?;
+        let volume: *mut simple_file_system::Protocol = self.protocol(
+            unsafe { (*image).device_handle },
+            &simple_file_system::PROTOCOL_GUID,
+            "boot volume not readable",
+        )?;
+
+        let mut root = ptr::null_mut();
+        check(
+            unsafe { ((*volume).open_volume)(volume, &mut root) },
+            "boot volume not readable",
+        )?;
+        let mut path = [0u16; 64];
+        for (slot, unit) in path.iter_mut().zip(KERNEL_PATH.encode_utf16()) {
+            *slot = unit;
+        }
+        let mut kernel: *mut file::Protocol = ptr::null_mut();
+        let status =
+            unsafe { ((*root).open)(root, &mut kernel, path.as_mut_ptr(), file::MODE_READ, 0) };
+        unsafe { ((*root).close)(root) };
+        check(status, "kernel not found at \\espress-os.elf")?;
+
+        // Seeking to the all-ones position moves to the end of the file.
+        let mut size = 0;
+        unsafe {
+            ((*kernel).set_position)(kernel, u64::MAX);
+            ((*kernel).get_position)(kernel, &mut size);
+            ((*kernel).set_position)(kernel, 0);
+        }
+        let buffer = self.allocate_pages(efi::BOOT_SERVICES_DATA, pages(0, size))?;
+        let mut len = size as usize;
+        let status = unsafe { ((*kernel).read)(kernel, &mut len, buffer as *mut c_void) };
+        unsafe { ((*kernel).close)(kernel) };
+        check(status, "kernel could not be read")?;
+        if len as u64 != size {
+            return Err(Error::new("kernel could not be read"));
+        }
+        Ok(unsafe { core::slice::from_raw_parts(buffer as *const u8, len) })
+    }
+
+    /// Returns the size of the memory map and of each descriptor.
+    fn memory_map_size(&self) -> Result<(usize, usize), Error> {
+        let (mut size, mut key, mut descriptor_size, mut version) = (0, 0, 0, 0);
+        let status = (self.boot_services().get_memory_map)(
+            &mut size,
+            ptr::null_mut(),
+            &mut key,
+            &mut descriptor_size,
+            &mut version,
+        );
+        if status != efi::Status::BUFFER_TOO_SMALL {
+            return Err(Error::firmware("memory map not available", status));
+        }
+        Ok((size, descriptor_size))
+    }
+
+    /// Reads the memory map into `map`.
+    ///
+    /// # Returns
+    ///
+    /// The size of the map and the key that identifies it.
+    fn memory_map(
+        &self,
+        map: *mut u8,
+        capacity: usize,
+        descriptor_size: usize,
+    ) -> Result<(usize, usize), Error> {
+        let (mut size, mut key, mut actual_descriptor_size, mut version) = (capacity, 0, 0, 0);
+        let status = (self.boot_services().get_memory_map)(
+            &mut size,
+            map as *mut efi::MemoryDescriptor,
+            &mut key,
+            &mut actual_descriptor_size,
+            &mut version,
+        );
+        check(status, "memory map not available")?;
+        if actual_descriptor_size != descriptor_size {
+            return Err(Error::new("memory map descriptor size changed"));
+        }
+        Ok((size, key))
+    }
+
+    /// Returns the physical address of the configuration table with the
+    /// first of `guids` the firmware has, or 0 if it has none of them.
+    fn config_table(&self, guids: &[efi::Guid]) -> u64 {
+        let system_table = unsafe { &*self.system_table };
+        let tables = unsafe {
+            core::slice::from_raw_parts(
+                system_table.configuration_table,
+                system_table.number_of_table_entries,
+            )
+        };
+        guids
+            .iter()
+            .find_map(|guid| tables.iter().find(|table| table.vendor_guid == *guid))
+            .map_or(0, |table| table.vendor_table as u64)
+    }
+
+    /// Returns the framebuffer of the first graphics output, in the
+    /// [requested mode](requested_mode) if it has one, or else in its
+    /// current mode.
+    fn framebuffer(&self) -> FramebufferInfo {
+        let mut framebuffer = FramebufferInfo {
+            address: 0,
+            size: 0,
+            width: 0,
+            height: 0,
+            stride: 0,
+            format: 0,
+            masks: [0; 3],
+            reserved: 0,
+        };
+        let mut guid = graphics_output::PROTOCOL_GUID;
+        let mut interface = ptr::null_mut();
+        let status =
+            (self.boot_services().locate_protocol)(&mut guid, ptr::null_mut(), &mut interface);
+        if status.is_error() || interface.is_null() {
+            return framebuffer;
+        }
+        let gop = interface as *mut graphics_output::Protocol;
+        if let Some((width, height)) = requested_mode() {
+            if !self.set_mode(gop, width, height) {
+                self.print("espress-efi: requested video mode not available\r\n");
+            }
+        }
+        let (mode, info) = unsafe { (&*(*gop).mode, &*(*(*gop).mode).info) };
+        if info.pixel_format == graphics_output::PIXEL_BLT_ONLY {
+            return framebuffer;
+        }
+        framebuffer.address = mode.frame_buffer_base;
+        framebuffer.size = mode.frame_buffer_size as u64;
+        framebuffer.width = info.horizontal_resolution;
+        framebuffer.height = info.vertical_resolution;
+        framebuffer.stride = info.pixels_per_scan_line;
+        framebuffer.format = info.pixel_format;
+        framebuffer.masks = [
+            info.pixel_information.red_mask,
+            info.pixel_information.green_mask,
+            info.pixel_information.blue_mask,
+        ];
+        framebuffer
+    }
+
+    /// Switches `gop` to the first mode of `width` by `height` pixels that
+    /// has a framebuffer.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the mode was set.
+    fn set_mode(&self, gop: *mut graphics_output::Protocol, width: u32, height: u32) -> bool {
+        let max_mode = unsafe { (*(*gop).mode).max_mode };
+        for number in 0..max_mode {
+            let mut size = 0;
+            let mut info = ptr::null_mut();
+            let status = unsafe { ((*gop).query_mode)(gop, number, &mut size, &mut info) };
+            if status.is_error() || info.is_null() {
+                continue;
+            }
+            let matches = unsafe {
+                (*info).horizontal_resolution == width
+                    && (*info).vertical_resolution == height
+                    && (*info).pixel_format != graphics_output::PIXEL_BLT_ONLY
+            };
+            (self.boot_services().free_pool)(info.cast());
+            if matches {
+                return !unsafe { ((*gop).set_mode)(gop, number) }.is_error();
+            }
+        }
+        false
+    }
+
+    /// Reads the final memory map, passes its size to `record`, and exits
+    /// boot services.
+    ///
+    /// `record` runs after boot services are gone if they were exited on
+    /// the first attempt, so it must not call the firmware.
+    fn exit_boot_services(
+        &self,
+        map: *mut u8,
+        capacity: usize,
+        descriptor_size: usize,
+        mut record: impl FnMut(usize),
+    ) -> Result<(), Error> {
+        // The key goes stale if anything allocates in between, e.g. an
+        // event handler; the map is then read once more.
+        for _ in 0..2 {
+            let (len, key) = self.memory_map(map, capacity, descriptor_size)?;
+            let status = (self.boot_services().exit_boot_services)(self.image, key);
+            if status == efi::Status::SUCCESS {
+                record(len);
+                return Ok(());
+            }
+        }
+        Err(Error::new("could not exit boot services"))
+    }
+}
+
+/// Returns the width and height requested with `video=<width>x<height>` on
+/// the kernel command line the loader was built with.
+///
+/// A depth may follow as `-<bpp>`; the kernel draws only 32-bit pixels, so
+/// any other depth makes the request invalid.
+fn requested_mode() -> Option<(u32, u32)> {
+    let cmdline = option_env!("ESPRESS_OS_CMDLINE")?;
+    let value = cmdline
+        .split_whitespace()
+        .find_map(|word| word.strip_prefix("video="))?;
+    let size = match value.split_once('-') {
+        Some((size, "32")) => size,
+        Some(_) => return None,
+        None => value,
+    };
+    let (width, height) = size.split_once('x')?;
+    Some((width.parse().ok()?, height.parse().ok()?))
+}
+
+/// Turns a firmware status into a result.
+fn check(status: efi::Status, message: &'static str) -> Result<(), Error> {
+    match status.is_error() {
+        true => Err(Error::firmware(message, status)),
+        false => Ok(()),
+    }
+}
+
+/// Pages spanned by `len` bytes starting at `start`.
+fn pages(start: u64, len: u64) -> u64 {
+    let first = start & !(PAGE_SIZE - 1);
+    (start + len - first).div_ceil(PAGE_SIZE)
+}
+
+/// Iterates over the descriptors of a memory map `len` bytes long.
+fn descriptors(
+    map: *const u8,
+    len: usize,
+    descriptor_size: usize,
+) -> impl Iterator<Item = efi::MemoryDescriptor> {
+    (0..len / descriptor_size).map(move |index| unsafe {
+        ptr::read_unaligned(map.add(index * descriptor_size) as *const efi::MemoryDescriptor)
+    })
+}
+
+/// Copies the memory map into `regions`, sorted by address and with
+/// adjacent entries of the same type merged.
+///
+/// Runs after boot services are exited, so it neither allocates nor calls
+/// the firmware.
+///
+/// # Returns
+///
+/// The number of entries written; entries beyond the capacity are dropped.
+fn convert_memory_map(
+    map: *const u8,
+    len: usize,
+    descriptor_size: usize,
+    regions: &mut [MemoryRegion],
+) -> usize {
+    let mut count = 0;
+    for descriptor in descriptors(map, len, descriptor_size) {
+        if count == regions.len() {
+            break;
+        }
+        regions[count] = MemoryRegion {
+            start: descriptor.physical_start,
+            len: descriptor.number_of_pages * PAGE_SIZE,
+            kind: descriptor.r#type,
+            reserved: 0,
+        };
+        count += 1;
+    }
+    regions[..count].sort_unstable_by_key(|region| region.start);
+
+    let mut merged = 0;
+    for index in 0..count {
+        let region = regions[index];
+        if merged > 0 {
+            let last = &mut regions[merged - 1];
+            if last.kind == region.kind && last.start + last.len == region.start {
+                last.len += region.len;
+                continue;
+            }
+        }
+        regions[merged] = region;
+        merged += 1;
+    }
+    merged
+}
+
+/// Switches to the kernel's page tables and stack and calls its entry point.
+///
+/// # Safety
+///
+/// Boot services must be exited, and the tables must map the kernel, its
+/// stack, and the running loader.
+unsafe fn enter_kernel(root: u64, stack_top: u64, entry: u64, info: u64) -> ! {
+    let efer = read_msr(MSR_EFER);
+    write_msr(MSR_EFER, efer | EFER_NXE);
+    core::arch::asm!(
+        "cli",
+        "mov r11, cr0",
+        "or r11, rax",
+        "mov cr0, r11",
+        "mov cr3, r8",
+        "mov rsp, r9",
+        "xor ebp, ebp",
+        "call r10",
+        "ud2",
+        in("rax") CR0_WP,
+        in("r8") root,
+        in("r9") stack_top,
+        in("r10") entry,
+        in("rdi") info,
+        in("rsi") handoff::MAGIC,
+        options(noreturn),
+    )
+}
+
+/// Reads `cr4`.
+fn read_cr4() -> u64 {
+    let value: u64;
+    unsafe { core::arch::asm!("mov {}, cr4", out(reg) value, options(nomem, nostack)) };
+    value
+}
+
+/// Reads model-specific register `msr`.
+unsafe fn read_msr(msr: u32) -> u64 {
+    let (low, high): (u32, u32);
+    core::arch::asm!("rdmsr", in("ecx") msr, out("eax") low, out("edx") high, options(nomem, nostack));
+    (u64::from(high) << 32) | u64::from(low)
+}
+
+/// Writes `value` to model-specific register `msr`.
+unsafe fn write_msr(msr: u32, value: u64) {
+    core::arch::asm!(
+        "wrmsr",
+        in("ecx") msr,
+        in("eax") value as u32,
+        in("edx") (value >> 32) as u32,
+        options(nostack),
+    );
+}
+
+/// Halts the loader on a panic; nothing here is expected to panic.
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    loop {
+        unsafe { core::arch::asm!("hlt", options(nomem, nostack)) };
+    }
+}
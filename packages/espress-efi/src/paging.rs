@@ -0,0 +1,136 @@
+//! # Kernel Page Tables
+//!
+//! Builds the four-level page tables the kernel starts with, in frames
+//! set aside before exiting boot services. The firmware identity-maps all
+//! memory, so tables are written through their physical addresses.
+//!
+//! Intermediate entries are present and writable; the permissions of a
+//! mapping are decided by its last level alone.
+
+use crate::Error;
+
+/// Size of a page and of a page table.
+pub const PAGE_SIZE: u64 = 4096;
+/// Size of a large page.
+pub const LARGE_PAGE_SIZE: u64 = 2 * 1024 * 1024;
+
+/// Entry flag: present.
+const PRESENT: u64 = 1 << 0;
+/// Entry flag: writable.
+pub const WRITABLE: u64 = 1 << 1;
+/// Entry flag: maps a large page.
+const HUGE: u64 = 1 << 7;
+/// Entry flag: not executable.
+pub const NO_EXECUTE: u64 = 1 << 63;
+/// Bits of an entry holding the physical address.
+const ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+/// Entries in a page table.
+const ENTRIES: usize = 512;
+
+/// Page tables under construction.
+pub struct PageTables {
+    /// Physical address of the level 4 table
+    root: u64,
+    /// Next unused frame of the pool
+    next: u64,
+    /// End of the pool
+    end: u64,
+}
+
+impl PageTables {
+    /// Starts empty page tables in the `frames` frames at `pool`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if the pool is empty.
+    pub fn new(pool: u64, frames: usize) -> Result<Self, Error> {
+        let mut tables = PageTables {
+            root: 0,
+            next: pool,
+            end: pool + frames as u64 * PAGE_SIZE,
+        };
+        tables.root = tables.allocate()?;
+        Ok(tables)
+    }
+
+    /// Physical address of the level 4 table, for `cr3`.
+    pub fn root(&self) -> u64 {
+        self.root
+    }
+
+    /// Maps the 4 KiB page at `virt` to the frame at `phys`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if the page is already mapped or the pool runs
+    /// out.
+    pub fn map_page(&mut self, virt: u64, phys: u64, flags: u64) -> Result<(), Error> {
+        let mut table = self.root;
+        for level in (1..4).rev() {
+            table = self.next_table(table, index(virt, level + 1))?;
+        }
+        let entry = entry(table, index(virt, 1));
+        if unsafe { *entry } & PRESENT != 0 {
+            return Err(Error::new("kernel pages overlap"));
+        }
+        unsafe { *entry = phys | flags | PRESENT };
+        Ok(())
+    }
+
+    /// Maps physical memory up to `end` twice with large pages: at `base`
+    /// for the kernel, and at 0 so the loader keeps running after the
+    /// switch. Both views share their tables below the level 4 table.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error`] if `end` is beyond what one level 4 entry
+    /// covers or the pool runs out.
+    pub fn map_physical_memory(&mut self, base: u64, end: u64) -> Result<(), Error> {
+        let l4_span = LARGE_PAGE_SIZE * (ENTRIES * ENTRIES) as u64;
+        if end > l4_span {
+            return Err(Error::new("physical memory too large to map"));
+        }
+        for phys in (0..end).step_by(LARGE_PAGE_SIZE as usize) {
+            let pdpt = self.next_table(self.root, index(base + phys, 4))?;
+            let pd = self.next_table(pdpt, index(base + phys, 3))?;
+            unsafe { *entry(pd, index(base + phys, 2)) = phys | WRITABLE | HUGE | PRESENT };
+        }
+        unsafe { *entry(self.root, 0) = *entry(self.root, index(base, 4)) };
+        Ok(())
+    }
+
+    /// Returns the table the entry at `index` of `table` points to,
+    /// creating it if needed.
+    fn next_table(&mut self, table: u64, index: usize) -> Result<u64, Error> {
+        let entry = entry(table, index);
+        let value = unsafe { *entry };
+        if value & PRESENT != 0 {
+            return Ok(value & ADDRESS_MASK);
+        }
+        let next = self.allocate()?;
+        unsafe { *entry = next | WRITABLE | PRESENT };
+        Ok(next)
+    }
+
+    /// Takes a zeroed frame from the pool.
+    fn allocate(&mut self) -> Result<u64, Error> {
+        if self.next >= self.end {
+            return Err(Error::new("out of page table frames"));
+        }
+        let frame = self.next;
+        self.next += PAGE_SIZE;
+        unsafe { core::ptr::write_bytes(frame as *mut u8, 0, PAGE_SIZE as usize) };
+        Ok(frame)
+    }
+}
+
+/// Index into the level `level` table for `virt`.
+fn index(virt: u64, level: u32) -> usize {
+    ((virt >> (12 + 9 * (level - 1))) & 0x1ff) as usize
+}
+
+/// Pointer to entry `index` of the table at physical address `table`.
+fn entry(table: u64, index: usize) -> *mut u64 {
+    (table as *mut u64).wrapping_add(index)
+}
@@ -0,0 +1,33 @@
+//! Build script for the EspressOS kernel.
+//!
+//! Passes the linker script for the target architecture to the linker for
+//! the kernel binary. On x86_64 it is the higher-half one, and the linker
+//! also pre-applies the PIE relocations for the link address.
+
+use std::env;
+
+fn main() {
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    match env::var("CARGO_CFG_TARGET_ARCH").as_deref() {
+        Ok(arch @ ("aarch64" | "riscv64")) => {
+            println!(
+                "cargo:rustc-link-arg-bins=-T{}/linker-{}.ld",
+                manifest_dir, arch
+            );
+        }
+        Ok("x86") => {
+            println!(
+                "cargo:rustc-link-arg-bins=-T{}/linker-i686.ld",
+                manifest_dir
+            );
+        }
+        _ => {
+            println!("cargo:rustc-link-arg-bins=-T{}/linker.ld", manifest_dir);
+            println!("cargo:rustc-link-arg-bins=--apply-dynamic-relocs");
+        }
+    }
+    println!("cargo:rerun-if-changed=linker.ld");
+    println!("cargo:rerun-if-changed=linker-aarch64.ld");
+    println!("cargo:rerun-if-changed=linker-i686.ld");
+    println!("cargo:rerun-if-changed=linker-riscv64.ld");
+}
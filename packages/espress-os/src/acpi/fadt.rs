@@ -0,0 +1,332 @@
+//! # FADT and Sleep States
+//!
+//! The Fixed ACPI Description Table (signature `FACP`) gives the I/O ports
+//! of the power management registers and the address of the DSDT. Entering
+//! a sleep state means writing the state's `SLP_TYP` values, together with
+//! `SLP_EN`, to the PM1 control registers.
+//!
+//! The `SLP_TYP` values are only found in the DSDT, as AML packages named
+//! `\_Sx_`. Without an interpreter, [`sleep_types`] looks for the package
+//! by its byte pattern and reads its first two integers, which works for
+//! the simple definitions firmware uses.
+//!
+//! The FADT may also describe a reset register, which resets the machine
+//! when [`reset`] writes the given value to it.
+//!
+//! Sleep states that keep memory, such as S3, resume at the waking vector
+//! the OS writes to the FACS with [`set_waking_vector`].
+//!
+//! Only the legacy I/O port fields are read, not their 64-bit generic
+//! address counterparts, which PC firmware fills in alike.
+
+use x86_64::instructions::port::Port;
+use x86_64::PhysAddr;
+
+use super::{read_u32, read_u64, SDT_HEADER_SIZE};
+use crate::mm;
+
+/// Offset of the 32-bit FACS address.
+const FIRMWARE_CTRL: usize = 36;
+/// Offset of the 32-bit DSDT address.
+const DSDT: usize = 40;
+/// Offset of the SMI command port.
+const SMI_COMMAND: usize = 48;
+/// Offset of the value that hands the hardware to the OS.
+const ACPI_ENABLE: usize = 52;
+/// Offset of the PM1a control block port.
+const PM1A_CONTROL: usize = 64;
+/// Offset of the PM1b control block port.
+const PM1B_CONTROL: usize = 68;
+/// Offset of the feature flags.
+const FLAGS: usize = 112;
+/// Offset of the reset register, a generic address structure.
+const RESET_REG: usize = 116;
+/// Offset of the value written to the reset register.
+const RESET_VALUE: usize = 128;
+/// Offset of the 64-bit FACS address, from ACPI 2.0 on.
+const X_FIRMWARE_CTRL: usize = 132;
+/// Offset of the 64-bit DSDT address, from ACPI 2.0 on.
+const X_DSDT: usize = 140;
+
+/// FACS offset of the real-mode waking vector.
+const FACS_WAKING_VECTOR: usize = 12;
+/// FACS offset of the 64-bit waking vector, from ACPI 2.0 on.
+const FACS_X_WAKING_VECTOR: usize = 24;
+
+/// Flag: the reset register is supported.
+const RESET_REG_SUP: u32 = 1 << 10;
+
+/// Generic address space: system memory.
+const SPACE_MEMORY: u8 = 0;
+/// Generic address space: system I/O ports.
+const SPACE_IO: u8 = 1;
+
+/// PM1 control: interrupts go to the OS rather than the SMM firmware.
+const SCI_EN: u16 = 1 << 0;
+/// PM1 control: shift of the sleep type field.
+const SLP_TYP_SHIFT: u16 = 10;
+/// PM1 control: enters the sleep state in the sleep type field.
+const SLP_EN: u16 = 1 << 13;
+
+/// Times the control register is polled after enabling ACPI.
+const ENABLE_POLLS: u32 = 1_000_000;
+
+/// AML opcode that names an object.
+const AML_NAME_OP: u8 = 0x08;
+/// AML opcode that starts a package.
+const AML_PACKAGE_OP: u8 = 0x12;
+/// AML root prefix, `\`.
+const AML_ROOT_CHAR: u8 = b'\\';
+
+/// Errors that can occur while entering a sleep state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SleepError {
+    /// There is no FADT or it lacks the PM1 control block
+    NoFadt,
+    /// The DSDT does not define the state
+    NoSleepType,
+    /// ACPI mode could not be enabled
+    NotEnabled,
+}
+
+/// The fields of the FADT the kernel uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fadt {
+    /// Physical address of the DSDT
+    pub dsdt: u64,
+    /// Physical address of the FACS, 0 if there is none
+    pub facs: u64,
+    /// Port that takes [`acpi_enable`](Self::acpi_enable), 0 if the
+    /// system is always in ACPI mode
+    pub smi_command: u16,
+    /// Value that switches the system to ACPI mode
+    pub acpi_enable: u8,
+    /// Port of the PM1a control register
+    pub pm1a_control: u16,
+    /// Port of the PM1b control register, 0 if there is none
+    pub pm1b_control: u16,
+    /// Register that resets the machine, if supported
+    pub reset: Option<ResetRegister>,
+}
+
+/// The reset register of the FADT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResetRegister {
+    /// Address space: 0 for memory, 1 for I/O ports
+    pub space: u8,
+    /// Address of the register in its space
+    pub address: u64,
+    /// Value that resets the machine
+    pub value: u8,
+}
+
+/// Returns the FADT.
+///
+/// # Returns
+///
+/// `None` before [`acpi::init`](super::init) or if there is no valid FADT.
+pub fn fadt() -> Option<Fadt> {
+    let table = super::find_table(b"FACP")?;
+    if table.len() < PM1B_CONTROL + 4 {
+        return None;
+    }
+    let dsdt = match table.get(X_DSDT..X_DSDT + 8) {
+        Some(_) if read_u64(table, X_DSDT) != 0 => read_u64(table, X_DSDT),
+        _ => u64::from(read_u32(table, DSDT)),
+    };
+    let facs = match table.get(X_FIRMWARE_CTRL..X_FIRMWARE_CTRL + 8) {
+        Some(_) if read_u64(table, X_FIRMWARE_CTRL) != 0 => read_u64(table, X_FIRMWARE_CTRL),
+        _ => u64::from(read_u32(table, FIRMWARE_CTRL)),
+    };
+    Some(Fadt {
+        dsdt,
+        facs,
+        smi_command: read_u32(table, SMI_COMMAND) as u16,
+        acpi_enable: table[ACPI_ENABLE],
+        pm1a_control: read_u32(table, PM1A_CONTROL) as u16,
+        pm1b_control: read_u32(table, PM1B_CONTROL) as u16,
+        reset: reset_register(table),
+    })
+}
+
+/// Reads the reset register from the FADT `table`, if it is supported and
+/// in an address space [`reset`] can write to.
+fn reset_register(table: &[u8]) -> Option<ResetRegister> {
+    table.get(RESET_VALUE)?;
+    if read_u32(table, FLAGS) & RESET_REG_SUP == 0 {
+        return None;
+    }
+    let register = ResetRegister {
+        space: table[RESET_REG],
+        address: read_u64(table, RESET_REG + 4),
+        value: table[RESET_VALUE],
+    };
+    let usable = matches!(register.space, SPACE_MEMORY | SPACE_IO) && register.address != 0;
+    usable.then_some(register)
+}
+
+/// Writes the reset value to the reset register.
+///
+/// # Returns
+///
+/// `false` if there is no usable reset register; otherwise the machine
+/// normally resets before this returns.
+pub fn reset() -> bool {
+    let Some(register) = fadt().and_then(|fadt| fadt.reset) else {
+        return false;
+    };
+    match register.space {
+        SPACE_IO => unsafe { Port::<u8>::new(register.address as u16).write(register.value) },
+        _ => {
+            // Only the linear mapping is usable here, which may not cover
+            // device memory.
+            let address = match PhysAddr::try_new(register.address) {
+                Ok(address) if address.as_u64() < mm::phys_memory_end() => address,
+                _ => return false,
+            };
+            let pointer = mm::phys_to_virt(address).as_mut_ptr::<u8>();
+            unsafe { pointer.write_volatile(register.value) };
+        }
+    }
+    true
+}
+
+/// Sets the address the firmware jumps to, in real mode, when the machine
+/// wakes up from a sleep state that keeps memory.
+///
+/// The 64-bit waking vector is cleared, so the firmware uses this one.
+///
+/// # Returns
+///
+/// `false` if there is no FACS or it is not reachable through the linear
+/// mapping.
+pub fn set_waking_vector(address: u32) -> bool {
+    let Some(facs) = fadt().map(|fadt| fadt.facs) else {
+        return false;
+    };
+    let facs = match PhysAddr::try_new(facs) {
+        Ok(facs) if facs.as_u64() != 0 && facs.as_u64() < mm::phys_memory_end() => facs,
+        _ => return false,
+    };
+    // The FACS is 64-byte aligned, so its fields are naturally aligned.
+    let table = mm::phys_to_virt(facs).as_mut_ptr::<u8>();
+    unsafe {
+        if core::slice::from_raw_parts(table, 4) != b"FACS" {
+            return false;
+        }
+        let length = table.add(4).cast::<u32>().read_volatile() as usize;
+        table
+            .add(FACS_WAKING_VECTOR)
+            .cast::<u32>()
+            .write_volatile(address);
+        if length >= FACS_X_WAKING_VECTOR + 8 {
+            table
+                .add(FACS_X_WAKING_VECTOR)
+                .cast::<u64>()
+                .write_volatile(0);
+        }
+    }
+    true
+}
+
+/// Returns the `SLP_TYP` values for the PM1a and PM1b control registers
+/// that enter sleep state `state`, e.g. 5 for soft off.
+///
+/// # Returns
+///
+/// `None` if there is no DSDT or it defines no `\_Sx_` package of a form
+/// understood here.
+pub fn sleep_types(state: u8) -> Option<(u8, u8)> {
+    let aml = super::table_at(fadt()?.dsdt)?.get(SDT_HEADER_SIZE..)?;
+    let name = [b'_', b'S', b'0' + state, b'_'];
+    let start = (2..aml.len().saturating_sub(5)).find(|&at| {
+        aml[at..at + 4] == name
+            && aml[at + 4] == AML_PACKAGE_OP
+            && (aml[at - 1] == AML_NAME_OP
+                || (aml[at - 1] == AML_ROOT_CHAR && aml[at - 2] == AML_NAME_OP))
+    })?;
+    // The package length takes one to four bytes, as the top two bits of
+    // the first say.
+    let mut at = start + 5;
+    at += 1 + usize::from(*aml.get(at)? >> 6);
+    let elements = *aml.get(at)?;
+    at += 1;
+    let a = aml_integer(aml, &mut at)?;
+    let b = if elements > 1 {
+        aml_integer(aml, &mut at)?
+    } else {
+        0
+    };
+    Some((a as u8, b as u8))
+}
+
+/// Enters sleep state `state` by writing its sleep types to the PM1
+/// control registers, switching the system to ACPI mode first if needed.
+///
+/// Returns only if the state could not be entered, or, for states that
+/// keep memory, once the machine wakes up.
+///
+/// # Errors
+///
+/// Returns a [`SleepError`] if the tables do not describe the state or
+/// ACPI mode cannot be enabled.
+pub fn enter_sleep_state(state: u8) -> Result<(), SleepError> {
+    let fadt = fadt()
+        .filter(|fadt| fadt.pm1a_control != 0)
+        .ok_or(SleepError::NoFadt)?;
+    let (slp_typ_a, slp_typ_b) = sleep_types(state).ok_or(SleepError::NoSleepType)?;
+    enable(&fadt)?;
+    if (2..=4).contains(&state) {
+        // Caches lose their contents in these states.
+        unsafe { core::arch::asm!("wbinvd", options(nostack, preserves_flags)) };
+    }
+    let mut pm1a = Port::<u16>::new(fadt.pm1a_control);
+    unsafe {
+        let value = pm1a.read() & !(0x7 << SLP_TYP_SHIFT);
+        if fadt.pm1b_control != 0 {
+            let mut pm1b = Port::<u16>::new(fadt.pm1b_control);
+            let value_b = pm1b.read() & !(0x7 << SLP_TYP_SHIFT);
+            pm1b.write(value_b | (u16::from(slp_typ_b) << SLP_TYP_SHIFT) | SLP_EN);
+        }
+        pm1a.write(value | (u16::from(slp_typ_a) << SLP_TYP_SHIFT) | SLP_EN);
+    }
+    Ok(())
+}
+
+/// Switches the system from SMM firmware control to ACPI mode, unless it
+/// is there already.
+fn enable(fadt: &Fadt) -> Result<(), SleepError> {
+    let mut pm1a = Port::<u16>::new(fadt.pm1a_control);
+    if unsafe { pm1a.read() } & SCI_EN != 0 {
+        return Ok(());
+    }
+    if fadt.smi_command == 0 || fadt.acpi_enable == 0 {
+        return Err(SleepError::NotEnabled);
+    }
+    unsafe { Port::<u8>::new(fadt.smi_command).write(fadt.acpi_enable) };
+    for _ in 0..ENABLE_POLLS {
+        if unsafe { pm1a.read() } & SCI_EN != 0 {
+            return Ok(());
+        }
+        core::hint::spin_loop();
+    }
+    Err(SleepError::NotEnabled)
+}
+
+/// Reads an AML integer constant at `*at` and moves past it.
+fn aml_integer(aml: &[u8], at: &mut usize) -> Option<u64> {
+    let (value, len) = match *aml.get(*at)? {
+        0x00 => (0, 1),
+        0x01 => (1, 1),
+        0xff => (u64::MAX, 1),
+        0x0a => (u64::from(*aml.get(*at + 1)?), 2),
+        0x0b => (
+            u64::from(u16::from_le_bytes([*aml.get(*at + 1)?, *aml.get(*at + 2)?])),
+            3,
+        ),
+        0x0c => (u64::from(read_u32(aml.get(*at + 1..*at + 5)?, 0)), 5),
+        _ => return None,
+    };
+    *at += len;
+    Some(value)
+}
@@ -0,0 +1,280 @@
+//! # MADT
+//!
+//! The Multiple APIC Description Table (signature `APIC`) lists the
+//! interrupt controllers: one local APIC per logical CPU, the I/O APICs
+//! with the range of global system interrupts (GSIs) each one serves, and
+//! the ISA IRQs that are not wired to the GSI of the same number. This is
+//! what interrupt routing through the APICs and SMP bring-up need, instead
+//! of assuming one CPU and the legacy IRQ mapping; for now the kernel
+//! still runs on the boot CPU with the 8259 PICs.
+//!
+//! Entries of the kinds not used here are skipped.
+
+use alloc::vec::Vec;
+use x86_64::PhysAddr;
+
+use super::{read_u16, read_u32, read_u64, SDT_HEADER_SIZE};
+
+/// Offset of the 32-bit local APIC address.
+const LOCAL_APIC_ADDRESS: usize = SDT_HEADER_SIZE;
+/// Offset of the flags.
+const FLAGS: usize = SDT_HEADER_SIZE + 4;
+/// Offset of the first entry.
+const ENTRIES_OFFSET: usize = SDT_HEADER_SIZE + 8;
+
+/// Flag: the machine also has the two legacy 8259 PICs.
+const PCAT_COMPAT: u32 = 1 << 0;
+
+/// Entry: processor local APIC.
+const ENTRY_LOCAL_APIC: u8 = 0;
+/// Entry: I/O APIC.
+const ENTRY_IO_APIC: u8 = 1;
+/// Entry: interrupt source override.
+const ENTRY_OVERRIDE: u8 = 2;
+/// Entry: local APIC NMI.
+const ENTRY_LOCAL_APIC_NMI: u8 = 4;
+/// Entry: 64-bit local APIC address override.
+const ENTRY_LOCAL_APIC_ADDRESS: u8 = 5;
+/// Entry: processor local x2APIC.
+const ENTRY_LOCAL_X2APIC: u8 = 9;
+
+/// Local APIC flag: the CPU is usable.
+const APIC_ENABLED: u32 = 1 << 0;
+/// Local APIC flag: the CPU can be brought online, though disabled now.
+const APIC_ONLINE_CAPABLE: u32 = 1 << 1;
+
+/// Processor UID of a local APIC NMI entry that applies to every CPU.
+const ALL_PROCESSORS: u8 = 0xff;
+
+/// A logical CPU and its local APIC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalApic {
+    /// ACPI processor UID, which NMI entries refer to
+    pub processor_uid: u32,
+    /// APIC ID, which interrupts and startup IPIs are addressed to
+    pub apic_id: u32,
+    /// The CPU is usable
+    pub enabled: bool,
+    /// The CPU may be enabled later
+    pub online_capable: bool,
+}
+
+/// An I/O APIC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoApic {
+    /// I/O APIC ID
+    pub id: u8,
+    /// Physical address of its registers
+    pub address: PhysAddr,
+    /// First GSI it serves, on its first redirection entry
+    pub gsi_base: u32,
+}
+
+/// The level that signals an interrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    /// Signalled by a high level or rising edge
+    ActiveHigh,
+    /// Signalled by a low level or falling edge
+    ActiveLow,
+}
+
+/// How an interrupt is signalled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerMode {
+    /// By an edge
+    Edge,
+    /// By a level held until acknowledged
+    Level,
+}
+
+/// Where an ISA IRQ arrives, and how.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptOverride {
+    /// ISA IRQ
+    pub irq: u8,
+    /// GSI it is wired to
+    pub gsi: u32,
+    /// Polarity of the line
+    pub polarity: Polarity,
+    /// Trigger mode of the line
+    pub trigger: TriggerMode,
+}
+
+/// A local APIC input wired to the non-maskable interrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalApicNmi {
+    /// ACPI processor UID of the CPU, or `None` for every CPU
+    pub processor_uid: Option<u32>,
+    /// Local interrupt input, LINT0 or LINT1
+    pub lint: u8,
+    /// Polarity of the input
+    pub polarity: Polarity,
+    /// Trigger mode of the input
+    pub trigger: TriggerMode,
+}
+
+/// The contents of the MADT.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Madt {
+    /// Physical address of the local APIC registers, the same for every CPU
+    pub local_apic_address: PhysAddr,
+    /// The machine also has the legacy PICs, which must be masked when the
+    /// APICs are used
+    pub legacy_pics: bool,
+    /// Every CPU, the boot CPU first
+    pub local_apics: Vec<LocalApic>,
+    /// Every I/O APIC
+    pub io_apics: Vec<IoApic>,
+    /// ISA IRQs that deviate from the identity mapping
+    pub overrides: Vec<InterruptOverride>,
+    /// Local APIC inputs wired to the NMI
+    pub nmis: Vec<LocalApicNmi>,
+}
+
+impl Madt {
+    /// Returns the CPUs that are usable or can be made so.
+    pub fn cpus(&self) -> impl Iterator<Item = &LocalApic> {
+        self.local_apics
+            .iter()
+            .filter(|apic| apic.enabled || apic.online_capable)
+    }
+
+    /// Number of usable CPUs, at least 1.
+    pub fn cpu_count(&self) -> usize {
+        self.local_apics
+            .iter()
+            .filter(|apic| apic.enabled)
+            .count()
+            .max(1)
+    }
+
+    /// Returns where ISA IRQ `irq` arrives: as listed in an override, or
+    /// else on the GSI of the same number, edge-triggered and active high.
+    pub fn isa_irq(&self, irq: u8) -> InterruptOverride {
+        self.overrides
+            .iter()
+            .find(|entry| entry.irq == irq)
+            .copied()
+            .unwrap_or(InterruptOverride {
+                irq,
+                gsi: u32::from(irq),
+                polarity: Polarity::ActiveHigh,
+                trigger: TriggerMode::Edge,
+            })
+    }
+
+    /// Returns the I/O APIC serving `gsi`, if any.
+    ///
+    /// Each I/O APIC serves at most 240 GSIs from its base; the one with
+    /// the highest base not above `gsi` is taken.
+    pub fn io_apic_for(&self, gsi: u32) -> Option<&IoApic> {
+        self.io_apics
+            .iter()
+            .filter(|io_apic| io_apic.gsi_base <= gsi)
+            .max_by_key(|io_apic| io_apic.gsi_base)
+    }
+}
+
+/// Parses the MADT.
+///
+/// # Returns
+///
+/// `None` before [`acpi::init`](super::init) or if there is no valid MADT.
+/// Entries that are cut short are skipped.
+pub fn madt() -> Option<Madt> {
+    let table = super::find_table(b"APIC")?;
+    let mut madt = Madt {
+        local_apic_address: PhysAddr::try_new(u64::from(read_u32(
+            table.get(..ENTRIES_OFFSET)?,
+            LOCAL_APIC_ADDRESS,
+        )))
+        .ok()?,
+        legacy_pics: read_u32(table, FLAGS) & PCAT_COMPAT != 0,
+        local_apics: Vec::new(),
+        io_apics: Vec::new(),
+        overrides: Vec::new(),
+        nmis: Vec::new(),
+    };
+
+    let mut entries = &table[ENTRIES_OFFSET..];
+    while let [kind, len, ..] = *entries {
+        let len = usize::from(len);
+        if len < 2 || len > entries.len() {
+            break;
+        }
+        let entry = &entries[..len];
+        entries = &entries[len..];
+        match (kind, len) {
+            (ENTRY_LOCAL_APIC, 8..) => madt.local_apics.push(local_apic(
+                u32::from(entry[2]),
+                u32::from(entry[3]),
+                read_u32(entry, 4),
+            )),
+            (ENTRY_LOCAL_X2APIC, 16..) => madt.local_apics.push(local_apic(
+                read_u32(entry, 12),
+                read_u32(entry, 4),
+                read_u32(entry, 8),
+            )),
+            (ENTRY_IO_APIC, 12..) => {
+                if let Ok(address) = PhysAddr::try_new(u64::from(read_u32(entry, 4))) {
+                    madt.io_apics.push(IoApic {
+                        id: entry[2],
+                        address,
+                        gsi_base: read_u32(entry, 8),
+                    });
+                }
+            }
+            // Bus 0 is ISA, the only bus overrides are defined for.
+            (ENTRY_OVERRIDE, 10..) if entry[2] == 0 => {
+                let (polarity, trigger) = decode_flags(read_u16(entry, 8));
+                madt.overrides.push(InterruptOverride {
+                    irq: entry[3],
+                    gsi: read_u32(entry, 4),
+                    polarity,
+                    trigger,
+                });
+            }
+            (ENTRY_LOCAL_APIC_NMI, 6..) => {
+                let (polarity, trigger) = decode_flags(read_u16(entry, 3));
+                madt.nmis.push(LocalApicNmi {
+                    processor_uid: (entry[2] != ALL_PROCESSORS).then_some(u32::from(entry[2])),
+                    lint: entry[5],
+                    polarity,
+                    trigger,
+                });
+            }
+            (ENTRY_LOCAL_APIC_ADDRESS, 12..) => {
+                if let Ok(address) = PhysAddr::try_new(read_u64(entry, 4)) {
+                    madt.local_apic_address = address;
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(madt)
+}
+
+/// A local APIC from the fields its entry kinds share.
+fn local_apic(processor_uid: u32, apic_id: u32, flags: u32) -> LocalApic {
+    LocalApic {
+        processor_uid,
+        apic_id,
+        enabled: flags & APIC_ENABLED != 0,
+        online_capable: flags & APIC_ONLINE_CAPABLE != 0,
+    }
+}
+
+/// Decodes the MPS INTI flags of an entry; "conforms to the bus" means
+/// active high and edge-triggered for ISA.
+fn decode_flags(flags: u16) -> (Polarity, TriggerMode) {
+    let polarity = match flags & 0b11 {
+        0b11 => Polarity::ActiveLow,
+        _ => Polarity::ActiveHigh,
+    };
+    let trigger = match (flags >> 2) & 0b11 {
+        0b11 => TriggerMode::Level,
+        _ => TriggerMode::Edge,
+    };
+    (polarity, trigger)
+}
@@ -0,0 +1,67 @@
+//! # MCFG Table
+//!
+//! The MCFG table lists the ECAM windows through which PCIe configuration
+//! space is memory-mapped: for each PCI segment group, a physical base
+//! address and the range of buses it decodes. Every function gets 4 KiB of
+//! configuration space at `base + (bus - start_bus) << 20 | device << 15 |
+//! function << 12`.
+
+use alloc::vec::Vec;
+use x86_64::PhysAddr;
+
+use super::{read_u16, read_u64, SDT_HEADER_SIZE};
+
+/// Offset of the first entry; a reserved field follows the header.
+const ENTRIES_OFFSET: usize = SDT_HEADER_SIZE + 8;
+
+/// Size of one entry.
+const ENTRY_SIZE: usize = 16;
+
+/// One ECAM window described by the MCFG table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EcamRegion {
+    /// Physical address of the configuration space of `start_bus`
+    pub base: PhysAddr,
+    /// PCI segment group
+    pub segment: u16,
+    /// First bus decoded by the window
+    pub start_bus: u8,
+    /// Last bus decoded by the window
+    pub end_bus: u8,
+}
+
+impl EcamRegion {
+    /// Size of the window in bytes.
+    pub fn size(&self) -> u64 {
+        (u64::from(self.end_bus) - u64::from(self.start_bus) + 1) << 20
+    }
+}
+
+/// Returns the ECAM windows listed in the MCFG table.
+///
+/// # Returns
+///
+/// An empty list if there is no MCFG table, e.g. on machines without PCIe
+/// or before [`acpi::init`](super::init). Entries with an invalid bus range
+/// or address are skipped.
+pub fn ecam_regions() -> Vec<EcamRegion> {
+    let Some(table) = super::find_table(b"MCFG") else {
+        return Vec::new();
+    };
+    table
+        .get(ENTRIES_OFFSET..)
+        .unwrap_or_default()
+        .as_chunks::<ENTRY_SIZE>()
+        .0
+        .iter()
+        .filter_map(|entry| {
+            let region = EcamRegion {
+                base: PhysAddr::try_new(read_u64(entry, 0)).ok()?,
+                segment: read_u16(entry, 8),
+                start_bus: entry[10],
+                end_bus: entry[11],
+            };
+            (region.start_bus <= region.end_bus).then_some(region)
+        })
+        .collect()
+}
@@ -0,0 +1,188 @@
+//! # ACPI Tables
+//!
+//! Locates the firmware's ACPI tables so other subsystems can read the ones
+//! they need, e.g. the [`mcfg`] table describing PCIe configuration space,
+//! the [`fadt`] with the power management registers, or the [`madt`]
+//! listing CPUs and interrupt controllers.
+//! Only the static tables are used; there is no AML interpreter.
+//!
+//! UEFI firmware gives the address of the RSDP, which the UEFI loader
+//! passes on (see [`boot::rsdp`](crate::boot::rsdp)). The `bootloader`
+//! crate does not, so [`init`] otherwise searches for it where BIOS
+//! firmware puts it: the first KiB of the Extended BIOS Data Area and the
+//! read-only BIOS area below 1 MiB. The RSDP points at the RSDT
+//! (32-bit table pointers) or, from ACPI 2.0 on, the XSDT (64-bit
+//! pointers), which lists every other table.
+//!
+//! Tables are read through the linear physical memory mapping; firmware
+//! places them in RAM reported by the memory map, which it covers.
+
+pub mod fadt;
+pub mod madt;
+pub mod mcfg;
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use x86_64::PhysAddr;
+
+use crate::{boot, mm};
+
+/// Physical address of the real-mode pointer to the EBDA segment.
+const EBDA_POINTER: u64 = 0x40e;
+
+/// Start and end of the BIOS area searched for the RSDP.
+const BIOS_AREA: (u64, u64) = (0xe_0000, 0x10_0000);
+
+/// Signature that starts the RSDP.
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+
+/// Size of the ACPI 1.0 RSDP, covered by its first checksum.
+const RSDP_V1_SIZE: usize = 20;
+
+/// Size of the ACPI 2.0 RSDP, covered by its extended checksum.
+const RSDP_V2_SIZE: usize = 36;
+
+/// Size of the header common to all system description tables.
+pub const SDT_HEADER_SIZE: usize = 36;
+
+/// Physical address of the root table; zero until found.
+static ROOT: AtomicU64 = AtomicU64::new(0);
+
+/// Whether the root table is an XSDT rather than an RSDT.
+static ROOT_IS_XSDT: AtomicBool = AtomicBool::new(false);
+
+/// Errors that can occur while locating the ACPI tables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    /// No valid RSDP was found
+    NoRsdp,
+    /// The root table is missing, outside memory, or corrupt
+    BadRootTable,
+}
+
+/// Locates the RSDP and records the root table.
+///
+/// Must be called after memory management is initialized.
+///
+/// # Errors
+///
+/// Returns an [`AcpiError`] if the firmware provides no usable tables.
+pub fn init() -> Result<(), AcpiError> {
+    let rsdp = find_rsdp().ok_or(AcpiError::NoRsdp)?;
+    // ACPI 2.0 firmware may still leave the XSDT out.
+    let (root, is_xsdt) = match rsdp.get(24..32).map(|_| read_u64(rsdp, 24)) {
+        Some(xsdt) if xsdt != 0 => (xsdt, true),
+        _ => (u64::from(read_u32(rsdp, 16)), false),
+    };
+    let signature = if is_xsdt { b"XSDT" } else { b"RSDT" };
+    match table_at(root) {
+        Some(table) if &table[..4] == signature => {
+            ROOT_IS_XSDT.store(is_xsdt, Ordering::Relaxed);
+            ROOT.store(root, Ordering::Relaxed);
+            Ok(())
+        }
+        _ => Err(AcpiError::BadRootTable),
+    }
+}
+
+/// Returns the first table with `signature`, header included.
+///
+/// # Returns
+///
+/// `None` before [`init`], if there is no such table, or if its checksum
+/// is wrong.
+pub fn find_table(signature: &[u8; 4]) -> Option<&'static [u8]> {
+    let root = ROOT.load(Ordering::Relaxed);
+    if root == 0 {
+        return None;
+    }
+    let root = table_at(root)?;
+    let width = if ROOT_IS_XSDT.load(Ordering::Relaxed) {
+        8
+    } else {
+        4
+    };
+    root[SDT_HEADER_SIZE..]
+        .chunks_exact(width)
+        .map(|entry| match width {
+            8 => read_u64(entry, 0),
+            _ => u64::from(read_u32(entry, 0)),
+        })
+        .filter_map(table_at)
+        .find(|table| &table[..4] == signature)
+}
+
+/// Returns the table at `addr` if it lies in mapped memory and its checksum
+/// is correct.
+fn table_at(addr: u64) -> Option<&'static [u8]> {
+    let header = phys_bytes(addr, SDT_HEADER_SIZE)?;
+    let len = read_u32(header, 4) as usize;
+    if len < SDT_HEADER_SIZE {
+        return None;
+    }
+    let table = phys_bytes(addr, len)?;
+    checksum_ok(table).then_some(table)
+}
+
+/// Returns the RSDP passed by the boot protocol, or else searches the EBDA
+/// and the BIOS area for a valid one.
+fn find_rsdp() -> Option<&'static [u8]> {
+    if let Some(rsdp) = boot::rsdp() {
+        return phys_bytes(rsdp.as_u64(), RSDP_V2_SIZE).and_then(valid_rsdp);
+    }
+    let ebda = u64::from(read_u16(phys_bytes(EBDA_POINTER, 2)?, 0)) << 4;
+    let areas = [(ebda, ebda + 1024), BIOS_AREA];
+    areas
+        .into_iter()
+        .filter(|&(start, _)| start != 0)
+        .flat_map(|(start, end)| (start..end).step_by(16))
+        .filter_map(|addr| phys_bytes(addr, RSDP_V2_SIZE))
+        .find_map(valid_rsdp)
+}
+
+/// Returns the RSDP in `candidate`, trimmed to its revision's size, if the
+/// signature and checksums are right.
+fn valid_rsdp(candidate: &'static [u8]) -> Option<&'static [u8]> {
+    if &candidate[..8] != RSDP_SIGNATURE || !checksum_ok(&candidate[..RSDP_V1_SIZE]) {
+        return None;
+    }
+    if candidate[15] >= 2 {
+        checksum_ok(candidate).then_some(candidate)
+    } else {
+        Some(&candidate[..RSDP_V1_SIZE])
+    }
+}
+
+/// Returns `len` bytes of physical memory at `addr`, if they lie inside the
+/// linear mapping.
+fn phys_bytes(addr: u64, len: usize) -> Option<&'static [u8]> {
+    let end = addr.checked_add(len as u64)?;
+    if end > mm::phys_memory_end() {
+        return None;
+    }
+    let virt = mm::phys_to_virt(PhysAddr::new(addr));
+    Some(unsafe { core::slice::from_raw_parts(virt.as_ptr(), len) })
+}
+
+/// Returns `true` if the bytes sum to zero, as every ACPI structure's do.
+fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
+
+/// Reads a little-endian `u16` at `offset`.
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+/// Reads a little-endian `u32` at `offset`.
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut value = [0; 4];
+    value.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(value)
+}
+
+/// Reads a little-endian `u64` at `offset`.
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut value = [0; 8];
+    value.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(value)
+}
@@ -0,0 +1,100 @@
+//! # aarch64 Entry Point
+//!
+//! QEMU loads the kernel ELF at its link address in RAM (see
+//! `linker-aarch64.ld`) and starts [`start`] at EL1 with the MMU off. For a
+//! Linux-style boot, `x0` holds the physical address of the flattened
+//! device tree; for an ELF kernel QEMU places the device tree at the start
+//! of RAM instead. [`start`] parks all CPUs but the first, sets up the boot
+//! stack, enables FP/SIMD (compiled Rust code uses the vector registers),
+//! clears `.bss`, and calls `kernel_main`, which the kernel binary defines,
+//! with the original `x0`.
+
+use core::arch::naked_asm;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+extern "C" {
+    static __bss_start: u8;
+    static __bss_end: u8;
+    static __boot_stack_top: u8;
+}
+
+/// Start of RAM on QEMU `virt`.
+pub const RAM_BASE: u64 = 0x4000_0000;
+
+/// End of the address range RAM can occupy on QEMU `virt`.
+pub const RAM_END: u64 = 0x40_0000_0000;
+
+/// First word of a flattened device tree, big-endian.
+const FDT_MAGIC: u32 = 0xd00d_feed;
+
+/// Physical address of the device tree, 0 if none was found.
+static DEVICETREE: AtomicU64 = AtomicU64::new(0);
+
+/// Kernel entry point.
+///
+/// # Safety
+///
+/// Only to be jumped to by the boot firmware.
+#[unsafe(naked)]
+#[export_name = "_start"]
+#[link_section = ".text.boot"]
+pub unsafe extern "C" fn start() -> ! {
+    naked_asm!(
+        // Only CPU 0 boots; any other CPU started here waits forever
+        "mrs x1, mpidr_el1",
+        "and x1, x1, #0xff",
+        "cbnz x1, 4f",
+        "adrp x1, {stack_top}",
+        "add x1, x1, :lo12:{stack_top}",
+        "mov sp, x1",
+        // CPACR_EL1.FPEN = 0b11: no traps on FP/SIMD instructions
+        "mov x1, #(3 << 20)",
+        "msr cpacr_el1, x1",
+        "isb",
+        "adrp x1, {bss_start}",
+        "add x1, x1, :lo12:{bss_start}",
+        "adrp x2, {bss_end}",
+        "add x2, x2, :lo12:{bss_end}",
+        "2:",
+        "cmp x1, x2",
+        "b.hs 3f",
+        "str xzr, [x1], #8",
+        "b 2b",
+        "3:",
+        "bl kernel_main",
+        "4:",
+        "wfe",
+        "b 4b",
+        stack_top = sym __boot_stack_top,
+        bss_start = sym __bss_start,
+        bss_end = sym __bss_end,
+    )
+}
+
+/// Records where the device tree is.
+///
+/// Takes `x0` as passed to [`start`], and falls back to the start of RAM if
+/// `x0` does not point to a device tree in RAM.
+pub fn record_devicetree(x0: usize) {
+    let found = [x0 as u64, RAM_BASE]
+        .into_iter()
+        .find(|&address| (RAM_BASE..RAM_END).contains(&address) && is_devicetree(address));
+    DEVICETREE.store(found.unwrap_or(0), Ordering::Relaxed);
+}
+
+/// Returns the physical address of the flattened device tree, if any.
+pub fn devicetree() -> Option<u64> {
+    match DEVICETREE.load(Ordering::Relaxed) {
+        0 => None,
+        address => Some(address),
+    }
+}
+
+/// Returns `true` if a device tree header is at `address`.
+///
+/// RAM is identity-mapped (see [`mmu`](super::mmu)), so it can be read in
+/// place.
+fn is_devicetree(address: u64) -> bool {
+    address.is_multiple_of(4)
+        && unsafe { core::ptr::read_volatile(address as *const u32) } == FDT_MAGIC.to_be()
+}
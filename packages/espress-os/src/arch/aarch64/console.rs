@@ -0,0 +1,36 @@
+//! # aarch64 Console Backend
+//!
+//! The first [PL011 UART](super::pl011) as the
+//! [console backend](crate::console::ConsoleBackend).
+
+use core::fmt::Write;
+
+use super::pl011::UART0;
+use crate::console::{self, ansi_color, Color, ConsoleBackend};
+
+/// Sets up the UART and registers it as the console backend.
+pub fn init() {
+    UART0.init();
+    console::register(&UartConsole);
+}
+
+/// Console backend for the first UART.
+struct UartConsole;
+
+impl ConsoleBackend for UartConsole {
+    fn name(&self) -> &'static str {
+        "serial0"
+    }
+
+    fn write_str(&self, s: &str) {
+        let _ = (&UART0).write_str(s);
+    }
+
+    fn write_colored(&self, s: &str, color: Color) {
+        let _ = write!(&UART0, "\x1b[{}m{}\x1b[0m", ansi_color(color), s);
+    }
+
+    fn try_read(&self) -> Option<u8> {
+        UART0.try_receive()
+    }
+}
@@ -0,0 +1,201 @@
+//! # aarch64 Exception Vectors
+//!
+//! The vector table that `VBAR_EL1` points to. Each of its 16 entries saves
+//! the general purpose registers, the return state, and all FP/SIMD
+//! registers into an [`ExceptionFrame`] on the current stack and calls
+//! [`handle_exception`] with the entry's [`Kind`] and source.
+//!
+//! IRQs go to the [GIC](super::gic). Anything else is a kernel bug at this
+//! stage, as nothing runs at EL0 yet, and panics with the syndrome.
+
+use core::arch::{asm, global_asm};
+
+/// Size of an [`ExceptionFrame`] in bytes.
+const FRAME_SIZE: usize = 784;
+
+/// Registers saved on exception entry, in the layout the vectors write.
+#[repr(C)]
+#[derive(Debug)]
+pub struct ExceptionFrame {
+    /// `x0` to `x30`
+    pub x: [u64; 31],
+    /// Address the exception returns to
+    pub elr: u64,
+    /// Saved program status
+    pub spsr: u64,
+    /// Padding to align the vector registers
+    _reserved: u64,
+    /// `q0` to `q31`
+    pub q: [u128; 32],
+}
+
+const _: () = assert!(core::mem::size_of::<ExceptionFrame>() == FRAME_SIZE);
+
+/// The type of an exception.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Synchronous: system calls, aborts, undefined instructions
+    Synchronous,
+    /// An interrupt
+    Irq,
+    /// A fast interrupt
+    Fiq,
+    /// An asynchronous system error
+    SError,
+}
+
+/// Where an exception was taken from, one group of four vectors each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// The current EL, running on `SP_EL0`
+    CurrentSp0,
+    /// The current EL, running on its own stack pointer
+    CurrentSpx,
+    /// EL0 in AArch64 state
+    Lower64,
+    /// EL0 in AArch32 state
+    Lower32,
+}
+
+global_asm!(
+    ".section .text.vectors, \"ax\"",
+    ".balign 0x800",
+    ".global __exception_vectors",
+    "__exception_vectors:",
+    ".irp vector, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15",
+    ".balign 0x80",
+    "sub sp, sp, #{frame_size}",
+    "stp x0, x1, [sp, #0]",
+    "mov x0, #\\vector",
+    "b __exception_common",
+    ".endr",
+    "",
+    "__exception_common:",
+    "stp x2, x3, [sp, #16]",
+    "stp x4, x5, [sp, #32]",
+    "stp x6, x7, [sp, #48]",
+    "stp x8, x9, [sp, #64]",
+    "stp x10, x11, [sp, #80]",
+    "stp x12, x13, [sp, #96]",
+    "stp x14, x15, [sp, #112]",
+    "stp x16, x17, [sp, #128]",
+    "stp x18, x19, [sp, #144]",
+    "stp x20, x21, [sp, #160]",
+    "stp x22, x23, [sp, #176]",
+    "stp x24, x25, [sp, #192]",
+    "stp x26, x27, [sp, #208]",
+    "stp x28, x29, [sp, #224]",
+    "mrs x1, elr_el1",
+    "mrs x2, spsr_el1",
+    "stp x30, x1, [sp, #240]",
+    "str x2, [sp, #256]",
+    "stp q0, q1, [sp, #272]",
+    "stp q2, q3, [sp, #304]",
+    "stp q4, q5, [sp, #336]",
+    "stp q6, q7, [sp, #368]",
+    "stp q8, q9, [sp, #400]",
+    "stp q10, q11, [sp, #432]",
+    "stp q12, q13, [sp, #464]",
+    "stp q14, q15, [sp, #496]",
+    "stp q16, q17, [sp, #528]",
+    "stp q18, q19, [sp, #560]",
+    "stp q20, q21, [sp, #592]",
+    "stp q22, q23, [sp, #624]",
+    "stp q24, q25, [sp, #656]",
+    "stp q26, q27, [sp, #688]",
+    "stp q28, q29, [sp, #720]",
+    "stp q30, q31, [sp, #752]",
+    "mov x1, sp",
+    "bl {handler}",
+    "ldp q0, q1, [sp, #272]",
+    "ldp q2, q3, [sp, #304]",
+    "ldp q4, q5, [sp, #336]",
+    "ldp q6, q7, [sp, #368]",
+    "ldp q8, q9, [sp, #400]",
+    "ldp q10, q11, [sp, #432]",
+    "ldp q12, q13, [sp, #464]",
+    "ldp q14, q15, [sp, #496]",
+    "ldp q16, q17, [sp, #528]",
+    "ldp q18, q19, [sp, #560]",
+    "ldp q20, q21, [sp, #592]",
+    "ldp q22, q23, [sp, #624]",
+    "ldp q24, q25, [sp, #656]",
+    "ldp q26, q27, [sp, #688]",
+    "ldp q28, q29, [sp, #720]",
+    "ldp q30, q31, [sp, #752]",
+    "ldr x2, [sp, #256]",
+    "ldp x30, x1, [sp, #240]",
+    "msr elr_el1, x1",
+    "msr spsr_el1, x2",
+    "ldp x28, x29, [sp, #224]",
+    "ldp x26, x27, [sp, #208]",
+    "ldp x24, x25, [sp, #192]",
+    "ldp x22, x23, [sp, #176]",
+    "ldp x20, x21, [sp, #160]",
+    "ldp x18, x19, [sp, #144]",
+    "ldp x16, x17, [sp, #128]",
+    "ldp x14, x15, [sp, #112]",
+    "ldp x12, x13, [sp, #96]",
+    "ldp x10, x11, [sp, #80]",
+    "ldp x8, x9, [sp, #64]",
+    "ldp x6, x7, [sp, #48]",
+    "ldp x4, x5, [sp, #32]",
+    "ldp x2, x3, [sp, #16]",
+    "ldp x0, x1, [sp, #0]",
+    "add sp, sp, #{frame_size}",
+    "eret",
+    frame_size = const FRAME_SIZE,
+    handler = sym handle_exception,
+);
+
+extern "C" {
+    static __exception_vectors: u8;
+}
+
+/// Points `VBAR_EL1` at the vector table.
+pub fn init() {
+    unsafe {
+        asm!(
+            "msr vbar_el1, {}",
+            "isb",
+            in(reg) core::ptr::addr_of!(__exception_vectors),
+            options(nostack)
+        );
+    }
+}
+
+/// Dispatches an exception taken through vector number `vector`.
+extern "C" fn handle_exception(vector: u64, frame: &ExceptionFrame) {
+    let kind = match vector % 4 {
+        0 => Kind::Synchronous,
+        1 => Kind::Irq,
+        2 => Kind::Fiq,
+        _ => Kind::SError,
+    };
+    let source = match vector / 4 {
+        0 => Source::CurrentSp0,
+        1 => Source::CurrentSpx,
+        2 => Source::Lower64,
+        _ => Source::Lower32,
+    };
+
+    if kind == Kind::Irq {
+        super::gic::handle_irq();
+        return;
+    }
+
+    let (esr, far): (u64, u64);
+    unsafe {
+        asm!("mrs {}, esr_el1", out(reg) esr, options(nomem, nostack, preserves_flags));
+        asm!("mrs {}, far_el1", out(reg) far, options(nomem, nostack, preserves_flags));
+    }
+    panic!(
+        "{:?} exception from {:?} at {:#x}: ESR {:#x} (class {:#x}), FAR {:#x}",
+        kind,
+        source,
+        frame.elr,
+        esr,
+        esr >> 26,
+        far
+    );
+}
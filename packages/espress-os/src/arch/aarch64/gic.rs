@@ -0,0 +1,121 @@
+//! # GICv2 Interrupt Controller
+//!
+//! Driver for the Arm Generic Interrupt Controller version 2, the default
+//! interrupt controller of QEMU `virt`. All interrupts are routed to CPU 0
+//! at one priority; handlers are plain functions registered per interrupt
+//! ID with [`register`] and called by [`handle_irq`] from the
+//! [exception vectors](super::exceptions).
+
+use core::ptr;
+use spin::Mutex;
+
+/// Physical address of the distributor on QEMU `virt`.
+const GICD_BASE: usize = 0x0800_0000;
+/// Physical address of the CPU interface on QEMU `virt`.
+const GICC_BASE: usize = 0x0801_0000;
+
+/// Distributor control register
+const GICD_CTLR: usize = 0x000;
+/// Interrupt set-enable registers
+const GICD_ISENABLER: usize = 0x100;
+/// Interrupt clear-enable registers
+const GICD_ICENABLER: usize = 0x180;
+/// Interrupt priority registers
+const GICD_IPRIORITYR: usize = 0x400;
+/// Interrupt processor targets registers
+const GICD_ITARGETSR: usize = 0x800;
+
+/// CPU interface control register
+const GICC_CTLR: usize = 0x000;
+/// Interrupt priority mask register
+const GICC_PMR: usize = 0x004;
+/// Interrupt acknowledge register
+const GICC_IAR: usize = 0x00c;
+/// End of interrupt register
+const GICC_EOIR: usize = 0x010;
+
+/// Interrupt IDs handled, covering the private interrupts and the shared
+/// peripheral interrupts of QEMU `virt`'s devices.
+pub const MAX_IRQS: usize = 256;
+
+/// First shared peripheral interrupt; lower IDs are private to each CPU.
+const FIRST_SPI: u32 = 32;
+
+/// Interrupt IDs from here on mean there was nothing to acknowledge.
+const SPURIOUS: u32 = 1020;
+
+/// Priority given to every interrupt; lower is more urgent.
+const PRIORITY: u8 = 0xa0;
+
+/// A function handling an interrupt.
+pub type Handler = fn();
+
+/// Handler of each interrupt ID.
+static HANDLERS: Mutex<[Option<Handler>; MAX_IRQS]> = Mutex::new([None; MAX_IRQS]);
+
+/// Enables the distributor and the CPU interface, with every interrupt
+/// disabled until [`enable`]d.
+pub fn init() {
+    for register in 0..MAX_IRQS / 32 {
+        write(GICD_BASE + GICD_ICENABLER + register * 4, u32::MAX);
+    }
+    write(GICD_BASE + GICD_CTLR, 1);
+    write(GICC_BASE + GICC_PMR, 0xff);
+    write(GICC_BASE + GICC_CTLR, 1);
+}
+
+/// Sets the function called when interrupt `irq` arrives.
+///
+/// # Panics
+///
+/// Panics if `irq` is not below [`MAX_IRQS`].
+pub fn register(irq: u32, handler: Handler) {
+    HANDLERS.lock()[irq as usize] = Some(handler);
+}
+
+/// Lets interrupt `irq` through to CPU 0.
+pub fn enable(irq: u32) {
+    let irq = irq as usize;
+    unsafe {
+        ptr::write_volatile((GICD_BASE + GICD_IPRIORITYR + irq) as *mut u8, PRIORITY);
+        if irq >= FIRST_SPI as usize {
+            ptr::write_volatile((GICD_BASE + GICD_ITARGETSR + irq) as *mut u8, 1);
+        }
+    }
+    write(GICD_BASE + GICD_ISENABLER + irq / 32 * 4, 1 << (irq % 32));
+}
+
+/// Stops interrupt `irq` from being signaled.
+pub fn disable(irq: u32) {
+    let irq = irq as usize;
+    write(GICD_BASE + GICD_ICENABLER + irq / 32 * 4, 1 << (irq % 32));
+}
+
+/// Acknowledges the pending interrupt, runs its handler, and signals its
+/// end.
+///
+/// Called from the IRQ exception vector with IRQs masked.
+pub fn handle_irq() {
+    let iar = read(GICC_BASE + GICC_IAR);
+    let irq = iar & 0x3ff;
+    if irq >= SPURIOUS {
+        return;
+    }
+    let handler = HANDLERS
+        .try_lock()
+        .and_then(|handlers| handlers.get(irq as usize).copied().flatten());
+    if let Some(handler) = handler {
+        handler();
+    }
+    write(GICC_BASE + GICC_EOIR, iar);
+}
+
+/// Reads the register at `address`.
+fn read(address: usize) -> u32 {
+    unsafe { ptr::read_volatile(address as *const u32) }
+}
+
+/// Writes the register at `address`.
+fn write(address: usize, value: u32) {
+    unsafe { ptr::write_volatile(address as *mut u32, value) }
+}
@@ -0,0 +1,48 @@
+//! # aarch64 Interrupt Masking
+//!
+//! Masking and waiting for IRQs through the I bit of `DAIF` and `wfi`.
+//! Interrupts are routed by the [GIC](super::gic) and taken through the
+//! [exception vectors](super::exceptions).
+
+use core::arch::asm;
+
+/// `DAIF`: IRQs are masked.
+const DAIF_I: u64 = 1 << 7;
+
+/// Unmasks IRQs.
+pub fn enable() {
+    unsafe { asm!("msr daifclr, #2", options(nomem, nostack)) };
+}
+
+/// Masks IRQs.
+pub fn disable() {
+    unsafe { asm!("msr daifset, #2", options(nomem, nostack)) };
+}
+
+/// Returns `true` if IRQs are unmasked.
+pub fn are_enabled() -> bool {
+    let daif: u64;
+    unsafe { asm!("mrs {}, daif", out(reg) daif, options(nomem, nostack, preserves_flags)) };
+    daif & DAIF_I == 0
+}
+
+/// Runs `f` with IRQs masked, restoring the previous state afterwards.
+pub fn without_interrupts<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let enabled = are_enabled();
+    if enabled {
+        disable();
+    }
+    let result = f();
+    if enabled {
+        enable();
+    }
+    result
+}
+
+/// Sleeps until the next interrupt.
+pub fn wait() {
+    unsafe { asm!("wfi", options(nomem, nostack, preserves_flags)) };
+}
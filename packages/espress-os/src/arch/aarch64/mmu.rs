@@ -0,0 +1,169 @@
+//! # aarch64 MMU
+//!
+//! [`init`] turns the MMU on with an identity mapping of the lower 512 GiB
+//! in 1 GiB blocks through `TTBR0_EL1`, with a 4 KiB granule and 39-bit
+//! virtual addresses, so translation starts at level 1:
+//!
+//! | Range              | Memory type                  | Contents on QEMU `virt`     |
+//! |--------------------|------------------------------|-----------------------------|
+//! | 0 - 1 GiB          | Device-nGnRnE, never execute | flash, GIC, UART, virtio    |
+//! | 1 GiB - 256 GiB    | Normal, write-back cacheable | RAM                         |
+//! | 256 GiB - 512 GiB  | Device-nGnRnE, never execute | high PCIe windows           |
+//!
+//! Until then all data accesses are Device accesses, which must be aligned
+//! and on which exclusive loads and stores (and so locks) need not work, so
+//! it runs before anything else. `TTBR1_EL1` walks are disabled.
+
+use core::arch::asm;
+use core::cell::UnsafeCell;
+
+use super::boot::{RAM_BASE, RAM_END};
+
+/// Size of the smallest page in bytes.
+pub const PAGE_SIZE: u64 = 4096;
+
+/// Size of a level 1 block in bytes.
+const BLOCK_SIZE: u64 = 1 << 30;
+
+/// Entries of a translation table.
+const ENTRIES: usize = 512;
+
+/// `MAIR_EL1` index of Device-nGnRnE memory.
+const ATTR_DEVICE: u64 = 0;
+/// `MAIR_EL1` index of Normal write-back memory.
+const ATTR_NORMAL: u64 = 1;
+/// `MAIR_EL1`: attribute 0 Device-nGnRnE, attribute 1 Normal inner and
+/// outer write-back read/write-allocate.
+const MAIR: u64 = 0xff << (8 * ATTR_NORMAL);
+
+/// Descriptor: a block mapping.
+const DESC_BLOCK: u64 = 0b01;
+/// Descriptor: the access flag, set so the first access does not fault.
+const DESC_AF: u64 = 1 << 10;
+/// Descriptor: inner shareable.
+const DESC_SH_INNER: u64 = 0b11 << 8;
+/// Descriptor: not executable at EL1.
+const DESC_PXN: u64 = 1 << 53;
+/// Descriptor: not executable at EL0.
+const DESC_UXN: u64 = 1 << 54;
+
+/// `TCR_EL1.T0SZ` for 39-bit virtual addresses.
+const TCR_T0SZ: u64 = 64 - 39;
+/// `TCR_EL1`: inner and outer write-back walks of inner shareable tables.
+const TCR_WALK_ATTRS: u64 = 0b01 << 8 | 0b01 << 10 | 0b11 << 12;
+/// `TCR_EL1.EPD1`: no walks through `TTBR1_EL1`.
+const TCR_EPD1: u64 = 1 << 23;
+/// Shift of `TCR_EL1.IPS`, the physical address size.
+const TCR_IPS_SHIFT: u64 = 32;
+
+/// `SCTLR_EL1`: MMU enable.
+const SCTLR_M: u64 = 1 << 0;
+/// `SCTLR_EL1`: data cache enable.
+const SCTLR_C: u64 = 1 << 2;
+/// `SCTLR_EL1`: instruction cache enable.
+const SCTLR_I: u64 = 1 << 12;
+
+/// The level 1 table, aligned to its size as `TTBR0_EL1` requires.
+#[repr(C, align(4096))]
+struct Table(UnsafeCell<[u64; ENTRIES]>);
+
+// Only written by `init`, before the MMU reads it and before other code runs.
+unsafe impl Sync for Table {}
+
+static IDENTITY: Table = Table(UnsafeCell::new([0; ENTRIES]));
+
+/// Builds the identity mapping and turns on the MMU and the caches.
+///
+/// # Safety
+///
+/// Must be called once, first thing after the entry point, with the MMU
+/// off.
+pub unsafe fn init() {
+    let table = IDENTITY.0.get();
+    for (index, entry) in (*table).iter_mut().enumerate() {
+        let address = index as u64 * BLOCK_SIZE;
+        let attributes = if (RAM_BASE..RAM_END).contains(&address) {
+            ATTR_NORMAL << 2 | DESC_SH_INNER
+        } else {
+            ATTR_DEVICE << 2 | DESC_PXN | DESC_UXN
+        };
+        *entry = address | attributes | DESC_AF | DESC_BLOCK;
+    }
+
+    let mmfr0: u64;
+    asm!("mrs {}, id_aa64mmfr0_el1", out(reg) mmfr0, options(nomem, nostack));
+    let tcr = TCR_T0SZ | TCR_WALK_ATTRS | TCR_EPD1 | (mmfr0 & 0b111) << TCR_IPS_SHIFT;
+
+    asm!(
+        "msr mair_el1, {mair}",
+        "msr tcr_el1, {tcr}",
+        "msr ttbr0_el1, {table}",
+        "dsb ish",
+        "isb",
+        "tlbi vmalle1",
+        "dsb ish",
+        "isb",
+        "mrs {sctlr}, sctlr_el1",
+        "orr {sctlr}, {sctlr}, {enable}",
+        "msr sctlr_el1, {sctlr}",
+        "isb",
+        mair = in(reg) MAIR,
+        tcr = in(reg) tcr,
+        table = in(reg) table as u64,
+        enable = in(reg) SCTLR_M | SCTLR_C | SCTLR_I,
+        sctlr = out(reg) _,
+        options(nostack)
+    );
+}
+
+/// Returns the physical address of the active top-level translation table.
+pub fn root() -> u64 {
+    let ttbr0: u64;
+    unsafe { asm!("mrs {}, ttbr0_el1", out(reg) ttbr0, options(nomem, nostack, preserves_flags)) };
+    ttbr0 & 0x0000_ffff_ffff_fffe
+}
+
+/// Switches to the translation tables rooted at physical address `root`.
+///
+/// # Safety
+///
+/// `root` must be a level 1 table that maps the running kernel at the
+/// same addresses as the current one.
+pub unsafe fn set_root(root: u64) {
+    asm!(
+        "msr ttbr0_el1, {}",
+        "isb",
+        "tlbi vmalle1",
+        "dsb ish",
+        "isb",
+        in(reg) root,
+        options(nostack)
+    );
+}
+
+/// Removes the translation of the page containing `address` from the TLB.
+pub fn flush_page(address: u64) {
+    unsafe {
+        asm!(
+            "dsb ishst",
+            "tlbi vaae1is, {}",
+            "dsb ish",
+            "isb",
+            in(reg) address >> 12,
+            options(nostack)
+        );
+    }
+}
+
+/// Removes all translations from the TLB.
+pub fn flush_all() {
+    unsafe {
+        asm!(
+            "dsb ishst",
+            "tlbi vmalle1is",
+            "dsb ish",
+            "isb",
+            options(nostack)
+        )
+    };
+}
@@ -0,0 +1,23 @@
+//! # aarch64
+//!
+//! Support for 64-bit Arm at EL1, as found on the QEMU `virt` machine.
+//! Besides the common [architecture interface](super) ([`console`],
+//! [`interrupts`], [`mmu`], and [`timer`]), these are the entry point
+//! ([`boot`]), the exception vectors ([`exceptions`]), the GICv2 interrupt
+//! controller ([`gic`]), and the PL011 UART ([`pl011`]). The kernel
+//! allocates from the shared [arena heap](super::heap).
+//!
+//! Device addresses are those of QEMU `virt`; the device tree the machine
+//! passes is recorded but not parsed yet.
+
+pub mod boot;
+pub mod console;
+pub mod exceptions;
+pub mod gic;
+pub mod interrupts;
+pub mod mmu;
+pub mod pl011;
+pub mod timer;
+
+/// Name of the architecture, as shown at boot.
+pub const NAME: &str = "aarch64";
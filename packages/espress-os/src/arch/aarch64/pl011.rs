@@ -0,0 +1,104 @@
+//! # PL011 UART
+//!
+//! Driver for the Arm PrimeCell PL011 UART, the serial port of QEMU `virt`.
+//! It runs without interrupts: output waits for room in the transmit FIFO
+//! and input is polled with [`Pl011::try_receive`].
+
+use core::ptr;
+
+/// Physical address of the first UART on QEMU `virt`.
+pub const UART0_BASE: usize = 0x0900_0000;
+
+/// The first UART, identity-mapped (see [`mmu`](super::mmu)).
+pub static UART0: Pl011 = Pl011::new(UART0_BASE);
+
+/// Data register
+const DR: usize = 0x00;
+/// Flag register
+const FR: usize = 0x18;
+/// Line control register
+const LCR_H: usize = 0x2c;
+/// Control register
+const CR: usize = 0x30;
+/// Interrupt mask set/clear register
+const IMSC: usize = 0x38;
+/// Interrupt clear register
+const ICR: usize = 0x44;
+
+/// FR: the receive FIFO is empty.
+const FR_RXFE: u32 = 1 << 4;
+/// FR: the transmit FIFO is full.
+const FR_TXFF: u32 = 1 << 5;
+/// LCR_H: enable the FIFOs.
+const LCR_H_FEN: u32 = 1 << 4;
+/// LCR_H: 8 data bits.
+const LCR_H_WLEN_8: u32 = 0b11 << 5;
+/// CR: enable the UART.
+const CR_UARTEN: u32 = 1 << 0;
+/// CR: enable the transmitter.
+const CR_TXE: u32 = 1 << 8;
+/// CR: enable the receiver.
+const CR_RXE: u32 = 1 << 9;
+
+/// A PL011 UART.
+pub struct Pl011 {
+    /// Address of the registers
+    base: usize,
+}
+
+impl Pl011 {
+    /// Creates a driver for the UART with registers at `base`.
+    pub const fn new(base: usize) -> Self {
+        Pl011 { base }
+    }
+
+    /// Sets the UART to 8 data bits, no parity, one stop bit, with FIFOs
+    /// and all interrupts masked.
+    ///
+    /// The baud rate set by the firmware is kept; QEMU ignores it anyway.
+    pub fn init(&self) {
+        self.write(CR, 0);
+        self.write(IMSC, 0);
+        self.write(ICR, 0x7ff);
+        self.write(LCR_H, LCR_H_FEN | LCR_H_WLEN_8);
+        self.write(CR, CR_UARTEN | CR_TXE | CR_RXE);
+    }
+
+    /// Sends one byte, waiting for room in the transmit FIFO.
+    pub fn send(&self, byte: u8) {
+        while self.read(FR) & FR_TXFF != 0 {
+            core::hint::spin_loop();
+        }
+        self.write(DR, u32::from(byte));
+    }
+
+    /// Returns the next received byte, or `None` if none is waiting.
+    pub fn try_receive(&self) -> Option<u8> {
+        if self.read(FR) & FR_RXFE != 0 {
+            return None;
+        }
+        Some(self.read(DR) as u8)
+    }
+
+    /// Reads the register at `offset`.
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { ptr::read_volatile((self.base + offset) as *const u32) }
+    }
+
+    /// Writes the register at `offset`.
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { ptr::write_volatile((self.base + offset) as *mut u32, value) }
+    }
+}
+
+impl core::fmt::Write for &Pl011 {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for byte in s.bytes() {
+            if byte == b'\n' {
+                self.send(b'\r');
+            }
+            self.send(byte);
+        }
+        Ok(())
+    }
+}
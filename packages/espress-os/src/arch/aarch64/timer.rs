@@ -0,0 +1,78 @@
+//! # aarch64 Generic Timer
+//!
+//! The virtual counter `CNTVCT_EL0` is the free-running counter of the
+//! architecture interface; unlike the x86_64 TSC its frequency is given by
+//! the firmware in `CNTFRQ_EL0`, so there is nothing to calibrate.
+//!
+//! [`init`] also starts the virtual timer as a periodic [`TICK_HZ`] tick
+//! through its private interrupt, counted by [`interrupts`].
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use super::gic;
+
+/// Rate of the periodic tick in Hz.
+pub const TICK_HZ: u64 = 100;
+
+/// Interrupt ID of the EL1 virtual timer on QEMU `virt`.
+const VIRTUAL_TIMER_IRQ: u32 = 27;
+
+/// `CNTV_CTL_EL0`: the timer is enabled.
+const CTL_ENABLE: u64 = 1 << 0;
+
+/// Ticks handled since [`init`].
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the current counter value.
+pub fn ticks() -> u64 {
+    let ticks: u64;
+    unsafe { asm!("isb", "mrs {}, cntvct_el0", out(reg) ticks, options(nomem, nostack)) };
+    ticks
+}
+
+/// Returns the counter frequency in Hz.
+pub fn frequency() -> u64 {
+    let frequency: u64;
+    unsafe { asm!("mrs {}, cntfrq_el0", out(reg) frequency, options(nomem, nostack)) };
+    frequency
+}
+
+/// Converts a number of counter ticks to a duration.
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    match frequency() {
+        0 => Duration::ZERO,
+        frequency => {
+            Duration::from_nanos((u128::from(ticks) * 1_000_000_000 / u128::from(frequency)) as u64)
+        }
+    }
+}
+
+/// Returns the number of periodic ticks since [`init`].
+pub fn interrupts() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Starts the periodic tick.
+///
+/// Must be called after [`gic::init`]; the tick arrives once interrupts
+/// are enabled.
+pub fn init() {
+    gic::register(VIRTUAL_TIMER_IRQ, tick);
+    rearm();
+    unsafe { asm!("msr cntv_ctl_el0, {}", in(reg) CTL_ENABLE, options(nomem, nostack)) };
+    gic::enable(VIRTUAL_TIMER_IRQ);
+}
+
+/// Schedules the next tick one period from now.
+fn rearm() {
+    let interval = frequency() / TICK_HZ;
+    unsafe { asm!("msr cntv_tval_el0, {}", in(reg) interval, options(nomem, nostack)) };
+}
+
+/// Handles the timer interrupt.
+fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+    rearm();
+}
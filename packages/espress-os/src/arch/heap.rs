@@ -0,0 +1,58 @@
+//! # Arena Heap
+//!
+//! The global allocator on the architectures other than x86_64, where the
+//! kernel has no frame allocator or page table management of its own yet: a
+//! fixed arena in `.bss`, managed by the same linked-list allocator as the
+//! x86_64 kernel heap.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::ptr::{self, NonNull};
+use linked_list_allocator::Heap;
+use spin::Mutex;
+
+use super::interrupts::without_interrupts;
+
+/// Size of the arena in bytes (4 MiB).
+pub const HEAP_SIZE: usize = 4 * 1024 * 1024;
+
+/// The arena.
+#[repr(C, align(4096))]
+struct Arena(UnsafeCell<[u8; HEAP_SIZE]>);
+
+// Handed to the allocator once, which owns it from then on.
+unsafe impl Sync for Arena {}
+
+static ARENA: Arena = Arena(UnsafeCell::new([0; HEAP_SIZE]));
+
+#[global_allocator]
+static ALLOCATOR: ArenaHeap = ArenaHeap(Mutex::new(Heap::empty()));
+
+/// The global allocator, empty until [`init`].
+struct ArenaHeap(Mutex<Heap>);
+
+unsafe impl GlobalAlloc for ArenaHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        without_interrupts(|| {
+            self.0
+                .lock()
+                .allocate_first_fit(layout)
+                .map_or(ptr::null_mut(), NonNull::as_ptr)
+        })
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        without_interrupts(|| {
+            self.0
+                .lock()
+                .deallocate(NonNull::new_unchecked(ptr), layout)
+        });
+    }
+}
+
+/// Hands the arena to the allocator.
+///
+/// Must be called once, after the MMU is on and before anything allocates.
+pub fn init() {
+    unsafe { ALLOCATOR.0.lock().init(ARENA.0.get().cast(), HEAP_SIZE) };
+}
@@ -0,0 +1,107 @@
+//! # i686 Entry Point
+//!
+//! A multiboot (version 1) loader finds the [`MultibootHeader`] in the
+//! first 8 KiB of the image, loads the kernel at its link address (see
+//! `linker-i686.ld`), and jumps to [`start`] in 32-bit protected mode with
+//! paging and interrupts off, flat segments of unknown selectors, the
+//! loader's magic number in `eax`, and the physical address of the
+//! multiboot information in `ebx`. [`start`] sets up the boot stack, clears
+//! `.bss`, and calls `kernel_main`, which the kernel binary defines, with
+//! both.
+
+use core::arch::naked_asm;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+extern "C" {
+    static __bss_start: u8;
+    static __bss_end: u8;
+    static __boot_stack_top: u8;
+}
+
+/// Magic number that marks the multiboot header.
+const MULTIBOOT_HEADER_MAGIC: u32 = 0x1bad_b002;
+
+/// Header flag: load modules page-aligned.
+const MULTIBOOT_PAGE_ALIGN: u32 = 1 << 0;
+/// Header flag: pass the amount of memory in the multiboot information.
+const MULTIBOOT_MEMORY_INFO: u32 = 1 << 1;
+
+/// Magic number a multiboot loader passes in `eax`.
+pub const MULTIBOOT_BOOTLOADER_MAGIC: u32 = 0x2bad_b002;
+
+/// The header a multiboot loader looks for.
+#[repr(C, align(4))]
+pub struct MultibootHeader {
+    /// [`MULTIBOOT_HEADER_MAGIC`]
+    magic: u32,
+    /// Features the kernel requires of the loader
+    flags: u32,
+    /// Makes the sum of the three fields zero
+    checksum: u32,
+}
+
+#[used]
+#[link_section = ".multiboot"]
+static MULTIBOOT_HEADER: MultibootHeader = MultibootHeader {
+    magic: MULTIBOOT_HEADER_MAGIC,
+    flags: MULTIBOOT_PAGE_ALIGN | MULTIBOOT_MEMORY_INFO,
+    checksum: 0u32
+        .wrapping_sub(MULTIBOOT_HEADER_MAGIC)
+        .wrapping_sub(MULTIBOOT_PAGE_ALIGN | MULTIBOOT_MEMORY_INFO),
+};
+
+/// Physical address of the multiboot information, 0 if there is none.
+static MULTIBOOT_INFO: AtomicU32 = AtomicU32::new(0);
+
+/// Kernel entry point.
+///
+/// The stack is 16-byte aligned at the call, as compiled code expects.
+///
+/// # Safety
+///
+/// Only to be jumped to by a multiboot loader.
+#[unsafe(naked)]
+#[export_name = "_start"]
+#[link_section = ".text.boot"]
+pub unsafe extern "C" fn start() -> ! {
+    naked_asm!(
+        "mov esp, offset {stack_top}",
+        "mov esi, eax",
+        "cld",
+        "mov edi, offset {bss_start}",
+        "mov ecx, offset {bss_end}",
+        "sub ecx, edi",
+        "xor eax, eax",
+        "rep stosb",
+        "sub esp, 8",
+        "push ebx",
+        "push esi",
+        "call kernel_main",
+        "2:",
+        "cli",
+        "hlt",
+        "jmp 2b",
+        stack_top = sym __boot_stack_top,
+        bss_start = sym __bss_start,
+        bss_end = sym __bss_end,
+    )
+}
+
+/// Records where the multiboot information is.
+///
+/// Takes `eax` and `ebx` as passed to [`start`] and ignores them unless
+/// `eax` is the [magic number](MULTIBOOT_BOOTLOADER_MAGIC) of a multiboot
+/// loader.
+pub fn record_multiboot(magic: u32, info: usize) {
+    if magic == MULTIBOOT_BOOTLOADER_MAGIC {
+        MULTIBOOT_INFO.store(info as u32, Ordering::Relaxed);
+    }
+}
+
+/// Returns the physical address of the multiboot information, if any.
+pub fn multiboot_info() -> Option<u32> {
+    match MULTIBOOT_INFO.load(Ordering::Relaxed) {
+        0 => None,
+        address => Some(address),
+    }
+}
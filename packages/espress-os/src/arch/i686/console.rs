@@ -0,0 +1,63 @@
+//! # i686 Console Backends
+//!
+//! The [VGA text screen](super::vga) and the COM1
+//! [serial port](super::uart) as
+//! [console backends](crate::console::ConsoleBackend).
+
+use core::fmt::Write;
+
+use super::uart::COM1;
+use super::vga::SCREEN;
+use crate::console::{self, ansi_color, Color, ConsoleBackend};
+
+/// Registers the VGA text screen and COM1 as the console backends.
+///
+/// The VGA screen is cleared first, dropping what the loader left there,
+/// and left out when the loader set up no text mode (see
+/// [`boot::has_text_mode`](crate::boot::has_text_mode)).
+pub fn init() {
+    if crate::boot::has_text_mode() {
+        SCREEN.lock().clear();
+        console::register(&VgaConsole);
+    }
+    COM1.init();
+    console::register(&SerialConsole);
+}
+
+/// Console backend for the VGA text screen.
+struct VgaConsole;
+
+impl ConsoleBackend for VgaConsole {
+    fn name(&self) -> &'static str {
+        "vga"
+    }
+
+    fn write_str(&self, s: &str) {
+        SCREEN.lock().write_string(s, Color::LightGray);
+    }
+
+    fn write_colored(&self, s: &str, color: Color) {
+        SCREEN.lock().write_string(s, color);
+    }
+}
+
+/// Console backend for the COM1 serial port.
+struct SerialConsole;
+
+impl ConsoleBackend for SerialConsole {
+    fn name(&self) -> &'static str {
+        "serial0"
+    }
+
+    fn write_str(&self, s: &str) {
+        let _ = (&COM1).write_str(s);
+    }
+
+    fn write_colored(&self, s: &str, color: Color) {
+        let _ = write!(&COM1, "\x1b[{}m{}\x1b[0m", ansi_color(color), s);
+    }
+
+    fn try_read(&self) -> Option<u8> {
+        COM1.try_receive()
+    }
+}
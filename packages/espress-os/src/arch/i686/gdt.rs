@@ -0,0 +1,56 @@
+//! # i686 Segment Descriptors
+//!
+//! A multiboot loader leaves flat segments loaded but makes no promise
+//! about its GDT, which may be overwritten at any time. [`init`] loads the
+//! kernel's own: a flat 4 GiB code and data segment, both ring 0. Nothing
+//! runs in ring 3 yet, so there is no TSS.
+
+use core::arch::asm;
+use core::mem::size_of_val;
+
+/// Selector of the kernel code segment.
+pub const KERNEL_CODE: u16 = 0x08;
+/// Selector of the kernel data segment.
+pub const KERNEL_DATA: u16 = 0x10;
+
+/// The null descriptor and the flat code and data segments.
+static GDT: [u64; 3] = [0, 0x00cf_9a00_0000_ffff, 0x00cf_9200_0000_ffff];
+
+/// Operand of `lgdt` and `lidt`.
+#[repr(C, packed)]
+pub(super) struct DescriptorTablePointer {
+    /// Size of the table in bytes, minus one
+    pub limit: u16,
+    /// Linear address of the table
+    pub base: u32,
+}
+
+/// Loads the GDT and reloads every segment register from it.
+///
+/// Must be called first thing after the entry point.
+pub fn init() {
+    let pointer = DescriptorTablePointer {
+        limit: (size_of_val(&GDT) - 1) as u16,
+        base: GDT.as_ptr() as u32,
+    };
+    unsafe {
+        asm!(
+            "lgdt [{pointer}]",
+            "mov ds, {data:x}",
+            "mov es, {data:x}",
+            "mov fs, {data:x}",
+            "mov gs, {data:x}",
+            "mov ss, {data:x}",
+            "push {code}",
+            "lea {target}, [2f]",
+            "push {target}",
+            "retf",
+            "2:",
+            pointer = in(reg) &pointer,
+            data = in(reg) u32::from(KERNEL_DATA),
+            code = const KERNEL_CODE,
+            target = out(reg) _,
+            options(preserves_flags)
+        );
+    }
+}
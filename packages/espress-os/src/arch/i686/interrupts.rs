@@ -0,0 +1,50 @@
+//! # i686 Interrupt Flag
+//!
+//! Masking and waiting for interrupts through `EFLAGS.IF`, `cli`, `sti`,
+//! and `hlt`. The interrupts are dispatched through the
+//! [interrupt descriptor table](super::trap) and the
+//! [8259 controllers](super::pic).
+
+use core::arch::asm;
+
+/// `EFLAGS.IF`: maskable interrupts are enabled.
+const EFLAGS_IF: u32 = 1 << 9;
+
+/// Enables maskable interrupts.
+pub fn enable() {
+    unsafe { asm!("sti", options(nomem, nostack)) };
+}
+
+/// Disables maskable interrupts.
+pub fn disable() {
+    unsafe { asm!("cli", options(nomem, nostack)) };
+}
+
+/// Returns `true` if maskable interrupts are enabled.
+pub fn are_enabled() -> bool {
+    let eflags: u32;
+    unsafe { asm!("pushfd", "pop {}", out(reg) eflags, options(nomem, preserves_flags)) };
+    eflags & EFLAGS_IF != 0
+}
+
+/// Runs `f` with interrupts disabled, restoring the previous state
+/// afterwards.
+pub fn without_interrupts<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let enabled = are_enabled();
+    if enabled {
+        disable();
+    }
+    let result = f();
+    if enabled {
+        enable();
+    }
+    result
+}
+
+/// Sleeps until the next interrupt.
+pub fn wait() {
+    unsafe { asm!("hlt", options(nomem, nostack)) };
+}
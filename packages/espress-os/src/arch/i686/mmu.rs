@@ -0,0 +1,135 @@
+//! # i686 MMU
+//!
+//! [`init`] turns on 32-bit paging with an identity mapping of the whole
+//! 4 GiB address space in 4 MiB pages, so a single page directory does:
+//!
+//! | Range         | Caching  | Contents on a PC                   |
+//! |---------------|----------|------------------------------------|
+//! | 0 - 3 GiB     | cached   | RAM, the VGA memory, the BIOS      |
+//! | 3 GiB - 4 GiB | uncached | PCI memory, local APIC, I/O APIC   |
+//!
+//! Entries and tables are half the size of those on x86_64: 32-bit
+//! entries, 1024 to a table, and two levels instead of four. PAE is not
+//! used, as not every i686 has it; 4 MiB pages only need `CR4.PSE`. The
+//! VGA memory below 1 MiB is mapped cached, but the firmware's memory type
+//! ranges keep it uncached anyway. All mappings are global, as there is one address space.
+
+use core::arch::asm;
+use core::cell::UnsafeCell;
+
+/// Size of the smallest page in bytes.
+pub const PAGE_SIZE: u64 = 4096;
+
+/// Size of a large page in bytes.
+const LARGE_PAGE_SIZE: u64 = 4 << 20;
+
+/// Entries of a page directory or page table.
+const ENTRIES: usize = 1024;
+
+/// Addresses from here on are mapped uncached.
+const DEVICE_BASE: u64 = 0xc000_0000;
+
+/// PDE: present.
+const PDE_PRESENT: u32 = 1 << 0;
+/// PDE: writable.
+const PDE_WRITABLE: u32 = 1 << 1;
+/// PDE: write-through.
+const PDE_WRITE_THROUGH: u32 = 1 << 3;
+/// PDE: caching disabled.
+const PDE_CACHE_DISABLE: u32 = 1 << 4;
+/// PDE: maps a 4 MiB page rather than pointing to a page table.
+const PDE_LARGE: u32 = 1 << 7;
+/// PDE: global, kept in the TLB across `CR3` writes.
+const PDE_GLOBAL: u32 = 1 << 8;
+
+/// `CR0.WP`: write protection applies to ring 0 as well.
+const CR0_WP: u32 = 1 << 16;
+/// `CR0.PG`: paging is on.
+const CR0_PG: u32 = 1 << 31;
+/// `CR4.PSE`: 4 MiB pages.
+const CR4_PSE: u32 = 1 << 4;
+/// `CR4.PGE`: global pages.
+const CR4_PGE: u32 = 1 << 7;
+
+/// A page directory, aligned to a page as `CR3` requires.
+#[repr(C, align(4096))]
+struct Directory(UnsafeCell<[u32; ENTRIES]>);
+
+// Only written by `init`, before paging uses it and before other code runs.
+unsafe impl Sync for Directory {}
+
+static IDENTITY: Directory = Directory(UnsafeCell::new([0; ENTRIES]));
+
+/// Builds the identity mapping and turns on paging.
+///
+/// # Safety
+///
+/// Must be called once, right after the [GDT](super::gdt) is loaded, with
+/// paging off.
+pub unsafe fn init() {
+    let directory = IDENTITY.0.get();
+    for (index, entry) in (*directory).iter_mut().enumerate() {
+        let address = index as u64 * LARGE_PAGE_SIZE;
+        let caching = if address >= DEVICE_BASE {
+            PDE_CACHE_DISABLE | PDE_WRITE_THROUGH
+        } else {
+            0
+        };
+        *entry = address as u32 | caching | PDE_PRESENT | PDE_WRITABLE | PDE_LARGE | PDE_GLOBAL;
+    }
+    asm!(
+        "mov {tmp}, cr4",
+        "or {tmp}, {cr4}",
+        "mov cr4, {tmp}",
+        "mov cr3, {root}",
+        "mov {tmp}, cr0",
+        "or {tmp}, {cr0}",
+        "mov cr0, {tmp}",
+        tmp = out(reg) _,
+        root = in(reg) directory as u32,
+        cr4 = const CR4_PSE | CR4_PGE,
+        cr0 = const CR0_PG | CR0_WP,
+        options(nostack)
+    );
+}
+
+/// Returns the physical address of the active page directory.
+pub fn root() -> u64 {
+    let cr3: u32;
+    unsafe { asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack, preserves_flags)) };
+    u64::from(cr3 & !0xfff)
+}
+
+/// Switches to the page directory at physical address `root`.
+///
+/// # Safety
+///
+/// `root` must be a page directory below 4 GiB that maps the running
+/// kernel at the same addresses as the current one.
+pub unsafe fn set_root(root: u64) {
+    asm!("mov cr3, {}", in(reg) root as u32, options(nostack, preserves_flags));
+}
+
+/// Removes the translation of the page containing `address` from the TLB.
+pub fn flush_page(address: u64) {
+    unsafe { asm!("invlpg [{}]", in(reg) address as u32, options(nostack, preserves_flags)) };
+}
+
+/// Removes all translations from the TLB.
+///
+/// Global pages, which all of the identity mapping is, are only flushed by
+/// toggling `CR4.PGE`.
+pub fn flush_all() {
+    unsafe {
+        asm!(
+            "mov {tmp}, cr4",
+            "xor {tmp}, {pge}",
+            "mov cr4, {tmp}",
+            "xor {tmp}, {pge}",
+            "mov cr4, {tmp}",
+            tmp = out(reg) _,
+            pge = const CR4_PGE,
+            options(nostack)
+        );
+    }
+}
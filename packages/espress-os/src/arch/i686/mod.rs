@@ -0,0 +1,30 @@
+//! # i686
+//!
+//! Support for 32-bit x86 in protected mode, for machines too old for
+//! x86_64, as started by a multiboot loader such as GRUB or QEMU's
+//! `-kernel`. Besides the common [architecture interface](super)
+//! ([`console`], [`interrupts`], [`mmu`], and [`timer`]), these are the
+//! entry point ([`boot`]), the segment descriptors ([`gdt`]), the interrupt
+//! descriptor table and its entry stubs ([`trap`]), the 8259 interrupt
+//! controllers ([`pic`]), port I/O ([`port`]), the 16550 UART ([`uart`]),
+//! and the VGA text screen ([`vga`]). The kernel allocates from the shared
+//! [arena heap](super::heap).
+//!
+//! The port only uses instructions every i686 has: no long mode, no SSE
+//! (the target is soft-float), and 32-bit page tables without PAE. The
+//! multiboot information the loader passes is recorded but not parsed yet.
+
+pub mod boot;
+pub mod console;
+pub mod gdt;
+pub mod interrupts;
+pub mod mmu;
+pub mod pic;
+pub mod port;
+pub mod timer;
+pub mod trap;
+pub mod uart;
+pub mod vga;
+
+/// Name of the architecture, as shown at boot.
+pub const NAME: &str = "i686";
@@ -0,0 +1,131 @@
+//! # 8259 Interrupt Controllers
+//!
+//! Driver for the PC's two cascaded 8259 programmable interrupt
+//! controllers, the only interrupt controllers every i686 machine has.
+//! [`init`] moves their 16 IRQs to vectors [`IRQ_BASE`] and up, clear of
+//! the CPU exceptions, and masks them all. Handlers are plain functions
+//! registered per IRQ with [`register`] and called by [`handle_irq`] from
+//! the [trap stubs](super::trap).
+
+use spin::Mutex;
+
+use super::port::{inb, outb};
+
+/// Vector of IRQ 0; the 16 IRQs follow.
+pub const IRQ_BASE: u8 = 32;
+
+/// Number of IRQs of the two controllers.
+pub const IRQS: usize = 16;
+
+/// Command port of the primary controller.
+const PRIMARY_COMMAND: u16 = 0x20;
+/// Data port of the primary controller.
+const PRIMARY_DATA: u16 = 0x21;
+/// Command port of the secondary controller.
+const SECONDARY_COMMAND: u16 = 0xa0;
+/// Data port of the secondary controller.
+const SECONDARY_DATA: u16 = 0xa1;
+
+/// ICW1: initialize, with ICW4 to follow.
+const ICW1_INIT: u8 = 0x11;
+/// ICW4: 8086 mode.
+const ICW4_8086: u8 = 0x01;
+/// OCW2: non-specific end of interrupt.
+const OCW2_EOI: u8 = 0x20;
+/// OCW3: read the in-service register on the next read.
+const OCW3_READ_ISR: u8 = 0x0b;
+
+/// The IRQ of the primary controller the secondary one is cascaded to.
+const CASCADE_IRQ: u8 = 2;
+
+/// A function handling an IRQ.
+pub type Handler = fn();
+
+/// Handler of each IRQ.
+static HANDLERS: Mutex<[Option<Handler>; IRQS]> = Mutex::new([None; IRQS]);
+
+/// Remaps both controllers to [`IRQ_BASE`], with every IRQ masked until
+/// [`enable`]d.
+pub fn init() {
+    unsafe {
+        outb(PRIMARY_COMMAND, ICW1_INIT);
+        outb(SECONDARY_COMMAND, ICW1_INIT);
+        outb(PRIMARY_DATA, IRQ_BASE);
+        outb(SECONDARY_DATA, IRQ_BASE + 8);
+        outb(PRIMARY_DATA, 1 << CASCADE_IRQ);
+        outb(SECONDARY_DATA, CASCADE_IRQ);
+        outb(PRIMARY_DATA, ICW4_8086);
+        outb(SECONDARY_DATA, ICW4_8086);
+        outb(PRIMARY_DATA, !(1 << CASCADE_IRQ));
+        outb(SECONDARY_DATA, 0xff);
+    }
+}
+
+/// Registers `handler` for `irq`, replacing any earlier one.
+///
+/// # Panics
+///
+/// Panics if `irq` is not below [`IRQS`].
+pub fn register(irq: u8, handler: Handler) {
+    HANDLERS.lock()[usize::from(irq)] = Some(handler);
+}
+
+/// Unmasks `irq`.
+pub fn enable(irq: u8) {
+    let (port, bit) = mask_bit(irq);
+    unsafe { outb(port, inb(port) & !bit) };
+}
+
+/// Masks `irq`.
+pub fn disable(irq: u8) {
+    let (port, bit) = mask_bit(irq);
+    unsafe { outb(port, inb(port) | bit) };
+}
+
+/// Calls the handler of `irq` and signals the end of the interrupt.
+///
+/// IRQ 7 and IRQ 15 are also what a controller raises when the line that
+/// asked for the interrupt dropped before it was acknowledged; such
+/// spurious IRQs are not in service and are ignored.
+pub(super) fn handle_irq(irq: u8) {
+    if (irq == 7 || irq == 15) && !in_service(irq) {
+        if irq == 15 {
+            unsafe { outb(PRIMARY_COMMAND, OCW2_EOI) };
+        }
+        return;
+    }
+
+    let handler = HANDLERS.lock()[usize::from(irq)];
+    if let Some(handler) = handler {
+        handler();
+    }
+
+    unsafe {
+        if irq >= 8 {
+            outb(SECONDARY_COMMAND, OCW2_EOI);
+        }
+        outb(PRIMARY_COMMAND, OCW2_EOI);
+    }
+}
+
+/// Returns the data port and the bit in it that mask `irq`.
+fn mask_bit(irq: u8) -> (u16, u8) {
+    if irq < 8 {
+        (PRIMARY_DATA, 1 << irq)
+    } else {
+        (SECONDARY_DATA, 1 << (irq - 8))
+    }
+}
+
+/// Returns `true` if `irq` is being serviced.
+fn in_service(irq: u8) -> bool {
+    let (command, bit) = if irq < 8 {
+        (PRIMARY_COMMAND, 1 << irq)
+    } else {
+        (SECONDARY_COMMAND, 1 << (irq - 8))
+    };
+    unsafe {
+        outb(command, OCW3_READ_ISR);
+        inb(command) & bit != 0
+    }
+}
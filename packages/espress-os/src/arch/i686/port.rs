@@ -0,0 +1,28 @@
+//! # i686 Port I/O
+//!
+//! The `in` and `out` instructions, through which the PC's legacy devices
+//! ([`pic`](super::pic), [`uart`](super::uart), the PIT behind the
+//! [timer](super::timer)) are programmed.
+
+use core::arch::asm;
+
+/// Reads a byte from I/O port `port`.
+///
+/// # Safety
+///
+/// Reading a device register can have side effects on the device.
+pub unsafe fn inb(port: u16) -> u8 {
+    let value: u8;
+    asm!("in al, dx", out("al") value, in("dx") port, options(nomem, nostack, preserves_flags));
+    value
+}
+
+/// Writes a byte to I/O port `port`.
+///
+/// # Safety
+///
+/// Writing a device register can break the device's state or memory
+/// safety, e.g. by reprogramming a DMA controller.
+pub unsafe fn outb(port: u16, value: u8) {
+    asm!("out dx, al", in("dx") port, in("al") value, options(nomem, nostack, preserves_flags));
+}
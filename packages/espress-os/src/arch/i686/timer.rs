@@ -0,0 +1,68 @@
+//! # i686 Timer
+//!
+//! Channel 0 of the 8254 PIT, on IRQ 0, as a periodic [`TICK_HZ`] tick.
+//! Not every machine this port targets has a TSC worth calibrating, so the
+//! tick count itself is the free-running counter of the architecture
+//! interface: it has millisecond resolution and only advances once
+//! interrupts are enabled, so the boot stages before that take no time.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use super::pic;
+use super::port::outb;
+
+/// Rate of the periodic tick in Hz.
+pub const TICK_HZ: u64 = 1000;
+
+/// Input clock of the PIT in Hz.
+const PIT_HZ: u64 = 1_193_182;
+
+/// IRQ of PIT channel 0.
+const PIT_IRQ: u8 = 0;
+
+/// Data port of channel 0.
+const CHANNEL0: u16 = 0x40;
+/// Mode/command port.
+const COMMAND: u16 = 0x43;
+
+/// Command: channel 0, low then high byte, mode 2 (rate generator).
+const COMMAND_RATE_GENERATOR: u8 = 0x34;
+
+/// Ticks handled since [`init`].
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the current counter value, the ticks since [`init`].
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Returns the counter frequency in Hz.
+pub fn frequency() -> u64 {
+    TICK_HZ
+}
+
+/// Converts a number of counter ticks to a duration.
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    Duration::from_millis(ticks * 1000 / TICK_HZ)
+}
+
+/// Starts the periodic tick.
+///
+/// Must be called after [`pic::init`]; the tick arrives once interrupts
+/// are enabled.
+pub fn init() {
+    let divisor = (PIT_HZ / TICK_HZ) as u16;
+    unsafe {
+        outb(COMMAND, COMMAND_RATE_GENERATOR);
+        outb(CHANNEL0, divisor as u8);
+        outb(CHANNEL0, (divisor >> 8) as u8);
+    }
+    pic::register(PIT_IRQ, tick);
+    pic::enable(PIT_IRQ);
+}
+
+/// Handles the PIT interrupt.
+fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
@@ -0,0 +1,157 @@
+//! # i686 Interrupt Descriptor Table
+//!
+//! An IDT of 32-bit interrupt gates for the 32 CPU exceptions and the 16
+//! [8259 IRQs](super::pic). Each vector has a small stub that pushes a
+//! zero error code where the CPU pushes none, and the vector number, and
+//! jumps to a common entry. That saves the general purpose registers with
+//! `pushad` into a [`TrapFrame`] on the current stack and calls
+//! [`handle_trap`].
+//!
+//! IRQs go to the [interrupt controllers](super::pic). An exception is a
+//! kernel bug at this stage, as nothing runs in ring 3 yet, and panics
+//! with the vector. The kernel is soft-float, so there is no FPU state to
+//! save.
+
+use core::arch::{asm, global_asm};
+use core::cell::UnsafeCell;
+use core::mem::size_of;
+
+use super::gdt::{DescriptorTablePointer, KERNEL_CODE};
+use super::pic::{IRQS, IRQ_BASE};
+
+/// Vectors with a stub: the exceptions and the IRQs.
+const VECTORS: usize = 48;
+
+/// Entries of the IDT.
+const ENTRIES: usize = 256;
+
+/// Gate type: present, ring 0, 32-bit interrupt gate.
+const INTERRUPT_GATE: u64 = 0x8e;
+
+/// Exception vector: page fault.
+const PAGE_FAULT: u32 = 14;
+
+/// Registers saved on trap entry, in the layout the stubs write.
+#[repr(C)]
+#[derive(Debug)]
+pub struct TrapFrame {
+    /// Saved `edi`
+    pub edi: u32,
+    /// Saved `esi`
+    pub esi: u32,
+    /// Saved `ebp`
+    pub ebp: u32,
+    /// `esp` as `pushad` found it, pointing to `vector`
+    pub esp: u32,
+    /// Saved `ebx`
+    pub ebx: u32,
+    /// Saved `edx`
+    pub edx: u32,
+    /// Saved `ecx`
+    pub ecx: u32,
+    /// Saved `eax`
+    pub eax: u32,
+    /// Vector number
+    pub vector: u32,
+    /// Error code pushed by the CPU, 0 for vectors without one
+    pub error_code: u32,
+    /// Address the trap returns to
+    pub eip: u32,
+    /// Code segment the trap returns to
+    pub cs: u32,
+    /// Saved flags
+    pub eflags: u32,
+}
+
+global_asm!(
+    ".section .text.trap, \"ax\"",
+    ".macro trap_stub vector",
+    "trap_stub_\\vector:",
+    ".if !(\\vector == 8 || (\\vector >= 10 && \\vector <= 14) || \\vector == 17 || \\vector == 21 || \\vector == 29 || \\vector == 30)",
+    "push 0",
+    ".endif",
+    "push \\vector",
+    "jmp trap_common",
+    ".endm",
+    "",
+    ".irp vector, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40, 41, 42, 43, 44, 45, 46, 47",
+    "trap_stub \\vector",
+    ".endr",
+    "",
+    "trap_common:",
+    "pushad",
+    "cld",
+    "mov ebp, esp",
+    "and esp, -16",
+    "sub esp, 12",
+    "push ebp",
+    "call {handler}",
+    "mov esp, ebp",
+    "popad",
+    "add esp, 8",
+    "iretd",
+    "",
+    ".pushsection .rodata",
+    ".balign 4",
+    ".global __trap_stubs",
+    "__trap_stubs:",
+    ".irp vector, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40, 41, 42, 43, 44, 45, 46, 47",
+    ".long trap_stub_\\vector",
+    ".endr",
+    ".popsection",
+    handler = sym handle_trap,
+);
+
+extern "C" {
+    static __trap_stubs: [u32; VECTORS];
+}
+
+/// The IDT.
+#[repr(C, align(8))]
+struct Idt(UnsafeCell<[u64; ENTRIES]>);
+
+// Only written by `init`, before interrupts are enabled.
+unsafe impl Sync for Idt {}
+
+static IDT: Idt = Idt(UnsafeCell::new([0; ENTRIES]));
+
+/// Fills in the IDT and loads it.
+pub fn init() {
+    unsafe {
+        let idt = &mut *IDT.0.get();
+        for (entry, &stub) in idt.iter_mut().zip(__trap_stubs.iter()) {
+            let stub = u64::from(stub);
+            *entry = (stub & 0xffff)
+                | u64::from(KERNEL_CODE) << 16
+                | INTERRUPT_GATE << 40
+                | (stub >> 16) << 48;
+        }
+        let pointer = DescriptorTablePointer {
+            limit: (ENTRIES * size_of::<u64>() - 1) as u16,
+            base: idt.as_ptr() as u32,
+        };
+        asm!("lidt [{}]", in(reg) &pointer, options(readonly, nostack, preserves_flags));
+    }
+}
+
+/// Dispatches a trap.
+extern "C" fn handle_trap(frame: &TrapFrame) {
+    let irq_base = u32::from(IRQ_BASE);
+    if (irq_base..irq_base + IRQS as u32).contains(&frame.vector) {
+        super::pic::handle_irq((frame.vector - irq_base) as u8);
+        return;
+    }
+
+    if frame.vector == PAGE_FAULT {
+        let cr2: u32;
+        unsafe { asm!("mov {}, cr2", out(reg) cr2, options(nomem, nostack, preserves_flags)) };
+        panic!(
+            "page fault at {:#x}: address {:#x}, error code {:#x}",
+            frame.eip, cr2, frame.error_code
+        );
+    }
+    panic!(
+        "unexpected exception {} at {:#x}: error code {:#x}",
+        frame.vector, frame.eip, frame.error_code
+    );
+}
@@ -0,0 +1,97 @@
+//! # 16550 UART
+//!
+//! Driver for the 16550-compatible serial ports of the PC, reached through
+//! port I/O. It runs without interrupts: output waits for the transmitter
+//! to be empty and input is polled with [`Uart::try_receive`].
+
+use super::port::{inb, outb};
+
+/// I/O port base of the first serial port.
+pub const COM1_BASE: u16 = 0x3f8;
+
+/// The first serial port.
+pub static COM1: Uart = Uart::new(COM1_BASE);
+
+/// Data register, or the low byte of the divisor with `LCR_DLAB` set
+const DATA: u16 = 0;
+/// Interrupt enable register, or the high byte of the divisor
+const IER: u16 = 1;
+/// FIFO control register
+const FCR: u16 = 2;
+/// Line control register
+const LCR: u16 = 3;
+/// Modem control register
+const MCR: u16 = 4;
+/// Line status register
+const LSR: u16 = 5;
+
+/// LCR: 8 data bits, no parity, one stop bit.
+const LCR_8N1: u8 = 0x03;
+/// LCR: the first two registers hold the baud rate divisor.
+const LCR_DLAB: u8 = 0x80;
+/// FCR: enable and clear the FIFOs, interrupt at 14 bytes.
+const FCR_ENABLE: u8 = 0xc7;
+/// MCR: data terminal ready, request to send, and OUT2.
+const MCR_READY: u8 = 0x0b;
+/// LSR: a received byte is waiting.
+const LSR_DATA_READY: u8 = 1 << 0;
+/// LSR: the transmit holding register is empty.
+const LSR_THR_EMPTY: u8 = 1 << 5;
+
+/// Divisor of the 115200 Hz base clock, for 115200 baud.
+const DIVISOR: u16 = 1;
+
+/// A 16550 UART.
+pub struct Uart {
+    /// First I/O port of the registers
+    base: u16,
+}
+
+impl Uart {
+    /// Creates a driver for the UART with registers at port `base`.
+    pub const fn new(base: u16) -> Self {
+        Uart { base }
+    }
+
+    /// Sets the UART to 115200 baud, 8 data bits, no parity, one stop bit,
+    /// with FIFOs and all interrupts disabled.
+    pub fn init(&self) {
+        unsafe {
+            outb(self.base + IER, 0);
+            outb(self.base + LCR, LCR_DLAB);
+            outb(self.base + DATA, DIVISOR as u8);
+            outb(self.base + IER, (DIVISOR >> 8) as u8);
+            outb(self.base + LCR, LCR_8N1);
+            outb(self.base + FCR, FCR_ENABLE);
+            outb(self.base + MCR, MCR_READY);
+        }
+    }
+
+    /// Sends one byte, waiting for the transmitter to be empty.
+    pub fn send(&self, byte: u8) {
+        while unsafe { inb(self.base + LSR) } & LSR_THR_EMPTY == 0 {
+            core::hint::spin_loop();
+        }
+        unsafe { outb(self.base + DATA, byte) };
+    }
+
+    /// Returns the next received byte, or `None` if none is waiting.
+    pub fn try_receive(&self) -> Option<u8> {
+        if unsafe { inb(self.base + LSR) } & LSR_DATA_READY == 0 {
+            return None;
+        }
+        Some(unsafe { inb(self.base + DATA) })
+    }
+}
+
+impl core::fmt::Write for &Uart {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for byte in s.bytes() {
+            if byte == b'\n' {
+                self.send(b'\r');
+            }
+            self.send(byte);
+        }
+        Ok(())
+    }
+}
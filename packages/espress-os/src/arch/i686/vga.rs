@@ -0,0 +1,107 @@
+//! # i686 VGA Text Screen
+//!
+//! A minimal writer for the 80x25 VGA text mode the loader leaves set up:
+//! text runs from the top of the screen and scrolls up once it reaches the
+//! bottom. The buffer at `0xb8000` is identity-mapped (see
+//! [`mmu`](super::mmu)); the hardware cursor is left alone.
+
+use core::ptr;
+use spin::Mutex;
+
+use crate::console::Color;
+
+/// Characters in a row.
+pub const WIDTH: usize = 80;
+/// Rows on the screen.
+pub const HEIGHT: usize = 25;
+
+/// Physical address of the text buffer.
+const BUFFER: usize = 0xb8000;
+
+/// Character shown for bytes outside printable ASCII.
+const REPLACEMENT: u8 = 0xfe;
+
+/// The screen, shared by every writer.
+pub static SCREEN: Mutex<TextScreen> = Mutex::new(TextScreen::new());
+
+/// Position of the next character on the screen.
+pub struct TextScreen {
+    /// Row being written
+    row: usize,
+    /// Column of the next character in the row
+    column: usize,
+}
+
+impl TextScreen {
+    /// Creates a writer starting at the top left.
+    const fn new() -> Self {
+        TextScreen { row: 0, column: 0 }
+    }
+
+    /// Blanks the screen and moves to the top left.
+    pub fn clear(&mut self) {
+        for row in 0..HEIGHT {
+            clear_row(row);
+        }
+        self.row = 0;
+        self.column = 0;
+    }
+
+    /// Writes `s` in `foreground` on black.
+    pub fn write_string(&mut self, s: &str, foreground: Color) {
+        let attribute = foreground as u8;
+        for byte in s.bytes() {
+            match byte {
+                b'\n' => self.new_line(),
+                0x20..=0x7e => self.write_byte(byte, attribute),
+                _ => self.write_byte(REPLACEMENT, attribute),
+            }
+        }
+    }
+
+    /// Writes one character cell, wrapping to the next line at the edge.
+    fn write_byte(&mut self, byte: u8, attribute: u8) {
+        if self.column >= WIDTH {
+            self.new_line();
+        }
+        write_cell(
+            self.row,
+            self.column,
+            u16::from(attribute) << 8 | u16::from(byte),
+        );
+        self.column += 1;
+    }
+
+    /// Moves to the start of the next line, scrolling at the bottom.
+    fn new_line(&mut self) {
+        self.column = 0;
+        if self.row + 1 < HEIGHT {
+            self.row += 1;
+            return;
+        }
+        for row in 1..HEIGHT {
+            for column in 0..WIDTH {
+                write_cell(row - 1, column, read_cell(row, column));
+            }
+        }
+        clear_row(HEIGHT - 1);
+    }
+}
+
+/// Blanks `row`.
+fn clear_row(row: usize) {
+    let blank = u16::from(Color::LightGray as u8) << 8 | u16::from(b' ');
+    for column in 0..WIDTH {
+        write_cell(row, column, blank);
+    }
+}
+
+/// Reads the character cell at `row` and `column`.
+fn read_cell(row: usize, column: usize) -> u16 {
+    unsafe { ptr::read_volatile((BUFFER as *const u16).add(row * WIDTH + column)) }
+}
+
+/// Writes the character cell at `row` and `column`.
+fn write_cell(row: usize, column: usize, cell: u16) {
+    unsafe { ptr::write_volatile((BUFFER as *mut u16).add(row * WIDTH + column), cell) }
+}
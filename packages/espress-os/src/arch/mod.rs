@@ -0,0 +1,47 @@
+//! # Architecture Support
+//!
+//! Everything that differs between the CPU architectures the kernel runs on
+//! lives in one submodule per architecture, and the one for the target is
+//! re-exported here, so portable code says `arch::interrupts::disable()`
+//! whatever the target. Every architecture provides the same interface:
+//!
+//! - `NAME`, the name of the architecture as shown at boot;
+//! - `console::init`, registering the architecture's console backends;
+//! - `interrupts`: `enable`, `disable`, `are_enabled`,
+//!   `without_interrupts`, and `wait` for the next interrupt;
+//! - `timer`: the free-running counter through `ticks`, `frequency`, and
+//!   `ticks_to_duration`;
+//! - `mmu`: `PAGE_SIZE`, the translation table `root` and `set_root`, and
+//!   `flush_page` and `flush_all` for the TLB.
+//!
+//! The console backends are the VGA text screen and COM1 on x86_64 and
+//! i686, the PL011 UART on aarch64, and the SBI firmware console on
+//! riscv64. Each architecture module has further modules of its own that
+//! only code for that architecture uses.
+//!
+//! Only x86_64 runs the whole kernel; memory management, processes, the
+//! drivers, and the subsystems built on them are still x86_64-only and left
+//! out of other targets. The aarch64 and riscv64 ports boot on the QEMU
+//! `virt` machine, and the i686 port on any PC with a multiboot loader, to
+//! the console with the MMU, traps, and the timer running, and allocate
+//! from a fixed [`heap`] arena.
+
+#[cfg(target_arch = "aarch64")]
+mod aarch64;
+#[cfg(not(target_arch = "x86_64"))]
+pub mod heap;
+#[cfg(target_arch = "x86")]
+mod i686;
+#[cfg(target_arch = "riscv64")]
+mod riscv64;
+#[cfg(target_arch = "x86_64")]
+mod x86;
+
+#[cfg(target_arch = "aarch64")]
+pub use aarch64::*;
+#[cfg(target_arch = "x86")]
+pub use i686::*;
+#[cfg(target_arch = "riscv64")]
+pub use riscv64::*;
+#[cfg(target_arch = "x86_64")]
+pub use x86::*;
@@ -0,0 +1,106 @@
+//! # riscv64 Entry Point
+//!
+//! OpenSBI starts the kernel at its link address in RAM (see
+//! `linker-riscv64.ld`) in supervisor mode with translation off, on one
+//! hart, with the hart ID in `a0` and the physical address of the flattened
+//! device tree in `a1`. [`start`] sets up the boot stack, enables the FPU
+//! (compiled Rust code uses the floating point registers), clears `.bss`,
+//! records the hart ID, and calls `kernel_main`, which the kernel binary
+//! defines, with the device tree address.
+
+use core::arch::naked_asm;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+extern "C" {
+    static __bss_start: u8;
+    static __bss_end: u8;
+    static __boot_stack_top: u8;
+}
+
+/// Start of RAM on QEMU `virt`.
+pub const RAM_BASE: u64 = 0x8000_0000;
+
+/// End of the address range RAM can occupy, the top of the lower half of
+/// Sv39.
+pub const RAM_END: u64 = 0x40_0000_0000;
+
+/// `sstatus.FS` set to Initial.
+const SSTATUS_FS_INITIAL: u64 = 1 << 13;
+
+/// First word of a flattened device tree, big-endian.
+const FDT_MAGIC: u32 = 0xd00d_feed;
+
+/// ID of the hart the kernel was started on.
+static BOOT_HART: AtomicU64 = AtomicU64::new(0);
+
+/// Physical address of the device tree, 0 if none was found.
+static DEVICETREE: AtomicU64 = AtomicU64::new(0);
+
+/// Kernel entry point.
+///
+/// # Safety
+///
+/// Only to be jumped to by the SBI firmware.
+#[unsafe(naked)]
+#[export_name = "_start"]
+#[link_section = ".text.boot"]
+pub unsafe extern "C" fn start() -> ! {
+    naked_asm!(
+        "la sp, {stack_top}",
+        "li t0, {fs_initial}",
+        "csrs sstatus, t0",
+        "la t0, {bss_start}",
+        "la t1, {bss_end}",
+        "2:",
+        "bgeu t0, t1, 3f",
+        "sd zero, 0(t0)",
+        "addi t0, t0, 8",
+        "j 2b",
+        "3:",
+        "la t0, {boot_hart}",
+        "sd a0, 0(t0)",
+        "mv a0, a1",
+        "call kernel_main",
+        "4:",
+        "wfi",
+        "j 4b",
+        stack_top = sym __boot_stack_top,
+        fs_initial = const SSTATUS_FS_INITIAL,
+        bss_start = sym __bss_start,
+        bss_end = sym __bss_end,
+        boot_hart = sym BOOT_HART,
+    )
+}
+
+/// Returns the ID of the hart the kernel was started on.
+pub fn boot_hart() -> u64 {
+    BOOT_HART.load(Ordering::Relaxed)
+}
+
+/// Records where the device tree is.
+///
+/// Takes `a1` as passed to [`start`] and ignores it unless it points to a
+/// device tree in RAM.
+pub fn record_devicetree(a1: usize) {
+    let address = a1 as u64;
+    if (RAM_BASE..RAM_END).contains(&address) && is_devicetree(address) {
+        DEVICETREE.store(address, Ordering::Relaxed);
+    }
+}
+
+/// Returns the physical address of the flattened device tree, if any.
+pub fn devicetree() -> Option<u64> {
+    match DEVICETREE.load(Ordering::Relaxed) {
+        0 => None,
+        address => Some(address),
+    }
+}
+
+/// Returns `true` if a device tree header is at `address`.
+///
+/// RAM is identity-mapped (see [`mmu`](super::mmu)), so it can be read in
+/// place.
+fn is_devicetree(address: u64) -> bool {
+    address.is_multiple_of(4)
+        && unsafe { core::ptr::read_volatile(address as *const u32) } == FDT_MAGIC.to_be()
+}
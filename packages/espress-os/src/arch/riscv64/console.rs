@@ -0,0 +1,75 @@
+//! # riscv64 Console Backend
+//!
+//! The SBI firmware's console as the
+//! [console backend](crate::console::ConsoleBackend): the debug console
+//! extension where the firmware has it, the legacy one-byte calls
+//! otherwise.
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use super::sbi;
+use crate::console::{self, ansi_color, Color, ConsoleBackend};
+
+/// The firmware has the debug console extension.
+static DEBUG_CONSOLE: AtomicBool = AtomicBool::new(false);
+
+/// Probes the firmware's console and registers it as the console backend.
+pub fn init() {
+    DEBUG_CONSOLE.store(sbi::has_debug_console(), Ordering::Relaxed);
+    console::register(&SbiConsole);
+}
+
+/// Console backend for the SBI console.
+struct SbiConsole;
+
+impl SbiConsole {
+    /// Writes all of `bytes`.
+    fn write_bytes(&self, mut bytes: &[u8]) {
+        if !DEBUG_CONSOLE.load(Ordering::Relaxed) {
+            bytes.iter().copied().for_each(sbi::legacy_putchar);
+            return;
+        }
+        while !bytes.is_empty() {
+            match sbi::console_write(bytes) {
+                Ok(written) => bytes = &bytes[written.min(bytes.len())..],
+                Err(_) => return,
+            }
+        }
+    }
+}
+
+impl ConsoleBackend for SbiConsole {
+    fn name(&self) -> &'static str {
+        "sbi"
+    }
+
+    fn write_str(&self, s: &str) {
+        self.write_bytes(s.as_bytes());
+    }
+
+    fn write_colored(&self, s: &str, color: Color) {
+        let _ = write!(Writer(self), "\x1b[{}m{}\x1b[0m", ansi_color(color), s);
+    }
+
+    fn try_read(&self) -> Option<u8> {
+        if !DEBUG_CONSOLE.load(Ordering::Relaxed) {
+            return sbi::legacy_getchar();
+        }
+        let mut byte = [0];
+        match sbi::console_read(&mut byte) {
+            Ok(1) => Some(byte[0]),
+            _ => None,
+        }
+    }
+}
+
+/// Adapts [`SbiConsole`] to `core::fmt::Write`.
+struct Writer<'a>(&'a SbiConsole);
+
+impl fmt::Write for Writer<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+//! # riscv64 Interrupt Masking
+//!
+//! Masking and waiting for interrupts through `sstatus.SIE` and `wfi`. The
+//! sources are enabled individually in `sie`, e.g. by the
+//! [timer](super::timer), and taken through the [trap vector](super::trap).
+
+use core::arch::asm;
+
+/// `sstatus.SIE`: supervisor interrupts are enabled.
+const SSTATUS_SIE: u64 = 1 << 1;
+
+/// Enables supervisor interrupts.
+pub fn enable() {
+    unsafe { asm!("csrsi sstatus, {}", const SSTATUS_SIE, options(nomem, nostack)) };
+}
+
+/// Disables supervisor interrupts.
+pub fn disable() {
+    unsafe { asm!("csrci sstatus, {}", const SSTATUS_SIE, options(nomem, nostack)) };
+}
+
+/// Returns `true` if supervisor interrupts are enabled.
+pub fn are_enabled() -> bool {
+    let sstatus: u64;
+    unsafe { asm!("csrr {}, sstatus", out(reg) sstatus, options(nomem, nostack)) };
+    sstatus & SSTATUS_SIE != 0
+}
+
+/// Runs `f` with interrupts disabled, restoring the previous state
+/// afterwards.
+pub fn without_interrupts<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let enabled = are_enabled();
+    if enabled {
+        disable();
+    }
+    let result = f();
+    if enabled {
+        enable();
+    }
+    result
+}
+
+/// Sleeps until the next interrupt.
+pub fn wait() {
+    unsafe { asm!("wfi", options(nomem, nostack)) };
+}
@@ -0,0 +1,110 @@
+//! # riscv64 MMU
+//!
+//! [`init`] turns on Sv39 translation with an identity mapping of the lower
+//! half of the address space (256 GiB) in 1 GiB gigapages:
+//!
+//! | Range            | Permissions            | Contents on QEMU `virt`         |
+//! |------------------|------------------------|---------------------------------|
+//! | 0 - 2 GiB        | read, write            | CLINT, PLIC, UART, virtio, PCIe |
+//! | 2 GiB - 256 GiB  | read, write, execute   | RAM                             |
+//!
+//! Sv39 has no memory types, so the split only keeps devices from being
+//! executed. All mappings are global, as there is one address space.
+
+use core::arch::asm;
+use core::cell::UnsafeCell;
+
+use super::boot::{RAM_BASE, RAM_END};
+
+/// Size of the smallest page in bytes.
+pub const PAGE_SIZE: u64 = 4096;
+
+/// Size of a gigapage in bytes.
+const GIGAPAGE_SIZE: u64 = 1 << 30;
+
+/// Entries of a page table.
+const ENTRIES: usize = 512;
+
+/// PTE: valid.
+const PTE_V: u64 = 1 << 0;
+/// PTE: readable.
+const PTE_R: u64 = 1 << 1;
+/// PTE: writable.
+const PTE_W: u64 = 1 << 2;
+/// PTE: executable.
+const PTE_X: u64 = 1 << 3;
+/// PTE: global.
+const PTE_G: u64 = 1 << 5;
+/// PTE: accessed, set so hardware need not.
+const PTE_A: u64 = 1 << 6;
+/// PTE: dirty, set so hardware need not.
+const PTE_D: u64 = 1 << 7;
+
+/// `satp.MODE` for Sv39.
+const SATP_SV39: u64 = 8 << 60;
+/// `satp.PPN`: the physical page number of the root table.
+const SATP_PPN: u64 = (1 << 44) - 1;
+
+/// The root table, aligned to a page as `satp` requires.
+#[repr(C, align(4096))]
+struct Table(UnsafeCell<[u64; ENTRIES]>);
+
+// Only written by `init`, before translation uses it and before other code
+// runs.
+unsafe impl Sync for Table {}
+
+static IDENTITY: Table = Table(UnsafeCell::new([0; ENTRIES]));
+
+/// Builds the identity mapping and turns on translation.
+///
+/// # Safety
+///
+/// Must be called once, first thing after the entry point, with
+/// translation off.
+pub unsafe fn init() {
+    let table = IDENTITY.0.get();
+    let lower_half = RAM_END / GIGAPAGE_SIZE;
+    for (index, entry) in (*table).iter_mut().enumerate().take(lower_half as usize) {
+        let address = index as u64 * GIGAPAGE_SIZE;
+        let permissions = if address >= RAM_BASE {
+            PTE_R | PTE_W | PTE_X
+        } else {
+            PTE_R | PTE_W
+        };
+        *entry = (address >> 12) << 10 | permissions | PTE_V | PTE_G | PTE_A | PTE_D;
+    }
+    set_root(table as u64);
+}
+
+/// Returns the physical address of the active root page table.
+pub fn root() -> u64 {
+    let satp: u64;
+    unsafe { asm!("csrr {}, satp", out(reg) satp, options(nomem, nostack)) };
+    (satp & SATP_PPN) << 12
+}
+
+/// Switches to the Sv39 page tables rooted at physical address `root`.
+///
+/// # Safety
+///
+/// `root` must be a root page table that maps the running kernel at the
+/// same addresses as the current one.
+pub unsafe fn set_root(root: u64) {
+    asm!(
+        "sfence.vma",
+        "csrw satp, {}",
+        "sfence.vma",
+        in(reg) SATP_SV39 | root >> 12,
+        options(nostack)
+    );
+}
+
+/// Removes the translation of the page containing `address` from the TLB.
+pub fn flush_page(address: u64) {
+    unsafe { asm!("sfence.vma {}, zero", in(reg) address, options(nostack)) };
+}
+
+/// Removes all translations from the TLB.
+pub fn flush_all() {
+    unsafe { asm!("sfence.vma", options(nostack)) };
+}
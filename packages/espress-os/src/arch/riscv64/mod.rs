@@ -0,0 +1,23 @@
+//! # riscv64
+//!
+//! Support for 64-bit RISC-V in supervisor mode, below OpenSBI on the QEMU
+//! `virt` machine. Besides the common [architecture interface](super)
+//! ([`console`], [`interrupts`], [`mmu`], and [`timer`]), these are the
+//! entry point ([`boot`]), the calls into the SBI firmware ([`sbi`]), and
+//! the trap vector ([`trap`]). The kernel allocates from the shared
+//! [arena heap](super::heap).
+//!
+//! The console and the timer go through the SBI, so the only device the
+//! kernel touches itself is memory; the device tree the firmware passes is
+//! recorded but not parsed yet.
+
+pub mod boot;
+pub mod console;
+pub mod interrupts;
+pub mod mmu;
+pub mod sbi;
+pub mod timer;
+pub mod trap;
+
+/// Name of the architecture, as shown at boot.
+pub const NAME: &str = "riscv64";
@@ -0,0 +1,169 @@
+//! # Supervisor Binary Interface
+//!
+//! Calls into the SBI firmware (OpenSBI) below the kernel through `ecall`.
+//! The kernel uses the base extension to find out what the firmware
+//! supports, the debug console extension (or the legacy console calls of
+//! older firmware) for the [console](super::console), and the timer
+//! extension for the [timer](super::timer).
+
+use core::arch::asm;
+
+/// Base extension
+const EID_BASE: usize = 0x10;
+/// Timer extension ("TIME")
+const EID_TIME: usize = 0x5449_4d45;
+/// Debug console extension ("DBCN")
+const EID_DBCN: usize = 0x4442_434e;
+/// Legacy `sbi_set_timer`
+const EID_LEGACY_SET_TIMER: usize = 0x00;
+/// Legacy `sbi_console_putchar`
+const EID_LEGACY_PUTCHAR: usize = 0x01;
+/// Legacy `sbi_console_getchar`
+const EID_LEGACY_GETCHAR: usize = 0x02;
+
+/// Base extension: `sbi_get_spec_version`
+const FID_SPEC_VERSION: usize = 0;
+/// Base extension: `sbi_probe_extension`
+const FID_PROBE_EXTENSION: usize = 3;
+/// Timer extension: `sbi_set_timer`
+const FID_SET_TIMER: usize = 0;
+/// Debug console extension: `sbi_debug_console_write`
+const FID_CONSOLE_WRITE: usize = 0;
+/// Debug console extension: `sbi_debug_console_read`
+const FID_CONSOLE_READ: usize = 1;
+
+/// An error returned by the SBI firmware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SbiError(pub isize);
+
+/// Makes the SBI call `fid` of extension `eid`.
+///
+/// # Returns
+///
+/// The value the call returned in `a1`.
+///
+/// # Errors
+///
+/// The error code the call returned in `a0`, if not zero.
+fn call(eid: usize, fid: usize, arg0: usize, arg1: usize, arg2: usize) -> Result<usize, SbiError> {
+    let error: isize;
+    let value: usize;
+    unsafe {
+        asm!(
+            "ecall",
+            inlateout("a0") arg0 => error,
+            inlateout("a1") arg1 => value,
+            in("a2") arg2,
+            in("a6") fid,
+            in("a7") eid,
+            options(nostack)
+        );
+    }
+    match error {
+        0 => Ok(value),
+        error => Err(SbiError(error)),
+    }
+}
+
+/// Makes the legacy SBI call `eid`, which returns its result in `a0`.
+fn legacy_call(eid: usize, arg0: usize) -> isize {
+    let result: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            inlateout("a0") arg0 => result,
+            in("a7") eid,
+            options(nostack)
+        );
+    }
+    result
+}
+
+/// Returns the SBI specification version as (major, minor).
+pub fn spec_version() -> (usize, usize) {
+    let version = call(EID_BASE, FID_SPEC_VERSION, 0, 0, 0).unwrap_or(0);
+    ((version >> 24) & 0x7f, version & 0xff_ffff)
+}
+
+/// Returns `true` if the firmware implements extension `eid`.
+pub fn has_extension(eid: usize) -> bool {
+    call(EID_BASE, FID_PROBE_EXTENSION, eid, 0, 0).is_ok_and(|value| value != 0)
+}
+
+/// Returns `true` if the firmware has the debug console extension.
+pub fn has_debug_console() -> bool {
+    has_extension(EID_DBCN)
+}
+
+/// Returns `true` if the firmware has the timer extension.
+pub fn has_timer() -> bool {
+    has_extension(EID_TIME)
+}
+
+/// Writes `bytes` to the debug console.
+///
+/// `bytes` must be identity-mapped, as the firmware reads them by physical
+/// address.
+///
+/// # Returns
+///
+/// The number of bytes written, which may be fewer than given.
+///
+/// # Errors
+///
+/// The firmware's error if nothing could be written.
+pub fn console_write(bytes: &[u8]) -> Result<usize, SbiError> {
+    call(
+        EID_DBCN,
+        FID_CONSOLE_WRITE,
+        bytes.len(),
+        bytes.as_ptr() as usize,
+        0,
+    )
+}
+
+/// Reads up to `buffer.len()` waiting bytes from the debug console.
+///
+/// # Returns
+///
+/// The number of bytes read, 0 if none were waiting.
+///
+/// # Errors
+///
+/// The firmware's error if the console cannot be read.
+pub fn console_read(buffer: &mut [u8]) -> Result<usize, SbiError> {
+    call(
+        EID_DBCN,
+        FID_CONSOLE_READ,
+        buffer.len(),
+        buffer.as_mut_ptr() as usize,
+        0,
+    )
+}
+
+/// Writes one byte through the legacy console call.
+pub fn legacy_putchar(byte: u8) {
+    legacy_call(EID_LEGACY_PUTCHAR, usize::from(byte));
+}
+
+/// Reads one byte through the legacy console call, `None` if none is
+/// waiting.
+pub fn legacy_getchar() -> Option<u8> {
+    u8::try_from(legacy_call(EID_LEGACY_GETCHAR, 0)).ok()
+}
+
+/// Schedules the next supervisor timer interrupt for when `time` reaches
+/// `deadline`, clearing any pending one.
+///
+/// # Errors
+///
+/// The firmware's error, e.g. if it has no timer extension.
+pub fn set_timer(deadline: u64) -> Result<(), SbiError> {
+    call(EID_TIME, FID_SET_TIMER, deadline as usize, 0, 0).map(|_| ())
+}
+
+/// Schedules the next supervisor timer interrupt like [`set_timer`]
+/// through the legacy call.
+pub fn legacy_set_timer(deadline: u64) {
+    legacy_call(EID_LEGACY_SET_TIMER, deadline as usize);
+}
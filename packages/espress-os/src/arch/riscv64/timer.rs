@@ -0,0 +1,77 @@
+//! # riscv64 Timer
+//!
+//! The `time` CSR is the free-running counter of the architecture
+//! interface. It counts at the platform's timebase frequency, which the
+//! device tree gives; until that is parsed, the 10 MHz of QEMU `virt` is
+//! assumed.
+//!
+//! [`init`] also starts a periodic [`TICK_HZ`] tick: each supervisor timer
+//! interrupt, counted by [`interrupts`], schedules the next one through the
+//! [SBI](super::sbi).
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
+
+use super::sbi;
+
+/// Rate of the periodic tick in Hz.
+pub const TICK_HZ: u64 = 100;
+
+/// Frequency of the `time` CSR on QEMU `virt`.
+pub const TIMEBASE_HZ: u64 = 10_000_000;
+
+/// `sie.STIE`: supervisor timer interrupts are enabled.
+const SIE_STIE: u64 = 1 << 5;
+
+/// Ticks handled since [`init`].
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// The firmware has the SBI timer extension.
+static TIMER_EXTENSION: AtomicBool = AtomicBool::new(false);
+
+/// Returns the current counter value.
+pub fn ticks() -> u64 {
+    let time: u64;
+    unsafe { asm!("rdtime {}", out(reg) time, options(nomem, nostack)) };
+    time
+}
+
+/// Returns the counter frequency in Hz.
+pub fn frequency() -> u64 {
+    TIMEBASE_HZ
+}
+
+/// Converts a number of counter ticks to a duration.
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    Duration::from_nanos((u128::from(ticks) * 1_000_000_000 / u128::from(TIMEBASE_HZ)) as u64)
+}
+
+/// Returns the number of periodic ticks since [`init`].
+pub fn interrupts() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Starts the periodic tick.
+///
+/// Must be called after [`trap::init`](super::trap::init); the tick
+/// arrives once interrupts are enabled.
+pub fn init() {
+    TIMER_EXTENSION.store(sbi::has_timer(), Ordering::Relaxed);
+    rearm();
+    unsafe { asm!("csrs sie, {}", in(reg) SIE_STIE, options(nomem, nostack)) };
+}
+
+/// Handles the supervisor timer interrupt.
+pub(super) fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+    rearm();
+}
+
+/// Schedules the next tick one period from now.
+fn rearm() {
+    let deadline = ticks() + TIMEBASE_HZ / TICK_HZ;
+    if !TIMER_EXTENSION.load(Ordering::Relaxed) || sbi::set_timer(deadline).is_err() {
+        sbi::legacy_set_timer(deadline);
+    }
+}
@@ -0,0 +1,124 @@
+//! # riscv64 Trap Vector
+//!
+//! The single supervisor trap entry that `stvec` points to in direct mode.
+//! It saves the integer registers, the floating point registers and
+//! `fcsr`, and `sepc` and `sstatus` into a [`TrapFrame`] on the current
+//! stack and calls [`handle_trap`].
+//!
+//! Supervisor timer interrupts go to the [timer](super::timer). Anything
+//! else is a kernel bug at this stage, as nothing runs in user mode yet,
+//! and panics with the cause.
+
+use core::arch::{asm, global_asm};
+
+/// Size of a [`TrapFrame`] in bytes.
+const FRAME_SIZE: usize = 544;
+
+/// `scause`: the trap is an interrupt.
+const CAUSE_INTERRUPT: u64 = 1 << 63;
+
+/// Interrupt cause: supervisor timer.
+const INTERRUPT_SUPERVISOR_TIMER: u64 = 5;
+
+/// Registers saved on trap entry, in the layout the vector writes.
+#[repr(C)]
+#[derive(Debug)]
+pub struct TrapFrame {
+    /// `x0` to `x31`; the slot of `x0` is unused and `x2` is the stack
+    /// pointer before the trap
+    pub x: [u64; 32],
+    /// `f0` to `f31`
+    pub f: [u64; 32],
+    /// Address the trap returns to
+    pub sepc: u64,
+    /// Saved status
+    pub sstatus: u64,
+    /// Floating point control and status
+    pub fcsr: u64,
+    /// Padding to keep the stack 16-byte aligned
+    _reserved: u64,
+}
+
+const _: () = assert!(core::mem::size_of::<TrapFrame>() == FRAME_SIZE);
+
+global_asm!(
+    ".section .text.trap, \"ax\"",
+    ".balign 4",
+    ".global __trap_vector",
+    "__trap_vector:",
+    "addi sp, sp, -{frame_size}",
+    ".irp reg, 1, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31",
+    "sd x\\reg, (\\reg * 8)(sp)",
+    ".endr",
+    "addi t0, sp, {frame_size}",
+    "sd t0, 16(sp)",
+    ".irp reg, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31",
+    "fsd f\\reg, (256 + \\reg * 8)(sp)",
+    ".endr",
+    "csrr t0, sepc",
+    "sd t0, 512(sp)",
+    "csrr t0, sstatus",
+    "sd t0, 520(sp)",
+    "frcsr t0",
+    "sd t0, 528(sp)",
+    "mv a0, sp",
+    "call {handler}",
+    "ld t0, 528(sp)",
+    "fscsr t0",
+    "ld t0, 520(sp)",
+    "csrw sstatus, t0",
+    "ld t0, 512(sp)",
+    "csrw sepc, t0",
+    ".irp reg, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31",
+    "fld f\\reg, (256 + \\reg * 8)(sp)",
+    ".endr",
+    ".irp reg, 1, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31",
+    "ld x\\reg, (\\reg * 8)(sp)",
+    ".endr",
+    "addi sp, sp, {frame_size}",
+    "sret",
+    frame_size = const FRAME_SIZE,
+    handler = sym handle_trap,
+);
+
+extern "C" {
+    static __trap_vector: u8;
+}
+
+/// Points `stvec` at the trap vector, in direct mode.
+pub fn init() {
+    unsafe {
+        asm!(
+            "csrw stvec, {}",
+            in(reg) core::ptr::addr_of!(__trap_vector),
+            options(nostack)
+        );
+    }
+}
+
+/// Dispatches a trap.
+extern "C" fn handle_trap(frame: &TrapFrame) {
+    let (scause, stval): (u64, u64);
+    unsafe {
+        asm!("csrr {}, scause", out(reg) scause, options(nomem, nostack));
+        asm!("csrr {}, stval", out(reg) stval, options(nomem, nostack));
+    }
+
+    if scause == CAUSE_INTERRUPT | INTERRUPT_SUPERVISOR_TIMER {
+        super::timer::tick();
+        return;
+    }
+
+    let kind = if scause & CAUSE_INTERRUPT != 0 {
+        "interrupt"
+    } else {
+        "exception"
+    };
+    panic!(
+        "unexpected {} {} at {:#x}: stval {:#x}",
+        kind,
+        scause & !CAUSE_INTERRUPT,
+        frame.sepc,
+        stval
+    );
+}
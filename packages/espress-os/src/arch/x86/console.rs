@@ -0,0 +1,73 @@
+//! # x86_64 Console Backends
+//!
+//! The VGA text screen and the COM1 serial port as
+//! [console backends](crate::console::ConsoleBackend).
+
+use core::fmt::Write;
+
+use crate::console::{self, ansi_color, Color, ConsoleBackend};
+
+/// Registers the VGA text screen and COM1 as the console backends.
+///
+/// The VGA screen is left out when the boot protocol set up no text mode
+/// (see [`boot::has_text_mode`](crate::boot::has_text_mode)); the
+/// [framebuffer console](crate::fbcon) is registered later instead. The VGA
+/// writer is reached through the physical memory mapping, so this must run
+/// after memory management is initialized.
+pub fn init() {
+    if crate::boot::has_text_mode() {
+        console::register(&VgaConsole);
+    }
+    console::register(&SerialConsole);
+}
+
+/// Console backend for the VGA text screen.
+struct VgaConsole;
+
+impl ConsoleBackend for VgaConsole {
+    fn name(&self) -> &'static str {
+        "vga"
+    }
+
+    fn write_str(&self, s: &str) {
+        crate::WRITER.lock().write_string(s);
+    }
+
+    fn write_colored(&self, s: &str, color: Color) {
+        crate::WRITER.lock().write_colored(s, color);
+    }
+
+    unsafe fn force_unlock(&self) {
+        crate::WRITER.force_unlock();
+    }
+}
+
+/// Console backend for the COM1 serial port.
+struct SerialConsole;
+
+impl ConsoleBackend for SerialConsole {
+    fn name(&self) -> &'static str {
+        "serial0"
+    }
+
+    fn write_str(&self, s: &str) {
+        let _ = crate::serial::SERIAL1.lock().write_str(s);
+    }
+
+    fn write_colored(&self, s: &str, color: Color) {
+        let _ = write!(
+            crate::serial::SERIAL1.lock(),
+            "\x1b[{}m{}\x1b[0m",
+            ansi_color(color),
+            s
+        );
+    }
+
+    fn try_read(&self) -> Option<u8> {
+        crate::serial::try_receive()
+    }
+
+    unsafe fn force_unlock(&self) {
+        crate::serial::SERIAL1.force_unlock();
+    }
+}
@@ -0,0 +1,189 @@
+//! # Execution Contexts
+//!
+//! A [`Context`] is everything needed to resume a suspended thread of
+//! execution: its stack pointer (the callee-saved registers live on the
+//! stack below it), its `FS` base (the thread pointer, see
+//! [`tls`](super::tls)), and, optionally, its FPU state. [`switch_to`] suspends
+//! the caller into one context and resumes another.
+//!
+//! ## Initial Frame
+//!
+//! A new context starts with a stack prepared by [`initial_frame`], laid
+//! out exactly like the frame [`switch_to`] leaves behind:
+//!
+//! ```text
+//! top -  8 | return address: context_entry
+//! top - 16 | rbp = 0
+//! top - 24 | rbx = 0
+//! top - 32 | r12 = entry
+//! top - 40 | r13 = argument
+//! top - 48 | r14 = 0
+//! top - 56 | r15 = 0                     <- saved stack pointer
+//! ```
+//!
+//! The first switch pops the registers and "returns" into
+//! `context_entry`, which calls `entry(argument)` on a 16-byte aligned
+//! stack.
+
+use alloc::boxed::Box;
+use core::arch::{global_asm, naked_asm};
+
+use super::fpu::FpuState;
+use super::tls;
+
+/// Number of 64-bit words in an initial frame.
+pub const INITIAL_FRAME_WORDS: usize = 7;
+
+/// Function a new context starts in; it receives the context's argument and
+/// must never return.
+pub type ContextEntry = extern "C" fn(usize) -> !;
+
+/// Saved execution state of a suspended thread.
+pub struct Context {
+    /// Stack pointer at the time of the switch
+    rsp: u64,
+    /// `FS` base at the time of the switch
+    fs_base: u64,
+    /// FPU/SSE state, saved and restored only if present
+    fpu: Option<Box<FpuState>>,
+}
+
+impl Context {
+    /// Creates an empty context, to be filled in by the first [`switch_to`]
+    /// away from the currently running code.
+    pub const fn empty() -> Self {
+        Context {
+            rsp: 0,
+            fs_base: 0,
+            fpu: None,
+        }
+    }
+
+    /// Creates a context that starts executing `entry(arg)` on a new stack.
+    ///
+    /// # Arguments
+    ///
+    /// * `stack_top` - One past the highest address of the stack; must be
+    ///   16-byte aligned
+    /// * `entry` - Function to run
+    /// * `arg` - Argument passed to `entry`
+    ///
+    /// # Safety
+    ///
+    /// The [`INITIAL_FRAME_WORDS`] words below `stack_top` must be mapped,
+    /// writable, and not in use.
+    pub unsafe fn new(stack_top: u64, entry: ContextEntry, arg: usize) -> Self {
+        let frame = initial_frame(entry, arg);
+        let rsp = stack_top - core::mem::size_of_val(&frame) as u64;
+        core::ptr::copy_nonoverlapping(frame.as_ptr(), rsp as *mut u64, frame.len());
+        Context {
+            rsp,
+            fs_base: 0,
+            fpu: None,
+        }
+    }
+
+    /// Gives the context its own FPU state, starting from the default state.
+    ///
+    /// Only contexts that use floating point or SSE instructions need this.
+    pub fn with_fpu(mut self) -> Self {
+        self.fpu = Some(Box::new(FpuState::new()));
+        self
+    }
+
+    /// Like [`with_fpu`](Self::with_fpu), for a context that may already be
+    /// running; keeps existing FPU state.
+    pub fn enable_fpu(&mut self) {
+        if self.fpu.is_none() {
+            self.fpu = Some(Box::new(FpuState::new()));
+        }
+    }
+
+    /// Sets the `FS` base the context starts with; it is zero by default.
+    ///
+    /// Only meaningful for a context that is not running, since a running
+    /// context's value is taken from the CPU at the next switch.
+    pub fn set_fs_base(&mut self, base: u64) {
+        self.fs_base = base;
+    }
+
+    /// Stack pointer saved at the last switch away from this context.
+    pub fn stack_pointer(&self) -> u64 {
+        self.rsp
+    }
+}
+
+/// Builds the initial stack frame for a context running `entry(arg)`.
+///
+/// Entries are ordered from the lowest address (the initial stack pointer)
+/// upwards; see the module documentation for the layout.
+pub fn initial_frame(entry: ContextEntry, arg: usize) -> [u64; INITIAL_FRAME_WORDS] {
+    extern "C" {
+        fn context_entry();
+    }
+
+    [
+        0,                                 // r15
+        0,                                 // r14
+        arg as u64,                        // r13
+        entry as usize as u64,             // r12
+        0,                                 // rbx
+        0,                                 // rbp
+        context_entry as *const () as u64, // return address
+    ]
+}
+
+/// Suspends the current execution into `prev` and resumes `next`.
+///
+/// Returns when some other code switches back to `prev`.
+///
+/// # Safety
+///
+/// Interrupts must be disabled. `next` must have been created with
+/// [`Context::new`] or filled in by an earlier switch, and its stack must
+/// still be mapped. Both contexts must stay at the same address until
+/// `prev` is resumed.
+pub unsafe fn switch_to(prev: &mut Context, next: &Context) {
+    if let Some(fpu) = prev.fpu.as_deref_mut() {
+        fpu.save();
+    }
+    if let Some(fpu) = next.fpu.as_deref() {
+        fpu.restore();
+    }
+    prev.fs_base = tls::fs_base();
+    if next.fs_base != prev.fs_base {
+        tls::set_fs_base(next.fs_base);
+    }
+    switch_stack(&mut prev.rsp, next.rsp);
+}
+
+global_asm!(
+    ".global context_entry",
+    "context_entry:",
+    "    mov rdi, r13",
+    "    call r12",
+    "    ud2",
+);
+
+/// Saves the callee-saved registers and stack pointer to `*prev_rsp` and
+/// resumes the stack at `next_rsp`.
+#[unsafe(naked)]
+unsafe extern "C" fn switch_stack(prev_rsp: *mut u64, next_rsp: u64) {
+    naked_asm!(
+        "push rbp",
+        "push rbx",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov [rdi], rsp",
+        "mov rsp, rsi",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbx",
+        "pop rbp",
+        "ret",
+    );
+}
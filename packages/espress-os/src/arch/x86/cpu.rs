@@ -0,0 +1,331 @@
+//! # CPU Identification and Topology
+//!
+//! What the kernel knows about the processors it runs on, gathered once by
+//! [`topology`] from two sources:
+//!
+//! - CPUID on the boot CPU: the vendor and brand string, family and model,
+//!   the hypervisor if there is one, how many threads share a core and how
+//!   many cores a package, and the caches (leaf 4 on Intel, `0x8000001d`
+//!   on AMD);
+//! - the ACPI [MADT](crate::acpi::madt): how many logical CPUs the firmware
+//!   lists and their APIC IDs, from which the number of packages follows.
+//!
+//! [`init`] logs the result at boot. The other CPUs are not started, so
+//! everything is as seen from the boot CPU.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::arch::x86_64::__cpuid_count;
+use spin::Once;
+
+use crate::acpi::madt;
+use crate::println;
+
+/// CPUID leaf 1, EDX: more than one logical processor per package.
+const CPUID_HTT: u32 = 1 << 28;
+/// CPUID leaf 1, ECX: running under a hypervisor.
+const CPUID_HYPERVISOR: u32 = 1 << 31;
+/// CPUID leaf `0x80000001`, ECX: AMD topology extensions.
+const CPUID_TOPOLOGY_EXTENSIONS: u32 = 1 << 22;
+
+/// Topology level type of CPUID leaf `0xb`: SMT threads.
+const LEVEL_SMT: u32 = 1;
+/// Topology level type of CPUID leaf `0xb`: cores.
+const LEVEL_CORE: u32 = 2;
+
+/// The topology, detected on first use.
+static TOPOLOGY: Once<Topology> = Once::new();
+
+/// What a cache holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheKind {
+    /// Data only
+    Data,
+    /// Instructions only
+    Instruction,
+    /// Both
+    Unified,
+}
+
+/// A CPU cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cache {
+    /// Cache level, 1 for the closest to the core
+    pub level: u8,
+    /// What the cache holds
+    pub kind: CacheKind,
+    /// Size in bytes
+    pub size: u64,
+    /// Size of a line in bytes
+    pub line_size: u32,
+    /// Associativity
+    pub ways: u32,
+    /// Most logical processors sharing it
+    pub shared_by: u32,
+}
+
+impl Cache {
+    /// Short name such as `L1d` or `L2`.
+    pub fn name(&self) -> String {
+        let suffix = match self.kind {
+            CacheKind::Data => "d",
+            CacheKind::Instruction => "i",
+            CacheKind::Unified => "",
+        };
+        alloc::format!("L{}{}", self.level, suffix)
+    }
+}
+
+/// The processors of the machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Topology {
+    /// Vendor ID, e.g. `GenuineIntel`
+    pub vendor: String,
+    /// Brand string, e.g. `Intel(R) Core(TM) i7-8650U CPU @ 1.90GHz`
+    pub brand: String,
+    /// Family, with the extended family folded in
+    pub family: u32,
+    /// Model, with the extended model folded in
+    pub model: u32,
+    /// Stepping
+    pub stepping: u32,
+    /// Hypervisor vendor ID, if running in a virtual machine
+    pub hypervisor: Option<String>,
+    /// Number of packages (sockets)
+    pub packages: u32,
+    /// Cores per package
+    pub cores_per_package: u32,
+    /// Threads per core
+    pub threads_per_core: u32,
+    /// APIC IDs of the logical CPUs, the boot CPU only without a MADT
+    pub apic_ids: Vec<u32>,
+    /// Caches of the boot CPU, innermost first
+    pub caches: Vec<Cache>,
+}
+
+impl Topology {
+    /// Total number of logical CPUs.
+    pub fn logical_cpus(&self) -> usize {
+        self.apic_ids.len()
+    }
+
+    /// Total number of cores.
+    pub fn cores(&self) -> u32 {
+        self.packages * self.cores_per_package
+    }
+}
+
+/// Detects the topology and logs it.
+///
+/// Must be called after [`acpi::init`](crate::acpi::init) so that the CPUs
+/// listed by the firmware are counted.
+pub fn init() {
+    let topology = topology();
+    let model = match &topology.hypervisor {
+        Some(hypervisor) => alloc::format!(
+            "{} family {} model {} stepping {}, under {}",
+            topology.vendor,
+            topology.family,
+            topology.model,
+            topology.stepping,
+            hypervisor
+        ),
+        None => alloc::format!(
+            "{} family {} model {} stepping {}",
+            topology.vendor,
+            topology.family,
+            topology.model,
+            topology.stepping
+        ),
+    };
+    println!("cpu: {} ({})", topology.brand, model);
+    println!(
+        "cpu: {} package(s), {} core(s), {} thread(s), {} per core",
+        topology.packages,
+        topology.cores(),
+        topology.logical_cpus(),
+        topology.threads_per_core
+    );
+    if !topology.caches.is_empty() {
+        let caches: Vec<String> = topology
+            .caches
+            .iter()
+            .map(|cache| alloc::format!("{} {} KiB", cache.name(), cache.size / 1024))
+            .collect();
+        println!("cpu: caches {}", caches.join(", "));
+    }
+}
+
+/// Returns the topology, detecting it on the first call.
+pub fn topology() -> &'static Topology {
+    TOPOLOGY.call_once(detect)
+}
+
+/// Gathers the topology from CPUID and the MADT.
+fn detect() -> Topology {
+    let max_leaf = __cpuid_count(0, 0).eax;
+    let max_extended = __cpuid_count(0x8000_0000, 0).eax;
+    let vendor = vendor();
+    let leaf1 = __cpuid_count(1, 0);
+
+    let base_family = (leaf1.eax >> 8) & 0xf;
+    let base_model = (leaf1.eax >> 4) & 0xf;
+    let family = match base_family {
+        0xf => base_family + ((leaf1.eax >> 20) & 0xff),
+        _ => base_family,
+    };
+    let model = match base_family {
+        0x6 | 0xf => base_model | ((leaf1.eax >> 12) & 0xf0),
+        _ => base_model,
+    };
+
+    let hypervisor = (leaf1.ecx & CPUID_HYPERVISOR != 0).then(|| {
+        let leaf = __cpuid_count(0x4000_0000, 0);
+        id_string(&[leaf.ebx, leaf.ecx, leaf.edx])
+    });
+
+    let brand = if max_extended >= 0x8000_0004 {
+        let registers: Vec<u32> = (0x8000_0002..=0x8000_0004)
+            .flat_map(|leaf| {
+                let result = __cpuid_count(leaf, 0);
+                [result.eax, result.ebx, result.ecx, result.edx]
+            })
+            .collect();
+        id_string(&registers)
+    } else {
+        String::new()
+    };
+
+    let is_amd = matches!(vendor.as_str(), "AuthenticAMD" | "HygonGenuine");
+    let (threads_per_core, logical_per_package) =
+        threads_and_logical_per_package(max_leaf, max_extended, is_amd, leaf1.ebx, leaf1.edx);
+    let cores_per_package = (logical_per_package / threads_per_core).max(1);
+
+    let apic_ids: Vec<u32> = match madt::madt() {
+        Some(madt) => madt.cpus().map(|cpu| cpu.apic_id).collect(),
+        None => Vec::new(),
+    };
+    let apic_ids = if apic_ids.is_empty() {
+        alloc::vec![leaf1.ebx >> 24]
+    } else {
+        apic_ids
+    };
+    let packages = (apic_ids.len() as u32).div_ceil(logical_per_package).max(1);
+
+    let topology_extensions = max_extended >= 0x8000_0001
+        && __cpuid_count(0x8000_0001, 0).ecx & CPUID_TOPOLOGY_EXTENSIONS != 0;
+    let caches = match (is_amd, topology_extensions) {
+        (true, true) => caches(0x8000_001d),
+        (true, false) => Vec::new(),
+        (false, _) if max_leaf >= 4 => caches(4),
+        (false, _) => Vec::new(),
+    };
+
+    Topology {
+        vendor,
+        brand,
+        family,
+        model,
+        stepping: leaf1.eax & 0xf,
+        hypervisor,
+        packages,
+        cores_per_package,
+        threads_per_core,
+        apic_ids,
+        caches,
+    }
+}
+
+/// Returns the number of threads per core and logical processors per
+/// package, both at least 1.
+///
+/// Prefers the topology enumeration of leaf `0xb`, and falls back to the
+/// older leaves 1 and 4 (Intel) or `0x80000008` and `0x8000001e` (AMD).
+fn threads_and_logical_per_package(
+    max_leaf: u32,
+    max_extended: u32,
+    is_amd: bool,
+    leaf1_ebx: u32,
+    leaf1_edx: u32,
+) -> (u32, u32) {
+    if max_leaf >= 0xb {
+        let mut threads = 0;
+        let mut logical = 0;
+        for subleaf in 0..8 {
+            let result = __cpuid_count(0xb, subleaf);
+            let count = result.ebx & 0xffff;
+            match (result.ecx >> 8) & 0xff {
+                0 => break,
+                LEVEL_SMT => threads = count,
+                LEVEL_CORE => logical = count,
+                _ => {}
+            }
+        }
+        if threads != 0 && logical != 0 {
+            return (threads, logical.max(threads));
+        }
+    }
+
+    let logical = if leaf1_edx & CPUID_HTT != 0 {
+        ((leaf1_ebx >> 16) & 0xff).max(1)
+    } else {
+        1
+    };
+    let cores = if is_amd && max_extended >= 0x8000_0008 {
+        (__cpuid_count(0x8000_0008, 0).ecx & 0xff) + 1
+    } else if !is_amd && max_leaf >= 4 {
+        (__cpuid_count(4, 0).eax >> 26) + 1
+    } else {
+        1
+    };
+    let threads = if is_amd && max_extended >= 0x8000_001e {
+        ((__cpuid_count(0x8000_001e, 0).ebx >> 8) & 0xff) + 1
+    } else {
+        (logical / cores).max(1)
+    };
+    (threads, logical.max(cores * threads))
+}
+
+/// Enumerates the caches described by the deterministic cache parameters
+/// leaf `leaf` (4 or `0x8000001d`, which share a format).
+fn caches(leaf: u32) -> Vec<Cache> {
+    let mut caches = Vec::new();
+    for subleaf in 0..16 {
+        let result = __cpuid_count(leaf, subleaf);
+        let kind = match result.eax & 0x1f {
+            1 => CacheKind::Data,
+            2 => CacheKind::Instruction,
+            3 => CacheKind::Unified,
+            _ => break,
+        };
+        let line_size = (result.ebx & 0xfff) + 1;
+        let partitions = ((result.ebx >> 12) & 0x3ff) + 1;
+        let ways = (result.ebx >> 22) + 1;
+        let sets = result.ecx + 1;
+        caches.push(Cache {
+            level: ((result.eax >> 5) & 0x7) as u8,
+            kind,
+            size: u64::from(ways) * u64::from(partitions) * u64::from(line_size) * u64::from(sets),
+            line_size,
+            ways,
+            shared_by: ((result.eax >> 14) & 0xfff) + 1,
+        });
+    }
+    caches
+}
+
+/// Returns the CPU vendor ID from leaf 0.
+fn vendor() -> String {
+    let leaf = __cpuid_count(0, 0);
+    id_string(&[leaf.ebx, leaf.edx, leaf.ecx])
+}
+
+/// Decodes ASCII packed into registers, dropping padding NULs and spaces.
+fn id_string(registers: &[u32]) -> String {
+    let bytes: Vec<u8> = registers
+        .iter()
+        .flat_map(|register| register.to_le_bytes())
+        .take_while(|&byte| byte != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).trim().into()
+}
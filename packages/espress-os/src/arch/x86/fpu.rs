@@ -0,0 +1,59 @@
+//! # FPU and SSE State
+//!
+//! The kernel itself is compiled without SSE, so kernel code never touches
+//! the floating point registers. Threads that do (userspace, or kernel code
+//! opting in) need their x87/MMX/SSE state saved across context switches;
+//! [`FpuState`] holds one such snapshot in `FXSAVE` format.
+
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+
+/// Register state saved by `fxsave64`.
+#[derive(Clone)]
+#[repr(C, align(16))]
+pub struct FpuState([u8; 512]);
+
+impl FpuState {
+    /// Returns the state of a freshly initialized FPU.
+    ///
+    /// Sets the default control word (all exceptions masked, 64-bit
+    /// precision, round to nearest) and MXCSR (all exceptions masked).
+    pub fn new() -> Self {
+        let mut area = [0u8; 512];
+        area[0..2].copy_from_slice(&0x037fu16.to_le_bytes());
+        area[24..28].copy_from_slice(&0x1f80u32.to_le_bytes());
+        FpuState(area)
+    }
+
+    /// Stores the current FPU/SSE registers into this snapshot.
+    pub fn save(&mut self) {
+        unsafe {
+            core::arch::asm!("fxsave64 [{}]", in(reg) self.0.as_mut_ptr(), options(nostack));
+        }
+    }
+
+    /// Loads the FPU/SSE registers from this snapshot.
+    pub fn restore(&self) {
+        unsafe {
+            core::arch::asm!("fxrstor64 [{}]", in(reg) self.0.as_ptr(), options(nostack));
+        }
+    }
+}
+
+impl Default for FpuState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Enables the FPU and SSE instructions together with `FXSAVE`/`FXRSTOR`.
+pub fn init() {
+    unsafe {
+        Cr0::update(|flags| {
+            flags.remove(Cr0Flags::EMULATE_COPROCESSOR);
+            flags.insert(Cr0Flags::MONITOR_COPROCESSOR);
+        });
+        Cr4::update(|flags| {
+            flags.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE);
+        });
+    }
+}
@@ -0,0 +1,227 @@
+//! # CPU Idle States
+//!
+//! What the CPU does while no thread is ready. The shallowest state, C1,
+//! is entered with `hlt` and always available. Where CPUID leaf 5
+//! advertises MONITOR/MWAIT sub-states for deeper C-states, [`init`] adds
+//! C2 and beyond, entered with `mwait` and the matching hint. Deeper states
+//! save more power but take longer to leave, so they only pay off for
+//! longer idle periods.
+//!
+//! Which state [`enter`] picks is decided by a ladder governor: after an
+//! idle period that lasted well beyond what the next deeper state needs,
+//! the next one goes one step deeper; after one that was shorter than the
+//! current state needs, it goes one step back. The deepest state used can
+//! be capped with [`set_max_state`]. Without ACPI `_CST` objects the exit
+//! latencies are not known, so the target residencies are conservative
+//! figures typical of current CPUs.
+//!
+//! Entries and time spent in each state are counted with the
+//! [TSC](super::tsc); [`stats`] reports them.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use core::arch::x86_64::__cpuid_count;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use core::time::Duration;
+
+use super::tsc;
+
+/// Most states supported: C1 through C7.
+pub const MAX_STATES: usize = 7;
+
+/// CPUID leaf 1, ECX: MONITOR/MWAIT are supported.
+const CPUID_MONITOR: u32 = 1 << 3;
+
+/// Minimum time worth spending in C1 through C7, in microseconds.
+const TARGET_RESIDENCY_US: [u64; MAX_STATES] = [0, 20, 100, 300, 600, 800, 1000];
+
+/// How many times the next state's target residency an idle period must
+/// last before the governor goes one step deeper.
+const PROMOTION_FACTOR: u32 = 2;
+
+/// How a state is entered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    /// `hlt`
+    Hlt,
+    /// `mwait` with the given hint
+    Mwait(u32),
+}
+
+/// An idle state the CPU supports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdleState {
+    /// C-state number, 1 for the shallowest
+    pub number: u8,
+    /// How the state is entered
+    pub method: Method,
+    /// Minimum idle time for the state to pay off
+    pub target_residency: Duration,
+}
+
+/// Usage of an idle state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdleStats {
+    /// The state
+    pub state: IdleState,
+    /// Times the state was entered
+    pub entries: u64,
+    /// Time spent in the state, including the interrupt that ended it
+    pub time: Duration,
+}
+
+/// The C1 state, available everywhere.
+const C1: IdleState = IdleState {
+    number: 1,
+    method: Method::Hlt,
+    target_residency: Duration::ZERO,
+};
+
+/// Supported states, shallowest first; set by [`init`].
+static STATES: OnceCell<Vec<IdleState>> = OnceCell::uninit();
+
+/// Index of the state the governor picks next.
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+/// Index of the deepest state the governor may pick.
+static MAX_STATE: AtomicUsize = AtomicUsize::new(MAX_STATES - 1);
+
+/// Times each state was entered.
+static ENTRIES: [AtomicU64; MAX_STATES] = [const { AtomicU64::new(0) }; MAX_STATES];
+/// TSC cycles spent in each state.
+static CYCLES: [AtomicU64; MAX_STATES] = [const { AtomicU64::new(0) }; MAX_STATES];
+
+/// Monitored by `mwait`; nothing writes to it, so only interrupts wake the
+/// CPU.
+static MONITOR: AtomicU64 = AtomicU64::new(0);
+
+/// Detects the supported idle states and logs them.
+///
+/// Must be called once, after the heap is set up and the TSC calibrated.
+///
+/// # Panics
+///
+/// Panics if called more than once.
+pub fn init() {
+    STATES
+        .try_init_once(detect)
+        .expect("idle::init called more than once");
+    let deeper: Vec<String> = states()[1..]
+        .iter()
+        .map(|state| format!("C{}", state.number))
+        .collect();
+    if deeper.is_empty() {
+        crate::println!("idle: C1 (hlt)");
+    } else {
+        crate::println!("idle: C1 (hlt), {} (mwait)", deeper.join(", "));
+    }
+}
+
+/// Returns the supported states, shallowest first; only C1 before
+/// [`init`].
+pub fn states() -> &'static [IdleState] {
+    match STATES.get() {
+        Some(states) => states,
+        None => core::slice::from_ref(&C1),
+    }
+}
+
+/// Finds the C-states CPUID advertises MWAIT sub-states for.
+fn detect() -> Vec<IdleState> {
+    let mut states = alloc::vec![C1];
+    let max_leaf = __cpuid_count(0, 0).eax;
+    if max_leaf < 5 || __cpuid_count(1, 0).ecx & CPUID_MONITOR == 0 {
+        return states;
+    }
+    // EDX holds the number of sub-states of C0 through C7, four bits each.
+    // Each state is entered in its first sub-state.
+    let sub_states = __cpuid_count(5, 0).edx;
+    for number in 2..=MAX_STATES as u32 {
+        if (sub_states >> (number * 4)) & 0xf == 0 {
+            continue;
+        }
+        states.push(IdleState {
+            number: number as u8,
+            method: Method::Mwait((number - 1) << 4),
+            target_residency: Duration::from_micros(TARGET_RESIDENCY_US[number as usize - 1]),
+        });
+    }
+    states
+}
+
+/// Sleeps until the next interrupt, in the state the governor picks.
+///
+/// Must be called with interrupts disabled, after checking that there is
+/// nothing to do, so that a wakeup cannot slip in between the check and
+/// the sleep. Returns with interrupts enabled, once the interrupt that woke
+/// the CPU has been handled.
+pub fn enter() {
+    let states = states();
+    let max = MAX_STATE.load(Ordering::Relaxed).min(states.len() - 1);
+    let index = CURRENT.load(Ordering::Relaxed).min(max);
+    let state = states[index];
+
+    let start = tsc::read();
+    // `sti` takes effect after the next instruction, so an interrupt
+    // cannot arrive before the CPU sleeps.
+    match state.method {
+        Method::Hlt => unsafe { core::arch::asm!("sti; hlt", options(nomem, nostack)) },
+        Method::Mwait(hint) => unsafe {
+            core::arch::asm!(
+                "monitor",
+                in("rax") MONITOR.as_ptr(),
+                in("ecx") 0,
+                in("edx") 0,
+                options(nostack),
+            );
+            core::arch::asm!(
+                "sti; mwait",
+                in("eax") hint,
+                in("ecx") 0,
+                options(nomem, nostack),
+            );
+        },
+    }
+    let cycles = tsc::read().wrapping_sub(start);
+
+    ENTRIES[index].fetch_add(1, Ordering::Relaxed);
+    CYCLES[index].fetch_add(cycles, Ordering::Relaxed);
+
+    let residency = tsc::cycles_to_duration(cycles);
+    let next = if index < max && residency >= states[index + 1].target_residency * PROMOTION_FACTOR
+    {
+        index + 1
+    } else if index > 0 && residency < state.target_residency {
+        index - 1
+    } else {
+        index
+    };
+    CURRENT.store(next, Ordering::Relaxed);
+}
+
+/// Caps the governor at the state numbered `number`, e.g. 1 to use only
+/// `hlt`.
+///
+/// Numbers beyond the deepest supported state lift the cap.
+pub fn set_max_state(number: u8) {
+    let states = states();
+    let index = states
+        .iter()
+        .rposition(|state| state.number <= number)
+        .unwrap_or(0);
+    MAX_STATE.store(index, Ordering::Relaxed);
+}
+
+/// Returns how often and how long each supported state was used.
+pub fn stats() -> Vec<IdleStats> {
+    states()
+        .iter()
+        .enumerate()
+        .map(|(index, &state)| IdleStats {
+            state,
+            entries: ENTRIES[index].load(Ordering::Relaxed),
+            time: tsc::cycles_to_duration(CYCLES[index].load(Ordering::Relaxed)),
+        })
+        .collect()
+}
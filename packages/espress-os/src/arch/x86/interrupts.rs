@@ -0,0 +1,12 @@
+//! # x86_64 Interrupt Flag
+//!
+//! Masking and waiting for interrupts through `RFLAGS.IF`, `cli`, `sti`, and
+//! `hlt`. The IDT and the interrupt controllers are set up by
+//! [`interrupts`](crate::interrupts).
+
+pub use x86_64::instructions::interrupts::{are_enabled, disable, enable, without_interrupts};
+
+/// Sleeps until the next interrupt.
+pub fn wait() {
+    x86_64::instructions::hlt();
+}
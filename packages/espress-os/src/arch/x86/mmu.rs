@@ -0,0 +1,38 @@
+//! # x86_64 MMU
+//!
+//! The architecture interface to address translation: the page table root
+//! in `CR3` and the TLB. Page tables themselves are managed by
+//! [`mm`](crate::mm).
+
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::PhysFrame;
+use x86_64::{instructions::tlb, PhysAddr, VirtAddr};
+
+/// Size of the smallest page in bytes.
+pub const PAGE_SIZE: u64 = 4096;
+
+/// Returns the physical address of the active top-level page table.
+pub fn root() -> u64 {
+    Cr3::read().0.start_address().as_u64()
+}
+
+/// Switches to the page tables rooted at physical address `root`.
+///
+/// # Safety
+///
+/// `root` must be a top-level page table that maps the running kernel at
+/// the same addresses as the current one.
+pub unsafe fn set_root(root: u64) {
+    let (_, flags) = Cr3::read();
+    Cr3::write(PhysFrame::containing_address(PhysAddr::new(root)), flags);
+}
+
+/// Removes the translation of the page containing `address` from the TLB.
+pub fn flush_page(address: u64) {
+    tlb::flush(VirtAddr::new(address));
+}
+
+/// Removes all non-global translations from the TLB.
+pub fn flush_all() {
+    tlb::flush_all();
+}
@@ -0,0 +1,30 @@
+//! # x86_64
+//!
+//! Low-level x86_64 primitives that the portable parts of the kernel build
+//! on. Besides the common [architecture interface](super) ([`console`],
+//! [`interrupts`], [`mmu`], and [`timer`]), these are saving and restoring
+//! execution contexts ([`context`]), the CPU model, topology, and caches
+//! ([`cpu`]), the floating point / SSE register state ([`fpu`]), the
+//! interval timer ([`pit`]), the battery-backed clock ([`rtc`]), the cycle
+//! counter ([`tsc`]), the thread pointer used for thread-local storage
+//! ([`tls`]), the CPU's idle states ([`idle`]), the memory types of pages
+//! ([`pat`]), and saving the CPU state across a suspend to RAM
+//! ([`suspend`]).
+
+pub mod console;
+pub mod context;
+pub mod cpu;
+pub mod fpu;
+pub mod idle;
+pub mod interrupts;
+pub mod mmu;
+pub mod pat;
+pub mod pit;
+pub mod rtc;
+pub mod suspend;
+pub mod timer;
+pub mod tls;
+pub mod tsc;
+
+/// Name of the architecture, as shown at boot.
+pub const NAME: &str = "x86_64";
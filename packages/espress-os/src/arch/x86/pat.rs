@@ -0,0 +1,60 @@
+//! # Page Attribute Table
+//!
+//! The memory type of a page is looked up in the PAT, indexed by the page's
+//! `PAT`, `PCD`, and `PWT` bits. The power-on table offers write-back,
+//! write-through, and two flavors of uncached memory, but no
+//! write-combining. [`init`] turns the write-through entry selected by `PWT`
+//! alone into write-combining, as Linux does; no kernel mapping uses that
+//! combination otherwise, and `PCD | PWT` still selects uncached memory for
+//! device registers.
+
+use core::arch::x86_64::__cpuid_count;
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::instructions::tlb;
+use x86_64::registers::model_specific::Msr;
+
+/// The `IA32_PAT` model-specific register.
+const IA32_PAT: u32 = 0x277;
+
+/// `CPUID.01H:EDX` bit for the PAT.
+const CPUID_PAT: u32 = 1 << 16;
+
+/// Memory type: uncacheable
+const UC: u8 = 0x00;
+/// Memory type: write-combining
+const WC: u8 = 0x01;
+/// Memory type: write-through
+const WT: u8 = 0x04;
+/// Memory type: write-back
+const WB: u8 = 0x06;
+/// Memory type: uncached, unless overridden by the MTRRs
+const UC_MINUS: u8 = 0x07;
+
+/// The power-on table with entry 1 (`PWT`) changed to write-combining.
+const PAT: u64 = u64::from_le_bytes([WB, WC, UC_MINUS, UC, WB, WT, UC_MINUS, UC]);
+
+/// Set once [`init`] has programmed the PAT.
+static WRITE_COMBINING: AtomicBool = AtomicBool::new(false);
+
+/// Programs the PAT so that `PWT` alone selects write-combining.
+///
+/// Does nothing on CPUs without a PAT, which then offer no
+/// write-combining.
+pub fn init() {
+    if __cpuid_count(1, 0).edx & CPUID_PAT == 0 {
+        return;
+    }
+    // Changing a memory type requires writing back the caches and dropping
+    // the TLB entries that cached the old one.
+    unsafe {
+        core::arch::asm!("wbinvd", options(nostack, preserves_flags));
+        Msr::new(IA32_PAT).write(PAT);
+    }
+    tlb::flush_all();
+    WRITE_COMBINING.store(true, Ordering::Release);
+}
+
+/// Returns `true` if pages mapped with only `PWT` set are write-combining.
+pub fn write_combining() -> bool {
+    WRITE_COMBINING.load(Ordering::Acquire)
+}
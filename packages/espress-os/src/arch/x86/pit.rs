@@ -0,0 +1,112 @@
+//! # Programmable Interval Timer
+//!
+//! The 8253/8254 PIT drives IRQ 0. Out of reset, channel 0 fires at about
+//! 18.2 Hz; [`set_frequency`] reprograms it as a rate generator for a
+//! steadier kernel tick. Channel 2, whose output can be polled, serves as a
+//! reference clock for [`busy_wait`] and, connected to the PC speaker,
+//! produces tones ([`start_tone`]); a busy wait silences the speaker.
+
+use x86_64::instructions::port::Port;
+
+/// Input clock of the PIT in Hz.
+pub const BASE_FREQUENCY: u32 = 1_193_182;
+
+/// Channel 0 data port.
+const CHANNEL_0: u16 = 0x40;
+
+/// Channel 2 data port.
+const CHANNEL_2: u16 = 0x42;
+
+/// NMI status and control port; bit 0 gates channel 2, bit 1 connects it
+/// to the speaker, and bit 5 reflects its output.
+const PORT_B: u16 = 0x61;
+
+/// Mode/command register.
+const COMMAND: u16 = 0x43;
+
+/// Channel 0, lobyte/hibyte access, mode 2 (rate generator), binary.
+const CHANNEL_0_RATE_GENERATOR: u8 = 0b0011_0100;
+
+/// Channel 2, lobyte/hibyte access, mode 0 (interrupt on terminal count),
+/// binary.
+const CHANNEL_2_ONE_SHOT: u8 = 0b1011_0000;
+
+/// Channel 2, lobyte/hibyte access, mode 3 (square wave generator), binary.
+const CHANNEL_2_SQUARE_WAVE: u8 = 0b1011_0110;
+
+/// Programs channel 0 to interrupt `hz` times per second.
+///
+/// The divisor is rounded to the nearest value the PIT supports.
+///
+/// # Returns
+///
+/// The frequency actually programmed.
+pub fn set_frequency(hz: u32) -> u32 {
+    let divisor = (BASE_FREQUENCY + hz / 2) / hz.max(1);
+    let divisor = divisor.clamp(1, u16::MAX as u32) as u16;
+
+    let mut command = Port::<u8>::new(COMMAND);
+    let mut data = Port::<u8>::new(CHANNEL_0);
+    unsafe {
+        command.write(CHANNEL_0_RATE_GENERATOR);
+        data.write(divisor as u8);
+        data.write((divisor >> 8) as u8);
+    }
+    BASE_FREQUENCY / divisor as u32
+}
+
+/// Spins until `count` cycles of the PIT input clock have passed.
+///
+/// Uses channel 2 with the speaker disconnected, so it works with
+/// interrupts disabled and does not disturb the kernel tick.
+pub fn busy_wait(count: u16) {
+    let mut port_b = Port::<u8>::new(PORT_B);
+    let mut command = Port::<u8>::new(COMMAND);
+    let mut data = Port::<u8>::new(CHANNEL_2);
+    unsafe {
+        let control = port_b.read() & !0b11;
+        port_b.write(control);
+        command.write(CHANNEL_2_ONE_SHOT);
+        data.write(count as u8);
+        data.write((count >> 8) as u8);
+        // Counting starts on the rising edge of the gate.
+        port_b.write(control | 0b01);
+        while port_b.read() & 0b10_0000 == 0 {
+            core::hint::spin_loop();
+        }
+        port_b.write(control);
+    }
+}
+
+/// Lets channel 2 drive the speaker with a square wave of `hz`.
+///
+/// The divisor is rounded to the nearest value the PIT supports.
+///
+/// # Returns
+///
+/// The frequency actually programmed.
+pub fn start_tone(hz: u32) -> u32 {
+    let divisor = (BASE_FREQUENCY + hz / 2) / hz.max(1);
+    let divisor = divisor.clamp(1, u16::MAX as u32) as u16;
+
+    let mut port_b = Port::<u8>::new(PORT_B);
+    let mut command = Port::<u8>::new(COMMAND);
+    let mut data = Port::<u8>::new(CHANNEL_2);
+    unsafe {
+        command.write(CHANNEL_2_SQUARE_WAVE);
+        data.write(divisor as u8);
+        data.write((divisor >> 8) as u8);
+        let control = port_b.read();
+        port_b.write(control | 0b11);
+    }
+    BASE_FREQUENCY / divisor as u32
+}
+
+/// Disconnects the speaker and stops channel 2.
+pub fn stop_tone() {
+    let mut port_b = Port::<u8>::new(PORT_B);
+    unsafe {
+        let control = port_b.read();
+        port_b.write(control & !0b11);
+    }
+}
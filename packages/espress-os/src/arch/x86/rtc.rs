@@ -0,0 +1,167 @@
+//! # CMOS Real-Time Clock
+//!
+//! The battery-backed clock of the PC, read through the CMOS index and
+//! data ports. It keeps the date and time to the second while the machine
+//! is off, in BCD or binary and in 12- or 24-hour format as status register
+//! B says; [`read`] and [`write`] take care of both. The century register
+//! is assumed at its usual index, `0x32`.
+//!
+//! The time is taken to be UTC. The wall clock is set from it at boot, see
+//! [`timer::clock`](crate::timer::clock).
+
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+use crate::timer::DateTime;
+
+/// CMOS index port; bit 7 disables NMIs while set.
+const INDEX: u16 = 0x70;
+/// CMOS data port.
+const DATA: u16 = 0x71;
+
+/// Disables NMIs while a register is selected.
+const NMI_DISABLE: u8 = 0x80;
+
+/// Register: seconds.
+const REG_SECONDS: u8 = 0x00;
+/// Register: minutes.
+const REG_MINUTES: u8 = 0x02;
+/// Register: hours.
+const REG_HOURS: u8 = 0x04;
+/// Register: day of the month.
+const REG_DAY: u8 = 0x07;
+/// Register: month.
+const REG_MONTH: u8 = 0x08;
+/// Register: year within the century.
+const REG_YEAR: u8 = 0x09;
+/// Register: status A; bit 7 is set while the clock updates.
+const REG_STATUS_A: u8 = 0x0a;
+/// Register: status B, the format of the time registers.
+const REG_STATUS_B: u8 = 0x0b;
+/// Register: century.
+const REG_CENTURY: u8 = 0x32;
+
+/// Status A: an update is in progress.
+const UPDATE_IN_PROGRESS: u8 = 0x80;
+/// Status B: halts updates while the time is set.
+const SET: u8 = 0x80;
+/// Status B: values are binary rather than BCD.
+const BINARY: u8 = 0x04;
+/// Status B: hours run 0-23 rather than 1-12.
+const HOURS_24: u8 = 0x02;
+/// Hours register in 12-hour format: the time is after noon.
+const PM: u8 = 0x80;
+
+/// Reads the date and time.
+///
+/// Waits out an update in progress and reads until two reads agree, so
+/// the result never mixes values from before and after a tick.
+pub fn read() -> DateTime {
+    interrupts::without_interrupts(|| {
+        let mut time = read_raw();
+        loop {
+            let again = read_raw();
+            if again == time {
+                break;
+            }
+            time = again;
+        }
+        decode(time, register(REG_STATUS_B))
+    })
+}
+
+/// Sets the date and time.
+pub fn write(time: &DateTime) {
+    interrupts::without_interrupts(|| {
+        let status = register(REG_STATUS_B);
+        let encode = |value: u8| match status & BINARY {
+            0 => ((value / 10) << 4) | (value % 10),
+            _ => value,
+        };
+        let hour = match status & HOURS_24 {
+            0 => {
+                let hour12 = encode(match time.hour % 12 {
+                    0 => 12,
+                    hour => hour,
+                });
+                if time.hour >= 12 {
+                    hour12 | PM
+                } else {
+                    hour12
+                }
+            }
+            _ => encode(time.hour),
+        };
+        set_register(REG_STATUS_B, status | SET);
+        set_register(REG_SECONDS, encode(time.second));
+        set_register(REG_MINUTES, encode(time.minute));
+        set_register(REG_HOURS, hour);
+        set_register(REG_DAY, encode(time.day));
+        set_register(REG_MONTH, encode(time.month));
+        set_register(REG_YEAR, encode((time.year % 100) as u8));
+        set_register(REG_CENTURY, encode((time.year / 100) as u8));
+        set_register(REG_STATUS_B, status & !SET);
+    })
+}
+
+/// Reads the time registers as they are, once no update is in progress.
+fn read_raw() -> [u8; 7] {
+    while register(REG_STATUS_A) & UPDATE_IN_PROGRESS != 0 {
+        core::hint::spin_loop();
+    }
+    [
+        register(REG_SECONDS),
+        register(REG_MINUTES),
+        register(REG_HOURS),
+        register(REG_DAY),
+        register(REG_MONTH),
+        register(REG_YEAR),
+        register(REG_CENTURY),
+    ]
+}
+
+/// Converts raw register values in the format `status` describes.
+fn decode(raw: [u8; 7], status: u8) -> DateTime {
+    let [second, minute, hour, day, month, year, century] = raw;
+    let decode = |value: u8| match status & BINARY {
+        0 => (value >> 4) * 10 + (value & 0x0f),
+        _ => value,
+    };
+    let hour = match status & HOURS_24 {
+        0 => decode(hour & !PM) % 12 + if hour & PM != 0 { 12 } else { 0 },
+        _ => decode(hour),
+    };
+    // A clock without a century register reads zero there.
+    let century = match decode(century) {
+        0 => 20,
+        century => century,
+    };
+    DateTime {
+        year: u16::from(century) * 100 + u16::from(decode(year)),
+        month: decode(month),
+        day: decode(day),
+        hour,
+        minute: decode(minute),
+        second: decode(second),
+    }
+}
+
+/// Reads CMOS register `index`.
+fn register(index: u8) -> u8 {
+    let mut index_port = Port::<u8>::new(INDEX);
+    let mut data = Port::<u8>::new(DATA);
+    unsafe {
+        index_port.write(NMI_DISABLE | index);
+        data.read()
+    }
+}
+
+/// Writes `value` to CMOS register `index`.
+fn set_register(index: u8, value: u8) {
+    let mut index_port = Port::<u8>::new(INDEX);
+    let mut data = Port::<u8>::new(DATA);
+    unsafe {
+        index_port.write(NMI_DISABLE | index);
+        data.write(value);
+    }
+}
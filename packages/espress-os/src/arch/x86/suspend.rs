@@ -0,0 +1,433 @@
+//! # Suspend and Resume
+//!
+//! Saving the CPU state before the machine is suspended to RAM (ACPI S3)
+//! and getting back to it when the machine wakes up.
+//!
+//! In S3 the CPU loses all of its state, memory excepted. On wakeup the
+//! firmware starts it in real mode at the waking vector the OS wrote to the
+//! FACS, which must therefore be code below 1 MiB. [`prepare`] copies a
+//! small trampoline there, into memory taken from the frame allocator once
+//! and kept for good, and builds a page table next to it that maps the
+//! trampoline's page at its physical address and the kernel half of the
+//! address space like the kernel's own page table does. The trampoline
+//! enters long mode with that page table and jumps to the kernel.
+//!
+//! [`sleep`] saves the callee-saved registers on the stack and everything
+//! else the kernel set up in the CPU (control registers, descriptor tables,
+//! segments, and model-specific registers) in a static, then calls the
+//! function that puts the machine to sleep. On wakeup, the resume path
+//! restores it all and returns from [`sleep`] as if that function had
+//! returned.
+//!
+//! Only the CPU is handled here; devices are restored by
+//! [`power::suspend`](crate::power::suspend).
+
+use core::arch::{asm, global_asm, naked_asm};
+use core::cell::UnsafeCell;
+use core::mem::{offset_of, size_of};
+use core::ptr::{addr_of, addr_of_mut};
+use spin::Once;
+use x86_64::instructions::segmentation::{Segment, CS, DS, ES, FS, GS, SS};
+use x86_64::instructions::tables::{lgdt, lidt, load_tss, sgdt, sidt};
+use x86_64::registers::control::{Cr0, Cr3, Cr4, Cr4Flags};
+use x86_64::registers::model_specific::Msr;
+use x86_64::structures::gdt::SegmentSelector;
+use x86_64::structures::paging::{PageTable, PageTableFlags};
+use x86_64::structures::DescriptorTablePointer;
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::fpu::FpuState;
+use crate::mm::{self, frame, paging::PagingMode};
+
+/// The real-mode code must lie below this physical address.
+const LOW_MEMORY_END: u64 = 0x10_0000;
+
+/// Offset of the [`TrampolineData`] in the trampoline.
+const DATA_OFFSET: usize = 0x80;
+
+/// Model-specific registers saved across a suspend, besides `EFER`:
+/// `STAR`, `LSTAR`, `SFMASK`, the `FS`, `GS`, and kernel `GS` bases, and
+/// `PAT`.
+const MSRS: [u32; 7] = [
+    0xc000_0081,
+    0xc000_0082,
+    0xc000_0084,
+    0xc000_0100,
+    0xc000_0101,
+    0xc000_0102,
+    0x277,
+];
+
+/// The `EFER` model-specific register.
+const EFER: u32 = 0xc000_0080;
+
+/// Flat 64-bit code segment used by the trampoline.
+const TRAMPOLINE_CODE: u64 = 0x00af_9a00_0000_ffff;
+/// Flat data segment used by the trampoline.
+const TRAMPOLINE_DATA: u64 = 0x00cf_9200_0000_ffff;
+/// Selector of [`TRAMPOLINE_CODE`].
+const TRAMPOLINE_CODE_SELECTOR: u16 = 0x08;
+/// Selector of [`TRAMPOLINE_DATA`].
+const TRAMPOLINE_DATA_SELECTOR: u16 = 0x10;
+
+/// GDT descriptor type bit that marks a TSS as busy.
+const TSS_BUSY: u8 = 1 << 1;
+
+/// What the trampoline needs to know, written by [`prepare`] at
+/// [`DATA_OFFSET`].
+#[repr(C)]
+struct TrampolineData {
+    /// Null, code, and data descriptors
+    gdt: [u64; 3],
+    /// Padding that aligns the GDT base
+    _pad: u16,
+    /// Limit of the GDT
+    gdt_limit: u16,
+    /// Physical address of the GDT
+    gdt_base: u32,
+    /// Physical address of the page table the trampoline enables
+    cr3: u32,
+    /// `CR4` with the paging mode bits
+    cr4: u32,
+    /// Physical address of the 64-bit code, for the far jump to it
+    long_mode: u32,
+    /// Code segment selector for the far jump
+    long_mode_selector: u16,
+    /// Padding that aligns the entry point
+    _pad2: u16,
+    /// Kernel address the trampoline jumps to in long mode
+    entry: u64,
+}
+
+/// CPU state saved by [`sleep`].
+#[repr(C)]
+struct CpuContext {
+    /// Stack pointer below the callee-saved registers
+    rsp: u64,
+    /// Control register 0
+    cr0: u64,
+    /// Page table root
+    cr3: u64,
+    /// Control register 4
+    cr4: u64,
+    /// Extended feature enable register
+    efer: u64,
+    /// The GDT
+    gdtr: DescriptorTablePointer,
+    /// The IDT
+    idtr: DescriptorTablePointer,
+    /// `CS`, `SS`, `DS`, `ES`, `FS`, and `GS`
+    segments: [u16; 6],
+    /// Task register
+    tr: u16,
+    /// Values of [`MSRS`]
+    msrs: [u64; MSRS.len()],
+}
+
+/// The saved CPU state, written before the suspend and read on resume.
+struct SavedContext(UnsafeCell<CpuContext>);
+
+// Only accessed by `sleep` and the resume path it returns through, with
+// interrupts disabled.
+unsafe impl Sync for SavedContext {}
+
+static CONTEXT: SavedContext = SavedContext(UnsafeCell::new(CpuContext {
+    rsp: 0,
+    cr0: 0,
+    cr3: 0,
+    cr4: 0,
+    efer: 0,
+    gdtr: DescriptorTablePointer {
+        limit: 0,
+        base: VirtAddr::zero(),
+    },
+    idtr: DescriptorTablePointer {
+        limit: 0,
+        base: VirtAddr::zero(),
+    },
+    segments: [0; 6],
+    tr: 0,
+    msrs: [0; MSRS.len()],
+}));
+
+/// Physical address of the low memory holding the trampoline (first
+/// frame) and its page tables (the frames after it).
+static LOW_MEMORY: Once<Option<u64>> = Once::new();
+
+extern "C" {
+    static suspend_trampoline: u8;
+    static suspend_trampoline_long: u8;
+    static suspend_trampoline_end: u8;
+    fn suspend_resume();
+}
+
+// Runs in real mode at the waking vector, with `CS` holding its address
+// divided by 16 and nothing else set up. The code only works on offsets
+// into itself, since it is copied elsewhere to run.
+global_asm!(
+    ".pushsection .rodata.suspend_trampoline, \"a\"",
+    ".balign 16",
+    ".code16",
+    "suspend_trampoline:",
+    "    cli",
+    "    cld",
+    "    mov ax, cs",
+    "    mov ds, ax",
+    "    lgdt [{gdtr}]",
+    "    mov eax, dword ptr [{cr4}]",
+    "    mov cr4, eax",
+    "    mov eax, dword ptr [{cr3}]",
+    "    mov cr3, eax",
+    "    mov ecx, {efer}",
+    "    rdmsr",
+    // Long mode and no-execute, which the kernel's page tables use.
+    "    or eax, (1 << 8) | (1 << 11)",
+    "    wrmsr",
+    "    mov eax, cr0",
+    "    or eax, (1 << 31) | 1",
+    "    mov cr0, eax",
+    // A far jump with a 32-bit offset, which only assembles as such with
+    // the segment spelled out.
+    "    jmp fword ptr ds:[{long_mode}]",
+    ".code64",
+    "suspend_trampoline_long:",
+    "    mov eax, {data_selector}",
+    "    mov ds, eax",
+    "    mov es, eax",
+    "    mov ss, eax",
+    "    jmp qword ptr [rip + suspend_trampoline + {entry}]",
+    ".org suspend_trampoline + {data}",
+    "    .space {data_size}",
+    "suspend_trampoline_end:",
+    ".popsection",
+    gdtr = const DATA_OFFSET + offset_of!(TrampolineData, gdt_limit),
+    cr4 = const DATA_OFFSET + offset_of!(TrampolineData, cr4),
+    cr3 = const DATA_OFFSET + offset_of!(TrampolineData, cr3),
+    efer = const EFER,
+    long_mode = const DATA_OFFSET + offset_of!(TrampolineData, long_mode),
+    data_selector = const TRAMPOLINE_DATA_SELECTOR,
+    entry = const DATA_OFFSET + offset_of!(TrampolineData, entry),
+    data = const DATA_OFFSET,
+    data_size = const size_of::<TrampolineData>(),
+);
+
+// Entered from the trampoline in long mode on its page table, with no
+// usable stack. Restores the control registers and the stack saved by
+// `sleep` and returns from it with 1.
+global_asm!(
+    ".global suspend_resume",
+    "suspend_resume:",
+    "    lea rsi, [rip + {context}]",
+    "    mov ecx, {efer}",
+    "    mov eax, [rsi + {context_efer}]",
+    "    mov edx, [rsi + {context_efer} + 4]",
+    "    wrmsr",
+    "    mov rax, [rsi + {context_cr4}]",
+    "    mov cr4, rax",
+    "    mov rax, [rsi + {context_cr3}]",
+    "    mov cr3, rax",
+    "    mov rax, [rsi + {context_cr0}]",
+    "    mov cr0, rax",
+    "    mov rsp, [rsi + {context_rsp}]",
+    "    sub rsp, 8",
+    "    call {restore_cpu}",
+    "    add rsp, 8",
+    "    mov eax, 1",
+    "    pop r15",
+    "    pop r14",
+    "    pop r13",
+    "    pop r12",
+    "    pop rbx",
+    "    pop rbp",
+    "    ret",
+    context = sym CONTEXT,
+    efer = const EFER,
+    context_efer = const offset_of!(CpuContext, efer),
+    context_cr4 = const offset_of!(CpuContext, cr4),
+    context_cr3 = const offset_of!(CpuContext, cr3),
+    context_cr0 = const offset_of!(CpuContext, cr0),
+    context_rsp = const offset_of!(CpuContext, rsp),
+    restore_cpu = sym restore_cpu,
+);
+
+/// Sets up the trampoline the machine resumes at.
+///
+/// The low memory for it is allocated on the first call. Later calls only
+/// refresh the page table, as the kernel's may have changed since.
+///
+/// # Returns
+///
+/// The physical address to write to the FACS as the waking vector, or
+/// `None` if there is no free memory below 1 MiB.
+pub fn prepare() -> Option<u32> {
+    let mode = PagingMode::current();
+    let frames = usize::from(mode.levels());
+    let base = (*LOW_MEMORY.call_once(|| {
+        mm::with_frames(|allocator| {
+            allocator.allocate_below(frame::order_for(frames as u64), LOW_MEMORY_END)
+        })
+        .map(PhysAddr::as_u64)
+    }))?;
+
+    unsafe {
+        let start = addr_of!(suspend_trampoline);
+        let len = addr_of!(suspend_trampoline_end) as usize - start as usize;
+        let long_mode = addr_of!(suspend_trampoline_long) as usize - start as usize;
+        let low = mm::phys_to_virt(PhysAddr::new(base)).as_mut_ptr::<u8>();
+        core::ptr::copy_nonoverlapping(start, low, len);
+
+        // The root table maps the kernel half like the kernel's own; its
+        // first entry starts a chain down to one 2 MiB page at address 0,
+        // which holds the trampoline.
+        let table = |index: usize| {
+            let address = base + (1 + index as u64) * frame::FRAME_SIZE;
+            (
+                address,
+                &mut *mm::phys_to_virt(PhysAddr::new(address)).as_mut_ptr::<PageTable>(),
+            )
+        };
+        let (root_address, root) = table(0);
+        let kernel_root = mm::paging::root_table(mm::phys_offset());
+        root.zero();
+        for index in 256..512 {
+            root[index] = kernel_root[index].clone();
+        }
+        let mut parent = root;
+        for level in 1..frames - 1 {
+            let (address, child) = table(level);
+            child.zero();
+            parent[0].set_addr(
+                PhysAddr::new(address),
+                PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+            );
+            parent = child;
+        }
+        parent[0].set_addr(
+            PhysAddr::zero(),
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::HUGE_PAGE,
+        );
+
+        let mut cr4 = Cr4Flags::PHYSICAL_ADDRESS_EXTENSION;
+        if mode == PagingMode::FiveLevel {
+            cr4 |= Cr4Flags::L5_PAGING;
+        }
+        let data = TrampolineData {
+            gdt: [0, TRAMPOLINE_CODE, TRAMPOLINE_DATA],
+            _pad: 0,
+            gdt_limit: (size_of::<[u64; 3]>() - 1) as u16,
+            gdt_base: (base as usize + DATA_OFFSET) as u32,
+            cr3: root_address as u32,
+            cr4: cr4.bits() as u32,
+            long_mode: (base as usize + long_mode) as u32,
+            long_mode_selector: TRAMPOLINE_CODE_SELECTOR,
+            _pad2: 0,
+            entry: suspend_resume as *const () as u64,
+        };
+        low.add(DATA_OFFSET).cast::<TrampolineData>().write(data);
+    }
+    Some(base as u32)
+}
+
+/// Saves the CPU state and calls `enter`, which is to put the machine to
+/// sleep.
+///
+/// [`prepare`] must have been called and its waking vector handed to the
+/// firmware.
+///
+/// # Returns
+///
+/// `true` once the machine has woken up and the CPU state is restored, or
+/// `false` if `enter` returned, i.e. the machine did not go to sleep.
+///
+/// # Safety
+///
+/// Interrupts must be disabled, and stay so until the devices that
+/// interrupt have been restored after wakeup.
+pub unsafe fn sleep(enter: extern "C" fn()) -> bool {
+    let mut fpu = FpuState::new();
+    fpu.save();
+
+    let context = &mut *CONTEXT.0.get();
+    context.cr0 = Cr0::read_raw();
+    let (root, flags) = Cr3::read_raw();
+    context.cr3 = root.start_address().as_u64() | u64::from(flags);
+    context.cr4 = Cr4::read_raw();
+    context.efer = Msr::new(EFER).read();
+    context.gdtr = sgdt();
+    context.idtr = sidt();
+    context.segments = [
+        CS::get_reg().0,
+        SS::get_reg().0,
+        DS::get_reg().0,
+        ES::get_reg().0,
+        FS::get_reg().0,
+        GS::get_reg().0,
+    ];
+    asm!("str {0:x}", out(reg) context.tr, options(nomem, nostack, preserves_flags));
+    for (value, msr) in context.msrs.iter_mut().zip(MSRS) {
+        *value = Msr::new(msr).read();
+    }
+
+    let resumed = save_and_call(addr_of_mut!(context.rsp), enter);
+    fpu.restore();
+    resumed
+}
+
+/// Saves the callee-saved registers and the stack pointer to `*rsp` and
+/// calls `enter`.
+///
+/// # Returns
+///
+/// `false` when `enter` returns; the resume path returns `true` from here
+/// on wakeup.
+#[unsafe(naked)]
+unsafe extern "C" fn save_and_call(rsp: *mut u64, enter: extern "C" fn()) -> bool {
+    naked_asm!(
+        "push rbp",
+        "push rbx",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov [rdi], rsp",
+        "sub rsp, 8",
+        "call rsi",
+        "add rsp, 8",
+        "xor eax, eax",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbx",
+        "pop rbp",
+        "ret",
+    );
+}
+
+/// Restores the CPU state [`sleep`] saved, other than what the resume path
+/// restored before it had a stack.
+extern "C" fn restore_cpu() {
+    unsafe {
+        let context = &*CONTEXT.0.get();
+        lgdt(&context.gdtr);
+        let [cs, ss, ds, es, fs, gs] = context.segments.map(SegmentSelector);
+        CS::set_reg(cs);
+        SS::set_reg(ss);
+        DS::set_reg(ds);
+        ES::set_reg(es);
+        FS::set_reg(fs);
+        GS::set_reg(gs);
+
+        // The TSS descriptor was marked busy when the task register was
+        // loaded at boot, and loading it again would fault.
+        let tss = SegmentSelector(context.tr);
+        let access = (context.gdtr.base + u64::from(tss.index()) * 8 + 5u64).as_mut_ptr::<u8>();
+        access.write_volatile(access.read_volatile() & !TSS_BUSY);
+        load_tss(tss);
+
+        lidt(&context.idtr);
+        for (value, msr) in context.msrs.iter().zip(MSRS) {
+            Msr::new(msr).write(*value);
+        }
+    }
+}
@@ -0,0 +1,25 @@
+//! # x86_64 Counter
+//!
+//! The free-running counter of the architecture interface, which on x86_64
+//! is the [TSC](super::tsc). Its frequency is only known once the TSC is
+//! calibrated.
+
+use core::time::Duration;
+
+use super::tsc;
+
+/// Returns the current counter value.
+pub fn ticks() -> u64 {
+    tsc::read()
+}
+
+/// Returns the counter frequency in Hz, or 0 before calibration.
+pub fn frequency() -> u64 {
+    tsc::frequency()
+}
+
+/// Converts a number of counter ticks to a duration, zero before
+/// calibration.
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    tsc::cycles_to_duration(ticks)
+}
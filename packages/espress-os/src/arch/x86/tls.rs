@@ -0,0 +1,119 @@
+//! # Thread-Local Storage
+//!
+//! On x86_64 the thread pointer is the `FS` segment base. Every thread has
+//! its own, saved and restored by [`switch_to`](super::context::switch_to):
+//! user threads set it with the `set_tls` system call, kernel threads get a
+//! [`TlsBlock`] so `#[thread_local]` statics in the kernel work.
+//!
+//! ## Kernel TLS Layout
+//!
+//! `linker.ld` collects the kernel's thread-locals into `.tdata`
+//! (initialized) and `.tbss` (zeroed), which together form the TLS
+//! template. The kernel is statically linked, so accesses use the
+//! local-exec model (variant II): the thread's copy of the template ends
+//! at the thread pointer, which in turn points at itself.
+//!
+//! ```text
+//! tp - size | .tdata copy, then zeroed .tbss
+//! tp        | tp (self pointer read by `mov reg, fs:0`)
+//! ```
+//!
+//! `size` is the template size rounded up to its alignment, which the
+//! linker script fixes at [`TLS_ALIGN`]; thread-locals must not need more.
+//!
+//! User threads do not get a kernel block: their `FS` base belongs to the
+//! program, so kernel code reachable from system calls and interrupts must
+//! not use `#[thread_local]`.
+
+use alloc::alloc::{alloc_zeroed, dealloc, handle_alloc_error};
+use core::alloc::Layout;
+use core::ptr::addr_of;
+use x86_64::registers::model_specific::FsBase;
+use x86_64::VirtAddr;
+
+extern "C" {
+    static __tdata_start: u8;
+    static __tdata_end: u8;
+    static __tbss_end: u8;
+}
+
+/// Alignment of the kernel TLS template (see `.tdata` in `linker.ld`).
+pub const TLS_ALIGN: usize = 64;
+
+/// Returns the `FS` base of the running thread.
+pub fn fs_base() -> u64 {
+    FsBase::read().as_u64()
+}
+
+/// Sets the `FS` base of the running thread.
+///
+/// # Safety
+///
+/// `base` must be canonical. If the running thread uses `#[thread_local]`,
+/// it must be the thread pointer of a live [`TlsBlock`].
+pub unsafe fn set_fs_base(base: u64) {
+    FsBase::write(VirtAddr::new_unsafe(base));
+}
+
+/// A kernel thread's copy of the TLS template.
+pub struct TlsBlock {
+    /// Start of the allocation
+    memory: *mut u8,
+    /// Layout of the allocation
+    layout: Layout,
+    /// Address of the self pointer, loaded into `FS` base
+    thread_pointer: u64,
+}
+
+// The block is only accessed through `FS` by the thread that owns it.
+unsafe impl Send for TlsBlock {}
+
+impl TlsBlock {
+    /// Allocates a block initialized from the template.
+    pub fn new() -> TlsBlock {
+        let (data, size) = template();
+        let layout = Layout::from_size_align(size + 8, TLS_ALIGN).expect("TLS template too large");
+        let memory = unsafe { alloc_zeroed(layout) };
+        if memory.is_null() {
+            handle_alloc_error(layout);
+        }
+        unsafe {
+            core::ptr::copy_nonoverlapping(data.as_ptr(), memory, data.len());
+            let thread_pointer = memory.add(size);
+            thread_pointer.cast::<u64>().write(thread_pointer as u64);
+            TlsBlock {
+                memory,
+                layout,
+                thread_pointer: thread_pointer as u64,
+            }
+        }
+    }
+
+    /// Value to load into `FS` base for this block.
+    pub fn thread_pointer(&self) -> u64 {
+        self.thread_pointer
+    }
+}
+
+impl Default for TlsBlock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for TlsBlock {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.memory, self.layout) };
+    }
+}
+
+/// Returns the initialized part of the template and the size of a block
+/// below the thread pointer.
+fn template() -> (&'static [u8], usize) {
+    let start = addr_of!(__tdata_start) as usize;
+    let data_end = addr_of!(__tdata_end) as usize;
+    // An empty `.tbss` may be placed before the end of `.tdata`.
+    let end = (addr_of!(__tbss_end) as usize).max(data_end);
+    let data = unsafe { core::slice::from_raw_parts(addr_of!(__tdata_start), data_end - start) };
+    (data, (end - start).next_multiple_of(TLS_ALIGN))
+}
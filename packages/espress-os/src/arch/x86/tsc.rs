@@ -0,0 +1,61 @@
+//! # Time Stamp Counter
+//!
+//! The TSC counts CPU cycles and is read with a single instruction, which
+//! makes it the clock of choice for fine-grained measurements such as the
+//! scheduler's per-thread CPU time. Its rate is not architecturally
+//! defined, so [`calibrate`] measures it against the PIT once at boot.
+//!
+//! The conversion assumes a constant-rate ("invariant") TSC, which every
+//! CPU of the last decade and every common emulator provides.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use super::pit;
+
+/// PIT cycles measured during calibration (about 10 ms).
+const CALIBRATION_PIT_CYCLES: u16 = 11_932;
+
+/// Measured TSC frequency in Hz; zero until calibrated.
+static FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+/// Reads the time stamp counter.
+pub fn read() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// Measures the TSC frequency against the PIT.
+///
+/// Busy-waits for about 10 ms; interrupts may be disabled.
+///
+/// # Returns
+///
+/// The frequency in Hz.
+pub fn calibrate() -> u64 {
+    let start = read();
+    pit::busy_wait(CALIBRATION_PIT_CYCLES);
+    let cycles = read() - start;
+    let hz = cycles * u64::from(pit::BASE_FREQUENCY) / u64::from(CALIBRATION_PIT_CYCLES);
+    FREQUENCY.store(hz, Ordering::Relaxed);
+    hz
+}
+
+/// The TSC frequency in Hz, or zero before [`calibrate`].
+pub fn frequency() -> u64 {
+    FREQUENCY.load(Ordering::Relaxed)
+}
+
+/// Converts a number of TSC cycles to time.
+///
+/// # Returns
+///
+/// [`Duration::ZERO`] before [`calibrate`].
+pub fn cycles_to_duration(cycles: u64) -> Duration {
+    match frequency() {
+        0 => Duration::ZERO,
+        hz => {
+            let nanos = u128::from(cycles) * 1_000_000_000 / u128::from(hz);
+            Duration::from_nanos(nanos.min(u128::from(u64::MAX)) as u64)
+        }
+    }
+}
@@ -0,0 +1,65 @@
+//! # Stack Backtraces
+//!
+//! Walks the chain of saved frame pointers to list the return addresses of
+//! the current call stack. The kernel is built with
+//! `-C force-frame-pointers=yes` (see `.cargo/config.toml`), so every Rust
+//! function pushes `rbp` and the chain is complete up to the entry point of
+//! the current stack, where `rbp` is zero.
+
+use core::arch::asm;
+
+use crate::println;
+
+/// Upper bound on the number of frames printed.
+const MAX_FRAMES: usize = 32;
+
+/// A saved frame record: the caller's `rbp` followed by the return address.
+#[repr(C)]
+struct Frame {
+    /// Frame pointer of the caller
+    rbp: *const Frame,
+    /// Address the current function returns to
+    return_address: u64,
+}
+
+/// Prints the return addresses of the current call stack, innermost first.
+///
+/// Addresses are printed as linked, so the trace can be symbolized against
+/// the kernel ELF; the runtime address is the printed one plus the
+/// [load offset](crate::reloc::load_offset) and, with KASLR, the
+/// [slide](crate::kaslr::slide), both printed alongside.
+pub fn print_backtrace() {
+    let rbp: *const Frame;
+    unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+
+    let load_offset = crate::reloc::load_offset();
+    let slide = crate::kaslr::slide();
+    println!(
+        "backtrace (load offset {:#x}, kaslr slide {:#x}):",
+        load_offset, slide
+    );
+    let slide = load_offset.wrapping_add(slide);
+
+    let mut frame = rbp;
+    for depth in 0..MAX_FRAMES {
+        if !is_valid_frame(frame) {
+            return;
+        }
+        let record = unsafe { &*frame };
+        if record.return_address == 0 {
+            return;
+        }
+        println!("  #{:<2} {:#018x}", depth, record.return_address - slide);
+        frame = record.rbp;
+    }
+    println!("  ...");
+}
+
+/// Checks that `frame` can plausibly be dereferenced.
+///
+/// Kernel stacks always live in the higher half, and frame records are
+/// 16-byte aligned by the calling convention.
+fn is_valid_frame(frame: *const Frame) -> bool {
+    let addr = frame as u64;
+    addr >= crate::mm::PHYS_MAP_BASE && addr.is_multiple_of(16)
+}
@@ -0,0 +1,300 @@
+//! # Block Cache
+//!
+//! A [`BlockCache`] sits in front of a slow device and keeps its most
+//! recently used blocks in memory. Reads are served from the cache when
+//! possible; runs of missing blocks are fetched from the device with one
+//! request. Writes only update the cache and mark the blocks dirty.
+//!
+//! Dirty blocks reach the device when they are evicted, when the
+//! `writeback` thread wakes up every [`WRITEBACK_INTERVAL`], or when the
+//! cache is flushed by [`sync`](super::sync). A write that fails during
+//! writeback leaves the block dirty for the next attempt.
+//!
+//! The cache is registered in place of its device and carries the same
+//! name, so partitions, `/dev`, and filesystems all share it. Device I/O
+//! happens with the cache locked, which also serializes requests to the
+//! device.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::time::Duration;
+use spin::Mutex;
+
+use super::{check_request, BlockDevice, BlockError};
+use crate::{println, scheduler, timer};
+
+/// Blocks a cache holds unless created with another capacity.
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+/// Time between two runs of the `writeback` thread.
+pub const WRITEBACK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Every cache created, for the `writeback` thread.
+static CACHES: Mutex<Vec<Weak<BlockCache>>> = Mutex::new(Vec::new());
+
+/// Counters of a cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Blocks read from the cache
+    pub hits: u64,
+    /// Blocks read from the device
+    pub misses: u64,
+    /// Dirty blocks written to the device
+    pub writebacks: u64,
+    /// Blocks dropped to make room
+    pub evictions: u64,
+}
+
+/// An LRU write-back cache in front of a block device.
+pub struct BlockCache {
+    /// The cached device
+    device: Arc<dyn BlockDevice>,
+    /// Maximum number of blocks held
+    capacity: usize,
+    /// Cached blocks
+    state: Mutex<State>,
+}
+
+/// Contents of a cache.
+#[derive(Default)]
+struct State {
+    /// Cached blocks by block number
+    blocks: BTreeMap<u64, Slot>,
+    /// Block numbers by last use, oldest first
+    lru: BTreeMap<u64, u64>,
+    /// Use counter, the key of the next entry in `lru`
+    clock: u64,
+    /// Counters
+    stats: CacheStats,
+}
+
+/// A cached block.
+struct Slot {
+    /// Block contents
+    data: Box<[u8]>,
+    /// Changed since it was read or last written back
+    dirty: bool,
+    /// Key in [`State::lru`]
+    used: u64,
+}
+
+impl State {
+    /// Marks `block` as just used.
+    fn touch(&mut self, block: u64) {
+        let used = self.clock;
+        self.clock += 1;
+        if let Some(slot) = self.blocks.get_mut(&block) {
+            self.lru.remove(&slot.used);
+            slot.used = used;
+            self.lru.insert(used, block);
+        }
+    }
+
+    /// Adds `block` as the most recently used one.
+    fn insert(&mut self, block: u64, data: Box<[u8]>, dirty: bool) {
+        let used = self.clock;
+        self.clock += 1;
+        if let Some(old) = self.blocks.insert(block, Slot { data, dirty, used }) {
+            self.lru.remove(&old.used);
+        }
+        self.lru.insert(used, block);
+    }
+}
+
+impl BlockCache {
+    /// Puts a cache of [`DEFAULT_CAPACITY`] blocks in front of `device`.
+    pub fn new(device: Arc<dyn BlockDevice>) -> Arc<Self> {
+        Self::with_capacity(device, DEFAULT_CAPACITY)
+    }
+
+    /// Puts a cache of `capacity` blocks in front of `device`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn with_capacity(device: Arc<dyn BlockDevice>, capacity: usize) -> Arc<Self> {
+        assert!(capacity > 0, "block cache without capacity");
+        let cache = Arc::new(BlockCache {
+            device,
+            capacity,
+            state: Mutex::new(State::default()),
+        });
+        let mut caches = CACHES.lock();
+        caches.retain(|cache| cache.strong_count() > 0);
+        caches.push(Arc::downgrade(&cache));
+        cache
+    }
+
+    /// The cached device.
+    pub fn device(&self) -> &Arc<dyn BlockDevice> {
+        &self.device
+    }
+
+    /// Returns the counters.
+    pub fn stats(&self) -> CacheStats {
+        self.state.lock().stats
+    }
+
+    /// Number of dirty blocks.
+    pub fn dirty_blocks(&self) -> usize {
+        self.state
+            .lock()
+            .blocks
+            .values()
+            .filter(|slot| slot.dirty)
+            .count()
+    }
+
+    /// Writes every dirty block to the device, in block order, without
+    /// waiting for the device to make them stable.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error of the device; the remaining blocks are
+    /// still written, and failed ones stay dirty.
+    pub fn write_back(&self) -> Result<(), BlockError> {
+        let mut state = self.state.lock();
+        let state = &mut *state;
+        let mut result = Ok(());
+        let mut written = 0;
+        for (&block, slot) in state.blocks.iter_mut().filter(|(_, slot)| slot.dirty) {
+            match self.device.write_blocks(block, &slot.data) {
+                Ok(()) => {
+                    slot.dirty = false;
+                    written += 1;
+                }
+                Err(err) => result = result.and(Err(err)),
+            }
+        }
+        state.stats.writebacks += written;
+        result
+    }
+
+    /// Drops the least recently used blocks until there is room for one
+    /// more, writing dirty ones back first.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the device if a dirty block cannot be written;
+    /// that block stays cached.
+    fn make_room(&self, state: &mut State) -> Result<(), BlockError> {
+        while state.blocks.len() >= self.capacity {
+            let (_, block) = state.lru.pop_first().expect("LRU list out of sync");
+            let slot = state.blocks.remove(&block).expect("LRU list out of sync");
+            if slot.dirty {
+                if let Err(err) = self.device.write_blocks(block, &slot.data) {
+                    state.insert(block, slot.data, true);
+                    return Err(err);
+                }
+                state.stats.writebacks += 1;
+            }
+            state.stats.evictions += 1;
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for BlockCache {
+    fn name(&self) -> &str {
+        self.device.name()
+    }
+
+    fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.device.block_count()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.device.is_read_only()
+    }
+
+    fn read_blocks(&self, start: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        check_request(self, start, buf.len())?;
+        let block_size = self.block_size();
+        let count = buf.len() / block_size;
+        let mut state = self.state.lock();
+        let mut index = 0;
+        while index < count {
+            let block = start + index as u64;
+            if let Some(slot) = state.blocks.get(&block) {
+                buf[index * block_size..][..block_size].copy_from_slice(&slot.data);
+                state.touch(block);
+                state.stats.hits += 1;
+                index += 1;
+                continue;
+            }
+
+            // Fetch the whole run of missing blocks at once.
+            let run = (index..count)
+                .take_while(|&i| !state.blocks.contains_key(&(start + i as u64)))
+                .count();
+            let bytes = &mut buf[index * block_size..][..run * block_size];
+            self.device.read_blocks(block, bytes)?;
+            state.stats.misses += run as u64;
+            for (i, data) in bytes.chunks(block_size).enumerate() {
+                // The data is read either way; it just is not kept.
+                if self.make_room(&mut state).is_err() {
+                    break;
+                }
+                state.insert(block + i as u64, data.into(), false);
+            }
+            index += run;
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, start: u64, buf: &[u8]) -> Result<(), BlockError> {
+        check_request(self, start, buf.len())?;
+        if self.is_read_only() {
+            return Err(BlockError::ReadOnly);
+        }
+        let mut state = self.state.lock();
+        for (i, data) in buf.chunks(self.block_size()).enumerate() {
+            let block = start + i as u64;
+            if let Some(slot) = state.blocks.get_mut(&block) {
+                slot.data.copy_from_slice(data);
+                slot.dirty = true;
+                state.touch(block);
+            } else {
+                self.make_room(&mut state)?;
+                state.insert(block, data.into(), true);
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        self.write_back()?;
+        self.device.flush()
+    }
+}
+
+/// Starts the `writeback` thread.
+///
+/// Must be called after the scheduler is initialized.
+///
+/// # Panics
+///
+/// Panics if the thread cannot be started.
+pub fn init() {
+    scheduler::spawn_named("writeback", run).expect("failed to start the writeback thread");
+}
+
+/// Body of the `writeback` thread: periodically writes back the dirty
+/// blocks of every cache.
+fn run() {
+    loop {
+        timer::sleep(WRITEBACK_INTERVAL);
+        let caches: Vec<_> = CACHES.lock().iter().filter_map(Weak::upgrade).collect();
+        for cache in caches {
+            if let Err(err) = cache.write_back() {
+                println!("block: writeback to {} failed: {:?}", cache.name(), err);
+            }
+        }
+    }
+}
@@ -0,0 +1,307 @@
+//! # Block Devices
+//!
+//! Storage that is read and written in fixed-size blocks: disks, the
+//! partitions on them, and RAM disks. Drivers implement [`BlockDevice`] and
+//! [`register`] their devices; filesystems look them up by name with
+//! [`find`].
+//!
+//! [`scan_partitions`] reads the partition table (MBR or GPT, see
+//! [`partition`]) of every whole device registered since the last scan and
+//! registers each partition as a device of its own, named after its disk:
+//! `ram0` holds `ram0p1`, `ram0p2`, and so on.
+//!
+//! Drivers of slow devices register them behind a [`BlockCache`], which
+//! buffers writes until [`sync`] or the periodic writeback.
+//!
+//! The registry lock is only taken in thread context and never held while
+//! a device does I/O.
+
+pub mod cache;
+pub mod partition;
+pub mod ramdisk;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::println;
+
+pub use cache::BlockCache;
+pub use partition::Partition;
+pub use ramdisk::RamDisk;
+
+/// Size of a sector in partition tables and of most disks' blocks.
+pub const SECTOR_SIZE: usize = 512;
+
+/// Registered devices, in registration order.
+static DEVICES: Mutex<Vec<Entry>> = Mutex::new(Vec::new());
+
+/// Errors that can occur during block I/O.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    /// The request reaches past the end of the device
+    OutOfRange,
+    /// The buffer length is not a multiple of the block size
+    BadBuffer,
+    /// The device does not allow writing
+    ReadOnly,
+    /// The device reported an error
+    Io,
+    /// The partition table is malformed
+    BadPartitionTable,
+}
+
+/// A device storing data in fixed-size blocks.
+pub trait BlockDevice: Send + Sync {
+    /// Unique name, e.g. `"ram0"` or `"ram0p1"`.
+    fn name(&self) -> &str;
+
+    /// Size of a block in bytes, a multiple of [`SECTOR_SIZE`].
+    fn block_size(&self) -> usize;
+
+    /// Number of blocks.
+    fn block_count(&self) -> u64;
+
+    /// Returns `true` if the device rejects writes.
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// Reads consecutive blocks starting at `start` into `buf`, whose length
+    /// must be a multiple of the block size.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`BlockError`] if the range or buffer is invalid or the
+    /// device fails.
+    fn read_blocks(&self, start: u64, buf: &mut [u8]) -> Result<(), BlockError>;
+
+    /// Writes `buf` to consecutive blocks starting at `start`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`BlockError`] if the range or buffer is invalid, the
+    /// device is read-only, or it fails.
+    fn write_blocks(&self, start: u64, buf: &[u8]) -> Result<(), BlockError>;
+
+    /// Waits until every completed write has reached stable storage.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BlockError::Io`] if the device fails.
+    fn flush(&self) -> Result<(), BlockError> {
+        Ok(())
+    }
+
+    /// Size of the device in bytes.
+    fn size(&self) -> u64 {
+        self.block_count() * self.block_size() as u64
+    }
+}
+
+/// Checks a request against the geometry of `device`.
+///
+/// # Errors
+///
+/// Returns [`BlockError::BadBuffer`] or [`BlockError::OutOfRange`].
+pub fn check_request(device: &dyn BlockDevice, start: u64, len: usize) -> Result<(), BlockError> {
+    let block_size = device.block_size();
+    if !len.is_multiple_of(block_size) {
+        return Err(BlockError::BadBuffer);
+    }
+    let blocks = (len / block_size) as u64;
+    match start.checked_add(blocks) {
+        Some(end) if end <= device.block_count() => Ok(()),
+        _ => Err(BlockError::OutOfRange),
+    }
+}
+
+/// Reads `buf.len()` bytes at byte `offset` of `device`, which need not be
+/// block-aligned.
+///
+/// # Errors
+///
+/// Returns [`BlockError::OutOfRange`] if the range reaches past the end of
+/// the device, and any error of the device.
+pub fn read_bytes(device: &dyn BlockDevice, offset: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+    let block_size = device.block_size() as u64;
+    let mut offset = offset;
+    let mut done = 0;
+    let mut bounce = Vec::new();
+    while done < buf.len() {
+        let block = offset / block_size;
+        let within = (offset % block_size) as usize;
+        let remaining = buf.len() - done;
+        if within == 0 && remaining as u64 >= block_size {
+            // Whole blocks go straight into the caller's buffer.
+            let len = remaining - remaining % block_size as usize;
+            device.read_blocks(block, &mut buf[done..done + len])?;
+            done += len;
+            offset += len as u64;
+            continue;
+        }
+        bounce.resize(block_size as usize, 0);
+        device.read_blocks(block, &mut bounce)?;
+        let len = remaining.min(block_size as usize - within);
+        buf[done..done + len].copy_from_slice(&bounce[within..within + len]);
+        done += len;
+        offset += len as u64;
+    }
+    Ok(())
+}
+
+/// Writes `buf` at byte `offset` of `device`, reading back the partial
+/// blocks at either end first.
+///
+/// # Errors
+///
+/// Returns [`BlockError::OutOfRange`] if the range reaches past the end of
+/// the device, and any error of the device.
+pub fn write_bytes(device: &dyn BlockDevice, offset: u64, buf: &[u8]) -> Result<(), BlockError> {
+    let block_size = device.block_size() as u64;
+    let mut offset = offset;
+    let mut done = 0;
+    let mut bounce = Vec::new();
+    while done < buf.len() {
+        let block = offset / block_size;
+        let within = (offset % block_size) as usize;
+        let remaining = buf.len() - done;
+        if within == 0 && remaining as u64 >= block_size {
+            let len = remaining - remaining % block_size as usize;
+            device.write_blocks(block, &buf[done..done + len])?;
+            done += len;
+            offset += len as u64;
+            continue;
+        }
+        bounce.resize(block_size as usize, 0);
+        device.read_blocks(block, &mut bounce)?;
+        let len = remaining.min(block_size as usize - within);
+        bounce[within..within + len].copy_from_slice(&buf[done..done + len]);
+        device.write_blocks(block, &bounce)?;
+        done += len;
+        offset += len as u64;
+    }
+    Ok(())
+}
+
+/// A registered device.
+struct Entry {
+    /// The device
+    device: Arc<dyn BlockDevice>,
+    /// Its partition table has been read, or it is a partition itself
+    scanned: bool,
+}
+
+/// Makes a whole device, such as a disk, available by name.
+///
+/// Its partitions show up after the next [`scan_partitions`].
+///
+/// # Panics
+///
+/// Panics if a device with the same name is already registered.
+pub fn register(device: Arc<dyn BlockDevice>) {
+    add(device, false);
+}
+
+/// Returns every registered device.
+pub fn devices() -> Vec<Arc<dyn BlockDevice>> {
+    DEVICES
+        .lock()
+        .iter()
+        .map(|entry| entry.device.clone())
+        .collect()
+}
+
+/// Returns the registered device called `name`.
+pub fn find(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    DEVICES
+        .lock()
+        .iter()
+        .find(|entry| entry.device.name() == name)
+        .map(|entry| entry.device.clone())
+}
+
+/// Reads the partition tables of all whole devices registered since the
+/// last scan and registers their partitions.
+///
+/// Devices without a partition table are left as they are; a malformed
+/// table is reported and skipped.
+///
+/// # Returns
+///
+/// The number of partitions registered.
+pub fn scan_partitions() -> usize {
+    let pending: Vec<_> = DEVICES
+        .lock()
+        .iter_mut()
+        .filter(|entry| !entry.scanned)
+        .map(|entry| {
+            entry.scanned = true;
+            entry.device.clone()
+        })
+        .collect();
+
+    let mut found = 0;
+    for disk in pending {
+        match partition::read_table(&*disk) {
+            Ok(entries) => {
+                for entry in entries {
+                    let partition = Partition::new(disk.clone(), &entry);
+                    println!(
+                        "block: {} ({}, {} blocks at {})",
+                        partition.name(),
+                        entry.kind,
+                        entry.block_count,
+                        entry.first_block
+                    );
+                    add(Arc::new(partition), true);
+                    found += 1;
+                }
+            }
+            Err(err) => println!("block: {}: {:?}", disk.name(), err),
+        }
+    }
+    found
+}
+
+/// Writes buffered data of every registered device to stable storage.
+///
+/// # Errors
+///
+/// Returns the first error encountered; the remaining devices are still
+/// flushed.
+pub fn sync() -> Result<(), BlockError> {
+    let mut result = Ok(());
+    for device in devices() {
+        if let Err(err) = device.flush() {
+            result = result.and(Err(err));
+        }
+    }
+    result
+}
+
+/// Prints every registered device with its size.
+pub fn print_devices() {
+    for device in devices() {
+        println!(
+            "  {:<12} {:>10} KiB  ({} x {} bytes)",
+            device.name(),
+            device.size() / 1024,
+            device.block_count(),
+            device.block_size()
+        );
+    }
+}
+
+/// Adds `device` to the registry.
+fn add(device: Arc<dyn BlockDevice>, scanned: bool) {
+    let mut devices = DEVICES.lock();
+    assert!(
+        devices
+            .iter()
+            .all(|entry| entry.device.name() != device.name()),
+        "block device {} registered twice",
+        device.name()
+    );
+    devices.push(Entry { device, scanned });
+}
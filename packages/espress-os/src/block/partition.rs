@@ -0,0 +1,456 @@
+//! # Partition Tables
+//!
+//! Two partition table formats are understood:
+//!
+//! - **MBR**: four primary entries at the end of block 0. An extended
+//!   partition holds a chain of extended boot records, each describing one
+//!   logical partition; logical partitions are numbered from 5.
+//! - **GPT**: a header in block 1 (with a backup copy in the last block)
+//!   pointing to an array of entries, both protected by CRC32. A GPT disk
+//!   carries a protective MBR with a single entry of type `0xEE`.
+//!
+//! Block addresses in either table count blocks of the disk they are on.
+//! Each partition becomes a [`Partition`], a block device that translates
+//! its block numbers to those of the disk and keeps requests inside its
+//! bounds.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use super::{check_request, BlockDevice, BlockError, SECTOR_SIZE};
+
+/// Offset of the first MBR entry.
+const MBR_ENTRIES: usize = 446;
+/// Size of an MBR entry.
+const MBR_ENTRY_SIZE: usize = 16;
+/// Offset of the MBR boot signature `0x55 0xaa`.
+const MBR_SIGNATURE: usize = 510;
+
+/// MBR type of the protective entry of a GPT disk.
+const MBR_TYPE_GPT: u8 = 0xee;
+/// MBR types of extended partitions.
+const MBR_TYPES_EXTENDED: [u8; 3] = [0x05, 0x0f, 0x85];
+
+/// Most logical partitions followed in an extended partition, which guards
+/// against cyclic chains.
+const MAX_LOGICAL: u32 = 128;
+
+/// Signature at the start of a GPT header.
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+/// Smallest valid GPT header.
+const GPT_HEADER_MIN: usize = 92;
+/// Smallest valid GPT entry.
+const GPT_ENTRY_MIN: usize = 128;
+/// Most GPT entries read, far more than any real table has.
+const GPT_MAX_ENTRIES: usize = 4096;
+/// Offset of the UTF-16 name in a GPT entry.
+const GPT_NAME_OFFSET: usize = 56;
+/// Length of the name in a GPT entry, in UTF-16 code units.
+const GPT_NAME_LEN: usize = 36;
+
+/// A GUID as stored on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    /// Returns `true` for the all-zero GUID that marks an unused entry.
+    pub fn is_zero(&self) -> bool {
+        self.0 == [0; 16]
+    }
+}
+
+impl fmt::Display for Guid {
+    /// Formats the GUID in the usual notation, whose first three groups are
+    /// stored little-endian.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:02x}{:02x}{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}-",
+            b[3], b[2], b[1], b[0], b[5], b[4], b[7], b[6], b[8], b[9]
+        )?;
+        for byte in &b[10..] {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// What a partition table says a partition contains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionKind {
+    /// An MBR partition of the given type byte
+    Mbr(u8),
+    /// A GPT partition of the given type GUID
+    Gpt(Guid),
+}
+
+impl fmt::Display for PartitionKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PartitionKind::Mbr(kind) => write!(f, "MBR type {kind:#04x}"),
+            PartitionKind::Gpt(guid) => write!(f, "GPT type {guid}"),
+        }
+    }
+}
+
+/// A partition as described by a partition table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableEntry {
+    /// Partition number, from 1
+    pub number: u32,
+    /// First block on the disk
+    pub first_block: u64,
+    /// Number of blocks
+    pub block_count: u64,
+    /// Contents type
+    pub kind: PartitionKind,
+    /// Name from a GPT entry, empty for MBR partitions
+    pub label: String,
+}
+
+/// Reads the partition table of `disk`.
+///
+/// # Returns
+///
+/// The partitions in table order, or an empty list if the disk has no
+/// partition table.
+///
+/// # Errors
+///
+/// Returns [`BlockError::BadPartitionTable`] if the table is damaged or
+/// describes partitions outside the disk, and any error of the disk.
+pub fn read_table(disk: &dyn BlockDevice) -> Result<Vec<TableEntry>, BlockError> {
+    if disk.block_size() < SECTOR_SIZE || disk.block_count() == 0 {
+        return Ok(Vec::new());
+    }
+    let mbr = read(disk, 0, 1)?;
+    if mbr[MBR_SIGNATURE..MBR_SIGNATURE + 2] != [0x55, 0xaa] {
+        return Ok(Vec::new());
+    }
+
+    let primary: Vec<_> = (0..4).map(|i| MbrEntry::parse(&mbr, i)).collect();
+    if primary.iter().any(|entry| entry.kind == MBR_TYPE_GPT) {
+        return read_gpt(disk);
+    }
+
+    let mut entries = Vec::new();
+    for (i, entry) in primary.iter().enumerate() {
+        if entry.kind == 0 || entry.count == 0 {
+            continue;
+        }
+        if MBR_TYPES_EXTENDED.contains(&entry.kind) {
+            read_logical(disk, entry, &mut entries)?;
+        } else {
+            entries.push(TableEntry {
+                number: i as u32 + 1,
+                first_block: entry.start,
+                block_count: entry.count,
+                kind: PartitionKind::Mbr(entry.kind),
+                label: String::new(),
+            });
+        }
+    }
+    check_bounds(disk, &entries)?;
+    entries.sort_by_key(|entry| entry.number);
+    Ok(entries)
+}
+
+/// A partition of a disk, usable as a block device of its own.
+pub struct Partition {
+    /// Device name, e.g. `ram0p1`
+    name: String,
+    /// Disk holding the partition
+    disk: Arc<dyn BlockDevice>,
+    /// First block on the disk
+    first_block: u64,
+    /// Number of blocks
+    block_count: u64,
+    /// Contents type
+    kind: PartitionKind,
+}
+
+impl Partition {
+    /// Wraps the partition `entry` of `disk`.
+    pub fn new(disk: Arc<dyn BlockDevice>, entry: &TableEntry) -> Self {
+        Partition {
+            name: alloc::format!("{}p{}", disk.name(), entry.number),
+            disk,
+            first_block: entry.first_block,
+            block_count: entry.block_count,
+            kind: entry.kind,
+        }
+    }
+
+    /// Disk holding the partition.
+    pub fn disk(&self) -> &Arc<dyn BlockDevice> {
+        &self.disk
+    }
+
+    /// First block on the disk.
+    pub fn first_block(&self) -> u64 {
+        self.first_block
+    }
+
+    /// Contents type.
+    pub fn kind(&self) -> PartitionKind {
+        self.kind
+    }
+}
+
+impl BlockDevice for Partition {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        self.disk.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.block_count
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.disk.is_read_only()
+    }
+
+    fn read_blocks(&self, start: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        check_request(self, start, buf.len())?;
+        self.disk.read_blocks(self.first_block + start, buf)
+    }
+
+    fn write_blocks(&self, start: u64, buf: &[u8]) -> Result<(), BlockError> {
+        check_request(self, start, buf.len())?;
+        self.disk.write_blocks(self.first_block + start, buf)
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        self.disk.flush()
+    }
+}
+
+/// An entry of an MBR or extended boot record.
+struct MbrEntry {
+    /// Partition type, zero if unused
+    kind: u8,
+    /// First block, relative to the record's base
+    start: u64,
+    /// Number of blocks
+    count: u64,
+}
+
+impl MbrEntry {
+    /// Parses entry `index` of the boot record `block`.
+    fn parse(block: &[u8], index: usize) -> Self {
+        let entry = &block[MBR_ENTRIES + index * MBR_ENTRY_SIZE..][..MBR_ENTRY_SIZE];
+        MbrEntry {
+            kind: entry[4],
+            start: u64::from(read_u32(entry, 8)),
+            count: u64::from(read_u32(entry, 12)),
+        }
+    }
+}
+
+/// Follows the chain of extended boot records of the extended partition
+/// `extended` and appends its logical partitions to `entries`.
+///
+/// Each record's first entry is a logical partition relative to the record;
+/// its second entry links to the next record relative to the start of the
+/// extended partition.
+fn read_logical(
+    disk: &dyn BlockDevice,
+    extended: &MbrEntry,
+    entries: &mut Vec<TableEntry>,
+) -> Result<(), BlockError> {
+    let mut record = extended.start;
+    for number in 5..5 + MAX_LOGICAL {
+        if record >= disk.block_count() {
+            return Err(BlockError::BadPartitionTable);
+        }
+        let ebr = read(disk, record, 1)?;
+        if ebr[MBR_SIGNATURE..MBR_SIGNATURE + 2] != [0x55, 0xaa] {
+            return Err(BlockError::BadPartitionTable);
+        }
+        let logical = MbrEntry::parse(&ebr, 0);
+        if logical.kind != 0 && logical.count != 0 {
+            entries.push(TableEntry {
+                number,
+                first_block: record + logical.start,
+                block_count: logical.count,
+                kind: PartitionKind::Mbr(logical.kind),
+                label: String::new(),
+            });
+        }
+        let next = MbrEntry::parse(&ebr, 1);
+        if next.kind == 0 || next.start == 0 {
+            return Ok(());
+        }
+        record = extended.start + next.start;
+    }
+    Err(BlockError::BadPartitionTable)
+}
+
+/// Reads the GPT of `disk`, falling back to the backup header if the
+/// primary one is damaged.
+fn read_gpt(disk: &dyn BlockDevice) -> Result<Vec<TableEntry>, BlockError> {
+    let last = disk.block_count() - 1;
+    let header = match read_gpt_header(disk, 1)? {
+        Some(header) => header,
+        None => read_gpt_header(disk, last)?.ok_or(BlockError::BadPartitionTable)?,
+    };
+
+    let block_size = disk.block_size();
+    let table_len = header.entry_count * header.entry_size;
+    let blocks = table_len.div_ceil(block_size) as u64;
+    if header.entries_block + blocks > disk.block_count() {
+        return Err(BlockError::BadPartitionTable);
+    }
+    let table = read(disk, header.entries_block, blocks)?;
+    if crc32(&table[..table_len]) != header.entries_crc {
+        return Err(BlockError::BadPartitionTable);
+    }
+
+    let mut entries = Vec::new();
+    for (i, entry) in table[..table_len]
+        .chunks_exact(header.entry_size)
+        .enumerate()
+    {
+        let kind = guid_at(entry, 0);
+        if kind.is_zero() {
+            continue;
+        }
+        let first = read_u64(entry, 32);
+        let last = read_u64(entry, 40);
+        if last < first {
+            return Err(BlockError::BadPartitionTable);
+        }
+        let name: Vec<u16> = (0..GPT_NAME_LEN)
+            .map(|c| read_u16(entry, GPT_NAME_OFFSET + 2 * c))
+            .take_while(|&unit| unit != 0)
+            .collect();
+        entries.push(TableEntry {
+            number: i as u32 + 1,
+            first_block: first,
+            block_count: last - first + 1,
+            kind: PartitionKind::Gpt(kind),
+            label: String::from_utf16_lossy(&name),
+        });
+    }
+    check_bounds(disk, &entries)?;
+    Ok(entries)
+}
+
+/// The parts of a GPT header needed to find the entries.
+struct GptHeader {
+    /// First block of the entry array
+    entries_block: u64,
+    /// Number of entries
+    entry_count: usize,
+    /// Size of an entry in bytes
+    entry_size: usize,
+    /// CRC32 of the entry array
+    entries_crc: u32,
+}
+
+/// Reads and validates the GPT header in block `block`.
+///
+/// # Returns
+///
+/// `None` if the block holds no valid header.
+fn read_gpt_header(disk: &dyn BlockDevice, block: u64) -> Result<Option<GptHeader>, BlockError> {
+    let mut data = read(disk, block, 1)?;
+    if &data[..8] != GPT_SIGNATURE {
+        return Ok(None);
+    }
+    let size = read_u32(&data, 12) as usize;
+    if !(GPT_HEADER_MIN..=data.len()).contains(&size) {
+        return Ok(None);
+    }
+    let crc = read_u32(&data, 16);
+    data[16..20].fill(0);
+    if crc32(&data[..size]) != crc || read_u64(&data, 24) != block {
+        return Ok(None);
+    }
+
+    let entry_count = read_u32(&data, 80) as usize;
+    let entry_size = read_u32(&data, 84) as usize;
+    if entry_count > GPT_MAX_ENTRIES
+        || entry_size < GPT_ENTRY_MIN
+        || !entry_size.is_multiple_of(8)
+        || entry_size > disk.block_size()
+    {
+        return Ok(None);
+    }
+    Ok(Some(GptHeader {
+        entries_block: read_u64(&data, 72),
+        entry_count,
+        entry_size,
+        entries_crc: read_u32(&data, 88),
+    }))
+}
+
+/// Checks that every partition lies on the disk.
+fn check_bounds(disk: &dyn BlockDevice, entries: &[TableEntry]) -> Result<(), BlockError> {
+    let fits = |entry: &TableEntry| {
+        entry.first_block > 0
+            && entry
+                .first_block
+                .checked_add(entry.block_count)
+                .is_some_and(|end| end <= disk.block_count())
+    };
+    if entries.iter().all(fits) {
+        Ok(())
+    } else {
+        Err(BlockError::BadPartitionTable)
+    }
+}
+
+/// Reads `count` blocks starting at `start` into a new buffer.
+fn read(disk: &dyn BlockDevice, start: u64, count: u64) -> Result<Vec<u8>, BlockError> {
+    let mut buf = vec![0; count as usize * disk.block_size()];
+    disk.read_blocks(start, &mut buf)?;
+    Ok(buf)
+}
+
+/// Computes the CRC32 (IEEE 802.3) of `data`, as used by GPT.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// Reads the GUID at `offset`.
+fn guid_at(data: &[u8], offset: usize) -> Guid {
+    let mut guid = [0; 16];
+    guid.copy_from_slice(&data[offset..offset + 16]);
+    Guid(guid)
+}
+
+/// Reads a little-endian `u16` at `offset`.
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+/// Reads a little-endian `u32` at `offset`.
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+/// Reads a little-endian `u64` at `offset`.
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
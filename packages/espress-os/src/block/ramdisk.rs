@@ -0,0 +1,79 @@
+//! # RAM Disks
+//!
+//! A block device backed by kernel memory, for disk images loaded by the
+//! bootloader and for testing filesystems without a disk driver.
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use super::{check_request, BlockDevice, BlockError, SECTOR_SIZE};
+
+/// A block device of [`SECTOR_SIZE`] blocks held in memory.
+pub struct RamDisk {
+    /// Device name
+    name: String,
+    /// Contents, a whole number of blocks
+    data: Mutex<Vec<u8>>,
+    /// Writes are rejected
+    read_only: bool,
+}
+
+impl RamDisk {
+    /// Creates a zero-filled disk of `blocks` blocks.
+    pub fn new(name: &str, blocks: usize) -> Self {
+        Self::from_image(name, vec![0; blocks * SECTOR_SIZE])
+    }
+
+    /// Creates a disk holding `image`, padded with zeros to a whole block.
+    pub fn from_image(name: &str, mut image: Vec<u8>) -> Self {
+        image.resize(image.len().next_multiple_of(SECTOR_SIZE), 0);
+        RamDisk {
+            name: String::from(name),
+            data: Mutex::new(image),
+            read_only: false,
+        }
+    }
+
+    /// Makes the disk reject writes.
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn block_count(&self) -> u64 {
+        (self.data.lock().len() / SECTOR_SIZE) as u64
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn read_blocks(&self, start: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        check_request(self, start, buf.len())?;
+        let offset = start as usize * SECTOR_SIZE;
+        buf.copy_from_slice(&self.data.lock()[offset..offset + buf.len()]);
+        Ok(())
+    }
+
+    fn write_blocks(&self, start: u64, buf: &[u8]) -> Result<(), BlockError> {
+        if self.read_only {
+            return Err(BlockError::ReadOnly);
+        }
+        check_request(self, start, buf.len())?;
+        let offset = start as usize * SECTOR_SIZE;
+        self.data.lock()[offset..offset + buf.len()].copy_from_slice(buf);
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+//! # `bootloader_api` Protocol
+//!
+//! With the `bootloader-api` feature, the kernel is started by version
+//! 0.11 of the `bootloader` crate through its `bootloader_api` boot
+//! information instead of the 0.9 one, from either BIOS or UEFI. This file
+//! holds the [`CONFIG`] the bootloader reads from the kernel image and the
+//! translation of its [`BootInfo`] into the kernel's terms.
+//!
+//! The 0.11 bootloader sets up a framebuffer in both cases, so there is no
+//! VGA text mode. A mode requested with `video=` on the command line is
+//! passed on as the minimum framebuffer size; the bootloader picks the
+//! first mode at least that large.
+
+use ::bootloader_api::config::{BootloaderConfig, Mapping};
+use ::bootloader_api::info::{self, MemoryRegionKind};
+use ::bootloader_api::BootInfo;
+use x86_64::structures::paging::Translate;
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::{cmdline, uefi, Framebuffer, PixelFormat};
+use crate::mm::{self, Region, RegionKind};
+
+/// Address of the boot stack, as under the 0.9 bootloader.
+const KERNEL_STACK: u64 = 0xffff_fd00_0000_0000;
+/// Address of the boot information, as under the 0.9 bootloader.
+const BOOT_INFO: u64 = 0xffff_fd80_0000_0000;
+
+/// E820 type: ACPI tables that may be reclaimed.
+const E820_ACPI_RECLAIM: u32 = 3;
+/// E820 type: ACPI non-volatile storage.
+const E820_ACPI_NVS: u32 = 4;
+/// E820 type: faulty RAM.
+const E820_UNUSABLE: u32 = 5;
+
+/// Where the bootloader maps the kernel and what it hands over.
+///
+/// Everything lands in the higher half at the same addresses the 0.9
+/// bootloader uses (see `[package.metadata.bootloader]` in `Cargo.toml`),
+/// and the kernel, a static PIE already relocated for its link address, is
+/// loaded there unmoved. The framebuffer is at least as large as
+/// [`cmdline::VIDEO_MODE`] asks for.
+pub const CONFIG: BootloaderConfig = {
+    let mut config = BootloaderConfig::new_default();
+    config.mappings.kernel_base = Mapping::FixedAddress(mm::KERNEL_BASE);
+    config.mappings.kernel_stack = Mapping::FixedAddress(KERNEL_STACK);
+    config.mappings.boot_info = Mapping::FixedAddress(BOOT_INFO);
+    config.mappings.physical_memory = Some(Mapping::FixedAddress(mm::PHYS_MAP_BASE));
+    if let Some(mode) = cmdline::VIDEO_MODE {
+        config.frame_buffer.minimum_framebuffer_width = Some(mode.width as u64);
+        config.frame_buffer.minimum_framebuffer_height = Some(mode.height as u64);
+    }
+    config
+};
+
+/// Returns the linear mapping of physical memory.
+///
+/// # Panics
+///
+/// Panics if the bootloader did not map physical memory, which [`CONFIG`]
+/// asks it to.
+pub fn phys_offset(boot_info: &BootInfo) -> VirtAddr {
+    let offset = boot_info
+        .physical_memory_offset
+        .into_option()
+        .expect("bootloader did not map physical memory");
+    VirtAddr::new(offset)
+}
+
+/// Returns the memory map in the kernel's terms.
+///
+/// The bootloader reports the kernel image with its own allocations. Frame
+/// zero is never handed out, as under the other protocols.
+pub fn memory_map(boot_info: &BootInfo) -> impl Iterator<Item = Region> + '_ {
+    boot_info.memory_regions.iter().map(|region| {
+        let kind = region_kind(region.kind);
+        let start = match (region.start, kind) {
+            (0, RegionKind::Usable) => 4096.min(region.end),
+            _ => region.start,
+        };
+        Region {
+            start: PhysAddr::new(start),
+            len: region.end - start,
+            kind,
+        }
+    })
+}
+
+/// Returns the framebuffer, if it has 32-bit pixels the kernel can draw.
+///
+/// The bootloader passes the framebuffer's virtual address, so this looks
+/// up its physical address and must run after
+/// [`mm::init`](crate::mm::init).
+pub fn framebuffer(boot_info: &BootInfo) -> Option<Framebuffer> {
+    let framebuffer = boot_info.framebuffer.as_ref()?;
+    let info = framebuffer.info();
+    let format = match info.pixel_format {
+        info::PixelFormat::Rgb => PixelFormat::Rgb,
+        info::PixelFormat::Bgr => PixelFormat::Bgr,
+        info::PixelFormat::Unknown {
+            red_position,
+            green_position,
+            blue_position,
+        } => PixelFormat::Bitmask {
+            red: 0xff << red_position,
+            green: 0xff << green_position,
+            blue: 0xff << blue_position,
+        },
+        _ => return None,
+    };
+    if info.bytes_per_pixel != 4 {
+        return None;
+    }
+    let virt = VirtAddr::from_ptr(framebuffer.buffer().as_ptr());
+    let address = mm::with_mapper(|mapper| mapper.translate_addr(virt))?;
+    Some(Framebuffer {
+        address,
+        size: info.byte_len as u64,
+        width: info.width as u32,
+        height: info.height as u32,
+        stride: info.stride as u32,
+        format,
+    })
+}
+
+/// Returns the RSDP, if the firmware has one.
+pub fn rsdp(boot_info: &BootInfo) -> Option<PhysAddr> {
+    boot_info.rsdp_addr.into_option().map(PhysAddr::new)
+}
+
+/// Maps a `bootloader_api` region kind to the kernel's.
+fn region_kind(kind: MemoryRegionKind) -> RegionKind {
+    match kind {
+        MemoryRegionKind::Usable => RegionKind::Usable,
+        MemoryRegionKind::Bootloader => RegionKind::Bootloader,
+        MemoryRegionKind::UnknownUefi(kind) => uefi::region_kind(kind),
+        MemoryRegionKind::UnknownBios(E820_ACPI_RECLAIM) => RegionKind::AcpiReclaimable,
+        MemoryRegionKind::UnknownBios(E820_ACPI_NVS) => RegionKind::AcpiNvs,
+        MemoryRegionKind::UnknownBios(E820_UNUSABLE) => RegionKind::BadMemory,
+        _ => RegionKind::Reserved,
+    }
+}
@@ -0,0 +1,141 @@
+//! # Kernel Command Line
+//!
+//! None of the boot protocols hands the kernel a command line: the
+//! `bootloader` crate has no way to pass one, and the UEFI loader does not
+//! forward its load options. The command line is therefore fixed when the
+//! kernel is built, from the `ESPRESS_OS_CMDLINE` environment variable,
+//! e.g. `ESPRESS_OS_CMDLINE=quiet cargo bootimage`. The UEFI loader reads
+//! the same variable when it is built.
+//!
+//! It is a list of words separated by whitespace. A word on its own is a
+//! flag, tested with [`flag`]; a word `name=value` is an option, read with
+//! [`option`].
+//!
+//! | Word                             | Effect                          |
+//! |----------------------------------|---------------------------------|
+//! | `quiet`                          | no [boot splash](super::splash) |
+//! | `video=<width>x<height>[-<bpp>]` | framebuffer mode to request     |
+//!
+//! The video mode is requested from the UEFI loader and the `bootloader`
+//! crate 0.11, which set up the framebuffer; see [`VIDEO_MODE`]. The
+//! `bootloader` crate 0.9 always starts the kernel in VGA text mode.
+
+/// The command line the kernel was built with.
+const CMDLINE: &str = match option_env!("ESPRESS_OS_CMDLINE") {
+    Some(cmdline) => cmdline,
+    None => "",
+};
+
+/// The display mode requested with `video=`, or `None` if there is no such
+/// option or it is malformed.
+///
+/// Parsed when the kernel is built, so that the `bootloader_api`
+/// configuration can ask for it.
+pub const VIDEO_MODE: Option<VideoMode> = parse_video(CMDLINE.as_bytes());
+
+/// A display mode requested on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VideoMode {
+    /// Width in pixels
+    pub width: u32,
+    /// Height in pixels
+    pub height: u32,
+    /// Bits per pixel, if given
+    pub bpp: Option<u32>,
+}
+
+/// Returns the whole command line.
+pub fn cmdline() -> &'static str {
+    CMDLINE
+}
+
+/// Returns `true` if the word `name` is on the command line.
+pub fn flag(name: &str) -> bool {
+    CMDLINE.split_whitespace().any(|word| word == name)
+}
+
+/// Returns the value of the option `name`, i.e. what follows `name=` in the
+/// first word that starts with it.
+pub fn option(name: &str) -> Option<&'static str> {
+    CMDLINE
+        .split_whitespace()
+        .find_map(|word| word.strip_prefix(name)?.strip_prefix('='))
+}
+
+/// Finds the first `video=` option in `cmdline` and parses its value.
+const fn parse_video(cmdline: &[u8]) -> Option<VideoMode> {
+    const KEY: &[u8] = b"video=";
+    let mut start = 0;
+    while start < cmdline.len() {
+        let mut end = start;
+        while end < cmdline.len() && !cmdline[end].is_ascii_whitespace() {
+            end += 1;
+        }
+        if has_prefix(cmdline, start, KEY) {
+            return parse_mode(cmdline, start + KEY.len(), end);
+        }
+        start = end + 1;
+    }
+    None
+}
+
+/// Parses `<width>x<height>[-<bpp>]` in `bytes[start..end]`.
+const fn parse_mode(bytes: &[u8], start: usize, end: usize) -> Option<VideoMode> {
+    let Some((width, next)) = parse_number(bytes, start, end) else {
+        return None;
+    };
+    if next == end || bytes[next] != b'x' {
+        return None;
+    }
+    let Some((height, next)) = parse_number(bytes, next + 1, end) else {
+        return None;
+    };
+    let bpp = if next == end {
+        None
+    } else if bytes[next] == b'-' {
+        match parse_number(bytes, next + 1, end) {
+            Some((bpp, next)) if next == end => Some(bpp),
+            _ => return None,
+        }
+    } else {
+        return None;
+    };
+    Some(VideoMode { width, height, bpp })
+}
+
+/// Parses the decimal number at the start of `bytes[start..end]`.
+///
+/// # Returns
+///
+/// The number and the index just past it, or `None` if there are no digits
+/// or the number does not fit into a `u32`.
+const fn parse_number(bytes: &[u8], start: usize, end: usize) -> Option<(u32, usize)> {
+    let mut value: u32 = 0;
+    let mut index = start;
+    while index < end && bytes[index].is_ascii_digit() {
+        let Some(shifted) = value.checked_mul(10) else {
+            return None;
+        };
+        let Some(sum) = shifted.checked_add((bytes[index] - b'0') as u32) else {
+            return None;
+        };
+        value = sum;
+        index += 1;
+    }
+    if index == start {
+        return None;
+    }
+    Some((value, index))
+}
+
+/// Returns `true` if `bytes[start..]` starts with `prefix`.
+const fn has_prefix(bytes: &[u8], start: usize, prefix: &[u8]) -> bool {
+    let mut index = 0;
+    while index < prefix.len() {
+        if start + index >= bytes.len() || bytes[start + index] != prefix[index] {
+            return false;
+        }
+        index += 1;
+    }
+    true
+}
@@ -0,0 +1,158 @@
+//! # Boot Protocols
+//!
+//! The kernel can be started in three ways, all ending in the common
+//! [`init`](crate::init) path:
+//!
+//! - by the `bootloader` crate (0.9) from legacy BIOS, which passes its
+//!   `BootInfo` and leaves the VGA text mode set up, or
+//! - by the EspressOS UEFI loader (`packages/espress-efi`), which passes a
+//!   [`uefi::BootInfo`] with the firmware's memory map, the GOP
+//!   framebuffer, the RSDP, and the SMBIOS entry point, having exited boot
+//!   services, or
+//! - with the `bootloader-api` feature, by the `bootloader` crate 0.11 from
+//!   BIOS or UEFI, which passes a `bootloader_api` `BootInfo` (see
+//!   [`bootloader_api`](self::bootloader_api)) and always sets up a
+//!   framebuffer. Such a kernel has no other entry point.
+//!
+//! On aarch64 the kernel is instead started directly by the machine, and on
+//! riscv64 by the OpenSBI firmware, with a device tree (see `arch::boot`)
+//! and runs identity-mapped. On i686 it is started by a multiboot loader
+//! (see `arch::boot`), also identity-mapped.
+//!
+//! On x86_64, all set up the same address space: all physical memory mapped at
+//! [`mm::PHYS_MAP_BASE`](crate::mm::PHYS_MAP_BASE), the kernel image at its
+//! link address, and a boot stack. What differs is recorded here by
+//! [`record`], so that drivers can ask which [`Protocol`] started the
+//! kernel and what the firmware handed over.
+//!
+//! The initialization that follows opens with the [`splash`] logo and is
+//! reported stage by stage through [`stage`] (see [`progress`]).
+
+#[cfg(feature = "bootloader-api")]
+pub mod bootloader_api;
+pub mod cmdline;
+pub mod progress;
+pub mod splash;
+#[cfg(target_arch = "x86_64")]
+pub mod uefi;
+
+pub use progress::stage;
+
+use spin::Once;
+use x86_64::PhysAddr;
+
+/// How the kernel was started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// By the `bootloader` crate from legacy BIOS, in VGA text mode
+    Bios,
+    /// By the EspressOS UEFI loader, with a linear framebuffer
+    Uefi,
+    /// By the `bootloader` crate 0.11, with a linear framebuffer
+    BootloaderApi,
+    /// Directly by the machine or its firmware, with a device tree (aarch64
+    /// and riscv64)
+    Devicetree,
+    /// By a multiboot loader such as GRUB, in VGA text mode (i686)
+    Multiboot,
+}
+
+/// Layout of a pixel in the framebuffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// Red in the lowest byte, then green and blue
+    Rgb,
+    /// Blue in the lowest byte, then green and red
+    Bgr,
+    /// The colors are at the bit positions of the given masks
+    Bitmask {
+        /// Bits of the red component
+        red: u32,
+        /// Bits of the green component
+        green: u32,
+        /// Bits of the blue component
+        blue: u32,
+    },
+}
+
+/// A linear framebuffer set up by the firmware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Framebuffer {
+    /// Physical address of the first pixel
+    pub address: PhysAddr,
+    /// Size in bytes
+    pub size: u64,
+    /// Visible width in pixels
+    pub width: u32,
+    /// Visible height in pixels
+    pub height: u32,
+    /// Pixels from the start of one line to the start of the next
+    pub stride: u32,
+    /// Layout of each 32-bit pixel
+    pub format: PixelFormat,
+}
+
+/// What the boot protocol handed over besides the memory map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Handoff {
+    protocol: Protocol,
+    framebuffer: Option<Framebuffer>,
+    rsdp: Option<PhysAddr>,
+    smbios: Option<PhysAddr>,
+}
+
+static HANDOFF: Once<Handoff> = Once::new();
+
+/// Records how the kernel was started; later calls are ignored.
+pub(crate) fn record(
+    protocol: Protocol,
+    framebuffer: Option<Framebuffer>,
+    rsdp: Option<PhysAddr>,
+    smbios: Option<PhysAddr>,
+) {
+    HANDOFF.call_once(|| Handoff {
+        protocol,
+        framebuffer,
+        rsdp,
+        smbios,
+    });
+}
+
+/// Returns how the kernel was started.
+pub fn protocol() -> Protocol {
+    HANDOFF
+        .get()
+        .map_or(Protocol::Bios, |handoff| handoff.protocol)
+}
+
+/// Returns the framebuffer the firmware set up, if any.
+///
+/// Always `None` when booted from BIOS by the `bootloader` crate 0.9,
+/// which leaves the display in VGA text mode. Otherwise it is in the mode
+/// requested with `video=` (see [`cmdline::VIDEO_MODE`]) if the loader
+/// could set it.
+pub fn framebuffer() -> Option<Framebuffer> {
+    HANDOFF.get().and_then(|handoff| handoff.framebuffer)
+}
+
+/// Returns the physical address of the RSDP if the firmware passed it.
+///
+/// BIOS firmware does not; the RSDP is then searched for in low memory
+/// (see [`acpi`](crate::acpi)).
+pub fn rsdp() -> Option<PhysAddr> {
+    HANDOFF.get().and_then(|handoff| handoff.rsdp)
+}
+
+/// Returns the physical address of the SMBIOS entry point if the firmware
+/// passed it.
+///
+/// BIOS firmware does not; the entry point is then searched for in the BIOS
+/// area (see [`smbios`](crate::smbios)).
+pub fn smbios() -> Option<PhysAddr> {
+    HANDOFF.get().and_then(|handoff| handoff.smbios)
+}
+
+/// Returns `true` if the VGA text buffer is there to be written to.
+pub fn has_text_mode() -> bool {
+    matches!(protocol(), Protocol::Bios | Protocol::Multiboot)
+}
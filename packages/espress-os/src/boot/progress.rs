@@ -0,0 +1,137 @@
+//! # Boot Progress
+//!
+//! Kernel initialization is a sequence of named stages. Each one is run
+//! through [`stage`], which times it and prints one line when it is done:
+//!
+//! ```text
+//! [ OK ] GDT and TSS
+//! [ OK ] PCI  (1.204 ms)
+//! [FAIL] Kernel heap: OutOfFrames
+//! ```
+//!
+//! On x86_64 the TSC is calibrated partway through, so the lines of earlier
+//! stages carry no time. Every stage is still recorded with its cycle count and can
+//! be listed with [`stages`] once the clock is known.
+
+use alloc::vec::Vec;
+use core::fmt;
+use core::time::Duration;
+use spin::Mutex;
+
+use crate::arch::timer;
+use crate::console::{self, Color};
+
+/// Most stages recorded; later ones are still run and printed.
+const MAX_STAGES: usize = 48;
+
+/// A finished boot stage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stage {
+    /// Name shown in the boot log
+    pub name: &'static str,
+    /// Ticks of the [architecture's counter](crate::arch::timer) the stage
+    /// took, TSC cycles on x86_64
+    pub cycles: u64,
+    /// The stage succeeded
+    pub ok: bool,
+}
+
+impl Stage {
+    /// Time the stage took, zero before the counter frequency is known.
+    pub fn duration(&self) -> Duration {
+        timer::ticks_to_duration(self.cycles)
+    }
+}
+
+/// The result of a stage's initialization function.
+pub trait Outcome {
+    /// Returns the error if the stage failed.
+    fn error(&self) -> Option<&dyn fmt::Debug>;
+}
+
+impl Outcome for () {
+    fn error(&self) -> Option<&dyn fmt::Debug> {
+        None
+    }
+}
+
+impl<T, E: fmt::Debug> Outcome for Result<T, E> {
+    fn error(&self) -> Option<&dyn fmt::Debug> {
+        self.as_ref().err().map(|err| err as &dyn fmt::Debug)
+    }
+}
+
+/// Stages finished so far, in order. A fixed array, since the first
+/// stages run before the heap exists.
+static STAGES: Mutex<StageLog> = Mutex::new(StageLog {
+    stages: [None; MAX_STAGES],
+    len: 0,
+});
+
+/// Storage behind [`STAGES`].
+struct StageLog {
+    /// The recorded stages
+    stages: [Option<Stage>; MAX_STAGES],
+    /// Number of recorded stages
+    len: usize,
+}
+
+/// Runs the boot stage `name` and reports how it went.
+///
+/// Prints `[ OK ]` or `[FAIL]` with the stage name, the error if `init`
+/// returned one, and the time taken if the counter frequency is known.
+///
+/// # Arguments
+///
+/// * `name` - Name shown in the boot log, e.g. `"GDT and TSS"`
+/// * `init` - The stage's initialization function
+///
+/// # Returns
+///
+/// What `init` returned, for the caller to act on a failure.
+pub fn stage<R: Outcome>(name: &'static str, init: impl FnOnce() -> R) -> R {
+    let start = timer::ticks();
+    let result = init();
+    let cycles = timer::ticks().wrapping_sub(start);
+    let error = result.error();
+
+    let mut log = STAGES.lock();
+    let index = log.len;
+    if let Some(slot) = log.stages.get_mut(index) {
+        *slot = Some(Stage {
+            name,
+            cycles,
+            ok: error.is_none(),
+        });
+        log.len += 1;
+    }
+    drop(log);
+
+    crate::print!("[");
+    match error {
+        None => console::print_colored(" OK ", Color::LightGreen),
+        Some(_) => console::print_colored("FAIL", Color::LightRed),
+    }
+    crate::print!("] {}", name);
+    if let Some(err) = error {
+        crate::print!(": {:?}", err);
+    }
+    if timer::frequency() != 0 {
+        let micros = timer::ticks_to_duration(cycles).as_micros();
+        crate::print!("  ({}.{:03} ms)", micros / 1000, micros % 1000);
+    }
+    crate::println!();
+    result
+}
+
+/// Returns the stages finished so far, in order.
+pub fn stages() -> Vec<Stage> {
+    let log = STAGES.lock();
+    log.stages[..log.len].iter().flatten().copied().collect()
+}
+
+/// Returns the total time of the stages finished so far.
+pub fn elapsed() -> Duration {
+    let cycles = stages().iter().map(|stage| stage.cycles).sum();
+    timer::ticks_to_duration(cycles)
+}
@@ -0,0 +1,56 @@
+//! # Boot Splash
+//!
+//! The EspressOS logo, printed in color on every console backend at the top
+//! of the boot log, before the [boot stages](super::progress). The
+//! `quiet` [command line](super::cmdline) flag leaves it out.
+//!
+//! The logo is text, so it shows in VGA text mode and on the serial port
+//! alike. With a framebuffer instead of text mode, it is printed before the
+//! [framebuffer console](crate::fbcon) exists, which replays it without
+//! colors.
+
+use crate::console::{self, Color};
+
+/// The logo, one line per row: the cup, `Espress`, and `OS`, each drawn in
+/// its own color.
+#[rustfmt::skip]
+const LOGO: [[&str; 3]; 6] = [
+    [r"  ( (    ", r"  _____                              ", r"   ___   ____"],
+    [r"   ) )   ", r" | ____|___ _ __  _ __ ___  ___ ___ ", r"   / _ \ / ___|"],
+    [r" ._____. ", r" |  _| / __| '_ \| '__/ _ \/ __/ __|", r"  | | | |\___ \"],
+    [r" |     |]", r" | |___\__ \ |_) | | |  __/\__ \__ \", r"  | |_| | ___) |"],
+    [r" \     / ", r" |_____|___/ .__/|_|  \___||___/___/", r"   \___/ |____/"],
+    [r"  `---'  ", r"           |_|", ""],
+];
+
+/// Colors of the cup, `Espress`, and `OS`.
+const COLORS: [Color; 3] = [Color::Brown, Color::Yellow, Color::LightCyan];
+
+/// Color of the steam rising from the cup.
+const STEAM: Color = Color::LightGray;
+
+/// Rows of the logo that are steam.
+const STEAM_ROWS: usize = 2;
+
+/// Prints the logo and the kernel version, unless the command line says
+/// `quiet`.
+pub fn show() {
+    if super::cmdline::flag("quiet") {
+        return;
+    }
+    crate::println!();
+    for (row, parts) in LOGO.iter().enumerate() {
+        for (column, part) in parts.iter().enumerate() {
+            let color = match (row, column) {
+                (row, 0) if row < STEAM_ROWS => STEAM,
+                _ => COLORS[column],
+            };
+            console::print_colored(part, color);
+        }
+        crate::println!();
+    }
+    crate::println!();
+    console::print_colored("  EspressOS", Color::White);
+    crate::println!(" {} ({})", env!("CARGO_PKG_VERSION"), crate::arch::NAME);
+    crate::println!();
+}
@@ -0,0 +1,190 @@
+//! # UEFI Handoff
+//!
+//! The structure the EspressOS UEFI loader passes to the kernel, and its
+//! translation into the kernel's own types.
+//!
+//! The loader jumps to the kernel's ELF entry point with a pointer to a
+//! [`BootInfo`] in `rdi`, like the `bootloader` crate, and [`MAGIC`] in
+//! `rsi`, which tells the two protocols apart. The pointer is into the
+//! linear physical memory mapping, so it stays valid after the lower half
+//! is unmapped.
+//!
+//! The layout is shared with `packages/espress-efi/src/handoff.rs` and must
+//! be kept in sync with it; [`VERSION`] is bumped on every change.
+
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::{Framebuffer, PixelFormat};
+use crate::mm::{Region, RegionKind};
+
+/// Value in `rsi` at the kernel entry point when started by the loader.
+pub const MAGIC: u64 = u64::from_le_bytes(*b"EspUEFI\0");
+
+/// Version of the [`BootInfo`] layout.
+pub const VERSION: u32 = 2;
+
+/// Most memory map entries the loader passes.
+pub const MAX_REGIONS: usize = 256;
+
+/// Loader memory type: the kernel image and its boot stack.
+pub const MEMORY_KERNEL: u32 = 0x8000_0000;
+/// Loader memory type: the page tables set up by the loader.
+pub const MEMORY_PAGE_TABLE: u32 = 0x8000_0001;
+/// Loader memory type: this structure.
+pub const MEMORY_BOOT_INFO: u32 = 0x8000_0002;
+
+/// UEFI memory type: the loader's code.
+const LOADER_CODE: u32 = 1;
+/// UEFI memory type: the loader's data.
+const LOADER_DATA: u32 = 2;
+/// UEFI memory type: boot services code, free after exit.
+const BOOT_SERVICES_CODE: u32 = 3;
+/// UEFI memory type: boot services data, free after exit.
+const BOOT_SERVICES_DATA: u32 = 4;
+/// UEFI memory type: free memory.
+const CONVENTIONAL: u32 = 7;
+/// UEFI memory type: memory with errors.
+const UNUSABLE: u32 = 8;
+/// UEFI memory type: ACPI tables.
+const ACPI_RECLAIM: u32 = 9;
+/// UEFI memory type: ACPI firmware storage.
+const ACPI_NVS: u32 = 10;
+
+/// GOP pixel format: red, green, blue, reserved bytes.
+const PIXEL_RGB: u32 = 0;
+/// GOP pixel format: blue, green, red, reserved bytes.
+const PIXEL_BGR: u32 = 1;
+/// GOP pixel format: given by the masks.
+const PIXEL_BITMASK: u32 = 2;
+
+/// A memory map entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct MemoryRegion {
+    /// First physical address
+    pub start: u64,
+    /// Length in bytes
+    pub len: u64,
+    /// UEFI memory type, or one of the loader's `MEMORY_*` types
+    pub kind: u32,
+    /// Unused
+    pub reserved: u32,
+}
+
+/// The GOP framebuffer as the loader found it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct FramebufferInfo {
+    /// Physical address, 0 if there is no usable framebuffer
+    pub address: u64,
+    /// Size in bytes
+    pub size: u64,
+    /// Visible width in pixels
+    pub width: u32,
+    /// Visible height in pixels
+    pub height: u32,
+    /// Pixels per scan line
+    pub stride: u32,
+    /// GOP pixel format
+    pub format: u32,
+    /// Red, green, and blue masks of the bitmask format
+    pub masks: [u32; 3],
+    /// Unused
+    pub reserved: u32,
+}
+
+/// Everything the loader hands over.
+#[derive(Debug)]
+#[repr(C)]
+pub struct BootInfo {
+    /// [`VERSION`] of the loader that filled this in
+    pub version: u32,
+    /// Number of valid entries in [`regions`](Self::regions)
+    pub region_count: u32,
+    /// Virtual address at which all physical memory is mapped
+    pub physical_memory_offset: u64,
+    /// Physical address of the RSDP, 0 if the firmware has none
+    pub rsdp: u64,
+    /// Physical address of the SMBIOS entry point, 0 if the firmware has
+    /// none
+    pub smbios: u64,
+    /// The framebuffer
+    pub framebuffer: FramebufferInfo,
+    /// The firmware memory map at exit from boot services, sorted by
+    /// address
+    pub regions: [MemoryRegion; MAX_REGIONS],
+}
+
+impl BootInfo {
+    /// Returns the linear mapping of physical memory.
+    pub fn phys_offset(&self) -> VirtAddr {
+        VirtAddr::new(self.physical_memory_offset)
+    }
+
+    /// Returns the memory map in the kernel's terms.
+    ///
+    /// Boot services memory is free once the kernel runs. Frame zero is
+    /// never handed out, as under the `bootloader` crate.
+    pub fn memory_map(&self) -> impl Iterator<Item = Region> + '_ {
+        let count = (self.region_count as usize).min(MAX_REGIONS);
+        self.regions[..count].iter().map(|region| {
+            let kind = region_kind(region.kind);
+            let (start, len) = match (region.start, kind) {
+                (0, RegionKind::Usable) => (4096, region.len.saturating_sub(4096)),
+                _ => (region.start, region.len),
+            };
+            Region {
+                start: PhysAddr::new(start),
+                len,
+                kind,
+            }
+        })
+    }
+
+    /// Returns the framebuffer, if the loader found one the kernel can
+    /// draw to.
+    pub fn framebuffer(&self) -> Option<Framebuffer> {
+        let info = &self.framebuffer;
+        let format = match info.format {
+            PIXEL_RGB => PixelFormat::Rgb,
+            PIXEL_BGR => PixelFormat::Bgr,
+            PIXEL_BITMASK => PixelFormat::Bitmask {
+                red: info.masks[0],
+                green: info.masks[1],
+                blue: info.masks[2],
+            },
+            _ => return None,
+        };
+        (info.address != 0).then(|| Framebuffer {
+            address: PhysAddr::new(info.address),
+            size: info.size,
+            width: info.width,
+            height: info.height,
+            stride: info.stride,
+            format,
+        })
+    }
+
+    /// Returns the RSDP, if the firmware has one.
+    pub fn rsdp(&self) -> Option<PhysAddr> {
+        (self.rsdp != 0).then(|| PhysAddr::new(self.rsdp))
+    }
+
+    /// Returns the SMBIOS entry point, if the firmware has one.
+    pub fn smbios(&self) -> Option<PhysAddr> {
+        (self.smbios != 0).then(|| PhysAddr::new(self.smbios))
+    }
+}
+
+/// Maps a UEFI memory type to the kernel's region kind.
+pub(super) fn region_kind(kind: u32) -> RegionKind {
+    match kind {
+        CONVENTIONAL | BOOT_SERVICES_CODE | BOOT_SERVICES_DATA => RegionKind::Usable,
+        MEMORY_KERNEL => RegionKind::Kernel,
+        LOADER_CODE | LOADER_DATA | MEMORY_PAGE_TABLE | MEMORY_BOOT_INFO => RegionKind::Bootloader,
+        ACPI_RECLAIM => RegionKind::AcpiReclaimable,
+        ACPI_NVS => RegionKind::AcpiNvs,
+        UNUSABLE => RegionKind::BadMemory,
+        _ => RegionKind::Reserved,
+    }
+}
@@ -0,0 +1,233 @@
+//! # Console
+//!
+//! Kernel-wide text output that is independent of any particular device.
+//! Output written with [`print!`](crate::print) and
+//! [`println!`](crate::println) goes to every registered
+//! [`ConsoleBackend`], so the same boot log appears on every device the
+//! architecture registers: the VGA screen and the serial port on x86_64
+//! and i686, the PL011 UART on aarch64, the SBI console on riscv64. On
+//! x86_64 a [framebuffer console](crate::fbcon) takes the place of the VGA
+//! screen when there is no text mode.
+//!
+//! Input is gathered the same way: [`try_read`] returns the next byte any
+//! backend has received.
+//!
+//! The last [`LOG_SIZE`] bytes of output are also kept in memory, so the
+//! boot log can be read back later with [`log`], like `dmesg`.
+//!
+//! Text can be colored with [`print_colored`]; backends that cannot show
+//! colors print it plain. Other device-specific output still goes through
+//! the device's own interface.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use spin::Mutex;
+
+use crate::arch::interrupts::without_interrupts;
+
+/// Text color palette: the 16 colors of the VGA text mode, which every
+/// backend maps to the closest it can show.
+pub use espress_vga_core::Color;
+
+/// Maximum number of backends that can be registered at the same time.
+const MAX_BACKENDS: usize = 8;
+
+/// Bytes of recent output kept for [`log`].
+pub const LOG_SIZE: usize = 16 * 1024;
+
+/// A device that can display console output.
+pub trait ConsoleBackend: Sync {
+    /// Short name of the backend, e.g. `"vga"` or `"serial0"`.
+    fn name(&self) -> &'static str;
+
+    /// Writes a string to the device.
+    fn write_str(&self, s: &str);
+
+    /// Writes a string in the given text color, or plain if the device has
+    /// no colors.
+    fn write_colored(&self, s: &str, color: Color) {
+        let _ = color;
+        self.write_str(s);
+    }
+
+    /// Returns the next byte received from the device, or `None` if none
+    /// is waiting or the device has no input.
+    fn try_read(&self) -> Option<u8> {
+        None
+    }
+
+    /// Releases the device's lock in case the interrupted code held it.
+    ///
+    /// # Safety
+    ///
+    /// See [`force_unlock`].
+    unsafe fn force_unlock(&self) {}
+}
+
+/// Registered backends, in registration order.
+static BACKENDS: Mutex<[Option<&'static dyn ConsoleBackend>; MAX_BACKENDS]> =
+    Mutex::new([None; MAX_BACKENDS]);
+
+/// The most recent output.
+static LOG: Mutex<LogBuffer> = Mutex::new(LogBuffer::new());
+
+/// Registers the architecture's default backends (see
+/// [`arch::console`](crate::arch::console)).
+pub fn init() {
+    crate::arch::console::init();
+}
+
+/// Adds a backend that receives all subsequent console output.
+///
+/// # Panics
+///
+/// Panics if [`MAX_BACKENDS`] backends are already registered.
+pub fn register(backend: &'static dyn ConsoleBackend) {
+    let mut backends = BACKENDS.lock();
+    let slot = backends
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .expect("too many console backends");
+    *slot = Some(backend);
+}
+
+/// Calls `f` for every registered backend.
+pub fn for_each_backend(mut f: impl FnMut(&'static dyn ConsoleBackend)) {
+    for backend in BACKENDS.lock().iter().flatten() {
+        f(*backend);
+    }
+}
+
+/// Releases the console's locks and those of every backend, so that a
+/// fatal exception or a panic can still be printed even if it interrupted
+/// console output.
+///
+/// # Safety
+///
+/// The caller must never return to the interrupted code, which would find
+/// its locks gone.
+pub unsafe fn force_unlock() {
+    BACKENDS.force_unlock();
+    LOG.force_unlock();
+    for backend in BACKENDS.lock().iter().flatten() {
+        backend.force_unlock();
+    }
+}
+
+/// Returns the next byte received by any backend, asking them in
+/// registration order, or `None` if none is waiting.
+pub fn try_read() -> Option<u8> {
+    let backends = *BACKENDS.lock();
+    backends
+        .iter()
+        .flatten()
+        .find_map(|backend| backend.try_read())
+}
+
+/// Returns the most recent output, oldest first.
+///
+/// A character cut in half where the buffer wrapped is replaced.
+pub fn log() -> String {
+    let bytes = without_interrupts(|| LOG.lock().contents());
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Prints formatted text to all console backends without a newline.
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::console::_print(format_args!($($arg)*)));
+}
+
+/// Prints formatted text to all console backends with a newline.
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+/// Internal function backing the console print macros.
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    without_interrupts(|| {
+        for backend in BACKENDS.lock().iter().flatten() {
+            let _ = BackendWriter(*backend).write_fmt(args);
+        }
+        let _ = LOG.lock().write_fmt(args);
+    });
+}
+
+/// Prints a string to all console backends in the given text color.
+///
+/// The in-memory [`log`] keeps it without the color.
+pub fn print_colored(s: &str, color: Color) {
+    without_interrupts(|| {
+        for backend in BACKENDS.lock().iter().flatten() {
+            backend.write_colored(s, color);
+        }
+        let _ = LOG.lock().write_str(s);
+    });
+}
+
+/// A ring buffer of the last [`LOG_SIZE`] bytes written.
+struct LogBuffer {
+    /// The bytes, starting at `start` and wrapping around
+    data: [u8; LOG_SIZE],
+    /// Index of the oldest byte
+    start: usize,
+    /// Bytes in use
+    len: usize,
+}
+
+impl LogBuffer {
+    /// Creates an empty buffer.
+    const fn new() -> Self {
+        LogBuffer {
+            data: [0; LOG_SIZE],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    /// Copies out the contents, oldest first.
+    fn contents(&self) -> Vec<u8> {
+        let (tail, head) = self.data.split_at(self.start);
+        head.iter().chain(tail).take(self.len).copied().collect()
+    }
+}
+
+impl Write for LogBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            self.data[(self.start + self.len) % LOG_SIZE] = byte;
+            if self.len < LOG_SIZE {
+                self.len += 1;
+            } else {
+                self.start = (self.start + 1) % LOG_SIZE;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Adapts a backend to `core::fmt::Write`.
+struct BackendWriter(&'static dyn ConsoleBackend);
+
+impl Write for BackendWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write_str(s);
+        Ok(())
+    }
+}
+
+/// Returns the ANSI SGR parameter selecting `color` as the text color.
+///
+/// The VGA palette orders the low three bits blue, green, red, while ANSI
+/// orders them red, green, blue; the intensity bit selects the bright
+/// variants.
+pub fn ansi_color(color: Color) -> u8 {
+    let vga = color as u8;
+    let rgb = (vga & 0b001) << 2 | vga & 0b010 | (vga & 0b100) >> 2;
+    let bright = if vga & 0b1000 != 0 { 60 } else { 0 };
+    30 + bright + rgb
+}
@@ -0,0 +1,253 @@
+//! # Framebuffer Console
+//!
+//! Shows the console on the linear framebuffer when the boot path set one
+//! up instead of the VGA text mode, i.e. under the EspressOS UEFI loader
+//! and the `bootloader` crate 0.11 (see [`boot::framebuffer`]). The screen
+//! is a grid of character cells drawn with the font of `espress-vga-core`
+//! and written by the same [`TextWriter`] as the VGA screen, so colors,
+//! line wrapping, and scrolling behave alike.
+//!
+//! The cells are also kept in memory, and a cell is only drawn when it
+//! changes. The framebuffer is mapped write-combining (see
+//! [`mmio::map_write_combining`]). Everything logged before the console
+//! existed is replayed onto it from [`console::log`].
+//!
+//! The mode is chosen by the loader. A mode requested with `video=` on the
+//! command line (see [`cmdline::VIDEO_MODE`]) is passed on to it, and
+//! [`init`] warns if the framebuffer does not match.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use espress_vga_core::font::{GLYPHS, GLYPH_HEIGHT, GLYPH_WIDTH};
+use espress_vga_core::{ColorCode, ScreenChar, TextBuffer, TextWriter, PALETTE};
+use spin::Mutex;
+
+use crate::boot::{self, cmdline, Framebuffer, PixelFormat, Protocol};
+use crate::console::{self, Color, ConsoleBackend};
+use crate::mm::mmio::{self, MmioError};
+use crate::println;
+
+/// Bytes per pixel; only 32-bit framebuffers are supported.
+const BYTES_PER_PIXEL: u64 = 4;
+
+/// The console, once set up.
+static CONSOLE: OnceCell<FramebufferConsole> = OnceCell::uninit();
+
+/// Errors that can occur while setting up the framebuffer console.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FbconError {
+    /// The framebuffer cannot hold a single character cell, or is smaller
+    /// than its dimensions say
+    TooSmall,
+    /// The framebuffer could not be mapped
+    Map(MmioError),
+}
+
+/// Character cells drawn onto the framebuffer.
+struct Screen {
+    /// First pixel of the mapped framebuffer
+    pixels: *mut u32,
+    /// Pixels from the start of one line to the start of the next
+    stride: usize,
+    /// Number of columns
+    columns: usize,
+    /// Number of rows
+    rows: usize,
+    /// Pixel values of the 16 colors
+    palette: [u32; 16],
+    /// The cells as drawn, row by row
+    cells: Vec<ScreenChar>,
+}
+
+// The framebuffer stays mapped for good and is only drawn to through the
+// console's lock.
+unsafe impl Send for Screen {}
+
+impl Screen {
+    /// Takes over the framebuffer mapped at `pixels` and clears it.
+    fn new(framebuffer: &Framebuffer, pixels: *mut u32, blank: ScreenChar) -> Screen {
+        let palette = PALETTE.map(|rgb| encode(framebuffer.format, rgb));
+        let stride = framebuffer.stride as usize;
+        let background = palette[blank.color_code.background() as usize];
+        for offset in 0..stride * framebuffer.height as usize {
+            unsafe { pixels.add(offset).write_volatile(background) };
+        }
+        let columns = framebuffer.width as usize / GLYPH_WIDTH;
+        let rows = framebuffer.height as usize / GLYPH_HEIGHT;
+        Screen {
+            pixels,
+            stride,
+            columns,
+            rows,
+            palette,
+            cells: vec![blank; columns * rows],
+        }
+    }
+
+    /// Draws `cell` at `row` and `column`.
+    fn draw(&mut self, row: usize, column: usize, cell: ScreenChar) {
+        let foreground = self.palette[cell.color_code.foreground() as usize];
+        let background = self.palette[cell.color_code.background() as usize];
+        let glyph = &GLYPHS[usize::from(cell.ascii_character)];
+        for (line, bits) in glyph.iter().enumerate() {
+            let start = (row * GLYPH_HEIGHT + line) * self.stride + column * GLYPH_WIDTH;
+            for x in 0..GLYPH_WIDTH {
+                let pixel = if bits & (0x80 >> x) != 0 {
+                    foreground
+                } else {
+                    background
+                };
+                unsafe { self.pixels.add(start + x).write_volatile(pixel) };
+            }
+        }
+    }
+}
+
+impl TextBuffer for Screen {
+    fn width(&self) -> usize {
+        self.columns
+    }
+
+    fn height(&self) -> usize {
+        self.rows
+    }
+
+    fn read(&self, row: usize, column: usize) -> ScreenChar {
+        self.cells[row * self.columns + column]
+    }
+
+    fn write(&mut self, row: usize, column: usize, cell: ScreenChar) {
+        let index = row * self.columns + column;
+        if self.cells[index] != cell {
+            self.cells[index] = cell;
+            self.draw(row, column, cell);
+        }
+    }
+}
+
+/// Console backend for the framebuffer.
+struct FramebufferConsole {
+    /// Writer drawing onto the framebuffer
+    writer: Mutex<TextWriter<Screen>>,
+}
+
+impl ConsoleBackend for FramebufferConsole {
+    fn name(&self) -> &'static str {
+        "fb0"
+    }
+
+    fn write_str(&self, s: &str) {
+        self.writer.lock().write_string(s);
+    }
+
+    fn write_colored(&self, s: &str, color: Color) {
+        self.writer.lock().write_colored(s, color);
+    }
+
+    unsafe fn force_unlock(&self) {
+        self.writer.force_unlock();
+    }
+}
+
+/// Sets up the framebuffer console and registers it as a console backend.
+///
+/// Does nothing if the display is in VGA text mode. Must run after the
+/// kernel heap is set up, and after [`pat::init`](crate::arch::pat::init)
+/// for the framebuffer to be write-combining.
+///
+/// # Errors
+///
+/// Returns [`FbconError::TooSmall`] if the framebuffer cannot hold text,
+/// or [`FbconError::Map`] if it cannot be mapped.
+pub fn init() -> Result<(), FbconError> {
+    if cmdline::option("video").is_some() && cmdline::VIDEO_MODE.is_none() {
+        println!("fbcon: ignoring malformed video= option");
+    }
+    let Some(framebuffer) = boot::framebuffer() else {
+        if cmdline::VIDEO_MODE.is_some() {
+            println!("fbcon: video= has no effect in VGA text mode");
+        }
+        return Ok(());
+    };
+    check_mode(&framebuffer);
+
+    let len = u64::from(framebuffer.stride) * u64::from(framebuffer.height) * BYTES_PER_PIXEL;
+    if framebuffer.width < GLYPH_WIDTH as u32
+        || framebuffer.height < GLYPH_HEIGHT as u32
+        || framebuffer.stride < framebuffer.width
+        || len > framebuffer.size
+    {
+        return Err(FbconError::TooSmall);
+    }
+    let pixels = mmio::map_write_combining(framebuffer.address, len).map_err(FbconError::Map)?;
+
+    let color_code = ColorCode::new(Color::Yellow, Color::Black);
+    let blank = ScreenChar {
+        ascii_character: b' ',
+        color_code,
+    };
+    let screen = Screen::new(&framebuffer, pixels.as_mut_ptr(), blank);
+    let mut writer = TextWriter::new(screen, color_code);
+    writer.write_string(&console::log());
+
+    let console = CONSOLE.get_or_init(|| FramebufferConsole {
+        writer: Mutex::new(writer),
+    });
+    console::register(console);
+    println!(
+        "fbcon: fb0 {}x{}, {}x{} characters",
+        framebuffer.width,
+        framebuffer.height,
+        framebuffer.width as usize / GLYPH_WIDTH,
+        framebuffer.height as usize / GLYPH_HEIGHT
+    );
+    Ok(())
+}
+
+/// Warns if the framebuffer is not the mode requested with `video=`.
+///
+/// The EspressOS loader sets exactly the requested mode if the firmware
+/// offers it, while the `bootloader` crate 0.11 only takes it as the
+/// minimum size. Only 32 bits per pixel are supported.
+fn check_mode(framebuffer: &Framebuffer) {
+    let Some(mode) = cmdline::VIDEO_MODE else {
+        return;
+    };
+    if mode.bpp.is_some_and(|bpp| bpp != 32) {
+        println!("fbcon: only 32 bits per pixel are supported");
+    }
+    let matches = match boot::protocol() {
+        Protocol::Uefi => framebuffer.width == mode.width && framebuffer.height == mode.height,
+        _ => framebuffer.width >= mode.width && framebuffer.height >= mode.height,
+    };
+    if !matches {
+        println!(
+            "fbcon: requested {}x{}, got {}x{}",
+            mode.width, mode.height, framebuffer.width, framebuffer.height
+        );
+    }
+}
+
+/// Returns the pixel value of `rgb` in `format`.
+fn encode(format: PixelFormat, [red, green, blue]: [u8; 3]) -> u32 {
+    match format {
+        PixelFormat::Rgb => u32::from_le_bytes([red, green, blue, 0]),
+        PixelFormat::Bgr => u32::from_le_bytes([blue, green, red, 0]),
+        PixelFormat::Bitmask {
+            red: red_mask,
+            green: green_mask,
+            blue: blue_mask,
+        } => scale(red, red_mask) | scale(green, green_mask) | scale(blue, blue_mask),
+    }
+}
+
+/// Scales an 8-bit color component to the bits of `mask`.
+fn scale(value: u8, mask: u32) -> u32 {
+    if mask == 0 {
+        return 0;
+    }
+    let shift = mask.trailing_zeros();
+    let max = u64::from(mask >> shift);
+    ((u64::from(value) * max / 255) as u32) << shift
+}
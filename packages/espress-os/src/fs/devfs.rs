@@ -0,0 +1,297 @@
+//! # Device Filesystem
+//!
+//! A synthetic filesystem, mounted at `/dev`, in which devices appear as
+//! files:
+//!
+//! - character devices registered with [`register`]; the kernel provides
+//!   `console`, `serial0`, `null`, `zero`, and `random`;
+//! - every registered [block device](crate::block), under its own name,
+//!   read and written at any byte offset.
+//!
+//! The directory is built on every lookup, so devices registered later
+//! show up without remounting.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+use super::{DirEntry, FileSystem, FsError, Inode, Metadata, NodeKind};
+use crate::block::{self, BlockDevice};
+use crate::{console, print, rand, serial};
+
+/// Inode number of the `/dev` directory.
+const ROOT_INODE: u64 = 1;
+
+/// Inode numbers of block devices start here, after character devices.
+const BLOCK_INODE_BASE: u64 = 1 << 32;
+
+/// Registered character devices by name.
+static CHAR_DEVICES: Mutex<BTreeMap<String, CharEntry>> = Mutex::new(BTreeMap::new());
+
+/// A device read and written as a stream of bytes.
+pub trait CharDevice: Send + Sync {
+    /// Reads available bytes into `buf`.
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes read; zero means none are available.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`FsError::NotSupported`] if the device cannot be read.
+    fn read(&self, buf: &mut [u8]) -> Result<usize, FsError>;
+
+    /// Writes `buf` to the device.
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes written.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`FsError::NotSupported`] if the device cannot be written.
+    fn write(&self, buf: &[u8]) -> Result<usize, FsError>;
+}
+
+/// A registered character device.
+#[derive(Clone)]
+struct CharEntry {
+    /// Inode number
+    inode: u64,
+    /// The device
+    device: Arc<dyn CharDevice>,
+}
+
+/// Makes `device` appear as `/dev/<name>`, replacing any character device
+/// of the same name.
+pub fn register(name: &str, device: Arc<dyn CharDevice>) {
+    static NEXT_INODE: AtomicU64 = AtomicU64::new(ROOT_INODE + 1);
+    let inode = NEXT_INODE.fetch_add(1, Ordering::Relaxed);
+    CHAR_DEVICES
+        .lock()
+        .insert(String::from(name), CharEntry { inode, device });
+}
+
+/// Registers the kernel's own character devices.
+pub fn init() {
+    register("console", Arc::new(Console));
+    register("serial0", Arc::new(Serial));
+    register("null", Arc::new(Null));
+    register("zero", Arc::new(Zero));
+    register("random", Arc::new(Random));
+}
+
+/// The device filesystem.
+pub struct DevFs;
+
+impl FileSystem for DevFs {
+    fn name(&self) -> &str {
+        "devfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::new(DevDirectory)
+    }
+}
+
+/// The `/dev` directory.
+struct DevDirectory;
+
+impl Inode for DevDirectory {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            kind: NodeKind::Directory,
+            size: 0,
+            inode: ROOT_INODE,
+            mode: 0o755,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        if let Some(entry) = CHAR_DEVICES.lock().get(name).cloned() {
+            return Ok(Arc::new(CharNode(entry)));
+        }
+        let devices = block::devices();
+        let position = devices
+            .iter()
+            .position(|device| device.name() == name)
+            .ok_or(FsError::NotFound)?;
+        Ok(Arc::new(BlockNode {
+            inode: BLOCK_INODE_BASE + position as u64,
+            device: devices[position].clone(),
+        }))
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+        let mut entries: Vec<_> = CHAR_DEVICES
+            .lock()
+            .keys()
+            .map(|name| DirEntry {
+                name: name.clone(),
+                kind: NodeKind::CharDevice,
+            })
+            .collect();
+        entries.extend(block::devices().iter().map(|device| DirEntry {
+            name: String::from(device.name()),
+            kind: NodeKind::BlockDevice,
+        }));
+        Ok(entries)
+    }
+}
+
+/// A character device node; offsets are ignored.
+struct CharNode(CharEntry);
+
+impl Inode for CharNode {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            kind: NodeKind::CharDevice,
+            size: 0,
+            inode: self.0.inode,
+            mode: 0o666,
+        }
+    }
+
+    fn read_at(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        self.0.device.read(buf)
+    }
+
+    fn write_at(&self, _offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        self.0.device.write(buf)
+    }
+}
+
+/// A block device node.
+struct BlockNode {
+    /// Inode number
+    inode: u64,
+    /// The device
+    device: Arc<dyn BlockDevice>,
+}
+
+impl Inode for BlockNode {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            kind: NodeKind::BlockDevice,
+            size: self.device.size(),
+            inode: self.inode,
+            mode: 0o660,
+        }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let len = (buf.len() as u64).min(self.device.size().saturating_sub(offset)) as usize;
+        block::read_bytes(&*self.device, offset, &mut buf[..len])?;
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        let len = (buf.len() as u64).min(self.device.size().saturating_sub(offset)) as usize;
+        if len == 0 && !buf.is_empty() {
+            return Err(FsError::NoSpace);
+        }
+        block::write_bytes(&*self.device, offset, &buf[..len])?;
+        Ok(len)
+    }
+}
+
+/// `/dev/console`: output goes to every console backend, input comes from
+/// any of them.
+struct Console;
+
+impl CharDevice for Console {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, FsError> {
+        let mut read = 0;
+        while read < buf.len() {
+            match console::try_read() {
+                Some(byte) => buf[read] = byte,
+                None => break,
+            }
+            read += 1;
+        }
+        Ok(read)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, FsError> {
+        for piece in buf.utf8_chunks() {
+            print!("{}", piece.valid());
+            if !piece.invalid().is_empty() {
+                print!("{}", char::REPLACEMENT_CHARACTER);
+            }
+        }
+        Ok(buf.len())
+    }
+}
+
+/// `/dev/serial0`: raw bytes to and from COM1.
+struct Serial;
+
+impl CharDevice for Serial {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, FsError> {
+        let mut read = 0;
+        while read < buf.len() {
+            match serial::try_receive() {
+                Some(byte) => buf[read] = byte,
+                None => break,
+            }
+            read += 1;
+        }
+        Ok(read)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, FsError> {
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            let mut port = serial::SERIAL1.lock();
+            for &byte in buf {
+                port.send_raw(byte);
+            }
+        });
+        Ok(buf.len())
+    }
+}
+
+/// `/dev/null`: discards writes, reads nothing.
+struct Null;
+
+impl CharDevice for Null {
+    fn read(&self, _buf: &mut [u8]) -> Result<usize, FsError> {
+        Ok(0)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, FsError> {
+        Ok(buf.len())
+    }
+}
+
+/// `/dev/zero`: discards writes, reads zeros.
+struct Zero;
+
+impl CharDevice for Zero {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, FsError> {
+        buf.fill(0);
+        Ok(buf.len())
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, FsError> {
+        Ok(buf.len())
+    }
+}
+
+/// `/dev/random`: random bytes from the kernel generator; writes are mixed
+/// into it.
+struct Random;
+
+impl CharDevice for Random {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, FsError> {
+        rand::fill(buf);
+        Ok(buf.len())
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, FsError> {
+        rand::add_entropy(buf);
+        Ok(buf.len())
+    }
+}
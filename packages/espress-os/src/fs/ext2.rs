@@ -0,0 +1,528 @@
+//! # ext2 Filesystems
+//!
+//! A read-only driver for ext2 volumes, which also reads ext3 and ext4
+//! volumes as long as they use no feature that changes the layout beyond
+//! extents and 64-bit block numbers; the journal is ignored.
+//!
+//! The superblock, 1024 bytes into the volume, gives the block size and how
+//! blocks and inodes are split into block groups. A table of group
+//! descriptors follows it, each locating its group's inode table. An inode
+//! holds a file's type, size, and where its data is: either in the classic
+//! scheme of twelve direct block numbers followed by single, double, and
+//! triple indirect blocks, or, with the extents flag, in a tree of extents
+//! each mapping a run of logical blocks to consecutive physical blocks.
+//! Unmapped blocks read as zeros.
+//!
+//! Directories are files of variable-length records, each naming an inode.
+//! Hash-indexed directories keep that format, so a linear scan finds every
+//! entry.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::{DirEntry, FileSystem, FsError, Inode, Metadata, NodeKind};
+use crate::block::{self, BlockDevice};
+
+/// Byte offset of the superblock.
+const SUPERBLOCK_OFFSET: u64 = 1024;
+/// Size of the superblock.
+const SUPERBLOCK_SIZE: usize = 1024;
+/// Superblock magic number.
+const MAGIC: u16 = 0xef53;
+
+/// Inode number of the root directory.
+const ROOT_INODE: u32 = 2;
+
+/// Incompatible feature: directory entries record the file type.
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+/// Incompatible feature: the journal needs replaying.
+const INCOMPAT_RECOVER: u32 = 0x0004;
+/// Incompatible feature: files may use extent trees.
+const INCOMPAT_EXTENTS: u32 = 0x0040;
+/// Incompatible feature: block numbers and descriptors are 64-bit.
+const INCOMPAT_64BIT: u32 = 0x0080;
+/// Incompatible feature: group metadata is packed into flexible groups.
+const INCOMPAT_FLEX_BG: u32 = 0x0200;
+/// Incompatible features this driver can read.
+const INCOMPAT_SUPPORTED: u32 =
+    INCOMPAT_FILETYPE | INCOMPAT_RECOVER | INCOMPAT_EXTENTS | INCOMPAT_64BIT | INCOMPAT_FLEX_BG;
+
+/// Inode flag: the data is described by an extent tree.
+const INODE_FLAG_EXTENTS: u32 = 0x0008_0000;
+/// Inode flag: the data is stored inside the inode.
+const INODE_FLAG_INLINE_DATA: u32 = 0x1000_0000;
+
+/// Mode bits holding the file type.
+const MODE_TYPE_MASK: u16 = 0xf000;
+/// File type: directory.
+const MODE_DIRECTORY: u16 = 0x4000;
+/// File type: regular file.
+const MODE_FILE: u16 = 0x8000;
+/// File type: symbolic link.
+const MODE_SYMLINK: u16 = 0xa000;
+/// File type: character device.
+const MODE_CHAR_DEVICE: u16 = 0x2000;
+/// File type: block device.
+const MODE_BLOCK_DEVICE: u16 = 0x6000;
+
+/// Magic number of an extent tree node header.
+const EXTENT_MAGIC: u16 = 0xf30a;
+/// Size of an extent header and of each entry after it.
+const EXTENT_ENTRY_SIZE: usize = 12;
+/// Extent lengths above this mark preallocated, unwritten extents.
+const EXTENT_MAX_INIT_LEN: u16 = 32768;
+/// Deepest extent tree followed.
+const EXTENT_MAX_DEPTH: u16 = 5;
+
+/// Number of direct block pointers in an inode.
+const DIRECT_BLOCKS: u64 = 12;
+/// Size of the block map or extent root inside an inode.
+const BLOCK_MAP_SIZE: usize = 60;
+
+/// A mounted ext2 volume.
+pub struct Ext2Fs {
+    /// Shared state of the volume
+    volume: Arc<Volume>,
+    /// Root directory, read at mount time
+    root: Arc<Ext2Node>,
+}
+
+impl Ext2Fs {
+    /// Reads the superblock of `device` and prepares the volume.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`FsError::NotSupported`] if the device holds no ext2
+    /// volume or the volume uses unsupported features,
+    /// [`FsError::Corrupted`] if the superblock or root directory is
+    /// inconsistent, and [`FsError::Io`] if the device fails.
+    pub fn new(device: Arc<dyn BlockDevice>) -> Result<Self, FsError> {
+        let mut sb = [0; SUPERBLOCK_SIZE];
+        block::read_bytes(&*device, SUPERBLOCK_OFFSET, &mut sb)?;
+        if read_u16(&sb, 56) != MAGIC {
+            return Err(FsError::NotSupported);
+        }
+        let incompat = read_u32(&sb, 96);
+        if incompat & !INCOMPAT_SUPPORTED != 0 {
+            return Err(FsError::NotSupported);
+        }
+
+        let log_block_size = read_u32(&sb, 24);
+        if log_block_size > 6 {
+            return Err(FsError::Corrupted);
+        }
+        let block_size = 1024u64 << log_block_size;
+        let revision = read_u32(&sb, 76);
+        let inode_size = if revision == 0 {
+            128
+        } else {
+            u64::from(read_u16(&sb, 88))
+        };
+        let desc_size = if incompat & INCOMPAT_64BIT != 0 {
+            u64::from(read_u16(&sb, 254)).max(32)
+        } else {
+            32
+        };
+        let inodes_per_group = read_u32(&sb, 40);
+        let blocks_count = u64::from(read_u32(&sb, 4));
+        if !(128..=block_size).contains(&inode_size)
+            || !inode_size.is_power_of_two()
+            || inodes_per_group == 0
+            || blocks_count * block_size > device.size()
+        {
+            return Err(FsError::Corrupted);
+        }
+
+        let volume = Arc::new(Volume {
+            device,
+            block_size,
+            inode_size,
+            desc_size,
+            descriptors_block: u64::from(read_u32(&sb, 20)) + 1,
+            inodes_per_group,
+            inodes_count: read_u32(&sb, 0),
+            filetype: incompat & INCOMPAT_FILETYPE != 0,
+            is_64bit: incompat & INCOMPAT_64BIT != 0,
+        });
+        let root = volume.node(ROOT_INODE)?;
+        if root.kind() != NodeKind::Directory {
+            return Err(FsError::Corrupted);
+        }
+        Ok(Ext2Fs {
+            volume,
+            root: Arc::new(root),
+        })
+    }
+
+    /// Size of a filesystem block in bytes.
+    pub fn block_size(&self) -> u64 {
+        self.volume.block_size
+    }
+}
+
+impl FileSystem for Ext2Fs {
+    fn name(&self) -> &str {
+        "ext2"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+/// Geometry and device of a volume, shared by its nodes.
+struct Volume {
+    /// Device holding the volume
+    device: Arc<dyn BlockDevice>,
+    /// Bytes per block
+    block_size: u64,
+    /// Bytes per inode table entry
+    inode_size: u64,
+    /// Bytes per group descriptor
+    desc_size: u64,
+    /// Block holding the first group descriptor
+    descriptors_block: u64,
+    /// Inodes in each block group
+    inodes_per_group: u32,
+    /// Total number of inodes
+    inodes_count: u32,
+    /// Directory entries record the file type
+    filetype: bool,
+    /// Block numbers in group descriptors are 64-bit
+    is_64bit: bool,
+}
+
+/// The parts of an on-disk inode the driver uses.
+#[derive(Clone, Copy)]
+struct RawInode {
+    /// Type and permission bits
+    mode: u16,
+    /// Size in bytes
+    size: u64,
+    /// Inode flags
+    flags: u32,
+    /// Block map, extent tree root, or fast symlink target
+    block_map: [u8; BLOCK_MAP_SIZE],
+}
+
+impl Volume {
+    /// Reads inode `number`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`FsError::Corrupted`] for numbers outside the volume.
+    fn node(self: &Arc<Self>, number: u32) -> Result<Ext2Node, FsError> {
+        if number == 0 || number > self.inodes_count {
+            return Err(FsError::Corrupted);
+        }
+        let group = u64::from((number - 1) / self.inodes_per_group);
+        let index = u64::from((number - 1) % self.inodes_per_group);
+
+        let mut desc = [0; 64];
+        let desc_offset = self.descriptors_block * self.block_size + group * self.desc_size;
+        block::read_bytes(
+            &*self.device,
+            desc_offset,
+            &mut desc[..self.desc_size.min(64) as usize],
+        )?;
+        let mut inode_table = u64::from(read_u32(&desc, 8));
+        if self.is_64bit && self.desc_size >= 64 {
+            inode_table |= u64::from(read_u32(&desc, 0x28)) << 32;
+        }
+
+        let mut raw = [0; 128];
+        let offset = inode_table * self.block_size + index * self.inode_size;
+        block::read_bytes(&*self.device, offset, &mut raw)?;
+        let mode = read_u16(&raw, 0);
+        let mut size = u64::from(read_u32(&raw, 4));
+        if mode & MODE_TYPE_MASK == MODE_FILE {
+            size |= u64::from(read_u32(&raw, 108)) << 32;
+        }
+        let mut block_map = [0; BLOCK_MAP_SIZE];
+        block_map.copy_from_slice(&raw[40..40 + BLOCK_MAP_SIZE]);
+
+        Ok(Ext2Node {
+            volume: self.clone(),
+            number,
+            raw: RawInode {
+                mode,
+                size,
+                flags: read_u32(&raw, 32),
+                block_map,
+            },
+        })
+    }
+
+    /// Reads block `block` into `buf`, which is one block long.
+    fn read_block(&self, block: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        block::read_bytes(&*self.device, block * self.block_size, buf)?;
+        Ok(())
+    }
+
+    /// Maps logical block `logical` of a file through its classic block
+    /// map.
+    ///
+    /// # Returns
+    ///
+    /// The physical block, or `None` for a hole.
+    fn map_indirect(
+        &self,
+        map: &[u8; BLOCK_MAP_SIZE],
+        logical: u64,
+    ) -> Result<Option<u64>, FsError> {
+        let per_block = self.block_size / 4;
+        if logical < DIRECT_BLOCKS {
+            return Ok(nonzero(read_u32(map, logical as usize * 4)));
+        }
+        // Number of levels below the pointer in the inode, and the index of
+        // the block within the range that pointer covers.
+        let mut remaining = logical - DIRECT_BLOCKS;
+        let mut level = 0;
+        let mut span = per_block;
+        while remaining >= span {
+            remaining -= span;
+            level += 1;
+            if level == 3 {
+                return Err(FsError::Corrupted);
+            }
+            span *= per_block;
+        }
+
+        let mut block = match nonzero(read_u32(map, (DIRECT_BLOCKS as usize + level) * 4)) {
+            Some(block) => block,
+            None => return Ok(None),
+        };
+        let mut pointers = vec![0; self.block_size as usize];
+        for _ in 0..=level {
+            span /= per_block;
+            self.read_block(block, &mut pointers)?;
+            let index = (remaining / span) as usize;
+            remaining %= span;
+            block = match nonzero(read_u32(&pointers, index * 4)) {
+                Some(block) => block,
+                None => return Ok(None),
+            };
+        }
+        Ok(Some(block))
+    }
+
+    /// Maps logical block `logical` of a file through its extent tree.
+    ///
+    /// # Returns
+    ///
+    /// The physical block, or `None` for a hole or an unwritten extent.
+    fn map_extent(&self, map: &[u8; BLOCK_MAP_SIZE], logical: u64) -> Result<Option<u64>, FsError> {
+        let mut node = map.to_vec();
+        for _ in 0..=EXTENT_MAX_DEPTH {
+            if read_u16(&node, 0) != EXTENT_MAGIC {
+                return Err(FsError::Corrupted);
+            }
+            let entries = usize::from(read_u16(&node, 2));
+            let depth = read_u16(&node, 6);
+            if EXTENT_ENTRY_SIZE * (entries + 1) > node.len() {
+                return Err(FsError::Corrupted);
+            }
+            let entry_at = |i: usize| EXTENT_ENTRY_SIZE * (i + 1);
+            // The last entry starting at or before the block covers it.
+            let found = (0..entries)
+                .take_while(|&i| u64::from(read_u32(&node, entry_at(i))) <= logical)
+                .last();
+            let Some(i) = found else {
+                return Ok(None);
+            };
+            let entry = entry_at(i);
+
+            if depth == 0 {
+                let first = u64::from(read_u32(&node, entry));
+                let len = read_u16(&node, entry + 4);
+                if len > EXTENT_MAX_INIT_LEN || logical >= first + u64::from(len) {
+                    return Ok(None);
+                }
+                let start = (u64::from(read_u16(&node, entry + 6)) << 32)
+                    | u64::from(read_u32(&node, entry + 8));
+                return Ok(Some(start + logical - first));
+            }
+            let child = u64::from(read_u32(&node, entry + 4))
+                | (u64::from(read_u16(&node, entry + 8)) << 32);
+            node = vec![0; self.block_size as usize];
+            self.read_block(child, &mut node)?;
+        }
+        Err(FsError::Corrupted)
+    }
+}
+
+/// A file, directory, or other node of an ext2 volume.
+struct Ext2Node {
+    /// Volume holding the node
+    volume: Arc<Volume>,
+    /// Inode number
+    number: u32,
+    /// The inode
+    raw: RawInode,
+}
+
+impl Ext2Node {
+    /// Node type from the mode.
+    fn kind(&self) -> NodeKind {
+        match self.raw.mode & MODE_TYPE_MASK {
+            MODE_DIRECTORY => NodeKind::Directory,
+            MODE_SYMLINK => NodeKind::Symlink,
+            MODE_CHAR_DEVICE => NodeKind::CharDevice,
+            MODE_BLOCK_DEVICE => NodeKind::BlockDevice,
+            _ => NodeKind::File,
+        }
+    }
+
+    /// Maps logical block `logical` to a physical block, `None` for a hole.
+    fn map(&self, logical: u64) -> Result<Option<u64>, FsError> {
+        if self.raw.flags & INODE_FLAG_EXTENTS != 0 {
+            self.volume.map_extent(&self.raw.block_map, logical)
+        } else {
+            self.volume.map_indirect(&self.raw.block_map, logical)
+        }
+    }
+
+    /// Returns `true` for a symbolic link whose target is stored in the
+    /// inode itself.
+    fn is_fast_symlink(&self) -> bool {
+        self.kind() == NodeKind::Symlink
+            && self.raw.size < BLOCK_MAP_SIZE as u64
+            && self.raw.flags & INODE_FLAG_EXTENTS == 0
+    }
+
+    /// Reads the file from `offset`, block by block.
+    fn read_data(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        if self.raw.flags & INODE_FLAG_INLINE_DATA != 0 {
+            return Err(FsError::NotSupported);
+        }
+        if self.is_fast_symlink() {
+            let target = &self.raw.block_map[..self.raw.size as usize];
+            let start = (offset as usize).min(target.len());
+            let len = buf.len().min(target.len() - start);
+            buf[..len].copy_from_slice(&target[start..start + len]);
+            return Ok(len);
+        }
+
+        let len = (buf.len() as u64).min(self.raw.size.saturating_sub(offset)) as usize;
+        let block_size = self.volume.block_size;
+        let mut block = vec![0; block_size as usize];
+        let mut done = 0;
+        while done < len {
+            let position = offset + done as u64;
+            let within = (position % block_size) as usize;
+            let chunk = (len - done).min(block_size as usize - within);
+            match self.map(position / block_size)? {
+                Some(physical) => {
+                    self.volume.read_block(physical, &mut block)?;
+                    buf[done..done + chunk].copy_from_slice(&block[within..within + chunk]);
+                }
+                None => buf[done..done + chunk].fill(0),
+            }
+            done += chunk;
+        }
+        Ok(len)
+    }
+
+    /// Parses the directory into names, inode numbers, and types.
+    fn entries(&self) -> Result<Vec<(String, u32, Option<NodeKind>)>, FsError> {
+        if self.kind() != NodeKind::Directory {
+            return Err(FsError::NotDirectory);
+        }
+        let mut data = vec![0; self.raw.size as usize];
+        let read = self.read_data(0, &mut data)?;
+        data.truncate(read);
+
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        while offset + 8 <= data.len() {
+            let inode = read_u32(&data, offset);
+            let rec_len = usize::from(read_u16(&data, offset + 4));
+            let (name_len, file_type) = if self.volume.filetype {
+                (usize::from(data[offset + 6]), Some(data[offset + 7]))
+            } else {
+                (usize::from(read_u16(&data, offset + 6)), None)
+            };
+            if rec_len < 8 || offset + rec_len > data.len() || 8 + name_len > rec_len {
+                return Err(FsError::Corrupted);
+            }
+            let name = &data[offset + 8..offset + 8 + name_len];
+            if inode != 0 && name != b"." && name != b".." {
+                let kind = file_type.and_then(|file_type| match file_type {
+                    1 => Some(NodeKind::File),
+                    2 => Some(NodeKind::Directory),
+                    3 => Some(NodeKind::CharDevice),
+                    4 => Some(NodeKind::BlockDevice),
+                    7 => Some(NodeKind::Symlink),
+                    _ => None,
+                });
+                entries.push((String::from_utf8_lossy(name).into_owned(), inode, kind));
+            }
+            offset += rec_len;
+        }
+        Ok(entries)
+    }
+}
+
+impl Inode for Ext2Node {
+    fn metadata(&self) -> Metadata {
+        let kind = self.kind();
+        Metadata {
+            kind,
+            size: match kind {
+                NodeKind::File | NodeKind::Symlink => self.raw.size,
+                _ => 0,
+            },
+            inode: u64::from(self.number),
+            mode: self.raw.mode & 0o7777,
+        }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        match self.kind() {
+            NodeKind::Directory => Err(FsError::IsDirectory),
+            NodeKind::File | NodeKind::Symlink => self.read_data(offset, buf),
+            _ => Err(FsError::NotSupported),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        let (_, number, _) = self
+            .entries()?
+            .into_iter()
+            .find(|(entry, _, _)| entry == name)
+            .ok_or(FsError::NotFound)?;
+        Ok(Arc::new(self.volume.node(number)?))
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+        self.entries()?
+            .into_iter()
+            .map(|(name, number, kind)| {
+                let kind = match kind {
+                    Some(kind) => kind,
+                    None => self.volume.node(number)?.kind(),
+                };
+                Ok(DirEntry { name, kind })
+            })
+            .collect()
+    }
+}
+
+/// Turns a zero block number (a hole) into `None`.
+fn nonzero(block: u32) -> Option<u64> {
+    (block != 0).then_some(u64::from(block))
+}
+
+/// Reads a little-endian `u16` at `offset`.
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+/// Reads a little-endian `u32` at `offset`.
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
@@ -0,0 +1,583 @@
+//! # FAT Filesystems
+//!
+//! A read-only driver for FAT16 and FAT32 volumes, the format host tools
+//! most readily produce (`mkfs.fat`, `mtools`).
+//!
+//! A volume starts with the boot sector and its BIOS parameter block, which
+//! gives the geometry: reserved sectors, one or more copies of the file
+//! allocation table, the fixed root directory region (FAT16 only), and the
+//! data area divided into clusters numbered from 2. The FAT has one entry
+//! per cluster holding the number of the next cluster of the same file, or
+//! an end-of-chain marker.
+//!
+//! Directories are files of 32-byte entries. A file's name is an 8.3 short
+//! name in its main entry, optionally preceded by long file name entries
+//! carrying up to 13 UTF-16 characters each, in reverse order and tied to
+//! the main entry by a checksum of the short name. Names are compared
+//! ignoring ASCII case, as FAT does.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use super::{DirEntry, FileSystem, FsError, Inode, Metadata, NodeKind};
+use crate::block::{self, BlockDevice, SECTOR_SIZE};
+
+/// Size of a directory entry.
+const DIR_ENTRY_SIZE: usize = 32;
+
+/// Attribute: the entry may not be written.
+const ATTR_READ_ONLY: u8 = 0x01;
+/// Attribute: the entry is the volume label.
+const ATTR_VOLUME_ID: u8 = 0x08;
+/// Attribute: the entry is a directory.
+const ATTR_DIRECTORY: u8 = 0x10;
+/// Attribute combination marking a long file name entry.
+const ATTR_LONG_NAME: u8 = 0x0f;
+
+/// First byte of a deleted entry.
+const ENTRY_DELETED: u8 = 0xe5;
+/// Flag in the sequence number of the last long name entry.
+const LFN_LAST: u8 = 0x40;
+/// Short name flag: the base name is lowercase.
+const CASE_LOWER_BASE: u8 = 0x08;
+/// Short name flag: the extension is lowercase.
+const CASE_LOWER_EXT: u8 = 0x10;
+
+/// Fewest clusters of a FAT16 volume; fewer make it FAT12.
+const FAT16_MIN_CLUSTERS: u32 = 4085;
+/// Fewest clusters of a FAT32 volume.
+const FAT32_MIN_CLUSTERS: u32 = 65525;
+
+/// Inode number of the root directory, which has no entry of its own.
+const ROOT_INODE: u64 = 1;
+
+/// FAT variant of a volume.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatType {
+    /// 16-bit FAT entries and a fixed root directory
+    Fat16,
+    /// 28-bit FAT entries and a root directory in clusters
+    Fat32,
+}
+
+/// A mounted FAT volume.
+pub struct FatFs {
+    /// Shared state of the volume
+    volume: Arc<Volume>,
+}
+
+impl FatFs {
+    /// Reads the boot sector of `device` and prepares the volume.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`FsError::NotSupported`] if the device holds no FAT16 or
+    /// FAT32 volume, [`FsError::Corrupted`] if the geometry is
+    /// inconsistent, and [`FsError::Io`] if the device fails.
+    pub fn new(device: Arc<dyn BlockDevice>) -> Result<Self, FsError> {
+        let mut boot = [0; SECTOR_SIZE];
+        block::read_bytes(&*device, 0, &mut boot)?;
+        if boot[510..512] != [0x55, 0xaa] {
+            return Err(FsError::NotSupported);
+        }
+
+        let bytes_per_sector = u64::from(read_u16(&boot, 11));
+        let sectors_per_cluster = u64::from(boot[13]);
+        let reserved = u64::from(read_u16(&boot, 14));
+        let fat_count = u64::from(boot[16]);
+        let root_entries = u64::from(read_u16(&boot, 17));
+        let total = match read_u16(&boot, 19) {
+            0 => u64::from(read_u32(&boot, 32)),
+            total => u64::from(total),
+        };
+        let fat_sectors = match read_u16(&boot, 22) {
+            0 => u64::from(read_u32(&boot, 36)),
+            size => u64::from(size),
+        };
+        if !bytes_per_sector.is_power_of_two()
+            || !(512..=4096).contains(&bytes_per_sector)
+            || !sectors_per_cluster.is_power_of_two()
+            || reserved == 0
+            || fat_count == 0
+            || fat_sectors == 0
+        {
+            return Err(FsError::NotSupported);
+        }
+
+        let root_sectors = (root_entries * DIR_ENTRY_SIZE as u64).div_ceil(bytes_per_sector);
+        let data_sector = reserved + fat_count * fat_sectors + root_sectors;
+        if data_sector >= total || total * bytes_per_sector > device.size() {
+            return Err(FsError::Corrupted);
+        }
+        let cluster_count = ((total - data_sector) / sectors_per_cluster) as u32;
+        let fat_type = if cluster_count < FAT16_MIN_CLUSTERS {
+            return Err(FsError::NotSupported);
+        } else if cluster_count < FAT32_MIN_CLUSTERS {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        };
+        let entry_size = if fat_type == FatType::Fat16 { 2 } else { 4 };
+        if fat_sectors * bytes_per_sector < (u64::from(cluster_count) + 2) * entry_size {
+            return Err(FsError::Corrupted);
+        }
+
+        Ok(FatFs {
+            volume: Arc::new(Volume {
+                device,
+                fat_type,
+                cluster_size: sectors_per_cluster * bytes_per_sector,
+                fat_offset: reserved * bytes_per_sector,
+                root_offset: (reserved + fat_count * fat_sectors) * bytes_per_sector,
+                root_size: root_entries * DIR_ENTRY_SIZE as u64,
+                data_offset: data_sector * bytes_per_sector,
+                root_cluster: read_u32(&boot, 44),
+                cluster_count,
+                fat_cache: Mutex::new(None),
+            }),
+        })
+    }
+
+    /// FAT variant of the volume.
+    pub fn fat_type(&self) -> FatType {
+        self.volume.fat_type
+    }
+}
+
+impl FileSystem for FatFs {
+    fn name(&self) -> &str {
+        match self.volume.fat_type {
+            FatType::Fat16 => "fat16",
+            FatType::Fat32 => "fat32",
+        }
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        let first_cluster = match self.volume.fat_type {
+            FatType::Fat16 => 0,
+            FatType::Fat32 => self.volume.root_cluster,
+        };
+        Arc::new(FatNode {
+            volume: self.volume.clone(),
+            kind: NodeKind::Directory,
+            first_cluster,
+            size: 0,
+            inode: ROOT_INODE,
+            read_only: false,
+        })
+    }
+}
+
+/// Geometry and device of a volume, shared by its nodes.
+struct Volume {
+    /// Device holding the volume
+    device: Arc<dyn BlockDevice>,
+    /// FAT variant
+    fat_type: FatType,
+    /// Bytes per cluster
+    cluster_size: u64,
+    /// Byte offset of the first FAT
+    fat_offset: u64,
+    /// Byte offset of the FAT16 root directory
+    root_offset: u64,
+    /// Size of the FAT16 root directory in bytes
+    root_size: u64,
+    /// Byte offset of cluster 2
+    data_offset: u64,
+    /// First cluster of the FAT32 root directory
+    root_cluster: u32,
+    /// Number of data clusters
+    cluster_count: u32,
+    /// Most recently read FAT sector and its byte offset
+    fat_cache: Mutex<Option<(u64, [u8; SECTOR_SIZE])>>,
+}
+
+impl Volume {
+    /// Returns the cluster following `cluster`, or `None` at the end of the
+    /// chain.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`FsError::Corrupted`] for free or bad clusters and
+    /// references outside the volume.
+    fn next_cluster(&self, cluster: u32) -> Result<Option<u32>, FsError> {
+        let (entry_size, end_of_chain, mask) = match self.fat_type {
+            FatType::Fat16 => (2, 0xfff8, 0xffff),
+            FatType::Fat32 => (4, 0x0fff_fff8, 0x0fff_ffff),
+        };
+        let offset = self.fat_offset + u64::from(cluster) * entry_size;
+        let sector = offset - offset % SECTOR_SIZE as u64;
+        let within = (offset - sector) as usize;
+
+        let mut cache = self.fat_cache.lock();
+        if cache.as_ref().is_none_or(|(cached, _)| *cached != sector) {
+            let mut data = [0; SECTOR_SIZE];
+            block::read_bytes(&*self.device, sector, &mut data)?;
+            *cache = Some((sector, data));
+        }
+        let (_, data) = cache.as_ref().unwrap();
+        let next = match self.fat_type {
+            FatType::Fat16 => u32::from(read_u16(data, within)),
+            FatType::Fat32 => read_u32(data, within),
+        } & mask;
+
+        if next >= end_of_chain {
+            Ok(None)
+        } else if self.is_valid(next) {
+            Ok(Some(next))
+        } else {
+            Err(FsError::Corrupted)
+        }
+    }
+
+    /// Returns `true` if `cluster` is a data cluster of the volume.
+    fn is_valid(&self, cluster: u32) -> bool {
+        (2..self.cluster_count + 2).contains(&cluster)
+    }
+
+    /// Byte offset of `cluster` on the device.
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.data_offset + u64::from(cluster - 2) * self.cluster_size
+    }
+
+    /// Reads the chain starting at `first` from byte `offset`, at most
+    /// `limit` bytes or until the chain ends.
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes read.
+    fn read_chain(
+        &self,
+        first: u32,
+        offset: u64,
+        buf: &mut [u8],
+        limit: u64,
+    ) -> Result<usize, FsError> {
+        let len = (buf.len() as u64).min(limit.saturating_sub(offset)) as usize;
+        if len == 0 || first == 0 {
+            return Ok(0);
+        }
+        if !self.is_valid(first) {
+            return Err(FsError::Corrupted);
+        }
+
+        let mut cluster = first;
+        for _ in 0..offset / self.cluster_size {
+            match self.next_cluster(cluster)? {
+                Some(next) => cluster = next,
+                None => return Ok(0),
+            }
+        }
+        let mut within = offset % self.cluster_size;
+        let mut done = 0;
+        while done < len {
+            let chunk = (len - done).min((self.cluster_size - within) as usize);
+            let at = self.cluster_offset(cluster) + within;
+            block::read_bytes(&*self.device, at, &mut buf[done..done + chunk])?;
+            done += chunk;
+            within = 0;
+            if done < len {
+                match self.next_cluster(cluster)? {
+                    Some(next) => cluster = next,
+                    None => break,
+                }
+            }
+        }
+        Ok(done)
+    }
+
+    /// Reads the raw entries of the directory starting at `first`, where
+    /// zero stands for the FAT16 root directory.
+    ///
+    /// # Returns
+    ///
+    /// The entries and the byte offset of each cluster on the device.
+    fn read_directory(&self, first: u32) -> Result<(Vec<u8>, Vec<u64>), FsError> {
+        if first == 0 {
+            let mut data = vec![0; self.root_size as usize];
+            block::read_bytes(&*self.device, self.root_offset, &mut data)?;
+            return Ok((data, vec![self.root_offset]));
+        }
+        if !self.is_valid(first) {
+            return Err(FsError::Corrupted);
+        }
+        let mut data = Vec::new();
+        let mut offsets = Vec::new();
+        let mut cluster = Some(first);
+        while let Some(current) = cluster {
+            // A cycle in the chain would otherwise never end.
+            if offsets.len() as u32 >= self.cluster_count {
+                return Err(FsError::Corrupted);
+            }
+            let offset = self.cluster_offset(current);
+            let start = data.len();
+            data.resize(start + self.cluster_size as usize, 0);
+            block::read_bytes(&*self.device, offset, &mut data[start..])?;
+            offsets.push(offset);
+            cluster = self.next_cluster(current)?;
+        }
+        Ok((data, offsets))
+    }
+}
+
+/// A file or directory of a FAT volume.
+struct FatNode {
+    /// Volume holding the node
+    volume: Arc<Volume>,
+    /// File or directory
+    kind: NodeKind,
+    /// First cluster, zero for empty files and the FAT16 root directory
+    first_cluster: u32,
+    /// File size in bytes
+    size: u64,
+    /// Node number: the first cluster, or the position of the entry for
+    /// empty files
+    inode: u64,
+    /// The read-only attribute is set
+    read_only: bool,
+}
+
+impl FatNode {
+    /// Parses the directory into its named entries.
+    fn entries(&self) -> Result<Vec<FatEntry>, FsError> {
+        if self.kind != NodeKind::Directory {
+            return Err(FsError::NotDirectory);
+        }
+        let (data, offsets) = self.volume.read_directory(self.first_cluster)?;
+        let chunk = match self.first_cluster {
+            0 => self.volume.root_size,
+            _ => self.volume.cluster_size,
+        };
+        let position = |index: usize| {
+            let byte = (index * DIR_ENTRY_SIZE) as u64;
+            offsets[(byte / chunk) as usize] + byte % chunk
+        };
+        Ok(parse_directory(&data, position))
+    }
+}
+
+impl Inode for FatNode {
+    fn metadata(&self) -> Metadata {
+        let mode = match (self.kind, self.read_only) {
+            (NodeKind::Directory, _) => 0o755,
+            (_, false) => 0o644,
+            (_, true) => 0o444,
+        };
+        Metadata {
+            kind: self.kind,
+            size: self.size,
+            inode: self.inode,
+            mode,
+        }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        if self.kind == NodeKind::Directory {
+            return Err(FsError::IsDirectory);
+        }
+        self.volume
+            .read_chain(self.first_cluster, offset, buf, self.size)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        let entry = self
+            .entries()?
+            .into_iter()
+            .find(|entry| entry.name.eq_ignore_ascii_case(name))
+            .ok_or(FsError::NotFound)?;
+        let inode = match entry.first_cluster {
+            0 => entry.position,
+            cluster => u64::from(cluster),
+        };
+        Ok(Arc::new(FatNode {
+            volume: self.volume.clone(),
+            kind: entry.kind,
+            first_cluster: entry.first_cluster,
+            size: entry.size,
+            inode,
+            read_only: entry.read_only,
+        }))
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+        Ok(self
+            .entries()?
+            .into_iter()
+            .map(|entry| DirEntry {
+                name: entry.name,
+                kind: entry.kind,
+            })
+            .collect())
+    }
+}
+
+/// A parsed directory entry.
+struct FatEntry {
+    /// Long name, or the short name if there is none
+    name: String,
+    /// File or directory
+    kind: NodeKind,
+    /// First cluster, zero for empty files
+    first_cluster: u32,
+    /// Size in bytes, zero for directories
+    size: u64,
+    /// Byte position of the entry on the device
+    position: u64,
+    /// The read-only attribute is set
+    read_only: bool,
+}
+
+/// Parses raw directory entries, skipping deleted ones, the volume label,
+/// and `.` and `..`.
+///
+/// `position` maps the index of an entry to its byte position on the
+/// device.
+fn parse_directory(data: &[u8], position: impl Fn(usize) -> u64) -> Vec<FatEntry> {
+    let mut entries = Vec::new();
+    let mut long_name = LongName::default();
+
+    for (index, raw) in data.as_chunks::<DIR_ENTRY_SIZE>().0.iter().enumerate() {
+        match raw[0] {
+            0 => break,
+            ENTRY_DELETED => {
+                long_name = LongName::default();
+                continue;
+            }
+            _ => {}
+        }
+        let attr = raw[11];
+        if attr & 0x3f == ATTR_LONG_NAME {
+            long_name.add(raw);
+            continue;
+        }
+        if attr & ATTR_VOLUME_ID != 0 || raw[0] == b'.' {
+            long_name = LongName::default();
+            continue;
+        }
+
+        let name = match long_name.take(checksum(&raw[..11])) {
+            Some(name) => name,
+            None => short_name(raw),
+        };
+        let is_directory = attr & ATTR_DIRECTORY != 0;
+        let first_cluster = (u32::from(read_u16(raw, 20)) << 16) | u32::from(read_u16(raw, 26));
+        entries.push(FatEntry {
+            name,
+            kind: if is_directory {
+                NodeKind::Directory
+            } else {
+                NodeKind::File
+            },
+            first_cluster,
+            size: if is_directory {
+                0
+            } else {
+                u64::from(read_u32(raw, 28))
+            },
+            position: position(index),
+            read_only: attr & ATTR_READ_ONLY != 0,
+        });
+    }
+    entries
+}
+
+/// Long name entries collected ahead of a main entry.
+#[derive(Default)]
+struct LongName {
+    /// Characters by position, 13 per entry
+    units: Vec<u16>,
+    /// Short name checksum all entries carry
+    checksum: Option<u8>,
+    /// The sequence is broken and must be ignored
+    broken: bool,
+}
+
+impl LongName {
+    /// Adds the long name entry `raw`.
+    fn add(&mut self, raw: &[u8; DIR_ENTRY_SIZE]) {
+        let sequence = raw[0];
+        let index = usize::from(sequence & 0x1f);
+        if sequence & LFN_LAST != 0 {
+            *self = LongName {
+                units: vec![0xffff; index * 13],
+                checksum: Some(raw[13]),
+                broken: index == 0,
+            };
+        }
+        if index == 0 || index * 13 > self.units.len() || self.checksum != Some(raw[13]) {
+            self.broken = true;
+            return;
+        }
+        let start = (index - 1) * 13;
+        let offsets = (1..11)
+            .step_by(2)
+            .chain((14..26).step_by(2))
+            .chain((28..32).step_by(2));
+        for (i, offset) in offsets.enumerate() {
+            self.units[start + i] = read_u16(raw, offset);
+        }
+    }
+
+    /// Returns the collected name if it belongs to the short name with
+    /// `checksum`, and starts over.
+    fn take(&mut self, checksum: u8) -> Option<String> {
+        let long_name = core::mem::take(self);
+        if long_name.broken || long_name.checksum != Some(checksum) {
+            return None;
+        }
+        let len = long_name
+            .units
+            .iter()
+            .position(|&unit| unit == 0 || unit == 0xffff)
+            .unwrap_or(long_name.units.len());
+        Some(String::from_utf16_lossy(&long_name.units[..len]))
+    }
+}
+
+/// Formats the 8.3 name of the main entry `raw`.
+fn short_name(raw: &[u8]) -> String {
+    let case = raw[12];
+    let part = |bytes: &[u8], lower: bool| -> String {
+        let mut part = String::new();
+        for (i, &byte) in bytes.iter().enumerate() {
+            // 0x05 stands for a leading 0xe5 byte.
+            let byte = if i == 0 && byte == 0x05 { 0xe5 } else { byte };
+            part.push(if lower {
+                char::from(byte.to_ascii_lowercase())
+            } else {
+                char::from(byte)
+            });
+        }
+        String::from(part.trim_end())
+    };
+    let mut name = part(&raw[..8], case & CASE_LOWER_BASE != 0);
+    let extension = part(&raw[8..11], case & CASE_LOWER_EXT != 0);
+    if !extension.is_empty() {
+        name.push('.');
+        name.push_str(&extension);
+    }
+    name
+}
+
+/// Computes the checksum of an 11-byte short name that ties long name
+/// entries to it.
+fn checksum(short_name: &[u8]) -> u8 {
+    short_name
+        .iter()
+        .fold(0u8, |sum, &byte| sum.rotate_right(1).wrapping_add(byte))
+}
+
+/// Reads a little-endian `u16` at `offset`.
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+/// Reads a little-endian `u32` at `offset`.
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
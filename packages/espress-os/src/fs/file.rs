@@ -0,0 +1,219 @@
+//! # Open Files
+//!
+//! An [`OpenFile`] is what a handle to a file refers to: the node, the
+//! access mode it was opened with, and the current position, which reads
+//! and writes advance. Handles duplicated from one another share the
+//! position, while every [`open`] starts a new one.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::fmt;
+use spin::Mutex;
+
+use super::{DirEntry, FsError, Inode, NodeKind};
+
+/// Open for reading only.
+pub const O_RDONLY: u32 = 0;
+/// Open for writing only.
+pub const O_WRONLY: u32 = 1;
+/// Open for reading and writing.
+pub const O_RDWR: u32 = 2;
+/// Bits selecting the access mode.
+pub const O_ACCMODE: u32 = 3;
+/// Create the file if it does not exist.
+pub const O_CREAT: u32 = 0x40;
+/// With [`O_CREAT`], fail if the file exists.
+pub const O_EXCL: u32 = 0x80;
+/// Truncate the file to zero length.
+pub const O_TRUNC: u32 = 0x200;
+/// Write at the end of the file.
+pub const O_APPEND: u32 = 0x400;
+/// Fail unless the path is a directory.
+pub const O_DIRECTORY: u32 = 0x1_0000;
+
+/// Flags [`open`] understands.
+const O_SUPPORTED: u32 = O_ACCMODE | O_CREAT | O_EXCL | O_TRUNC | O_APPEND | O_DIRECTORY;
+
+/// Reference point of a seek.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Whence {
+    /// From the start of the file
+    Set,
+    /// From the current position
+    Current,
+    /// From the end of the file
+    End,
+}
+
+/// An open file, directory, or device.
+pub struct OpenFile {
+    /// Path the file was opened by
+    path: String,
+    /// The node
+    inode: Arc<dyn Inode>,
+    /// Flags passed to [`open`]
+    flags: u32,
+    /// Byte offset, or entry index for directories
+    position: Mutex<u64>,
+}
+
+/// Opens `path` with the `O_*` `flags`.
+///
+/// # Errors
+///
+/// Returns [`FsError::InvalidArgument`] for unknown flags,
+/// [`FsError::IsDirectory`] for writing to a directory,
+/// [`FsError::AlreadyExists`] for [`O_EXCL`] on an existing
+/// file, [`FsError::NotDirectory`] for [`O_DIRECTORY`] on anything else,
+/// and the errors of looking up or creating the file.
+pub fn open(path: &str, flags: u32) -> Result<Arc<OpenFile>, FsError> {
+    if flags & !O_SUPPORTED != 0 || flags & O_ACCMODE == O_ACCMODE {
+        return Err(FsError::InvalidArgument);
+    }
+    let inode = match super::lookup(path) {
+        Ok(_) if flags & O_CREAT != 0 && flags & O_EXCL != 0 => return Err(FsError::AlreadyExists),
+        Ok(inode) => inode,
+        Err(FsError::NotFound) if flags & O_CREAT != 0 => super::create(path, NodeKind::File)?,
+        Err(err) => return Err(err),
+    };
+
+    let kind = inode.metadata().kind;
+    let writes = flags & O_ACCMODE != O_RDONLY;
+    if kind == NodeKind::Directory && writes {
+        return Err(FsError::IsDirectory);
+    }
+    if kind != NodeKind::Directory && flags & O_DIRECTORY != 0 {
+        return Err(FsError::NotDirectory);
+    }
+    if kind == NodeKind::File && writes && flags & O_TRUNC != 0 {
+        inode.truncate(0)?;
+    }
+    Ok(Arc::new(OpenFile {
+        path: String::from(path),
+        inode,
+        flags,
+        position: Mutex::new(0),
+    }))
+}
+
+impl OpenFile {
+    /// Path the file was opened by.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The node.
+    pub fn inode(&self) -> &Arc<dyn Inode> {
+        &self.inode
+    }
+
+    /// Returns `true` if the file was opened for reading.
+    pub fn is_readable(&self) -> bool {
+        self.flags & O_ACCMODE != O_WRONLY
+    }
+
+    /// Returns `true` if the file was opened for writing.
+    pub fn is_writable(&self) -> bool {
+        self.flags & O_ACCMODE != O_RDONLY
+    }
+
+    /// Reads from the current position and advances it.
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes read, zero at the end of the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`FsError::BadAccess`] unless opened for reading, and the
+    /// errors of [`Inode::read_at`].
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, FsError> {
+        if !self.is_readable() {
+            return Err(FsError::BadAccess);
+        }
+        let mut position = self.position.lock();
+        let read = self.inode.read_at(*position, buf)?;
+        *position += read as u64;
+        Ok(read)
+    }
+
+    /// Writes at the current position, or at the end with [`O_APPEND`],
+    /// and advances the position.
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes written.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`FsError::BadAccess`] unless opened for writing, and the
+    /// errors of [`Inode::write_at`].
+    pub fn write(&self, buf: &[u8]) -> Result<usize, FsError> {
+        if !self.is_writable() {
+            return Err(FsError::BadAccess);
+        }
+        let mut position = self.position.lock();
+        if self.flags & O_APPEND != 0 {
+            *position = self.inode.metadata().size;
+        }
+        let written = self.inode.write_at(*position, buf)?;
+        *position += written as u64;
+        Ok(written)
+    }
+
+    /// Moves the position to `offset` relative to `whence`.
+    ///
+    /// # Returns
+    ///
+    /// The new position.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`FsError::InvalidArgument`] if the position would be
+    /// negative.
+    pub fn seek(&self, offset: i64, whence: Whence) -> Result<u64, FsError> {
+        let mut position = self.position.lock();
+        let base = match whence {
+            Whence::Set => 0,
+            Whence::Current => *position,
+            Whence::End => self.inode.metadata().size,
+        };
+        *position = base
+            .checked_add_signed(offset)
+            .ok_or(FsError::InvalidArgument)?;
+        Ok(*position)
+    }
+
+    /// Passes directory entries, starting at the current position, to
+    /// `accept` until it returns `false`, and advances past the accepted
+    /// ones. Mount points inside the directory are included.
+    ///
+    /// # Returns
+    ///
+    /// The number of entries accepted, zero once all have been returned.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`FsError::NotDirectory`] if the file is not a directory.
+    pub fn read_dir(&self, mut accept: impl FnMut(&DirEntry) -> bool) -> Result<usize, FsError> {
+        let mut position = self.position.lock();
+        let entries = super::read_dir(&self.path)?;
+        let accepted = entries
+            .iter()
+            .skip(*position as usize)
+            .take_while(|entry| accept(entry))
+            .count();
+        *position += accepted as u64;
+        Ok(accepted)
+    }
+}
+
+impl fmt::Debug for OpenFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OpenFile")
+            .field("path", &self.path)
+            .field("flags", &self.flags)
+            .field("position", &*self.position.lock())
+            .finish()
+    }
+}
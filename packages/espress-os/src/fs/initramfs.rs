@@ -0,0 +1,65 @@
+//! # Initial RAM Filesystem
+//!
+//! A ustar archive that becomes the root filesystem at boot, before (and
+//! without) any disk driver: it supplies the first user programs, fonts,
+//! and configuration files. It is mounted read-only at `/`, and every file
+//! in [`PROGRAM_DIR`] is registered as a program under its file name, so
+//! [`spawn_program`](crate::process::spawn_program) finds it.
+//!
+//! A multiboot loader passes the archive as a boot module, which the boot
+//! path hands to [`load`]. The bootloader this kernel boots with today
+//! cannot load modules, so with the `initramfs` feature the archive
+//! `initramfs.tar` next to `Cargo.toml` is linked into the kernel image
+//! instead:
+//!
+//! ```text
+//! tar --format=ustar -cf packages/espress-os/initramfs.tar -C rootfs .
+//! cargo build --features initramfs
+//! ```
+
+use alloc::sync::Arc;
+
+use super::tar::TarFs;
+use super::FsError;
+use crate::{println, process};
+
+/// Directory whose files are registered as programs.
+pub const PROGRAM_DIR: &str = "/bin";
+
+/// Mounts the archive linked into the kernel, if any.
+///
+/// Failure is reported and leaves `/` unmounted.
+pub fn init() {
+    #[cfg(feature = "initramfs")]
+    {
+        static ARCHIVE: &[u8] =
+            include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/initramfs.tar"));
+        if let Err(err) = load(ARCHIVE) {
+            println!("initramfs: not mounted: {:?}", err);
+        }
+    }
+}
+
+/// Parses the tar archive `image`, mounts it at `/`, and registers its
+/// programs.
+///
+/// # Errors
+///
+/// Returns [`FsError::Corrupted`] if the archive is malformed and
+/// [`FsError::Busy`] if a root filesystem is already mounted.
+pub fn load(image: &'static [u8]) -> Result<(), FsError> {
+    let fs = Arc::new(TarFs::new(image)?);
+    let nodes = fs.node_count();
+    let programs = fs.files_in(PROGRAM_DIR);
+    super::mount("/", fs)?;
+    for &(name, data) in &programs {
+        process::register_program(name, data);
+    }
+    println!(
+        "initramfs: {} KiB, {} nodes, {} programs",
+        image.len() / 1024,
+        nodes,
+        programs.len()
+    );
+    Ok(())
+}
@@ -0,0 +1,571 @@
+//! # ISO 9660 Filesystems
+//!
+//! A read-only driver for ISO 9660 volumes, the format of CD images and of
+//! the hybrid ISO images `grub-mkrescue` writes, with the Rock Ridge
+//! extensions most tools add.
+//!
+//! The first 32 KiB of a volume are unused; volume descriptors follow in
+//! 2048-byte sectors, the primary one giving the logical block size and the
+//! directory record of the root directory. Every file occupies one or more
+//! extents of consecutive blocks. Directories are files of variable-length
+//! records that never cross a block boundary; each names an extent and
+//! carries flags. Plain ISO 9660 names are uppercase, may end in a `;1`
+//! version, and are shown here in lowercase without the version.
+//!
+//! Rock Ridge stores POSIX attributes in the system use area at the end of
+//! each record, as entries of the System Use Sharing Protocol (SUSP),
+//! possibly continued in another block (`CE`). The root directory's first
+//! record announces the protocol (`SP`). This driver reads original names
+//! (`NM`), permissions and file types (`PX`), symbolic links (`SL`), and
+//! the relocation of deep directories (`CL`, `RE`).
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::{DirEntry, FileSystem, FsError, Inode, Metadata, NodeKind};
+use crate::block::{self, BlockDevice};
+
+/// Size of a volume descriptor, and of a sector in their area.
+const DESCRIPTOR_SIZE: usize = 2048;
+/// Byte offset of the first volume descriptor.
+const DESCRIPTOR_OFFSET: u64 = 16 * DESCRIPTOR_SIZE as u64;
+/// Most volume descriptors read looking for the primary one.
+const MAX_DESCRIPTORS: u64 = 32;
+/// Standard identifier of every volume descriptor.
+const STANDARD_ID: &[u8] = b"CD001";
+/// Volume descriptor type: primary.
+const DESCRIPTOR_PRIMARY: u8 = 1;
+/// Volume descriptor type: end of the set.
+const DESCRIPTOR_TERMINATOR: u8 = 255;
+
+/// Size of a directory record without its name.
+const RECORD_HEADER_SIZE: usize = 33;
+/// Record flag: the entry is a directory.
+const FLAG_DIRECTORY: u8 = 0x02;
+/// Record flag: the file continues in the next record.
+const FLAG_MULTI_EXTENT: u8 = 0x80;
+
+/// Size of the `SP` entry announcing SUSP.
+const SP_SIZE: usize = 7;
+/// Most continuation areas followed for one record.
+const MAX_CONTINUATIONS: usize = 16;
+/// `NM` and `SL` flag: more of the name follows in the next entry.
+const RR_CONTINUE: u8 = 0x01;
+/// `NM` and `SL` flag: the name is `.`.
+const RR_CURRENT: u8 = 0x02;
+/// `NM` and `SL` flag: the name is `..`.
+const RR_PARENT: u8 = 0x04;
+/// `SL` flag: the component is the root directory.
+const RR_ROOT: u8 = 0x08;
+
+/// A mounted ISO 9660 volume.
+pub struct Iso9660Fs {
+    /// Shared state of the volume
+    volume: Arc<Volume>,
+    /// The root directory
+    root: Entry,
+}
+
+impl Iso9660Fs {
+    /// Reads the volume descriptors of `device` and prepares the volume.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`FsError::NotSupported`] if the device holds no ISO 9660
+    /// volume, [`FsError::Corrupted`] if the descriptors or the root
+    /// directory are malformed, and [`FsError::Io`] if the device fails.
+    pub fn new(device: Arc<dyn BlockDevice>) -> Result<Self, FsError> {
+        let mut descriptor = [0; DESCRIPTOR_SIZE];
+        let mut index = 0;
+        loop {
+            if index == MAX_DESCRIPTORS {
+                return Err(FsError::NotSupported);
+            }
+            let offset = DESCRIPTOR_OFFSET + index * DESCRIPTOR_SIZE as u64;
+            if offset + DESCRIPTOR_SIZE as u64 > device.size() {
+                return Err(FsError::NotSupported);
+            }
+            block::read_bytes(&*device, offset, &mut descriptor)?;
+            if &descriptor[1..6] != STANDARD_ID {
+                return Err(FsError::NotSupported);
+            }
+            match descriptor[0] {
+                DESCRIPTOR_PRIMARY => break,
+                DESCRIPTOR_TERMINATOR => return Err(FsError::NotSupported),
+                _ => index += 1,
+            }
+        }
+
+        let block_size = u64::from(read_u16(&descriptor, 128));
+        if !block_size.is_power_of_two() || !(512..=2048).contains(&block_size) {
+            return Err(FsError::Corrupted);
+        }
+        let mut volume = Volume {
+            device,
+            block_size,
+            susp_skip: None,
+        };
+        let root = RawRecord::parse(&descriptor[156..190]).ok_or(FsError::Corrupted)?;
+        if root.flags & FLAG_DIRECTORY == 0 {
+            return Err(FsError::Corrupted);
+        }
+        let root = Entry {
+            name: String::new(),
+            kind: NodeKind::Directory,
+            extents: vec![(root.lba * block_size, root.size)],
+            inode: root.lba * block_size,
+            mode: None,
+            link: None,
+        };
+
+        // The first record of the root directory announces Rock Ridge.
+        let data = volume.read_extents(&root.extents, 0, block_size)?;
+        let first = RawRecord::parse(&data).ok_or(FsError::Corrupted)?;
+        let area = first.system_use;
+        if area.len() >= SP_SIZE && &area[..2] == b"SP" && area[4..6] == [0xbe, 0xef] {
+            volume.susp_skip = Some(usize::from(area[6]));
+        }
+
+        Ok(Iso9660Fs {
+            volume: Arc::new(volume),
+            root,
+        })
+    }
+
+    /// Returns `true` if the volume carries Rock Ridge attributes.
+    pub fn has_rock_ridge(&self) -> bool {
+        self.volume.susp_skip.is_some()
+    }
+}
+
+impl FileSystem for Iso9660Fs {
+    fn name(&self) -> &str {
+        "iso9660"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::new(IsoNode {
+            volume: self.volume.clone(),
+            entry: self.root.clone(),
+        })
+    }
+}
+
+/// Geometry and device of a volume, shared by its nodes.
+struct Volume {
+    /// Device holding the volume
+    device: Arc<dyn BlockDevice>,
+    /// Bytes per logical block
+    block_size: u64,
+    /// Bytes to skip at the start of each system use area, if the volume
+    /// uses SUSP
+    susp_skip: Option<usize>,
+}
+
+impl Volume {
+    /// Reads `len` bytes at `offset` into a file made of `extents`.
+    fn read_extents(
+        &self,
+        extents: &[(u64, u64)],
+        offset: u64,
+        len: u64,
+    ) -> Result<Vec<u8>, FsError> {
+        let mut data = vec![0; len as usize];
+        let read = read_extents(&*self.device, extents, offset, &mut data)?;
+        data.truncate(read);
+        Ok(data)
+    }
+
+    /// Parses the directory made of `extents` into its named entries.
+    fn read_directory(&self, extents: &[(u64, u64)]) -> Result<Vec<Entry>, FsError> {
+        let size = extents.iter().map(|&(_, len)| len).sum();
+        let data = self.read_extents(extents, 0, size)?;
+        let mut entries: Vec<Entry> = Vec::new();
+        // The previous record said the file continues.
+        let mut continued = false;
+        let mut position = 0;
+        while position < data.len() {
+            if data[position] == 0 {
+                // Records do not cross blocks; the rest of this one is
+                // padding.
+                position = (position + 1).next_multiple_of(self.block_size as usize);
+                continue;
+            }
+            let start = position;
+            let record = RawRecord::parse(&data[position..]).ok_or(FsError::Corrupted)?;
+            position += record.len;
+            if record.identifier == [0] || record.identifier == [1] {
+                continue;
+            }
+
+            let extent = (record.lba * self.block_size, record.size);
+            if continued {
+                let last = entries.last_mut().ok_or(FsError::Corrupted)?;
+                last.extents.push(extent);
+                continued = record.flags & FLAG_MULTI_EXTENT != 0;
+                continue;
+            }
+            continued = record.flags & FLAG_MULTI_EXTENT != 0;
+
+            let rock_ridge = match self.susp_skip {
+                Some(skip) => self.rock_ridge(record.system_use.get(skip..).unwrap_or(&[]))?,
+                None => RockRidge::default(),
+            };
+            if rock_ridge.relocated {
+                continue;
+            }
+            let mut kind = if record.flags & FLAG_DIRECTORY != 0 {
+                NodeKind::Directory
+            } else {
+                NodeKind::File
+            };
+            let mut data_extents = vec![extent];
+            if let Some(lba) = rock_ridge.child_link {
+                kind = NodeKind::Directory;
+                data_extents = vec![self.directory_extent(lba)?];
+            }
+            if rock_ridge.link.is_some() {
+                kind = NodeKind::Symlink;
+            }
+            let inode = match kind {
+                NodeKind::Directory => data_extents[0].0,
+                _ => device_offset(extents, start as u64),
+            };
+            let name = rock_ridge
+                .name
+                .unwrap_or_else(|| plain_name(record.identifier, kind));
+            entries.push(Entry {
+                name,
+                kind,
+                extents: data_extents,
+                inode,
+                mode: rock_ridge.mode.map(|mode| (mode & 0o7777) as u16),
+                link: rock_ridge.link,
+            });
+        }
+        Ok(entries)
+    }
+
+    /// Returns the extent of the directory starting at block `lba`, whose
+    /// size its own `.` record gives.
+    fn directory_extent(&self, lba: u64) -> Result<(u64, u64), FsError> {
+        let start = lba * self.block_size;
+        let mut first = vec![0; self.block_size as usize];
+        block::read_bytes(&*self.device, start, &mut first)?;
+        let record = RawRecord::parse(&first).ok_or(FsError::Corrupted)?;
+        if record.identifier != [0] || record.lba != lba {
+            return Err(FsError::Corrupted);
+        }
+        Ok((start, record.size))
+    }
+
+    /// Collects the Rock Ridge entries of a system use area and its
+    /// continuations.
+    fn rock_ridge(&self, area: &[u8]) -> Result<RockRidge, FsError> {
+        let mut rock_ridge = RockRidge::default();
+        let mut link_state = LinkState::default();
+        let mut area = area.to_vec();
+        for _ in 0..=MAX_CONTINUATIONS {
+            let mut continuation = None;
+            let mut position = 0;
+            while position + 4 <= area.len() {
+                let len = usize::from(area[position + 2]);
+                if len < 4 || position + len > area.len() {
+                    break;
+                }
+                let entry = &area[position..position + len];
+                position += len;
+                match &entry[..2] {
+                    b"ST" => break,
+                    b"CE" if len >= 28 => {
+                        let lba = u64::from(read_u32(entry, 4));
+                        let offset = u64::from(read_u32(entry, 12));
+                        let size = u64::from(read_u32(entry, 20));
+                        continuation = Some((lba * self.block_size + offset, size));
+                    }
+                    b"NM" if len >= 5 => {
+                        let flags = entry[4];
+                        if flags & (RR_CURRENT | RR_PARENT) == 0 {
+                            let name = rock_ridge.name.get_or_insert_with(String::new);
+                            name.push_str(&String::from_utf8_lossy(&entry[5..]));
+                        }
+                    }
+                    b"PX" if len >= 36 => rock_ridge.mode = Some(read_u32(entry, 4)),
+                    b"SL" if len >= 5 => {
+                        let link = rock_ridge.link.get_or_insert_with(String::new);
+                        link_state.add(link, &entry[5..]);
+                    }
+                    b"CL" if len >= 12 => {
+                        rock_ridge.child_link = Some(u64::from(read_u32(entry, 4)))
+                    }
+                    b"RE" => rock_ridge.relocated = true,
+                    _ => {}
+                }
+            }
+            let Some((offset, size)) = continuation else {
+                return Ok(rock_ridge);
+            };
+            if size > self.block_size {
+                return Err(FsError::Corrupted);
+            }
+            area = vec![0; size as usize];
+            block::read_bytes(&*self.device, offset, &mut area)?;
+        }
+        Err(FsError::Corrupted)
+    }
+}
+
+/// Reads from a file made of `extents`, starting `offset` bytes into it.
+///
+/// # Returns
+///
+/// The number of bytes read, short at the end of the file.
+fn read_extents(
+    device: &dyn BlockDevice,
+    extents: &[(u64, u64)],
+    offset: u64,
+    buf: &mut [u8],
+) -> Result<usize, FsError> {
+    let mut skip = offset;
+    let mut done = 0;
+    for &(start, len) in extents {
+        if done == buf.len() {
+            break;
+        }
+        if skip >= len {
+            skip -= len;
+            continue;
+        }
+        let count = (len - skip).min((buf.len() - done) as u64) as usize;
+        block::read_bytes(device, start + skip, &mut buf[done..done + count])?;
+        done += count;
+        skip = 0;
+    }
+    Ok(done)
+}
+
+/// Returns the byte offset on the device of byte `offset` of a file made
+/// of `extents`.
+fn device_offset(extents: &[(u64, u64)], offset: u64) -> u64 {
+    let mut skip = offset;
+    for &(start, len) in extents {
+        if skip < len {
+            return start + skip;
+        }
+        skip -= len;
+    }
+    skip
+}
+
+/// Turns an ISO 9660 identifier into a name: without the version and a
+/// trailing dot of files, in lowercase.
+fn plain_name(identifier: &[u8], kind: NodeKind) -> String {
+    let mut name = identifier;
+    if kind != NodeKind::Directory {
+        if let Some(end) = name.iter().position(|&byte| byte == b';') {
+            name = &name[..end];
+        }
+        if let Some(stripped) = name.strip_suffix(b".") {
+            name = stripped;
+        }
+    }
+    String::from_utf8_lossy(name).to_ascii_lowercase()
+}
+
+/// The fixed part of a directory record.
+struct RawRecord<'a> {
+    /// Length of the whole record
+    len: usize,
+    /// First block of the extent
+    lba: u64,
+    /// Size of the extent in bytes
+    size: u64,
+    /// File flags
+    flags: u8,
+    /// File identifier; `[0]` is `.` and `[1]` is `..`
+    identifier: &'a [u8],
+    /// System use area after the identifier
+    system_use: &'a [u8],
+}
+
+impl<'a> RawRecord<'a> {
+    /// Parses the record at the start of `data`.
+    fn parse(data: &'a [u8]) -> Option<Self> {
+        let len = usize::from(*data.first()?);
+        if len < RECORD_HEADER_SIZE || len > data.len() {
+            return None;
+        }
+        let name_len = usize::from(data[32]);
+        let name_end = RECORD_HEADER_SIZE + name_len;
+        // The identifier is padded to an even length.
+        let system_use = name_end + (name_len + 1) % 2;
+        if system_use > len {
+            return None;
+        }
+        Some(RawRecord {
+            len,
+            lba: u64::from(read_u32(data, 2)),
+            size: u64::from(read_u32(data, 10)),
+            flags: data[25],
+            identifier: &data[RECORD_HEADER_SIZE..name_end],
+            system_use: &data[system_use..len],
+        })
+    }
+}
+
+/// Rock Ridge attributes of a record.
+#[derive(Default)]
+struct RockRidge {
+    /// Original name
+    name: Option<String>,
+    /// POSIX mode
+    mode: Option<u32>,
+    /// Target of a symbolic link
+    link: Option<String>,
+    /// Block of the directory relocated here
+    child_link: Option<u64>,
+    /// The record is a relocated directory, listed under its real parent
+    relocated: bool,
+}
+
+/// Progress of assembling a link target from `SL` components.
+#[derive(Default)]
+struct LinkState {
+    /// The next component starts a new path component
+    separate: bool,
+}
+
+impl LinkState {
+    /// Appends the components of an `SL` entry to `link`.
+    fn add(&mut self, link: &mut String, mut components: &[u8]) {
+        while let [flags, len, rest @ ..] = components {
+            let len = usize::from(*len).min(rest.len());
+            let content = &rest[..len];
+            components = &rest[len..];
+            if flags & RR_ROOT != 0 {
+                link.clear();
+                link.push('/');
+                self.separate = false;
+                continue;
+            }
+            if self.separate {
+                link.push('/');
+            }
+            if flags & RR_CURRENT != 0 {
+                link.push('.');
+            } else if flags & RR_PARENT != 0 {
+                link.push_str("..");
+            } else {
+                link.push_str(&String::from_utf8_lossy(content));
+            }
+            self.separate = flags & RR_CONTINUE == 0;
+        }
+    }
+}
+
+/// A parsed directory entry.
+#[derive(Clone)]
+struct Entry {
+    /// Rock Ridge name, or the cleaned-up ISO 9660 name
+    name: String,
+    /// File, directory, or symbolic link
+    kind: NodeKind,
+    /// Byte offsets and lengths of the data
+    extents: Vec<(u64, u64)>,
+    /// Node number: the byte offset of a directory's data, or the
+    /// position of a file's record
+    inode: u64,
+    /// Rock Ridge permissions
+    mode: Option<u16>,
+    /// Rock Ridge link target
+    link: Option<String>,
+}
+
+/// A file, directory, or symbolic link of an ISO 9660 volume.
+struct IsoNode {
+    /// Volume holding the node
+    volume: Arc<Volume>,
+    /// The node's entry in its parent
+    entry: Entry,
+}
+
+impl Inode for IsoNode {
+    fn metadata(&self) -> Metadata {
+        let entry = &self.entry;
+        let size = match (&entry.link, entry.kind) {
+            (Some(link), _) => link.len() as u64,
+            (None, NodeKind::Directory) => 0,
+            (None, _) => entry.extents.iter().map(|&(_, len)| len).sum(),
+        };
+        let mode = entry.mode.unwrap_or(match entry.kind {
+            NodeKind::Directory => 0o555,
+            _ => 0o444,
+        });
+        Metadata {
+            kind: entry.kind,
+            size,
+            inode: entry.inode,
+            mode,
+        }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        match (&self.entry.link, self.entry.kind) {
+            (_, NodeKind::Directory) => Err(FsError::IsDirectory),
+            (Some(link), _) => {
+                let link = link.as_bytes();
+                let start = (offset as usize).min(link.len());
+                let len = buf.len().min(link.len() - start);
+                buf[..len].copy_from_slice(&link[start..start + len]);
+                Ok(len)
+            }
+            (None, _) => read_extents(&*self.volume.device, &self.entry.extents, offset, buf),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        if self.entry.kind != NodeKind::Directory {
+            return Err(FsError::NotDirectory);
+        }
+        let entry = self
+            .volume
+            .read_directory(&self.entry.extents)?
+            .into_iter()
+            .find(|entry| entry.name == name)
+            .ok_or(FsError::NotFound)?;
+        Ok(Arc::new(IsoNode {
+            volume: self.volume.clone(),
+            entry,
+        }))
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+        if self.entry.kind != NodeKind::Directory {
+            return Err(FsError::NotDirectory);
+        }
+        Ok(self
+            .volume
+            .read_directory(&self.entry.extents)?
+            .into_iter()
+            .map(|entry| DirEntry {
+                name: entry.name,
+                kind: entry.kind,
+            })
+            .collect())
+    }
+}
+
+/// Reads a little-endian `u16` at `offset`; ISO 9660 stores most numbers
+/// in both byte orders, little-endian first.
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+/// Reads a little-endian `u32` at `offset`.
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
@@ -0,0 +1,563 @@
+//! # Virtual File System
+//!
+//! One tree of files for the whole kernel, assembled from the filesystems
+//! [`mount`]ed into it. A filesystem implements [`FileSystem`], which hands
+//! out its root directory, and [`Inode`] for every file and directory; the
+//! VFS only resolves paths and dispatches.
+//!
+//! Paths are absolute and resolved lexically: `.` and empty components are
+//! dropped and `..` steps back one component, so symbolic links are not
+//! followed. A path belongs to the filesystem with the longest mount point
+//! that is a prefix of it. Mount points need not exist in the filesystem
+//! below them; [`read_dir`] lists them either way.
+//!
+//! Filesystems: [`tar`] archives (the [`initramfs`]), [`fat`] volumes,
+//! [`ext2`] volumes, [`iso9660`] images, the heap-backed [`ramfs`], and
+//! [`devfs`], which exposes devices.
+//!
+//! Processes reach files through [`file::open`], which pairs a node with
+//! the position reads and writes advance.
+//!
+//! The mount table lock is only taken in thread context and never held
+//! while a filesystem works.
+
+pub mod devfs;
+pub mod ext2;
+pub mod fat;
+pub mod file;
+pub mod initramfs;
+pub mod iso9660;
+pub mod ramfs;
+pub mod tar;
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::Any;
+use spin::Mutex;
+
+use crate::block::{self, BlockError};
+use crate::println;
+
+/// Longest path accepted.
+pub const PATH_MAX: usize = 4096;
+
+/// Longest file name accepted.
+pub const NAME_MAX: usize = 255;
+
+/// Mounted filesystems, in mount order.
+static MOUNTS: Mutex<Vec<Mount>> = Mutex::new(Vec::new());
+
+/// Errors that can occur during file operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    /// The path does not exist
+    NotFound,
+    /// A directory was expected
+    NotDirectory,
+    /// A file was expected
+    IsDirectory,
+    /// The name is already taken
+    AlreadyExists,
+    /// The directory still has entries
+    NotEmpty,
+    /// The path is relative, too long, or has an invalid name
+    InvalidPath,
+    /// A flag or offset is out of range
+    InvalidArgument,
+    /// The file was not opened for this kind of access
+    BadAccess,
+    /// The filesystem does not allow modification
+    ReadOnly,
+    /// The filesystem does not support the operation
+    NotSupported,
+    /// The path is a mount point, or a filesystem is mounted there already
+    Busy,
+    /// The operation would span two filesystems
+    CrossDevice,
+    /// The filesystem is full
+    NoSpace,
+    /// The on-disk structures are inconsistent
+    Corrupted,
+    /// The underlying device failed
+    Io(BlockError),
+}
+
+impl From<BlockError> for FsError {
+    fn from(err: BlockError) -> Self {
+        FsError::Io(err)
+    }
+}
+
+/// Type of a node in the tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    /// A regular file
+    File,
+    /// A directory
+    Directory,
+    /// A symbolic link, whose contents are the target path
+    Symlink,
+    /// A device read and written a byte stream at a time
+    CharDevice,
+    /// A device read and written in blocks
+    BlockDevice,
+}
+
+/// Attributes of a node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    /// Node type
+    pub kind: NodeKind,
+    /// Size in bytes, zero for directories and character devices
+    pub size: u64,
+    /// Number unique within the filesystem
+    pub inode: u64,
+    /// Unix permission bits
+    pub mode: u16,
+}
+
+/// An entry of a directory listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    /// File name
+    pub name: String,
+    /// Node type
+    pub kind: NodeKind,
+}
+
+/// A file, directory, or other node of a filesystem.
+///
+/// Operations a node does not support keep their default implementation,
+/// which fails with the appropriate error.
+pub trait Inode: Any + Send + Sync {
+    /// Returns the node's attributes.
+    fn metadata(&self) -> Metadata;
+
+    /// Reads from the file at byte `offset` into `buf`.
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes read, zero at the end of the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`FsError::IsDirectory`] for directories.
+    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> Result<usize, FsError> {
+        Err(FsError::IsDirectory)
+    }
+
+    /// Writes `buf` to the file at byte `offset`, growing it if needed.
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes written.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`FsError::ReadOnly`] unless the filesystem is writable.
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> Result<usize, FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    /// Sets the size of the file, filling any new bytes with zeros.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`FsError::ReadOnly`] unless the filesystem is writable.
+    fn truncate(&self, _size: u64) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    /// Looks up `name` in the directory.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`FsError::NotFound`] if there is no such entry and
+    /// [`FsError::NotDirectory`] if the node is not a directory.
+    fn lookup(&self, _name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::NotDirectory)
+    }
+
+    /// Lists the directory, without `.` and `..`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`FsError::NotDirectory`] if the node is not a directory.
+    fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+        Err(FsError::NotDirectory)
+    }
+
+    /// Creates an empty file or directory called `name` in the directory.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`FsError::AlreadyExists`] if the name is taken and
+    /// [`FsError::ReadOnly`] unless the filesystem is writable.
+    fn create(&self, _name: &str, _kind: NodeKind) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    /// Removes the entry `name` from the directory; directories must be
+    /// empty.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`FsError::NotEmpty`] for a directory with entries and
+    /// [`FsError::ReadOnly`] unless the filesystem is writable.
+    fn unlink(&self, _name: &str) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    /// Moves the entry `name` of the directory to `new_name` in `new_dir`,
+    /// another directory of the same filesystem, replacing a file or empty
+    /// directory of the same type there.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`FsError::NotFound`] if there is no such entry,
+    /// [`FsError::InvalidPath`] for moving a directory into itself, and
+    /// [`FsError::ReadOnly`] unless the filesystem is writable.
+    fn rename(
+        &self,
+        _name: &str,
+        _new_dir: &Arc<dyn Inode>,
+        _new_name: &str,
+    ) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+}
+
+/// A mountable filesystem.
+pub trait FileSystem: Send + Sync {
+    /// Short type name, e.g. `"tar"`.
+    fn name(&self) -> &str;
+
+    /// Returns the root directory.
+    fn root(&self) -> Arc<dyn Inode>;
+
+    /// Writes all modified data to the underlying device.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`FsError::Io`] if the device fails.
+    fn sync(&self) -> Result<(), FsError> {
+        Ok(())
+    }
+}
+
+/// A filesystem attached to the tree.
+struct Mount {
+    /// Normalized mount point
+    path: Vec<String>,
+    /// The filesystem
+    fs: Arc<dyn FileSystem>,
+}
+
+/// Mounts the initramfs at `/`, [`devfs`] at `/dev`, and an empty
+/// [`ramfs`] at `/tmp`.
+pub fn init() {
+    initramfs::init();
+    devfs::init();
+    mount("/dev", Arc::new(devfs::DevFs)).expect("failed to mount /dev");
+    mount("/tmp", Arc::new(ramfs::RamFs::new())).expect("failed to mount /tmp");
+}
+
+/// Attaches `fs` to the tree at `path`.
+///
+/// # Errors
+///
+/// Returns [`FsError::InvalidPath`] for a malformed path and
+/// [`FsError::Busy`] if a filesystem is already mounted there.
+pub fn mount(path: &str, fs: Arc<dyn FileSystem>) -> Result<(), FsError> {
+    let path = owned(&normalize(path)?);
+    let mut mounts = MOUNTS.lock();
+    if mounts.iter().any(|mount| mount.path == path) {
+        return Err(FsError::Busy);
+    }
+    mounts.push(Mount { path, fs });
+    Ok(())
+}
+
+/// Detaches the filesystem mounted at `path` after syncing it.
+///
+/// # Errors
+///
+/// Returns [`FsError::NotFound`] if nothing is mounted at `path`,
+/// [`FsError::Busy`] if other filesystems are mounted below it, and any
+/// error of syncing, in which case it stays mounted.
+pub fn unmount(path: &str) -> Result<(), FsError> {
+    let path = normalize(path)?;
+    let fs = {
+        let mounts = MOUNTS.lock();
+        let mount = mounts
+            .iter()
+            .find(|mount| mount.path == path)
+            .ok_or(FsError::NotFound)?;
+        let nested = mounts
+            .iter()
+            .any(|other| other.path.len() > path.len() && other.path.starts_with(&mount.path));
+        if nested {
+            return Err(FsError::Busy);
+        }
+        mount.fs.clone()
+    };
+    fs.sync()?;
+    MOUNTS
+        .lock()
+        .retain(|mount| mount.path != path || !Arc::ptr_eq(&mount.fs, &fs));
+    Ok(())
+}
+
+/// Returns the mount points with the type of their filesystem, in mount
+/// order.
+pub fn mounts() -> Vec<(String, String)> {
+    MOUNTS
+        .lock()
+        .iter()
+        .map(|mount| (join(&mount.path), String::from(mount.fs.name())))
+        .collect()
+}
+
+/// Syncs every mounted filesystem, then flushes every block device.
+///
+/// # Errors
+///
+/// Returns the first error encountered; the remaining filesystems and
+/// devices are still synced.
+pub fn sync() -> Result<(), FsError> {
+    let filesystems: Vec<_> = MOUNTS.lock().iter().map(|mount| mount.fs.clone()).collect();
+    let mut result = Ok(());
+    for fs in filesystems {
+        if let Err(err) = fs.sync() {
+            result = result.and(Err(err));
+        }
+    }
+    result.and(block::sync().map_err(FsError::from))
+}
+
+/// Resolves `path` to a node.
+///
+/// # Errors
+///
+/// Returns [`FsError::NotFound`] if a component does not exist or nothing
+/// is mounted above it, and [`FsError::NotDirectory`] if a component other
+/// than the last is not a directory.
+pub fn lookup(path: &str) -> Result<Arc<dyn Inode>, FsError> {
+    let components = normalize(path)?;
+    let (fs, depth) = resolve(&components)?;
+    let mut node = fs.root();
+    for name in &components[depth..] {
+        node = node.lookup(name)?;
+    }
+    Ok(node)
+}
+
+/// Returns the attributes of the node at `path`.
+///
+/// # Errors
+///
+/// See [`lookup`].
+pub fn metadata(path: &str) -> Result<Metadata, FsError> {
+    Ok(lookup(path)?.metadata())
+}
+
+/// Lists the directory at `path`, including the filesystems mounted
+/// directly inside it.
+///
+/// # Errors
+///
+/// See [`lookup`]; fails with [`FsError::NotDirectory`] if the node is not
+/// a directory.
+pub fn read_dir(path: &str) -> Result<Vec<DirEntry>, FsError> {
+    let components = normalize(path)?;
+    let mut entries = match lookup(path) {
+        Ok(dir) => dir.read_dir()?,
+        // A mount point missing from the filesystem below still lists.
+        Err(FsError::NotFound) if !mounted_below(&components).is_empty() => Vec::new(),
+        Err(err) => return Err(err),
+    };
+    for name in mounted_below(&components) {
+        if entries.iter().all(|entry| entry.name != name) {
+            entries.push(DirEntry {
+                name,
+                kind: NodeKind::Directory,
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// Reads the whole file at `path`.
+///
+/// # Errors
+///
+/// See [`lookup`]; fails with [`FsError::IsDirectory`] for directories.
+pub fn read_to_end(path: &str) -> Result<Vec<u8>, FsError> {
+    let file = lookup(path)?;
+    let mut data = vec![0; file.metadata().size as usize];
+    let mut filled = 0;
+    loop {
+        if filled == data.len() {
+            // The size may have been stale; check for more.
+            data.resize(filled + 4096, 0);
+        }
+        match file.read_at(filled as u64, &mut data[filled..])? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    data.truncate(filled);
+    Ok(data)
+}
+
+/// Creates an empty file or directory at `path`.
+///
+/// # Errors
+///
+/// Returns [`FsError::AlreadyExists`] if the path exists and the errors of
+/// [`lookup`] for its parent.
+pub fn create(path: &str, kind: NodeKind) -> Result<Arc<dyn Inode>, FsError> {
+    let components = normalize(path)?;
+    let parent = parent_of(&components)?;
+    parent.dir.create(parent.name, kind)
+}
+
+/// Removes the file or empty directory at `path`.
+///
+/// # Errors
+///
+/// Returns [`FsError::Busy`] for a mount point, [`FsError::NotEmpty`] for
+/// a directory with entries, and the errors of [`lookup`].
+pub fn remove(path: &str) -> Result<(), FsError> {
+    let components = normalize(path)?;
+    if MOUNTS.lock().iter().any(|mount| mount.path == components) {
+        return Err(FsError::Busy);
+    }
+    let parent = parent_of(&components)?;
+    parent.dir.unlink(parent.name)
+}
+
+/// Moves the node at `from` to `to`, replacing a file or empty directory
+/// there.
+///
+/// # Errors
+///
+/// Returns [`FsError::CrossDevice`] if the paths are on different
+/// filesystems, [`FsError::Busy`] if either is a mount point, and the
+/// errors of [`Inode::rename`] and of [`lookup`] for the parents.
+pub fn rename(from: &str, to: &str) -> Result<(), FsError> {
+    let from = normalize(from)?;
+    let to = normalize(to)?;
+    let is_mount_point = |path: &[&str]| MOUNTS.lock().iter().any(|mount| mount.path == path);
+    if is_mount_point(&from) || is_mount_point(&to) {
+        return Err(FsError::Busy);
+    }
+    let from = parent_of(&from)?;
+    let to = parent_of(&to)?;
+    if !Arc::ptr_eq(&from.fs, &to.fs) {
+        return Err(FsError::CrossDevice);
+    }
+    from.dir.rename(from.name, &to.dir, to.name)
+}
+
+/// Prints the mount table.
+pub fn print_mounts() {
+    for (path, fs) in mounts() {
+        println!("  {path:<16} {fs}");
+    }
+}
+
+/// Splits `path` into its components after resolving `.` and `..`.
+///
+/// # Errors
+///
+/// Returns [`FsError::InvalidPath`] if the path is relative, too long, or
+/// has a component longer than [`NAME_MAX`].
+fn normalize(path: &str) -> Result<Vec<&str>, FsError> {
+    if !path.starts_with('/') || path.len() > PATH_MAX {
+        return Err(FsError::InvalidPath);
+    }
+    let mut components = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            name if name.len() > NAME_MAX => return Err(FsError::InvalidPath),
+            name => components.push(name),
+        }
+    }
+    Ok(components)
+}
+
+/// Finds the filesystem responsible for the normalized `path`.
+///
+/// # Returns
+///
+/// The filesystem and the number of leading components naming its mount
+/// point.
+fn resolve(path: &[&str]) -> Result<(Arc<dyn FileSystem>, usize), FsError> {
+    MOUNTS
+        .lock()
+        .iter()
+        .filter(|mount| mount.path.len() <= path.len() && same_prefix(&mount.path, path))
+        .max_by_key(|mount| mount.path.len())
+        .map(|mount| (mount.fs.clone(), mount.path.len()))
+        .ok_or(FsError::NotFound)
+}
+
+/// The directory holding a path.
+struct Parent<'a> {
+    /// Filesystem of the directory
+    fs: Arc<dyn FileSystem>,
+    /// The directory
+    dir: Arc<dyn Inode>,
+    /// Last component of the path
+    name: &'a str,
+}
+
+/// Looks up the directory containing the normalized `path`.
+fn parent_of<'a>(path: &[&'a str]) -> Result<Parent<'a>, FsError> {
+    let (name, parent) = path.split_last().ok_or(FsError::Busy)?;
+    let (fs, depth) = resolve(parent)?;
+    let mut dir = fs.root();
+    for component in &parent[depth..] {
+        dir = dir.lookup(component)?;
+    }
+    Ok(Parent { fs, dir, name })
+}
+
+/// Names of the mount points directly inside the normalized `dir`.
+fn mounted_below(dir: &[&str]) -> Vec<String> {
+    MOUNTS
+        .lock()
+        .iter()
+        .filter(|mount| mount.path.len() == dir.len() + 1 && same_prefix(&mount.path, dir))
+        .map(|mount| mount.path[dir.len()].clone())
+        .collect()
+}
+
+/// Copies normalized components.
+fn owned(path: &[&str]) -> Vec<String> {
+    path.iter().map(|&name| String::from(name)).collect()
+}
+
+/// Joins components into an absolute path.
+fn join(path: &[String]) -> String {
+    if path.is_empty() {
+        return String::from("/");
+    }
+    path.iter().fold(String::new(), |mut joined, name| {
+        joined.push('/');
+        joined.push_str(name);
+        joined
+    })
+}
+
+/// Returns `true` if `a` and `b` agree on their common length.
+fn same_prefix(a: &[String], b: &[&str]) -> bool {
+    a.iter().zip(b).all(|(a, b)| a == b)
+}
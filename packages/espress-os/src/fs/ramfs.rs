@@ -0,0 +1,301 @@
+//! # RAM Filesystem
+//!
+//! A writable filesystem kept entirely on the kernel heap, mounted at
+//! `/tmp`. It supports everything the VFS offers, which makes it the
+//! reference for how the other filesystems should behave. Its contents are
+//! lost on reboot.
+//!
+//! Each node guards its contents with its own lock. An operation touching
+//! two directories (a rename) locks them in inode order.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::{Mutex, MutexGuard};
+
+use super::{DirEntry, FileSystem, FsError, Inode, Metadata, NodeKind};
+
+/// Permissions of new files.
+const FILE_MODE: u16 = 0o644;
+/// Permissions of new directories.
+const DIRECTORY_MODE: u16 = 0o755;
+
+/// A heap-backed filesystem.
+pub struct RamFs {
+    /// Root directory
+    root: Arc<RamNode>,
+}
+
+impl RamFs {
+    /// Creates an empty filesystem.
+    pub fn new() -> Self {
+        let next_inode = Arc::new(AtomicU64::new(1));
+        RamFs {
+            root: RamNode::new(&next_inode, NodeKind::Directory),
+        }
+    }
+}
+
+impl Default for RamFs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FileSystem for RamFs {
+    fn name(&self) -> &str {
+        "ramfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+/// Contents of a node.
+enum Content {
+    /// File data
+    File(Vec<u8>),
+    /// Directory entries by name
+    Directory(BTreeMap<String, Arc<RamNode>>),
+}
+
+/// A file or directory of a [`RamFs`].
+struct RamNode {
+    /// Node number
+    inode: u64,
+    /// Source of node numbers, shared by the filesystem
+    next_inode: Arc<AtomicU64>,
+    /// Contents
+    content: Mutex<Content>,
+}
+
+impl RamNode {
+    /// Creates an empty node numbered from `next_inode`.
+    fn new(next_inode: &Arc<AtomicU64>, kind: NodeKind) -> Arc<Self> {
+        let content = match kind {
+            NodeKind::Directory => Content::Directory(BTreeMap::new()),
+            _ => Content::File(Vec::new()),
+        };
+        Arc::new(RamNode {
+            inode: next_inode.fetch_add(1, Ordering::Relaxed),
+            next_inode: next_inode.clone(),
+            content: Mutex::new(content),
+        })
+    }
+
+    /// Node type.
+    fn kind(&self) -> NodeKind {
+        match *self.content.lock() {
+            Content::File(_) => NodeKind::File,
+            Content::Directory(_) => NodeKind::Directory,
+        }
+    }
+
+    /// Returns `true` if `node` is this directory or lies below it.
+    fn contains(&self, node: &RamNode) -> bool {
+        if core::ptr::eq(self, node) {
+            return true;
+        }
+        match &*self.content.lock() {
+            Content::Directory(entries) => entries.values().any(|child| child.contains(node)),
+            Content::File(_) => false,
+        }
+    }
+
+    /// Looks up the child `name` of this directory.
+    fn lookup_node(&self, name: &str) -> Result<Arc<RamNode>, FsError> {
+        let mut content = self.content.lock();
+        entries(&mut content)?
+            .get(name)
+            .cloned()
+            .ok_or(FsError::NotFound)
+    }
+}
+
+/// Returns the entries of a locked directory.
+fn entries<'a>(
+    content: &'a mut MutexGuard<Content>,
+) -> Result<&'a mut BTreeMap<String, Arc<RamNode>>, FsError> {
+    match &mut **content {
+        Content::Directory(entries) => Ok(entries),
+        Content::File(_) => Err(FsError::NotDirectory),
+    }
+}
+
+/// Checks that `node` may replace `existing` under its name.
+fn check_replace(node: &RamNode, existing: &RamNode) -> Result<(), FsError> {
+    if core::ptr::eq(node, existing) {
+        return Ok(());
+    }
+    match (node.kind(), &*existing.content.lock()) {
+        (NodeKind::Directory, Content::Directory(entries)) if entries.is_empty() => Ok(()),
+        (NodeKind::Directory, Content::Directory(_)) => Err(FsError::NotEmpty),
+        (NodeKind::Directory, Content::File(_)) => Err(FsError::NotDirectory),
+        (_, Content::Directory(_)) => Err(FsError::IsDirectory),
+        (_, Content::File(_)) => Ok(()),
+    }
+}
+
+impl Inode for RamNode {
+    fn metadata(&self) -> Metadata {
+        let (kind, size, mode) = match &*self.content.lock() {
+            Content::File(data) => (NodeKind::File, data.len() as u64, FILE_MODE),
+            Content::Directory(_) => (NodeKind::Directory, 0, DIRECTORY_MODE),
+        };
+        Metadata {
+            kind,
+            size,
+            inode: self.inode,
+            mode,
+        }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let content = self.content.lock();
+        let Content::File(data) = &*content else {
+            return Err(FsError::IsDirectory);
+        };
+        let start = (offset as usize).min(data.len());
+        let len = buf.len().min(data.len() - start);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        let mut content = self.content.lock();
+        let Content::File(data) = &mut *content else {
+            return Err(FsError::IsDirectory);
+        };
+        let start = usize::try_from(offset).map_err(|_| FsError::NoSpace)?;
+        let end = start.checked_add(buf.len()).ok_or(FsError::NoSpace)?;
+        if end > data.len() {
+            data.try_reserve(end - data.len())
+                .map_err(|_| FsError::NoSpace)?;
+            data.resize(end, 0);
+        }
+        data[start..end].copy_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, size: u64) -> Result<(), FsError> {
+        let mut content = self.content.lock();
+        let Content::File(data) = &mut *content else {
+            return Err(FsError::IsDirectory);
+        };
+        let size = usize::try_from(size).map_err(|_| FsError::NoSpace)?;
+        if size > data.len() {
+            data.try_reserve(size - data.len())
+                .map_err(|_| FsError::NoSpace)?;
+        }
+        data.resize(size, 0);
+        data.shrink_to_fit();
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        let mut content = self.content.lock();
+        match entries(&mut content)?.get(name) {
+            Some(node) => Ok(node.clone()),
+            None => Err(FsError::NotFound),
+        }
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+        let children: Vec<_> = {
+            let mut content = self.content.lock();
+            entries(&mut content)?
+                .iter()
+                .map(|(name, node)| (name.clone(), node.clone()))
+                .collect()
+        };
+        Ok(children
+            .into_iter()
+            .map(|(name, node)| DirEntry {
+                name,
+                kind: node.kind(),
+            })
+            .collect())
+    }
+
+    fn create(&self, name: &str, kind: NodeKind) -> Result<Arc<dyn Inode>, FsError> {
+        if !matches!(kind, NodeKind::File | NodeKind::Directory) {
+            return Err(FsError::NotSupported);
+        }
+        let mut content = self.content.lock();
+        let entries = entries(&mut content)?;
+        if entries.contains_key(name) {
+            return Err(FsError::AlreadyExists);
+        }
+        let node = RamNode::new(&self.next_inode, kind);
+        entries.insert(String::from(name), node.clone());
+        Ok(node)
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), FsError> {
+        let mut content = self.content.lock();
+        let entries = entries(&mut content)?;
+        let node = entries.get(name).ok_or(FsError::NotFound)?;
+        if let Content::Directory(children) = &*node.content.lock() {
+            if !children.is_empty() {
+                return Err(FsError::NotEmpty);
+            }
+        }
+        entries.remove(name);
+        Ok(())
+    }
+
+    fn rename(&self, name: &str, new_dir: &Arc<dyn Inode>, new_name: &str) -> Result<(), FsError> {
+        let new_dir: &dyn Any = &**new_dir;
+        let new_dir = new_dir
+            .downcast_ref::<RamNode>()
+            .ok_or(FsError::CrossDevice)?;
+
+        if core::ptr::eq(self, new_dir) {
+            let mut content = self.content.lock();
+            let entries = entries(&mut content)?;
+            let node = entries.get(name).ok_or(FsError::NotFound)?.clone();
+            if let Some(existing) = entries.get(new_name) {
+                check_replace(&node, existing)?;
+            }
+            entries.remove(name);
+            entries.insert(String::from(new_name), node);
+            return Ok(());
+        }
+
+        let node = self.lookup_node(name)?;
+        if node.contains(new_dir) {
+            return Err(FsError::InvalidPath);
+        }
+        // Lock both directories in inode order.
+        let (mut source, mut target) = if self.inode < new_dir.inode {
+            let source = self.content.lock();
+            (source, new_dir.content.lock())
+        } else {
+            let target = new_dir.content.lock();
+            (self.content.lock(), target)
+        };
+        let source = entries(&mut source)?;
+        let target = entries(&mut target)?;
+        if !source
+            .get(name)
+            .is_some_and(|current| Arc::ptr_eq(current, &node))
+        {
+            // Changed since the lookup.
+            return Err(FsError::NotFound);
+        }
+        if let Some(existing) = target.get(new_name) {
+            // The source directory lies below the node it would replace.
+            if core::ptr::eq(&**existing, self) {
+                return Err(FsError::NotEmpty);
+            }
+            check_replace(&node, existing)?;
+        }
+        source.remove(name);
+        target.insert(String::from(new_name), node);
+        Ok(())
+    }
+}
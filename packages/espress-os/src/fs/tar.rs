@@ -0,0 +1,365 @@
+//! # Tar Archives
+//!
+//! A read-only filesystem over a ustar archive held in memory. The archive
+//! is parsed once into a tree of nodes whose contents borrow from the
+//! archive, so files cost no copies.
+//!
+//! An archive is a sequence of 512-byte headers, each followed by the
+//! entry's data padded to a whole block, and ends with zero blocks. Besides
+//! plain ustar entries, GNU long names (`L` entries) and base-256 sizes are
+//! understood; pax extended headers and hard links are skipped.
+//! Directories missing from the archive are created implicitly, and a later
+//! entry for the same path replaces an earlier one.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use super::{DirEntry, FileSystem, FsError, Inode, Metadata, NodeKind};
+
+/// Size of a header and the unit of data padding.
+const BLOCK_SIZE: usize = 512;
+
+/// Offset and length of the header fields used.
+const NAME: (usize, usize) = (0, 100);
+const MODE: (usize, usize) = (100, 8);
+const SIZE: (usize, usize) = (124, 12);
+const CHECKSUM: (usize, usize) = (148, 8);
+const TYPEFLAG: usize = 156;
+const LINKNAME: (usize, usize) = (157, 100);
+const MAGIC: (usize, usize) = (257, 5);
+const PREFIX: (usize, usize) = (345, 155);
+
+/// Permissions of directories the archive does not list.
+const IMPLICIT_DIR_MODE: u16 = 0o755;
+
+/// A filesystem backed by a tar archive.
+pub struct TarFs {
+    /// Root directory
+    root: Arc<TarNode>,
+    /// Number of nodes, including the root
+    node_count: u64,
+}
+
+impl TarFs {
+    /// Parses `image`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`FsError::Corrupted`] if a header has a bad checksum or an
+    /// entry runs past the end of the image.
+    pub fn new(image: &'static [u8]) -> Result<Self, FsError> {
+        let mut root = Builder::directory(IMPLICIT_DIR_MODE);
+        let mut long_name: Option<&'static [u8]> = None;
+        let mut offset = 0;
+
+        while offset + BLOCK_SIZE <= image.len() {
+            let header = &image[offset..offset + BLOCK_SIZE];
+            if header.iter().all(|&byte| byte == 0) {
+                break;
+            }
+            if !checksum_ok(header) {
+                return Err(FsError::Corrupted);
+            }
+            let size = parse_number(field(header, SIZE)).ok_or(FsError::Corrupted)? as usize;
+            let data_start = offset + BLOCK_SIZE;
+            let data = image
+                .get(data_start..data_start.checked_add(size).ok_or(FsError::Corrupted)?)
+                .ok_or(FsError::Corrupted)?;
+            offset = data_start + size.next_multiple_of(BLOCK_SIZE);
+
+            let typeflag = header[TYPEFLAG];
+            if typeflag == b'L' {
+                long_name = Some(trim(data));
+                continue;
+            }
+            let mode = parse_number(field(header, MODE)).unwrap_or(0) as u16 & 0o7777;
+            let node = match typeflag {
+                b'0' | 0 | b'7' => Builder::file(NodeKind::File, mode, data),
+                b'5' => Builder::directory(mode),
+                b'2' => Builder::file(NodeKind::Symlink, mode, trim(field(header, LINKNAME))),
+                _ => {
+                    long_name = None;
+                    continue;
+                }
+            };
+
+            let mut path = Vec::new();
+            match long_name.take() {
+                Some(name) => path.extend(components(name)?),
+                None => {
+                    if field(header, MAGIC) == b"ustar" {
+                        path.extend(components(trim(field(header, PREFIX)))?);
+                    }
+                    path.extend(components(trim(field(header, NAME)))?);
+                }
+            }
+            root.insert(&path, node);
+        }
+
+        let mut node_count = 0;
+        let root = root.freeze(&mut node_count);
+        Ok(TarFs { root, node_count })
+    }
+
+    /// Number of files, directories, and links in the archive.
+    pub fn node_count(&self) -> u64 {
+        self.node_count
+    }
+
+    /// Returns the regular files directly inside the directory `path`, with
+    /// names and contents borrowed from the archive.
+    ///
+    /// # Returns
+    ///
+    /// An empty list if there is no such directory.
+    pub fn files_in(&self, path: &str) -> Vec<(&'static str, &'static [u8])> {
+        let mut dir = &self.root;
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            match dir.children.get(name) {
+                Some(child) => dir = child,
+                None => return Vec::new(),
+            }
+        }
+        dir.children
+            .iter()
+            .filter(|(_, node)| node.kind == NodeKind::File)
+            .map(|(&name, node)| (name, node.data))
+            .collect()
+    }
+}
+
+impl FileSystem for TarFs {
+    fn name(&self) -> &str {
+        "tar"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+/// A file, directory, or link in the archive.
+struct TarNode {
+    /// Node number, the root being 1
+    inode: u64,
+    /// Node type
+    kind: NodeKind,
+    /// Permission bits
+    mode: u16,
+    /// File contents or link target
+    data: &'static [u8],
+    /// Directory entries by name
+    children: BTreeMap<&'static str, Arc<TarNode>>,
+}
+
+impl Inode for TarNode {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            kind: self.kind,
+            size: self.data.len() as u64,
+            inode: self.inode,
+            mode: self.mode,
+        }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        if self.kind == NodeKind::Directory {
+            return Err(FsError::IsDirectory);
+        }
+        let start = (offset as usize).min(self.data.len());
+        let len = buf.len().min(self.data.len() - start);
+        buf[..len].copy_from_slice(&self.data[start..start + len]);
+        Ok(len)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        if self.kind != NodeKind::Directory {
+            return Err(FsError::NotDirectory);
+        }
+        match self.children.get(name) {
+            Some(node) => Ok(node.clone()),
+            None => Err(FsError::NotFound),
+        }
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+        if self.kind != NodeKind::Directory {
+            return Err(FsError::NotDirectory);
+        }
+        Ok(self
+            .children
+            .iter()
+            .map(|(&name, node)| DirEntry {
+                name: String::from(name),
+                kind: node.kind,
+            })
+            .collect())
+    }
+}
+
+/// A node under construction.
+struct Builder {
+    /// Node type
+    kind: NodeKind,
+    /// Permission bits
+    mode: u16,
+    /// File contents or link target
+    data: &'static [u8],
+    /// Directory entries by name
+    children: BTreeMap<&'static str, Builder>,
+}
+
+impl Builder {
+    /// An empty directory.
+    fn directory(mode: u16) -> Self {
+        Builder {
+            kind: NodeKind::Directory,
+            mode,
+            data: &[],
+            children: BTreeMap::new(),
+        }
+    }
+
+    /// A file or link holding `data`.
+    fn file(kind: NodeKind, mode: u16, data: &'static [u8]) -> Self {
+        Builder {
+            kind,
+            mode,
+            data,
+            children: BTreeMap::new(),
+        }
+    }
+
+    /// Places `node` at `path` below this directory, creating missing
+    /// directories on the way and replacing whatever was there.
+    ///
+    /// A directory entry for an existing directory only updates its mode.
+    fn insert(&mut self, path: &[&'static str], node: Builder) {
+        let Some((&name, rest)) = path.split_first() else {
+            // The archive lists the root itself.
+            if node.kind == NodeKind::Directory {
+                self.mode = node.mode;
+            }
+            return;
+        };
+        if rest.is_empty() {
+            match self.children.get_mut(name) {
+                Some(existing)
+                    if existing.kind == NodeKind::Directory && node.kind == NodeKind::Directory =>
+                {
+                    existing.mode = node.mode;
+                }
+                _ => {
+                    self.children.insert(name, node);
+                }
+            }
+            return;
+        }
+        let dir = self
+            .children
+            .entry(name)
+            .or_insert_with(|| Builder::directory(IMPLICIT_DIR_MODE));
+        if dir.kind != NodeKind::Directory {
+            *dir = Builder::directory(IMPLICIT_DIR_MODE);
+        }
+        dir.insert(rest, node);
+    }
+
+    /// Turns the tree into shared nodes, numbering them from
+    /// `count + 1` in depth-first order.
+    fn freeze(self, count: &mut u64) -> Arc<TarNode> {
+        *count += 1;
+        let inode = *count;
+        let children = self
+            .children
+            .into_iter()
+            .map(|(name, child)| (name, child.freeze(count)))
+            .collect();
+        Arc::new(TarNode {
+            inode,
+            kind: self.kind,
+            mode: self.mode,
+            data: self.data,
+            children,
+        })
+    }
+}
+
+/// Returns the bytes of the header field `(offset, len)`.
+fn field(header: &[u8], (offset, len): (usize, usize)) -> &[u8] {
+    &header[offset..offset + len]
+}
+
+/// Cuts a field off at its first NUL.
+fn trim(bytes: &[u8]) -> &[u8] {
+    let end = bytes
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(bytes.len());
+    &bytes[..end]
+}
+
+/// Splits an archive path into its components, dropping `.` and empty
+/// ones.
+///
+/// # Errors
+///
+/// Returns [`FsError::Corrupted`] if the path is not UTF-8 or contains
+/// `..`.
+fn components(path: &'static [u8]) -> Result<Vec<&'static str>, FsError> {
+    let path = core::str::from_utf8(path).map_err(|_| FsError::Corrupted)?;
+    let mut components = Vec::new();
+    for name in path.split('/') {
+        match name {
+            "" | "." => {}
+            ".." => return Err(FsError::Corrupted),
+            name => components.push(name),
+        }
+    }
+    Ok(components)
+}
+
+/// Parses a numeric field: octal digits padded with spaces or NULs, or a
+/// big-endian base-256 number if the top bit of the first byte is set.
+fn parse_number(bytes: &[u8]) -> Option<u64> {
+    if bytes.first().is_some_and(|&byte| byte & 0x80 != 0) {
+        return bytes[1..]
+            .iter()
+            .try_fold(u64::from(bytes[0] & 0x7f), |n, &byte| {
+                n.checked_mul(256).map(|n| n + u64::from(byte))
+            });
+    }
+    let digits = trim(bytes);
+    let digits = digits
+        .iter()
+        .skip_while(|&&byte| byte == b' ')
+        .take_while(|&&byte| byte != b' ');
+    let mut value: u64 = 0;
+    for &digit in digits {
+        if !(b'0'..=b'7').contains(&digit) {
+            return None;
+        }
+        value = value.checked_mul(8)? + u64::from(digit - b'0');
+    }
+    Some(value)
+}
+
+/// Verifies the header checksum, the byte sum of the header with the
+/// checksum field taken as spaces.
+fn checksum_ok(header: &[u8]) -> bool {
+    let (start, len) = CHECKSUM;
+    let sum: u64 = header
+        .iter()
+        .enumerate()
+        .map(|(i, &byte)| {
+            if (start..start + len).contains(&i) {
+                u64::from(b' ')
+            } else {
+                u64::from(byte)
+            }
+        })
+        .sum();
+    parse_number(field(header, CHECKSUM)) == Some(sum)
+}
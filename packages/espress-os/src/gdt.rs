@@ -0,0 +1,115 @@
+//! # Global Descriptor Table
+//!
+//! Sets up the kernel and user segments and the Task State Segment (TSS).
+//! The TSS provides the Interrupt Stack Table, which lets critical exception
+//! handlers run on known-good stacks even when the interrupted stack is
+//! exhausted, and `RSP0`, the stack the CPU switches to when an interrupt
+//! arrives in user mode.
+//!
+//! The segments are laid out in the order `syscall`/`sysret` expect: kernel
+//! code, kernel data, user data, user code.
+//!
+//! The interrupt stacks are allocated from the guarded stack region in
+//! [`crate::mm::stack`], so this must run after memory management is up.
+
+use core::cell::UnsafeCell;
+use core::ptr::addr_of_mut;
+use spin::Once;
+use x86_64::instructions::segmentation::{Segment, CS, SS};
+use x86_64::instructions::tables::load_tss;
+use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
+use x86_64::structures::tss::TaskStateSegment;
+use x86_64::VirtAddr;
+
+use crate::mm::stack::KernelStack;
+
+/// IST slot used by the double fault handler.
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+
+/// Size of each interrupt stack in 4 KiB pages.
+const INTERRUPT_STACK_PAGES: usize = 5;
+
+/// Segment selectors created while building the GDT.
+#[derive(Debug, Clone, Copy)]
+pub struct Selectors {
+    /// Kernel code segment selector
+    pub kernel_code: SegmentSelector,
+    /// Kernel data segment selector
+    pub kernel_data: SegmentSelector,
+    /// User data segment selector (RPL 3)
+    pub user_data: SegmentSelector,
+    /// User code segment selector (RPL 3)
+    pub user_code: SegmentSelector,
+    /// Task State Segment selector
+    pub tss: SegmentSelector,
+}
+
+/// The TSS, which stays mutable after loading so `RSP0` can follow the
+/// running thread.
+struct Tss(UnsafeCell<TaskStateSegment>);
+
+// Only `RSP0` is written after initialization, with interrupts disabled on
+// the single CPU that uses this TSS.
+unsafe impl Sync for Tss {}
+
+static TSS: Once<Tss> = Once::new();
+static GDT: Once<(GlobalDescriptorTable, Selectors)> = Once::new();
+
+/// Builds and loads the GDT and TSS.
+///
+/// # Panics
+///
+/// Panics if the interrupt stacks cannot be allocated.
+pub fn init() {
+    let tss = TSS.call_once(|| {
+        let mut tss = TaskStateSegment::new();
+        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+            KernelStack::new(INTERRUPT_STACK_PAGES, "double-fault")
+                .expect("failed to allocate the double fault stack")
+                .leak();
+        Tss(UnsafeCell::new(tss))
+    });
+
+    let (gdt, selectors) = GDT.call_once(|| {
+        let mut gdt = GlobalDescriptorTable::new();
+        let kernel_code = gdt.add_entry(Descriptor::kernel_code_segment());
+        let kernel_data = gdt.add_entry(Descriptor::kernel_data_segment());
+        let user_data = gdt.add_entry(Descriptor::user_data_segment());
+        let user_code = gdt.add_entry(Descriptor::user_code_segment());
+        let tss = gdt.add_entry(Descriptor::tss_segment(unsafe { &*tss.0.get() }));
+        let selectors = Selectors {
+            kernel_code,
+            kernel_data,
+            user_data,
+            user_code,
+            tss,
+        };
+        (gdt, selectors)
+    });
+
+    gdt.load();
+    unsafe {
+        CS::set_reg(selectors.kernel_code);
+        SS::set_reg(selectors.kernel_data);
+        load_tss(selectors.tss);
+    }
+}
+
+/// Returns the segment selectors.
+///
+/// # Panics
+///
+/// Panics if the GDT has not been initialized.
+pub fn selectors() -> &'static Selectors {
+    &GDT.get().expect("GDT not initialized").1
+}
+
+/// Sets the stack the CPU switches to on an interrupt from user mode.
+///
+/// The scheduler points this at the kernel stack of every thread it
+/// switches to. Must be called with interrupts disabled.
+pub fn set_kernel_stack(top: VirtAddr) {
+    if let Some(tss) = TSS.get() {
+        unsafe { addr_of_mut!((*tss.0.get()).privilege_stack_table[0]).write(top) };
+    }
+}
@@ -0,0 +1,246 @@
+//! # Interrupt Handling
+//!
+//! Builds the Interrupt Descriptor Table (IDT) and provides the CPU exception
+//! handlers. Faults that hit a stack guard page are reported as a stack
+//! overflow naming the task that owns the stack.
+//!
+//! Exceptions raised by user mode code terminate the offending thread
+//! instead of halting the kernel (see [`crate::usermode`]).
+//!
+//! Hardware interrupts arrive through the legacy 8259 PICs, remapped to
+//! vectors starting at [`PIC_1_OFFSET`] so they do not overlap the CPU
+//! exceptions.
+
+use pic8259::ChainedPics;
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::VirtAddr;
+
+use crate::mm::{cow, probe, stack};
+use crate::{console, gdt, println, usermode};
+
+/// First vector used by the primary PIC.
+pub const PIC_1_OFFSET: u8 = 32;
+
+/// First vector used by the secondary PIC.
+pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+
+/// The chained primary and secondary PICs.
+pub static PICS: Mutex<ChainedPics> =
+    Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+
+/// Interrupt vectors of the hardware interrupts handled by the kernel.
+#[derive(Debug, Clone, Copy)]
+#[repr(u8)]
+pub enum InterruptIndex {
+    /// Programmable interval timer (IRQ 0)
+    Timer = PIC_1_OFFSET,
+    /// PS/2 keyboard (IRQ 1)
+    Keyboard,
+    /// PS/2 mouse (IRQ 12)
+    Mouse = PIC_2_OFFSET + 4,
+}
+
+impl InterruptIndex {
+    /// The vector number as `u8`.
+    pub fn as_u8(self) -> u8 {
+        self as u8
+    }
+
+    /// The vector number as an IDT index.
+    pub fn as_usize(self) -> usize {
+        usize::from(self.as_u8())
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.divide_error.set_handler_fn(divide_error_handler);
+        idt.breakpoint.set_handler_fn(breakpoint_handler);
+        idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+        idt.page_fault.set_handler_fn(page_fault_handler);
+        idt.general_protection_fault.set_handler_fn(general_protection_fault_handler);
+        unsafe {
+            idt.double_fault
+                .set_handler_fn(double_fault_handler)
+                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+        }
+        idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
+        idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
+        idt[InterruptIndex::Mouse.as_usize()].set_handler_fn(mouse_interrupt_handler);
+        idt
+    };
+}
+
+/// Loads the IDT into the CPU.
+pub fn init_idt() {
+    IDT.load();
+}
+
+/// Remaps and unmasks the PICs.
+///
+/// Interrupts stay disabled on the CPU until explicitly enabled.
+pub fn init_pics() {
+    unsafe { PICS.lock().initialize() };
+}
+
+extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
+    println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+}
+
+extern "x86-interrupt" fn divide_error_handler(stack_frame: InterruptStackFrame) {
+    if usermode::is_user_frame(&stack_frame) {
+        usermode::kill_current(format_args!("divide error"), &stack_frame);
+    }
+    unsafe { console::force_unlock() };
+    println!("EXCEPTION: DIVIDE ERROR\n{:#?}", stack_frame);
+    crate::hlt_loop();
+}
+
+extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) {
+    if usermode::is_user_frame(&stack_frame) {
+        usermode::kill_current(format_args!("invalid opcode"), &stack_frame);
+    }
+    unsafe { console::force_unlock() };
+    println!("EXCEPTION: INVALID OPCODE\n{:#?}", stack_frame);
+    crate::hlt_loop();
+}
+
+/// Page fault handler.
+///
+/// Faults raised by memory probes are redirected to the probe's recovery
+/// path, and write faults on copy-on-write pages are resolved and the access
+/// is retried. Any other fault kills the thread if it came from user mode
+/// and is fatal otherwise.
+///
+/// A fault on a guard page is normally escalated to a double fault because
+/// the CPU cannot push the exception frame onto the overflowed stack, but a
+/// stray access to another task's guard page arrives here directly.
+extern "x86-interrupt" fn page_fault_handler(
+    mut stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    if probe::fixup(&mut stack_frame) {
+        return;
+    }
+    let addr = Cr2::read();
+    if cow::handle_fault(addr, error_code) {
+        return;
+    }
+    if usermode::is_user_frame(&stack_frame) {
+        usermode::kill_current(
+            format_args!("page fault on {:#x} ({:?})", addr.as_u64(), error_code),
+            &stack_frame,
+        );
+    }
+    unsafe { console::force_unlock() };
+
+    println!("EXCEPTION: PAGE FAULT");
+    report_guard_hit(addr);
+    println!("Accessed Address: {:?}", addr);
+    println!("Error Code: {:?}", error_code);
+    println!("{:#?}", stack_frame);
+    crate::hlt_loop();
+}
+
+extern "x86-interrupt" fn general_protection_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    if usermode::is_user_frame(&stack_frame) {
+        usermode::kill_current(
+            format_args!("general protection fault (error code {:#x})", error_code),
+            &stack_frame,
+        );
+    }
+    unsafe { console::force_unlock() };
+    println!(
+        "EXCEPTION: GENERAL PROTECTION FAULT (error code {:#x})",
+        error_code
+    );
+    println!("{:#?}", stack_frame);
+    crate::hlt_loop();
+}
+
+/// Double fault handler, running on its own IST stack.
+///
+/// CR2 still holds the address of the page fault that could not be
+/// delivered, which identifies the overflowed stack.
+extern "x86-interrupt" fn double_fault_handler(
+    stack_frame: InterruptStackFrame,
+    _error_code: u64,
+) -> ! {
+    let addr = Cr2::read();
+    unsafe { console::force_unlock() };
+
+    println!("EXCEPTION: DOUBLE FAULT");
+    report_guard_hit(addr);
+    println!("{:#?}", stack_frame);
+    crate::hlt_loop();
+}
+
+/// Timer interrupt handler.
+///
+/// Advances kernel time and drives preemption. The end of interrupt is
+/// signaled first, since the scheduler may switch to another thread before
+/// this handler returns.
+extern "x86-interrupt" fn timer_interrupt_handler(stack_frame: InterruptStackFrame) {
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
+    }
+    crate::rand::add_interrupt_timing(InterruptIndex::Timer.as_u8());
+    crate::timer::tick();
+    crate::scheduler::tick();
+    if usermode::is_user_frame(&stack_frame) {
+        crate::process::signal::check_fatal();
+    }
+}
+
+/// Keyboard interrupt handler.
+///
+/// Reads the scancode from the PS/2 controller and hands it to the keyboard
+/// task; decoding happens outside of interrupt context.
+extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    let mut port = Port::new(0x60);
+    let scancode: u8 = unsafe { port.read() };
+    crate::task::keyboard::add_scancode(scancode);
+    crate::rand::add_interrupt_timing(InterruptIndex::Keyboard.as_u8());
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
+    }
+}
+
+/// Mouse interrupt handler.
+///
+/// Reads a packet byte from the PS/2 controller and hands it to the mouse
+/// driver.
+extern "x86-interrupt" fn mouse_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    let mut port = Port::new(0x60);
+    let byte: u8 = unsafe { port.read() };
+    crate::mouse::add_byte(byte);
+    crate::rand::add_interrupt_timing(InterruptIndex::Mouse.as_u8());
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Mouse.as_u8());
+    }
+}
+
+/// Prints a stack overflow diagnostic if `addr` hit a stack guard page.
+fn report_guard_hit(addr: VirtAddr) {
+    if let Some(hit) = stack::guard_hit(addr) {
+        println!(
+            "kernel stack overflow in task `{}` (fault at {:#x}, stack {:#x}..{:#x})",
+            hit.owner,
+            addr.as_u64(),
+            hit.bottom.as_u64(),
+            hit.top.as_u64()
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! # Kernel Address Space Layout Randomization
+//!
+//! Moves the running kernel image to a random virtual base early during boot.
+//!
+//! The kernel is a position-independent executable that has already been
+//! relocated for wherever the bootloader put it (see [`reloc`](crate::reloc)).
+//! With the `kaslr` feature enabled, [`relocate`] picks a random slide, maps
+//! the image's physical frames a second time at `load address + slide`,
+//! applies every `R_X86_64_RELATIVE` relocation for the new base, and jumps
+//! into the copy.
+//! Once the kernel no longer references the link-address view,
+//! [`release_link_mapping`] unmaps it.
+//!
+//! The slide is a multiple of 2 MiB and keeps the image inside the top 2 GiB
+//! of the address space required by the kernel code model, and below the
+//! end of the address space if the bootloader loaded it above its link
+//! address.
+
+use core::arch::asm;
+use core::ptr::addr_of;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::structures::paging::mapper::TranslateResult;
+use x86_64::structures::paging::{Mapper, Page, PhysFrame, Size4KiB, Translate};
+use x86_64::VirtAddr;
+
+use crate::mm::{self, KernelFrameAllocator};
+use crate::{rand, reloc};
+
+extern "C" {
+    static __kernel_start: u8;
+    static __kernel_end: u8;
+}
+
+/// Granularity of the randomized base.
+const SLIDE_ALIGN: u64 = 2 * 1024 * 1024;
+
+/// Upper bound for the slide, keeping the image in the top 2 GiB.
+const MAX_SLIDE: u64 = 1024 * 1024 * 1024;
+
+/// Distance between the link address and the running kernel image.
+static SLIDE: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the offset of the running kernel from where it was loaded.
+///
+/// This is zero unless the kernel was relocated by [`relocate`]; the
+/// offset of the load address from the link address is
+/// [`reloc::load_offset`].
+pub fn slide() -> u64 {
+    SLIDE.load(Ordering::Relaxed)
+}
+
+/// Moves the kernel to a random base and continues at `next` there.
+///
+/// `next` is called in the relocated image with `arg` as its only argument.
+/// Any address of a kernel function or static taken before this call refers
+/// to the link-address view; `arg` is passed through unchanged, so callers
+/// that pass such addresses must rebase them with [`slide`].
+///
+/// If no suitable slide can be chosen, the kernel stays where it is and
+/// `next` is called directly.
+///
+/// # Panics
+///
+/// Panics if the image contains a relocation type other than
+/// `R_X86_64_RELATIVE`, which a static PIE link never produces.
+pub fn relocate(next: extern "C" fn(usize) -> !, arg: usize) -> ! {
+    let start = addr_of!(__kernel_start) as u64;
+    let end = addr_of!(__kernel_end) as u64;
+    let image_size = (end - start).next_multiple_of(SLIDE_ALIGN);
+
+    let Some(slide) = choose_slide(image_size, 0u64.wrapping_sub(end)) else {
+        next(arg);
+    };
+
+    if !map_alias(start, end, slide) {
+        next(arg);
+    }
+    unsafe { apply_relocations(slide) };
+    SLIDE.store(slide, Ordering::Relaxed);
+
+    let target = next as usize as u64 + slide;
+    unsafe {
+        asm!(
+            "jmp {target}",
+            target = in(reg) target,
+            in("rdi") arg,
+            options(noreturn)
+        );
+    }
+}
+
+/// Unmaps the link-address view of the kernel image after relocation.
+///
+/// The frames are shared with the relocated view and are not freed. Must only
+/// be called once nothing references the old addresses any more, i.e. after
+/// the GDT, IDT, and all stacks were set up from the relocated image.
+pub fn release_link_mapping() {
+    let slide = slide();
+    if slide == 0 {
+        return;
+    }
+
+    let start = addr_of!(__kernel_start) as u64 - slide;
+    let end = addr_of!(__kernel_end) as u64 - slide;
+    mm::with_mapper(|mapper| {
+        for page in pages(start, end) {
+            if let Ok((_, flush)) = mapper.unmap(page) {
+                flush.flush();
+            }
+        }
+    });
+}
+
+/// Picks a random 2 MiB aligned slide that does not overlap the image and
+/// fits into the `room` bytes above it.
+fn choose_slide(image_size: u64, room: u64) -> Option<u64> {
+    let slots = MAX_SLIDE.min(room).checked_sub(image_size)? / SLIDE_ALIGN;
+    if slots == 0 {
+        return None;
+    }
+    Some(image_size + (rand::next_u64() % slots) * SLIDE_ALIGN)
+}
+
+/// Maps every mapped page of `[start, end)` a second time at `+slide`.
+///
+/// # Returns
+///
+/// `false` if the alias could not be established, in which case any partial
+/// alias mapping is removed again.
+fn map_alias(start: u64, end: u64, slide: u64) -> bool {
+    mm::with_mapper(|mapper| {
+        for page in pages(start, end) {
+            let (frame, flags) = match mapper.translate(page.start_address()) {
+                TranslateResult::Mapped { frame, flags, .. } => {
+                    (PhysFrame::containing_address(frame.start_address()), flags)
+                }
+                _ => continue,
+            };
+            let alias = Page::<Size4KiB>::containing_address(page.start_address() + slide);
+            match unsafe { mapper.map_to(alias, frame, flags, &mut KernelFrameAllocator) } {
+                Ok(flush) => flush.flush(),
+                Err(_) => {
+                    for page in pages(start + slide, alias.start_address().as_u64()) {
+                        if let Ok((_, flush)) = mapper.unmap(page) {
+                            flush.flush();
+                        }
+                    }
+                    return false;
+                }
+            }
+        }
+        true
+    })
+}
+
+/// Applies all dynamic relocations for a kernel base moved by `slide`.
+///
+/// The relocated view shares frames with the running image, so this patches
+/// the running image as well; it stays usable because both views remain
+/// mapped until [`release_link_mapping`].
+///
+/// # Safety
+///
+/// The alias mapping at `+slide` must be in place.
+unsafe fn apply_relocations(slide: u64) {
+    let skipped = reloc::apply(reloc::load_offset() + slide);
+    assert_eq!(
+        skipped, 0,
+        "kaslr: unsupported relocation types in the image"
+    );
+}
+
+/// Iterates over the pages covering `[start, end)`.
+fn pages(start: u64, end: u64) -> impl Iterator<Item = Page<Size4KiB>> {
+    let first = Page::<Size4KiB>::containing_address(VirtAddr::new(start));
+    let last = Page::<Size4KiB>::containing_address(VirtAddr::new(end).align_up(4096u64));
+    Page::range(first, last)
+}
@@ -11,18 +11,391 @@
 //! - VGA text mode output with full color support
 //! - Thread-safe global writer interface
 //! - Print macros for formatted output
-//! - Bare-metal x86_64 compatibility
+//! - Console output fanned out to VGA and serial, or to a framebuffer
+//!   console in a mode chosen with `video=` on the command line
+//! - Physical frame allocation, kernel heap, and guarded kernel stacks
+//! - GDT/TSS, CPU exception handling, and PIC hardware interrupts
+//! - Cooperative async tasks with a FIFO executor
+//! - An interactive shell on the keyboard and console
+//! - Preemptive priority-scheduled kernel threads, idling in `hlt` or
+//!   MWAIT C-states with per-state statistics
+//! - One-shot and periodic kernel timers, and a wall clock set from the
+//!   CMOS RTC
+//! - Ring 3 user mode with fault isolation and `syscall` entry
+//! - ELF processes in isolated address spaces
+//! - PS/2 mouse with an event queue and a text-mode pointer
+//! - PC speaker beeps and tunes
+//! - ChaCha20 random number generator fed by RDSEED/RDRAND, TSC jitter,
+//!   and interrupt timings
+//! - PCI/PCIe enumeration with ECAM found through ACPI
+//! - Machine, BIOS, and memory module information from SMBIOS
+//! - CPU model, core and thread counts, and caches from CPUID and the MADT
+//! - Power off through ACPI S5, suspend to RAM through ACPI S3, and reboot
+//!   through the ACPI reset register, the keyboard controller, or a triple
+//!   fault
+//! - Virtio console as a paravirtual console backend and virtio entropy
+//!   source
+//! - Network interface registry with a loopback device, Ethernet, ARP,
+//!   IPv4 with fragment reassembly and a routing table, IPv6 with neighbour
+//!   discovery and SLAAC, ICMP ping, dual-stack UDP and TCP, DHCP, TFTP,
+//!   and SNTP clients, pcap packet capture, and an optional HTTP status
+//!   server
+//! - Block devices with MBR and GPT partition tables and a write-back cache
+//! - Virtual file system with a tar initramfs as root, FAT16/FAT32, ext2,
+//!   and a RAM filesystem at `/tmp`
+//! - Boots from legacy BIOS through the `bootloader` crate or from UEFI
+//!   through the EspressOS loader, or optionally through `bootloader_api`
+//! - Position-independent kernel image that relocates itself to its load
+//!   address, and optionally to a random base (KASLR)
+//! - Bare-metal x86_64, aarch64 and riscv64 ports that boot on QEMU `virt`
+//!   to a PL011 UART or SBI console, and an i686 port for older PCs, all
+//!   behind a common [`arch`] interface
 //! 
+//! ## Cargo Features
+//!
+//! The larger subsystems can be left out for a smaller kernel; only what is
+//! compiled in is initialized at boot, and the system calls of a missing
+//! subsystem fail with `ENOSYS`.
+//!
+//! | Feature    | Default | Contents                                          |
+//! |------------|---------|---------------------------------------------------|
+//! | `fs`       | yes     | block devices, the VFS, and the file systems       |
+//! | `net`      | yes     | the network stack and sockets; needs `fs`          |
+//! | `selftest` | no      | memory management self-test during boot            |
+//!
+//! `initramfs` implies `fs`, and `http-server` and `netdump` imply `net`.
+//! With `--no-default-features` the kernel is text-only: console, memory,
+//! processes, pipes, and the scheduler.
+//!
 //! ## Usage
 //! 
 //! This library is primarily intended for use as a kernel library, providing
 //! the basic building blocks for a minimal operating system.
 
 #![no_std]
+#![cfg_attr(target_arch = "x86_64", feature(abi_x86_interrupt))]
+#![cfg_attr(target_arch = "x86_64", feature(alloc_error_handler))]
+
+extern crate alloc;
 
 // VGA buffer constants
+#[cfg(target_arch = "x86_64")]
 const BUFFER_HEIGHT: usize = 25;
+#[cfg(target_arch = "x86_64")]
 const BUFFER_WIDTH: usize = 80;
 
-// Include all the VGA-related code
-include!("vga.rs");
+#[cfg(target_arch = "x86_64")]
+pub mod acpi;
+pub mod arch;
+#[cfg(target_arch = "x86_64")]
+pub mod backtrace;
+#[cfg(all(target_arch = "x86_64", feature = "fs"))]
+pub mod block;
+pub mod boot;
+pub mod console;
+#[cfg(target_arch = "x86_64")]
+pub mod fbcon;
+#[cfg(all(target_arch = "x86_64", feature = "fs"))]
+pub mod fs;
+#[cfg(target_arch = "x86_64")]
+pub mod gdt;
+#[cfg(target_arch = "x86_64")]
+pub mod interrupts;
+#[cfg(target_arch = "x86_64")]
+pub mod kaslr;
+#[cfg(target_arch = "x86_64")]
+pub mod mm;
+#[cfg(target_arch = "x86_64")]
+pub mod mouse;
+#[cfg(all(target_arch = "x86_64", feature = "net"))]
+pub mod net;
+#[cfg(target_arch = "x86_64")]
+pub mod pci;
+#[cfg(target_arch = "x86_64")]
+pub mod power;
+#[cfg(target_arch = "x86_64")]
+pub mod process;
+#[cfg(target_arch = "x86_64")]
+pub mod rand;
+#[cfg(target_arch = "x86_64")]
+pub mod reloc;
+#[cfg(target_arch = "x86_64")]
+pub mod scheduler;
+#[cfg(all(target_arch = "x86_64", feature = "selftest"))]
+pub mod selftest;
+#[cfg(target_arch = "x86_64")]
+pub mod serial;
+#[cfg(target_arch = "x86_64")]
+pub mod shell;
+#[cfg(target_arch = "x86_64")]
+pub mod smbios;
+#[cfg(target_arch = "x86_64")]
+pub mod speaker;
+#[cfg(target_arch = "x86_64")]
+pub mod syscall;
+#[cfg(target_arch = "x86_64")]
+pub mod task;
+#[cfg(target_arch = "x86_64")]
+pub mod timer;
+#[cfg(target_arch = "x86_64")]
+pub mod trace;
+#[cfg(target_arch = "x86_64")]
+pub mod usermode;
+#[cfg(target_arch = "x86_64")]
+pub mod vga;
+#[cfg(target_arch = "x86_64")]
+pub mod virtio;
+#[cfg(target_arch = "x86_64")]
+pub mod workqueue;
+
+#[cfg(target_arch = "x86_64")]
+use mm::stack::{KernelStack, DEFAULT_STACK_PAGES};
+
+pub use console::Color;
+#[cfg(target_arch = "x86_64")]
+pub use vga::{Writer, WRITER};
+
+#[cfg(target_arch = "x86_64")]
+#[doc(hidden)]
+pub use vga::_vga_print;
+
+/// Initializes the core kernel subsystems and continues at `entry`.
+///
+/// Brings up memory management first, since everything else allocates from
+/// it. With the `kaslr` feature the kernel then moves itself to a random
+/// base (see [`kaslr`]); the remaining initialization runs from the final
+/// location in [`init_relocated`].
+///
+/// # Arguments
+///
+/// * `boot_info` - Boot information provided by the `bootloader` crate
+/// * `entry` - Kernel main function, started on a guarded kernel stack
+#[cfg(target_arch = "x86_64")]
+pub fn init(boot_info: &'static bootloader::BootInfo, entry: extern "C" fn() -> !) -> ! {
+    boot::record(boot::Protocol::Bios, None, None, None);
+    mm::init(
+        x86_64::VirtAddr::new(boot_info.physical_memory_offset),
+        boot_info.memory_map.iter().map(mm::Region::from),
+    );
+    start(entry)
+}
+
+/// Initializes the core kernel subsystems like [`init`], for a kernel
+/// started by the UEFI loader.
+///
+/// # Arguments
+///
+/// * `boot_info` - Boot information provided by the UEFI loader
+/// * `entry` - Kernel main function, started on a guarded kernel stack
+///
+/// # Panics
+///
+/// Panics if the loader uses a different version of the handoff.
+#[cfg(target_arch = "x86_64")]
+pub fn init_uefi(boot_info: &'static boot::uefi::BootInfo, entry: extern "C" fn() -> !) -> ! {
+    assert_eq!(
+        boot_info.version,
+        boot::uefi::VERSION,
+        "UEFI loader handoff version mismatch"
+    );
+    boot::record(
+        boot::Protocol::Uefi,
+        boot_info.framebuffer(),
+        boot_info.rsdp(),
+        boot_info.smbios(),
+    );
+    mm::init(boot_info.phys_offset(), boot_info.memory_map());
+    start(entry)
+}
+
+/// Initializes the core kernel subsystems like [`init`], for a kernel
+/// started by the `bootloader` crate 0.11.
+///
+/// # Arguments
+///
+/// * `boot_info` - Boot information provided through `bootloader_api`
+/// * `entry` - Kernel main function, started on a guarded kernel stack
+#[cfg(all(target_arch = "x86_64", feature = "bootloader-api"))]
+pub fn init_bootloader_api(
+    boot_info: &'static bootloader_api::BootInfo,
+    entry: extern "C" fn() -> !,
+) -> ! {
+    use boot::bootloader_api as api;
+
+    mm::init(api::phys_offset(boot_info), api::memory_map(boot_info));
+    boot::record(
+        boot::Protocol::BootloaderApi,
+        api::framebuffer(boot_info),
+        api::rsdp(boot_info),
+        None,
+    );
+    start(entry)
+}
+
+/// Moves the kernel to its final base, if enabled, and continues in
+/// [`init_relocated`].
+#[cfg(target_arch = "x86_64")]
+fn start(entry: extern "C" fn() -> !) -> ! {
+    if cfg!(feature = "kaslr") {
+        kaslr::relocate(init_relocated, entry as usize);
+    }
+    init_relocated(entry as usize)
+}
+
+/// Second initialization phase, running at the kernel's final address.
+///
+/// Registers the console backends, shows the boot splash, and reports the
+/// physical memory map.
+/// Everything after that runs as a [boot stage](boot::stage) that reports
+/// its outcome: mapping the kernel heap, setting up the framebuffer
+/// console, and loading the GDT/TSS, the system call MSRs, the IDT, and the
+/// PICs. Then the bootloader's lower-half mappings (and, after relocation,
+/// the link-address view of the kernel) are dropped, leaving the kernel
+/// running purely from its final higher-half mapping, which is prepared to
+/// be shared with process address spaces and checked for writable and
+/// executable mappings (and, with the `selftest` feature, exercised by
+/// [`selftest`]). The remaining stages bring up devices, the scheduler, the
+/// timer, and, if compiled in, networking and the file systems before
+/// interrupts are enabled, execution switches to a guarded stack, and
+/// `entry` is called as the first kernel thread.
+///
+/// `entry` is the link-time address of the kernel main function; it is
+/// rebased by the KASLR slide here.
+#[cfg(target_arch = "x86_64")]
+extern "C" fn init_relocated(entry: usize) -> ! {
+    console::init();
+    boot::splash::show();
+    mm::print_memory_map();
+    boot::stage("Kernel heap", mm::heap::init).expect("failed to map the kernel heap");
+
+    boot::stage("Page protection", mm::protect::init);
+    boot::stage("FPU", arch::fpu::init);
+    boot::stage("PAT", arch::pat::init);
+    let _ = boot::stage("Framebuffer console", fbcon::init);
+    boot::stage("GDT and TSS", gdt::init);
+    boot::stage("System calls", syscall::init);
+    boot::stage("IDT", interrupts::init_idt);
+    boot::stage("PIC", interrupts::init_pics);
+    boot::stage("Kernel address space", || {
+        mm::release_lower_half();
+        kaslr::release_link_mapping();
+        mm::address_space::init();
+        mm::protect::check_wx();
+    });
+    #[cfg(feature = "selftest")]
+    selftest::run();
+    boot::stage("PCI", pci::init);
+    // Machines without SMBIOS run fine; the stage line tells.
+    let _ = boot::stage("SMBIOS", smbios::init);
+    boot::stage("CPU topology", arch::cpu::init);
+    boot::stage("TSC", || {
+        arch::tsc::calibrate();
+    });
+    boot::stage("Idle states", arch::idle::init);
+    boot::stage("Random numbers", rand::init);
+    boot::stage("Scheduler", scheduler::init);
+    boot::stage("Timer", timer::init);
+    boot::stage("Work queues", workqueue::init);
+    #[cfg(feature = "fs")]
+    boot::stage("Block cache", block::cache::init);
+    boot::stage("Mouse", mouse::init);
+    boot::stage("Virtio console", virtio::console::init);
+    boot::stage("Virtio RNG", virtio::rng::init);
+    #[cfg(feature = "netdump")]
+    boot::stage("Packet capture", || {
+        net::netdump::start(net::netdump::Sink::Serial, None, net::netdump::DEFAULT_SNAPLEN)
+    })
+    .expect("failed to start the packet capture");
+    #[cfg(feature = "net")]
+    boot::stage("Network", net::init);
+    #[cfg(feature = "net")]
+    boot::stage("SNTP", || net::sntp::start(None, false));
+    #[cfg(feature = "http-server")]
+    boot::stage("HTTP server", || net::http::start(net::http::PORT))
+        .expect("failed to start the HTTP server");
+    #[cfg(feature = "fs")]
+    boot::stage("File systems", fs::init);
+    x86_64::instructions::interrupts::enable();
+
+    let entry = entry + kaslr::slide() as usize;
+    let entry: extern "C" fn() -> ! = unsafe { core::mem::transmute(entry) };
+    KernelStack::new(DEFAULT_STACK_PAGES, "kernel")
+        .expect("failed to allocate the kernel stack")
+        .run_on(entry)
+}
+
+/// Initializes the kernel on aarch64 or riscv64 and calls `entry`.
+///
+/// Both are started directly by the machine or its firmware with a device
+/// tree. The MMU is turned on and the heap arena handed to the allocator
+/// before anything else, as locks and allocations need both. Then the
+/// console is registered and the splash shown, and the trap or exception
+/// vectors, the interrupt controller where there is one, and the timer are
+/// set up as [boot stages](boot::stage) before interrupts are enabled.
+///
+/// # Arguments
+///
+/// * `devicetree` - Physical address of the device tree as passed by the
+///   machine
+/// * `entry` - Kernel main function, called on the boot stack
+#[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+pub fn init_devicetree(devicetree: usize, entry: extern "C" fn() -> !) -> ! {
+    unsafe { arch::mmu::init() };
+    arch::heap::init();
+    arch::boot::record_devicetree(devicetree);
+    boot::record(boot::Protocol::Devicetree, None, None, None);
+
+    console::init();
+    boot::splash::show();
+    #[cfg(target_arch = "aarch64")]
+    boot::stage("Exception vectors", arch::exceptions::init);
+    #[cfg(target_arch = "aarch64")]
+    boot::stage("GIC", arch::gic::init);
+    #[cfg(target_arch = "riscv64")]
+    boot::stage("Trap vector", arch::trap::init);
+    boot::stage("Timer", arch::timer::init);
+    arch::interrupts::enable();
+    entry()
+}
+
+/// Initializes the kernel on i686 and calls `entry`.
+///
+/// Loads the kernel's GDT over the loader's, turns on paging, and hands the
+/// heap arena to the allocator before anything else. Then the console is
+/// registered and the splash shown, and the IDT, the interrupt controllers,
+/// and the timer are set up as [boot stages](boot::stage) before interrupts
+/// are enabled.
+///
+/// # Arguments
+///
+/// * `magic` - `eax` as passed by the loader, the multiboot magic number
+/// * `info` - `ebx` as passed by the loader, the physical address of the
+///   multiboot information
+/// * `entry` - Kernel main function, called on the boot stack
+#[cfg(target_arch = "x86")]
+pub fn init_multiboot(magic: u32, info: usize, entry: extern "C" fn() -> !) -> ! {
+    arch::gdt::init();
+    unsafe { arch::mmu::init() };
+    arch::heap::init();
+    arch::boot::record_multiboot(magic, info);
+    boot::record(boot::Protocol::Multiboot, None, None, None);
+
+    console::init();
+    boot::splash::show();
+    boot::stage("IDT", arch::trap::init);
+    boot::stage("PIC", arch::pic::init);
+    boot::stage("Timer", arch::timer::init);
+    arch::interrupts::enable();
+    entry()
+}
+
+/// Halts the CPU forever.
+///
+/// Unlike an empty `loop {}`, this puts the CPU to sleep between interrupts
+/// instead of spinning at full power.
+#[cfg(target_arch = "x86_64")]
+pub fn hlt_loop() -> ! {
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
@@ -1,32 +1,158 @@
 //! # EspressOS Kernel Binary
-//! 
+//!
 //! This is the main binary entry point for the EspressOS kernel.
 
 #![no_std]
 #![no_main]
 
+#[cfg(all(target_arch = "x86_64", not(feature = "bootloader-api")))]
+use bootloader::BootInfo;
 use core::panic::PanicInfo;
-use espress_os::{vga_println};
+#[cfg(target_arch = "x86_64")]
+use core::time::Duration;
+#[cfg(all(target_arch = "x86_64", not(feature = "bootloader-api")))]
+use espress_os::boot::uefi;
+#[cfg(target_arch = "x86_64")]
+use espress_os::power::{self, PanicAction};
+#[cfg(target_arch = "x86_64")]
+use espress_os::shell;
+#[cfg(target_arch = "x86_64")]
+use espress_os::task::{executor::Executor, Task};
+use espress_os::{boot, println};
 
 /// Panic handler for the kernel.
-/// 
+///
 /// This function is called when a panic occurs in the kernel. Since we're running
 /// in a bare-metal environment without an operating system, we cannot unwind the
-/// stack or perform complex error handling. Instead, we enter an infinite loop
-/// to halt the system.
+/// stack or perform complex error handling. Instead, we print the panic to every
+/// console backend, the serial port included, after taking their locks from
+/// whatever code the panic interrupted. Then we sound a low beep, so the panic is
+/// noticed even without a visible console, and enter an infinite loop to halt the
+/// system, unless the [panic action](espress_os::power::PanicAction) says to power
+/// off or reboot instead.
+#[cfg(target_arch = "x86_64")]
 #[panic_handler]
-fn panic(_info: &PanicInfo) -> ! {
-    loop {}
+fn panic(info: &PanicInfo) -> ! {
+    espress_os::arch::interrupts::disable();
+    unsafe { espress_os::console::force_unlock() };
+    println!("{}", info);
+    espress_os::speaker::beep_spin(220, Duration::from_millis(500));
+    match power::panic_action() {
+        PanicAction::PowerOff => power::power_off(),
+        PanicAction::Reboot => power::reset(),
+        PanicAction::Halt => loop {},
+    }
 }
 
-/// Kernel entry point.
-/// 
-/// This function serves as the entry point for the EspressOS kernel. The bootloader
-/// transfers control to this function after setting up the basic execution environment.
+/// Panic handler for the kernel on aarch64, riscv64, and i686.
+///
+/// The serial console is the only way to tell, so the panic is printed
+/// there before the CPU stops with interrupts masked.
+#[cfg(not(target_arch = "x86_64"))]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    espress_os::arch::interrupts::disable();
+    println!("{}", info);
+    loop {
+        espress_os::arch::interrupts::wait();
+    }
+}
+
+/// Kernel entry point, called by either boot protocol.
+///
+/// The `bootloader` crate passes its `BootInfo` in the first argument. The
+/// UEFI loader passes its own boot information there and
+/// [`uefi::MAGIC`] in the second argument, which the `bootloader` crate
+/// leaves undefined (see [`espress_os::boot`]).
+///
+/// The kernel first relocates itself for the address it was loaded at (see
+/// [`espress_os::reloc`]).
+#[cfg(all(target_arch = "x86_64", not(feature = "bootloader-api")))]
+#[export_name = "_start"]
+pub extern "C" fn start(boot_info: usize, magic: u64) -> ! {
+    unsafe { espress_os::reloc::relocate_self() };
+    if magic == uefi::MAGIC {
+        espress_os::init_uefi(unsafe { &*(boot_info as *const uefi::BootInfo) }, kernel_run)
+    } else {
+        kernel_main(unsafe { &*(boot_info as *const BootInfo) })
+    }
+}
+
+/// Kernel entry point for the `bootloader` crate.
+///
+/// The bootloader transfers control here after setting up the basic
+/// execution environment.
+///
+/// Kernel initialization moves execution off the bootloader's stack onto a
+/// guarded stack from the memory manager (and, with KASLR, to a randomized
+/// kernel base) before continuing in [`kernel_run`].
+#[cfg(all(target_arch = "x86_64", not(feature = "bootloader-api")))]
+fn kernel_main(boot_info: &'static BootInfo) -> ! {
+    espress_os::init(boot_info, kernel_run)
+}
+
+#[cfg(all(target_arch = "x86_64", feature = "bootloader-api"))]
+bootloader_api::entry_point!(
+    bootloader_api_main,
+    config = &espress_os::boot::bootloader_api::CONFIG
+);
+
+/// Kernel entry point for the `bootloader` crate 0.11, replacing the other
+/// two with the `bootloader-api` feature.
+#[cfg(all(target_arch = "x86_64", feature = "bootloader-api"))]
+fn bootloader_api_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
+    unsafe { espress_os::reloc::relocate_self() };
+    espress_os::init_bootloader_api(boot_info, kernel_run)
+}
+
+/// Kernel entry point on aarch64 and riscv64, called by
+/// [`arch::boot::start`](espress_os::arch::boot::start) with the boot stack
+/// set up.
+#[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
 #[no_mangle]
-pub extern "C" fn _start() -> ! {
-    vga_println!("Hello World!");
-    vga_println!("Welcome to EspressOS!");
+extern "C" fn kernel_main(devicetree: usize) -> ! {
+    espress_os::init_devicetree(devicetree, kernel_run)
+}
+
+/// Kernel entry point on i686, called by
+/// [`arch::boot::start`](espress_os::arch::boot::start) with the boot stack
+/// set up.
+#[cfg(target_arch = "x86")]
+#[no_mangle]
+extern "C" fn kernel_main(magic: u32, info: usize) -> ! {
+    espress_os::init_multiboot(magic, info, kernel_run)
+}
+
+/// Main kernel flow, running on the guarded kernel stack.
+///
+/// Reports the end of the boot sequence and hands control to the task
+/// executor running the [shell](espress_os::shell), which never returns.
+/// On the other architectures, which have no executor yet, the CPU idles
+/// instead, woken by the timer.
+extern "C" fn kernel_run() -> ! {
+    let time = boot::progress::elapsed().as_micros();
+    println!(
+        "EspressOS ready after {} boot stages ({}.{:03} ms)",
+        boot::progress::stages().len(),
+        time / 1000,
+        time % 1000
+    );
+
+    run()
+}
+
+/// Runs the task executor, which never returns.
+#[cfg(target_arch = "x86_64")]
+fn run() -> ! {
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(shell::run()));
+    executor.run();
+}
 
-    loop {}
+/// Idles, woken by the timer tick.
+#[cfg(not(target_arch = "x86_64"))]
+fn run() -> ! {
+    loop {
+        espress_os::arch::interrupts::wait();
+    }
 }
@@ -0,0 +1,476 @@
+//! # Address Spaces
+//!
+//! An [`AddressSpace`] is a page table hierarchy of its own: a private
+//! lower half for user mappings and the kernel's higher half, shared by
+//! reference. Processes each own one; loading its root into CR3
+//! ([`AddressSpace::activate`]) switches the user half and leaves the
+//! kernel untouched.
+//!
+//! The kernel half is shared at the entries of the root table, so it stays
+//! identical in every address space only if those entries never change
+//! after the first address space is created. With 4-level paging, [`init`]
+//! therefore gives every empty kernel level 4 entry a page table up front.
+//! With 5-level paging the kernel lives below the top level 5 entry, whose
+//! level 4 table is shared as a whole, so there is nothing to prepare.
+//!
+//! The user half always fits into one level 4 table: the root itself with
+//! 4-level paging, or the table below the first level 5 entry, which every
+//! address space gets of its own with 5-level paging. User mappings are
+//! made through that table, where both modes translate alike.
+//!
+//! User pages, and every page table on the way to them, carry
+//! `USER_ACCESSIBLE`, which the CPU requires at all levels before code
+//! running at CPL 3 may touch a page. Pages are filled through the physical
+//! memory map before they are mapped, so read-only and executable user
+//! pages never have to be writable, not even briefly. Likewise,
+//! [`AddressSpace::read`] and [`AddressSpace::write`] check and access user
+//! buffers through the physical memory map, so a bad pointer passed to a
+//! system call is an error rather than a kernel page fault.
+
+use spin::Mutex;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::mapper::{MapToError, TranslateResult};
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags,
+    PhysFrame, Size4KiB, Translate,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::paging::PagingMode;
+use super::{tlb, KernelFrameAllocator, USER_SPACE_END};
+
+const PAGE_SIZE: u64 = 4096;
+
+/// First root table index of the kernel half, with either paging mode.
+const KERNEL_ROOT_START: usize = 256;
+
+/// Level 4 entries covering the user half, i.e. up to [`USER_SPACE_END`].
+const USER_L4_ENTRIES: usize = 256;
+
+/// Flags of the page tables leading to user pages.
+const USER_TABLE_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::USER_ACCESSIBLE);
+
+/// Errors that can occur while creating an address space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressSpaceError {
+    /// Address spaces were not initialized yet
+    Unsupported,
+    /// No frame for the root table was available
+    OutOfMemory,
+}
+
+/// Errors that can occur while mapping user memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserMapError {
+    /// The range is not page aligned or not inside the user half
+    InvalidRange,
+    /// A page in the range is already mapped
+    AlreadyMapped,
+    /// Physical memory for the pages or page tables ran out
+    OutOfMemory,
+}
+
+/// A user pointer that does not refer to accessible user memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BadUserAddress(pub VirtAddr);
+
+/// Root table of the kernel's own address space.
+static KERNEL_ROOT: Mutex<Option<PhysFrame>> = Mutex::new(None);
+
+/// Records the kernel's root table and, with 4-level paging, backs every
+/// kernel level 4 entry with a page table, so the kernel half can be shared
+/// by address spaces.
+///
+/// Must run after the lower half has been released and before the first
+/// [`AddressSpace`] is created.
+///
+/// # Panics
+///
+/// Panics if memory for the page tables runs out.
+pub fn init() {
+    *KERNEL_ROOT.lock() = Some(Cr3::read().0);
+    if PagingMode::current() == PagingMode::FiveLevel {
+        return;
+    }
+
+    super::with_mapper(|mapper| {
+        for entry in mapper.level_4_table().iter_mut().skip(KERNEL_ROOT_START) {
+            if !entry.is_unused() {
+                continue;
+            }
+            let frame = zeroed_frame().expect("out of memory for kernel page tables");
+            entry.set_frame(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+        }
+    });
+}
+
+/// Returns `true` if `[start, start + len)` lies inside the user half.
+pub fn is_user_range(start: VirtAddr, len: u64) -> bool {
+    start
+        .as_u64()
+        .checked_add(len)
+        .is_some_and(|end| end <= USER_SPACE_END)
+}
+
+/// A set of page tables with a private user half.
+///
+/// Dropping an address space frees its user pages and page tables.
+pub struct AddressSpace {
+    /// Root table, of level 4 or 5 depending on the paging mode
+    root: PhysFrame,
+    /// Level 4 table holding the user half; `root` with 4-level paging
+    user_table: PhysFrame,
+    /// Serializes changes to the user half
+    lock: Mutex<()>,
+}
+
+impl AddressSpace {
+    /// Creates an address space with an empty user half.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`AddressSpaceError`] if [`init`] has not run yet or no
+    /// frame is available.
+    pub fn new() -> Result<AddressSpace, AddressSpaceError> {
+        let kernel_root = KERNEL_ROOT.lock().ok_or(AddressSpaceError::Unsupported)?;
+        let root = zeroed_frame().ok_or(AddressSpaceError::OutOfMemory)?;
+
+        let source = unsafe { table(kernel_root) };
+        let target = unsafe { table(root) };
+        for index in KERNEL_ROOT_START..512 {
+            target[index] = source[index].clone();
+        }
+
+        let user_table = match PagingMode::current() {
+            PagingMode::FourLevel => root,
+            PagingMode::FiveLevel => {
+                let Some(user_table) = zeroed_frame() else {
+                    unsafe { KernelFrameAllocator.deallocate_frame(root) };
+                    return Err(AddressSpaceError::OutOfMemory);
+                };
+                target[0].set_frame(user_table, USER_TABLE_FLAGS);
+                user_table
+            }
+        };
+        Ok(AddressSpace {
+            root,
+            user_table,
+            lock: Mutex::new(()),
+        })
+    }
+
+    /// Frame holding the root table.
+    pub fn root(&self) -> PhysFrame {
+        self.root
+    }
+
+    /// Returns `true` if CR3 points to this address space.
+    pub fn is_active(&self) -> bool {
+        Cr3::read().0 == self.root
+    }
+
+    /// Loads this address space into CR3, unless it already is.
+    pub fn activate(&self) {
+        let (current, flags) = Cr3::read();
+        if current != self.root {
+            unsafe { Cr3::write(self.root, flags) };
+        }
+    }
+
+    /// Maps fresh user pages over `[start, start + len)`.
+    ///
+    /// The pages start out with a copy of `contents` followed by zeros.
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - Page-aligned start of the range
+    /// * `len` - Length of the range, rounded up to whole pages
+    /// * `flags` - Access flags; `PRESENT` and `USER_ACCESSIBLE` are added
+    /// * `contents` - Initial data, at most `len` bytes
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`UserMapError`] if the range is invalid or partly mapped,
+    /// or if memory runs out. Pages mapped before the failure are unmapped
+    /// again.
+    pub fn map(
+        &self,
+        start: VirtAddr,
+        len: u64,
+        flags: PageTableFlags,
+        contents: &[u8],
+    ) -> Result<(), UserMapError> {
+        let len = len.next_multiple_of(PAGE_SIZE);
+        if !start.is_aligned(PAGE_SIZE) || !is_user_range(start, len) || contents.len() as u64 > len
+        {
+            return Err(UserMapError::InvalidRange);
+        }
+
+        let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+
+        let result = self.with_mapper(|mapper| {
+            for (index, page) in pages(start, len).enumerate() {
+                let frame = zeroed_frame().ok_or((index, UserMapError::OutOfMemory))?;
+
+                let offset = index * PAGE_SIZE as usize;
+                let chunk = contents.get(offset..).unwrap_or(&[]);
+                let chunk = &chunk[..chunk.len().min(PAGE_SIZE as usize)];
+                let bytes: *mut u8 = super::phys_to_virt(frame.start_address()).as_mut_ptr();
+                unsafe { core::ptr::copy_nonoverlapping(chunk.as_ptr(), bytes, chunk.len()) };
+
+                let mapped = unsafe {
+                    mapper.map_to_with_table_flags(
+                        page,
+                        frame,
+                        flags,
+                        USER_TABLE_FLAGS,
+                        &mut KernelFrameAllocator,
+                    )
+                };
+                match mapped {
+                    Ok(flush) => flush.flush(),
+                    Err(err) => {
+                        unsafe { KernelFrameAllocator.deallocate_frame(frame) };
+                        let err = match err {
+                            MapToError::PageAlreadyMapped(_) => UserMapError::AlreadyMapped,
+                            _ => UserMapError::OutOfMemory,
+                        };
+                        return Err((index, err));
+                    }
+                }
+            }
+            Ok(())
+        });
+
+        result.map_err(|(mapped, err)| {
+            self.unmap(start, mapped as u64 * PAGE_SIZE);
+            err
+        })
+    }
+
+    /// Unmaps the user pages in `[start, start + len)` and frees their
+    /// frames.
+    ///
+    /// Pages that are not mapped are skipped; the page tables themselves
+    /// are kept until the address space is dropped.
+    pub fn unmap(&self, start: VirtAddr, len: u64) {
+        if !is_user_range(start, len) {
+            return;
+        }
+        self.with_mapper(|mapper| {
+            for page in pages(start, len) {
+                if let Ok((frame, flush)) = mapper.unmap(page) {
+                    flush.ignore();
+                    tlb::shootdown(page.start_address());
+                    unsafe { KernelFrameAllocator.deallocate_frame(frame) };
+                }
+            }
+        });
+    }
+
+    /// Copies `dst.len()` bytes from user address `src` into `dst`.
+    ///
+    /// Every page touched must be mapped `USER_ACCESSIBLE`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first inaccessible address.
+    pub fn read(&self, src: VirtAddr, dst: &mut [u8]) -> Result<(), BadUserAddress> {
+        let required = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        self.for_each_chunk(
+            src,
+            dst.len() as u64,
+            required,
+            |offset, bytes, len| unsafe {
+                core::ptr::copy_nonoverlapping(bytes, dst[offset..].as_mut_ptr(), len);
+            },
+        )
+    }
+
+    /// Copies `src` to user address `dst`.
+    ///
+    /// Every page touched must be mapped `USER_ACCESSIBLE` and writable.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first inaccessible address.
+    pub fn write(&self, dst: VirtAddr, src: &[u8]) -> Result<(), BadUserAddress> {
+        let required =
+            PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::WRITABLE;
+        self.for_each_chunk(
+            dst,
+            src.len() as u64,
+            required,
+            |offset, bytes, len| unsafe {
+                core::ptr::copy_nonoverlapping(src[offset..].as_ptr(), bytes, len);
+            },
+        )
+    }
+
+    /// Returns the physical address user address `addr` is mapped to.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BadUserAddress`] unless `addr` is mapped `USER_ACCESSIBLE`.
+    pub fn translate(&self, addr: VirtAddr) -> Result<PhysAddr, BadUserAddress> {
+        if !is_user_range(addr, 1) {
+            return Err(BadUserAddress(addr));
+        }
+        let required = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        self.with_mapper(|mapper| match mapper.translate(addr) {
+            TranslateResult::Mapped {
+                frame,
+                offset,
+                flags,
+            } if flags.contains(required) => Ok(frame.start_address() + offset),
+            _ => Err(BadUserAddress(addr)),
+        })
+    }
+
+    /// Copies `src` to user address `dst`, even into read-only pages.
+    ///
+    /// Meant for loaders filling in a program image.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first address that is not mapped for user mode.
+    pub fn load(&self, dst: VirtAddr, src: &[u8]) -> Result<(), BadUserAddress> {
+        let required = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        self.for_each_chunk(
+            dst,
+            src.len() as u64,
+            required,
+            |offset, bytes, len| unsafe {
+                core::ptr::copy_nonoverlapping(src[offset..].as_ptr(), bytes, len);
+            },
+        )
+    }
+
+    /// Runs `f` with a mapper for the user half of this address space.
+    fn with_mapper<R>(&self, f: impl FnOnce(&mut OffsetPageTable) -> R) -> R {
+        let _guard = self.lock.lock();
+        let mut mapper =
+            unsafe { OffsetPageTable::new(table(self.user_table), super::phys_offset()) };
+        f(&mut mapper)
+    }
+
+    /// Resolves `[start, start + len)` page by page and calls `f` with the
+    /// offset into the range, a pointer to the bytes in the physical memory
+    /// map, and the number of bytes in that page.
+    ///
+    /// Every page must be mapped with at least the `required` flags. The
+    /// whole range is checked before `f` is called for the first time.
+    fn for_each_chunk(
+        &self,
+        start: VirtAddr,
+        len: u64,
+        required: PageTableFlags,
+        mut f: impl FnMut(usize, *mut u8, usize),
+    ) -> Result<(), BadUserAddress> {
+        if !is_user_range(start, len) {
+            return Err(BadUserAddress(start));
+        }
+
+        self.with_mapper(|mapper| {
+            let resolve = |addr: VirtAddr| match mapper.translate(addr) {
+                TranslateResult::Mapped {
+                    frame,
+                    offset,
+                    flags,
+                } if flags.contains(required) => Ok(frame.start_address() + offset),
+                _ => Err(BadUserAddress(addr)),
+            };
+
+            let mut offset = 0;
+            while offset < len {
+                resolve(start + offset)?;
+                offset = ((start + offset).align_down(PAGE_SIZE) + PAGE_SIZE) - start;
+            }
+
+            let mut offset = 0;
+            while offset < len {
+                let addr = start + offset;
+                let chunk = (PAGE_SIZE - u64::from(addr.page_offset())).min(len - offset);
+                let phys = resolve(addr)?;
+                f(
+                    offset as usize,
+                    super::phys_to_virt(phys).as_mut_ptr(),
+                    chunk as usize,
+                );
+                offset += chunk;
+            }
+            Ok(())
+        })
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        // Nothing may keep running on page tables that are about to be
+        // freed; kernel threads may still have this address space loaded.
+        if self.is_active() {
+            if let Some(kernel_root) = *KERNEL_ROOT.lock() {
+                let (_, flags) = Cr3::read();
+                unsafe { Cr3::write(kernel_root, flags) };
+            }
+        }
+
+        let user_table = unsafe { table(self.user_table) };
+        for entry in user_table.iter().take(USER_L4_ENTRIES) {
+            if !entry.is_unused() {
+                unsafe { free_table(PhysFrame::containing_address(entry.addr()), 3) };
+            }
+        }
+        if self.user_table != self.root {
+            unsafe { KernelFrameAllocator.deallocate_frame(self.user_table) };
+        }
+        unsafe { KernelFrameAllocator.deallocate_frame(self.root) };
+    }
+}
+
+/// Frees the page table `frame` of the given level, everything mapped
+/// through it, and the lower-level tables it references.
+///
+/// # Safety
+///
+/// The table must belong to a user half that is no longer in use.
+unsafe fn free_table(frame: PhysFrame, level: u8) {
+    for entry in table(frame).iter() {
+        if entry.is_unused() {
+            continue;
+        }
+        let child = PhysFrame::containing_address(entry.addr());
+        if level == 1 {
+            KernelFrameAllocator.deallocate_frame(child);
+        } else {
+            free_table(child, level - 1);
+        }
+    }
+    KernelFrameAllocator.deallocate_frame(frame);
+}
+
+/// Returns the page table stored in `frame`.
+///
+/// # Safety
+///
+/// `frame` must hold a page table, and the caller must not create aliasing
+/// mutable references to it.
+unsafe fn table(frame: PhysFrame) -> &'static mut PageTable {
+    &mut *super::phys_to_virt(frame.start_address()).as_mut_ptr()
+}
+
+/// Allocates a frame and fills it with zeros.
+fn zeroed_frame() -> Option<PhysFrame> {
+    let frame = KernelFrameAllocator.allocate_frame()?;
+    let bytes: *mut u8 = super::phys_to_virt(frame.start_address()).as_mut_ptr();
+    unsafe { core::ptr::write_bytes(bytes, 0, PAGE_SIZE as usize) };
+    Some(frame)
+}
+
+/// Iterates over the pages covering `[start, start + len)`.
+fn pages(start: VirtAddr, len: u64) -> impl Iterator<Item = Page<Size4KiB>> {
+    let first = Page::<Size4KiB>::containing_address(start);
+    (0..len.div_ceil(PAGE_SIZE)).map(move |index| first + index)
+}
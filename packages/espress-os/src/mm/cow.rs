@@ -0,0 +1,194 @@
+//! # Copy-on-Write Pages
+//!
+//! A copy-on-write page is mapped read-only with [`COW_FLAG`] set and its
+//! frame's reference count (see [`refcount`](super::refcount)) raised for
+//! every additional mapping. The first write to such a page faults;
+//! [`handle_fault`] then gives the writer a private copy of the frame, or
+//! simply makes the page writable again if nobody else shares it any more.
+//!
+//! This is the groundwork for duplicating address spaces cheaply once
+//! userspace processes exist.
+
+use x86_64::structures::idt::PageFaultErrorCode;
+use x86_64::structures::paging::mapper::{MappedFrame, TranslateResult};
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTableFlags, PhysFrame,
+    Size4KiB, Translate,
+};
+use x86_64::VirtAddr;
+
+use super::frame::FRAME_SIZE;
+use super::refcount::{self, RefCountError};
+use super::{tlb, KernelFrameAllocator};
+
+/// Page table flag marking a page as copy-on-write.
+///
+/// Uses one of the bits the CPU ignores and leaves to the OS.
+pub const COW_FLAG: PageTableFlags = PageTableFlags::BIT_9;
+
+/// Errors that can occur while sharing a page copy-on-write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CowError {
+    /// The page is not mapped, or mapped by a huge page
+    NotMapped,
+    /// The frame's reference count could not be raised
+    RefCount(RefCountError),
+    /// The destination page could not be mapped
+    MappingFailed,
+}
+
+/// Maps the frame behind `src` at `dst` as well, sharing it copy-on-write.
+///
+/// Both pages end up read-only with [`COW_FLAG`] set, and the frame gets one
+/// more reference. `src` and `dst` may live in different address spaces.
+///
+/// # Arguments
+///
+/// * `src_mapper` - Page table containing `src`
+/// * `src` - Page whose frame is shared
+/// * `dst_mapper` - Page table receiving the new mapping
+/// * `dst` - Unmapped page to map the shared frame at
+///
+/// # Errors
+///
+/// Returns a [`CowError`] if `src` is not a mapped 4 KiB page, if the
+/// frame's reference count cannot be raised, or if `dst` cannot be mapped.
+/// On error the source mapping is left unchanged.
+pub fn share(
+    src_mapper: &mut OffsetPageTable,
+    src: Page<Size4KiB>,
+    dst_mapper: &mut OffsetPageTable,
+    dst: Page<Size4KiB>,
+) -> Result<(), CowError> {
+    let (frame, flags) = translate(src_mapper, src).ok_or(CowError::NotMapped)?;
+    let flags = if flags.contains(PageTableFlags::WRITABLE) {
+        (flags - PageTableFlags::WRITABLE) | COW_FLAG
+    } else {
+        flags
+    };
+
+    refcount::increment(frame).map_err(CowError::RefCount)?;
+    match unsafe { dst_mapper.map_to(dst, frame, flags, &mut KernelFrameAllocator) } {
+        Ok(flush) => flush.flush(),
+        Err(_) => {
+            refcount::decrement(frame);
+            return Err(CowError::MappingFailed);
+        }
+    }
+
+    if let Ok(flush) = unsafe { src_mapper.update_flags(src, flags) } {
+        flush.ignore();
+        tlb::shootdown(src.start_address());
+    }
+    Ok(())
+}
+
+/// Unmaps `page` and drops its reference to the underlying frame.
+///
+/// The frame is returned to the frame allocator once its last mapping is
+/// gone. Pages that were never shared are freed right away.
+pub fn unmap(mapper: &mut OffsetPageTable, page: Page<Size4KiB>) {
+    if let Ok((frame, flush)) = mapper.unmap(page) {
+        flush.ignore();
+        tlb::shootdown(page.start_address());
+        if refcount::decrement(frame) == 0 {
+            unsafe { KernelFrameAllocator.deallocate_frame(frame) };
+        }
+    }
+}
+
+/// Resolves a write fault on a copy-on-write page.
+///
+/// Called from the page fault handler. If the fault was a write to a present
+/// page marked [`COW_FLAG`], the page is either made writable in place (when
+/// this is the last mapping of the frame) or remapped to a private copy.
+///
+/// The mapper is only try-locked, so a fault taken while the mapper is held
+/// is left to the regular fault path instead of deadlocking.
+///
+/// # Returns
+///
+/// `true` if the fault was handled and the faulting access can be retried.
+pub fn handle_fault(addr: VirtAddr, error_code: PageFaultErrorCode) -> bool {
+    let write_to_present =
+        PageFaultErrorCode::CAUSED_BY_WRITE | PageFaultErrorCode::PROTECTION_VIOLATION;
+    if !error_code.contains(write_to_present) {
+        return false;
+    }
+
+    super::try_with_mapper(|mapper| {
+        let page = Page::<Size4KiB>::containing_address(addr);
+        let Some((frame, flags)) = translate(mapper, page) else {
+            return false;
+        };
+        if !flags.contains(COW_FLAG) {
+            return false;
+        }
+        let flags = (flags - COW_FLAG) | PageTableFlags::WRITABLE;
+
+        if refcount::count(frame) == 1 {
+            return match unsafe { mapper.update_flags(page, flags) } {
+                Ok(flush) => {
+                    flush.flush();
+                    true
+                }
+                Err(_) => false,
+            };
+        }
+
+        let Some(copy) = KernelFrameAllocator.allocate_frame() else {
+            return false;
+        };
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                super::phys_to_virt(frame.start_address()).as_ptr::<u8>(),
+                super::phys_to_virt(copy.start_address()).as_mut_ptr::<u8>(),
+                FRAME_SIZE as usize,
+            );
+        }
+
+        // The page tables for `page` already exist, so mapping the copy
+        // cannot fail for lack of memory once the old entry is gone.
+        let Ok((_, flush)) = mapper.unmap(page) else {
+            unsafe { KernelFrameAllocator.deallocate_frame(copy) };
+            return false;
+        };
+        flush.ignore();
+        match unsafe { mapper.map_to(page, copy, flags, &mut KernelFrameAllocator) } {
+            Ok(flush) => {
+                flush.ignore();
+                tlb::shootdown(page.start_address());
+                if refcount::decrement(frame) == 0 {
+                    unsafe { KernelFrameAllocator.deallocate_frame(frame) };
+                }
+                true
+            }
+            Err(_) => {
+                unsafe { KernelFrameAllocator.deallocate_frame(copy) };
+                let old_flags = (flags - PageTableFlags::WRITABLE) | COW_FLAG;
+                if let Ok(flush) =
+                    unsafe { mapper.map_to(page, frame, old_flags, &mut KernelFrameAllocator) }
+                {
+                    flush.flush();
+                }
+                false
+            }
+        }
+    })
+    .unwrap_or(false)
+}
+
+/// Returns the frame and leaf flags of a page mapped by a 4 KiB entry.
+fn translate(
+    mapper: &OffsetPageTable,
+    page: Page<Size4KiB>,
+) -> Option<(PhysFrame<Size4KiB>, PageTableFlags)> {
+    match mapper.translate(page.start_address()) {
+        TranslateResult::Mapped {
+            frame: MappedFrame::Size4KiB(frame),
+            flags,
+            ..
+        } => Some((frame, flags)),
+        _ => None,
+    }
+}
@@ -0,0 +1,284 @@
+//! # DMA Buffers
+//!
+//! Physically contiguous, zeroed buffers for devices that access memory
+//! directly. Every buffer is a single buddy block, so it is contiguous and
+//! naturally aligned to its own size, and exposes both the physical address
+//! to program into the device and a virtual address for the driver.
+//!
+//! ## Cache Modes
+//!
+//! x86 keeps DMA coherent with the CPU caches, so most drivers want
+//! [`CacheMode::WriteBack`], which simply hands out the block's address in
+//! the linear physical memory mapping. Devices that expect every CPU write to
+//! reach memory immediately, or buffers that are shared with hardware that
+//! does not snoop, can use [`CacheMode::Uncached`] instead. Buffers the CPU
+//! mostly fills for the device, such as frame or command buffers, can use
+//! [`CacheMode::WriteCombining`], which buffers and merges writes but does
+//! not cache reads; it relies on the PAT entry set up by
+//! [`pat::init`](crate::arch::pat::init) and falls back to uncached memory
+//! on CPUs without a PAT.
+//!
+//! Uncached and write-combining buffers are mapped a second time in a
+//! dedicated region at [`DMA_REGION_START`]` + physical address` with their
+//! memory type; the cached view in the linear mapping must not be touched
+//! while such a buffer is alive.
+
+use core::slice;
+use x86_64::structures::paging::{Mapper, Page, PageTableFlags, PhysFrame, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::frame::{block_size, order_for, FRAME_SIZE, MAX_ORDER};
+use super::{tlb, KernelFrameAllocator};
+use crate::arch::pat;
+
+/// First address of the virtual region holding uncached and
+/// write-combining DMA mappings.
+pub const DMA_REGION_START: u64 = 0xffff_fe00_0000_0000;
+
+/// Size of the DMA region (one level 4 entry).
+pub const DMA_REGION_SIZE: u64 = 1 << 39;
+
+/// Caching behavior of the CPU mapping of a DMA buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheMode {
+    /// Normal cached memory, relying on x86 DMA cache coherency
+    WriteBack,
+    /// Caching disabled (`PCD | PWT`), every access goes to memory
+    Uncached,
+    /// Write-combining (`PWT` with the kernel's PAT): writes are buffered
+    /// and merged, reads go to memory
+    WriteCombining,
+}
+
+impl CacheMode {
+    /// Page table flags selecting the memory type, for modes that need a
+    /// mapping of their own.
+    fn page_flags(self) -> Option<PageTableFlags> {
+        match self {
+            CacheMode::WriteBack => None,
+            CacheMode::Uncached => Some(PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH),
+            CacheMode::WriteCombining => Some(PageTableFlags::WRITE_THROUGH),
+        }
+    }
+}
+
+/// Errors that can occur while allocating a DMA buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmaError {
+    /// The length is zero or larger than the biggest buddy block
+    InvalidSize,
+    /// The alignment is not a power of two or larger than the biggest block
+    InvalidAlignment,
+    /// No physically contiguous block of the required size is free
+    OutOfMemory,
+    /// The block lies outside the DMA region or could not be mapped
+    MappingFailed,
+}
+
+/// A physically contiguous buffer shared with a device.
+///
+/// The memory is returned to the frame allocator when the buffer is dropped.
+/// The driver must make sure the device no longer accesses it by then.
+#[derive(Debug)]
+pub struct DmaBuffer {
+    /// Physical start address, as seen by the device
+    phys: PhysAddr,
+    /// Virtual start address, as seen by the CPU
+    virt: VirtAddr,
+    /// Requested length in bytes
+    len: usize,
+    /// Buddy order of the backing block
+    order: usize,
+    /// Caching behavior of `virt`
+    mode: CacheMode,
+}
+
+/// Allocates a zeroed, cache-coherent DMA buffer.
+///
+/// Shorthand for [`alloc`] with [`CacheMode::WriteBack`].
+///
+/// # Arguments
+///
+/// * `len` - Buffer length in bytes
+/// * `align` - Required physical alignment; must be a power of two
+///
+/// # Errors
+///
+/// See [`alloc`].
+pub fn alloc_coherent(len: usize, align: usize) -> Result<DmaBuffer, DmaError> {
+    alloc(len, align, CacheMode::WriteBack)
+}
+
+/// Allocates a zeroed DMA buffer with the given cache mode.
+///
+/// The buffer is rounded up to a power-of-two number of frames and is always
+/// aligned to at least its rounded size.
+///
+/// # Arguments
+///
+/// * `len` - Buffer length in bytes
+/// * `align` - Required physical alignment; must be a power of two
+/// * `mode` - Caching behavior of the CPU mapping
+///
+/// # Errors
+///
+/// Returns a [`DmaError`] if the size or alignment cannot be satisfied by a
+/// single buddy block, if no such block is free, or if the uncached or
+/// write-combining mapping cannot be created.
+pub fn alloc(len: usize, align: usize, mode: CacheMode) -> Result<DmaBuffer, DmaError> {
+    if len == 0 || len as u64 > block_size(MAX_ORDER) {
+        return Err(DmaError::InvalidSize);
+    }
+    if !align.is_power_of_two() || align as u64 > block_size(MAX_ORDER) {
+        return Err(DmaError::InvalidAlignment);
+    }
+
+    let frames = (len as u64)
+        .div_ceil(FRAME_SIZE)
+        .max(align as u64 / FRAME_SIZE);
+    let order = order_for(frames);
+    let phys = super::with_frames(|frames| frames.allocate(order)).ok_or(DmaError::OutOfMemory)?;
+
+    let mode = match mode {
+        CacheMode::WriteCombining if !pat::write_combining() => CacheMode::Uncached,
+        mode => mode,
+    };
+    let virt = match mode.page_flags() {
+        None => super::phys_to_virt(phys),
+        Some(flags) => match map_dma(phys, order, flags) {
+            Some(virt) => virt,
+            None => {
+                unsafe { super::with_frames(|frames| frames.deallocate(phys, order)) };
+                return Err(DmaError::MappingFailed);
+            }
+        },
+    };
+
+    unsafe { core::ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, block_size(order) as usize) };
+
+    Ok(DmaBuffer {
+        phys,
+        virt,
+        len,
+        order,
+        mode,
+    })
+}
+
+impl DmaBuffer {
+    /// Physical address of the buffer, to be handed to the device.
+    pub fn phys_addr(&self) -> PhysAddr {
+        self.phys
+    }
+
+    /// Virtual address of the buffer, for CPU access.
+    pub fn virt_addr(&self) -> VirtAddr {
+        self.virt
+    }
+
+    /// Length of the buffer in bytes, as requested.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the buffer has a length of zero, which never happens
+    /// for successfully allocated buffers.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Caching behavior of the CPU mapping; [`CacheMode::Uncached`] if
+    /// write-combining was requested but is not available.
+    pub fn cache_mode(&self) -> CacheMode {
+        self.mode
+    }
+
+    /// Raw pointer to the start of the buffer.
+    pub fn as_ptr(&self) -> *const u8 {
+        self.virt.as_ptr()
+    }
+
+    /// Raw mutable pointer to the start of the buffer.
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.virt.as_mut_ptr()
+    }
+
+    /// Views the buffer as a byte slice.
+    ///
+    /// The device may change the contents at any time while it owns the
+    /// buffer; use volatile accesses through [`as_ptr`](Self::as_ptr) for
+    /// memory that is live on the device side.
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.as_ptr(), self.len) }
+    }
+
+    /// Views the buffer as a mutable byte slice.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.as_mut_ptr(), self.len) }
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        if self.mode != CacheMode::WriteBack {
+            let first = Page::<Size4KiB>::containing_address(self.virt);
+            super::with_mapper(|mapper| {
+                for index in 0..1u64 << self.order {
+                    if let Ok((_, flush)) = mapper.unmap(first + index) {
+                        flush.ignore();
+                    }
+                }
+            });
+            tlb::shootdown_range(self.virt, 1 << self.order);
+        }
+        unsafe { super::with_frames(|frames| frames.deallocate(self.phys, self.order)) };
+    }
+}
+
+/// Maps a block into the DMA region with the memory type selected by
+/// `cache_flags`.
+///
+/// # Returns
+///
+/// The virtual address of the mapping, or `None` if the block lies outside
+/// the region or a page table could not be allocated. A partial mapping is
+/// removed again on failure.
+fn map_dma(phys: PhysAddr, order: usize, cache_flags: PageTableFlags) -> Option<VirtAddr> {
+    let size = block_size(order);
+    if phys.as_u64() + size > DMA_REGION_SIZE {
+        return None;
+    }
+
+    let virt = VirtAddr::new(DMA_REGION_START + phys.as_u64());
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_EXECUTE
+        | cache_flags;
+    let first_page = Page::<Size4KiB>::containing_address(virt);
+    let first_frame = PhysFrame::<Size4KiB>::containing_address(phys);
+
+    super::with_mapper(|mapper| {
+        for index in 0..size / FRAME_SIZE {
+            let result = unsafe {
+                mapper.map_to(
+                    first_page + index,
+                    first_frame + index,
+                    flags,
+                    &mut KernelFrameAllocator,
+                )
+            };
+            match result {
+                Ok(flush) => flush.flush(),
+                Err(_) => {
+                    for mapped in 0..index {
+                        if let Ok((_, flush)) = mapper.unmap(first_page + mapped) {
+                            flush.flush();
+                        }
+                    }
+                    return None;
+                }
+            }
+        }
+        Some(virt)
+    })
+}
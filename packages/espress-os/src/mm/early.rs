@@ -0,0 +1,153 @@
+//! # Early Allocator
+//!
+//! Serves allocations made before the kernel heap is mapped, such as
+//! scratch space for firmware tables, copies of the boot information, or
+//! console buffers. Until [`heap::init`](super::heap::init) succeeds, the
+//! global allocator takes its memory from here: a bump allocator over a
+//! fixed arena in the kernel's `.bss`, so it needs neither paging changes
+//! nor free frames.
+//!
+//! Freeing the most recent block gives its space back; any other freed
+//! block is only poisoned. Once the heap comes online the arena is retired:
+//! blocks still in use stay valid and are freed back here, never to the
+//! heap, while the unused rest of the arena and every block freed from then
+//! on is filled with [`POISON`], so a stale pointer into it reads an
+//! obvious pattern.
+
+use core::alloc::Layout;
+use core::cell::UnsafeCell;
+use core::ptr::{self, NonNull};
+use spin::Mutex;
+
+/// Size of the arena in bytes (64 KiB).
+pub const ARENA_SIZE: usize = 64 * 1024;
+
+/// Byte written over memory that must no longer be used.
+pub const POISON: u8 = 0xa5;
+
+/// The arena, page-aligned so large alignments waste little of it.
+#[repr(C, align(4096))]
+struct Arena(UnsafeCell<[u8; ARENA_SIZE]>);
+
+// The bytes are only handed out under `STATE`, each range to one owner.
+unsafe impl Sync for Arena {}
+
+static ARENA: Arena = Arena(UnsafeCell::new([0; ARENA_SIZE]));
+
+static STATE: Mutex<EarlyState> = Mutex::new(EarlyState {
+    next: 0,
+    live: 0,
+    allocations: 0,
+    retired: false,
+});
+
+/// Bookkeeping of the bump allocator.
+struct EarlyState {
+    /// Offset of the first unused byte
+    next: usize,
+    /// Number of blocks not yet freed
+    live: usize,
+    /// Number of blocks handed out since boot
+    allocations: usize,
+    /// The heap has taken over
+    retired: bool,
+}
+
+/// Usage of the early arena.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EarlyStats {
+    /// Size of the arena in bytes
+    pub size: usize,
+    /// Bytes handed out, including alignment padding
+    pub used: usize,
+    /// Number of blocks not yet freed
+    pub live: usize,
+    /// Number of blocks handed out since boot
+    pub allocations: usize,
+    /// The heap has taken over
+    pub retired: bool,
+}
+
+/// Allocates a block for `layout` from the arena.
+///
+/// # Returns
+///
+/// `None` if the arena is full or already retired.
+pub fn alloc(layout: Layout) -> Option<NonNull<u8>> {
+    let mut state = STATE.lock();
+    if state.retired {
+        return None;
+    }
+    let base = base() as usize;
+    let start = (base + state.next).checked_next_multiple_of(layout.align())? - base;
+    let end = start.checked_add(layout.size())?;
+    if end > ARENA_SIZE {
+        return None;
+    }
+    state.next = end;
+    state.live += 1;
+    state.allocations += 1;
+    NonNull::new(unsafe { base_mut().add(start) })
+}
+
+/// Frees a block returned by [`alloc`].
+///
+/// The block is poisoned. If it is the most recent one and the arena is
+/// not retired, its space is reused by the next allocation.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by [`alloc`] for `layout` and not freed
+/// since.
+pub unsafe fn dealloc(ptr: *mut u8, layout: Layout) {
+    let mut state = STATE.lock();
+    let start = ptr as usize - base() as usize;
+    ptr::write_bytes(ptr, POISON, layout.size());
+    if !state.retired && start + layout.size() == state.next {
+        state.next = start;
+    }
+    state.live -= 1;
+}
+
+/// Returns `true` if `ptr` points into the arena.
+pub fn contains(ptr: *const u8) -> bool {
+    let base = base() as usize;
+    (base..base + ARENA_SIZE).contains(&(ptr as usize))
+}
+
+/// Returns a snapshot of the arena usage.
+pub fn stats() -> EarlyStats {
+    let state = STATE.lock();
+    EarlyStats {
+        size: ARENA_SIZE,
+        used: state.next,
+        live: state.live,
+        allocations: state.allocations,
+        retired: state.retired,
+    }
+}
+
+/// Stops handing out memory and poisons the unused rest of the arena.
+///
+/// Called by the heap once it takes over.
+///
+/// # Returns
+///
+/// The arena usage at the handoff.
+pub(super) fn retire() -> EarlyStats {
+    let mut state = STATE.lock();
+    state.retired = true;
+    unsafe { ptr::write_bytes(base_mut().add(state.next), POISON, ARENA_SIZE - state.next) };
+    drop(state);
+    stats()
+}
+
+/// First byte of the arena.
+fn base() -> *const u8 {
+    ARENA.0.get().cast()
+}
+
+/// First byte of the arena, for writing.
+fn base_mut() -> *mut u8 {
+    ARENA.0.get().cast()
+}
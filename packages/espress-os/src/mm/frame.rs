@@ -0,0 +1,234 @@
+//! # Physical Frame Allocator
+//!
+//! A binary buddy allocator over 4 KiB physical frames. Free blocks are kept
+//! in one intrusive singly-linked list per order; the link pointer is stored
+//! in the first bytes of the free block itself and accessed through the
+//! bootloader's physical memory mapping, so the allocator needs no storage of
+//! its own beyond the list heads.
+
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// Size of a single physical frame in bytes.
+pub const FRAME_SIZE: u64 = 4096;
+
+/// Largest block order handed out by the allocator (2^10 frames = 4 MiB).
+pub const MAX_ORDER: usize = 10;
+
+/// Binary buddy allocator for physical memory.
+///
+/// Blocks of order `n` span `2^n` contiguous frames and are always aligned to
+/// their own size, which makes finding a block's buddy a single XOR.
+pub struct BuddyAllocator {
+    /// Head of the free list for each order (physical address, 0 = empty)
+    free_lists: [u64; MAX_ORDER + 1],
+    /// Virtual address at which all physical memory is mapped
+    phys_offset: VirtAddr,
+    /// Number of frames handed to the allocator at boot
+    total_frames: u64,
+    /// Number of frames currently sitting in the free lists
+    free_frames: u64,
+}
+
+impl BuddyAllocator {
+    /// Creates an empty allocator.
+    ///
+    /// Memory must be added with [`add_region`](Self::add_region) before any
+    /// allocation can succeed.
+    pub const fn new(phys_offset: VirtAddr) -> Self {
+        BuddyAllocator {
+            free_lists: [0; MAX_ORDER + 1],
+            phys_offset,
+            total_frames: 0,
+            free_frames: 0,
+        }
+    }
+
+    /// Hands the physical range `[start, end)` to the allocator.
+    ///
+    /// The range is trimmed to frame boundaries and split into the largest
+    /// naturally aligned blocks that fit.
+    ///
+    /// # Safety
+    ///
+    /// The range must be unused RAM that is covered by the physical memory
+    /// mapping and not handed out by anything else.
+    pub unsafe fn add_region(&mut self, start: u64, end: u64) {
+        let mut addr = align_up(start.max(FRAME_SIZE), FRAME_SIZE);
+        let end = end & !(FRAME_SIZE - 1);
+
+        while addr < end {
+            let mut order = MAX_ORDER;
+            while order > 0
+                && (!addr.is_multiple_of(block_size(order)) || addr + block_size(order) > end)
+            {
+                order -= 1;
+            }
+            self.push(order, addr);
+            self.total_frames += 1 << order;
+            self.free_frames += 1 << order;
+            addr += block_size(order);
+        }
+    }
+
+    /// Allocates a block of `2^order` contiguous frames.
+    ///
+    /// # Returns
+    ///
+    /// The physical address of the block, aligned to its size, or `None`
+    /// if no block of that order can be produced.
+    pub fn allocate(&mut self, order: usize) -> Option<PhysAddr> {
+        if order > MAX_ORDER {
+            return None;
+        }
+
+        let mut current = (order..=MAX_ORDER).find(|&o| self.free_lists[o] != 0)?;
+        let addr = self.pop(current)?;
+
+        // Split the block down, returning the upper halves to the free lists.
+        while current > order {
+            current -= 1;
+            self.push(current, addr + block_size(current));
+        }
+
+        self.free_frames -= 1 << order;
+        Some(PhysAddr::new(addr))
+    }
+
+    /// Allocates a block of `2^order` contiguous frames that ends at or
+    /// below physical address `limit`, for hardware that cannot reach
+    /// higher, such as code the CPU runs in real mode.
+    ///
+    /// The free lists are searched from `order` up for a block low enough;
+    /// this walks the lists, so it is meant for rare allocations.
+    ///
+    /// # Returns
+    ///
+    /// The physical address of the block, aligned to its size, or `None`
+    /// if no free block lies low enough.
+    pub fn allocate_below(&mut self, order: usize, limit: u64) -> Option<PhysAddr> {
+        if order > MAX_ORDER {
+            return None;
+        }
+
+        let (mut current, addr) = (order..=MAX_ORDER).find_map(|o| {
+            let mut addr = self.free_lists[o];
+            while addr != 0 {
+                if addr + block_size(order) <= limit {
+                    return Some((o, addr));
+                }
+                addr = unsafe { self.link(addr).read() };
+            }
+            None
+        })?;
+        self.remove(current, addr);
+
+        // Split the block down, keeping the lowest part.
+        while current > order {
+            current -= 1;
+            self.push(current, addr + block_size(current));
+        }
+
+        self.free_frames -= 1 << order;
+        Some(PhysAddr::new(addr))
+    }
+
+    /// Returns a block previously obtained from [`allocate`](Self::allocate).
+    ///
+    /// Free buddies are merged back into larger blocks eagerly.
+    ///
+    /// # Safety
+    ///
+    /// `addr` and `order` must describe a block that is currently allocated
+    /// and no longer referenced.
+    pub unsafe fn deallocate(&mut self, addr: PhysAddr, order: usize) {
+        let mut addr = addr.as_u64();
+        let mut order = order;
+        self.free_frames += 1 << order;
+
+        while order < MAX_ORDER {
+            let buddy = addr ^ block_size(order);
+            if !self.remove(order, buddy) {
+                break;
+            }
+            addr = addr.min(buddy);
+            order += 1;
+        }
+        self.push(order, addr);
+    }
+
+    /// Number of frames managed by the allocator.
+    pub fn total_frames(&self) -> u64 {
+        self.total_frames
+    }
+
+    /// Number of frames currently available for allocation.
+    pub fn free_frames(&self) -> u64 {
+        self.free_frames
+    }
+
+    /// Returns a pointer to the link word stored at the start of a free block.
+    fn link(&self, addr: u64) -> *mut u64 {
+        (self.phys_offset + addr).as_mut_ptr()
+    }
+
+    fn push(&mut self, order: usize, addr: u64) {
+        unsafe { self.link(addr).write(self.free_lists[order]) };
+        self.free_lists[order] = addr;
+    }
+
+    fn pop(&mut self, order: usize) -> Option<u64> {
+        let head = self.free_lists[order];
+        if head == 0 {
+            return None;
+        }
+        self.free_lists[order] = unsafe { self.link(head).read() };
+        Some(head)
+    }
+
+    /// Unlinks `addr` from the free list of `order`, if present.
+    fn remove(&mut self, order: usize, addr: u64) -> bool {
+        let mut prev: Option<u64> = None;
+        let mut current = self.free_lists[order];
+
+        while current != 0 {
+            let next = unsafe { self.link(current).read() };
+            if current == addr {
+                match prev {
+                    Some(prev) => unsafe { self.link(prev).write(next) },
+                    None => self.free_lists[order] = next,
+                }
+                return true;
+            }
+            prev = Some(current);
+            current = next;
+        }
+        false
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BuddyAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        self.allocate(0).map(PhysFrame::containing_address)
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BuddyAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.deallocate(frame.start_address(), 0);
+    }
+}
+
+/// Size in bytes of a block of the given order.
+pub const fn block_size(order: usize) -> u64 {
+    FRAME_SIZE << order
+}
+
+/// Smallest order whose block holds at least `frames` frames.
+pub fn order_for(frames: u64) -> usize {
+    frames.max(1).next_power_of_two().trailing_zeros() as usize
+}
+
+fn align_up(value: u64, align: u64) -> u64 {
+    (value + align - 1) & !(align - 1)
+}
@@ -0,0 +1,352 @@
+//! # Kernel Heap
+//!
+//! Backs the `alloc` crate with a linked-list allocator on a fixed virtual
+//! region that is mapped with fresh frames during boot.
+//!
+//! ## Out-of-Memory Handling
+//!
+//! What happens when an allocation cannot be satisfied depends on the
+//! [`OomPolicy`]:
+//!
+//! - [`OomPolicy::ReturnNull`] (the default) lets fallible APIs such as
+//!   [`try_alloc`] or `Vec::try_reserve` see the failure and recover.
+//!   Infallible allocations (`Box::new`, `Vec::push`, ...) still end up in the
+//!   allocation error handler.
+//! - [`OomPolicy::Panic`] treats every failed allocation as fatal, which makes
+//!   the first failure visible during development even if a fallible caller
+//!   would have handled it.
+//!
+//! In both cases a fatal failure prints the failed layout, the heap usage,
+//! and a backtrace before stopping.
+//!
+//! ## Before the Heap
+//!
+//! Until [`init`] has mapped the heap, allocations are served from the
+//! static [early arena](super::early). Blocks from there are freed back to
+//! it, also after the heap has taken over, and are not counted in
+//! [`stats`].
+//!
+//! ## Interrupts
+//!
+//! The scheduler and interrupt handlers allocate too, e.g. when a wakeup
+//! queues a thread. The heap is therefore only locked with interrupts
+//! disabled: an interrupt can never arrive while the interrupted code holds
+//! the lock and then spin on it forever.
+//!
+//! ## Statistics
+//!
+//! The allocator keeps running totals that are available through [`stats`].
+//! With [`show_in_status_bar`] enabled they are also displayed in the VGA
+//! status bar and refreshed on every allocation and deallocation, which makes
+//! leaks visible while the kernel runs.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use linked_list_allocator::Heap;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags, Size4KiB,
+};
+use x86_64::VirtAddr;
+
+use super::{early, KernelFrameAllocator};
+use crate::println;
+
+/// First address of the kernel heap.
+pub const HEAP_START: u64 = 0xffff_fc00_0000_0000;
+
+/// Size of the kernel heap in bytes (4 MiB).
+pub const HEAP_SIZE: usize = 4 * 1024 * 1024;
+
+const PAGE_SIZE: u64 = 4096;
+
+#[global_allocator]
+static ALLOCATOR: KernelHeap = KernelHeap {
+    state: Mutex::new(HeapState {
+        heap: Heap::empty(),
+        stats: HeapStats::new(),
+    }),
+};
+
+/// Whether heap statistics are shown in the VGA status bar.
+static STATUS_BAR: AtomicBool = AtomicBool::new(false);
+
+/// Currently active [`OomPolicy`], stored as its discriminant.
+static OOM_POLICY: AtomicU8 = AtomicU8::new(OomPolicy::ReturnNull as u8);
+
+/// What the heap does when an allocation fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum OomPolicy {
+    /// Report the failure to the caller by returning null
+    ReturnNull,
+    /// Print diagnostics and panic on any failed allocation
+    Panic,
+}
+
+/// Errors that can occur while setting up the heap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeapError {
+    /// Physical memory for the heap pages or page tables ran out
+    OutOfMemory,
+}
+
+/// Heap usage counters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    /// Total size of the heap in bytes
+    pub size: usize,
+    /// Bytes currently handed out, as requested by callers
+    pub allocated_bytes: usize,
+    /// Bytes not in use, as seen by the allocator (excludes its overhead)
+    pub free_bytes: usize,
+    /// Number of live allocations
+    pub allocations: usize,
+    /// Number of allocations made since boot
+    pub total_allocations: u64,
+    /// Highest value `allocated_bytes` has reached
+    pub peak_bytes: usize,
+}
+
+impl HeapStats {
+    const fn new() -> Self {
+        HeapStats {
+            size: 0,
+            allocated_bytes: 0,
+            free_bytes: 0,
+            allocations: 0,
+            total_allocations: 0,
+            peak_bytes: 0,
+        }
+    }
+}
+
+/// The heap together with its counters, guarded by one lock.
+struct HeapState {
+    heap: Heap,
+    stats: HeapStats,
+}
+
+impl HeapState {
+    /// Returns the counters with the allocator-derived fields filled in.
+    fn stats(&self) -> HeapStats {
+        HeapStats {
+            size: self.heap.size(),
+            free_bytes: self.heap.free(),
+            ..self.stats
+        }
+    }
+}
+
+/// Global allocator wrapping a locked linked-list heap.
+struct KernelHeap {
+    state: Mutex<HeapState>,
+}
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        interrupts::without_interrupts(|| self.alloc_locked(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        interrupts::without_interrupts(|| self.dealloc_locked(ptr, layout));
+    }
+}
+
+impl KernelHeap {
+    /// Body of [`GlobalAlloc::alloc`]; must run with interrupts disabled.
+    fn alloc_locked(&self, layout: Layout) -> *mut u8 {
+        let result = {
+            let mut state = self.state.lock();
+            if state.heap.size() == 0 {
+                return early::alloc(layout).map_or(ptr::null_mut(), NonNull::as_ptr);
+            }
+            let result = state.heap.allocate_first_fit(layout);
+            if result.is_ok() {
+                let stats = &mut state.stats;
+                stats.allocated_bytes += layout.size();
+                stats.allocations += 1;
+                stats.total_allocations += 1;
+                stats.peak_bytes = stats.peak_bytes.max(stats.allocated_bytes);
+            }
+            result
+        };
+
+        match result {
+            Ok(ptr) => {
+                refresh_status_bar();
+                ptr.as_ptr()
+            }
+            Err(()) if oom_policy() == OomPolicy::Panic => {
+                report_failure(layout);
+                panic!("out of memory allocating {:?}", layout);
+            }
+            Err(()) => ptr::null_mut(),
+        }
+    }
+
+    /// Body of [`GlobalAlloc::dealloc`]; must run with interrupts disabled.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been allocated by this allocator with `layout`.
+    unsafe fn dealloc_locked(&self, ptr: *mut u8, layout: Layout) {
+        if early::contains(ptr) {
+            early::dealloc(ptr, layout);
+            return;
+        }
+        {
+            let mut state = self.state.lock();
+            state.heap.deallocate(NonNull::new_unchecked(ptr), layout);
+            state.stats.allocated_bytes -= layout.size();
+            state.stats.allocations -= 1;
+        }
+        refresh_status_bar();
+    }
+}
+
+/// Maps the heap region and hands it to the allocator, retiring the
+/// [early arena](super::early).
+///
+/// # Errors
+///
+/// Returns [`HeapError::OutOfMemory`] if the heap could not be backed by
+/// physical memory. Pages mapped so far stay mapped but unused.
+///
+/// # Panics
+///
+/// Panics if called more than once.
+pub fn init() -> Result<(), HeapError> {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let first = Page::<Size4KiB>::containing_address(VirtAddr::new(HEAP_START));
+
+    super::with_mapper(|mapper| {
+        for index in 0..HEAP_SIZE as u64 / PAGE_SIZE {
+            let frame = KernelFrameAllocator
+                .allocate_frame()
+                .ok_or(HeapError::OutOfMemory)?;
+            match unsafe { mapper.map_to(first + index, frame, flags, &mut KernelFrameAllocator) } {
+                Ok(flush) => flush.flush(),
+                Err(_) => {
+                    unsafe { KernelFrameAllocator.deallocate_frame(frame) };
+                    return Err(HeapError::OutOfMemory);
+                }
+            }
+        }
+        Ok(())
+    })?;
+
+    interrupts::without_interrupts(|| {
+        let mut state = ALLOCATOR.state.lock();
+        assert!(state.heap.size() == 0, "kernel heap initialized twice");
+        unsafe { state.heap.init(HEAP_START as *mut u8, HEAP_SIZE) };
+    });
+
+    let early = early::retire();
+    println!(
+        "heap: {} KiB online, early arena retired with {} of {} bytes used, {} blocks live",
+        HEAP_SIZE / 1024,
+        early.used,
+        early.size,
+        early.live
+    );
+    Ok(())
+}
+
+/// Returns a snapshot of the heap counters.
+pub fn stats() -> HeapStats {
+    interrupts::without_interrupts(|| ALLOCATOR.state.lock().stats())
+}
+
+/// Enables or disables the live heap display in the VGA status bar.
+///
+/// Disabling it removes the status bar again.
+pub fn show_in_status_bar(enabled: bool) {
+    STATUS_BAR.store(enabled, Ordering::Relaxed);
+    if enabled {
+        refresh_status_bar();
+    } else {
+        crate::WRITER.lock().clear_status();
+    }
+}
+
+/// Redraws the status bar with the current counters, if enabled.
+///
+/// Both the heap and the VGA writer are only try-locked: an allocation made
+/// while either is held simply skips the update.
+fn refresh_status_bar() {
+    if !STATUS_BAR.load(Ordering::Relaxed) {
+        return;
+    }
+    let Some(stats) = ALLOCATOR.state.try_lock().map(|state| state.stats()) else {
+        return;
+    };
+    if let Some(mut writer) = crate::WRITER.try_lock() {
+        writer.set_status(format_args!(
+            " heap: {} KiB used / {} KiB | peak {} KiB | {} live allocs",
+            stats.allocated_bytes / 1024,
+            stats.size / 1024,
+            stats.peak_bytes / 1024,
+            stats.allocations
+        ));
+    }
+}
+
+/// Returns the active out-of-memory policy.
+pub fn oom_policy() -> OomPolicy {
+    match OOM_POLICY.load(Ordering::Relaxed) {
+        0 => OomPolicy::ReturnNull,
+        _ => OomPolicy::Panic,
+    }
+}
+
+/// Changes the out-of-memory policy.
+pub fn set_oom_policy(policy: OomPolicy) {
+    OOM_POLICY.store(policy as u8, Ordering::Relaxed);
+}
+
+/// Allocates memory for `layout` without going through the allocation error
+/// handler.
+///
+/// Under [`OomPolicy::ReturnNull`] a failed allocation returns `None`; under
+/// [`OomPolicy::Panic`] it panics. The memory must be released with
+/// `alloc::alloc::dealloc` using the same layout.
+pub fn try_alloc(layout: Layout) -> Option<NonNull<u8>> {
+    if layout.size() == 0 {
+        return None;
+    }
+    NonNull::new(unsafe { ALLOCATOR.alloc(layout) })
+}
+
+/// Prints the failed layout, the heap usage, and a backtrace.
+///
+/// The heap is only try-locked, since the failing allocation may come from
+/// code that holds it.
+fn report_failure(layout: Layout) {
+    println!(
+        "heap: allocation of {} bytes (align {}) failed",
+        layout.size(),
+        layout.align()
+    );
+    match ALLOCATOR.state.try_lock().map(|state| state.stats()) {
+        Some(stats) => println!(
+            "heap: {} bytes total, {} allocated in {} allocations, {} free, peak {}",
+            stats.size,
+            stats.allocated_bytes,
+            stats.allocations,
+            stats.free_bytes,
+            stats.peak_bytes
+        ),
+        None => println!("heap: statistics unavailable (heap locked)"),
+    }
+    crate::backtrace::print_backtrace();
+}
+
+/// Allocation error handler for infallible allocations.
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+    report_failure(layout);
+    panic!("out of memory allocating {:?}", layout);
+}
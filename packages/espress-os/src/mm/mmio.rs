@@ -0,0 +1,148 @@
+//! # Memory-Mapped I/O
+//!
+//! Device registers and other memory-mapped I/O ranges may lie above the
+//! end of RAM, outside the linear physical memory mapping, and must not be
+//! cached in any case. [`map`] maps such a range uncached into a dedicated
+//! region starting at [`MMIO_REGION_START`]. Framebuffers, which are only
+//! written and benefit from merging the writes, are mapped write-combining
+//! with [`map_write_combining`] instead.
+//!
+//! Mappings are permanent: device windows stay mapped for the lifetime of
+//! the kernel, so the region is handed out by a simple bump allocator.
+//! Ranges that are suitably aligned are mapped with 2 MiB pages, which keeps
+//! large windows such as PCIe configuration space cheap.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::structures::paging::{Mapper, Page, PageTableFlags, PhysFrame, Size2MiB, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::frame::FRAME_SIZE;
+use super::KernelFrameAllocator;
+
+/// First address of the virtual region holding MMIO mappings.
+pub const MMIO_REGION_START: u64 = 0xffff_ff00_0000_0000;
+
+/// Size of the MMIO region (one level 4 entry).
+pub const MMIO_REGION_SIZE: u64 = 1 << 39;
+
+/// Size of a large page.
+const LARGE_PAGE_SIZE: u64 = 2 * 1024 * 1024;
+
+/// Next unused address of the MMIO region.
+static NEXT: AtomicU64 = AtomicU64::new(MMIO_REGION_START);
+
+/// Errors that can occur while mapping an MMIO range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MmioError {
+    /// The range is empty or wraps around the physical address space
+    InvalidRange,
+    /// The MMIO region has no room for the range
+    OutOfSpace,
+    /// A page table could not be allocated
+    MappingFailed,
+}
+
+/// Maps `len` bytes of physical memory at `phys` uncached.
+///
+/// The range is extended to whole pages; the returned address corresponds
+/// to `phys` itself, including its offset into the first page.
+///
+/// # Arguments
+///
+/// * `phys` - Physical start address of the range
+/// * `len` - Length of the range in bytes
+///
+/// # Errors
+///
+/// Returns an [`MmioError`] if the range is invalid, does not fit into the
+/// remaining MMIO region, or cannot be mapped. The virtual space reserved
+/// for a failed mapping is not reused.
+pub fn map(phys: PhysAddr, len: u64) -> Result<VirtAddr, MmioError> {
+    map_with(
+        phys,
+        len,
+        PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH,
+    )
+}
+
+/// Maps `len` bytes of physical memory at `phys` write-combining, like
+/// [`map`] otherwise.
+///
+/// The mapping is write-through until
+/// [`pat::init`](crate::arch::pat::init) has run, and stays so on CPUs
+/// without a PAT.
+///
+/// # Errors
+///
+/// See [`map`].
+pub fn map_write_combining(phys: PhysAddr, len: u64) -> Result<VirtAddr, MmioError> {
+    map_with(phys, len, PageTableFlags::WRITE_THROUGH)
+}
+
+/// Maps a range into the MMIO region with the memory type selected by
+/// `cache_flags`.
+fn map_with(phys: PhysAddr, len: u64, cache_flags: PageTableFlags) -> Result<VirtAddr, MmioError> {
+    let end = phys
+        .as_u64()
+        .checked_add(len)
+        .filter(|_| len > 0)
+        .ok_or(MmioError::InvalidRange)?;
+    let start = phys.align_down(FRAME_SIZE).as_u64();
+    let size = (end - start).next_multiple_of(FRAME_SIZE);
+
+    // Keeping the virtual address congruent to the physical one modulo
+    // 2 MiB lets any aligned part of the range use large pages.
+    let mut next = NEXT.load(Ordering::Relaxed);
+    let reserved = loop {
+        let base = next.next_multiple_of(LARGE_PAGE_SIZE) + start % LARGE_PAGE_SIZE;
+        let end = base
+            .checked_add(size)
+            .filter(|&end| end <= MMIO_REGION_START + MMIO_REGION_SIZE)
+            .ok_or(MmioError::OutOfSpace)?;
+        match NEXT.compare_exchange_weak(next, end, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => break base,
+            Err(current) => next = current,
+        }
+    };
+
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_EXECUTE
+        | cache_flags;
+    super::with_mapper(|mapper| {
+        let mut offset = 0;
+        while offset < size {
+            let frame = PhysAddr::new(start + offset);
+            let page = VirtAddr::new(reserved + offset);
+            let result = if frame.is_aligned(LARGE_PAGE_SIZE) && size - offset >= LARGE_PAGE_SIZE {
+                unsafe {
+                    mapper.map_to(
+                        Page::<Size2MiB>::containing_address(page),
+                        PhysFrame::<Size2MiB>::containing_address(frame),
+                        flags,
+                        &mut KernelFrameAllocator,
+                    )
+                }
+                .map(|flush| flush.flush())
+                .map(|()| LARGE_PAGE_SIZE)
+                .map_err(|_| MmioError::MappingFailed)
+            } else {
+                unsafe {
+                    mapper.map_to(
+                        Page::<Size4KiB>::containing_address(page),
+                        PhysFrame::<Size4KiB>::containing_address(frame),
+                        flags,
+                        &mut KernelFrameAllocator,
+                    )
+                }
+                .map(|flush| flush.flush())
+                .map(|()| FRAME_SIZE)
+                .map_err(|_| MmioError::MappingFailed)
+            };
+            offset += result?;
+        }
+        Ok(())
+    })?;
+
+    Ok(VirtAddr::new(reserved + phys.as_u64() - start))
+}
@@ -0,0 +1,234 @@
+//! # Memory Management
+//!
+//! Owns the kernel's view of physical and virtual memory: the physical frame
+//! allocator, the active page table mapper, and the kernel virtual regions
+//! carved out for specific purposes such as the heap, guarded stacks, DMA
+//! buffers, and device memory. Allocations made before the heap exists come
+//! from a static [`early`] arena.
+//!
+//! All physical memory is reachable through the bootloader's linear mapping at
+//! [`phys_offset`], which is how page tables and free frames are accessed.
+//!
+//! ## Address Space Layout
+//!
+//! The kernel lives entirely in the canonical higher half; the lower half is
+//! reserved for userspace and left unmapped once boot is complete. Each
+//! process gets its own lower half in an [`AddressSpace`].
+//!
+//! | Start                         | Contents                                 |
+//! |-------------------------------|------------------------------------------|
+//! | `0x0000_0000_0000_0000`       | userspace, up to [`USER_SPACE_END`]      |
+//! | [`PHYS_MAP_BASE`]             | linear map of all physical memory        |
+//! | [`heap::HEAP_START`]          | kernel heap                              |
+//! | `0xffff_fd00_0000_0000`       | bootloader stack (abandoned after boot)  |
+//! | `0xffff_fd80_0000_0000`       | boot information                         |
+//! | [`dma::DMA_REGION_START`]     | uncached and write-combining DMA buffers |
+//! | [`stack::STACK_REGION_START`] | guarded kernel stacks                    |
+//! | [`mmio::MMIO_REGION_START`]   | uncached device memory mappings          |
+//! | [`KERNEL_BASE`]               | kernel image (see `linker.ld`)           |
+//!
+//! ## Lock Ordering
+//!
+//! When both are needed, the mapper lock is always taken before the frame
+//! allocator lock. [`KernelFrameAllocator`] takes the frame lock internally,
+//! so it can be passed to mapper operations while the mapper is held.
+
+pub mod address_space;
+pub mod cow;
+pub mod dma;
+pub mod early;
+pub mod frame;
+pub mod heap;
+pub mod mmio;
+pub mod paging;
+pub mod probe;
+pub mod protect;
+pub mod refcount;
+pub mod regions;
+pub mod stack;
+pub mod tlb;
+
+pub use address_space::AddressSpace;
+pub use regions::{print_memory_map, regions, Region, RegionKind};
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, OffsetPageTable, PageTable, PhysFrame, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+use frame::BuddyAllocator;
+
+/// First address past the userspace half of the address space.
+pub const USER_SPACE_END: u64 = 0x0000_8000_0000_0000;
+
+/// Virtual address requested for the linear physical memory mapping.
+///
+/// Must match `physical-memory-offset` in the bootloader configuration and
+/// `PHYS_MAP_BASE` in the UEFI loader; the `bootloader_api` configuration
+/// uses it directly.
+pub const PHYS_MAP_BASE: u64 = 0xffff_8000_0000_0000;
+
+/// Virtual address the kernel image is linked at (`KERNEL_VIRT_BASE` in
+/// `linker.ld`).
+pub const KERNEL_BASE: u64 = 0xffff_ffff_8000_0000;
+
+/// Virtual address at which the bootloader mapped all physical memory.
+static PHYS_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// End of the highest physical memory region reported by the bootloader.
+static PHYS_MEMORY_END: AtomicU64 = AtomicU64::new(0);
+
+/// Mapper for the active level 4 page table.
+static MAPPER: Mutex<Option<OffsetPageTable<'static>>> = Mutex::new(None);
+
+/// Global physical frame allocator.
+static FRAMES: Mutex<Option<BuddyAllocator>> = Mutex::new(None);
+
+/// Initializes the memory management subsystem.
+///
+/// Records the physical memory offset, captures the boot memory map (see
+/// [`regions`]), wraps the active page table in a mapper, and hands every
+/// usable region to the frame allocator.
+///
+/// # Arguments
+///
+/// * `phys_offset` - Virtual address at which all physical memory is mapped
+/// * `memory_map` - Physical memory map passed by the boot protocol
+///
+/// # Panics
+///
+/// Panics if called more than once, or if the bootloader left 5-level
+/// paging enabled on a CPU that does not report LA57 support.
+pub fn init(phys_offset: VirtAddr, memory_map: impl IntoIterator<Item = Region>) {
+    assert!(
+        paging::PagingMode::current() == paging::PagingMode::FourLevel || paging::la57_supported(),
+        "5-level paging enabled without CPU support"
+    );
+    PHYS_OFFSET.store(phys_offset.as_u64(), Ordering::Relaxed);
+
+    let mut frames = FRAMES.lock();
+    assert!(frames.is_none(), "memory management initialized twice");
+
+    regions::capture(memory_map);
+
+    let mut allocator = BuddyAllocator::new(phys_offset);
+    for region in regions() {
+        PHYS_MEMORY_END.fetch_max(region.end().as_u64(), Ordering::Relaxed);
+        if region.kind == RegionKind::Usable {
+            unsafe { allocator.add_region(region.start.as_u64(), region.end().as_u64()) };
+        }
+    }
+    *frames = Some(allocator);
+
+    let level_4_table = unsafe { paging::kernel_level_4_table(phys_offset) };
+    *MAPPER.lock() = Some(unsafe { OffsetPageTable::new(level_4_table, phys_offset) });
+}
+
+/// Unmaps everything the bootloader left in the lower half.
+///
+/// The bootloader identity-maps its own code and low memory such as the VGA
+/// buffer. Once the kernel runs on its own GDT, IDT, and stacks, none of that
+/// is needed, and clearing the lower half of the root table leaves it free
+/// for userspace. The page table frames themselves belong to bootloader
+/// memory regions and are not reused.
+///
+/// Must be called after every lower-half reference (GDT, IDT, stacks, VGA
+/// buffer) has been moved to the higher half.
+pub fn release_lower_half() {
+    with_root_table(|root, _| {
+        for entry in root.iter_mut().take(256) {
+            entry.set_unused();
+        }
+    });
+    tlb::flush_all();
+}
+
+/// Returns the virtual address of the physical memory mapping.
+pub fn phys_offset() -> VirtAddr {
+    VirtAddr::new(PHYS_OFFSET.load(Ordering::Relaxed))
+}
+
+/// Returns the end of the highest physical memory region.
+///
+/// This is also the size of the linear physical memory mapping.
+pub fn phys_memory_end() -> u64 {
+    PHYS_MEMORY_END.load(Ordering::Relaxed)
+}
+
+/// Translates a physical address into its address in the linear mapping.
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    phys_offset() + addr.as_u64()
+}
+
+/// Runs `f` with exclusive access to the kernel page table mapper.
+///
+/// # Panics
+///
+/// Panics if the memory subsystem has not been initialized.
+pub fn with_mapper<R>(f: impl FnOnce(&mut OffsetPageTable<'static>) -> R) -> R {
+    let mut mapper = MAPPER.lock();
+    f(mapper.as_mut().expect("memory management not initialized"))
+}
+
+/// Runs `f` with exclusive access to the kernel page table mapper, unless
+/// the mapper is currently locked.
+///
+/// Meant for fault handlers, which must not block on a lock the interrupted
+/// code may hold.
+///
+/// # Returns
+///
+/// `None` if the mapper is locked or not initialized yet.
+pub fn try_with_mapper<R>(f: impl FnOnce(&mut OffsetPageTable<'static>) -> R) -> Option<R> {
+    let mut mapper = MAPPER.try_lock()?;
+    Some(f(mapper.as_mut()?))
+}
+
+/// Runs `f` with exclusive access to the buddy frame allocator.
+///
+/// # Panics
+///
+/// Panics if the memory subsystem has not been initialized.
+pub fn with_frames<R>(f: impl FnOnce(&mut BuddyAllocator) -> R) -> R {
+    let mut frames = FRAMES.lock();
+    f(frames.as_mut().expect("memory management not initialized"))
+}
+
+/// Runs `f` on the root page table and its level (4 or 5).
+///
+/// Holds the mapper lock while `f` runs. In 4-level mode the root is the
+/// mapper's own level 4 table; see [`paging`] for 5-level mode.
+///
+/// # Panics
+///
+/// Panics if the memory subsystem has not been initialized.
+fn with_root_table<R>(f: impl FnOnce(&mut PageTable, u8) -> R) -> R {
+    let mode = paging::PagingMode::current();
+    with_mapper(|mapper| match mode {
+        paging::PagingMode::FourLevel => f(mapper.level_4_table(), mode.levels()),
+        paging::PagingMode::FiveLevel => {
+            f(unsafe { paging::root_table(phys_offset()) }, mode.levels())
+        }
+    })
+}
+
+/// Handle to the global frame allocator usable with `x86_64` mapper APIs.
+///
+/// Each call locks the global allocator for the duration of the operation.
+pub struct KernelFrameAllocator;
+
+unsafe impl FrameAllocator<Size4KiB> for KernelFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        FRAMES.lock().as_mut()?.allocate_frame()
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for KernelFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        if let Some(frames) = FRAMES.lock().as_mut() {
+            frames.deallocate_frame(frame);
+        }
+    }
+}
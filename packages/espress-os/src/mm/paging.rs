@@ -0,0 +1,95 @@
+//! # Paging Mode
+//!
+//! Detects whether the CPU translates addresses with four or five levels of
+//! page tables and locates the tables the rest of the memory manager works
+//! on.
+//!
+//! With 5-level paging (LA57) enabled, CR3 points to a level 5 table. Every
+//! address the kernel uses lies in its top entry, so the kernel's mapper
+//! operates on the level 4 table below that entry: within that 256 TiB slice
+//! 5-level translation is identical to 4-level translation, and the rest of
+//! the memory manager needs no changes. Whole-table operations such as the
+//! W^X check and clearing the lower half start at the real root instead,
+//! and [address spaces](super::address_space) get a level 5 root of their
+//! own.
+//!
+//! The mode is whatever the bootloader enabled; [`mm::init`](super::init)
+//! checks it against [`la57_supported`].
+
+use core::arch::x86_64::__cpuid_count;
+use x86_64::registers::control::{Cr3, Cr4, Cr4Flags};
+use x86_64::structures::paging::PageTable;
+use x86_64::VirtAddr;
+
+/// Number of page table levels in use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PagingMode {
+    /// Classic 48-bit virtual addresses, rooted in a level 4 table
+    FourLevel,
+    /// 57-bit virtual addresses (LA57), rooted in a level 5 table
+    FiveLevel,
+}
+
+impl PagingMode {
+    /// Returns the mode the CPU is currently running in.
+    pub fn current() -> PagingMode {
+        if Cr4::read().contains(Cr4Flags::L5_PAGING) {
+            PagingMode::FiveLevel
+        } else {
+            PagingMode::FourLevel
+        }
+    }
+
+    /// Number of page table levels, i.e. the level of the root table.
+    pub fn levels(self) -> u8 {
+        match self {
+            PagingMode::FourLevel => 4,
+            PagingMode::FiveLevel => 5,
+        }
+    }
+}
+
+/// Returns `true` if the CPU supports 5-level paging (CPUID.07H:ECX.LA57).
+pub fn la57_supported() -> bool {
+    let max_leaf = __cpuid_count(0, 0).eax;
+    max_leaf >= 7 && __cpuid_count(7, 0).ecx & (1 << 16) != 0
+}
+
+/// Returns the root page table referenced by CR3.
+///
+/// This is a level 4 or level 5 table depending on [`PagingMode::current`].
+///
+/// # Safety
+///
+/// All physical memory must be mapped at `phys_offset`, and the caller must
+/// not create aliasing mutable references to the table.
+pub unsafe fn root_table(phys_offset: VirtAddr) -> &'static mut PageTable {
+    let (root_frame, _) = Cr3::read();
+    let virt = phys_offset + root_frame.start_address().as_u64();
+    &mut *virt.as_mut_ptr()
+}
+
+/// Returns the level 4 table covering the kernel's half of the address space.
+///
+/// In 4-level mode this is the root table; in 5-level mode it is the table
+/// referenced by the top entry of the root table.
+///
+/// # Safety
+///
+/// Same requirements as [`root_table`].
+///
+/// # Panics
+///
+/// Panics if 5-level paging is active but the top level 5 entry is unused.
+pub unsafe fn kernel_level_4_table(phys_offset: VirtAddr) -> &'static mut PageTable {
+    let root = root_table(phys_offset);
+    match PagingMode::current() {
+        PagingMode::FourLevel => root,
+        PagingMode::FiveLevel => {
+            let entry = &root[511];
+            assert!(!entry.is_unused(), "no level 4 table for the higher half");
+            let virt = phys_offset + entry.addr().as_u64();
+            &mut *virt.as_mut_ptr()
+        }
+    }
+}
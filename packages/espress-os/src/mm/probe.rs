@@ -0,0 +1,71 @@
+//! # Fault-Tolerant Memory Probes
+//!
+//! [`probe_read`] reads a byte from an address that may not be mapped. The
+//! load instruction is registered as a known faulting site: if it raises a
+//! page fault, [`fixup`] redirects the faulting context to a recovery path
+//! instead of treating the fault as fatal, and the probe reports failure.
+
+use core::arch::global_asm;
+use core::ptr::addr_of;
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::VirtAddr;
+
+global_asm!(
+    ".pushsection .text.probe_read, \"ax\"",
+    ".global __probe_read",
+    "__probe_read:",
+    ".global __probe_read_load",
+    "__probe_read_load:",
+    "    movzx eax, byte ptr [rdi]",
+    "    mov byte ptr [rsi], al",
+    "    mov eax, 1",
+    "    ret",
+    ".global __probe_read_fixup",
+    "__probe_read_fixup:",
+    "    xor eax, eax",
+    "    ret",
+    ".popsection",
+);
+
+extern "C" {
+    /// Loads the byte at `addr` into `*out`; returns 1 on success, 0 if the
+    /// load faulted.
+    fn __probe_read(addr: u64, out: *mut u8) -> u32;
+    static __probe_read_load: u8;
+    static __probe_read_fixup: u8;
+}
+
+/// Reads the byte at `addr`, tolerating page faults.
+///
+/// # Returns
+///
+/// The byte, or `None` if reading it caused a page fault.
+pub fn probe_read(addr: VirtAddr) -> Option<u8> {
+    let mut value = 0;
+    match unsafe { __probe_read(addr.as_u64(), &mut value) } {
+        0 => None,
+        _ => Some(value),
+    }
+}
+
+/// Redirects a fault raised by a probe to its recovery path.
+///
+/// Called from the page fault handler before anything else.
+///
+/// # Returns
+///
+/// `true` if the fault came from a probe and execution can resume.
+pub fn fixup(stack_frame: &mut InterruptStackFrame) -> bool {
+    let load = VirtAddr::from_ptr(addr_of!(__probe_read_load));
+    if stack_frame.instruction_pointer != load {
+        return false;
+    }
+
+    let fixup = VirtAddr::from_ptr(addr_of!(__probe_read_fixup));
+    unsafe {
+        stack_frame
+            .as_mut()
+            .update(|frame| frame.instruction_pointer = fixup);
+    }
+    true
+}
@@ -0,0 +1,228 @@
+//! # Kernel Page Protections
+//!
+//! Enforces W^X for kernel mappings: no page may be both writable and
+//! executable. Kernel image sections get their permissions from the section
+//! symbols exported by `linker.ld`:
+//!
+//! | Section          | Permissions |
+//! |------------------|-------------|
+//! | `.text`          | R X         |
+//! | `.rodata`        | R           |
+//! | `.data`, `.bss`  | R W         |
+//!
+//! After boot, [`check_wx`] walks the whole page table and reports any
+//! mapping that still violates the policy.
+
+use core::ptr::addr_of;
+use x86_64::registers::control::{Cr0, Cr0Flags, Efer, EferFlags};
+use x86_64::structures::paging::mapper::TranslateResult;
+use x86_64::structures::paging::{Mapper, Page, PageTable, PageTableFlags, Size4KiB, Translate};
+use x86_64::VirtAddr;
+
+use crate::vga_println;
+
+extern "C" {
+    static __text_start: u8;
+    static __text_end: u8;
+    static __rodata_start: u8;
+    static __rodata_end: u8;
+    static __data_start: u8;
+    static __data_end: u8;
+    static __bss_start: u8;
+    static __bss_end: u8;
+}
+
+/// Size of the address range covered by one level 4 entry.
+const L4_ENTRY_SPAN: u64 = 1 << 39;
+
+/// Enables no-execute support and applies kernel section permissions.
+///
+/// Sets `EFER.NXE` so the `NO_EXECUTE` bit is honored and `CR0.WP` so
+/// read-only pages are read-only for the kernel too. The linear physical
+/// memory map is made non-executable at the level 4 entries covering it.
+pub fn init() {
+    unsafe {
+        Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE));
+        Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT));
+    }
+
+    let rw_nx = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    unsafe {
+        protect_section(
+            addr_of!(__text_start),
+            addr_of!(__text_end),
+            PageTableFlags::empty(),
+        );
+        protect_section(
+            addr_of!(__rodata_start),
+            addr_of!(__rodata_end),
+            PageTableFlags::NO_EXECUTE,
+        );
+        protect_section(addr_of!(__data_start), addr_of!(__data_end), rw_nx);
+        protect_section(addr_of!(__bss_start), addr_of!(__bss_end), rw_nx);
+    }
+
+    let phys_map_start = super::phys_offset().as_u64();
+    let phys_map_end = phys_map_start + super::phys_memory_end();
+    super::with_mapper(|mapper| {
+        let level_4_table = mapper.level_4_table();
+        let mut addr = phys_map_start;
+        while addr < phys_map_end {
+            let index = VirtAddr::new(addr).p4_index();
+            let entry = &mut level_4_table[index];
+            if !entry.is_unused() {
+                entry.set_flags(entry.flags() | PageTableFlags::NO_EXECUTE);
+            }
+            addr += L4_ENTRY_SPAN;
+        }
+    });
+    super::tlb::flush_all();
+}
+
+/// Rewrites the permissions of every page in `[start, end)`.
+///
+/// The writable and no-execute bits are replaced by `permissions`; all other
+/// flags set by the bootloader are kept.
+///
+/// # Safety
+///
+/// The range must be a kernel image section; making pages read-only or
+/// non-executable that the kernel still writes to or runs would fault.
+unsafe fn protect_section(start: *const u8, end: *const u8, permissions: PageTableFlags) {
+    let start = Page::<Size4KiB>::containing_address(VirtAddr::from_ptr(start));
+    let end = VirtAddr::from_ptr(end);
+    if end <= start.start_address() {
+        return;
+    }
+    let end = Page::<Size4KiB>::containing_address(end - 1u64);
+
+    super::with_mapper(|mapper| {
+        for page in Page::range_inclusive(start, end) {
+            let flags = match mapper.translate(page.start_address()) {
+                TranslateResult::Mapped { flags, .. } => flags,
+                _ => continue,
+            };
+            let flags =
+                (flags - PageTableFlags::WRITABLE - PageTableFlags::NO_EXECUTE) | permissions;
+            match mapper.update_flags(page, flags) {
+                Ok(flush) => flush.flush(),
+                Err(err) => vga_println!(
+                    "mm: cannot protect {:#x}: {:?}",
+                    page.start_address().as_u64(),
+                    err
+                ),
+            }
+        }
+    });
+}
+
+/// Walks all page tables and reports mappings that are writable and
+/// executable.
+///
+/// Permissions are evaluated as the CPU sees them: a mapping is writable only
+/// if every level allows writes, and executable only if no level sets
+/// `NO_EXECUTE`. Adjacent violating pages are reported as one range.
+///
+/// # Returns
+///
+/// The number of violating ranges found.
+pub fn check_wx() -> usize {
+    let mut report = ViolationReport::default();
+
+    super::with_root_table(|root, levels| {
+        let va_bits = 12 + 9 * levels as u32;
+        walk(
+            root,
+            levels,
+            0,
+            va_bits,
+            PageTableFlags::WRITABLE,
+            &mut report,
+        );
+    });
+    report.flush();
+
+    if report.count > 0 {
+        vga_println!("mm: {} W^X violation(s) found", report.count);
+    }
+    report.count
+}
+
+/// Recursively walks a page table of the given level.
+///
+/// `inherited` carries the effective writable/no-execute bits of the parent
+/// entries; `va_bits` is the width of a virtual address in the current
+/// paging mode.
+fn walk(
+    table: &PageTable,
+    level: u8,
+    base: u64,
+    va_bits: u32,
+    inherited: PageTableFlags,
+    report: &mut ViolationReport,
+) {
+    let shift = 12 + 9 * (level as u64 - 1);
+
+    for (index, entry) in table.iter().enumerate() {
+        if entry.is_unused() || !entry.flags().contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+
+        let flags = entry.flags();
+        let mut effective = inherited & flags & PageTableFlags::WRITABLE;
+        effective |= (inherited | flags) & PageTableFlags::NO_EXECUTE;
+
+        let addr = sign_extend(base | (index as u64) << shift, va_bits);
+        let is_leaf = level == 1 || flags.contains(PageTableFlags::HUGE_PAGE);
+
+        if is_leaf {
+            if effective.contains(PageTableFlags::WRITABLE)
+                && !effective.contains(PageTableFlags::NO_EXECUTE)
+            {
+                report.add(addr, 1 << shift);
+            }
+        } else {
+            let next = super::phys_to_virt(entry.addr());
+            let next: &PageTable = unsafe { &*next.as_ptr() };
+            walk(next, level - 1, addr, va_bits, effective, report);
+        }
+    }
+}
+
+/// Makes `addr` canonical for a `va_bits`-wide virtual address space.
+fn sign_extend(addr: u64, va_bits: u32) -> u64 {
+    let unused = 64 - va_bits;
+    (((addr << unused) as i64) >> unused) as u64
+}
+
+/// Coalesces violating pages into ranges while walking.
+#[derive(Default)]
+struct ViolationReport {
+    /// Range currently being extended, as `(start, end)`
+    current: Option<(u64, u64)>,
+    /// Number of ranges reported so far
+    count: usize,
+}
+
+impl ViolationReport {
+    fn add(&mut self, start: u64, len: u64) {
+        match &mut self.current {
+            Some((_, end)) if *end == start => *end += len,
+            _ => {
+                self.flush();
+                self.current = Some((start, start + len));
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Some((start, end)) = self.current.take() {
+            vga_println!(
+                "mm: W^X violation: {:#x}..{:#x} is writable and executable",
+                start,
+                end
+            );
+            self.count += 1;
+        }
+    }
+}
@@ -0,0 +1,146 @@
+//! # Frame Reference Counts
+//!
+//! Tracks how many mappings share a physical frame. Frames that were never
+//! shared are not tracked at all and count as having a single owner, so the
+//! table only costs memory for the parts of physical memory that actually
+//! contain shared frames.
+//!
+//! Counters live in chunks allocated from the frame allocator on first use.
+//! Each chunk covers [`CHUNK_SPAN`] bytes of physical memory; a fixed
+//! directory of chunk pointers covers up to [`MAX_TRACKED_MEMORY`].
+
+use core::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+use x86_64::structures::paging::PhysFrame;
+use x86_64::PhysAddr;
+
+use super::frame::{block_size, FRAME_SIZE};
+
+/// Buddy order of a counter chunk.
+const CHUNK_ORDER: usize = 4;
+
+/// Number of counters in one chunk.
+const COUNTERS_PER_CHUNK: u64 = block_size(CHUNK_ORDER) / core::mem::size_of::<u16>() as u64;
+
+/// Physical memory covered by one chunk of counters.
+pub const CHUNK_SPAN: u64 = COUNTERS_PER_CHUNK * FRAME_SIZE;
+
+/// Number of entries in the chunk directory.
+const DIRECTORY_SIZE: usize = 4096;
+
+/// Highest physical address (exclusive) whose frames can be shared.
+pub const MAX_TRACKED_MEMORY: u64 = CHUNK_SPAN * DIRECTORY_SIZE as u64;
+
+/// Physical address of each counter chunk (0 = not allocated yet).
+static DIRECTORY: [AtomicU64; DIRECTORY_SIZE] = [const { AtomicU64::new(0) }; DIRECTORY_SIZE];
+
+/// Errors that can occur while taking a reference to a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefCountError {
+    /// The frame lies above [`MAX_TRACKED_MEMORY`]
+    Untrackable,
+    /// No memory was left for a counter chunk
+    OutOfMemory,
+    /// The frame already has the maximum number of references
+    Overflow,
+}
+
+/// Returns the number of mappings sharing `frame`.
+///
+/// Untracked frames have exactly one owner.
+pub fn count(frame: PhysFrame) -> u32 {
+    counter(frame, false)
+        .ok()
+        .flatten()
+        .map_or(1, |counter| counter.load(Ordering::Acquire) as u32 + 1)
+}
+
+/// Adds a reference to `frame`.
+///
+/// # Returns
+///
+/// The new number of references.
+///
+/// # Errors
+///
+/// Returns a [`RefCountError`] if the frame cannot be tracked or its count
+/// would overflow.
+pub fn increment(frame: PhysFrame) -> Result<u32, RefCountError> {
+    let counter = counter(frame, true)?.ok_or(RefCountError::OutOfMemory)?;
+    let mut extra = counter.load(Ordering::Acquire);
+    loop {
+        let next = extra.checked_add(1).ok_or(RefCountError::Overflow)?;
+        match counter.compare_exchange_weak(extra, next, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => return Ok(next as u32 + 1),
+            Err(current) => extra = current,
+        }
+    }
+}
+
+/// Drops a reference to `frame`.
+///
+/// # Returns
+///
+/// The number of references left. Zero means the caller held the last one
+/// and is responsible for freeing the frame.
+pub fn decrement(frame: PhysFrame) -> u32 {
+    let Some(counter) = counter(frame, false).ok().flatten() else {
+        return 0;
+    };
+    let mut extra = counter.load(Ordering::Acquire);
+    while extra > 0 {
+        match counter.compare_exchange_weak(extra, extra - 1, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => return extra as u32,
+            Err(current) => extra = current,
+        }
+    }
+    0
+}
+
+/// Looks up the counter of `frame`, allocating its chunk if `create` is set.
+///
+/// # Returns
+///
+/// `Ok(None)` if the chunk does not exist (and was not created).
+fn counter(frame: PhysFrame, create: bool) -> Result<Option<&'static AtomicU16>, RefCountError> {
+    let addr = frame.start_address().as_u64();
+    if addr >= MAX_TRACKED_MEMORY {
+        return Err(RefCountError::Untrackable);
+    }
+
+    let entry = &DIRECTORY[(addr / CHUNK_SPAN) as usize];
+    let mut chunk = entry.load(Ordering::Acquire);
+    if chunk == 0 {
+        if !create {
+            return Ok(None);
+        }
+        chunk = allocate_chunk(entry)?;
+    }
+
+    let index = (addr % CHUNK_SPAN) / FRAME_SIZE;
+    let counters: *const AtomicU16 = super::phys_to_virt(PhysAddr::new(chunk)).as_ptr();
+    Ok(Some(unsafe { &*counters.add(index as usize) }))
+}
+
+/// Allocates a zeroed chunk and installs it in `entry`.
+///
+/// If another CPU installed a chunk first, the new one is freed again and the
+/// winner is returned.
+fn allocate_chunk(entry: &AtomicU64) -> Result<u64, RefCountError> {
+    let chunk = super::with_frames(|frames| frames.allocate(CHUNK_ORDER))
+        .ok_or(RefCountError::OutOfMemory)?;
+    unsafe {
+        core::ptr::write_bytes(
+            super::phys_to_virt(chunk).as_mut_ptr::<u8>(),
+            0,
+            block_size(CHUNK_ORDER) as usize,
+        );
+    }
+
+    match entry.compare_exchange(0, chunk.as_u64(), Ordering::AcqRel, Ordering::Acquire) {
+        Ok(_) => Ok(chunk.as_u64()),
+        Err(existing) => {
+            unsafe { super::with_frames(|frames| frames.deallocate(chunk, CHUNK_ORDER)) };
+            Ok(existing)
+        }
+    }
+}
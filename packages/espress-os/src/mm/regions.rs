@@ -0,0 +1,176 @@
+//! # Physical Memory Regions
+//!
+//! A bootloader-independent copy of the physical memory map, captured once
+//! during [`mm::init`](super::init) and available to any subsystem through
+//! [`regions`].
+
+use bootloader::bootinfo::{MemoryRegion, MemoryRegionType};
+use spin::Once;
+use x86_64::PhysAddr;
+
+use crate::println;
+
+/// Maximum number of regions kept from the boot memory map.
+const MAX_REGIONS: usize = 64;
+
+/// What a physical memory region is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    /// Free RAM handed to the frame allocator
+    Usable,
+    /// The kernel image and its boot stack
+    Kernel,
+    /// Memory used by the bootloader (page tables, boot information)
+    Bootloader,
+    /// ACPI tables that may be reclaimed once parsed
+    AcpiReclaimable,
+    /// ACPI non-volatile storage that must be preserved
+    AcpiNvs,
+    /// RAM reported as faulty
+    BadMemory,
+    /// Firmware-reserved or otherwise unavailable memory
+    Reserved,
+}
+
+impl RegionKind {
+    /// Short lowercase name used in reports.
+    pub fn name(self) -> &'static str {
+        match self {
+            RegionKind::Usable => "usable",
+            RegionKind::Kernel => "kernel",
+            RegionKind::Bootloader => "bootloader",
+            RegionKind::AcpiReclaimable => "acpi reclaimable",
+            RegionKind::AcpiNvs => "acpi nvs",
+            RegionKind::BadMemory => "bad memory",
+            RegionKind::Reserved => "reserved",
+        }
+    }
+}
+
+impl From<MemoryRegionType> for RegionKind {
+    fn from(region_type: MemoryRegionType) -> Self {
+        match region_type {
+            MemoryRegionType::Usable => RegionKind::Usable,
+            MemoryRegionType::Kernel | MemoryRegionType::KernelStack => RegionKind::Kernel,
+            MemoryRegionType::InUse
+            | MemoryRegionType::PageTable
+            | MemoryRegionType::Bootloader
+            | MemoryRegionType::BootInfo
+            | MemoryRegionType::Package => RegionKind::Bootloader,
+            MemoryRegionType::AcpiReclaimable => RegionKind::AcpiReclaimable,
+            MemoryRegionType::AcpiNvs => RegionKind::AcpiNvs,
+            MemoryRegionType::BadMemory => RegionKind::BadMemory,
+            _ => RegionKind::Reserved,
+        }
+    }
+}
+
+/// A contiguous range of physical memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    /// First address of the region
+    pub start: PhysAddr,
+    /// Length of the region in bytes
+    pub len: u64,
+    /// What the region is used for
+    pub kind: RegionKind,
+}
+
+impl Region {
+    /// One past the last address of the region.
+    pub fn end(&self) -> PhysAddr {
+        self.start + self.len
+    }
+}
+
+impl From<&MemoryRegion> for Region {
+    fn from(region: &MemoryRegion) -> Self {
+        let start = region.range.start_addr();
+        Region {
+            start: PhysAddr::new(start),
+            len: region.range.end_addr() - start,
+            kind: RegionKind::from(region.region_type),
+        }
+    }
+}
+
+/// Captured memory map: fixed storage plus the number of valid entries.
+struct RegionTable {
+    regions: [Region; MAX_REGIONS],
+    len: usize,
+}
+
+static REGIONS: Once<RegionTable> = Once::new();
+
+/// Captures the memory map passed by the boot protocol.
+///
+/// Adjacent regions of the same kind are merged, and regions beyond
+/// [`MAX_REGIONS`] are dropped.
+pub(super) fn capture(memory_map: impl IntoIterator<Item = Region>) {
+    REGIONS.call_once(|| {
+        let mut table = RegionTable {
+            regions: [Region {
+                start: PhysAddr::zero(),
+                len: 0,
+                kind: RegionKind::Reserved,
+            }; MAX_REGIONS],
+            len: 0,
+        };
+
+        for region in memory_map {
+            if region.len == 0 {
+                continue;
+            }
+
+            if let Some(last) = table.regions[..table.len].last_mut() {
+                if last.kind == region.kind && last.end() == region.start {
+                    last.len += region.len;
+                    continue;
+                }
+            }
+            if table.len < MAX_REGIONS {
+                table.regions[table.len] = region;
+                table.len += 1;
+            }
+        }
+        table
+    });
+}
+
+/// Returns the physical memory map captured at boot.
+///
+/// The slice is empty before memory management is initialized.
+pub fn regions() -> &'static [Region] {
+    REGIONS
+        .get()
+        .map_or(&[], |table| &table.regions[..table.len])
+}
+
+/// Prints the memory map as a table followed by usable/reserved totals.
+pub fn print_memory_map() {
+    println!("Physical memory map:");
+    println!("  {:<18}  {:<18}  {}", "base", "length", "type");
+
+    let mut usable = 0;
+    let mut reserved = 0;
+    for region in regions() {
+        println!(
+            "  {:#018x}  {:#018x}  {}",
+            region.start.as_u64(),
+            region.len,
+            region.kind.name()
+        );
+        match region.kind {
+            RegionKind::Usable => usable += region.len,
+            _ => reserved += region.len,
+        }
+    }
+
+    println!(
+        "  usable: {} KiB ({} MiB), reserved: {} KiB ({} MiB)",
+        usable / 1024,
+        usable / (1024 * 1024),
+        reserved / 1024,
+        reserved / (1024 * 1024)
+    );
+}
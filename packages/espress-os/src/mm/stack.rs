@@ -0,0 +1,299 @@
+//! # Guarded Kernel Stacks
+//!
+//! Kernel and interrupt stacks are allocated from a dedicated virtual region
+//! that is divided into fixed-size slots. Each slot starts with an unmapped
+//! guard page followed by the mapped stack pages, so running off the bottom of
+//! a stack faults on the guard page instead of silently overwriting whatever
+//! happens to live below it.
+//!
+//! Every slot records the name of the task that owns it, which lets the fault
+//! handlers turn a faulting address into a diagnostic such as
+//! "stack overflow in `kernel`".
+//!
+//! New stacks are filled with [`STACK_PAINT`]; the deepest word that no
+//! longer holds the pattern marks how much of the stack was ever used (see
+//! [`KernelStack::peak_usage`]).
+//!
+//! ## Layout
+//!
+//! ```text
+//! slot base                                     slot base + STACK_SLOT_SIZE
+//! | guard page | stack pages (grow down) <- top | unmapped ...              |
+//! ```
+
+use core::arch::asm;
+use spin::Mutex;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags, Size4KiB,
+};
+use x86_64::VirtAddr;
+
+use super::{tlb, KernelFrameAllocator};
+
+/// First address of the virtual region reserved for kernel stacks.
+pub const STACK_REGION_START: u64 = 0xffff_fe80_0000_0000;
+
+/// Virtual space reserved for each stack, including its guard page.
+pub const STACK_SLOT_SIZE: u64 = 1024 * 1024;
+
+/// Maximum number of stacks that can exist at the same time.
+pub const MAX_STACKS: usize = 512;
+
+/// Size of the unmapped guard region at the bottom of each slot.
+pub const GUARD_SIZE: u64 = PAGE_SIZE;
+
+/// Largest stack, in pages, that fits into a slot next to its guard page.
+pub const MAX_STACK_PAGES: usize = ((STACK_SLOT_SIZE - GUARD_SIZE) / PAGE_SIZE) as usize;
+
+/// Default kernel stack size in pages (64 KiB).
+pub const DEFAULT_STACK_PAGES: usize = 16;
+
+/// Pattern written to every word of a new stack.
+pub const STACK_PAINT: u64 = 0x5354_4143_4b50_4e54;
+
+const PAGE_SIZE: u64 = 4096;
+
+/// Bookkeeping for an allocated stack slot.
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    /// Name of the task that owns the stack
+    owner: &'static str,
+    /// Number of mapped stack pages
+    pages: usize,
+}
+
+/// Allocation state of every stack slot.
+static SLOTS: Mutex<[Option<Slot>; MAX_STACKS]> = Mutex::new([None; MAX_STACKS]);
+
+/// Errors that can occur while allocating a kernel stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackError {
+    /// The requested size is zero or does not fit into a slot
+    InvalidSize,
+    /// Every stack slot is in use
+    NoVirtualSpace,
+    /// Physical memory for the stack pages or page tables ran out
+    OutOfMemory,
+}
+
+/// A kernel stack with an unmapped guard page beneath it.
+///
+/// The stack pages are unmapped and their frames returned to the frame
+/// allocator when the stack is dropped.
+#[derive(Debug)]
+pub struct KernelStack {
+    /// Index of the slot backing this stack
+    slot: usize,
+    /// Number of mapped stack pages
+    pages: usize,
+}
+
+impl KernelStack {
+    /// Allocates and maps a new guarded stack.
+    ///
+    /// # Arguments
+    ///
+    /// * `pages` - Usable stack size in 4 KiB pages
+    /// * `owner` - Name of the owning task, reported on overflow
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`StackError`] if the size is invalid or if virtual or
+    /// physical memory is exhausted.
+    pub fn new(pages: usize, owner: &'static str) -> Result<KernelStack, StackError> {
+        if pages == 0 || pages > MAX_STACK_PAGES {
+            return Err(StackError::InvalidSize);
+        }
+
+        let slot = reserve_slot(Slot { owner, pages })?;
+        let stack = KernelStack { slot, pages: 0 };
+        stack.map_pages(pages)
+    }
+
+    /// Lowest usable address of the stack (just above the guard page).
+    pub fn bottom(&self) -> VirtAddr {
+        slot_base(self.slot) + GUARD_SIZE
+    }
+
+    /// Initial stack pointer value: one past the highest usable address.
+    pub fn top(&self) -> VirtAddr {
+        self.bottom() + self.pages as u64 * PAGE_SIZE
+    }
+
+    /// Usable size of the stack in bytes.
+    pub fn size(&self) -> u64 {
+        self.pages as u64 * PAGE_SIZE
+    }
+
+    /// Highest number of bytes the stack has ever had in use.
+    ///
+    /// Scans up from the bottom for the first word that is no longer
+    /// [`STACK_PAINT`], so a program that happens to store the pattern
+    /// itself is undercounted by a few words at most.
+    pub fn peak_usage(&self) -> u64 {
+        let bottom = self.bottom().as_u64();
+        let words = self.size() / 8;
+        let untouched = (0..words)
+            .take_while(|&index| {
+                let word = (bottom + index * 8) as *const u64;
+                unsafe { word.read_volatile() == STACK_PAINT }
+            })
+            .count() as u64;
+        (words - untouched) * 8
+    }
+
+    /// The guard page directly below the stack.
+    pub fn guard_page(&self) -> Page {
+        Page::containing_address(slot_base(self.slot))
+    }
+
+    /// Name of the task that owns this stack.
+    pub fn owner(&self) -> &'static str {
+        SLOTS.lock()[self.slot].map_or("<unknown>", |slot| slot.owner)
+    }
+
+    /// Releases ownership without unmapping, keeping the stack alive forever.
+    ///
+    /// Used for stacks that must outlive any Rust owner, such as the
+    /// interrupt stacks referenced by the TSS.
+    ///
+    /// # Returns
+    ///
+    /// The top of the stack.
+    pub fn leak(self) -> VirtAddr {
+        let top = self.top();
+        core::mem::forget(self);
+        top
+    }
+
+    /// Switches to this stack and calls `entry` on it.
+    ///
+    /// The current stack is abandoned; this is meant for moving off the
+    /// bootloader-provided stack onto a guarded one early during boot.
+    pub fn run_on(self, entry: extern "C" fn() -> !) -> ! {
+        let top = self.leak();
+        unsafe {
+            asm!(
+                "mov rsp, {top}",
+                "xor rbp, rbp",
+                "call {entry}",
+                top = in(reg) top.as_u64(),
+                entry = in(reg) entry,
+                options(noreturn)
+            );
+        }
+    }
+
+    /// Maps `pages` fresh frames at the bottom of the slot.
+    ///
+    /// On failure everything mapped so far is torn down again by `Drop`.
+    fn map_pages(mut self, pages: usize) -> Result<KernelStack, StackError> {
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        let bottom = self.bottom();
+
+        super::with_mapper(|mapper| {
+            for index in 0..pages {
+                let page = Page::<Size4KiB>::containing_address(bottom + index as u64 * PAGE_SIZE);
+                let frame = KernelFrameAllocator
+                    .allocate_frame()
+                    .ok_or(StackError::OutOfMemory)?;
+                match unsafe { mapper.map_to(page, frame, flags, &mut KernelFrameAllocator) } {
+                    Ok(flush) => {
+                        flush.flush();
+                        let words = page.start_address().as_mut_ptr::<u64>();
+                        for index in 0..(PAGE_SIZE / 8) as usize {
+                            unsafe { words.add(index).write(STACK_PAINT) };
+                        }
+                    }
+                    Err(_) => {
+                        unsafe { KernelFrameAllocator.deallocate_frame(frame) };
+                        return Err(StackError::OutOfMemory);
+                    }
+                }
+                self.pages += 1;
+            }
+            Ok(())
+        })?;
+
+        Ok(self)
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        let bottom = self.bottom();
+        super::with_mapper(|mapper| {
+            for index in 0..self.pages {
+                let page = Page::<Size4KiB>::containing_address(bottom + index as u64 * PAGE_SIZE);
+                if let Ok((frame, flush)) = mapper.unmap(page) {
+                    flush.ignore();
+                    tlb::shootdown(page.start_address());
+                    unsafe { KernelFrameAllocator.deallocate_frame(frame) };
+                }
+            }
+        });
+        SLOTS.lock()[self.slot] = None;
+    }
+}
+
+/// Description of a fault that landed in a stack guard region.
+#[derive(Debug, Clone, Copy)]
+pub struct GuardHit {
+    /// Name of the task whose stack overflowed
+    pub owner: &'static str,
+    /// Lowest usable address of the overflowed stack
+    pub bottom: VirtAddr,
+    /// Highest address (exclusive) of the overflowed stack
+    pub top: VirtAddr,
+}
+
+/// Checks whether `addr` lies in the guard region of an allocated stack.
+///
+/// Addresses in the unmapped space of a slot are attributed to the stack
+/// directly above them, since a stack that skips over its guard page with a
+/// large frame lands there.
+///
+/// This is called from fault handlers, so it never blocks: if the slot table
+/// is locked it gives up and returns `None`.
+pub fn guard_hit(addr: VirtAddr) -> Option<GuardHit> {
+    let addr = addr.as_u64();
+    if !(STACK_REGION_START..STACK_REGION_START + STACK_SLOT_SIZE * MAX_STACKS as u64)
+        .contains(&addr)
+    {
+        return None;
+    }
+
+    let index = ((addr - STACK_REGION_START) / STACK_SLOT_SIZE) as usize;
+    let offset = (addr - STACK_REGION_START) % STACK_SLOT_SIZE;
+    let slots = SLOTS.try_lock()?;
+
+    let owner_index = match slots[index] {
+        Some(_) if offset < GUARD_SIZE => index,
+        Some(slot) if offset < GUARD_SIZE + slot.pages as u64 * PAGE_SIZE => return None,
+        _ => index + 1,
+    };
+    let slot = (*slots.get(owner_index)?)?;
+    let bottom = slot_base(owner_index) + GUARD_SIZE;
+
+    Some(GuardHit {
+        owner: slot.owner,
+        bottom,
+        top: bottom + slot.pages as u64 * PAGE_SIZE,
+    })
+}
+
+/// Claims a free slot for a new stack.
+fn reserve_slot(slot: Slot) -> Result<usize, StackError> {
+    let mut slots = SLOTS.lock();
+    let index = slots
+        .iter()
+        .position(Option::is_none)
+        .ok_or(StackError::NoVirtualSpace)?;
+    slots[index] = Some(slot);
+    Ok(index)
+}
+
+/// Virtual base address of a slot (the start of its guard page).
+fn slot_base(slot: usize) -> VirtAddr {
+    VirtAddr::new(STACK_REGION_START + slot as u64 * STACK_SLOT_SIZE)
+}
@@ -0,0 +1,141 @@
+//! # TLB Management
+//!
+//! Local TLB invalidation plus the shootdown protocol that keeps other
+//! CPUs' TLBs consistent when a mapping is removed or downgraded.
+//!
+//! Creating a mapping never needs a shootdown, since x86 does not cache
+//! non-present entries. Unmapping a page or taking away permissions does: the
+//! initiating CPU flushes its own TLB, publishes the affected range, sends an
+//! inter-processor interrupt to every other online CPU, and waits until each
+//! of them has flushed and acknowledged the request in
+//! [`handle_shootdown_ipi`].
+//!
+//! Until application processors are brought up only the boot CPU is online
+//! and a shootdown is just a local flush. The interrupt controller driver
+//! registers how IPIs are delivered with [`set_ipi_sender`] and reports CPUs
+//! coming online with [`cpu_online`].
+
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use spin::{Mutex, Once};
+use x86_64::instructions::tlb;
+use x86_64::VirtAddr;
+
+const PAGE_SIZE: u64 = 4096;
+
+/// Ranges of at least this many pages are flushed with a full TLB flush.
+const FULL_FLUSH_THRESHOLD: u64 = 64;
+
+/// Marker stored in [`REQUEST_PAGES`] for "flush everything".
+const FLUSH_ALL: u64 = u64::MAX;
+
+/// Number of CPUs taking part in shootdowns.
+static ONLINE_CPUS: AtomicU32 = AtomicU32::new(1);
+
+/// Sends the shootdown IPI to every CPU except the current one.
+static IPI_SENDER: Once<fn()> = Once::new();
+
+/// Serializes shootdown initiators; only one request is in flight at a time.
+static SHOOTDOWN: Mutex<()> = Mutex::new(());
+
+/// Start address of the request in flight.
+static REQUEST_START: AtomicU64 = AtomicU64::new(0);
+
+/// Number of pages of the request in flight, or [`FLUSH_ALL`].
+static REQUEST_PAGES: AtomicU64 = AtomicU64::new(0);
+
+/// CPUs that still have to acknowledge the request in flight.
+static PENDING_ACKS: AtomicU32 = AtomicU32::new(0);
+
+/// Invalidates the TLB entry for `addr` on the current CPU.
+pub fn flush(addr: VirtAddr) {
+    tlb::flush(addr);
+}
+
+/// Invalidates all non-global TLB entries on the current CPU.
+pub fn flush_all() {
+    tlb::flush_all();
+}
+
+/// Invalidates `pages` pages starting at `start` on the current CPU.
+///
+/// Large ranges are handled with a full flush, which is cheaper than
+/// invalidating page by page.
+pub fn flush_range(start: VirtAddr, pages: u64) {
+    if pages >= FULL_FLUSH_THRESHOLD {
+        flush_all();
+        return;
+    }
+    for index in 0..pages {
+        flush(start + index * PAGE_SIZE);
+    }
+}
+
+/// Invalidates the page containing `addr` on every online CPU.
+pub fn shootdown(addr: VirtAddr) {
+    shootdown_range(addr, 1);
+}
+
+/// Invalidates `pages` pages starting at `start` on every online CPU.
+///
+/// Returns once all CPUs have acknowledged the flush.
+pub fn shootdown_range(start: VirtAddr, pages: u64) {
+    flush_range(start, pages);
+    broadcast(start.as_u64(), pages);
+}
+
+/// Invalidates all non-global TLB entries on every online CPU.
+pub fn shootdown_all() {
+    flush_all();
+    broadcast(0, FLUSH_ALL);
+}
+
+/// Registers the function used to send the shootdown IPI to all other CPUs.
+///
+/// Only the first registration takes effect.
+pub fn set_ipi_sender(send: fn()) {
+    IPI_SENDER.call_once(|| send);
+}
+
+/// Records that another CPU has come online and takes part in shootdowns.
+pub fn cpu_online() {
+    ONLINE_CPUS.fetch_add(1, Ordering::AcqRel);
+}
+
+/// Returns the number of CPUs taking part in shootdowns.
+pub fn online_cpus() -> u32 {
+    ONLINE_CPUS.load(Ordering::Acquire)
+}
+
+/// Performs the flush of the request in flight and acknowledges it.
+///
+/// Must be called by the shootdown IPI handler on each receiving CPU.
+pub fn handle_shootdown_ipi() {
+    let pages = REQUEST_PAGES.load(Ordering::Acquire);
+    if pages == FLUSH_ALL {
+        flush_all();
+    } else {
+        flush_range(VirtAddr::new(REQUEST_START.load(Ordering::Acquire)), pages);
+    }
+    PENDING_ACKS.fetch_sub(1, Ordering::AcqRel);
+}
+
+/// Asks all other CPUs to flush `pages` pages at `start` and waits for them.
+fn broadcast(start: u64, pages: u64) {
+    let others = online_cpus() - 1;
+    if others == 0 {
+        return;
+    }
+    let Some(send) = IPI_SENDER.get() else {
+        return;
+    };
+
+    let _guard = SHOOTDOWN.lock();
+    REQUEST_START.store(start, Ordering::Release);
+    REQUEST_PAGES.store(pages, Ordering::Release);
+    PENDING_ACKS.store(others, Ordering::Release);
+    send();
+
+    while PENDING_ACKS.load(Ordering::Acquire) != 0 {
+        core::hint::spin_loop();
+    }
+}
@@ -0,0 +1,405 @@
+//! # PS/2 Mouse
+//!
+//! Driver for a mouse on the auxiliary port of the PS/2 controller, which
+//! also hosts the keyboard. [`init`] enables the port and its interrupt
+//! (IRQ 12), probes for an IntelliMouse wheel, and turns on data reporting.
+//!
+//! The mouse sends a packet of three bytes per change, four with a wheel:
+//! button states with the sign and overflow bits of the movement, then the
+//! low bytes of the X and Y movement, then the wheel movement. The interrupt
+//! handler assembles packets with [`add_byte`] and queues them as
+//! [`MouseEvent`]s, which threads take with [`next_event`] or
+//! [`try_next_event`]. A full queue drops its oldest event.
+//!
+//! The handler also moves a pointer over the 80x25 text screen, which the
+//! VGA writer draws as an inverted cell once [`show_pointer`] enables it.
+//! Drawing happens on the workqueue, since the writer's lock may be held
+//! by the interrupted thread.
+
+use conquer_once::spin::OnceCell;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use crossbeam_queue::ArrayQueue;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+use crate::interrupts::PICS;
+use crate::scheduler::WaitQueue;
+use crate::workqueue::{self, Work};
+use crate::{println, vga, BUFFER_HEIGHT, BUFFER_WIDTH};
+
+/// Data port of the PS/2 controller.
+const DATA_PORT: u16 = 0x60;
+/// Status (read) and command (write) port of the PS/2 controller.
+const COMMAND_PORT: u16 = 0x64;
+
+/// Status bit: a byte is waiting in the output buffer.
+const STATUS_OUTPUT_FULL: u8 = 0x01;
+/// Status bit: the controller has not yet taken the last input byte.
+const STATUS_INPUT_FULL: u8 = 0x02;
+/// Status bit: the waiting byte came from the auxiliary port.
+const STATUS_AUX_DATA: u8 = 0x20;
+
+/// Controller command: read the configuration byte.
+const CMD_READ_CONFIG: u8 = 0x20;
+/// Controller command: write the configuration byte.
+const CMD_WRITE_CONFIG: u8 = 0x60;
+/// Controller command: enable the auxiliary port.
+const CMD_ENABLE_AUX: u8 = 0xa8;
+/// Controller command: send the next data byte to the auxiliary port.
+const CMD_WRITE_AUX: u8 = 0xd4;
+
+/// Configuration bit: interrupt on auxiliary port data.
+const CONFIG_AUX_INTERRUPT: u8 = 0x02;
+/// Configuration bit: the auxiliary port clock is disabled.
+const CONFIG_AUX_CLOCK_DISABLED: u8 = 0x20;
+
+/// Mouse command: restore default settings.
+const MOUSE_SET_DEFAULTS: u8 = 0xf6;
+/// Mouse command: start sending packets.
+const MOUSE_ENABLE_REPORTING: u8 = 0xf4;
+/// Mouse command: set the sample rate to the next byte.
+const MOUSE_SET_SAMPLE_RATE: u8 = 0xf3;
+/// Mouse command: report the device ID.
+const MOUSE_GET_ID: u8 = 0xf2;
+/// Mouse reply: command acknowledged.
+const MOUSE_ACK: u8 = 0xfa;
+/// Device ID of an IntelliMouse with a wheel.
+const ID_INTELLIMOUSE: u8 = 3;
+/// Sample rates whose sequence unlocks the IntelliMouse packet format.
+const INTELLIMOUSE_KNOCK: [u8; 3] = [200, 100, 80];
+
+/// Status polls before a controller or mouse reply is given up on.
+const TIMEOUT_POLLS: usize = 100_000;
+
+/// Flag in the first packet byte: left button.
+const PACKET_LEFT: u8 = 0x01;
+/// Flag in the first packet byte: right button.
+const PACKET_RIGHT: u8 = 0x02;
+/// Flag in the first packet byte: middle button.
+const PACKET_MIDDLE: u8 = 0x04;
+/// Flag in the first packet byte: always set, used to find packet starts.
+const PACKET_SYNC: u8 = 0x08;
+/// Flag in the first packet byte: the X movement is negative.
+const PACKET_X_SIGN: u8 = 0x10;
+/// Flag in the first packet byte: the Y movement is negative.
+const PACKET_Y_SIGN: u8 = 0x20;
+/// Flags in the first packet byte: a movement overflowed.
+const PACKET_OVERFLOW: u8 = 0xc0;
+
+/// Number of events buffered before the oldest are dropped.
+const EVENT_QUEUE_CAPACITY: usize = 128;
+
+/// Mouse movement units per text column of pointer movement.
+const UNITS_PER_COLUMN: u32 = 8;
+/// Mouse movement units per text row of pointer movement.
+const UNITS_PER_ROW: u32 = 16;
+
+/// Decoded events, oldest first.
+static EVENTS: OnceCell<ArrayQueue<MouseEvent>> = OnceCell::uninit();
+
+/// Where threads wait for events.
+static EVENT_READY: WaitQueue = WaitQueue::new();
+
+/// Bytes of the packet being received; only touched by the interrupt
+/// handler and by [`init`] with interrupts disabled.
+static PACKET: Mutex<Packet> = Mutex::new(Packet {
+    bytes: [0; 4],
+    len: 0,
+});
+
+/// The mouse sends four-byte packets with wheel movement.
+static HAS_WHEEL: AtomicBool = AtomicBool::new(false);
+
+/// The pointer is drawn on the screen.
+static POINTER_VISIBLE: AtomicBool = AtomicBool::new(false);
+/// Pointer position in movement units from the left edge.
+static POINTER_X: AtomicU32 = AtomicU32::new(0);
+/// Pointer position in movement units from the top edge.
+static POINTER_Y: AtomicU32 = AtomicU32::new(0);
+
+/// Redraws the pointer after it moved.
+static REDRAW: Work = Work::new(redraw_pointer);
+
+/// Buttons held down.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MouseButtons {
+    /// Left button
+    pub left: bool,
+    /// Right button
+    pub right: bool,
+    /// Middle button, or pressed wheel
+    pub middle: bool,
+}
+
+/// One packet from the mouse.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MouseEvent {
+    /// Movement to the right
+    pub dx: i16,
+    /// Movement down, in screen orientation
+    pub dy: i16,
+    /// Wheel movement, positive towards the user
+    pub wheel: i8,
+    /// Buttons held down after the movement
+    pub buttons: MouseButtons,
+}
+
+/// Errors that can occur while setting up the mouse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseError {
+    /// The controller or the mouse did not respond in time
+    Timeout,
+    /// The mouse answered a command with something other than an
+    /// acknowledgement
+    NotAcknowledged(u8),
+}
+
+/// A packet being assembled.
+struct Packet {
+    /// Bytes received so far
+    bytes: [u8; 4],
+    /// Number of bytes received
+    len: usize,
+}
+
+/// Sets up the mouse and reports what was found.
+///
+/// Must be called after the IDT and PICs are set up and the workqueue is
+/// running. Without a mouse the kernel runs on; the failure is printed.
+pub fn init() {
+    EVENTS
+        .try_init_once(|| ArrayQueue::new(EVENT_QUEUE_CAPACITY))
+        .expect("mouse initialized twice");
+    POINTER_X.store(
+        BUFFER_WIDTH as u32 * UNITS_PER_COLUMN / 2,
+        Ordering::Relaxed,
+    );
+    POINTER_Y.store(BUFFER_HEIGHT as u32 * UNITS_PER_ROW / 2, Ordering::Relaxed);
+
+    match interrupts::without_interrupts(enable) {
+        Ok(wheel) => println!(
+            "mouse: PS/2 mouse{}",
+            if wheel { " with wheel" } else { "" }
+        ),
+        Err(err) => println!("mouse: not available ({:?})", err),
+    }
+}
+
+/// Returns `true` if the mouse reports wheel movement.
+pub fn has_wheel() -> bool {
+    HAS_WHEEL.load(Ordering::Relaxed)
+}
+
+/// Takes the oldest event, if any.
+pub fn try_next_event() -> Option<MouseEvent> {
+    EVENTS.try_get().ok()?.pop()
+}
+
+/// Takes the oldest event, blocking until there is one.
+///
+/// # Panics
+///
+/// Panics if called before [`init`] or outside a scheduler thread.
+pub fn next_event() -> MouseEvent {
+    let events = EVENTS.try_get().expect("mouse not initialized");
+    let mut event = None;
+    EVENT_READY.wait_until(|| {
+        event = events.pop();
+        event.is_some()
+    });
+    event.expect("woken without an event")
+}
+
+/// Shows or hides the mouse pointer on the text screen.
+pub fn show_pointer(visible: bool) {
+    POINTER_VISIBLE.store(visible, Ordering::Relaxed);
+    redraw_pointer();
+}
+
+/// Text cell under the pointer as row and column.
+pub fn pointer_position() -> (usize, usize) {
+    let x = POINTER_X.load(Ordering::Relaxed) / UNITS_PER_COLUMN;
+    let y = POINTER_Y.load(Ordering::Relaxed) / UNITS_PER_ROW;
+    (y as usize, x as usize)
+}
+
+/// Takes a byte the mouse sent and queues an event once a packet is
+/// complete.
+///
+/// Called from the mouse interrupt handler, so it must not block or
+/// allocate. A byte that cannot start a packet is dropped, which brings a
+/// receiver that lost a byte back in step.
+pub(crate) fn add_byte(byte: u8) {
+    let mut packet = PACKET.lock();
+    if packet.len == 0 && byte & PACKET_SYNC == 0 {
+        return;
+    }
+    let len = packet.len;
+    packet.bytes[len] = byte;
+    packet.len += 1;
+    let size = if has_wheel() { 4 } else { 3 };
+    if packet.len < size {
+        return;
+    }
+    packet.len = 0;
+    let event = decode(&packet.bytes[..size]);
+    drop(packet);
+
+    move_pointer(event.dx, event.dy);
+    if let Ok(events) = EVENTS.try_get() {
+        events.force_push(event);
+        EVENT_READY.notify_one();
+    }
+}
+
+/// Decodes a complete packet.
+fn decode(bytes: &[u8]) -> MouseEvent {
+    let flags = bytes[0];
+    let (mut dx, mut dy) = (i16::from(bytes[1]), i16::from(bytes[2]));
+    if flags & PACKET_X_SIGN != 0 {
+        dx -= 0x100;
+    }
+    if flags & PACKET_Y_SIGN != 0 {
+        dy -= 0x100;
+    }
+    if flags & PACKET_OVERFLOW != 0 {
+        // The counts are meaningless once they overflowed.
+        (dx, dy) = (0, 0);
+    }
+    // The low nibble of the fourth byte is the signed wheel movement.
+    let wheel = bytes.get(3).map_or(0, |&byte| ((byte << 4) as i8) >> 4);
+    MouseEvent {
+        dx,
+        dy: -dy,
+        wheel,
+        buttons: MouseButtons {
+            left: flags & PACKET_LEFT != 0,
+            right: flags & PACKET_RIGHT != 0,
+            middle: flags & PACKET_MIDDLE != 0,
+        },
+    }
+}
+
+/// Moves the pointer, keeping it on the screen, and schedules a redraw.
+fn move_pointer(dx: i16, dy: i16) {
+    let max_x = BUFFER_WIDTH as u32 * UNITS_PER_COLUMN - 1;
+    let max_y = BUFFER_HEIGHT as u32 * UNITS_PER_ROW - 1;
+    let x = POINTER_X
+        .load(Ordering::Relaxed)
+        .saturating_add_signed(dx.into());
+    let y = POINTER_Y
+        .load(Ordering::Relaxed)
+        .saturating_add_signed(dy.into());
+    POINTER_X.store(x.min(max_x), Ordering::Relaxed);
+    POINTER_Y.store(y.min(max_y), Ordering::Relaxed);
+    if POINTER_VISIBLE.load(Ordering::Relaxed) {
+        workqueue::schedule(&REDRAW);
+    }
+}
+
+/// Draws the pointer at its current position, or removes it if hidden.
+fn redraw_pointer() {
+    let position = POINTER_VISIBLE
+        .load(Ordering::Relaxed)
+        .then(pointer_position);
+    vga::WRITER.lock().set_pointer(position);
+}
+
+/// Enables the auxiliary port, its interrupt, and the mouse.
+///
+/// # Returns
+///
+/// `true` if the mouse sends wheel movement.
+fn enable() -> Result<bool, MouseError> {
+    let mut data = Port::<u8>::new(DATA_PORT);
+    // Drop stale bytes.
+    while status() & STATUS_OUTPUT_FULL != 0 {
+        unsafe { data.read() };
+    }
+    *PACKET.lock() = Packet {
+        bytes: [0; 4],
+        len: 0,
+    };
+
+    controller_command(CMD_ENABLE_AUX)?;
+    controller_command(CMD_READ_CONFIG)?;
+    let config = read_data(false)?;
+    controller_command(CMD_WRITE_CONFIG)?;
+    write_data(config & !CONFIG_AUX_CLOCK_DISABLED | CONFIG_AUX_INTERRUPT)?;
+
+    mouse_command(MOUSE_SET_DEFAULTS)?;
+    for rate in INTELLIMOUSE_KNOCK {
+        mouse_command(MOUSE_SET_SAMPLE_RATE)?;
+        mouse_command(rate)?;
+    }
+    mouse_command(MOUSE_GET_ID)?;
+    let wheel = read_data(true)? == ID_INTELLIMOUSE;
+    HAS_WHEEL.store(wheel, Ordering::Relaxed);
+    mouse_command(MOUSE_ENABLE_REPORTING)?;
+
+    // IRQ 12 arrives through the secondary PIC, cascaded on IRQ 2.
+    unsafe {
+        let mut pics = PICS.lock();
+        let [primary, secondary] = pics.read_masks();
+        pics.write_masks(primary & !(1 << 2), secondary & !(1 << 4));
+    }
+    Ok(wheel)
+}
+
+/// Reads the controller status.
+fn status() -> u8 {
+    unsafe { Port::<u8>::new(COMMAND_PORT).read() }
+}
+
+/// Waits until the controller accepts input.
+fn wait_input() -> Result<(), MouseError> {
+    for _ in 0..TIMEOUT_POLLS {
+        if status() & STATUS_INPUT_FULL == 0 {
+            return Ok(());
+        }
+        core::hint::spin_loop();
+    }
+    Err(MouseError::Timeout)
+}
+
+/// Sends a command to the controller.
+fn controller_command(command: u8) -> Result<(), MouseError> {
+    wait_input()?;
+    unsafe { Port::new(COMMAND_PORT).write(command) };
+    Ok(())
+}
+
+/// Writes a byte to the data port.
+fn write_data(byte: u8) -> Result<(), MouseError> {
+    wait_input()?;
+    unsafe { Port::new(DATA_PORT).write(byte) };
+    Ok(())
+}
+
+/// Reads a byte from the data port, from the auxiliary port if `aux`;
+/// keyboard bytes arriving meanwhile are dropped.
+fn read_data(aux: bool) -> Result<u8, MouseError> {
+    let mut data = Port::<u8>::new(DATA_PORT);
+    for _ in 0..TIMEOUT_POLLS {
+        let status = status();
+        if status & STATUS_OUTPUT_FULL != 0 {
+            let byte = unsafe { data.read() };
+            if !aux || status & STATUS_AUX_DATA != 0 {
+                return Ok(byte);
+            }
+        }
+        core::hint::spin_loop();
+    }
+    Err(MouseError::Timeout)
+}
+
+/// Sends a byte to the mouse and waits for its acknowledgement.
+fn mouse_command(byte: u8) -> Result<(), MouseError> {
+    controller_command(CMD_WRITE_AUX)?;
+    write_data(byte)?;
+    match read_data(true)? {
+        MOUSE_ACK => Ok(()),
+        other => Err(MouseError::NotAcknowledged(other)),
+    }
+}
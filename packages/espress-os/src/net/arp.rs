@@ -0,0 +1,368 @@
+//! # Address Resolution Protocol
+//!
+//! Finds the hardware address behind an IPv4 address on the local link
+//! (RFC 826). Answers are kept in a cache per interface for
+//! [`CACHE_TIMEOUT`].
+//!
+//! Packets for an address that is not resolved yet are held back by
+//! [`transmit`] while a request goes out; they are sent as soon as the
+//! reply arrives. Unanswered requests are repeated every
+//! [`RETRY_INTERVAL`], and after [`MAX_ATTEMPTS`] the held packets are
+//! dropped.
+//!
+//! Assigning an address to an interface announces it with a gratuitous
+//! ARP request, see [`announce`], so neighbours update stale entries.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+use core::time::Duration;
+use spin::Mutex;
+
+use super::ethernet::{self, ETHERTYPE_ARP, ETHERTYPE_IPV4};
+use super::{Interface, MacAddress, NetError};
+use crate::println;
+use crate::timer::{self, Timer};
+
+/// How long a resolved address is trusted.
+pub const CACHE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Time between requests for an unresolved address.
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Requests sent before an address is given up.
+pub const MAX_ATTEMPTS: u32 = 3;
+
+/// Packets held back per unresolved address; older ones are dropped.
+const MAX_HELD: usize = 8;
+
+/// Hardware type of Ethernet.
+const HTYPE_ETHERNET: u16 = 1;
+
+/// Operation: request.
+const OP_REQUEST: u16 = 1;
+/// Operation: reply.
+const OP_REPLY: u16 = 2;
+
+/// Size of an ARP packet for IPv4 over Ethernet.
+const PACKET_LEN: usize = 28;
+
+/// Cache entries by interface index and address.
+static CACHE: Mutex<BTreeMap<(usize, Ipv4Addr), Entry>> = Mutex::new(BTreeMap::new());
+
+/// State of an address in the cache.
+enum Entry {
+    /// The address is known
+    Resolved {
+        /// Hardware address
+        mac: MacAddress,
+        /// Tick after which the entry is stale
+        expires: u64,
+    },
+    /// A request is outstanding
+    Pending {
+        /// Interface the request went out on
+        interface: Weak<Interface>,
+        /// Held packets with their EtherType
+        held: Vec<(u16, Vec<u8>)>,
+        /// Requests sent so far
+        attempts: u32,
+        /// Tick of the last request
+        requested: u64,
+    },
+}
+
+/// An ARP packet for IPv4 over Ethernet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Packet {
+    /// [`OP_REQUEST`] or [`OP_REPLY`]
+    op: u16,
+    /// Sender hardware address
+    sha: MacAddress,
+    /// Sender protocol address
+    spa: Ipv4Addr,
+    /// Target hardware address
+    tha: MacAddress,
+    /// Target protocol address
+    tpa: Ipv4Addr,
+}
+
+impl Packet {
+    /// Parses an ARP packet, rejecting anything but IPv4 over Ethernet.
+    fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < PACKET_LEN {
+            return None;
+        }
+        let be16 = |at: usize| u16::from_be_bytes([data[at], data[at + 1]]);
+        if be16(0) != HTYPE_ETHERNET || be16(2) != ETHERTYPE_IPV4 || data[4] != 6 || data[5] != 4 {
+            return None;
+        }
+        let mac = |at: usize| {
+            let mut bytes = [0; 6];
+            bytes.copy_from_slice(&data[at..at + 6]);
+            MacAddress(bytes)
+        };
+        let ip = |at: usize| Ipv4Addr::new(data[at], data[at + 1], data[at + 2], data[at + 3]);
+        Some(Packet {
+            op: be16(6),
+            sha: mac(8),
+            spa: ip(14),
+            tha: mac(18),
+            tpa: ip(24),
+        })
+    }
+
+    /// Serializes the packet.
+    fn to_bytes(self) -> [u8; PACKET_LEN] {
+        let mut bytes = [0; PACKET_LEN];
+        bytes[0..2].copy_from_slice(&HTYPE_ETHERNET.to_be_bytes());
+        bytes[2..4].copy_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        bytes[4] = 6;
+        bytes[5] = 4;
+        bytes[6..8].copy_from_slice(&self.op.to_be_bytes());
+        bytes[8..14].copy_from_slice(&self.sha.0);
+        bytes[14..18].copy_from_slice(&self.spa.octets());
+        bytes[18..24].copy_from_slice(&self.tha.0);
+        bytes[24..28].copy_from_slice(&self.tpa.octets());
+        bytes
+    }
+}
+
+/// Returns the cached hardware address of `address` on `interface`.
+pub fn lookup(interface: &Interface, address: Ipv4Addr) -> Option<MacAddress> {
+    match CACHE.lock().get(&(interface.index(), address)) {
+        Some(Entry::Resolved { mac, expires }) if *expires > timer::ticks() => Some(*mac),
+        _ => None,
+    }
+}
+
+/// Sends `packet` of protocol `ethertype` to the neighbour `next_hop` on
+/// `interface`, resolving its hardware address first if needed.
+///
+/// The limited broadcast address and anything on the loopback interface
+/// need no resolution.
+///
+/// # Errors
+///
+/// Returns the errors of [`ethernet::send`] if the packet is sent at once.
+/// A held packet that is dropped later is not reported.
+pub fn transmit(
+    interface: &Arc<Interface>,
+    next_hop: Ipv4Addr,
+    ethertype: u16,
+    packet: Vec<u8>,
+) -> Result<(), NetError> {
+    if interface.is_loopback() {
+        return ethernet::send(interface, MacAddress::ZERO, ethertype, &packet);
+    }
+    if next_hop.is_broadcast() {
+        return ethernet::send(interface, MacAddress::BROADCAST, ethertype, &packet);
+    }
+    if let Some(mac) = lookup(interface, next_hop) {
+        return ethernet::send(interface, mac, ethertype, &packet);
+    }
+
+    let now = timer::ticks();
+    let mut cache = CACHE.lock();
+    let entry = cache
+        .entry((interface.index(), next_hop))
+        .or_insert(Entry::Pending {
+            interface: Arc::downgrade(interface),
+            held: Vec::new(),
+            attempts: 0,
+            requested: 0,
+        });
+    let send_request = match entry {
+        Entry::Pending {
+            held,
+            attempts,
+            requested,
+            ..
+        } => {
+            if held.len() == MAX_HELD {
+                held.remove(0);
+            }
+            held.push((ethertype, packet));
+            // A fresh entry asks right away, later packets leave repeating
+            // the request to the aging timer.
+            let first = *attempts == 0;
+            if first {
+                *attempts = 1;
+                *requested = now;
+            }
+            first
+        }
+        stale @ Entry::Resolved { .. } => {
+            *stale = Entry::Pending {
+                interface: Arc::downgrade(interface),
+                held: alloc::vec![(ethertype, packet)],
+                attempts: 1,
+                requested: now,
+            };
+            true
+        }
+    };
+    drop(cache);
+    if send_request {
+        request(interface, next_hop)?;
+    }
+    Ok(())
+}
+
+/// Announces the address of `interface` with a gratuitous ARP request.
+///
+/// # Errors
+///
+/// Returns the errors of [`ethernet::send`].
+pub fn announce(interface: &Interface) -> Result<(), NetError> {
+    let Some(address) = interface.ipv4_address() else {
+        return Ok(());
+    };
+    let packet = Packet {
+        op: OP_REQUEST,
+        sha: interface.mac_address(),
+        spa: address,
+        tha: MacAddress::ZERO,
+        tpa: address,
+    };
+    ethernet::send(
+        interface,
+        MacAddress::BROADCAST,
+        ETHERTYPE_ARP,
+        &packet.to_bytes(),
+    )
+}
+
+/// Drops every cache entry of `interface`, e.g. after its address changed.
+pub fn flush(interface: &Interface) {
+    CACHE
+        .lock()
+        .retain(|&(index, _), _| index != interface.index());
+}
+
+/// Returns the resolved entries as interface name, address, and hardware
+/// address.
+pub fn entries() -> Vec<(String, Ipv4Addr, MacAddress)> {
+    let interfaces = super::interfaces();
+    let now = timer::ticks();
+    CACHE
+        .lock()
+        .iter()
+        .filter_map(|(&(index, address), entry)| match entry {
+            Entry::Resolved { mac, expires } if *expires > now => {
+                let name = interfaces.iter().find(|i| i.index() == index)?.name();
+                Some((String::from(name), address, *mac))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Prints the resolved entries.
+pub fn print_cache() {
+    for (name, address, mac) in entries() {
+        println!("  {:<15} {}  {}", address, mac, name);
+    }
+}
+
+/// Starts the timer that repeats requests and ages out entries.
+pub(super) fn init() {
+    Timer::periodic(RETRY_INTERVAL, age);
+}
+
+/// Handles a received ARP packet.
+///
+/// # Returns
+///
+/// `false` if the packet is malformed.
+pub(super) fn receive(interface: &Arc<Interface>, data: &[u8]) -> bool {
+    let Some(packet) = Packet::parse(data) else {
+        return false;
+    };
+    let ours = interface.ipv4_address();
+    let for_us = ours == Some(packet.tpa);
+
+    // Merge the sender into the cache if it is known or asks for us.
+    let known = CACHE.lock().contains_key(&(interface.index(), packet.spa));
+    if (known || for_us) && !packet.spa.is_unspecified() {
+        resolved(interface, packet.spa, packet.sha);
+    }
+
+    if for_us && packet.op == OP_REQUEST {
+        let reply = Packet {
+            op: OP_REPLY,
+            sha: interface.mac_address(),
+            spa: packet.tpa,
+            tha: packet.sha,
+            tpa: packet.spa,
+        };
+        let _ = ethernet::send(interface, packet.sha, ETHERTYPE_ARP, &reply.to_bytes());
+    }
+    true
+}
+
+/// Records that `address` is at `mac` and sends the packets held for it.
+fn resolved(interface: &Interface, address: Ipv4Addr, mac: MacAddress) {
+    let entry = Entry::Resolved {
+        mac,
+        expires: timer::ticks() + timer::duration_to_ticks(CACHE_TIMEOUT),
+    };
+    let previous = CACHE.lock().insert((interface.index(), address), entry);
+    if let Some(Entry::Pending { held, .. }) = previous {
+        for (ethertype, packet) in held {
+            let _ = ethernet::send(interface, mac, ethertype, &packet);
+        }
+    }
+}
+
+/// Broadcasts a request for `address`.
+fn request(interface: &Interface, address: Ipv4Addr) -> Result<(), NetError> {
+    let packet = Packet {
+        op: OP_REQUEST,
+        sha: interface.mac_address(),
+        spa: interface.ipv4_address().unwrap_or(Ipv4Addr::UNSPECIFIED),
+        tha: MacAddress::ZERO,
+        tpa: address,
+    };
+    ethernet::send(
+        interface,
+        MacAddress::BROADCAST,
+        ETHERTYPE_ARP,
+        &packet.to_bytes(),
+    )
+}
+
+/// Repeats outstanding requests, gives up on unanswered ones, and drops
+/// stale entries. Runs on the timer thread every [`RETRY_INTERVAL`].
+fn age() {
+    let now = timer::ticks();
+    let retry = timer::duration_to_ticks(RETRY_INTERVAL);
+    let mut again = Vec::new();
+    CACHE.lock().retain(|&(_, address), entry| match entry {
+        Entry::Resolved { expires, .. } => *expires > now,
+        Entry::Pending {
+            interface,
+            attempts,
+            requested,
+            ..
+        } => {
+            if now - *requested < retry {
+                return true;
+            }
+            if *attempts >= MAX_ATTEMPTS {
+                return false;
+            }
+            *attempts += 1;
+            *requested = now;
+            if let Some(interface) = interface.upgrade() {
+                again.push((interface, address));
+            }
+            true
+        }
+    });
+    for (interface, address) in again {
+        let _ = request(&interface, address);
+    }
+}
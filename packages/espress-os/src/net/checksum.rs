@@ -0,0 +1,92 @@
+//! # Internet Checksum
+//!
+//! The ones' complement sum of 16-bit words (RFC 1071) that protects the
+//! IPv4 header, ICMP messages, and, together with a pseudo-header of the
+//! addresses, UDP and TCP segments and ICMPv6 messages.
+
+use core::net::IpAddr;
+
+/// A running Internet checksum.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Checksum {
+    /// Sum of the words so far, carries not yet folded
+    sum: u64,
+    /// An odd byte is waiting for its partner
+    odd: Option<u8>,
+}
+
+impl Checksum {
+    /// Starts a new checksum.
+    pub const fn new() -> Self {
+        Checksum { sum: 0, odd: None }
+    }
+
+    /// Adds `data` as big-endian words, continuing an odd byte left by the
+    /// previous call.
+    pub fn add(&mut self, data: &[u8]) -> &mut Self {
+        let mut data = data;
+        if let Some(high) = self.odd.take() {
+            match data.split_first() {
+                Some((&low, rest)) => {
+                    self.sum += u64::from(u16::from_be_bytes([high, low]));
+                    data = rest;
+                }
+                None => {
+                    self.odd = Some(high);
+                    return self;
+                }
+            }
+        }
+        let (words, rest) = data.as_chunks::<2>();
+        for &word in words {
+            self.sum += u64::from(u16::from_be_bytes(word));
+        }
+        if let [last] = rest {
+            self.odd = Some(*last);
+        }
+        self
+    }
+
+    /// Adds one 16-bit word.
+    pub fn add_u16(&mut self, value: u16) -> &mut Self {
+        self.add(&value.to_be_bytes())
+    }
+
+    /// Returns the checksum to put into a header, padding an odd byte with
+    /// zero.
+    pub fn finish(&self) -> u16 {
+        let mut sum = self.sum;
+        if let Some(high) = self.odd {
+            sum += u64::from(high) << 8;
+        }
+        while sum > 0xffff {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+        !(sum as u16)
+    }
+}
+
+/// Computes the checksum of `data`.
+///
+/// Over data that includes its own correct checksum the result is zero.
+pub fn checksum(data: &[u8]) -> u16 {
+    Checksum::new().add(data).finish()
+}
+
+/// Starts the checksum of a UDP, TCP, or ICMPv6 message of `len` bytes
+/// with the pseudo-header of the addresses and protocol.
+///
+/// The IPv4 and IPv6 pseudo-headers differ only in the width of their
+/// fields, which the sum does not notice.
+pub fn pseudo_header(src: IpAddr, dst: IpAddr, protocol: u8, len: usize) -> Checksum {
+    let mut sum = Checksum::new();
+    for address in [src, dst] {
+        match address {
+            IpAddr::V4(address) => sum.add(&address.octets()),
+            IpAddr::V6(address) => sum.add(&address.octets()),
+        };
+    }
+    sum.add(&(len as u32).to_be_bytes())
+        .add_u16(u16::from(protocol));
+    sum
+}
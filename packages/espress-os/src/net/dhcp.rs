@@ -0,0 +1,565 @@
+//! # DHCP Client
+//!
+//! Configures Ethernet interfaces automatically (RFC 2131): the client
+//! broadcasts a DISCOVER, takes the first OFFER, REQUESTs the offered
+//! address, and on the server's ACK assigns the address and netmask to the
+//! interface, makes the router the default gateway, and records the DNS
+//! and NTP servers. Unanswered messages are repeated with exponential backoff.
+//!
+//! Each interface gets a `dhcp` thread that holds the lease: halfway
+//! through (T1) it asks the server that granted it for a renewal, after
+//! seven eighths (T2) it asks any server, and if the lease runs out anyway
+//! the address is removed and the client starts over. Servers that send a
+//! NAK make it start over at once.
+//!
+//! Every Ethernet interface is configured this way as soon as it is
+//! registered; see [`start`].
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::net::{Ipv4Addr, SocketAddrV4};
+use core::time::Duration;
+use spin::Mutex;
+
+use super::ipv4::Ipv4Cidr;
+use super::udp::UdpSocket;
+use super::{route, Interface, NetError};
+use crate::timer::{self, uptime};
+use crate::{println, rand, scheduler};
+
+/// Port of DHCP servers.
+pub const SERVER_PORT: u16 = 67;
+/// Port of DHCP clients.
+pub const CLIENT_PORT: u16 = 68;
+
+/// Message type: client looks for servers.
+pub const DHCPDISCOVER: u8 = 1;
+/// Message type: server offers an address.
+pub const DHCPOFFER: u8 = 2;
+/// Message type: client asks for an address or a renewal.
+pub const DHCPREQUEST: u8 = 3;
+/// Message type: server grants the request.
+pub const DHCPACK: u8 = 5;
+/// Message type: server refuses the request.
+pub const DHCPNAK: u8 = 6;
+
+/// First wait for an answer; doubled after every unanswered attempt.
+const INITIAL_TIMEOUT: Duration = Duration::from_secs(4);
+/// Longest wait for an answer.
+const MAX_TIMEOUT: Duration = Duration::from_secs(64);
+/// Shortest time between renewal attempts.
+const MIN_RENEW_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Host name sent to the server.
+const HOSTNAME: &[u8] = b"espress-os";
+
+/// BOOTP operation of client messages.
+const OP_REQUEST: u8 = 1;
+/// BOOTP operation of server messages.
+const OP_REPLY: u8 = 2;
+/// Hardware type of Ethernet.
+const HTYPE_ETHERNET: u8 = 1;
+/// Flag asking the server to broadcast its answer.
+const FLAG_BROADCAST: u16 = 0x8000;
+/// Marks the start of the options.
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// Offset of the options, after the fixed fields and the cookie.
+const OPTIONS_OFFSET: usize = 240;
+/// Shortest message some BOOTP relays accept.
+const MIN_MESSAGE_LEN: usize = 300;
+
+/// Option: padding.
+const OPTION_PAD: u8 = 0;
+/// Option: netmask.
+const OPTION_SUBNET_MASK: u8 = 1;
+/// Option: routers, most preferred first.
+const OPTION_ROUTER: u8 = 3;
+/// Option: DNS servers, most preferred first.
+const OPTION_DNS: u8 = 6;
+/// Option: host name of the client.
+const OPTION_HOSTNAME: u8 = 12;
+/// Option: NTP servers, most preferred first.
+const OPTION_NTP: u8 = 42;
+/// Option: address the client asks for.
+const OPTION_REQUESTED_ADDRESS: u8 = 50;
+/// Option: lease time in seconds.
+const OPTION_LEASE_TIME: u8 = 51;
+/// Option: message type.
+const OPTION_MESSAGE_TYPE: u8 = 53;
+/// Option: address of the server.
+const OPTION_SERVER_ID: u8 = 54;
+/// Option: options the client wants.
+const OPTION_PARAMETER_LIST: u8 = 55;
+/// Option: T1 in seconds.
+const OPTION_RENEWAL_TIME: u8 = 58;
+/// Option: T2 in seconds.
+const OPTION_REBINDING_TIME: u8 = 59;
+/// Option: client identifier.
+const OPTION_CLIENT_ID: u8 = 61;
+/// Option: end of the options.
+const OPTION_END: u8 = 255;
+
+/// Options asked of the server.
+const PARAMETERS: [u8; 7] = [
+    OPTION_SUBNET_MASK,
+    OPTION_ROUTER,
+    OPTION_DNS,
+    OPTION_NTP,
+    OPTION_LEASE_TIME,
+    OPTION_RENEWAL_TIME,
+    OPTION_REBINDING_TIME,
+];
+
+/// Current leases by interface index.
+static LEASES: Mutex<BTreeMap<usize, Lease>> = Mutex::new(BTreeMap::new());
+
+/// A lease granted by a server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lease {
+    /// Address and network of the interface
+    pub address: Ipv4Cidr,
+    /// Default gateway, if the server named one
+    pub gateway: Option<Ipv4Addr>,
+    /// DNS servers, most preferred first
+    pub dns_servers: Vec<Ipv4Addr>,
+    /// NTP servers, most preferred first
+    pub ntp_servers: Vec<Ipv4Addr>,
+    /// Server that granted the lease
+    pub server: Ipv4Addr,
+    /// Uptime at which the lease was requested
+    pub obtained: Duration,
+    /// Length of the lease
+    pub duration: Duration,
+    /// Time after which the lease is renewed (T1)
+    pub renew_after: Duration,
+    /// Time after which any server is asked (T2)
+    pub rebind_after: Duration,
+}
+
+impl Lease {
+    /// Uptime at which the lease runs out.
+    pub fn expires(&self) -> Duration {
+        self.obtained + self.duration
+    }
+}
+
+/// The parts of a server message the client uses.
+#[derive(Debug, Clone)]
+struct Reply {
+    /// Message type
+    kind: u8,
+    /// Transaction ID
+    xid: u32,
+    /// Hardware address of the client
+    chaddr: [u8; 6],
+    /// Address offered or granted
+    yiaddr: Ipv4Addr,
+    /// Option 54
+    server: Option<Ipv4Addr>,
+    /// Option 1
+    netmask: Option<Ipv4Addr>,
+    /// First address of option 3
+    router: Option<Ipv4Addr>,
+    /// Option 6
+    dns_servers: Vec<Ipv4Addr>,
+    /// Option 42
+    ntp_servers: Vec<Ipv4Addr>,
+    /// Option 51, seconds
+    lease_time: Option<u32>,
+    /// Option 58, seconds
+    renewal_time: Option<u32>,
+    /// Option 59, seconds
+    rebinding_time: Option<u32>,
+}
+
+impl Reply {
+    /// Parses a message from a server.
+    ///
+    /// # Returns
+    ///
+    /// `None` if it is malformed, not a reply, or has no message type.
+    fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < OPTIONS_OFFSET
+            || data[0] != OP_REPLY
+            || data[1] != HTYPE_ETHERNET
+            || data[2] != 6
+            || data[236..240] != MAGIC_COOKIE
+        {
+            return None;
+        }
+        let ip = |bytes: &[u8]| Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]);
+        let mut reply = Reply {
+            kind: 0,
+            xid: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            chaddr: [0; 6],
+            yiaddr: ip(&data[16..20]),
+            server: None,
+            netmask: None,
+            router: None,
+            dns_servers: Vec::new(),
+            ntp_servers: Vec::new(),
+            lease_time: None,
+            renewal_time: None,
+            rebinding_time: None,
+        };
+        reply.chaddr.copy_from_slice(&data[28..34]);
+
+        let mut at = OPTIONS_OFFSET;
+        while at < data.len() {
+            let code = data[at];
+            match code {
+                OPTION_PAD => {
+                    at += 1;
+                    continue;
+                }
+                OPTION_END => break,
+                _ => {}
+            }
+            let len = usize::from(*data.get(at + 1)?);
+            let value = data.get(at + 2..at + 2 + len)?;
+            let seconds = || Some(u32::from_be_bytes(value.try_into().ok()?));
+            match (code, len) {
+                (OPTION_MESSAGE_TYPE, 1) => reply.kind = value[0],
+                (OPTION_SUBNET_MASK, 4) => reply.netmask = Some(ip(value)),
+                (OPTION_SERVER_ID, 4) => reply.server = Some(ip(value)),
+                (OPTION_ROUTER, 4..) => reply.router = Some(ip(value)),
+                (OPTION_DNS, _) => {
+                    reply.dns_servers = value.as_chunks::<4>().0.iter().map(|a| ip(a)).collect();
+                }
+                (OPTION_NTP, _) => {
+                    reply.ntp_servers = value.as_chunks::<4>().0.iter().map(|a| ip(a)).collect();
+                }
+                (OPTION_LEASE_TIME, 4) => reply.lease_time = seconds(),
+                (OPTION_RENEWAL_TIME, 4) => reply.renewal_time = seconds(),
+                (OPTION_REBINDING_TIME, 4) => reply.rebinding_time = seconds(),
+                _ => {}
+            }
+            at += 2 + len;
+        }
+        (reply.kind != 0).then_some(reply)
+    }
+
+    /// Turns an ACK into a lease.
+    ///
+    /// # Arguments
+    ///
+    /// * `obtained` - Uptime at which the request was sent
+    /// * `server` - Server to use if the ACK does not name one
+    fn lease(&self, obtained: Duration, server: Ipv4Addr) -> Option<Lease> {
+        if self.yiaddr.is_unspecified() {
+            return None;
+        }
+        let address = match self.netmask {
+            Some(netmask) => Ipv4Cidr::from_netmask(self.yiaddr, netmask)?,
+            // Without a netmask, fall back to the address class.
+            None => {
+                let prefix_len = match self.yiaddr.octets()[0] {
+                    0..=127 => 8,
+                    128..=191 => 16,
+                    _ => 24,
+                };
+                Ipv4Cidr::new(self.yiaddr, prefix_len)
+            }
+        };
+        let seconds = |seconds: u32| Duration::from_secs(u64::from(seconds));
+        let duration = seconds(self.lease_time?);
+        let renew_after = self
+            .renewal_time
+            .map(seconds)
+            .unwrap_or(duration / 2)
+            .min(duration);
+        let rebind_after = self
+            .rebinding_time
+            .map(seconds)
+            .unwrap_or(duration * 7 / 8)
+            .clamp(renew_after, duration);
+        Some(Lease {
+            address,
+            gateway: self.router,
+            dns_servers: self.dns_servers.clone(),
+            ntp_servers: self.ntp_servers.clone(),
+            server: self.server.unwrap_or(server),
+            obtained,
+            duration,
+            renew_after,
+            rebind_after,
+        })
+    }
+}
+
+/// Starts a `dhcp` thread that configures `interface` and keeps its lease.
+///
+/// Called by [`register`](super::register) for every Ethernet interface.
+///
+/// # Errors
+///
+/// Returns [`NetError::AddressInUse`] if a client already runs on the
+/// interface.
+///
+/// # Panics
+///
+/// Panics if the thread cannot be started.
+pub fn start(interface: &Arc<Interface>) -> Result<(), NetError> {
+    let socket = UdpSocket::bind_interface(CLIENT_PORT, interface)?;
+    let interface = interface.clone();
+    scheduler::spawn_named("dhcp", move || run(&socket, &interface))
+        .expect("failed to start the dhcp thread");
+    Ok(())
+}
+
+/// Returns the current lease of `interface`.
+pub fn lease(interface: &Interface) -> Option<Lease> {
+    LEASES.lock().get(&interface.index()).cloned()
+}
+
+/// Returns the DNS servers of every lease, most preferred first.
+pub fn dns_servers() -> Vec<Ipv4Addr> {
+    servers(|lease| &lease.dns_servers)
+}
+
+/// Returns the NTP servers of every lease, most preferred first.
+pub fn ntp_servers() -> Vec<Ipv4Addr> {
+    servers(|lease| &lease.ntp_servers)
+}
+
+/// Collects the servers `list` names in every lease, without duplicates.
+fn servers(list: impl Fn(&Lease) -> &Vec<Ipv4Addr>) -> Vec<Ipv4Addr> {
+    let mut servers = Vec::new();
+    for lease in LEASES.lock().values() {
+        for &server in list(lease) {
+            if !servers.contains(&server) {
+                servers.push(server);
+            }
+        }
+    }
+    servers
+}
+
+/// Prints every lease.
+pub fn print_leases() {
+    let now = uptime();
+    for interface in super::interfaces() {
+        let Some(lease) = lease(&interface) else {
+            continue;
+        };
+        let gateway = lease
+            .gateway
+            .map_or_else(|| String::from("-"), |gateway| gateway.to_string());
+        println!(
+            "  {:<6} {:<18} gateway {:<15} server {:<15} {} s left",
+            interface.name(),
+            lease.address.to_string(),
+            gateway,
+            lease.server,
+            lease.expires().saturating_sub(now).as_secs()
+        );
+    }
+}
+
+/// Body of a `dhcp` thread: obtains leases and keeps them, forever.
+fn run(socket: &UdpSocket, interface: &Arc<Interface>) {
+    loop {
+        let lease = obtain(socket, interface);
+        apply(interface, &lease);
+        keep(socket, interface, lease);
+        LEASES.lock().remove(&interface.index());
+        interface.set_ipv4(None);
+        println!("dhcp: {} lost its lease", interface.name());
+    }
+}
+
+/// Goes through DISCOVER, OFFER, REQUEST, and ACK until a server grants a
+/// lease.
+fn obtain(socket: &UdpSocket, interface: &Arc<Interface>) -> Lease {
+    let mut timeout = INITIAL_TIMEOUT;
+    loop {
+        let xid = rand::next_u32();
+        let discover = message(DHCPDISCOVER, xid, interface, Ipv4Addr::UNSPECIFIED, &[]);
+        let _ = broadcast(socket, interface, &discover);
+        let offer = wait_reply(socket, interface, xid, timeout);
+        if let Some(offer) = offer.filter(|offer| offer.kind == DHCPOFFER) {
+            if let Some(server) = offer.server {
+                let sent = uptime();
+                let request = message(
+                    DHCPREQUEST,
+                    xid,
+                    interface,
+                    Ipv4Addr::UNSPECIFIED,
+                    &[
+                        (OPTION_REQUESTED_ADDRESS, &offer.yiaddr.octets()),
+                        (OPTION_SERVER_ID, &server.octets()),
+                    ],
+                );
+                let _ = broadcast(socket, interface, &request);
+                let ack = wait_reply(socket, interface, xid, INITIAL_TIMEOUT);
+                if let Some(lease) = ack
+                    .filter(|ack| ack.kind == DHCPACK)
+                    .and_then(|ack| ack.lease(sent, server))
+                {
+                    return lease;
+                }
+            }
+        }
+        timeout = (timeout * 2).min(MAX_TIMEOUT);
+    }
+}
+
+/// Renews `lease` at T1 and rebinds it at T2 for as long as a server
+/// extends it.
+///
+/// Returns when the lease runs out or a server refuses it.
+fn keep(socket: &UdpSocket, interface: &Arc<Interface>, mut lease: Lease) {
+    loop {
+        let elapsed = uptime().saturating_sub(lease.obtained);
+        if elapsed >= lease.duration {
+            return;
+        }
+        if elapsed < lease.renew_after {
+            timer::sleep(lease.renew_after - elapsed);
+            continue;
+        }
+        // Renew with the granting server until T2, then ask any server.
+        let rebinding = elapsed >= lease.rebind_after;
+        let phase_end = if rebinding {
+            lease.duration
+        } else {
+            lease.rebind_after
+        };
+        let left = phase_end - elapsed;
+        let wait = (left / 2).max(MIN_RENEW_INTERVAL).min(left);
+
+        let xid = rand::next_u32();
+        let sent = uptime();
+        let request = message(DHCPREQUEST, xid, interface, lease.address.address(), &[]);
+        let _ = if rebinding {
+            broadcast(socket, interface, &request)
+        } else {
+            socket.send_to(
+                &request,
+                SocketAddrV4::new(lease.server, SERVER_PORT).into(),
+            )
+        };
+        match wait_reply(socket, interface, xid, wait) {
+            Some(reply) if reply.kind == DHCPNAK => return,
+            Some(reply) if reply.kind == DHCPACK => {
+                if let Some(renewed) = reply.lease(sent, lease.server) {
+                    apply(interface, &renewed);
+                    lease = renewed;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Configures `interface` from `lease` and records the lease.
+fn apply(interface: &Arc<Interface>, lease: &Lease) {
+    if interface.ipv4() != Some(lease.address) {
+        interface.set_ipv4(Some(lease.address));
+        println!(
+            "dhcp: {} bound to {}, lease {} s",
+            interface.name(),
+            lease.address,
+            lease.duration.as_secs()
+        );
+    }
+    if let Some(gateway) = lease.gateway {
+        route::set_default_gateway(gateway, interface.clone());
+    }
+    LEASES.lock().insert(interface.index(), lease.clone());
+}
+
+/// Broadcasts `message` on `interface`.
+fn broadcast(
+    socket: &UdpSocket,
+    interface: &Arc<Interface>,
+    message: &[u8],
+) -> Result<(), NetError> {
+    let src = interface.ipv4_address().unwrap_or(Ipv4Addr::UNSPECIFIED);
+    let dst = SocketAddrV4::new(Ipv4Addr::BROADCAST, SERVER_PORT);
+    socket.send_via(interface, src.into(), dst.into(), message)
+}
+
+/// Waits up to `timeout` for a server message of transaction `xid`,
+/// ignoring everything else.
+fn wait_reply(
+    socket: &UdpSocket,
+    interface: &Interface,
+    xid: u32,
+    timeout: Duration,
+) -> Option<Reply> {
+    let deadline = uptime() + timeout;
+    loop {
+        let left = deadline
+            .checked_sub(uptime())
+            .filter(|left| !left.is_zero())?;
+        let datagram = socket.recv_timeout(left).ok()?;
+        if datagram.src.port() != SERVER_PORT {
+            continue;
+        }
+        let Some(reply) = Reply::parse(&datagram.data) else {
+            continue;
+        };
+        if reply.xid == xid && reply.chaddr == interface.mac_address().0 {
+            return Some(reply);
+        }
+    }
+}
+
+/// Builds a client message.
+///
+/// # Arguments
+///
+/// * `kind` - Message type
+/// * `xid` - Transaction ID
+/// * `interface` - Interface the message is about
+/// * `ciaddr` - Current address when renewing, unspecified otherwise
+/// * `options` - Options after the common ones
+fn message(
+    kind: u8,
+    xid: u32,
+    interface: &Interface,
+    ciaddr: Ipv4Addr,
+    options: &[(u8, &[u8])],
+) -> Vec<u8> {
+    let mac = interface.mac_address().0;
+    // Without an address the client cannot receive unicast replies.
+    let flags = if ciaddr.is_unspecified() {
+        FLAG_BROADCAST
+    } else {
+        0
+    };
+    let mut message = Vec::with_capacity(MIN_MESSAGE_LEN);
+    message.extend_from_slice(&[OP_REQUEST, HTYPE_ETHERNET, 6, 0]);
+    message.extend_from_slice(&xid.to_be_bytes());
+    message.extend_from_slice(&[0, 0]);
+    message.extend_from_slice(&flags.to_be_bytes());
+    message.extend_from_slice(&ciaddr.octets());
+    // yiaddr, siaddr, giaddr
+    message.resize(28, 0);
+    message.extend_from_slice(&mac);
+    // The rest of chaddr, sname, and file
+    message.resize(236, 0);
+    message.extend_from_slice(&MAGIC_COOKIE);
+
+    let mut client_id = [HTYPE_ETHERNET; 7];
+    client_id[1..].copy_from_slice(&mac);
+    let common: [(u8, &[u8]); 4] = [
+        (OPTION_MESSAGE_TYPE, &[kind]),
+        (OPTION_CLIENT_ID, &client_id),
+        (OPTION_HOSTNAME, HOSTNAME),
+        (OPTION_PARAMETER_LIST, &PARAMETERS),
+    ];
+    for &(code, value) in common.iter().chain(options) {
+        message.push(code);
+        message.push(value.len() as u8);
+        message.extend_from_slice(value);
+    }
+    message.push(OPTION_END);
+    if message.len() < MIN_MESSAGE_LEN {
+        message.resize(MIN_MESSAGE_LEN, OPTION_PAD);
+    }
+    message
+}
@@ -0,0 +1,116 @@
+//! # Ethernet
+//!
+//! Framing of every packet the stack sends or receives: a 14-byte header
+//! with the destination and source hardware addresses and the EtherType,
+//! which names the protocol of the payload. The checksum at the end of the
+//! frame is left to the hardware.
+//!
+//! Received frames addressed to the interface, to broadcast, or to a
+//! multicast group are passed to the protocol their EtherType names; the
+//! loopback interface takes every frame.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use super::{arp, ipv4, ipv6, Interface, MacAddress, NetError, LINK_HEADER_LEN};
+
+/// EtherType of IPv4.
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+/// EtherType of ARP.
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+/// EtherType of IPv6.
+pub const ETHERTYPE_IPV6: u16 = 0x86dd;
+
+/// Shortest frame on the wire, without the checksum; shorter ones are
+/// padded.
+pub const MIN_FRAME_LEN: usize = 60;
+
+/// Header of an Ethernet frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EthernetHeader {
+    /// Receiving station
+    pub dst: MacAddress,
+    /// Sending station
+    pub src: MacAddress,
+    /// Protocol of the payload
+    pub ethertype: u16,
+}
+
+impl EthernetHeader {
+    /// Splits `frame` into header and payload.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the frame is shorter than a header.
+    pub fn parse(frame: &[u8]) -> Option<(Self, &[u8])> {
+        if frame.len() < LINK_HEADER_LEN {
+            return None;
+        }
+        let mut dst = [0; 6];
+        let mut src = [0; 6];
+        dst.copy_from_slice(&frame[0..6]);
+        src.copy_from_slice(&frame[6..12]);
+        let header = EthernetHeader {
+            dst: MacAddress(dst),
+            src: MacAddress(src),
+            ethertype: u16::from_be_bytes([frame[12], frame[13]]),
+        };
+        Some((header, &frame[LINK_HEADER_LEN..]))
+    }
+
+    /// Appends the header to `buf`.
+    pub fn write(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.dst.0);
+        buf.extend_from_slice(&self.src.0);
+        buf.extend_from_slice(&self.ethertype.to_be_bytes());
+    }
+}
+
+/// Sends `payload` to `dst` on `interface`.
+///
+/// # Errors
+///
+/// Returns the errors of [`Interface::transmit`].
+pub fn send(
+    interface: &Interface,
+    dst: MacAddress,
+    ethertype: u16,
+    payload: &[u8],
+) -> Result<(), NetError> {
+    let header = EthernetHeader {
+        dst,
+        src: interface.mac_address(),
+        ethertype,
+    };
+    let mut frame = Vec::with_capacity((LINK_HEADER_LEN + payload.len()).max(MIN_FRAME_LEN));
+    header.write(&mut frame);
+    frame.extend_from_slice(payload);
+    if frame.len() < MIN_FRAME_LEN {
+        frame.resize(MIN_FRAME_LEN, 0);
+    }
+    interface.transmit(&frame)
+}
+
+/// Passes a received frame to the protocol it carries.
+///
+/// # Returns
+///
+/// `false` if the frame was not for this interface or its protocol is not
+/// supported.
+pub(super) fn receive(interface: &Arc<Interface>, frame: &[u8]) -> bool {
+    let Some((header, payload)) = EthernetHeader::parse(frame) else {
+        return false;
+    };
+    let for_us = interface.is_loopback()
+        || header.dst == interface.mac_address()
+        || header.dst.is_multicast();
+    if !for_us {
+        return false;
+    }
+    match header.ethertype {
+        ETHERTYPE_ARP => arp::receive(interface, payload),
+        ETHERTYPE_IPV4 => ipv4::receive(interface, payload),
+        ETHERTYPE_IPV6 => ipv6::receive(interface, payload),
+        _ => false,
+    }
+}
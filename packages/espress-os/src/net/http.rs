@@ -0,0 +1,309 @@
+//! # HTTP Status Server
+//!
+//! A tiny HTTP/1.1 server for remote diagnostics, built with the
+//! `http-server` feature. It answers `GET` and `HEAD` requests for:
+//!
+//! - `/`: an HTML page with everything below
+//! - `/uptime`: time since boot
+//! - `/memory`: physical frames and kernel heap
+//! - `/tasks`: every thread, as in [`print_tasks`](crate::scheduler::print_tasks)
+//! - `/dmesg`: the console log
+//! - `/net`: interfaces with their addresses, routes, and TCP connections
+//!
+//! Everything but `/` is plain text, so `curl` output stays readable.
+//! Requests are served one at a time by the `httpd` thread, and every
+//! connection is closed after its response.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::time::Duration;
+
+use super::tcp::{self, TcpListener, TcpStream};
+use super::{route, NetError};
+use crate::scheduler;
+use crate::{console, mm, timer};
+
+/// The usual HTTP port.
+pub const PORT: u16 = 80;
+
+/// Longest request head accepted.
+const MAX_REQUEST_LEN: usize = 4096;
+
+/// How long a client may take to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Text pages by path, with their titles on the HTML page.
+const PAGES: [Page; 5] = [
+    ("/uptime", "Uptime", uptime),
+    ("/memory", "Memory", memory),
+    ("/tasks", "Tasks", task_list),
+    ("/net", "Network", network),
+    ("/dmesg", "Kernel log", dmesg),
+];
+
+/// A text page: its path, its title, and the function that renders it.
+type Page = (&'static str, &'static str, fn() -> String);
+
+/// Starts the `httpd` thread, serving on `port`.
+///
+/// # Errors
+///
+/// Returns [`NetError::AddressInUse`] if something listens on the port
+/// already.
+///
+/// # Panics
+///
+/// Panics if the thread cannot be started.
+pub fn start(port: u16) -> Result<(), NetError> {
+    let listener = TcpListener::bind(port)?;
+    scheduler::spawn_named("httpd", move || serve(&listener))
+        .expect("failed to start the httpd thread");
+    Ok(())
+}
+
+/// Body of the `httpd` thread.
+fn serve(listener: &TcpListener) {
+    loop {
+        let stream = listener.accept();
+        handle(&stream);
+    }
+}
+
+/// Answers one request.
+fn handle(stream: &TcpStream) {
+    let Some(head) = read_head(stream) else {
+        return respond(
+            stream,
+            "400 Bad Request",
+            "text/plain",
+            "bad request\n",
+            false,
+        );
+    };
+    let mut words = head.lines().next().unwrap_or("").split_whitespace();
+    let (Some(method), Some(target), Some(_version)) = (words.next(), words.next(), words.next())
+    else {
+        return respond(
+            stream,
+            "400 Bad Request",
+            "text/plain",
+            "bad request\n",
+            false,
+        );
+    };
+    let head_only = match method {
+        "GET" => false,
+        "HEAD" => true,
+        _ => {
+            return respond(
+                stream,
+                "405 Method Not Allowed",
+                "text/plain",
+                "only GET and HEAD are supported\n",
+                false,
+            )
+        }
+    };
+    let path = target.split('?').next().unwrap_or(target);
+    if path == "/" {
+        return respond(
+            stream,
+            "200 OK",
+            "text/html; charset=utf-8",
+            &index(),
+            head_only,
+        );
+    }
+    match PAGES.iter().find(|(page, _, _)| *page == path) {
+        Some((_, _, render)) => respond(
+            stream,
+            "200 OK",
+            "text/plain; charset=utf-8",
+            &render(),
+            head_only,
+        ),
+        None => respond(
+            stream,
+            "404 Not Found",
+            "text/plain",
+            "not found\n",
+            head_only,
+        ),
+    }
+}
+
+/// Reads the request line and headers.
+///
+/// # Returns
+///
+/// `None` if the client closes the connection, times out, or sends too
+/// much or something that is not text.
+fn read_head(stream: &TcpStream) -> Option<String> {
+    let mut request = Vec::new();
+    let mut buf = [0; 512];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        if request.len() > MAX_REQUEST_LEN {
+            return None;
+        }
+        match stream.recv_timeout(&mut buf, REQUEST_TIMEOUT) {
+            Ok(0) | Err(_) => return None,
+            Ok(len) => request.extend_from_slice(&buf[..len]),
+        }
+    }
+    String::from_utf8(request).ok()
+}
+
+/// Sends a complete response.
+///
+/// # Arguments
+///
+/// * `stream` - The connection
+/// * `status` - Status code and reason, e.g. `"200 OK"`
+/// * `content_type` - Media type of the body
+/// * `body` - The body
+/// * `head_only` - Leave out the body, for `HEAD`
+fn respond(stream: &TcpStream, status: &str, content_type: &str, body: &str, head_only: bool) {
+    let head = format!(
+        "HTTP/1.1 {status}\r\nServer: espress-os\r\nContent-Type: {content_type}\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    if stream.send_all(head.as_bytes()).is_ok() && !head_only {
+        let _ = stream.send_all(body.as_bytes());
+    }
+}
+
+/// The HTML overview of every text page.
+fn index() -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+         <title>espress-os</title></head><body>\n<h1>espress-os</h1>\n",
+    );
+    for (path, title, render) in PAGES {
+        let _ = write!(
+            html,
+            "<h2><a href=\"{path}\">{title}</a></h2>\n<pre>{}</pre>\n",
+            escape(&render())
+        );
+    }
+    html.push_str("</body></html>\n");
+    html
+}
+
+/// Time since boot.
+fn uptime() -> String {
+    let seconds = timer::uptime().as_secs();
+    format!(
+        "up {}d {:02}:{:02}:{:02}\n",
+        seconds / 86400,
+        seconds / 3600 % 24,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Usage of physical memory and of the kernel heap.
+fn memory() -> String {
+    let (total, free) = mm::with_frames(|frames| (frames.total_frames(), frames.free_frames()));
+    let heap = mm::heap::stats();
+    format!(
+        "frames: {} KiB total, {} KiB free\n\
+         heap:   {} KiB total, {} KiB allocated in {} blocks, {} KiB peak\n",
+        total * 4,
+        free * 4,
+        heap.size / 1024,
+        heap.allocated_bytes / 1024,
+        heap.allocations,
+        heap.peak_bytes / 1024
+    )
+}
+
+/// Every thread.
+fn task_list() -> String {
+    let mut text = format!(
+        "{:>4}  {:>4}  {:<16}  {:<7}  {:<8}  {:>10}\n",
+        "tid", "pid", "name", "state", "priority", "cpu (ms)"
+    );
+    for task in scheduler::tasks() {
+        let _ = writeln!(
+            text,
+            "{:>4}  {:>4}  {:<16}  {:<7}  {:<8}  {:>10}",
+            task.id.as_u64(),
+            task.pid.map_or(0, |pid| pid.as_u64()),
+            task.name,
+            task.state.name(),
+            task.priority.name(),
+            task.cpu_time.as_millis()
+        );
+    }
+    text
+}
+
+/// Interfaces, routes, and TCP connections.
+fn network() -> String {
+    let mut text = String::from("interfaces:\n");
+    for interface in super::interfaces() {
+        let address = interface
+            .ipv4()
+            .map_or_else(|| String::from("-"), |cidr| cidr.to_string());
+        let stats = interface.stats();
+        let _ = writeln!(
+            text,
+            "  {:<6} {:<18} {}  rx {}  tx {}",
+            interface.name(),
+            address,
+            interface.mac_address(),
+            stats.rx_packets,
+            stats.tx_packets
+        );
+        for cidr in interface.ipv6() {
+            let _ = writeln!(text, "         {}", cidr);
+        }
+    }
+    text.push_str("routes:\n");
+    for route in route::routes() {
+        let gateway = route
+            .gateway
+            .map_or_else(|| String::from("-"), |gateway| gateway.to_string());
+        let _ = writeln!(
+            text,
+            "  {:<18} {:<15} {}",
+            route.destination.to_string(),
+            gateway,
+            route.interface.name()
+        );
+    }
+    text.push_str("tcp:\n");
+    for info in tcp::connections() {
+        let _ = writeln!(
+            text,
+            "  {:<21} {:<21} {}",
+            info.local.to_string(),
+            info.remote.to_string(),
+            info.state.name()
+        );
+    }
+    text
+}
+
+/// The console log.
+fn dmesg() -> String {
+    console::log()
+}
+
+/// Escapes `text` for use in HTML.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,261 @@
+//! # ICMP
+//!
+//! The control messages of IPv4 (RFC 792). The kernel answers every echo
+//! request addressed to it, so `ping` from the host works as soon as an
+//! interface has an address, and [`ping`] sends echo requests of its own
+//! and prints the round-trip times like the command of the same name.
+//! [`echo`] and [`ping`] take IPv6 addresses too, and send ICMPv6 echo
+//! requests to them (see [`icmpv6`](super::icmpv6)).
+//!
+//! Replies are matched to requests by identifier and sequence number;
+//! every call to [`ping`] uses a random identifier of its own.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::net::IpAddr;
+use core::time::Duration;
+use spin::Mutex;
+
+use super::checksum::checksum;
+use super::ipv4::{self, Ipv4Header, PROTOCOL_ICMP};
+use super::{icmpv6, Interface, NetError};
+use crate::arch::tsc;
+use crate::scheduler::WaitQueue;
+use crate::timer;
+use crate::{println, rand};
+
+/// Type of an echo reply.
+pub const TYPE_ECHO_REPLY: u8 = 0;
+/// Type of a destination unreachable message.
+pub const TYPE_DEST_UNREACHABLE: u8 = 3;
+/// Type of an echo request.
+pub const TYPE_ECHO_REQUEST: u8 = 8;
+/// Type of a time exceeded message.
+pub const TYPE_TIME_EXCEEDED: u8 = 11;
+
+/// Size of an ICMP header.
+pub const HEADER_LEN: usize = 8;
+
+/// Bytes of data in the echo requests of [`ping`], as in the usual tool.
+pub const PING_DATA_LEN: usize = 56;
+
+/// How long [`ping`] waits for each reply.
+pub const PING_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Time between the requests of [`ping`].
+pub const PING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Replies kept until their requester collects them; older ones are
+/// dropped.
+const MAX_REPLIES: usize = 32;
+
+/// Echo replies not yet collected.
+static REPLIES: Mutex<Vec<EchoReply>> = Mutex::new(Vec::new());
+
+/// Where requesters wait for replies.
+static REPLY_READY: WaitQueue = WaitQueue::new();
+
+/// An echo reply that arrived.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EchoReply {
+    /// Sender of the reply
+    pub from: IpAddr,
+    /// Identifier of the request
+    pub id: u16,
+    /// Sequence number of the request
+    pub seq: u16,
+    /// Time to live or hop limit the reply arrived with
+    pub ttl: u8,
+    /// Bytes of ICMP message
+    pub len: usize,
+}
+
+/// Results of a [`ping`] run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PingStats {
+    /// Requests sent
+    pub transmitted: u32,
+    /// Replies received
+    pub received: u32,
+    /// Shortest round trip
+    pub min: Duration,
+    /// Longest round trip
+    pub max: Duration,
+    /// Sum of all round trips
+    pub total: Duration,
+}
+
+/// Sends one echo request to `dst` and waits up to `timeout` for the
+/// reply.
+///
+/// # Returns
+///
+/// The reply and the round-trip time.
+///
+/// # Errors
+///
+/// Returns [`NetError::Timeout`] if no reply arrives in time, and the
+/// errors of [`ipv4::send`] or [`icmpv6::send`].
+///
+/// # Panics
+///
+/// Panics if called from outside a scheduler thread.
+pub fn echo(
+    dst: IpAddr,
+    id: u16,
+    seq: u16,
+    data: &[u8],
+    timeout: Duration,
+) -> Result<(EchoReply, Duration), NetError> {
+    let rest = echo_rest(id, seq);
+    let start = tsc::read();
+    match dst {
+        IpAddr::V4(dst) => {
+            let message = build(TYPE_ECHO_REQUEST, 0, &rest, data);
+            ipv4::send(dst, PROTOCOL_ICMP, &message)?;
+        }
+        IpAddr::V6(dst) => {
+            let body = [&rest[..], data].concat();
+            icmpv6::send(dst, icmpv6::TYPE_ECHO_REQUEST, 0, &body)?;
+        }
+    }
+
+    let mut reply = None;
+    REPLY_READY.wait_until_timeout(timeout, || {
+        let mut replies = REPLIES.lock();
+        if let Some(at) = replies.iter().position(|r| r.id == id && r.seq == seq) {
+            reply = Some(replies.remove(at));
+        }
+        reply.is_some()
+    });
+    let rtt = tsc::cycles_to_duration(tsc::read().wrapping_sub(start));
+    reply.map(|reply| (reply, rtt)).ok_or(NetError::Timeout)
+}
+
+/// Pings `dst` `count` times, printing every reply and a summary.
+///
+/// # Panics
+///
+/// Panics if called from outside a scheduler thread.
+pub fn ping(dst: IpAddr, count: u32) -> PingStats {
+    let id = rand::next_u32() as u16;
+    let data: Vec<u8> = (0..PING_DATA_LEN).map(|i| i as u8).collect();
+    let mut stats = PingStats::default();
+    println!("PING {} {} bytes of data", dst, PING_DATA_LEN);
+    for seq in 1..=count {
+        if seq > 1 {
+            timer::sleep(PING_INTERVAL);
+        }
+        stats.transmitted += 1;
+        match echo(dst, id, seq as u16, &data, PING_TIMEOUT) {
+            Ok((reply, rtt)) => {
+                println!(
+                    "{} bytes from {}: icmp_seq={} ttl={} time={}.{:03} ms",
+                    reply.len,
+                    reply.from,
+                    seq,
+                    reply.ttl,
+                    rtt.as_micros() / 1000,
+                    rtt.as_micros() % 1000
+                );
+                stats.min = if stats.received == 0 {
+                    rtt
+                } else {
+                    stats.min.min(rtt)
+                };
+                stats.max = stats.max.max(rtt);
+                stats.total += rtt;
+                stats.received += 1;
+            }
+            Err(NetError::Timeout) => println!("request timeout for icmp_seq={}", seq),
+            Err(err) => println!("ping: {}: {:?}", dst, err),
+        }
+    }
+
+    let loss = match stats.transmitted {
+        0 => 0,
+        sent => (sent - stats.received) * 100 / sent,
+    };
+    println!(
+        "--- {} ping statistics --- {} transmitted, {} received, {}% packet loss",
+        dst, stats.transmitted, stats.received, loss
+    );
+    if stats.received > 0 {
+        let avg = stats.total / stats.received;
+        println!(
+            "rtt min/avg/max = {:?}/{:?}/{:?}",
+            stats.min, avg, stats.max
+        );
+    }
+    stats
+}
+
+/// Handles a received ICMP message.
+///
+/// # Returns
+///
+/// `false` if the message is malformed.
+pub(super) fn receive(interface: &Arc<Interface>, header: &Ipv4Header, message: &[u8]) -> bool {
+    if message.len() < HEADER_LEN || checksum(message) != 0 {
+        return false;
+    }
+    let be16 = |at: usize| u16::from_be_bytes([message[at], message[at + 1]]);
+    match message[0] {
+        TYPE_ECHO_REQUEST => {
+            let reply = build(TYPE_ECHO_REPLY, 0, &message[4..8], &message[HEADER_LEN..]);
+            // Broadcast requests are answered from the interface's address.
+            let src = match ipv4::local_interface(header.dst) {
+                Some(_) => Some(header.dst),
+                None => interface.ipv4_address(),
+            };
+            if let Some(src) = src {
+                let _ = ipv4::send_from(Some(src), header.src, PROTOCOL_ICMP, &reply);
+            }
+        }
+        TYPE_ECHO_REPLY => record_reply(EchoReply {
+            from: header.src.into(),
+            id: be16(4),
+            seq: be16(6),
+            ttl: header.ttl,
+            len: message.len(),
+        }),
+        _ => {}
+    }
+    true
+}
+
+/// Hands an echo reply of either version to the requester waiting for it.
+pub(super) fn record_reply(reply: EchoReply) {
+    let mut replies = REPLIES.lock();
+    if replies.len() == MAX_REPLIES {
+        replies.remove(0);
+    }
+    replies.push(reply);
+    drop(replies);
+    REPLY_READY.notify_all();
+}
+
+/// The identifier and sequence number words of an echo message.
+fn echo_rest(id: u16, seq: u16) -> [u8; 4] {
+    let [a, b] = id.to_be_bytes();
+    let [c, d] = seq.to_be_bytes();
+    [a, b, c, d]
+}
+
+/// Builds a message with its checksum.
+///
+/// # Arguments
+///
+/// * `kind` - Message type
+/// * `code` - Message code
+/// * `rest` - The four bytes after the checksum
+/// * `data` - Data following the header
+fn build(kind: u8, code: u8, rest: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(HEADER_LEN + data.len());
+    message.extend_from_slice(&[kind, code, 0, 0]);
+    message.extend_from_slice(&rest[..4]);
+    message.extend_from_slice(data);
+    let sum = checksum(&message);
+    message[2..4].copy_from_slice(&sum.to_be_bytes());
+    message
+}
@@ -0,0 +1,128 @@
+//! # ICMPv6
+//!
+//! The control messages of IPv6 (RFC 4443), which also carry neighbour
+//! discovery (see [`ndp`](super::ndp)). The kernel answers every echo
+//! request addressed to it; echo replies go to [`icmp`](super::icmp),
+//! whose [`ping`](super::icmp::ping) speaks both versions of IP.
+//!
+//! Unlike ICMP, the checksum covers a pseudo-header of the addresses.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::net::Ipv6Addr;
+
+use super::checksum::pseudo_header;
+use super::icmp::{self, EchoReply};
+use super::ipv6::{self, Ipv6Header, NEXT_HEADER_ICMPV6};
+use super::{ndp, Interface, NetError};
+
+/// Type of a destination unreachable message.
+pub const TYPE_DEST_UNREACHABLE: u8 = 1;
+/// Type of a packet too big message.
+pub const TYPE_PACKET_TOO_BIG: u8 = 2;
+/// Type of a time exceeded message.
+pub const TYPE_TIME_EXCEEDED: u8 = 3;
+/// Type of an echo request.
+pub const TYPE_ECHO_REQUEST: u8 = 128;
+/// Type of an echo reply.
+pub const TYPE_ECHO_REPLY: u8 = 129;
+/// Type of a router solicitation.
+pub const TYPE_ROUTER_SOLICITATION: u8 = 133;
+/// Type of a router advertisement.
+pub const TYPE_ROUTER_ADVERTISEMENT: u8 = 134;
+/// Type of a neighbour solicitation.
+pub const TYPE_NEIGHBOR_SOLICITATION: u8 = 135;
+/// Type of a neighbour advertisement.
+pub const TYPE_NEIGHBOR_ADVERTISEMENT: u8 = 136;
+/// Type of a redirect.
+pub const TYPE_REDIRECT: u8 = 137;
+
+/// Size of the type, code, and checksum that every message starts with.
+pub const HEADER_LEN: usize = 4;
+
+/// Sends a message of type `kind` to `dst`, from the address
+/// [`ipv6::send`] would use.
+///
+/// # Arguments
+///
+/// * `dst` - Destination
+/// * `kind` - Message type
+/// * `code` - Message code
+/// * `body` - Everything after the checksum
+///
+/// # Errors
+///
+/// Returns the errors of [`ipv6::send`].
+pub fn send(dst: Ipv6Addr, kind: u8, code: u8, body: &[u8]) -> Result<(), NetError> {
+    let src = ipv6::source_address(dst)?;
+    let message = build(src, dst, kind, code, body);
+    ipv6::send_from(Some(src), dst, NEXT_HEADER_ICMPV6, &message)
+}
+
+/// Builds a message from `src` to `dst` with its checksum.
+///
+/// # Arguments
+///
+/// * `src` - Source address, part of the checksum
+/// * `dst` - Destination address, part of the checksum
+/// * `kind` - Message type
+/// * `code` - Message code
+/// * `body` - Everything after the checksum
+pub fn build(src: Ipv6Addr, dst: Ipv6Addr, kind: u8, code: u8, body: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(HEADER_LEN + body.len());
+    message.extend_from_slice(&[kind, code, 0, 0]);
+    message.extend_from_slice(body);
+    let sum = pseudo_header(src.into(), dst.into(), NEXT_HEADER_ICMPV6, message.len())
+        .add(&message)
+        .finish();
+    message[2..4].copy_from_slice(&sum.to_be_bytes());
+    message
+}
+
+/// Handles a received ICMPv6 message.
+///
+/// # Returns
+///
+/// `false` if the message is malformed.
+pub(super) fn receive(interface: &Arc<Interface>, header: &Ipv6Header, message: &[u8]) -> bool {
+    if message.len() < HEADER_LEN
+        || pseudo_header(
+            header.src.into(),
+            header.dst.into(),
+            NEXT_HEADER_ICMPV6,
+            message.len(),
+        )
+        .add(message)
+        .finish()
+            != 0
+    {
+        return false;
+    }
+    let be16 = |at: usize| u16::from_be_bytes([message[at], message[at + 1]]);
+    match message[0] {
+        TYPE_ECHO_REQUEST if message.len() >= 8 => {
+            // Multicast requests are answered from an address of the
+            // interface.
+            let src = match header.dst.is_multicast() {
+                true => ipv6::select_source(interface, header.src),
+                false => Some(header.dst),
+            };
+            if let Some(src) = src {
+                let reply = build(src, header.src, TYPE_ECHO_REPLY, 0, &message[HEADER_LEN..]);
+                let _ = ipv6::send_from(Some(src), header.src, NEXT_HEADER_ICMPV6, &reply);
+            }
+        }
+        TYPE_ECHO_REPLY if message.len() >= 8 => icmp::record_reply(EchoReply {
+            from: header.src.into(),
+            id: be16(4),
+            seq: be16(6),
+            ttl: header.hop_limit,
+            len: message.len(),
+        }),
+        kind @ TYPE_ROUTER_SOLICITATION..=TYPE_REDIRECT => {
+            return ndp::receive(interface, header, kind, message)
+        }
+        _ => {}
+    }
+    true
+}
@@ -0,0 +1,100 @@
+//! # IP
+//!
+//! What UDP and TCP need from the network layer, for either version of IP:
+//! the interface and source address for a destination, and sending. The
+//! transport protocols use the same numbers on both versions, see
+//! [`ipv4::PROTOCOL_TCP`] and [`ipv4::PROTOCOL_UDP`].
+
+use alloc::sync::Arc;
+use core::net::IpAddr;
+
+use super::{ipv4, ipv6, Interface, NetError};
+
+/// Finds the interface datagrams to `dst` leave through and the source
+/// address they get.
+///
+/// # Errors
+///
+/// Returns [`NetError::NoRoute`] if no route leads to `dst` and
+/// [`NetError::NoAddress`] if the interface has no address of its version.
+pub fn route(dst: IpAddr) -> Result<(Arc<Interface>, IpAddr), NetError> {
+    match dst {
+        IpAddr::V4(dst) => {
+            let (interface, _) = ipv4::next_hop(dst)?;
+            let src = interface.ipv4_address().ok_or(NetError::NoAddress)?;
+            Ok((interface, src.into()))
+        }
+        IpAddr::V6(dst) => {
+            let (interface, _) = ipv6::next_hop(dst)?;
+            let src = ipv6::select_source(&interface, dst).ok_or(NetError::NoAddress)?;
+            Ok((interface, src.into()))
+        }
+    }
+}
+
+/// Sends `payload` of `protocol` from `src` to `dst`, routed like any
+/// other datagram.
+///
+/// # Errors
+///
+/// Returns [`NetError::NoAddress`] if `src` and `dst` are of different
+/// versions, and the errors of [`ipv4::send_from`] or
+/// [`ipv6::send_from`].
+pub fn send(src: IpAddr, dst: IpAddr, protocol: u8, payload: &[u8]) -> Result<(), NetError> {
+    match (src, dst) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => ipv4::send_from(Some(src), dst, protocol, payload),
+        (IpAddr::V6(src), IpAddr::V6(dst)) => ipv6::send_from(Some(src), dst, protocol, payload),
+        _ => Err(NetError::NoAddress),
+    }
+}
+
+/// Sends `payload` of `protocol` from `src` straight to `dst` on
+/// `interface`, bypassing the routing table.
+///
+/// # Errors
+///
+/// Returns [`NetError::NoAddress`] if `src` and `dst` are of different
+/// versions, and the errors of [`ipv4::send_via`] or [`ipv6::send_via`].
+pub fn send_via(
+    interface: &Arc<Interface>,
+    src: IpAddr,
+    dst: IpAddr,
+    protocol: u8,
+    payload: &[u8],
+) -> Result<(), NetError> {
+    match (src, dst) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            ipv4::send_via(interface, dst, src, dst, protocol, payload)
+        }
+        (IpAddr::V6(src), IpAddr::V6(dst)) => {
+            ipv6::send_via(interface, dst, src, dst, protocol, payload)
+        }
+        _ => Err(NetError::NoAddress),
+    }
+}
+
+/// Size of the header without options or extensions of the version of
+/// `address`.
+pub fn header_len(address: IpAddr) -> usize {
+    match address {
+        IpAddr::V4(_) => ipv4::HEADER_LEN,
+        IpAddr::V6(_) => ipv6::HEADER_LEN,
+    }
+}
+
+/// Largest payload of a datagram of the version of `address`.
+pub fn max_payload_len(address: IpAddr) -> usize {
+    match address {
+        IpAddr::V4(_) => ipv4::MAX_DATAGRAM_LEN - ipv4::HEADER_LEN,
+        IpAddr::V6(_) => ipv6::MAX_PAYLOAD_LEN,
+    }
+}
+
+/// Returns `true` if `address` names a group of hosts rather than one:
+/// a broadcast or multicast address.
+pub fn is_group(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => address.is_broadcast() || address.is_multicast(),
+        IpAddr::V6(address) => address.is_multicast(),
+    }
+}
@@ -0,0 +1,531 @@
+//! # IPv4
+//!
+//! Sends and receives IPv4 datagrams (RFC 791). Outgoing datagrams are
+//! routed (see [`route`](super::route)), given the address of the
+//! interface they leave through as source, split into fragments if they do
+//! not fit its MTU, and handed to ARP for delivery to the next hop.
+//!
+//! Incoming datagrams are checked, reassembled from fragments if needed,
+//! and passed to the protocol they carry if they are addressed to the
+//! interface: its own address, the broadcast address of its network, or
+//! the limited broadcast address. An interface without an address accepts
+//! every datagram, so that it can be configured over the network.
+//! Reassembly is bounded: at most [`MAX_REASSEMBLIES`] datagrams at a time,
+//! each dropped after [`MAX_FRAGMENTS`] fragments or when its timeout runs
+//! out, so fragments from a remote host cannot exhaust the heap.
+//!
+//! Options are skipped on receipt and never sent.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::net::Ipv4Addr;
+use core::sync::atomic::{AtomicU16, Ordering};
+use core::time::Duration;
+use spin::Mutex;
+
+use super::checksum::checksum;
+use super::ethernet::ETHERTYPE_IPV4;
+use super::{arp, icmp, route, tcp, udp, Interface, NetError};
+use crate::timer::{self, Timer};
+
+/// Size of a header without options.
+pub const HEADER_LEN: usize = 20;
+
+/// Largest datagram, header included.
+pub const MAX_DATAGRAM_LEN: usize = 65535;
+
+/// Time to live of outgoing datagrams.
+pub const DEFAULT_TTL: u8 = 64;
+
+/// How long the fragments of a datagram are kept waiting for the rest.
+pub const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Protocol number of ICMP.
+pub const PROTOCOL_ICMP: u8 = 1;
+/// Protocol number of TCP.
+pub const PROTOCOL_TCP: u8 = 6;
+/// Protocol number of UDP.
+pub const PROTOCOL_UDP: u8 = 17;
+
+/// Flag: more fragments follow.
+const FLAG_MORE_FRAGMENTS: u16 = 0x2000;
+/// Flag: the datagram must not be fragmented.
+const FLAG_DONT_FRAGMENT: u16 = 0x4000;
+/// Mask of the fragment offset, in units of 8 bytes.
+const FRAGMENT_OFFSET_MASK: u16 = 0x1fff;
+
+/// Datagrams being reassembled at the same time; more are dropped.
+const MAX_REASSEMBLIES: usize = 16;
+
+/// Fragments accepted for one datagram, duplicates included; a datagram
+/// that takes more is dropped, so that a sender cannot grow its entry
+/// without bound.
+const MAX_FRAGMENTS: usize = 64;
+
+/// Identification of the next datagram sent.
+static NEXT_ID: AtomicU16 = AtomicU16::new(0);
+
+/// Datagrams being reassembled.
+static REASSEMBLY: Mutex<BTreeMap<FragmentKey, Reassembly>> = Mutex::new(BTreeMap::new());
+
+/// An address with the length of its network prefix, e.g. `10.0.2.15/24`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Ipv4Cidr {
+    /// The address
+    address: Ipv4Addr,
+    /// Bits of the network part, 0 to 32
+    prefix_len: u8,
+}
+
+impl Ipv4Cidr {
+    /// The network of every address, `0.0.0.0/0`.
+    pub const DEFAULT: Ipv4Cidr = Ipv4Cidr {
+        address: Ipv4Addr::UNSPECIFIED,
+        prefix_len: 0,
+    };
+
+    /// Pairs `address` with a prefix length.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prefix_len` is larger than 32.
+    pub const fn new(address: Ipv4Addr, prefix_len: u8) -> Self {
+        assert!(prefix_len <= 32, "IPv4 prefix longer than 32 bits");
+        Ipv4Cidr {
+            address,
+            prefix_len,
+        }
+    }
+
+    /// Pairs `address` with the prefix length of `netmask`.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the netmask is not a contiguous run of ones.
+    pub fn from_netmask(address: Ipv4Addr, netmask: Ipv4Addr) -> Option<Self> {
+        let mask = u32::from(netmask);
+        if mask.leading_ones() + mask.trailing_zeros() != 32 {
+            return None;
+        }
+        Some(Self::new(address, mask.leading_ones() as u8))
+    }
+
+    /// The address.
+    pub fn address(&self) -> Ipv4Addr {
+        self.address
+    }
+
+    /// Bits of the network part.
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// The netmask, e.g. `255.255.255.0` for a `/24`.
+    pub fn netmask(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.mask())
+    }
+
+    /// The network with the host part cleared.
+    pub fn network(&self) -> Ipv4Cidr {
+        Self::new(
+            Ipv4Addr::from(u32::from(self.address) & self.mask()),
+            self.prefix_len,
+        )
+    }
+
+    /// The broadcast address of the network.
+    pub fn broadcast(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.address) | !self.mask())
+    }
+
+    /// Returns `true` if `address` is in the network.
+    pub fn contains(&self, address: Ipv4Addr) -> bool {
+        (u32::from(address) ^ u32::from(self.address)) & self.mask() == 0
+    }
+
+    /// The netmask as a number.
+    fn mask(&self) -> u32 {
+        u32::MAX
+            .checked_shl(32 - u32::from(self.prefix_len))
+            .unwrap_or(0)
+    }
+}
+
+impl fmt::Display for Ipv4Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_len)
+    }
+}
+
+/// Header of an IPv4 datagram.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Header {
+    /// Type of service
+    pub tos: u8,
+    /// Length of the datagram (or fragment), header included
+    pub total_len: u16,
+    /// Identification shared by the fragments of a datagram
+    pub id: u16,
+    /// Flags and fragment offset
+    pub flags_fragment: u16,
+    /// Time to live
+    pub ttl: u8,
+    /// Protocol of the payload
+    pub protocol: u8,
+    /// Source address
+    pub src: Ipv4Addr,
+    /// Destination address
+    pub dst: Ipv4Addr,
+}
+
+impl Ipv4Header {
+    /// Splits `data` into header and payload, checking the version, header
+    /// checksum, and lengths. Trailing padding of the frame is cut off.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the datagram is malformed.
+    pub fn parse(data: &[u8]) -> Option<(Self, &[u8])> {
+        let first = *data.first()?;
+        let header_len = usize::from(first & 0xf) * 4;
+        if first >> 4 != 4 || header_len < HEADER_LEN || data.len() < header_len {
+            return None;
+        }
+        if checksum(&data[..header_len]) != 0 {
+            return None;
+        }
+        let be16 = |at: usize| u16::from_be_bytes([data[at], data[at + 1]]);
+        let total_len = be16(2);
+        if usize::from(total_len) < header_len || usize::from(total_len) > data.len() {
+            return None;
+        }
+        let ip = |at: usize| Ipv4Addr::new(data[at], data[at + 1], data[at + 2], data[at + 3]);
+        let header = Ipv4Header {
+            tos: data[1],
+            total_len,
+            id: be16(4),
+            flags_fragment: be16(6),
+            ttl: data[8],
+            protocol: data[9],
+            src: ip(12),
+            dst: ip(16),
+        };
+        Some((header, &data[header_len..usize::from(total_len)]))
+    }
+
+    /// Appends the header, without options and with its checksum, to `buf`.
+    pub fn write(&self, buf: &mut Vec<u8>) {
+        let start = buf.len();
+        buf.push(0x45);
+        buf.push(self.tos);
+        buf.extend_from_slice(&self.total_len.to_be_bytes());
+        buf.extend_from_slice(&self.id.to_be_bytes());
+        buf.extend_from_slice(&self.flags_fragment.to_be_bytes());
+        buf.push(self.ttl);
+        buf.push(self.protocol);
+        buf.extend_from_slice(&[0, 0]);
+        buf.extend_from_slice(&self.src.octets());
+        buf.extend_from_slice(&self.dst.octets());
+        let sum = checksum(&buf[start..]);
+        buf[start + 10..start + 12].copy_from_slice(&sum.to_be_bytes());
+    }
+
+    /// Offset of the fragment in the datagram, in bytes.
+    pub fn fragment_offset(&self) -> usize {
+        usize::from(self.flags_fragment & FRAGMENT_OFFSET_MASK) * 8
+    }
+
+    /// Returns `true` if more fragments follow.
+    pub fn more_fragments(&self) -> bool {
+        self.flags_fragment & FLAG_MORE_FRAGMENTS != 0
+    }
+
+    /// Returns `true` if the datagram is a fragment of a larger one.
+    pub fn is_fragment(&self) -> bool {
+        self.more_fragments() || self.fragment_offset() != 0
+    }
+}
+
+/// Identifies the fragments of one datagram.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct FragmentKey {
+    /// Source address
+    src: Ipv4Addr,
+    /// Destination address
+    dst: Ipv4Addr,
+    /// Protocol
+    protocol: u8,
+    /// Identification
+    id: u16,
+}
+
+/// A datagram being reassembled.
+struct Reassembly {
+    /// Payload received so far
+    data: Vec<u8>,
+    /// Byte ranges of `data` that have arrived, sorted, without overlapping
+    /// or adjacent ones
+    received: Vec<(usize, usize)>,
+    /// Fragments that have arrived, duplicates included
+    fragments: usize,
+    /// Payload length, known once the last fragment arrived
+    total_len: Option<usize>,
+    /// Tick after which the fragments are dropped
+    expires: u64,
+}
+
+impl Reassembly {
+    /// Records that the bytes from `start` to `end` have arrived, merging
+    /// the range with those it overlaps or touches.
+    fn insert(&mut self, start: usize, end: usize) {
+        let first = self.received.partition_point(|&(_, stop)| stop < start);
+        let last = self.received.partition_point(|&(begin, _)| begin <= end);
+        let merged = self.received[first..last]
+            .iter()
+            .fold((start, end), |(start, end), &(begin, stop)| {
+                (start.min(begin), end.max(stop))
+            });
+        self.received.splice(first..last, [merged]);
+    }
+
+    /// Returns `true` once every byte has arrived.
+    fn is_complete(&self) -> bool {
+        let Some(total_len) = self.total_len else {
+            return false;
+        };
+        matches!(self.received[..], [(0, end)] if end >= total_len)
+    }
+}
+
+/// Sends `payload` of `protocol` to `dst`, from the address of the
+/// interface the route leads through.
+///
+/// Datagrams to an address of this machine go through the loopback
+/// interface.
+///
+/// # Errors
+///
+/// Returns [`NetError::NoRoute`] if no route leads to `dst`,
+/// [`NetError::NoAddress`] if the interface has no address,
+/// [`NetError::PacketTooLong`] if the datagram exceeds
+/// [`MAX_DATAGRAM_LEN`], and the errors of the link layer.
+pub fn send(dst: Ipv4Addr, protocol: u8, payload: &[u8]) -> Result<(), NetError> {
+    send_from(None, dst, protocol, payload)
+}
+
+/// Like [`send`], but from `src` if given rather than from the address of
+/// the interface.
+///
+/// # Errors
+///
+/// See [`send`].
+pub fn send_from(
+    src: Option<Ipv4Addr>,
+    dst: Ipv4Addr,
+    protocol: u8,
+    payload: &[u8],
+) -> Result<(), NetError> {
+    let (interface, next_hop) = next_hop(dst)?;
+    let src = match src {
+        Some(src) => src,
+        None => interface.ipv4_address().ok_or(NetError::NoAddress)?,
+    };
+    send_via(&interface, next_hop, src, dst, protocol, payload)
+}
+
+/// Returns the address [`send`] would use as source for datagrams to `dst`,
+/// e.g. for the pseudo-header of a transport checksum.
+///
+/// # Errors
+///
+/// Returns [`NetError::NoRoute`] if no route leads to `dst` and
+/// [`NetError::NoAddress`] if the interface has no address.
+pub fn source_address(dst: Ipv4Addr) -> Result<Ipv4Addr, NetError> {
+    let (interface, _) = next_hop(dst)?;
+    interface.ipv4_address().ok_or(NetError::NoAddress)
+}
+
+/// Finds the interface and neighbour for `dst`, the loopback interface for
+/// an address of this machine.
+///
+/// # Errors
+///
+/// Returns [`NetError::NoRoute`] if no route leads to `dst`.
+pub fn next_hop(dst: Ipv4Addr) -> Result<(Arc<Interface>, Ipv4Addr), NetError> {
+    match local_interface(dst) {
+        Some(_) => Ok((super::find("lo").ok_or(NetError::NoRoute)?, dst)),
+        None => route::lookup(dst).ok_or(NetError::NoRoute),
+    }
+}
+
+/// Sends `payload` of `protocol` from `src` to `dst` through `interface`
+/// to the neighbour `next_hop`, bypassing the routing table.
+///
+/// Used where the route is known or there is none yet, e.g. to broadcast
+/// on an interface without an address.
+///
+/// # Errors
+///
+/// Returns [`NetError::PacketTooLong`] if the datagram exceeds
+/// [`MAX_DATAGRAM_LEN`], [`NetError::FrameTooLong`] if the interface's MTU
+/// cannot carry a header and 8 bytes of payload, the least a fragment
+/// holds, and the errors of the link layer.
+pub fn send_via(
+    interface: &Arc<Interface>,
+    next_hop: Ipv4Addr,
+    src: Ipv4Addr,
+    dst: Ipv4Addr,
+    protocol: u8,
+    payload: &[u8],
+) -> Result<(), NetError> {
+    if HEADER_LEN + payload.len() > MAX_DATAGRAM_LEN {
+        return Err(NetError::PacketTooLong);
+    }
+    let mtu = interface.mtu();
+    if mtu < HEADER_LEN + 8 {
+        return Err(NetError::FrameTooLong);
+    }
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    // Every fragment but the last carries a multiple of 8 bytes.
+    let max_fragment = (mtu - HEADER_LEN) & !7;
+    let mut offset = 0;
+    loop {
+        let len = (payload.len() - offset).min(max_fragment);
+        let last = offset + len == payload.len();
+        let mut flags_fragment = (offset / 8) as u16;
+        if !last {
+            flags_fragment |= FLAG_MORE_FRAGMENTS;
+        } else if offset == 0 {
+            flags_fragment |= FLAG_DONT_FRAGMENT;
+        }
+        let header = Ipv4Header {
+            tos: 0,
+            total_len: (HEADER_LEN + len) as u16,
+            id,
+            flags_fragment,
+            ttl: DEFAULT_TTL,
+            protocol,
+            src,
+            dst,
+        };
+        let mut packet = Vec::with_capacity(HEADER_LEN + len);
+        header.write(&mut packet);
+        packet.extend_from_slice(&payload[offset..offset + len]);
+        arp::transmit(interface, next_hop, ETHERTYPE_IPV4, packet)?;
+        offset += len;
+        if last {
+            return Ok(());
+        }
+    }
+}
+
+/// Returns the interface that has the address `address`.
+pub fn local_interface(address: Ipv4Addr) -> Option<Arc<Interface>> {
+    super::interfaces()
+        .into_iter()
+        .find(|interface| interface.ipv4_address() == Some(address))
+}
+
+/// Returns `true` if `interface` accepts datagrams for `dst`.
+pub fn is_local(interface: &Interface, dst: Ipv4Addr) -> bool {
+    match interface.ipv4() {
+        None => true,
+        // Only the stack itself sends on the loopback interface.
+        Some(_) if interface.is_loopback() => true,
+        Some(cidr) => dst == cidr.address() || dst == cidr.broadcast() || dst.is_broadcast(),
+    }
+}
+
+/// Starts the timer that drops incomplete datagrams.
+pub(super) fn init() {
+    NEXT_ID.store(crate::rand::next_u32() as u16, Ordering::Relaxed);
+    Timer::periodic(REASSEMBLY_TIMEOUT / 2, || {
+        let now = timer::ticks();
+        REASSEMBLY.lock().retain(|_, r| r.expires > now);
+    });
+}
+
+/// Handles a received datagram.
+///
+/// # Returns
+///
+/// `false` if the datagram is malformed, not for this interface, or of a
+/// protocol that is not supported.
+pub(super) fn receive(interface: &Arc<Interface>, data: &[u8]) -> bool {
+    let Some((header, payload)) = Ipv4Header::parse(data) else {
+        return false;
+    };
+    if !is_local(interface, header.dst) {
+        return false;
+    }
+    if !header.is_fragment() {
+        return deliver(interface, &header, payload);
+    }
+    match reassemble(&header, payload) {
+        Some(datagram) => deliver(interface, &header, &datagram),
+        None => true,
+    }
+}
+
+/// Adds a fragment to its datagram.
+///
+/// # Returns
+///
+/// The payload of the whole datagram once the fragment completes it.
+fn reassemble(header: &Ipv4Header, payload: &[u8]) -> Option<Vec<u8>> {
+    let key = FragmentKey {
+        src: header.src,
+        dst: header.dst,
+        protocol: header.protocol,
+        id: header.id,
+    };
+    let start = header.fragment_offset();
+    let end = start + payload.len();
+    if end > MAX_DATAGRAM_LEN - HEADER_LEN {
+        return None;
+    }
+
+    let mut table = REASSEMBLY.lock();
+    if !table.contains_key(&key) && table.len() >= MAX_REASSEMBLIES {
+        return None;
+    }
+    let expires = timer::ticks() + timer::duration_to_ticks(REASSEMBLY_TIMEOUT);
+    let entry = table.entry(key).or_insert_with(|| Reassembly {
+        data: Vec::new(),
+        received: Vec::new(),
+        fragments: 0,
+        total_len: None,
+        expires,
+    });
+    entry.fragments += 1;
+    if entry.fragments > MAX_FRAGMENTS {
+        table.remove(&key);
+        return None;
+    }
+    if entry.data.len() < end {
+        entry.data.resize(end, 0);
+    }
+    entry.data[start..end].copy_from_slice(payload);
+    entry.insert(start, end);
+    if !header.more_fragments() {
+        entry.total_len = Some(end);
+    }
+    if !entry.is_complete() {
+        return None;
+    }
+    let mut entry = table.remove(&key)?;
+    entry.data.truncate(entry.total_len?);
+    Some(entry.data)
+}
+
+/// Passes a complete datagram to its protocol.
+fn deliver(interface: &Arc<Interface>, header: &Ipv4Header, payload: &[u8]) -> bool {
+    match header.protocol {
+        PROTOCOL_ICMP => icmp::receive(interface, header, payload),
+        PROTOCOL_TCP => tcp::receive(interface, header.src.into(), header.dst.into(), payload),
+        PROTOCOL_UDP => udp::receive(interface, header.src.into(), header.dst.into(), payload),
+        _ => false,
+    }
+}
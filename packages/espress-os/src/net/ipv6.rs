@@ -0,0 +1,424 @@
+//! # IPv6
+//!
+//! Sends and receives IPv6 packets (RFC 8200). Every Ethernet interface
+//! gets a link-local address derived from its hardware address as soon as
+//! the stack runs, and global addresses from the prefixes routers announce
+//! (see [`ndp`](super::ndp)); the loopback interface has `::1`.
+//!
+//! Outgoing packets are sent straight to destinations on a prefix of an
+//! interface, to link-local and multicast destinations on the first
+//! Ethernet interface (addresses carry no zone here), and to a default
+//! router otherwise. Hardware addresses of neighbours are found with
+//! neighbour discovery.
+//!
+//! Incoming packets are accepted for the addresses of the interface and
+//! for every multicast group. Hop-by-hop and destination options are
+//! skipped; fragments are dropped, and packets larger than the MTU are
+//! never sent, since the stack neither fragments nor reassembles.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::net::Ipv6Addr;
+
+use super::ethernet::ETHERTYPE_IPV6;
+use super::ipv4::{PROTOCOL_TCP, PROTOCOL_UDP};
+use super::{icmpv6, ndp, tcp, udp, Interface, MacAddress, NetError};
+
+/// Size of the fixed header.
+pub const HEADER_LEN: usize = 40;
+
+/// Largest payload, extension headers included.
+pub const MAX_PAYLOAD_LEN: usize = 65535;
+
+/// Hop limit of outgoing packets.
+pub const DEFAULT_HOP_LIMIT: u8 = 64;
+
+/// Next header: hop-by-hop options.
+pub const NEXT_HEADER_HOP_BY_HOP: u8 = 0;
+/// Next header: ICMPv6.
+pub const NEXT_HEADER_ICMPV6: u8 = 58;
+/// Next header: destination options.
+pub const NEXT_HEADER_DEST_OPTIONS: u8 = 60;
+
+/// The group of every node on the link, `ff02::1`.
+pub const ALL_NODES: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+
+/// The group of every router on the link, `ff02::2`.
+pub const ALL_ROUTERS: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 2);
+
+/// An address with the length of its prefix, e.g. `fe80::1/64`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Ipv6Cidr {
+    /// The address
+    address: Ipv6Addr,
+    /// Bits of the prefix, 0 to 128
+    prefix_len: u8,
+}
+
+impl Ipv6Cidr {
+    /// Pairs `address` with a prefix length.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prefix_len` is larger than 128.
+    pub const fn new(address: Ipv6Addr, prefix_len: u8) -> Self {
+        assert!(prefix_len <= 128, "IPv6 prefix longer than 128 bits");
+        Ipv6Cidr {
+            address,
+            prefix_len,
+        }
+    }
+
+    /// The address.
+    pub fn address(&self) -> Ipv6Addr {
+        self.address
+    }
+
+    /// Bits of the prefix.
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// The prefix with the interface identifier cleared.
+    pub fn network(&self) -> Ipv6Cidr {
+        Self::new(
+            Ipv6Addr::from(u128::from(self.address) & self.mask()),
+            self.prefix_len,
+        )
+    }
+
+    /// Returns `true` if `address` has the prefix.
+    pub fn contains(&self, address: Ipv6Addr) -> bool {
+        (u128::from(address) ^ u128::from(self.address)) & self.mask() == 0
+    }
+
+    /// The prefix mask as a number.
+    fn mask(&self) -> u128 {
+        u128::MAX
+            .checked_shl(128 - u32::from(self.prefix_len))
+            .unwrap_or(0)
+    }
+}
+
+impl fmt::Display for Ipv6Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_len)
+    }
+}
+
+/// Fixed header of an IPv6 packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv6Header {
+    /// Traffic class
+    pub traffic_class: u8,
+    /// Flow label, 20 bits
+    pub flow_label: u32,
+    /// Length of everything after the fixed header
+    pub payload_len: u16,
+    /// Type of the header after this one
+    pub next_header: u8,
+    /// Hops left
+    pub hop_limit: u8,
+    /// Source address
+    pub src: Ipv6Addr,
+    /// Destination address
+    pub dst: Ipv6Addr,
+}
+
+impl Ipv6Header {
+    /// Splits `data` into header and payload, checking the version and
+    /// length. Trailing padding of the frame is cut off.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the packet is malformed.
+    pub fn parse(data: &[u8]) -> Option<(Self, &[u8])> {
+        if data.len() < HEADER_LEN || data[0] >> 4 != 6 {
+            return None;
+        }
+        let first = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        let payload_len = u16::from_be_bytes([data[4], data[5]]);
+        let end = HEADER_LEN + usize::from(payload_len);
+        if end > data.len() {
+            return None;
+        }
+        let ip = |at: usize| {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(&data[at..at + 16]);
+            Ipv6Addr::from(octets)
+        };
+        let header = Ipv6Header {
+            traffic_class: (first >> 20) as u8,
+            flow_label: first & 0xfffff,
+            payload_len,
+            next_header: data[6],
+            hop_limit: data[7],
+            src: ip(8),
+            dst: ip(24),
+        };
+        Some((header, &data[HEADER_LEN..end]))
+    }
+
+    /// Appends the header to `buf`.
+    pub fn write(&self, buf: &mut Vec<u8>) {
+        let first = 6 << 28 | u32::from(self.traffic_class) << 20 | self.flow_label & 0xfffff;
+        buf.extend_from_slice(&first.to_be_bytes());
+        buf.extend_from_slice(&self.payload_len.to_be_bytes());
+        buf.push(self.next_header);
+        buf.push(self.hop_limit);
+        buf.extend_from_slice(&self.src.octets());
+        buf.extend_from_slice(&self.dst.octets());
+    }
+}
+
+/// The modified EUI-64 interface identifier of `mac` (RFC 4291), the
+/// lower half of the addresses the interface configures itself.
+pub fn interface_id(mac: MacAddress) -> [u8; 8] {
+    let [a, b, c, d, e, f] = mac.0;
+    [a ^ 0x02, b, c, 0xff, 0xfe, d, e, f]
+}
+
+/// The link-local address of an interface with hardware address `mac`.
+pub fn link_local(mac: MacAddress) -> Ipv6Addr {
+    let mut octets = [0; 16];
+    octets[..2].copy_from_slice(&[0xfe, 0x80]);
+    octets[8..].copy_from_slice(&interface_id(mac));
+    Ipv6Addr::from(octets)
+}
+
+/// The solicited-node group of `address`, which neighbour solicitations
+/// for it are sent to.
+pub fn solicited_node(address: Ipv6Addr) -> Ipv6Addr {
+    let octets = address.octets();
+    Ipv6Addr::from([
+        0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, 0xff, octets[13], octets[14], octets[15],
+    ])
+}
+
+/// The Ethernet group address that carries the multicast group `address`.
+pub fn multicast_mac(address: Ipv6Addr) -> MacAddress {
+    let octets = address.octets();
+    MacAddress([0x33, 0x33, octets[12], octets[13], octets[14], octets[15]])
+}
+
+/// Returns `true` if `address` is only meaningful on one link: a
+/// link-local address or a link-local multicast group.
+pub fn is_link_scope(address: Ipv6Addr) -> bool {
+    address.is_unicast_link_local() || address.segments()[0] & 0xff0f == 0xff02
+}
+
+/// Sends `payload` of protocol `next_header` to `dst`, from an address of
+/// the interface it leaves through.
+///
+/// Packets to an address of this machine go through the loopback
+/// interface.
+///
+/// # Errors
+///
+/// Returns [`NetError::NoRoute`] if no route leads to `dst`,
+/// [`NetError::NoAddress`] if the interface has no address,
+/// [`NetError::PacketTooLong`] if the packet does not fit the MTU, and
+/// the errors of the link layer.
+pub fn send(dst: Ipv6Addr, next_header: u8, payload: &[u8]) -> Result<(), NetError> {
+    send_from(None, dst, next_header, payload)
+}
+
+/// Like [`send`], but from `src` if given rather than from the address
+/// [`select_source`] picks.
+///
+/// # Errors
+///
+/// See [`send`].
+pub fn send_from(
+    src: Option<Ipv6Addr>,
+    dst: Ipv6Addr,
+    next_header: u8,
+    payload: &[u8],
+) -> Result<(), NetError> {
+    let (interface, next_hop) = next_hop(dst)?;
+    let src = match src {
+        Some(src) => src,
+        None => select_source(&interface, dst).ok_or(NetError::NoAddress)?,
+    };
+    send_via(&interface, next_hop, src, dst, next_header, payload)
+}
+
+/// Returns the address [`send`] would use as source for packets to `dst`.
+///
+/// # Errors
+///
+/// Returns [`NetError::NoRoute`] if no route leads to `dst` and
+/// [`NetError::NoAddress`] if the interface has no address.
+pub fn source_address(dst: Ipv6Addr) -> Result<Ipv6Addr, NetError> {
+    let (interface, _) = next_hop(dst)?;
+    select_source(&interface, dst).ok_or(NetError::NoAddress)
+}
+
+/// Picks the address of `interface` to send to `dst` from: `dst` itself
+/// if it is an address of this machine, the link-local address for
+/// destinations on the link, and a global address otherwise, if there is
+/// one.
+pub fn select_source(interface: &Interface, dst: Ipv6Addr) -> Option<Ipv6Addr> {
+    if local_interface(dst).is_some() {
+        return Some(dst);
+    }
+    let addresses = interface.ipv6();
+    let link_local = addresses
+        .iter()
+        .map(|cidr| cidr.address())
+        .find(|address| address.is_unicast_link_local());
+    if is_link_scope(dst) {
+        return link_local;
+    }
+    addresses
+        .iter()
+        .map(|cidr| cidr.address())
+        .find(|address| !address.is_unicast_link_local())
+        .or(link_local)
+}
+
+/// Finds the interface and neighbour for `dst`, the loopback interface for
+/// an address of this machine.
+///
+/// # Errors
+///
+/// Returns [`NetError::NoRoute`] if no route leads to `dst`.
+pub fn next_hop(dst: Ipv6Addr) -> Result<(Arc<Interface>, Ipv6Addr), NetError> {
+    if local_interface(dst).is_some() {
+        return Ok((super::find("lo").ok_or(NetError::NoRoute)?, dst));
+    }
+    let interfaces = super::interfaces();
+    let mut links = interfaces.iter().filter(|i| !i.is_loopback());
+    if is_link_scope(dst) {
+        return links
+            .next()
+            .map(|interface| (interface.clone(), dst))
+            .ok_or(NetError::NoRoute);
+    }
+    let on_link = links.find(|interface| {
+        interface
+            .ipv6()
+            .iter()
+            .any(|cidr| !cidr.address().is_unicast_link_local() && cidr.contains(dst))
+    });
+    match on_link {
+        Some(interface) => Ok((interface.clone(), dst)),
+        None => ndp::default_router().ok_or(NetError::NoRoute),
+    }
+}
+
+/// Sends `payload` of protocol `next_header` from `src` to `dst` through
+/// `interface` to the neighbour `next_hop`, bypassing the routing.
+///
+/// # Errors
+///
+/// Returns [`NetError::PacketTooLong`] if the packet does not fit the MTU
+/// of the interface, and the errors of the link layer.
+pub fn send_via(
+    interface: &Arc<Interface>,
+    next_hop: Ipv6Addr,
+    src: Ipv6Addr,
+    dst: Ipv6Addr,
+    next_header: u8,
+    payload: &[u8],
+) -> Result<(), NetError> {
+    send_with_hop_limit(
+        interface,
+        next_hop,
+        src,
+        dst,
+        next_header,
+        DEFAULT_HOP_LIMIT,
+        payload,
+    )
+}
+
+/// Like [`send_via`], with the hop limit `hop_limit`; neighbour discovery
+/// sends with 255 to prove that its messages never crossed a router.
+///
+/// # Errors
+///
+/// See [`send_via`].
+pub(super) fn send_with_hop_limit(
+    interface: &Arc<Interface>,
+    next_hop: Ipv6Addr,
+    src: Ipv6Addr,
+    dst: Ipv6Addr,
+    next_header: u8,
+    hop_limit: u8,
+    payload: &[u8],
+) -> Result<(), NetError> {
+    if payload.len() > MAX_PAYLOAD_LEN || HEADER_LEN + payload.len() > interface.mtu() {
+        return Err(NetError::PacketTooLong);
+    }
+    let header = Ipv6Header {
+        traffic_class: 0,
+        flow_label: 0,
+        payload_len: payload.len() as u16,
+        next_header,
+        hop_limit,
+        src,
+        dst,
+    };
+    let mut packet = Vec::with_capacity(HEADER_LEN + payload.len());
+    header.write(&mut packet);
+    packet.extend_from_slice(payload);
+    ndp::transmit(interface, next_hop, ETHERTYPE_IPV6, packet)
+}
+
+/// Returns the interface that has the address `address`.
+pub fn local_interface(address: Ipv6Addr) -> Option<Arc<Interface>> {
+    super::interfaces().into_iter().find(|interface| {
+        interface
+            .ipv6()
+            .iter()
+            .any(|cidr| cidr.address() == address)
+    })
+}
+
+/// Returns `true` if `interface` accepts packets for `dst`.
+pub fn is_local(interface: &Interface, dst: Ipv6Addr) -> bool {
+    // Only the stack itself sends on the loopback interface.
+    interface.is_loopback()
+        || dst.is_multicast()
+        || interface.ipv6().iter().any(|cidr| cidr.address() == dst)
+}
+
+/// Handles a received packet.
+///
+/// # Returns
+///
+/// `false` if the packet is malformed, not for this interface, or of a
+/// protocol that is not supported.
+pub(super) fn receive(interface: &Arc<Interface>, data: &[u8]) -> bool {
+    let Some((header, mut payload)) = Ipv6Header::parse(data) else {
+        return false;
+    };
+    if !is_local(interface, header.dst) {
+        return false;
+    }
+    let mut next_header = header.next_header;
+    while matches!(
+        next_header,
+        NEXT_HEADER_HOP_BY_HOP | NEXT_HEADER_DEST_OPTIONS
+    ) {
+        // Options headers give their length in units of 8 bytes, not
+        // counting the first 8.
+        let Some(&[next, len]) = payload.get(..2) else {
+            return false;
+        };
+        let len = (usize::from(len) + 1) * 8;
+        if len > payload.len() {
+            return false;
+        }
+        next_header = next;
+        payload = &payload[len..];
+    }
+    match next_header {
+        NEXT_HEADER_ICMPV6 => icmpv6::receive(interface, &header, payload),
+        PROTOCOL_TCP => tcp::receive(interface, header.src.into(), header.dst.into(), payload),
+        PROTOCOL_UDP => udp::receive(interface, header.src.into(), header.dst.into(), payload),
+        _ => false,
+    }
+}
@@ -0,0 +1,57 @@
+//! # Loopback Device
+//!
+//! The `lo` interface: every frame sent on it is received on it again, so
+//! the stack can talk to itself without any hardware. It has the addresses
+//! `127.0.0.1/8` and `::1/128`.
+
+use alloc::sync::{Arc, Weak};
+use conquer_once::spin::OnceCell;
+use core::net::{Ipv4Addr, Ipv6Addr};
+
+use super::ipv4::Ipv4Cidr;
+use super::ipv6::Ipv6Cidr;
+use super::{Interface, MacAddress, NetDevice, NetError};
+
+/// Largest payload, as on Linux.
+pub const MTU: usize = 65536;
+
+/// The loopback device.
+struct Loopback {
+    /// Its own interface, set right after registration
+    interface: OnceCell<Weak<Interface>>,
+}
+
+impl NetDevice for Loopback {
+    fn mac_address(&self) -> MacAddress {
+        MacAddress::ZERO
+    }
+
+    fn mtu(&self) -> usize {
+        MTU
+    }
+
+    fn is_loopback(&self) -> bool {
+        true
+    }
+
+    fn transmit(&self, frame: &[u8]) -> Result<(), NetError> {
+        let interface = self
+            .interface
+            .get()
+            .and_then(Weak::upgrade)
+            .ok_or(NetError::Device)?;
+        interface.receive(frame.to_vec());
+        Ok(())
+    }
+}
+
+/// Registers the loopback interface.
+pub(super) fn init() {
+    let device = Arc::new(Loopback {
+        interface: OnceCell::uninit(),
+    });
+    let interface = super::register(device.clone());
+    device.interface.init_once(|| Arc::downgrade(&interface));
+    interface.set_ipv4(Some(Ipv4Cidr::new(Ipv4Addr::LOCALHOST, 8)));
+    interface.add_ipv6(Ipv6Cidr::new(Ipv6Addr::LOCALHOST, 128));
+}
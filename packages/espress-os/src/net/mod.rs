@@ -0,0 +1,513 @@
+//! # Networking
+//!
+//! The network stack, independent of any particular network card. Drivers
+//! implement [`NetDevice`] and [`register`] their devices, which become
+//! [`Interface`]s: `lo` for the loopback device, `eth0`, `eth1`, ... for
+//! everything else, in registration order.
+//!
+//! Every device exchanges Ethernet frames. Drivers hand received frames to
+//! [`Interface::receive`], which may be called from any context; the
+//! frames are queued and passed up the protocol layers by the `netrx`
+//! thread, so protocol code always runs in thread context. Outgoing frames
+//! go down through [`Interface::transmit`].
+//!
+//! Every interface counts the packets and bytes it moves, see
+//! [`Interface::stats`], and the frames can be captured for Wireshark
+//! with [`netdump`]. Once the stack is running, Ethernet interfaces
+//! configure IPv4 over [`dhcp`] and IPv6 from router advertisements (see
+//! [`ndp`]); sockets speak both versions.
+
+pub mod arp;
+pub mod checksum;
+pub mod dhcp;
+pub mod ethernet;
+#[cfg(feature = "http-server")]
+pub mod http;
+pub mod icmp;
+pub mod icmpv6;
+pub mod ip;
+pub mod ipv4;
+pub mod ipv6;
+pub mod loopback;
+pub mod ndp;
+pub mod netdump;
+pub mod route;
+pub mod sntp;
+pub mod socket;
+pub mod tcp;
+pub mod tftp;
+pub mod udp;
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use core::fmt;
+use core::net::{Ipv4Addr, Ipv6Addr};
+use core::ops::RangeInclusive;
+use core::sync::atomic::{AtomicU64, Ordering};
+use crossbeam_queue::ArrayQueue;
+use spin::Mutex;
+
+use ipv4::Ipv4Cidr;
+use ipv6::Ipv6Cidr;
+use route::Route;
+
+use crate::scheduler::{self, WaitQueue};
+use crate::{println, rand};
+
+/// Size of the Ethernet header that precedes the payload of every frame.
+pub const LINK_HEADER_LEN: usize = 14;
+
+/// Local ports handed out to UDP sockets bound to port 0 and to outgoing
+/// TCP connections.
+pub const EPHEMERAL_PORTS: RangeInclusive<u16> = 49152..=65535;
+
+/// Received frames waiting for the `netrx` thread.
+const RX_QUEUE_LEN: usize = 256;
+
+/// Registered interfaces, in registration order.
+static INTERFACES: Mutex<Vec<Arc<Interface>>> = Mutex::new(Vec::new());
+
+/// Received frames, oldest first.
+static RX_QUEUE: OnceCell<ArrayQueue<(Arc<Interface>, Vec<u8>)>> = OnceCell::uninit();
+
+/// Where the `netrx` thread waits for frames.
+static RX_READY: WaitQueue = WaitQueue::new();
+
+/// Errors that can occur in the network stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetError {
+    /// The frame is larger than the interface's MTU allows
+    FrameTooLong,
+    /// The device has no room for the frame right now
+    NoBuffer,
+    /// The device reported an error
+    Device,
+    /// No route leads to the destination
+    NoRoute,
+    /// The interface has no address to send from
+    NoAddress,
+    /// The packet is larger than the protocol allows
+    PacketTooLong,
+    /// No answer arrived in time
+    Timeout,
+    /// The port is already bound
+    AddressInUse,
+    /// The peer refused the connection
+    ConnectionRefused,
+    /// The peer reset the connection
+    ConnectionReset,
+    /// The socket is not connected, or no longer
+    NotConnected,
+    /// The server has no such file
+    NotFound,
+    /// The peer rejected the request
+    Rejected,
+    /// The socket is connected already
+    AlreadyConnected,
+    /// The socket is in the wrong state for the operation
+    InvalidState,
+    /// The operation does not apply to the kind of socket
+    NotSupported,
+}
+
+/// A 48-bit Ethernet hardware address.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl MacAddress {
+    /// The address of every station.
+    pub const BROADCAST: MacAddress = MacAddress([0xff; 6]);
+
+    /// The all-zero address.
+    pub const ZERO: MacAddress = MacAddress([0; 6]);
+
+    /// Returns `true` for the broadcast address.
+    pub fn is_broadcast(&self) -> bool {
+        *self == Self::BROADCAST
+    }
+
+    /// Returns `true` for group addresses, including broadcast.
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 1 != 0
+    }
+}
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}")
+    }
+}
+
+/// A network card, or anything else that sends and receives Ethernet
+/// frames.
+pub trait NetDevice: Send + Sync {
+    /// Hardware address of the device.
+    fn mac_address(&self) -> MacAddress;
+
+    /// Largest payload of a frame, excluding the Ethernet header.
+    fn mtu(&self) -> usize;
+
+    /// Returns `true` for the loopback device.
+    fn is_loopback(&self) -> bool {
+        false
+    }
+
+    /// Sends `frame`, a complete Ethernet frame without the checksum.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NetError::NoBuffer`] if the device is busy and
+    /// [`NetError::Device`] if it fails.
+    fn transmit(&self, frame: &[u8]) -> Result<(), NetError>;
+}
+
+/// Counters of an interface.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InterfaceStats {
+    /// Frames received
+    pub rx_packets: u64,
+    /// Bytes received
+    pub rx_bytes: u64,
+    /// Received frames that were dropped, for lack of room or of a protocol
+    pub rx_dropped: u64,
+    /// Frames sent
+    pub tx_packets: u64,
+    /// Bytes sent
+    pub tx_bytes: u64,
+    /// Frames the device failed to send
+    pub tx_errors: u64,
+}
+
+/// Live counters of an interface.
+#[derive(Default)]
+struct Counters {
+    /// See [`InterfaceStats::rx_packets`]
+    rx_packets: AtomicU64,
+    /// See [`InterfaceStats::rx_bytes`]
+    rx_bytes: AtomicU64,
+    /// See [`InterfaceStats::rx_dropped`]
+    rx_dropped: AtomicU64,
+    /// See [`InterfaceStats::tx_packets`]
+    tx_packets: AtomicU64,
+    /// See [`InterfaceStats::tx_bytes`]
+    tx_bytes: AtomicU64,
+    /// See [`InterfaceStats::tx_errors`]
+    tx_errors: AtomicU64,
+}
+
+/// A registered network device.
+pub struct Interface {
+    /// Position in the registry
+    index: usize,
+    /// Interface name, e.g. `"eth0"`
+    name: String,
+    /// The device
+    device: Arc<dyn NetDevice>,
+    /// Counters
+    counters: Counters,
+    /// IPv4 address and network, once assigned
+    ipv4: Mutex<Option<Ipv4Cidr>>,
+    /// IPv6 addresses with their prefixes, link-local first
+    ipv6: Mutex<Vec<Ipv6Cidr>>,
+}
+
+impl Interface {
+    /// Position in the registry, unique for the lifetime of the kernel.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Interface name, e.g. `"eth0"` or `"lo"`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The device.
+    pub fn device(&self) -> &Arc<dyn NetDevice> {
+        &self.device
+    }
+
+    /// Hardware address of the device.
+    pub fn mac_address(&self) -> MacAddress {
+        self.device.mac_address()
+    }
+
+    /// Largest payload of a frame.
+    pub fn mtu(&self) -> usize {
+        self.device.mtu()
+    }
+
+    /// Returns `true` for the loopback interface.
+    pub fn is_loopback(&self) -> bool {
+        self.device.is_loopback()
+    }
+
+    /// IPv4 address and network of the interface, if one is assigned.
+    pub fn ipv4(&self) -> Option<Ipv4Cidr> {
+        *self.ipv4.lock()
+    }
+
+    /// IPv4 address of the interface, if one is assigned.
+    pub fn ipv4_address(&self) -> Option<Ipv4Addr> {
+        self.ipv4().map(|cidr| cidr.address())
+    }
+
+    /// Assigns `address` to the interface, or removes the address with
+    /// `None`.
+    ///
+    /// The route to the network of the old address is replaced by one to
+    /// the new network; removing the address removes every route through
+    /// the interface. A new address is announced with a gratuitous ARP
+    /// request, and the ARP cache of the interface is flushed.
+    pub fn set_ipv4(self: &Arc<Self>, address: Option<Ipv4Cidr>) {
+        let old = core::mem::replace(&mut *self.ipv4.lock(), address);
+        match (old, address) {
+            (_, None) => route::remove_interface(self),
+            (old, Some(new)) => {
+                if let Some(old) = old {
+                    route::remove(old.network());
+                }
+                route::add(Route {
+                    destination: new.network(),
+                    gateway: None,
+                    interface: self.clone(),
+                });
+            }
+        }
+        arp::flush(self);
+        if address.is_some() && !self.is_loopback() {
+            let _ = arp::announce(self);
+        }
+    }
+
+    /// IPv6 addresses of the interface with their prefixes, link-local
+    /// first.
+    pub fn ipv6(&self) -> Vec<Ipv6Cidr> {
+        self.ipv6.lock().clone()
+    }
+
+    /// Adds `address` to the interface, replacing its prefix length if the
+    /// address is assigned already.
+    pub fn add_ipv6(&self, address: Ipv6Cidr) {
+        let mut addresses = self.ipv6.lock();
+        addresses.retain(|cidr| cidr.address() != address.address());
+        addresses.push(address);
+        addresses.sort_by_key(|cidr| !cidr.address().is_unicast_link_local());
+    }
+
+    /// Removes the IPv6 address `address` from the interface.
+    ///
+    /// # Returns
+    ///
+    /// `false` if the interface did not have it.
+    pub fn remove_ipv6(&self, address: Ipv6Addr) -> bool {
+        let mut addresses = self.ipv6.lock();
+        let len = addresses.len();
+        addresses.retain(|cidr| cidr.address() != address);
+        addresses.len() != len
+    }
+
+    /// Returns the counters.
+    pub fn stats(&self) -> InterfaceStats {
+        let c = &self.counters;
+        InterfaceStats {
+            rx_packets: c.rx_packets.load(Ordering::Relaxed),
+            rx_bytes: c.rx_bytes.load(Ordering::Relaxed),
+            rx_dropped: c.rx_dropped.load(Ordering::Relaxed),
+            tx_packets: c.tx_packets.load(Ordering::Relaxed),
+            tx_bytes: c.tx_bytes.load(Ordering::Relaxed),
+            tx_errors: c.tx_errors.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Sends a complete Ethernet frame.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NetError::FrameTooLong`] if the payload exceeds the MTU,
+    /// and the errors of [`NetDevice::transmit`].
+    pub fn transmit(&self, frame: &[u8]) -> Result<(), NetError> {
+        if frame.len() > LINK_HEADER_LEN + self.mtu() {
+            return Err(NetError::FrameTooLong);
+        }
+        match self.device.transmit(frame) {
+            Ok(()) => {
+                netdump::capture(self, frame);
+                self.counters.tx_packets.fetch_add(1, Ordering::Relaxed);
+                self.counters
+                    .tx_bytes
+                    .fetch_add(frame.len() as u64, Ordering::Relaxed);
+                Ok(())
+            }
+            Err(err) => {
+                self.counters.tx_errors.fetch_add(1, Ordering::Relaxed);
+                Err(err)
+            }
+        }
+    }
+
+    /// Queues a received frame for the protocol layers.
+    ///
+    /// Called by drivers from any context; does not block. The frame is
+    /// dropped if the queue is full or the stack is not initialized.
+    pub fn receive(self: &Arc<Self>, frame: Vec<u8>) {
+        self.counters.rx_packets.fetch_add(1, Ordering::Relaxed);
+        self.counters
+            .rx_bytes
+            .fetch_add(frame.len() as u64, Ordering::Relaxed);
+        let queued = RX_QUEUE
+            .try_get()
+            .map(|queue| queue.push((self.clone(), frame)).is_ok())
+            .unwrap_or(false);
+        if queued {
+            RX_READY.notify_one();
+        } else {
+            self.count_dropped();
+        }
+    }
+
+    /// Counts a received frame that was not used.
+    fn count_dropped(&self) {
+        self.counters.rx_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl fmt::Debug for Interface {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Interface")
+            .field("index", &self.index)
+            .field("name", &self.name)
+            .field("mac_address", &self.mac_address())
+            .field("mtu", &self.mtu())
+            .field("ipv4", &self.ipv4())
+            .field("ipv6", &self.ipv6())
+            .finish()
+    }
+}
+
+/// Makes `device` available as an interface.
+///
+/// # Returns
+///
+/// The interface, through which the driver passes up received frames.
+pub fn register(device: Arc<dyn NetDevice>) -> Arc<Interface> {
+    let mut interfaces = INTERFACES.lock();
+    let name = if device.is_loopback() {
+        String::from("lo")
+    } else {
+        let ethernet = interfaces.iter().filter(|i| !i.is_loopback()).count();
+        alloc::format!("eth{ethernet}")
+    };
+    let interface = Arc::new(Interface {
+        index: interfaces.len(),
+        name,
+        device,
+        counters: Counters::default(),
+        ipv4: Mutex::new(None),
+        ipv6: Mutex::new(Vec::new()),
+    });
+    interfaces.push(interface.clone());
+    drop(interfaces);
+    if !interface.is_loopback() && RX_QUEUE.is_initialized() {
+        configure(&interface);
+    }
+    interface
+}
+
+/// Returns every registered interface.
+pub fn interfaces() -> Vec<Arc<Interface>> {
+    INTERFACES.lock().clone()
+}
+
+/// Returns the interface called `name`.
+pub fn find(name: &str) -> Option<Arc<Interface>> {
+    INTERFACES.lock().iter().find(|i| i.name() == name).cloned()
+}
+
+/// Prints every interface with its address and counters.
+pub fn print_interfaces() {
+    for interface in interfaces() {
+        let stats = interface.stats();
+        println!(
+            "  {:<6} {}  mtu {:<5}  rx {} ({} bytes, {} dropped)  tx {} ({} bytes, {} errors)",
+            interface.name(),
+            interface.mac_address(),
+            interface.mtu(),
+            stats.rx_packets,
+            stats.rx_bytes,
+            stats.rx_dropped,
+            stats.tx_packets,
+            stats.tx_bytes,
+            stats.tx_errors
+        );
+    }
+}
+
+/// Picks a random ephemeral port for which `in_use` returns `false`.
+pub(crate) fn ephemeral_port(in_use: impl Fn(u16) -> bool) -> Option<u16> {
+    let first = *EPHEMERAL_PORTS.start();
+    let count = u32::from(EPHEMERAL_PORTS.end() - first) + 1;
+    let start = rand::next_u32() % count;
+    (0..count)
+        .map(|i| first + ((start + i) % count) as u16)
+        .find(|&port| !in_use(port))
+}
+
+/// Sets up the receive queue, registers the loopback interface, and starts
+/// the `netrx` thread, the protocol timers, and address configuration on
+/// the interfaces registered so far.
+///
+/// Must be called after the scheduler is initialized.
+///
+/// # Panics
+///
+/// Panics if called twice or if the thread cannot be started.
+pub fn init() {
+    RX_QUEUE
+        .try_init_once(|| ArrayQueue::new(RX_QUEUE_LEN))
+        .expect("net::init called twice");
+    arp::init();
+    ipv4::init();
+    ndp::init();
+    tcp::init();
+    loopback::init();
+    scheduler::spawn_named("netrx", run).expect("failed to start the netrx thread");
+    for interface in interfaces() {
+        if !interface.is_loopback() {
+            configure(&interface);
+        }
+    }
+}
+
+/// Starts configuring the addresses of an Ethernet interface: IPv4 over
+/// DHCP, and IPv6 from its link-local address and router advertisements.
+fn configure(interface: &Arc<Interface>) {
+    let _ = dhcp::start(interface);
+    ndp::start(interface);
+}
+
+/// Body of the `netrx` thread: passes received frames up the stack.
+fn run() {
+    let queue = RX_QUEUE.try_get().expect("net not initialized");
+    loop {
+        let mut next = None;
+        RX_READY.wait_until(|| {
+            next = queue.pop();
+            next.is_some()
+        });
+        if let Some((interface, frame)) = next {
+            netdump::capture(&interface, &frame);
+            deliver(&interface, &frame);
+        }
+    }
+}
+
+/// Hands a received frame to the protocol it carries.
+fn deliver(interface: &Arc<Interface>, frame: &[u8]) {
+    if !ethernet::receive(interface, frame) {
+        interface.count_dropped();
+    }
+}
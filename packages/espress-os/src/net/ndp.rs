@@ -0,0 +1,614 @@
+//! # Neighbour Discovery
+//!
+//! What ARP does for IPv4, and more (RFC 4861): finds the hardware
+//! address behind the IPv6 address of a neighbour, answers solicitations
+//! for the addresses of this machine, and learns default routers from
+//! router advertisements. The addresses of an interface are configured
+//! from the prefixes routers advertise, without a server (SLAAC,
+//! RFC 4862): the advertised /64 prefix followed by the interface
+//! identifier of the hardware address.
+//!
+//! As with ARP, packets for a neighbour that is not resolved yet are held
+//! back by [`transmit`] while solicitations go out every
+//! [`RETRANS_TIMER`]; after [`MAX_MULTICAST_SOLICIT`] the held packets
+//! are dropped. Answers are trusted for [`REACHABLE_TIME`].
+//!
+//! Every interface solicits routers when it starts, up to
+//! [`MAX_RTR_SOLICITATIONS`] times. Advertised prefixes are assumed to be
+//! on-link, redirects are ignored, and duplicate address detection is not
+//! performed: an address is used as soon as it is configured.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::net::Ipv6Addr;
+use core::time::Duration;
+use spin::Mutex;
+
+use super::ethernet;
+use super::icmpv6::{
+    self, TYPE_NEIGHBOR_ADVERTISEMENT, TYPE_NEIGHBOR_SOLICITATION, TYPE_ROUTER_ADVERTISEMENT,
+    TYPE_ROUTER_SOLICITATION,
+};
+use super::ipv6::{self, Ipv6Cidr, Ipv6Header, NEXT_HEADER_ICMPV6};
+use super::{Interface, MacAddress, NetError};
+use crate::println;
+use crate::timer::{self, Timer};
+
+/// How long a resolved address is trusted.
+pub const REACHABLE_TIME: Duration = Duration::from_secs(30);
+
+/// Time between solicitations for an unresolved address.
+pub const RETRANS_TIMER: Duration = Duration::from_secs(1);
+
+/// Solicitations sent before an address is given up.
+pub const MAX_MULTICAST_SOLICIT: u32 = 3;
+
+/// Router solicitations an interface sends when it starts.
+pub const MAX_RTR_SOLICITATIONS: u32 = 3;
+
+/// Time between router solicitations.
+pub const RTR_SOLICITATION_INTERVAL: Duration = Duration::from_secs(4);
+
+/// Packets held back per unresolved address; older ones are dropped.
+const MAX_HELD: usize = 8;
+
+/// Hop limit of every message, which proves it did not cross a router.
+const HOP_LIMIT: u8 = 255;
+
+/// Option: link-layer address of the sender.
+const OPTION_SOURCE_LINK_ADDR: u8 = 1;
+/// Option: link-layer address of the target.
+const OPTION_TARGET_LINK_ADDR: u8 = 2;
+/// Option: prefix information.
+const OPTION_PREFIX_INFO: u8 = 3;
+
+/// Advertisement flag: the answer is to a solicitation.
+const FLAG_SOLICITED: u8 = 0x40;
+/// Advertisement flag: the answer replaces cached addresses.
+const FLAG_OVERRIDE: u8 = 0x20;
+
+/// Prefix flag: addresses may be configured from the prefix.
+const PREFIX_AUTONOMOUS: u8 = 0x40;
+
+/// Length of the prefixes addresses are configured from.
+const SLAAC_PREFIX_LEN: u8 = 64;
+
+/// Lifetime that never runs out.
+const INFINITE: u32 = u32::MAX;
+
+/// Cache entries by interface index and address.
+static CACHE: Mutex<BTreeMap<(usize, Ipv6Addr), Entry>> = Mutex::new(BTreeMap::new());
+
+/// Default routers by interface index and address.
+static ROUTERS: Mutex<BTreeMap<(usize, Ipv6Addr), Lifetime>> = Mutex::new(BTreeMap::new());
+
+/// Configured addresses by interface index and address.
+static ADDRESSES: Mutex<BTreeMap<(usize, Ipv6Addr), Lifetime>> = Mutex::new(BTreeMap::new());
+
+/// Interfaces still soliciting routers, by index.
+static SOLICITING: Mutex<BTreeMap<usize, Solicitation>> = Mutex::new(BTreeMap::new());
+
+/// State of an address in the cache.
+enum Entry {
+    /// The address is known
+    Resolved {
+        /// Hardware address
+        mac: MacAddress,
+        /// Tick after which the entry is stale
+        expires: u64,
+    },
+    /// A solicitation is outstanding
+    Pending {
+        /// Interface the solicitation went out on
+        interface: Weak<Interface>,
+        /// Held packets with their EtherType
+        held: Vec<(u16, Vec<u8>)>,
+        /// Solicitations sent so far
+        attempts: u32,
+        /// Tick of the last solicitation
+        requested: u64,
+    },
+}
+
+/// How long a router or configured address is valid.
+struct Lifetime {
+    /// Interface it belongs to
+    interface: Weak<Interface>,
+    /// Tick after which it is dropped, `None` for never
+    expires: Option<u64>,
+}
+
+/// Router solicitation in progress on an interface.
+struct Solicitation {
+    /// The interface
+    interface: Weak<Interface>,
+    /// Solicitations sent so far
+    sent: u32,
+    /// Tick of the last solicitation
+    last: u64,
+}
+
+/// Returns the cached hardware address of `address` on `interface`.
+pub fn lookup(interface: &Interface, address: Ipv6Addr) -> Option<MacAddress> {
+    match CACHE.lock().get(&(interface.index(), address)) {
+        Some(Entry::Resolved { mac, expires }) if *expires > timer::ticks() => Some(*mac),
+        _ => None,
+    }
+}
+
+/// Sends `packet` of protocol `ethertype` to the neighbour `next_hop` on
+/// `interface`, resolving its hardware address first if needed.
+///
+/// Multicast groups and anything on the loopback interface need no
+/// resolution.
+///
+/// # Errors
+///
+/// Returns the errors of [`ethernet::send`] if the packet is sent at once.
+/// A held packet that is dropped later is not reported.
+pub fn transmit(
+    interface: &Arc<Interface>,
+    next_hop: Ipv6Addr,
+    ethertype: u16,
+    packet: Vec<u8>,
+) -> Result<(), NetError> {
+    if interface.is_loopback() {
+        return ethernet::send(interface, MacAddress::ZERO, ethertype, &packet);
+    }
+    if next_hop.is_multicast() {
+        let mac = ipv6::multicast_mac(next_hop);
+        return ethernet::send(interface, mac, ethertype, &packet);
+    }
+    if let Some(mac) = lookup(interface, next_hop) {
+        return ethernet::send(interface, mac, ethertype, &packet);
+    }
+
+    let now = timer::ticks();
+    let mut cache = CACHE.lock();
+    let entry = cache
+        .entry((interface.index(), next_hop))
+        .or_insert(Entry::Pending {
+            interface: Arc::downgrade(interface),
+            held: Vec::new(),
+            attempts: 0,
+            requested: 0,
+        });
+    let send_solicitation = match entry {
+        Entry::Pending {
+            held,
+            attempts,
+            requested,
+            ..
+        } => {
+            if held.len() == MAX_HELD {
+                held.remove(0);
+            }
+            held.push((ethertype, packet));
+            let first = *attempts == 0;
+            if first {
+                *attempts = 1;
+                *requested = now;
+            }
+            first
+        }
+        stale @ Entry::Resolved { .. } => {
+            *stale = Entry::Pending {
+                interface: Arc::downgrade(interface),
+                held: alloc::vec![(ethertype, packet)],
+                attempts: 1,
+                requested: now,
+            };
+            true
+        }
+    };
+    drop(cache);
+    if send_solicitation {
+        solicit(interface, next_hop)?;
+    }
+    Ok(())
+}
+
+/// Returns a default router that has not expired, with its interface.
+pub fn default_router() -> Option<(Arc<Interface>, Ipv6Addr)> {
+    routers().into_iter().next()
+}
+
+/// Returns every default router with its interface.
+pub fn routers() -> Vec<(Arc<Interface>, Ipv6Addr)> {
+    let now = timer::ticks();
+    ROUTERS
+        .lock()
+        .iter()
+        .filter(|(_, router)| router.expires.is_none_or(|expires| expires > now))
+        .filter_map(|(&(_, address), router)| Some((router.interface.upgrade()?, address)))
+        .collect()
+}
+
+/// Returns the resolved entries as interface name, address, and hardware
+/// address.
+pub fn entries() -> Vec<(String, Ipv6Addr, MacAddress)> {
+    let interfaces = super::interfaces();
+    let now = timer::ticks();
+    CACHE
+        .lock()
+        .iter()
+        .filter_map(|(&(index, address), entry)| match entry {
+            Entry::Resolved { mac, expires } if *expires > now => {
+                let name = interfaces.iter().find(|i| i.index() == index)?.name();
+                Some((String::from(name), address, *mac))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Prints the resolved entries and the default routers.
+pub fn print_cache() {
+    for (name, address, mac) in entries() {
+        println!("  {:<39} {}  {}", address, mac, name);
+    }
+    for (interface, address) in routers() {
+        println!("  default via {} dev {}", address, interface.name());
+    }
+}
+
+/// Starts the timer that repeats solicitations and ages out entries.
+pub(super) fn init() {
+    Timer::periodic(RETRANS_TIMER, age);
+}
+
+/// Gives `interface` its link-local address and starts soliciting
+/// routers.
+pub(super) fn start(interface: &Arc<Interface>) {
+    let address = ipv6::link_local(interface.mac_address());
+    interface.add_ipv6(Ipv6Cidr::new(address, SLAAC_PREFIX_LEN));
+    SOLICITING.lock().insert(
+        interface.index(),
+        Solicitation {
+            interface: Arc::downgrade(interface),
+            sent: 1,
+            last: timer::ticks(),
+        },
+    );
+    let _ = solicit_router(interface);
+}
+
+/// Handles a received neighbour discovery message of type `kind`, whose
+/// checksum is verified.
+///
+/// # Returns
+///
+/// `false` if the message is malformed or may have crossed a router.
+pub(super) fn receive(
+    interface: &Arc<Interface>,
+    header: &Ipv6Header,
+    kind: u8,
+    message: &[u8],
+) -> bool {
+    if header.hop_limit != HOP_LIMIT || message[1] != 0 {
+        return false;
+    }
+    let body = &message[icmpv6::HEADER_LEN..];
+    match kind {
+        TYPE_NEIGHBOR_SOLICITATION => on_solicitation(interface, header, body),
+        TYPE_NEIGHBOR_ADVERTISEMENT => on_advertisement(interface, body),
+        TYPE_ROUTER_ADVERTISEMENT => on_router_advertisement(interface, header, body),
+        // Router solicitations are for routers; redirects are ignored.
+        _ => true,
+    }
+}
+
+/// Answers a neighbour solicitation for an address of `interface`.
+fn on_solicitation(interface: &Arc<Interface>, header: &Ipv6Header, body: &[u8]) -> bool {
+    let Some(target) = address_at(body, 4) else {
+        return false;
+    };
+    let Some(options) = parse_options(&body[20..]) else {
+        return false;
+    };
+    if !interface.ipv6().iter().any(|cidr| cidr.address() == target) {
+        return true;
+    }
+    // A solicitation from the unspecified address probes for duplicates,
+    // and is answered to every node.
+    let (dst, flags) = if header.src.is_unspecified() {
+        (ipv6::ALL_NODES, FLAG_OVERRIDE)
+    } else {
+        if let Some(mac) = link_address(&options, OPTION_SOURCE_LINK_ADDR) {
+            resolved(interface, header.src, mac);
+        }
+        (header.src, FLAG_SOLICITED | FLAG_OVERRIDE)
+    };
+    let mut advertisement = alloc::vec![flags, 0, 0, 0];
+    advertisement.extend_from_slice(&target.octets());
+    push_link_address(&mut advertisement, OPTION_TARGET_LINK_ADDR, interface);
+    let _ = send(
+        interface,
+        target,
+        dst,
+        TYPE_NEIGHBOR_ADVERTISEMENT,
+        &advertisement,
+    );
+    true
+}
+
+/// Updates the cache entry of the target of a neighbour advertisement.
+fn on_advertisement(interface: &Arc<Interface>, body: &[u8]) -> bool {
+    let Some(target) = address_at(body, 4) else {
+        return false;
+    };
+    let Some(options) = parse_options(&body[20..]) else {
+        return false;
+    };
+    let known = CACHE.lock().contains_key(&(interface.index(), target));
+    if let Some(mac) = link_address(&options, OPTION_TARGET_LINK_ADDR).filter(|_| known) {
+        resolved(interface, target, mac);
+    }
+    true
+}
+
+/// Learns a default router and configures addresses from its prefixes.
+fn on_router_advertisement(interface: &Arc<Interface>, header: &Ipv6Header, body: &[u8]) -> bool {
+    if !header.src.is_unicast_link_local() || body.len() < 12 {
+        return false;
+    }
+    let Some(options) = parse_options(&body[12..]) else {
+        return false;
+    };
+    SOLICITING.lock().remove(&interface.index());
+    if let Some(mac) = link_address(&options, OPTION_SOURCE_LINK_ADDR) {
+        resolved(interface, header.src, mac);
+    }
+
+    let lifetime = u16::from_be_bytes([body[2], body[3]]);
+    let key = (interface.index(), header.src);
+    if lifetime == 0 {
+        ROUTERS.lock().remove(&key);
+    } else {
+        let expires = timer::ticks() + timer::duration_to_ticks(secs(lifetime.into()));
+        let new = ROUTERS
+            .lock()
+            .insert(
+                key,
+                Lifetime {
+                    interface: Arc::downgrade(interface),
+                    expires: Some(expires),
+                },
+            )
+            .is_none();
+        if new {
+            println!(
+                "ndp: {} default router {}, lifetime {} s",
+                interface.name(),
+                header.src,
+                lifetime
+            );
+        }
+    }
+
+    for (kind, option) in options {
+        if kind != OPTION_PREFIX_INFO || option.len() != 32 {
+            continue;
+        }
+        let be32 = |at: usize| {
+            u32::from_be_bytes([option[at], option[at + 1], option[at + 2], option[at + 3]])
+        };
+        let (prefix_len, flags, valid, preferred) = (option[2], option[3], be32(4), be32(8));
+        let Some(prefix) = address_at(option, 16) else {
+            continue;
+        };
+        if flags & PREFIX_AUTONOMOUS != 0
+            && prefix_len == SLAAC_PREFIX_LEN
+            && !prefix.is_unicast_link_local()
+            && preferred <= valid
+        {
+            configure(interface, prefix, valid);
+        }
+    }
+    true
+}
+
+/// Configures the address of `interface` on `prefix`, or renews it, for
+/// `valid` seconds.
+fn configure(interface: &Arc<Interface>, prefix: Ipv6Addr, valid: u32) {
+    let mut octets = prefix.octets();
+    octets[8..].copy_from_slice(&ipv6::interface_id(interface.mac_address()));
+    let address = Ipv6Addr::from(octets);
+    let expires =
+        (valid != INFINITE).then(|| timer::ticks() + timer::duration_to_ticks(secs(valid)));
+    let mut addresses = ADDRESSES.lock();
+    match addresses.get_mut(&(interface.index(), address)) {
+        Some(lifetime) => lifetime.expires = expires,
+        None if valid == 0 => {}
+        None => {
+            addresses.insert(
+                (interface.index(), address),
+                Lifetime {
+                    interface: Arc::downgrade(interface),
+                    expires,
+                },
+            );
+            drop(addresses);
+            let cidr = Ipv6Cidr::new(address, SLAAC_PREFIX_LEN);
+            interface.add_ipv6(cidr);
+            println!("ndp: {} configured {}", interface.name(), cidr);
+        }
+    }
+}
+
+/// Records that `address` is at `mac` and sends the packets held for it.
+fn resolved(interface: &Interface, address: Ipv6Addr, mac: MacAddress) {
+    let entry = Entry::Resolved {
+        mac,
+        expires: timer::ticks() + timer::duration_to_ticks(REACHABLE_TIME),
+    };
+    let previous = CACHE.lock().insert((interface.index(), address), entry);
+    if let Some(Entry::Pending { held, .. }) = previous {
+        for (ethertype, packet) in held {
+            let _ = ethernet::send(interface, mac, ethertype, &packet);
+        }
+    }
+}
+
+/// Sends a neighbour solicitation for `target` to its solicited-node
+/// group.
+fn solicit(interface: &Arc<Interface>, target: Ipv6Addr) -> Result<(), NetError> {
+    let src = ipv6::select_source(interface, target).ok_or(NetError::NoAddress)?;
+    let mut solicitation = alloc::vec![0; 4];
+    solicitation.extend_from_slice(&target.octets());
+    push_link_address(&mut solicitation, OPTION_SOURCE_LINK_ADDR, interface);
+    send(
+        interface,
+        src,
+        ipv6::solicited_node(target),
+        TYPE_NEIGHBOR_SOLICITATION,
+        &solicitation,
+    )
+}
+
+/// Sends a router solicitation to every router on the link.
+fn solicit_router(interface: &Arc<Interface>) -> Result<(), NetError> {
+    let src = ipv6::select_source(interface, ipv6::ALL_ROUTERS).ok_or(NetError::NoAddress)?;
+    let mut solicitation = alloc::vec![0; 4];
+    push_link_address(&mut solicitation, OPTION_SOURCE_LINK_ADDR, interface);
+    send(
+        interface,
+        src,
+        ipv6::ALL_ROUTERS,
+        TYPE_ROUTER_SOLICITATION,
+        &solicitation,
+    )
+}
+
+/// Sends a message of type `kind` from `src` to `dst` on `interface`.
+fn send(
+    interface: &Arc<Interface>,
+    src: Ipv6Addr,
+    dst: Ipv6Addr,
+    kind: u8,
+    body: &[u8],
+) -> Result<(), NetError> {
+    let message = icmpv6::build(src, dst, kind, 0, body);
+    ipv6::send_with_hop_limit(
+        interface,
+        dst,
+        src,
+        dst,
+        NEXT_HEADER_ICMPV6,
+        HOP_LIMIT,
+        &message,
+    )
+}
+
+/// Appends a link-layer address option of type `kind` with the hardware
+/// address of `interface`.
+fn push_link_address(message: &mut Vec<u8>, kind: u8, interface: &Interface) {
+    message.extend_from_slice(&[kind, 1]);
+    message.extend_from_slice(&interface.mac_address().0);
+}
+
+/// Reads the address at offset `at` of `data`.
+fn address_at(data: &[u8], at: usize) -> Option<Ipv6Addr> {
+    let octets: [u8; 16] = data.get(at..at + 16)?.try_into().ok()?;
+    Some(Ipv6Addr::from(octets))
+}
+
+/// Splits `data` into options as type and whole option.
+///
+/// # Returns
+///
+/// `None` if an option is malformed, which invalidates the message.
+fn parse_options(data: &[u8]) -> Option<Vec<(u8, &[u8])>> {
+    let mut options = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        // Lengths are in units of 8 bytes, and never zero.
+        let len = usize::from(*rest.get(1)?) * 8;
+        if len == 0 || len > rest.len() {
+            return None;
+        }
+        options.push((rest[0], &rest[..len]));
+        rest = &rest[len..];
+    }
+    Some(options)
+}
+
+/// The hardware address in the first link-layer address option of type
+/// `kind`.
+fn link_address(options: &[(u8, &[u8])], kind: u8) -> Option<MacAddress> {
+    let (_, option) = options.iter().find(|(k, _)| *k == kind)?;
+    Some(MacAddress(option.get(2..8)?.try_into().ok()?))
+}
+
+/// A lifetime in seconds as a duration.
+fn secs(seconds: u32) -> Duration {
+    Duration::from_secs(seconds.into())
+}
+
+/// Repeats outstanding solicitations, gives up on unanswered ones, and
+/// drops stale entries, routers, and addresses. Runs on the timer thread
+/// every [`RETRANS_TIMER`].
+fn age() {
+    let now = timer::ticks();
+    let retry = timer::duration_to_ticks(RETRANS_TIMER);
+    let mut again = Vec::new();
+    CACHE.lock().retain(|&(_, address), entry| match entry {
+        Entry::Resolved { expires, .. } => *expires > now,
+        Entry::Pending {
+            interface,
+            attempts,
+            requested,
+            ..
+        } => {
+            if now - *requested < retry {
+                return true;
+            }
+            if *attempts >= MAX_MULTICAST_SOLICIT {
+                return false;
+            }
+            *attempts += 1;
+            *requested = now;
+            if let Some(interface) = interface.upgrade() {
+                again.push((interface, address));
+            }
+            true
+        }
+    });
+    for (interface, address) in again {
+        let _ = solicit(&interface, address);
+    }
+
+    let alive = |lifetime: &Lifetime| lifetime.expires.is_none_or(|expires| expires > now);
+    ROUTERS.lock().retain(|_, router| alive(router));
+    let expired: Vec<(Weak<Interface>, Ipv6Addr)> = ADDRESSES
+        .lock()
+        .extract_if(.., |_, lifetime| !alive(lifetime))
+        .map(|((_, address), lifetime)| (lifetime.interface, address))
+        .collect();
+    for (interface, address) in expired {
+        if let Some(interface) = interface.upgrade() {
+            interface.remove_ipv6(address);
+            println!("ndp: {} address {} expired", interface.name(), address);
+        }
+    }
+
+    let interval = timer::duration_to_ticks(RTR_SOLICITATION_INTERVAL);
+    let mut solicit = Vec::new();
+    SOLICITING.lock().retain(|_, solicitation| {
+        if now - solicitation.last < interval {
+            return true;
+        }
+        if solicitation.sent >= MAX_RTR_SOLICITATIONS {
+            return false;
+        }
+        solicitation.sent += 1;
+        solicitation.last = now;
+        solicit.extend(solicitation.interface.upgrade());
+        true
+    });
+    for interface in solicit {
+        let _ = solicit_router(&interface);
+    }
+}
@@ -0,0 +1,271 @@
+//! # Packet Capture
+//!
+//! Copies the frames the interfaces send and receive into a stream in the
+//! classic pcap format, which Wireshark and tcpdump read directly. The
+//! stream goes to a [`Sink`]: COM2, e.g. with QEMU's
+//! `-serial stdio -serial file:capture.pcap` (or a pipe into
+//! `wireshark -k -i -`), or a file such as one on the ramfs at `/tmp`.
+//!
+//! Sent frames are captured in [`Interface::transmit`], received ones as
+//! the `netrx` thread takes them from the queue, so frames dropped there
+//! do not show up. Records are queued and written out by the `netdump`
+//! thread, so a slow sink does not hold up the stack; when the queue is
+//! full, frames are dropped from the capture and counted.
+//!
+//! The capture can also be started from boot with the `netdump` feature,
+//! which streams every interface to COM2.
+
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use core::time::Duration;
+use spin::Mutex;
+
+use super::{Interface, LINK_HEADER_LEN};
+use crate::fs::file::{self, OpenFile, O_CREAT, O_TRUNC, O_WRONLY};
+use crate::fs::FsError;
+use crate::println;
+use crate::scheduler::{self, WaitQueue};
+use crate::serial;
+use crate::timer::clock;
+
+/// Bytes of a frame kept by default.
+pub const DEFAULT_SNAPLEN: usize = 65535;
+
+/// Records waiting for the `netdump` thread before frames are dropped.
+const QUEUE_LEN: usize = 256;
+
+/// Magic number of a pcap file with microsecond timestamps.
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+/// Major version of the pcap format.
+const PCAP_VERSION_MAJOR: u16 = 2;
+/// Minor version of the pcap format.
+const PCAP_VERSION_MINOR: u16 = 4;
+/// Link type of Ethernet frames.
+const LINKTYPE_ETHERNET: u32 = 1;
+
+/// [`FILTER`] value that captures every interface.
+const ALL_INTERFACES: usize = usize::MAX;
+
+/// Whether a capture is running, checked before anything else.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Index of the interface captured, [`ALL_INTERFACES`] for every one.
+static FILTER: AtomicUsize = AtomicUsize::new(ALL_INTERFACES);
+
+/// Bytes of a frame kept.
+static SNAPLEN: AtomicUsize = AtomicUsize::new(DEFAULT_SNAPLEN);
+
+/// Sink of the running capture.
+static OUTPUT: Mutex<Option<Output>> = Mutex::new(None);
+
+/// Records waiting to be written.
+static QUEUE: Mutex<VecDeque<Record>> = Mutex::new(VecDeque::new());
+
+/// Wakes the `netdump` thread when records are queued.
+static QUEUE_READY: WaitQueue = WaitQueue::new();
+
+/// Whether the `netdump` thread has been started.
+static THREAD_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Frames captured since the capture started.
+static CAPTURED: AtomicU64 = AtomicU64::new(0);
+
+/// Frames dropped from the capture since it started.
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Where the capture is written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Sink {
+    /// The second serial port
+    Serial,
+    /// A file, created or truncated
+    File(String),
+}
+
+/// Counters of the capture.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CaptureStats {
+    /// Frames written or waiting to be
+    pub captured: u64,
+    /// Frames left out because the queue was full or the sink failed
+    pub dropped: u64,
+}
+
+/// An opened [`Sink`].
+enum Output {
+    /// See [`Sink::Serial`]
+    Serial,
+    /// See [`Sink::File`]
+    File(Arc<OpenFile>),
+}
+
+impl Output {
+    /// Writes `bytes` to the sink.
+    fn write(&self, bytes: &[u8]) -> Result<(), FsError> {
+        match self {
+            Output::Serial => serial::write_com2(bytes),
+            Output::File(file) => {
+                let mut written = 0;
+                while written < bytes.len() {
+                    match file.write(&bytes[written..])? {
+                        0 => return Err(FsError::NoSpace),
+                        n => written += n,
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A captured frame.
+struct Record {
+    /// Wall-clock time of the capture
+    time: Duration,
+    /// Length of the frame before it was cut to the snapshot length
+    len: usize,
+    /// The frame, at most the snapshot length
+    data: Vec<u8>,
+}
+
+/// Starts capturing into `sink`, replacing a running capture.
+///
+/// # Arguments
+///
+/// * `sink` - Where the capture goes; it starts with the pcap header
+/// * `interface` - Interface to capture, or `None` for all
+/// * `snaplen` - Bytes of every frame to keep, at least the Ethernet header
+///
+/// # Errors
+///
+/// Returns the errors of opening or writing to a [`Sink::File`].
+///
+/// # Panics
+///
+/// Panics if the `netdump` thread cannot be started.
+pub fn start(sink: Sink, interface: Option<&Interface>, snaplen: usize) -> Result<(), FsError> {
+    let snaplen = snaplen.clamp(LINK_HEADER_LEN, DEFAULT_SNAPLEN);
+    let output = match sink {
+        Sink::Serial => Output::Serial,
+        Sink::File(path) => Output::File(file::open(&path, O_WRONLY | O_CREAT | O_TRUNC)?),
+    };
+    output.write(&file_header(snaplen))?;
+
+    let mut current = OUTPUT.lock();
+    ACTIVE.store(false, Ordering::Relaxed);
+    QUEUE.lock().clear();
+    CAPTURED.store(0, Ordering::Relaxed);
+    DROPPED.store(0, Ordering::Relaxed);
+    FILTER.store(
+        interface.map_or(ALL_INTERFACES, Interface::index),
+        Ordering::Relaxed,
+    );
+    SNAPLEN.store(snaplen, Ordering::Relaxed);
+    *current = Some(output);
+    ACTIVE.store(true, Ordering::Relaxed);
+    drop(current);
+
+    if !THREAD_STARTED.swap(true, Ordering::Relaxed) {
+        scheduler::spawn_named("netdump", run).expect("failed to start the netdump thread");
+    }
+    Ok(())
+}
+
+/// Stops the capture, after writing out the frames already captured.
+pub fn stop() {
+    ACTIVE.store(false, Ordering::Relaxed);
+    let mut output = OUTPUT.lock();
+    if let Some(output) = output.as_ref() {
+        while let Some(record) = QUEUE.lock().pop_front() {
+            write_record(output, &record);
+        }
+    }
+    *output = None;
+}
+
+/// Returns `true` while a capture is running.
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Returns the counters of the running or last capture.
+pub fn stats() -> CaptureStats {
+    CaptureStats {
+        captured: CAPTURED.load(Ordering::Relaxed),
+        dropped: DROPPED.load(Ordering::Relaxed),
+    }
+}
+
+/// Captures `frame`, sent or received on `interface`, if a capture is
+/// running and wants it.
+///
+/// Called in thread context.
+pub(super) fn capture(interface: &Interface, frame: &[u8]) {
+    let filter = FILTER.load(Ordering::Relaxed);
+    if !ACTIVE.load(Ordering::Relaxed) || (filter != ALL_INTERFACES && filter != interface.index())
+    {
+        return;
+    }
+    let snaplen = SNAPLEN.load(Ordering::Relaxed);
+    let record = Record {
+        time: clock::now(),
+        len: frame.len(),
+        data: frame[..frame.len().min(snaplen)].to_vec(),
+    };
+    let mut queue = QUEUE.lock();
+    if queue.len() >= QUEUE_LEN {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+    queue.push_back(record);
+    drop(queue);
+    CAPTURED.fetch_add(1, Ordering::Relaxed);
+    QUEUE_READY.notify_one();
+}
+
+/// Body of the `netdump` thread: writes queued records to the sink.
+fn run() {
+    loop {
+        let mut next = None;
+        QUEUE_READY.wait_until(|| {
+            next = QUEUE.lock().pop_front();
+            next.is_some()
+        });
+        if let Some(record) = next {
+            if let Some(output) = OUTPUT.lock().as_ref() {
+                write_record(output, &record);
+            }
+        }
+    }
+}
+
+/// Writes `record` to `output`, and stops the capture if that fails.
+fn write_record(output: &Output, record: &Record) {
+    let mut bytes = Vec::with_capacity(16 + record.data.len());
+    bytes.extend_from_slice(&(record.time.as_secs() as u32).to_le_bytes());
+    bytes.extend_from_slice(&record.time.subsec_micros().to_le_bytes());
+    bytes.extend_from_slice(&(record.data.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&(record.len as u32).to_le_bytes());
+    bytes.extend_from_slice(&record.data);
+    if let Err(err) = output.write(&bytes) {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+        if ACTIVE.swap(false, Ordering::Relaxed) {
+            println!("netdump: capture stopped: {:?}", err);
+        }
+    }
+}
+
+/// The pcap file header for Ethernet frames of at most `snaplen` bytes.
+fn file_header(snaplen: usize) -> [u8; 24] {
+    let mut header = [0; 24];
+    header[0..4].copy_from_slice(&PCAP_MAGIC.to_le_bytes());
+    header[4..6].copy_from_slice(&PCAP_VERSION_MAJOR.to_le_bytes());
+    header[6..8].copy_from_slice(&PCAP_VERSION_MINOR.to_le_bytes());
+    // Time zone offset and timestamp accuracy stay zero.
+    header[16..20].copy_from_slice(&(snaplen as u32).to_le_bytes());
+    header[20..24].copy_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+    header
+}
@@ -0,0 +1,111 @@
+//! # IPv4 Routing
+//!
+//! The routing table decides which interface a datagram leaves through and
+//! which neighbour receives it. Each route covers a destination network
+//! and either reaches it directly or through a gateway; the most specific
+//! route that matches wins, and the default route `0.0.0.0/0` catches
+//! everything else.
+//!
+//! Assigning an address to an interface adds the route to its own network;
+//! removing the address removes every route through the interface.
+
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+use spin::Mutex;
+
+use super::ipv4::Ipv4Cidr;
+use super::Interface;
+use crate::println;
+
+/// The routing table, most specific routes first.
+static ROUTES: Mutex<Vec<Route>> = Mutex::new(Vec::new());
+
+/// An entry of the routing table.
+#[derive(Debug, Clone)]
+pub struct Route {
+    /// Destination network
+    pub destination: Ipv4Cidr,
+    /// Neighbour to forward to, `None` if the network is attached
+    pub gateway: Option<Ipv4Addr>,
+    /// Interface to send through
+    pub interface: Arc<Interface>,
+}
+
+/// Adds `route`, replacing a route to the same network through the same
+/// interface.
+pub fn add(route: Route) {
+    let mut routes = ROUTES.lock();
+    routes.retain(|r| {
+        r.destination != route.destination || r.interface.index() != route.interface.index()
+    });
+    let at = routes
+        .iter()
+        .position(|r| r.destination.prefix_len() < route.destination.prefix_len())
+        .unwrap_or(routes.len());
+    routes.insert(at, route);
+}
+
+/// Removes every route to `destination`.
+///
+/// # Returns
+///
+/// `true` if a route was removed.
+pub fn remove(destination: Ipv4Cidr) -> bool {
+    let mut routes = ROUTES.lock();
+    let before = routes.len();
+    routes.retain(|r| r.destination != destination);
+    routes.len() != before
+}
+
+/// Removes every route through `interface`.
+pub fn remove_interface(interface: &Interface) {
+    ROUTES
+        .lock()
+        .retain(|r| r.interface.index() != interface.index());
+}
+
+/// Makes `gateway`, reached through `interface`, the default route.
+pub fn set_default_gateway(gateway: Ipv4Addr, interface: Arc<Interface>) {
+    remove(Ipv4Cidr::DEFAULT);
+    add(Route {
+        destination: Ipv4Cidr::DEFAULT,
+        gateway: Some(gateway),
+        interface,
+    });
+}
+
+/// Finds the way to `destination`.
+///
+/// # Returns
+///
+/// The interface to send through and the neighbour to send to, or `None`
+/// if no route matches.
+pub fn lookup(destination: Ipv4Addr) -> Option<(Arc<Interface>, Ipv4Addr)> {
+    ROUTES
+        .lock()
+        .iter()
+        .find(|r| r.destination.contains(destination))
+        .map(|r| (r.interface.clone(), r.gateway.unwrap_or(destination)))
+}
+
+/// Returns the routing table, most specific routes first.
+pub fn routes() -> Vec<Route> {
+    ROUTES.lock().clone()
+}
+
+/// Prints the routing table.
+pub fn print_routes() {
+    for route in routes() {
+        let gateway = route
+            .gateway
+            .map_or_else(|| String::from("-"), |gateway| gateway.to_string());
+        println!(
+            "  {:<18} {:<15} {}",
+            route.destination.to_string(),
+            gateway,
+            route.interface.name()
+        );
+    }
+}
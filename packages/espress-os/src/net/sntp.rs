@@ -0,0 +1,238 @@
+//! # SNTP Client
+//!
+//! Keeps the wall clock (see [`clock`]) in step with an NTP server, using
+//! the simple subset of the protocol (SNTP, RFC 4330). The `sntp` thread
+//! that [`start`] spawns queries the server at once and then every
+//! [`SYNC_INTERVAL`], and sets the clock to the server's time corrected by
+//! half the round trip; if asked to, it also writes the result back to the
+//! CMOS RTC so the next boot starts out close.
+//!
+//! The server is the one given to [`start`] or [`set_server`], or else the
+//! first NTP server DHCP named. While none is known or the server does not
+//! answer, the thread tries again every [`RETRY_INTERVAL`].
+
+use core::net::{IpAddr, SocketAddr};
+use core::time::Duration;
+use spin::Mutex;
+
+use super::udp::UdpSocket;
+use super::{dhcp, NetError};
+use crate::println;
+use crate::scheduler;
+use crate::timer::{self, clock};
+
+/// Port servers listen on.
+pub const PORT: u16 = 123;
+
+/// Time between synchronizations.
+pub const SYNC_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// Time between attempts while no server answers.
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long a query waits for the answer.
+pub const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Size of a packet without extensions.
+const PACKET_LEN: usize = 48;
+
+/// Protocol version sent.
+const VERSION: u8 = 4;
+
+/// Mode: client.
+const MODE_CLIENT: u8 = 3;
+/// Mode: server.
+const MODE_SERVER: u8 = 4;
+
+/// Leap indicator: the server's clock is not synchronized.
+const LEAP_UNSYNCHRONIZED: u8 = 3;
+
+/// Seconds from the NTP epoch, 1900, to the Unix epoch.
+const NTP_TO_UNIX: u64 = 2_208_988_800;
+
+/// Steps of the clock smaller than this are not logged.
+const QUIET_STEP: Duration = Duration::from_secs(1);
+
+/// Server set with [`start`] or [`set_server`].
+static SERVER: Mutex<Option<IpAddr>> = Mutex::new(None);
+
+/// The result of a query.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    /// Server that answered
+    pub server: IpAddr,
+    /// Nanoseconds the wall clock was behind the server, negative if it
+    /// was ahead
+    pub offset: i64,
+    /// Round trip to the server, without its processing time
+    pub delay: Duration,
+    /// Stratum of the server: 1 for a reference clock, more further away
+    pub stratum: u8,
+}
+
+/// Starts the `sntp` thread, which synchronizes the wall clock with
+/// `server`, or a server from DHCP if `None`.
+///
+/// # Arguments
+///
+/// * `server` - The NTP server to use
+/// * `write_rtc` - Write the clock back to the CMOS RTC after every
+///   synchronization
+///
+/// # Panics
+///
+/// Panics if the thread cannot be started.
+pub fn start(server: Option<IpAddr>, write_rtc: bool) {
+    if server.is_some() {
+        set_server(server);
+    }
+    scheduler::spawn_named("sntp", move || run(write_rtc))
+        .expect("failed to start the sntp thread");
+}
+
+/// Makes `server` the NTP server, or falls back to DHCP with `None`.
+pub fn set_server(server: Option<IpAddr>) {
+    *SERVER.lock() = server;
+}
+
+/// Returns the NTP server in use, if any is known.
+pub fn server() -> Option<IpAddr> {
+    let server = *SERVER.lock();
+    server.or_else(|| dhcp::ntp_servers().first().map(|&server| server.into()))
+}
+
+/// Asks `server` for the time, without setting the clock.
+///
+/// # Errors
+///
+/// Returns [`NetError::Timeout`] if no answer arrives in time,
+/// [`NetError::Rejected`] if the server is not synchronized itself, and
+/// the errors of [`UdpSocket::bind`] and [`UdpSocket::send_to`].
+///
+/// # Panics
+///
+/// Panics if called from outside a scheduler thread.
+pub fn query(server: IpAddr) -> Result<Sample, NetError> {
+    let socket = UdpSocket::bind(0)?;
+    let sent = clock::now();
+    let mut request = [0; PACKET_LEN];
+    request[0] = (VERSION << 3) | MODE_CLIENT;
+    // Echoed by the server, which pairs the answer with the request.
+    request[40..48].copy_from_slice(&to_ntp(sent).to_be_bytes());
+    socket.send_to(&request, SocketAddr::new(server, PORT))?;
+
+    loop {
+        let datagram = socket.recv_timeout(TIMEOUT)?;
+        let received = clock::now();
+        let data = &datagram.data;
+        if datagram.src != SocketAddr::new(server, PORT) || data.len() < PACKET_LEN {
+            continue;
+        }
+        let be64 = |at: usize| u64::from_be_bytes(data[at..at + 8].try_into().unwrap());
+        if be64(24) != to_ntp(sent) {
+            continue;
+        }
+        let (leap, mode, stratum) = (data[0] >> 6, data[0] & 0x07, data[1]);
+        // Stratum 0 is a "kiss-o'-death" telling the client to go away.
+        if leap == LEAP_UNSYNCHRONIZED || mode != MODE_SERVER || stratum == 0 || be64(40) == 0 {
+            return Err(NetError::Rejected);
+        }
+        let nanos = |time: Duration| time.as_nanos() as i128;
+        let t1 = nanos(sent);
+        let t2 = nanos(from_ntp(be64(32)));
+        let t3 = nanos(from_ntp(be64(40)));
+        let t4 = nanos(received);
+        return Ok(Sample {
+            server,
+            offset: (((t2 - t1) + (t3 - t4)) / 2) as i64,
+            delay: Duration::from_nanos(((t4 - t1) - (t3 - t2)).max(0) as u64),
+            stratum,
+        });
+    }
+}
+
+/// Sets the wall clock from `server`.
+///
+/// # Arguments
+///
+/// * `server` - The NTP server
+/// * `write_rtc` - Also write the clock back to the CMOS RTC
+///
+/// # Errors
+///
+/// See [`query`].
+///
+/// # Panics
+///
+/// Panics if called from outside a scheduler thread.
+pub fn sync(server: IpAddr, write_rtc: bool) -> Result<Sample, NetError> {
+    let sample = query(server)?;
+    let step = Duration::from_nanos(sample.offset.unsigned_abs());
+    let now = clock::now();
+    clock::set(match sample.offset {
+        0.. => now + step,
+        _ => now.saturating_sub(step),
+    });
+    if write_rtc {
+        clock::write_rtc();
+    }
+    Ok(sample)
+}
+
+/// Body of the `sntp` thread.
+fn run(write_rtc: bool) {
+    let mut synced = false;
+    let mut failing = false;
+    loop {
+        let Some(server) = server() else {
+            timer::sleep(RETRY_INTERVAL);
+            continue;
+        };
+        match sync(server, write_rtc) {
+            Ok(sample) => {
+                let step = Duration::from_nanos(sample.offset.unsigned_abs());
+                if !synced || step >= QUIET_STEP {
+                    println!(
+                        "sntp: clock set to {} from {} (offset {} ms)",
+                        clock::date_time(),
+                        server,
+                        sample.offset / 1_000_000
+                    );
+                }
+                synced = true;
+                failing = false;
+                timer::sleep(SYNC_INTERVAL);
+            }
+            Err(err) => {
+                // Report the first failure only, not every retry.
+                if !failing {
+                    println!("sntp: {}: {:?}", server, err);
+                }
+                failing = true;
+                timer::sleep(RETRY_INTERVAL);
+            }
+        }
+    }
+}
+
+/// Converts a time since the Unix epoch to an NTP timestamp: seconds since
+/// 1900 in the upper half, the fraction of a second in the lower.
+fn to_ntp(time: Duration) -> u64 {
+    let secs = (time.as_secs() + NTP_TO_UNIX) as u32;
+    let fraction = (u64::from(time.subsec_nanos()) << 32) / 1_000_000_000;
+    (u64::from(secs) << 32) | fraction
+}
+
+/// Converts an NTP timestamp to a time since the Unix epoch.
+///
+/// Seconds wrap around in 2036; smaller values than the Unix epoch are
+/// taken to be after that.
+fn from_ntp(timestamp: u64) -> Duration {
+    let secs = timestamp >> 32;
+    let secs = match secs.checked_sub(NTP_TO_UNIX) {
+        Some(secs) => secs,
+        None => secs + (1 << 32) - NTP_TO_UNIX,
+    };
+    let nanos = ((timestamp & 0xffff_ffff) * 1_000_000_000) >> 32;
+    Duration::new(secs, nanos as u32)
+}
@@ -0,0 +1,299 @@
+//! # Sockets
+//!
+//! The objects behind the socket handles of user processes. A [`Socket`]
+//! holds one of the kernel's [`UdpSocket`], [`TcpListener`], or
+//! [`TcpStream`], and follows the BSD calls that turn one into another: a
+//! stream socket becomes a listener with [`listen`](Socket::listen) or a
+//! connection with [`connect`](Socket::connect), and every connection
+//! [`accept`](Socket::accept)ed is a socket of its own. A datagram socket
+//! binds a port on first use unless [`bind`](Socket::bind) chose one, and
+//! may be connected to a default peer.
+//!
+//! Sockets speak both versions of IP whatever family they were created
+//! for, and bind their port on every interface; the address passed to
+//! [`bind`](Socket::bind) is not used.
+
+use alloc::sync::Arc;
+use core::fmt;
+use core::net::SocketAddr;
+use spin::Mutex;
+
+use super::tcp::{TcpListener, TcpStream};
+use super::udp::UdpSocket;
+use super::NetError;
+
+/// The kind of a socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketType {
+    /// A TCP byte stream
+    Stream,
+    /// UDP datagrams
+    Datagram,
+}
+
+/// A socket, shared by every handle to it.
+pub struct Socket {
+    /// Kind chosen at creation
+    kind: SocketType,
+    /// What the socket has become so far
+    state: Mutex<State>,
+}
+
+/// The kernel object behind a socket.
+///
+/// Objects are reference-counted so that blocking calls can use them
+/// without holding the state lock.
+enum State {
+    /// A stream socket before `listen` or `connect`
+    Unconnected {
+        /// Port chosen with `bind`, 0 for any
+        port: u16,
+    },
+    /// A stream socket accepting connections
+    Listening(Arc<TcpListener>),
+    /// A stream socket with a connection
+    Connected(Arc<TcpStream>),
+    /// A datagram socket
+    Datagram {
+        /// The bound port, once there is one
+        socket: Option<Arc<UdpSocket>>,
+        /// Default destination and only accepted source, once connected
+        peer: Option<SocketAddr>,
+    },
+}
+
+/// What [`Socket::send_to`] and [`Socket::recv_from`] go through.
+enum Endpoint {
+    /// The connection of a stream socket
+    Stream(Arc<TcpStream>),
+    /// The port of a datagram socket, with the default peer
+    Datagram(Arc<UdpSocket>, Option<SocketAddr>),
+}
+
+impl Socket {
+    /// Creates an unbound socket of the given kind.
+    pub fn new(kind: SocketType) -> Self {
+        let state = match kind {
+            SocketType::Stream => State::Unconnected { port: 0 },
+            SocketType::Datagram => State::Datagram {
+                socket: None,
+                peer: None,
+            },
+        };
+        Socket {
+            kind,
+            state: Mutex::new(state),
+        }
+    }
+
+    /// Kind of the socket.
+    pub fn kind(&self) -> SocketType {
+        self.kind
+    }
+
+    /// Binds the port of `local`, or an ephemeral port if it is 0.
+    ///
+    /// A stream socket only claims the port when it starts listening.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NetError::InvalidState`] if the socket is bound already
+    /// and the errors of [`UdpSocket::bind`].
+    pub fn bind(&self, local: SocketAddr) -> Result<(), NetError> {
+        let mut state = self.state.lock();
+        match &mut *state {
+            State::Unconnected { port: port @ 0 } => *port = local.port(),
+            State::Datagram { socket, .. } if socket.is_none() => {
+                *socket = Some(Arc::new(UdpSocket::bind(local.port())?));
+            }
+            _ => return Err(NetError::InvalidState),
+        }
+        Ok(())
+    }
+
+    /// Starts accepting connections on the bound port, or an ephemeral
+    /// port if none was bound. Does nothing if the socket listens already.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NetError::NotSupported`] for datagram sockets,
+    /// [`NetError::InvalidState`] for connected ones, and the errors of
+    /// [`TcpListener::bind`].
+    pub fn listen(&self) -> Result<(), NetError> {
+        let mut state = self.state.lock();
+        match *state {
+            State::Unconnected { port } => {
+                *state = State::Listening(Arc::new(TcpListener::bind(port)?));
+                Ok(())
+            }
+            State::Listening(_) => Ok(()),
+            State::Connected(_) => Err(NetError::InvalidState),
+            State::Datagram { .. } => Err(NetError::NotSupported),
+        }
+    }
+
+    /// Waits for a connection to the listening socket.
+    ///
+    /// # Returns
+    ///
+    /// A socket for the connection, and the address of the peer.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NetError::NotSupported`] for datagram sockets and
+    /// [`NetError::InvalidState`] unless the socket listens.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from outside a scheduler thread.
+    pub fn accept(&self) -> Result<(Socket, SocketAddr), NetError> {
+        let listener = match &*self.state.lock() {
+            State::Listening(listener) => listener.clone(),
+            State::Datagram { .. } => return Err(NetError::NotSupported),
+            _ => return Err(NetError::InvalidState),
+        };
+        let stream = listener.accept();
+        let peer = stream.peer_addr();
+        let socket = Socket {
+            kind: SocketType::Stream,
+            state: Mutex::new(State::Connected(Arc::new(stream))),
+        };
+        Ok((socket, peer))
+    }
+
+    /// Connects a stream socket to `remote`, or makes `remote` the default
+    /// peer of a datagram socket.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NetError::AlreadyConnected`] for a connected stream
+    /// socket, [`NetError::InvalidState`] for a listening one, and the
+    /// errors of [`TcpStream::connect`] and [`UdpSocket::bind`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from outside a scheduler thread.
+    pub fn connect(&self, remote: SocketAddr) -> Result<(), NetError> {
+        match &mut *self.state.lock() {
+            State::Unconnected { .. } => {}
+            State::Listening(_) => return Err(NetError::InvalidState),
+            State::Connected(_) => return Err(NetError::AlreadyConnected),
+            State::Datagram { socket, peer } => {
+                if socket.is_none() {
+                    *socket = Some(Arc::new(UdpSocket::bind(0)?));
+                }
+                *peer = Some(remote);
+                return Ok(());
+            }
+        }
+        let stream = TcpStream::connect(remote)?;
+        let mut state = self.state.lock();
+        // Another thread may have connected meanwhile.
+        if !matches!(*state, State::Unconnected { .. }) {
+            return Err(NetError::AlreadyConnected);
+        }
+        *state = State::Connected(Arc::new(stream));
+        Ok(())
+    }
+
+    /// Sends `data` over the connection of a stream socket, or as a
+    /// datagram to `dst` or the default peer.
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes sent, all of `data` for datagrams.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NetError::NotConnected`] if there is no connection or
+    /// destination, and the errors of [`TcpStream::send`] and
+    /// [`UdpSocket::send_to`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from outside a scheduler thread.
+    pub fn send_to(&self, data: &[u8], dst: Option<SocketAddr>) -> Result<usize, NetError> {
+        match self.endpoint()? {
+            Endpoint::Stream(stream) => stream.send(data),
+            Endpoint::Datagram(socket, peer) => {
+                let dst = dst.or(peer).ok_or(NetError::NotConnected)?;
+                socket.send_to(data, dst)?;
+                Ok(data.len())
+            }
+        }
+    }
+
+    /// Reads from the connection of a stream socket, or the next datagram
+    /// into `buf`, waiting until there is something to read. The part of
+    /// a datagram that does not fit is discarded.
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes read, and the sender. A stream socket reads 0
+    /// bytes once the peer has closed its end.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NetError::NotConnected`] for stream sockets without a
+    /// connection, and the errors of [`TcpStream::recv`] and
+    /// [`UdpSocket::bind`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from outside a scheduler thread.
+    pub fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr), NetError> {
+        let (socket, peer) = match self.endpoint()? {
+            Endpoint::Stream(stream) => {
+                let len = stream.recv(buf)?;
+                return Ok((len, stream.peer_addr()));
+            }
+            Endpoint::Datagram(socket, peer) => (socket, peer),
+        };
+        loop {
+            let datagram = socket.recv();
+            if peer.is_some_and(|peer| peer != datagram.src) {
+                continue;
+            }
+            let len = datagram.data.len().min(buf.len());
+            buf[..len].copy_from_slice(&datagram.data[..len]);
+            return Ok((len, datagram.src));
+        }
+    }
+
+    /// Returns the object data goes through, binding a datagram socket to
+    /// an ephemeral port first if it has none.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NetError::NotConnected`] for stream sockets without a
+    /// connection, and the errors of [`UdpSocket::bind`].
+    fn endpoint(&self) -> Result<Endpoint, NetError> {
+        match &mut *self.state.lock() {
+            State::Connected(stream) => Ok(Endpoint::Stream(stream.clone())),
+            State::Datagram { socket, peer } => {
+                let socket = match socket {
+                    Some(socket) => socket.clone(),
+                    None => socket.insert(Arc::new(UdpSocket::bind(0)?)).clone(),
+                };
+                Ok(Endpoint::Datagram(socket, *peer))
+            }
+            _ => Err(NetError::NotConnected),
+        }
+    }
+}
+
+impl fmt::Debug for Socket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = match &*self.state.lock() {
+            State::Unconnected { .. } => "unconnected",
+            State::Listening(_) => "listening",
+            State::Connected(_) => "connected",
+            State::Datagram { peer: Some(_), .. } => "connected",
+            State::Datagram { .. } => "unconnected",
+        };
+        f.debug_struct("Socket")
+            .field("kind", &self.kind)
+            .field("state", &state)
+            .finish()
+    }
+}
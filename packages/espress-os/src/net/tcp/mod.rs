@@ -0,0 +1,551 @@
+//! # TCP
+//!
+//! Reliable, ordered byte streams (RFC 793). A [`TcpListener`] accepts
+//! connections on a local port, [`TcpStream::connect`] opens one to a
+//! remote port, and both ends then [`send`](TcpStream::send) and
+//! [`recv`](TcpStream::recv) until they close.
+//!
+//! Every connection has a send buffer of [`SEND_BUFFER`] bytes and a
+//! receive buffer of [`RECV_BUFFER`] bytes whose free room is the window
+//! offered to the peer, so a sender never overruns a reader that falls
+//! behind. The state machine lives in `tcb`; this module keeps the table
+//! of connections, routes arriving segments to them, runs their timers,
+//! and blocks callers until their operation can complete.
+//!
+//! Listeners are dual-stack: a port accepts connections over IPv4 and
+//! IPv6 alike.
+//!
+//! Closing a stream lets the connection finish in the background: queued
+//! data is still delivered, and the connection lingers in TIME-WAIT before
+//! it is forgotten. Segments for which there is no connection or listener
+//! are answered with a reset.
+
+mod segment;
+mod tcb;
+
+pub use segment::TcpHeader;
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::net::{IpAddr, Ipv6Addr, SocketAddr};
+use core::time::Duration;
+use spin::Mutex;
+
+use super::ipv4::PROTOCOL_TCP;
+use super::{ip, Interface, NetError};
+use crate::println;
+use crate::scheduler::WaitQueue;
+use crate::timer::{self, Timer};
+use segment::{ACK, RST, SYN};
+use tcb::Tcb;
+
+/// Bytes of data a connection queues for sending.
+pub const SEND_BUFFER: usize = 65536;
+
+/// Bytes of received data a connection holds until they are read; the
+/// largest window that fits the header.
+pub const RECV_BUFFER: usize = 65535;
+
+/// Maximum segment size assumed when the peer announces none.
+pub const DEFAULT_MSS: u16 = 536;
+
+/// Connections a listener queues until they are accepted.
+pub const DEFAULT_BACKLOG: usize = 16;
+
+/// Period of the timer that drives retransmissions and timeouts.
+const TIMER_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Connections by local and remote address.
+static CONNECTIONS: Mutex<BTreeMap<Endpoints, Arc<Connection>>> = Mutex::new(BTreeMap::new());
+
+/// Listeners by local port.
+static LISTENERS: Mutex<BTreeMap<u16, Arc<Listener>>> = Mutex::new(BTreeMap::new());
+
+/// The local and remote address of a connection.
+type Endpoints = (SocketAddr, SocketAddr);
+
+/// States of a connection, as in RFC 793.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// No connection
+    Closed,
+    /// Waiting for connection requests
+    Listen,
+    /// SYN sent, waiting for the answer
+    SynSent,
+    /// SYN received and answered, waiting for the acknowledgment
+    SynReceived,
+    /// Open; data flows both ways
+    Established,
+    /// FIN sent, waiting for its acknowledgment
+    FinWait1,
+    /// FIN acknowledged, waiting for the peer's FIN
+    FinWait2,
+    /// Peer's FIN received, waiting for the application to close
+    CloseWait,
+    /// Both FINs crossed, waiting for the acknowledgment of ours
+    Closing,
+    /// Closed after the peer, waiting for the acknowledgment of our FIN
+    LastAck,
+    /// Waiting for old segments of the connection to die out
+    TimeWait,
+}
+
+impl State {
+    /// Name of the state as in RFC 793, for listings.
+    pub fn name(self) -> &'static str {
+        match self {
+            State::Closed => "CLOSED",
+            State::Listen => "LISTEN",
+            State::SynSent => "SYN-SENT",
+            State::SynReceived => "SYN-RECEIVED",
+            State::Established => "ESTABLISHED",
+            State::FinWait1 => "FIN-WAIT-1",
+            State::FinWait2 => "FIN-WAIT-2",
+            State::CloseWait => "CLOSE-WAIT",
+            State::Closing => "CLOSING",
+            State::LastAck => "LAST-ACK",
+            State::TimeWait => "TIME-WAIT",
+        }
+    }
+}
+
+/// A snapshot of a connection or listener, for listings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// Local address and port
+    pub local: SocketAddr,
+    /// Remote address and port, unspecified for listeners
+    pub remote: SocketAddr,
+    /// Connection state
+    pub state: State,
+    /// Bytes queued for sending and not yet acknowledged
+    pub send_queue: usize,
+    /// Bytes received and not yet read
+    pub recv_queue: usize,
+}
+
+/// A connection and the threads waiting on it.
+struct Connection {
+    /// Connection state
+    tcb: Mutex<Tcb>,
+    /// Where callers wait for the state to change
+    events: WaitQueue,
+}
+
+impl Connection {
+    /// Blocks until `op` returns a result.
+    ///
+    /// `op` runs with the control block locked, once up front and again
+    /// whenever the connection changes.
+    fn wait<T>(&self, mut op: impl FnMut(&mut Tcb) -> Option<T>) -> T {
+        let mut result = None;
+        self.events.wait_until(|| {
+            result = op(&mut self.tcb.lock());
+            result.is_some()
+        });
+        result.expect("woken without a result")
+    }
+}
+
+/// Connections waiting to be accepted on a port.
+struct Listener {
+    /// Local port
+    port: u16,
+    /// Established connections, oldest first
+    backlog: Mutex<VecDeque<Arc<Connection>>>,
+    /// Longest backlog
+    max_backlog: usize,
+    /// Where `accept` waits for connections
+    ready: WaitQueue,
+}
+
+/// A socket accepting connections on a local port, from any address.
+///
+/// Dropping the listener resets the connections it has not handed out.
+pub struct TcpListener {
+    /// Shared with the receive path
+    listener: Arc<Listener>,
+}
+
+impl TcpListener {
+    /// Listens on `port`, or on a free ephemeral port if `port` is 0.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NetError::AddressInUse`] if something listens on the port
+    /// already or no ephemeral port is free.
+    pub fn bind(port: u16) -> Result<Self, NetError> {
+        let mut listeners = LISTENERS.lock();
+        let port = match port {
+            0 => super::ephemeral_port(|port| listeners.contains_key(&port))
+                .ok_or(NetError::AddressInUse)?,
+            port if listeners.contains_key(&port) => return Err(NetError::AddressInUse),
+            port => port,
+        };
+        let listener = Arc::new(Listener {
+            port,
+            backlog: Mutex::new(VecDeque::new()),
+            max_backlog: DEFAULT_BACKLOG,
+            ready: WaitQueue::new(),
+        });
+        listeners.insert(port, listener.clone());
+        Ok(TcpListener { listener })
+    }
+
+    /// The local port.
+    pub fn local_port(&self) -> u16 {
+        self.listener.port
+    }
+
+    /// Waits for a connection.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from outside a scheduler thread.
+    pub fn accept(&self) -> TcpStream {
+        let mut stream = None;
+        self.listener.ready.wait_until(|| {
+            stream = self.try_accept();
+            stream.is_some()
+        });
+        stream.expect("woken without a connection")
+    }
+
+    /// Takes an established connection without blocking.
+    pub fn try_accept(&self) -> Option<TcpStream> {
+        let connection = self.listener.backlog.lock().pop_front()?;
+        Some(TcpStream { connection })
+    }
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        LISTENERS.lock().remove(&self.listener.port);
+        let backlog = core::mem::take(&mut *self.listener.backlog.lock());
+        for connection in backlog {
+            connection.tcb.lock().abort();
+        }
+    }
+}
+
+/// A connection, from either end.
+///
+/// Dropping the stream closes it like [`close`](Self::close).
+pub struct TcpStream {
+    /// Shared with the receive path and the timer
+    connection: Arc<Connection>,
+}
+
+impl TcpStream {
+    /// Opens a connection to `remote` from an ephemeral port.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NetError::ConnectionRefused`] if the peer resets the
+    /// attempt, [`NetError::Timeout`] if it never answers,
+    /// [`NetError::AddressInUse`] if no local port is free, and the errors
+    /// of [`ip::route`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from outside a scheduler thread.
+    pub fn connect(remote: SocketAddr) -> Result<Self, NetError> {
+        let (interface, address) = ip::route(remote.ip())?;
+        let connection = {
+            let mut connections = CONNECTIONS.lock();
+            let listeners = LISTENERS.lock();
+            let port = super::ephemeral_port(|port| {
+                listeners.contains_key(&port)
+                    || connections.contains_key(&(SocketAddr::new(address, port), remote))
+            })
+            .ok_or(NetError::AddressInUse)?;
+            let local = SocketAddr::new(address, port);
+            let mut tcb = Tcb::new(local, remote, local_mss(&interface, address));
+            // The answer cannot be processed before the table is unlocked.
+            tcb.connect();
+            let connection = Arc::new(Connection {
+                tcb: Mutex::new(tcb),
+                events: WaitQueue::new(),
+            });
+            connections.insert((local, remote), connection.clone());
+            connection
+        };
+        connection.wait(|tcb| match tcb.state {
+            State::SynSent | State::SynReceived => None,
+            State::Closed => Some(Err(tcb.error.unwrap_or(NetError::ConnectionRefused))),
+            _ => Some(Ok(())),
+        })?;
+        Ok(TcpStream { connection })
+    }
+
+    /// Local address and port.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.connection.tcb.lock().local
+    }
+
+    /// Remote address and port.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.connection.tcb.lock().remote
+    }
+
+    /// State of the connection.
+    pub fn state(&self) -> State {
+        self.connection.tcb.lock().state
+    }
+
+    /// Queues some of `data` for sending, waiting while the send buffer is
+    /// full.
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes queued.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NetError::NotConnected`] after the stream was shut down,
+    /// and the error that ended the connection, e.g.
+    /// [`NetError::ConnectionReset`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from outside a scheduler thread.
+    pub fn send(&self, data: &[u8]) -> Result<usize, NetError> {
+        self.connection.wait(|tcb| tcb.write(data))
+    }
+
+    /// Queues all of `data` for sending.
+    ///
+    /// # Errors
+    ///
+    /// See [`send`](Self::send).
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from outside a scheduler thread.
+    pub fn send_all(&self, mut data: &[u8]) -> Result<(), NetError> {
+        while !data.is_empty() {
+            let sent = self.send(data)?;
+            data = &data[sent..];
+        }
+        Ok(())
+    }
+
+    /// Reads received data into `buf`, waiting until there is some.
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes read, 0 once the peer has closed its end and
+    /// everything was read.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error that ended the connection, e.g.
+    /// [`NetError::ConnectionReset`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from outside a scheduler thread.
+    pub fn recv(&self, buf: &mut [u8]) -> Result<usize, NetError> {
+        self.connection.wait(|tcb| tcb.read(buf))
+    }
+
+    /// Like [`recv`](Self::recv), but gives up after `timeout`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NetError::Timeout`] if nothing arrives in time, and the
+    /// errors of [`recv`](Self::recv).
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from outside a scheduler thread.
+    pub fn recv_timeout(&self, buf: &mut [u8], timeout: Duration) -> Result<usize, NetError> {
+        let mut result = None;
+        self.connection.events.wait_until_timeout(timeout, || {
+            result = self.connection.tcb.lock().read(buf);
+            result.is_some()
+        });
+        result.unwrap_or(Err(NetError::Timeout))
+    }
+
+    /// Sends a FIN once the queued data is out; the stream can still
+    /// receive.
+    pub fn shutdown(&self) {
+        self.connection.tcb.lock().shutdown();
+    }
+
+    /// Closes the stream. The connection delivers the queued data and
+    /// finishes in the background; unread received data makes it reset
+    /// instead.
+    pub fn close(self) {}
+
+    /// Resets the connection, discarding queued data.
+    pub fn abort(&self) {
+        self.connection.tcb.lock().abort();
+        self.connection.events.notify_all();
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        self.connection.tcb.lock().close();
+    }
+}
+
+/// Returns every listener and connection.
+pub fn connections() -> Vec<ConnectionInfo> {
+    // Listeners take both versions, which `[::]` stands for.
+    let unspecified = SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0);
+    let mut info: Vec<ConnectionInfo> = LISTENERS
+        .lock()
+        .keys()
+        .map(|&port| ConnectionInfo {
+            local: SocketAddr::new(unspecified.ip(), port),
+            remote: unspecified,
+            state: State::Listen,
+            send_queue: 0,
+            recv_queue: 0,
+        })
+        .collect();
+    for connection in CONNECTIONS.lock().values() {
+        let tcb = connection.tcb.lock();
+        info.push(ConnectionInfo {
+            local: tcb.local,
+            remote: tcb.remote,
+            state: tcb.state,
+            send_queue: tcb.send_queue_len(),
+            recv_queue: tcb.recv_queue_len(),
+        });
+    }
+    info
+}
+
+/// Prints every listener and connection, like `netstat`.
+pub fn print_connections() {
+    for info in connections() {
+        println!(
+            "  {:<21} {:<21} {:<12} send {:<6} recv {}",
+            info.local.to_string(),
+            info.remote.to_string(),
+            info.state.name(),
+            info.send_queue,
+            info.recv_queue
+        );
+    }
+}
+
+/// Starts the timer that drives retransmissions and timeouts.
+pub(super) fn init() {
+    Timer::periodic(TIMER_INTERVAL, tick);
+}
+
+/// Handles a segment received from `src` for `dst`.
+///
+/// # Returns
+///
+/// `false` if the segment is malformed.
+pub(super) fn receive(
+    interface: &Arc<Interface>,
+    src: IpAddr,
+    dst: IpAddr,
+    segment: &[u8],
+) -> bool {
+    let Some((seg, payload)) = TcpHeader::parse(src, dst, segment) else {
+        return false;
+    };
+    let local = SocketAddr::new(dst, seg.dst_port);
+    let remote = SocketAddr::new(src, seg.src_port);
+
+    let connection = CONNECTIONS.lock().get(&(local, remote)).cloned();
+    if let Some(connection) = connection.filter(|c| !c.tcb.lock().is_closed()) {
+        let mut tcb = connection.tcb.lock();
+        let listener = match tcb.on_segment(&seg, payload) {
+            true => tcb.listener.take(),
+            false => None,
+        };
+        drop(tcb);
+        if let Some(listener) = listener {
+            match listener.upgrade() {
+                Some(listener) => {
+                    listener.backlog.lock().push_back(connection.clone());
+                    listener.ready.notify_all();
+                }
+                None => connection.tcb.lock().abort(),
+            }
+        }
+        connection.events.notify_all();
+        return true;
+    }
+
+    if seg.has(SYN) && !seg.has(ACK) && !seg.has(RST) && !ip::is_group(dst) {
+        let listener = LISTENERS.lock().get(&seg.dst_port).cloned();
+        if let Some(listener) = listener {
+            // Without room in the backlog, the peer retries the SYN later.
+            if listener.backlog.lock().len() < listener.max_backlog {
+                let mut tcb = Tcb::new(local, remote, local_mss(interface, dst));
+                tcb.listener = Some(Arc::downgrade(&listener));
+                tcb.accept_syn(&seg);
+                let connection = Arc::new(Connection {
+                    tcb: Mutex::new(tcb),
+                    events: WaitQueue::new(),
+                });
+                CONNECTIONS.lock().insert((local, remote), connection);
+            }
+            return true;
+        }
+    }
+    if !seg.has(RST) && !ip::is_group(dst) {
+        send_reset(local, remote, &seg, payload.len());
+    }
+    true
+}
+
+/// Answers a segment for which there is no connection with a reset.
+fn send_reset(local: SocketAddr, remote: SocketAddr, seg: &TcpHeader, payload_len: usize) {
+    let (seq, ack, flags) = if seg.has(ACK) {
+        (seg.ack, 0, RST)
+    } else {
+        (0, seg.seq.wrapping_add(seg.seq_len(payload_len)), RST | ACK)
+    };
+    let reset = TcpHeader {
+        src_port: local.port(),
+        dst_port: remote.port(),
+        seq,
+        ack,
+        flags,
+        window: 0,
+        mss: None,
+    };
+    let segment = reset.build(local.ip(), remote.ip(), &[]);
+    let _ = ip::send(local.ip(), remote.ip(), PROTOCOL_TCP, &segment);
+}
+
+/// Largest segment from `address` that fits the MTU of `interface`.
+fn local_mss(interface: &Interface, address: IpAddr) -> u16 {
+    interface
+        .mtu()
+        .saturating_sub(ip::header_len(address) + segment::HEADER_LEN)
+        .min(usize::from(u16::MAX)) as u16
+}
+
+/// Runs the timers of every connection and forgets closed connections.
+fn tick() {
+    let now = timer::ticks();
+    let connections: Vec<Arc<Connection>> = CONNECTIONS.lock().values().cloned().collect();
+    for connection in connections {
+        let mut tcb = connection.tcb.lock();
+        let was = tcb.state;
+        tcb.on_tick(now);
+        let changed = tcb.state != was;
+        drop(tcb);
+        if changed {
+            connection.events.notify_all();
+        }
+    }
+    CONNECTIONS
+        .lock()
+        .retain(|_, connection| !connection.tcb.lock().is_closed());
+}
@@ -0,0 +1,167 @@
+//! # TCP Segments
+//!
+//! The header of a TCP segment and arithmetic on sequence numbers, which
+//! wrap around at 2^32 and are compared relative to each other.
+//!
+//! The only option understood is the maximum segment size, which is sent
+//! with every SYN; others are skipped on receipt.
+
+use alloc::vec::Vec;
+use core::net::IpAddr;
+
+use crate::net::checksum::pseudo_header;
+use crate::net::ipv4::PROTOCOL_TCP;
+
+/// Size of a header without options.
+pub const HEADER_LEN: usize = 20;
+
+/// Flag: the sender has no more data.
+pub const FIN: u8 = 0x01;
+/// Flag: synchronize sequence numbers.
+pub const SYN: u8 = 0x02;
+/// Flag: reset the connection.
+pub const RST: u8 = 0x04;
+/// Flag: deliver the data without waiting for more.
+pub const PSH: u8 = 0x08;
+/// Flag: the acknowledgment number is valid.
+pub const ACK: u8 = 0x10;
+
+/// Option: end of the options.
+const OPTION_END: u8 = 0;
+/// Option: padding.
+const OPTION_NOP: u8 = 1;
+/// Option: maximum segment size.
+const OPTION_MSS: u8 = 2;
+
+/// Header of a TCP segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpHeader {
+    /// Port of the sender
+    pub src_port: u16,
+    /// Port of the receiver
+    pub dst_port: u16,
+    /// Sequence number of the first byte
+    pub seq: u32,
+    /// Next sequence number expected from the receiver
+    pub ack: u32,
+    /// Control flags
+    pub flags: u8,
+    /// Bytes the sender is willing to receive
+    pub window: u16,
+    /// Maximum segment size option
+    pub mss: Option<u16>,
+}
+
+impl TcpHeader {
+    /// Splits `segment` into header and payload, checking the checksum over
+    /// the pseudo-header of `src` and `dst`.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the segment is malformed.
+    pub fn parse(src: IpAddr, dst: IpAddr, segment: &[u8]) -> Option<(Self, &[u8])> {
+        if segment.len() < HEADER_LEN {
+            return None;
+        }
+        let header_len = usize::from(segment[12] >> 4) * 4;
+        if header_len < HEADER_LEN || header_len > segment.len() {
+            return None;
+        }
+        if pseudo_header(src, dst, PROTOCOL_TCP, segment.len())
+            .add(segment)
+            .finish()
+            != 0
+        {
+            return None;
+        }
+        let be16 = |at: usize| u16::from_be_bytes([segment[at], segment[at + 1]]);
+        let be32 = |at: usize| {
+            u32::from_be_bytes([
+                segment[at],
+                segment[at + 1],
+                segment[at + 2],
+                segment[at + 3],
+            ])
+        };
+        let mut header = TcpHeader {
+            src_port: be16(0),
+            dst_port: be16(2),
+            seq: be32(4),
+            ack: be32(8),
+            flags: segment[13],
+            window: be16(14),
+            mss: None,
+        };
+
+        let options = &segment[HEADER_LEN..header_len];
+        let mut at = 0;
+        while at < options.len() {
+            match options[at] {
+                OPTION_END => break,
+                OPTION_NOP => at += 1,
+                kind => {
+                    let len = usize::from(*options.get(at + 1)?);
+                    if len < 2 || at + len > options.len() {
+                        return None;
+                    }
+                    if kind == OPTION_MSS && len == 4 {
+                        header.mss = Some(u16::from_be_bytes([options[at + 2], options[at + 3]]));
+                    }
+                    at += len;
+                }
+            }
+        }
+        Some((header, &segment[header_len..]))
+    }
+
+    /// Builds a segment carrying `payload`, with its checksum over the
+    /// pseudo-header of `src` and `dst`.
+    pub fn build(&self, src: IpAddr, dst: IpAddr, payload: &[u8]) -> Vec<u8> {
+        let header_len = if self.mss.is_some() {
+            HEADER_LEN + 4
+        } else {
+            HEADER_LEN
+        };
+        let mut segment = Vec::with_capacity(header_len + payload.len());
+        segment.extend_from_slice(&self.src_port.to_be_bytes());
+        segment.extend_from_slice(&self.dst_port.to_be_bytes());
+        segment.extend_from_slice(&self.seq.to_be_bytes());
+        segment.extend_from_slice(&self.ack.to_be_bytes());
+        segment.push((header_len as u8 / 4) << 4);
+        segment.push(self.flags);
+        segment.extend_from_slice(&self.window.to_be_bytes());
+        // Checksum and urgent pointer
+        segment.extend_from_slice(&[0, 0, 0, 0]);
+        if let Some(mss) = self.mss {
+            segment.extend_from_slice(&[OPTION_MSS, 4]);
+            segment.extend_from_slice(&mss.to_be_bytes());
+        }
+        segment.extend_from_slice(payload);
+        let sum = pseudo_header(src, dst, PROTOCOL_TCP, segment.len())
+            .add(&segment)
+            .finish();
+        segment[16..18].copy_from_slice(&sum.to_be_bytes());
+        segment
+    }
+
+    /// Returns `true` if `flag` is set.
+    pub fn has(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// Sequence numbers the segment occupies: its payload, and one each for
+    /// SYN and FIN.
+    pub fn seq_len(&self, payload_len: usize) -> u32 {
+        payload_len as u32 + u32::from(self.has(SYN)) + u32::from(self.has(FIN))
+    }
+}
+
+/// Returns `true` if sequence number `a` comes before `b`.
+pub fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+/// Returns `true` if sequence number `a` comes before `b` or equals it.
+pub fn seq_le(a: u32, b: u32) -> bool {
+    a == b || seq_lt(a, b)
+}
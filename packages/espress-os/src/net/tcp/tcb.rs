@@ -0,0 +1,711 @@
+//! # TCP Control Blocks
+//!
+//! The state of one connection and the state machine of RFC 793 that
+//! drives it: how arriving segments are checked against the receive window,
+//! what their acknowledgments and data do, what is sent in return, and what
+//! happens when the retransmission timer expires.
+//!
+//! Sent data stays in the send buffer until it is acknowledged. On a
+//! timeout everything unacknowledged is sent again (go-back-N) and the
+//! retransmission timeout doubles; round-trip times are measured as in
+//! RFC 6298, never on retransmitted segments. A peer that closes its window
+//! is probed with one byte at a time until it opens again.
+//!
+//! Received data that arrives out of order is kept, a few segments' worth,
+//! until the gap before it is filled.
+
+use alloc::collections::VecDeque;
+use alloc::sync::Weak;
+use alloc::vec::Vec;
+use core::net::SocketAddr;
+use core::time::Duration;
+
+use super::segment::{seq_le, seq_lt, TcpHeader, ACK, FIN, PSH, RST, SYN};
+use super::{Listener, State, RECV_BUFFER, SEND_BUFFER};
+use crate::net::ip;
+use crate::net::ipv4::PROTOCOL_TCP;
+use crate::net::NetError;
+use crate::timer;
+
+/// Retransmission timeout before the first round trip is measured.
+const INITIAL_RTO: Duration = Duration::from_secs(1);
+/// Shortest retransmission timeout.
+const MIN_RTO: Duration = Duration::from_millis(200);
+/// Longest retransmission timeout.
+const MAX_RTO: Duration = Duration::from_secs(60);
+
+/// Retransmissions of a SYN before the connection attempt fails.
+const MAX_SYN_RETRIES: u32 = 5;
+/// Retransmissions of data before the connection is aborted.
+const MAX_RETRIES: u32 = 12;
+
+/// Maximum segment lifetime; TIME-WAIT lasts twice as long.
+const MSL: Duration = Duration::from_secs(30);
+/// How long a closed socket waits in FIN-WAIT-2 for the peer's FIN.
+const FIN_WAIT_2_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Out-of-order segments kept per connection.
+const MAX_OUT_OF_ORDER: usize = 16;
+
+/// The state of one connection.
+pub(super) struct Tcb {
+    /// Connection state
+    pub(super) state: State,
+    /// Local address and port
+    pub(super) local: SocketAddr,
+    /// Remote address and port
+    pub(super) remote: SocketAddr,
+    /// Why the connection ended, if it failed
+    pub(super) error: Option<NetError>,
+    /// Listener that gets the connection once it is established
+    pub(super) listener: Option<Weak<Listener>>,
+    /// Initial send sequence number
+    iss: u32,
+    /// Oldest unacknowledged sequence number
+    snd_una: u32,
+    /// Next sequence number to send
+    snd_nxt: u32,
+    /// Send window granted by the peer
+    snd_wnd: u32,
+    /// Sequence number of the segment that last updated the window
+    snd_wl1: u32,
+    /// Acknowledgment number of the segment that last updated the window
+    snd_wl2: u32,
+    /// Largest segment the peer accepts
+    mss: usize,
+    /// Largest segment this end accepts, announced with the SYN
+    local_mss: u16,
+    /// Data not yet acknowledged, sent or not
+    send_buf: VecDeque<u8>,
+    /// Sequence number of the first byte of `send_buf`
+    buf_seq: u32,
+    /// The application is done sending; a FIN follows the data
+    fin_queued: bool,
+    /// The FIN has been sent
+    fin_sent: bool,
+    /// Next sequence number expected
+    rcv_nxt: u32,
+    /// Data received in order but not yet read
+    recv_buf: VecDeque<u8>,
+    /// Segments received ahead of `rcv_nxt`
+    out_of_order: Vec<(u32, Vec<u8>)>,
+    /// The peer's FIN has arrived
+    fin_received: bool,
+    /// An acknowledgment should be sent
+    ack_pending: bool,
+    /// Smoothed round-trip time, once measured
+    srtt: Option<Duration>,
+    /// Round-trip time variation
+    rttvar: Duration,
+    /// Retransmission timeout
+    rto: Duration,
+    /// Sequence number whose acknowledgment ends the running measurement,
+    /// and the tick it started
+    rtt_sample: Option<(u32, u64)>,
+    /// Tick at which the retransmission timer expires
+    retransmit_at: Option<u64>,
+    /// Retransmissions since the last acknowledgment
+    retries: u32,
+    /// Tick at which TIME-WAIT or an orphaned FIN-WAIT-2 ends
+    deadline: Option<u64>,
+    /// The application has closed the socket
+    orphaned: bool,
+}
+
+impl Tcb {
+    /// Creates a closed control block.
+    ///
+    /// # Arguments
+    ///
+    /// * `local` - Local address and port
+    /// * `remote` - Remote address and port
+    /// * `local_mss` - Largest segment this end accepts
+    pub(super) fn new(local: SocketAddr, remote: SocketAddr, local_mss: u16) -> Self {
+        let iss = crate::rand::next_u32();
+        Tcb {
+            state: State::Closed,
+            local,
+            remote,
+            error: None,
+            listener: None,
+            iss,
+            snd_una: iss,
+            snd_nxt: iss,
+            snd_wnd: 0,
+            snd_wl1: 0,
+            snd_wl2: 0,
+            mss: usize::from(super::DEFAULT_MSS),
+            local_mss,
+            send_buf: VecDeque::new(),
+            buf_seq: iss.wrapping_add(1),
+            fin_queued: false,
+            fin_sent: false,
+            rcv_nxt: 0,
+            recv_buf: VecDeque::new(),
+            out_of_order: Vec::new(),
+            fin_received: false,
+            ack_pending: false,
+            srtt: None,
+            rttvar: Duration::ZERO,
+            rto: INITIAL_RTO,
+            rtt_sample: None,
+            retransmit_at: None,
+            retries: 0,
+            deadline: None,
+            orphaned: false,
+        }
+    }
+
+    /// Starts an active open by sending a SYN.
+    pub(super) fn connect(&mut self) {
+        self.state = State::SynSent;
+        self.transmit(SYN, self.iss, &[]);
+        self.snd_nxt = self.iss.wrapping_add(1);
+        self.arm_retransmit();
+    }
+
+    /// Answers the SYN a listener received with a SYN-ACK.
+    pub(super) fn accept_syn(&mut self, syn: &TcpHeader) {
+        self.state = State::SynReceived;
+        self.rcv_nxt = syn.seq.wrapping_add(1);
+        self.set_peer_mss(syn.mss);
+        self.update_window(syn);
+        self.transmit(SYN | ACK, self.iss, &[]);
+        self.snd_nxt = self.iss.wrapping_add(1);
+        self.arm_retransmit();
+    }
+
+    /// Bytes queued for sending and not yet acknowledged.
+    pub(super) fn send_queue_len(&self) -> usize {
+        self.send_buf.len()
+    }
+
+    /// Bytes received and not yet read.
+    pub(super) fn recv_queue_len(&self) -> usize {
+        self.recv_buf.len()
+    }
+
+    /// Reads received data into `buf`.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the caller has to wait; `Some(Ok(0))` at the end of the
+    /// stream.
+    pub(super) fn read(&mut self, buf: &mut [u8]) -> Option<Result<usize, NetError>> {
+        if !self.recv_buf.is_empty() {
+            let before = self.recv_window();
+            let len = buf.len().min(self.recv_buf.len());
+            for (dst, src) in buf.iter_mut().zip(self.recv_buf.drain(..len)) {
+                *dst = src;
+            }
+            // Tell the peer once enough room opened for a full segment.
+            let threshold = self.mss.min(RECV_BUFFER / 2);
+            if before < threshold && self.recv_window() >= threshold {
+                self.send_ack();
+            }
+            return Some(Ok(len));
+        }
+        if let Some(err) = self.error {
+            return Some(Err(err));
+        }
+        match self.state {
+            _ if self.fin_received => Some(Ok(0)),
+            State::Closed => Some(Err(NetError::NotConnected)),
+            _ => None,
+        }
+    }
+
+    /// Queues `data` for sending and sends what the window allows.
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes queued, or `None` if the send buffer is full and
+    /// the caller has to wait.
+    pub(super) fn write(&mut self, data: &[u8]) -> Option<Result<usize, NetError>> {
+        if let Some(err) = self.error {
+            return Some(Err(err));
+        }
+        if self.fin_queued || !matches!(self.state, State::Established | State::CloseWait) {
+            return Some(Err(NetError::NotConnected));
+        }
+        let len = data.len().min(SEND_BUFFER - self.send_buf.len());
+        if len == 0 && !data.is_empty() {
+            return None;
+        }
+        self.send_buf.extend(&data[..len]);
+        self.output();
+        Some(Ok(len))
+    }
+
+    /// Sends a FIN after the queued data.
+    pub(super) fn shutdown(&mut self) {
+        match self.state {
+            State::Established => self.state = State::FinWait1,
+            State::CloseWait => self.state = State::LastAck,
+            State::SynSent => return self.enter_closed(),
+            _ => return,
+        }
+        self.fin_queued = true;
+        self.output();
+    }
+
+    /// Closes the connection on behalf of an application that is done with
+    /// it, resetting it if received data would be lost.
+    pub(super) fn close(&mut self) {
+        self.orphaned = true;
+        if !self.recv_buf.is_empty() {
+            return self.abort();
+        }
+        self.shutdown();
+        if self.state == State::FinWait2 {
+            self.deadline = Some(deadline(FIN_WAIT_2_TIMEOUT));
+        }
+    }
+
+    /// Resets the connection.
+    pub(super) fn abort(&mut self) {
+        if !matches!(self.state, State::Closed | State::SynSent | State::TimeWait) {
+            self.transmit(RST | ACK, self.snd_nxt, &[]);
+        }
+        self.enter_closed();
+    }
+
+    /// Returns `true` once the connection can be forgotten.
+    pub(super) fn is_closed(&self) -> bool {
+        self.state == State::Closed
+    }
+
+    /// Handles a segment that arrived for the connection.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the segment completed a passive open.
+    pub(super) fn on_segment(&mut self, seg: &TcpHeader, payload: &[u8]) -> bool {
+        match self.state {
+            State::Closed | State::Listen => return false,
+            State::SynSent => {
+                self.on_syn_sent(seg);
+                return false;
+            }
+            _ => {}
+        }
+
+        if !self.acceptable(seg.seq, seg.seq_len(payload.len())) {
+            if !seg.has(RST) {
+                self.send_ack();
+            }
+            return false;
+        }
+        if seg.has(RST) {
+            // A reset half-open connection is simply forgotten.
+            if self.listener.is_none() {
+                self.error = Some(NetError::ConnectionReset);
+            }
+            self.enter_closed();
+            return false;
+        }
+        if seg.has(SYN) {
+            self.transmit(RST, self.snd_nxt, &[]);
+            self.error = Some(NetError::ConnectionReset);
+            self.enter_closed();
+            return false;
+        }
+        if !seg.has(ACK) {
+            return false;
+        }
+
+        let mut established = false;
+        if self.state == State::SynReceived {
+            if !(seq_lt(self.snd_una, seg.ack) && seq_le(seg.ack, self.snd_nxt)) {
+                self.transmit(RST, seg.ack, &[]);
+                return false;
+            }
+            self.state = State::Established;
+            established = true;
+        }
+        if !self.on_ack(seg) || self.state == State::Closed {
+            self.flush_ack();
+            return established;
+        }
+        self.on_data(seg.seq, payload);
+        if seg.has(FIN) {
+            self.on_fin(seg.seq.wrapping_add(payload.len() as u32));
+        }
+        self.output();
+        self.flush_ack();
+        established
+    }
+
+    /// Handles the retransmission and connection timers.
+    pub(super) fn on_tick(&mut self, now: u64) {
+        if self.deadline.is_some_and(|deadline| now >= deadline) {
+            self.enter_closed();
+            return;
+        }
+        if !self.retransmit_at.is_some_and(|at| now >= at) {
+            return;
+        }
+        self.rto = (self.rto * 2).min(MAX_RTO);
+        self.retransmit_at = Some(deadline(self.rto));
+        self.rtt_sample = None;
+
+        let synchronized = !matches!(self.state, State::SynSent | State::SynReceived);
+        // A closed window is probed for as long as it takes.
+        if synchronized && self.snd_wnd == 0 {
+            self.snd_nxt = self.snd_una;
+            self.fin_sent = false;
+            self.output_probe();
+            return;
+        }
+
+        self.retries += 1;
+        let limit = if synchronized {
+            MAX_RETRIES
+        } else {
+            MAX_SYN_RETRIES
+        };
+        if self.retries > limit {
+            self.error = Some(NetError::Timeout);
+            self.abort();
+            return;
+        }
+        match self.state {
+            State::SynSent => self.transmit(SYN, self.iss, &[]),
+            State::SynReceived => self.transmit(SYN | ACK, self.iss, &[]),
+            _ => {
+                self.snd_nxt = self.snd_una;
+                self.fin_sent = false;
+                self.output();
+            }
+        }
+    }
+
+    /// Handles a segment in SYN-SENT, where the peer answers the SYN.
+    fn on_syn_sent(&mut self, seg: &TcpHeader) {
+        let ack_ok = seq_lt(self.iss, seg.ack) && seq_le(seg.ack, self.snd_nxt);
+        if seg.has(ACK) && !ack_ok {
+            if !seg.has(RST) {
+                self.transmit(RST, seg.ack, &[]);
+            }
+            return;
+        }
+        if seg.has(RST) {
+            if seg.has(ACK) {
+                self.error = Some(NetError::ConnectionRefused);
+                self.enter_closed();
+            }
+            return;
+        }
+        if !seg.has(SYN) {
+            return;
+        }
+        self.rcv_nxt = seg.seq.wrapping_add(1);
+        self.set_peer_mss(seg.mss);
+        self.update_window(seg);
+        if seg.has(ACK) {
+            self.snd_una = seg.ack;
+            self.retransmit_at = None;
+            self.retries = 0;
+            self.state = State::Established;
+            self.send_ack();
+        } else {
+            // Both ends opened at once.
+            self.state = State::SynReceived;
+            self.transmit(SYN | ACK, self.iss, &[]);
+        }
+    }
+
+    /// Returns `true` if a segment of `len` sequence numbers starting at
+    /// `seq` overlaps the receive window.
+    fn acceptable(&self, seq: u32, len: u32) -> bool {
+        let window = self.recv_window() as u32;
+        let end = self.rcv_nxt.wrapping_add(window);
+        let in_window = |seq: u32| seq_le(self.rcv_nxt, seq) && seq_lt(seq, end);
+        match (len, window) {
+            (0, 0) => seq == self.rcv_nxt,
+            (0, _) => in_window(seq),
+            (_, 0) => false,
+            _ => in_window(seq) || in_window(seq.wrapping_add(len - 1)),
+        }
+    }
+
+    /// Processes the acknowledgment and window of `seg`.
+    ///
+    /// # Returns
+    ///
+    /// `false` if the segment acknowledges data never sent and must be
+    /// dropped.
+    fn on_ack(&mut self, seg: &TcpHeader) -> bool {
+        if seq_lt(self.snd_nxt, seg.ack) {
+            self.ack_pending = true;
+            return false;
+        }
+        if seq_lt(self.snd_una, seg.ack) {
+            if let Some((seq, start)) = self.rtt_sample {
+                if seq_le(seq, seg.ack) {
+                    self.rtt_sample = None;
+                    self.update_rtt(timer::ticks_to_duration(timer::ticks() - start));
+                }
+            }
+            let mut fin_acked = false;
+            let data_acked = seg.ack.wrapping_sub(self.buf_seq) as i32;
+            if data_acked > 0 {
+                let drained = (data_acked as usize).min(self.send_buf.len());
+                self.send_buf.drain(..drained);
+                self.buf_seq = self.buf_seq.wrapping_add(drained as u32);
+                fin_acked = self.fin_sent && data_acked as usize > drained;
+            }
+            self.snd_una = seg.ack;
+            self.retries = 0;
+            self.retransmit_at = None;
+            if self.snd_una != self.snd_nxt {
+                self.arm_retransmit();
+            }
+            if fin_acked {
+                match self.state {
+                    State::FinWait1 => {
+                        self.state = State::FinWait2;
+                        if self.orphaned {
+                            self.deadline = Some(deadline(FIN_WAIT_2_TIMEOUT));
+                        }
+                    }
+                    State::Closing => self.enter_time_wait(),
+                    State::LastAck => self.enter_closed(),
+                    _ => {}
+                }
+            }
+        }
+        if seq_lt(self.snd_wl1, seg.seq)
+            || (self.snd_wl1 == seg.seq && seq_le(self.snd_wl2, seg.ack))
+        {
+            self.update_window(seg);
+        }
+        true
+    }
+
+    /// Takes the payload of a segment starting at `seq`.
+    fn on_data(&mut self, seq: u32, payload: &[u8]) {
+        if payload.is_empty()
+            || !matches!(
+                self.state,
+                State::Established | State::FinWait1 | State::FinWait2
+            )
+        {
+            return;
+        }
+        self.ack_pending = true;
+        let (seq, payload) = match self.rcv_nxt.wrapping_sub(seq) as i32 {
+            // Part of it arrived before.
+            skip @ 1.. if (skip as usize) < payload.len() => {
+                (self.rcv_nxt, &payload[skip as usize..])
+            }
+            1.. => return,
+            _ => (seq, payload),
+        };
+        if seq == self.rcv_nxt {
+            self.append(payload);
+            self.drain_out_of_order();
+        } else if self.out_of_order.len() < MAX_OUT_OF_ORDER {
+            self.out_of_order.push((seq, payload.to_vec()));
+        }
+    }
+
+    /// Handles a FIN at sequence number `seq`.
+    fn on_fin(&mut self, seq: u32) {
+        if self.fin_received || seq != self.rcv_nxt {
+            return;
+        }
+        self.fin_received = true;
+        self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+        self.ack_pending = true;
+        match self.state {
+            State::SynReceived | State::Established => self.state = State::CloseWait,
+            State::FinWait1 => self.state = State::Closing,
+            State::FinWait2 => self.enter_time_wait(),
+            _ => {}
+        }
+    }
+
+    /// Appends in-order data to the receive buffer, as far as it has room.
+    fn append(&mut self, data: &[u8]) {
+        let len = data.len().min(self.recv_window());
+        self.recv_buf.extend(&data[..len]);
+        self.rcv_nxt = self.rcv_nxt.wrapping_add(len as u32);
+    }
+
+    /// Moves out-of-order segments that now continue the stream into the
+    /// receive buffer, and drops those that are stale.
+    fn drain_out_of_order(&mut self) {
+        while let Some(at) = self.out_of_order.iter().position(|(seq, data)| {
+            seq_le(*seq, self.rcv_nxt) && seq_lt(self.rcv_nxt, seq.wrapping_add(data.len() as u32))
+        }) {
+            let (seq, data) = self.out_of_order.swap_remove(at);
+            let skip = self.rcv_nxt.wrapping_sub(seq) as usize;
+            self.append(&data[skip..]);
+        }
+        let rcv_nxt = self.rcv_nxt;
+        self.out_of_order
+            .retain(|(seq, data)| seq_lt(rcv_nxt, seq.wrapping_add(data.len() as u32)));
+    }
+
+    /// Sends as much queued data, and the FIN, as the peer's window allows.
+    fn output(&mut self) {
+        if !matches!(
+            self.state,
+            State::Established
+                | State::CloseWait
+                | State::FinWait1
+                | State::Closing
+                | State::LastAck
+        ) {
+            return;
+        }
+        while !self.fin_sent {
+            let offset = self.snd_nxt.wrapping_sub(self.buf_seq) as usize;
+            let pending = self.send_buf.len() - offset;
+            let usable = self
+                .snd_una
+                .wrapping_add(self.snd_wnd)
+                .wrapping_sub(self.snd_nxt) as i32;
+            let len = pending.min(usable.max(0) as usize).min(self.mss);
+            let fin = self.fin_queued && len == pending;
+            if len == 0 && !fin {
+                break;
+            }
+            self.send_data(offset, len, fin);
+        }
+        // Probe a closed window once the timer expires.
+        if self.retransmit_at.is_none() && self.snd_wnd == 0 && self.has_unsent() {
+            self.arm_retransmit();
+        }
+    }
+
+    /// Sends one byte, or the FIN, beyond a closed window.
+    fn output_probe(&mut self) {
+        let offset = self.snd_nxt.wrapping_sub(self.buf_seq) as usize;
+        let len = (self.send_buf.len() - offset).min(1);
+        let fin = self.fin_queued && len == 0;
+        if len > 0 || fin {
+            self.send_data(offset, len, fin);
+        }
+    }
+
+    /// Sends `len` bytes of the send buffer from `offset`, and the FIN if
+    /// `fin` is set, advancing `snd_nxt`.
+    fn send_data(&mut self, offset: usize, len: usize, fin: bool) {
+        let data: Vec<u8> = self.send_buf.range(offset..offset + len).copied().collect();
+        let mut flags = ACK;
+        if len > 0 && offset + len == self.send_buf.len() {
+            flags |= PSH;
+        }
+        if fin {
+            flags |= FIN;
+        }
+        self.transmit(flags, self.snd_nxt, &data);
+        let end = self.snd_nxt.wrapping_add(len as u32 + u32::from(fin));
+        // Karn's rule: retransmissions are not timed.
+        if self.rtt_sample.is_none() && self.retries == 0 {
+            self.rtt_sample = Some((end, timer::ticks()));
+        }
+        self.snd_nxt = end;
+        self.fin_sent |= fin;
+        if self.retransmit_at.is_none() {
+            self.arm_retransmit();
+        }
+    }
+
+    /// Returns `true` if queued data or the FIN has not been sent yet.
+    fn has_unsent(&self) -> bool {
+        let offset = self.snd_nxt.wrapping_sub(self.buf_seq) as usize;
+        offset < self.send_buf.len() || (self.fin_queued && !self.fin_sent)
+    }
+
+    /// Sends an acknowledgment if one is due.
+    fn flush_ack(&mut self) {
+        if self.ack_pending {
+            self.send_ack();
+        }
+    }
+
+    /// Sends an empty segment acknowledging everything received.
+    fn send_ack(&mut self) {
+        self.transmit(ACK, self.snd_nxt, &[]);
+    }
+
+    /// Sends a segment with sequence number `seq`.
+    fn transmit(&mut self, flags: u8, seq: u32, payload: &[u8]) {
+        let header = TcpHeader {
+            src_port: self.local.port(),
+            dst_port: self.remote.port(),
+            seq,
+            ack: if flags & ACK != 0 { self.rcv_nxt } else { 0 },
+            flags,
+            window: self.recv_window().min(usize::from(u16::MAX)) as u16,
+            mss: (flags & SYN != 0).then_some(self.local_mss),
+        };
+        let segment = header.build(self.local.ip(), self.remote.ip(), payload);
+        let _ = ip::send(self.local.ip(), self.remote.ip(), PROTOCOL_TCP, &segment);
+        if flags & ACK != 0 {
+            self.ack_pending = false;
+        }
+    }
+
+    /// Free room in the receive buffer.
+    fn recv_window(&self) -> usize {
+        RECV_BUFFER - self.recv_buf.len()
+    }
+
+    /// Takes the window of `seg`.
+    fn update_window(&mut self, seg: &TcpHeader) {
+        self.snd_wnd = u32::from(seg.window);
+        self.snd_wl1 = seg.seq;
+        self.snd_wl2 = seg.ack;
+    }
+
+    /// Takes the peer's maximum segment size, the default if it sent none.
+    fn set_peer_mss(&mut self, mss: Option<u16>) {
+        let mss = mss.unwrap_or(super::DEFAULT_MSS).min(self.local_mss);
+        self.mss = usize::from(mss).max(1);
+    }
+
+    /// Folds a round-trip time measurement into the timeout (RFC 6298).
+    fn update_rtt(&mut self, rtt: Duration) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(rtt);
+                self.rttvar = rtt / 2;
+            }
+            Some(srtt) => {
+                let delta = srtt.abs_diff(rtt);
+                self.rttvar = (self.rttvar * 3 + delta) / 4;
+                self.srtt = Some((srtt * 7 + rtt) / 8);
+            }
+        }
+        let variance = (self.rttvar * 4).max(timer::ticks_to_duration(1));
+        self.rto = (self.srtt.unwrap_or(rtt) + variance).clamp(MIN_RTO, MAX_RTO);
+    }
+
+    /// Starts the retransmission timer.
+    fn arm_retransmit(&mut self) {
+        self.retransmit_at = Some(deadline(self.rto));
+    }
+
+    /// Waits out old duplicates of the connection's segments.
+    fn enter_time_wait(&mut self) {
+        self.state = State::TimeWait;
+        self.retransmit_at = None;
+        self.deadline = Some(deadline(MSL * 2));
+    }
+
+    /// Ends the connection.
+    fn enter_closed(&mut self) {
+        self.state = State::Closed;
+        self.retransmit_at = None;
+        self.deadline = None;
+    }
+}
+
+/// The tick `delay` from now.
+fn deadline(delay: Duration) -> u64 {
+    timer::ticks() + timer::duration_to_ticks(delay)
+}
@@ -0,0 +1,150 @@
+//! # TFTP Client
+//!
+//! Fetches files from a TFTP server (RFC 1350) with [`get`], e.g. test
+//! programs or an initramfs served by the development host, without
+//! rebuilding the boot image.
+//!
+//! Files are read in octet mode, in blocks of [`BLOCK_SIZE`] bytes that
+//! are acknowledged one at a time. A block that does not arrive within
+//! [`TIMEOUT`] makes the client repeat its last packet, up to
+//! [`MAX_ATTEMPTS`] times. Block numbers wrap around, so files may be
+//! larger than 32 MiB.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::net::{IpAddr, SocketAddr};
+use core::time::Duration;
+
+use super::udp::UdpSocket;
+use super::NetError;
+use crate::println;
+
+/// Port servers listen on for requests.
+pub const PORT: u16 = 69;
+
+/// Bytes of file data per block; a shorter block ends the transfer.
+pub const BLOCK_SIZE: usize = 512;
+
+/// How long the client waits for the next block.
+pub const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Times a packet is sent before the transfer is given up.
+pub const MAX_ATTEMPTS: u32 = 5;
+
+/// Opcode: read request.
+const OP_RRQ: u16 = 1;
+/// Opcode: data block.
+const OP_DATA: u16 = 3;
+/// Opcode: acknowledgment.
+const OP_ACK: u16 = 4;
+/// Opcode: error.
+const OP_ERROR: u16 = 5;
+
+/// Error code: file not found.
+const ERROR_NOT_FOUND: u16 = 1;
+/// Error code: unknown transfer ID, i.e. a packet from a stranger.
+const ERROR_UNKNOWN_TID: u16 = 5;
+
+/// Reads the file `filename` from the TFTP server at `server`.
+///
+/// # Returns
+///
+/// The contents of the file.
+///
+/// # Errors
+///
+/// Returns [`NetError::NotFound`] if the server has no such file,
+/// [`NetError::Rejected`] if it reports any other error,
+/// [`NetError::Timeout`] if it stops answering, and the errors of
+/// [`UdpSocket::bind`] and [`UdpSocket::send_to`].
+///
+/// # Panics
+///
+/// Panics if called from outside a scheduler thread.
+pub fn get(server: IpAddr, filename: &str) -> Result<Vec<u8>, NetError> {
+    let socket = UdpSocket::bind(0)?;
+    let mut request = Vec::with_capacity(filename.len() + 9);
+    request.extend_from_slice(&OP_RRQ.to_be_bytes());
+    request.extend_from_slice(filename.as_bytes());
+    request.extend_from_slice(b"\0octet\0");
+
+    // The server answers from a port of its own, which identifies the
+    // transfer from then on.
+    let mut peer: Option<SocketAddr> = None;
+    let mut last = (request, SocketAddr::new(server, PORT));
+    socket.send_to(&last.0, last.1)?;
+    let mut file = Vec::new();
+    let mut block: u16 = 1;
+    let mut attempts = 1;
+    loop {
+        let datagram = match socket.recv_timeout(TIMEOUT) {
+            Ok(datagram) => datagram,
+            Err(NetError::Timeout) if attempts < MAX_ATTEMPTS => {
+                attempts += 1;
+                socket.send_to(&last.0, last.1)?;
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        let data = &datagram.data;
+        if datagram.src.ip() != server || data.len() < 4 {
+            continue;
+        }
+        if peer.is_some_and(|peer| peer != datagram.src) {
+            let _ = socket.send_to(&error(ERROR_UNKNOWN_TID), datagram.src);
+            continue;
+        }
+        let be16 = |at: usize| u16::from_be_bytes([data[at], data[at + 1]]);
+        match be16(0) {
+            OP_DATA if be16(2) == block => {
+                peer = Some(datagram.src);
+                file.extend_from_slice(&data[4..]);
+                last = (ack(block), datagram.src);
+                socket.send_to(&last.0, last.1)?;
+                if data.len() - 4 < BLOCK_SIZE {
+                    return Ok(file);
+                }
+                block = block.wrapping_add(1);
+                attempts = 1;
+            }
+            // Our acknowledgment was lost; the server repeats the block.
+            OP_DATA if peer.is_some() && be16(2) == block.wrapping_sub(1) => {
+                socket.send_to(&last.0, last.1)?;
+            }
+            OP_ERROR => {
+                let message = &data[4..];
+                let end = message
+                    .iter()
+                    .position(|&b| b == 0)
+                    .unwrap_or(message.len());
+                println!(
+                    "tftp: {}: {}",
+                    filename,
+                    String::from_utf8_lossy(&message[..end])
+                );
+                return Err(match be16(2) {
+                    ERROR_NOT_FOUND => NetError::NotFound,
+                    _ => NetError::Rejected,
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+/// An acknowledgment of `block`.
+fn ack(block: u16) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(4);
+    packet.extend_from_slice(&OP_ACK.to_be_bytes());
+    packet.extend_from_slice(&block.to_be_bytes());
+    packet
+}
+
+/// An error packet with code `code` and no message.
+fn error(code: u16) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(5);
+    packet.extend_from_slice(&OP_ERROR.to_be_bytes());
+    packet.extend_from_slice(&code.to_be_bytes());
+    packet.push(0);
+    packet
+}
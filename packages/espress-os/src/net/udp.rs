@@ -0,0 +1,275 @@
+//! # UDP
+//!
+//! Connectionless datagrams between ports (RFC 768). A [`UdpSocket`] owns a
+//! local port, optionally on one interface only, and queues the datagrams
+//! that arrive for it until they are read. Datagrams for a port nobody has
+//! bound are dropped.
+//!
+//! Sockets are dual-stack: a port is bound for IPv4 and IPv6 at once, and
+//! addresses of either version can be sent to.
+//!
+//! A socket bound to an interface takes precedence over one bound to the
+//! same port on every interface, which lets a DHCP client per interface
+//! share the client port.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::net::{IpAddr, SocketAddr};
+use core::time::Duration;
+use spin::Mutex;
+
+use super::checksum::pseudo_header;
+use super::ipv4::PROTOCOL_UDP;
+use super::{ip, Interface, NetError};
+use crate::scheduler::WaitQueue;
+
+/// Size of a UDP header.
+pub const HEADER_LEN: usize = 8;
+
+/// Datagrams queued per socket; more are dropped until it is read.
+const MAX_QUEUED: usize = 64;
+
+/// Bound sockets by port and interface index, `None` for every interface.
+static SOCKETS: Mutex<BTreeMap<Binding, Arc<Shared>>> = Mutex::new(BTreeMap::new());
+
+/// A local port and the index of the interface it is bound on, if any.
+type Binding = (u16, Option<usize>);
+
+/// A received datagram.
+#[derive(Debug, Clone)]
+pub struct Datagram {
+    /// Sender
+    pub src: SocketAddr,
+    /// Address it was sent to, possibly a broadcast or multicast address
+    pub dst: IpAddr,
+    /// Interface it arrived on
+    pub interface: Arc<Interface>,
+    /// Payload
+    pub data: Vec<u8>,
+}
+
+/// State shared between a socket and the receive path.
+struct Shared {
+    /// Datagrams not yet read, oldest first
+    queue: Mutex<VecDeque<Datagram>>,
+    /// Where readers wait for datagrams
+    ready: WaitQueue,
+}
+
+/// A bound UDP port. Dropping the socket releases the port.
+pub struct UdpSocket {
+    /// Local port
+    port: u16,
+    /// Index of the interface the socket is bound to, if any
+    interface: Option<usize>,
+    /// Receive queue
+    shared: Arc<Shared>,
+}
+
+impl UdpSocket {
+    /// Binds `port` on every interface, or a free ephemeral port if `port`
+    /// is 0.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NetError::AddressInUse`] if the port is taken or no
+    /// ephemeral port is free.
+    pub fn bind(port: u16) -> Result<Self, NetError> {
+        Self::bind_to(port, None)
+    }
+
+    /// Binds `port` on `interface` only.
+    ///
+    /// # Errors
+    ///
+    /// See [`bind`](Self::bind).
+    pub fn bind_interface(port: u16, interface: &Interface) -> Result<Self, NetError> {
+        Self::bind_to(port, Some(interface.index()))
+    }
+
+    /// Binds `port` on the interface with index `interface`, or on every
+    /// interface.
+    fn bind_to(port: u16, interface: Option<usize>) -> Result<Self, NetError> {
+        let mut sockets = SOCKETS.lock();
+        let port = match port {
+            0 => super::ephemeral_port(|port| sockets.contains_key(&(port, interface)))
+                .ok_or(NetError::AddressInUse)?,
+            port if sockets.contains_key(&(port, interface)) => return Err(NetError::AddressInUse),
+            port => port,
+        };
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(VecDeque::new()),
+            ready: WaitQueue::new(),
+        });
+        sockets.insert((port, interface), shared.clone());
+        Ok(UdpSocket {
+            port,
+            interface,
+            shared,
+        })
+    }
+
+    /// The local port.
+    pub fn local_port(&self) -> u16 {
+        self.port
+    }
+
+    /// Sends `data` to `dst`, routed like any other datagram.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NetError::PacketTooLong`] if `data` does not fit a
+    /// datagram, and the errors of [`ip::route`] and [`ip::send`].
+    pub fn send_to(&self, data: &[u8], dst: SocketAddr) -> Result<(), NetError> {
+        let (_, src) = ip::route(dst.ip())?;
+        let segment = build(src, self.port, dst, data)?;
+        ip::send(src, dst.ip(), PROTOCOL_UDP, &segment)
+    }
+
+    /// Sends `data` from `src` to `dst` through `interface`, bypassing the
+    /// routing table; for broadcasts before the interface has an address.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NetError::PacketTooLong`] if `data` does not fit a
+    /// datagram, and the errors of [`ip::send_via`].
+    pub fn send_via(
+        &self,
+        interface: &Arc<Interface>,
+        src: IpAddr,
+        dst: SocketAddr,
+        data: &[u8],
+    ) -> Result<(), NetError> {
+        let segment = build(src, self.port, dst, data)?;
+        ip::send_via(interface, src, dst.ip(), PROTOCOL_UDP, &segment)
+    }
+
+    /// Takes the oldest queued datagram without blocking.
+    pub fn try_recv(&self) -> Option<Datagram> {
+        self.shared.queue.lock().pop_front()
+    }
+
+    /// Waits for a datagram.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from outside a scheduler thread.
+    pub fn recv(&self) -> Datagram {
+        let mut datagram = None;
+        self.shared.ready.wait_until(|| {
+            datagram = self.try_recv();
+            datagram.is_some()
+        });
+        datagram.expect("woken without a datagram")
+    }
+
+    /// Waits up to `timeout` for a datagram.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NetError::Timeout`] if none arrives in time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from outside a scheduler thread.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Datagram, NetError> {
+        let mut datagram = None;
+        self.shared.ready.wait_until_timeout(timeout, || {
+            datagram = self.try_recv();
+            datagram.is_some()
+        });
+        datagram.ok_or(NetError::Timeout)
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        SOCKETS.lock().remove(&(self.port, self.interface));
+    }
+}
+
+/// Handles a segment received from `src` for `dst`.
+///
+/// # Returns
+///
+/// `false` if the segment is malformed or no socket is bound to its port.
+pub(super) fn receive(
+    interface: &Arc<Interface>,
+    src: IpAddr,
+    dst: IpAddr,
+    segment: &[u8],
+) -> bool {
+    if segment.len() < HEADER_LEN {
+        return false;
+    }
+    let be16 = |at: usize| u16::from_be_bytes([segment[at], segment[at + 1]]);
+    let len = usize::from(be16(4));
+    if len < HEADER_LEN || len > segment.len() {
+        return false;
+    }
+    let segment = &segment[..len];
+    // A zero checksum means the sender did not compute one, which IPv6
+    // does not allow.
+    if (be16(6) != 0 || dst.is_ipv6())
+        && pseudo_header(src, dst, PROTOCOL_UDP, len)
+            .add(segment)
+            .finish()
+            != 0
+    {
+        return false;
+    }
+
+    let port = be16(2);
+    let shared = {
+        let sockets = SOCKETS.lock();
+        sockets
+            .get(&(port, Some(interface.index())))
+            .or_else(|| sockets.get(&(port, None)))
+            .cloned()
+    };
+    let Some(shared) = shared else {
+        return false;
+    };
+    let mut queue = shared.queue.lock();
+    if queue.len() == MAX_QUEUED {
+        return false;
+    }
+    queue.push_back(Datagram {
+        src: SocketAddr::new(src, be16(0)),
+        dst,
+        interface: interface.clone(),
+        data: segment[HEADER_LEN..].to_vec(),
+    });
+    drop(queue);
+    shared.ready.notify_all();
+    true
+}
+
+/// Builds a segment with its checksum.
+///
+/// # Errors
+///
+/// Returns [`NetError::PacketTooLong`] if `data` does not fit a datagram.
+fn build(src: IpAddr, src_port: u16, dst: SocketAddr, data: &[u8]) -> Result<Vec<u8>, NetError> {
+    let len = HEADER_LEN + data.len();
+    if len > ip::max_payload_len(dst.ip()) {
+        return Err(NetError::PacketTooLong);
+    }
+    let mut segment = Vec::with_capacity(len);
+    segment.extend_from_slice(&src_port.to_be_bytes());
+    segment.extend_from_slice(&dst.port().to_be_bytes());
+    segment.extend_from_slice(&(len as u16).to_be_bytes());
+    segment.extend_from_slice(&[0, 0]);
+    segment.extend_from_slice(data);
+    // A computed zero is sent as all ones; zero means no checksum.
+    let sum = match pseudo_header(src, dst.ip(), PROTOCOL_UDP, len)
+        .add(&segment)
+        .finish()
+    {
+        0 => 0xffff,
+        sum => sum,
+    };
+    segment[6..8].copy_from_slice(&sum.to_be_bytes());
+    Ok(segment)
+}
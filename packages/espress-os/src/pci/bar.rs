@@ -0,0 +1,130 @@
+//! # Base Address Registers
+//!
+//! A function's BARs tell where its memory and I/O port windows were placed
+//! by the firmware. Each is either an I/O BAR or a memory BAR; a 64-bit
+//! memory BAR takes up two consecutive slots. The size of a window is found
+//! by writing all ones and reading back which address bits stick, with
+//! decoding switched off meanwhile so the device does not answer at the
+//! bogus address.
+
+use x86_64::PhysAddr;
+
+use super::{PciDevice, COMMAND};
+
+/// Offset of the first BAR.
+const BAR0: u16 = 0x10;
+
+/// Number of BARs in a type 0 header.
+pub const BAR_COUNT: u8 = 6;
+
+/// Command register bit enabling I/O space decoding.
+pub const COMMAND_IO: u16 = 1 << 0;
+/// Command register bit enabling memory space decoding.
+pub const COMMAND_MEMORY: u16 = 1 << 1;
+/// Command register bit allowing the function to master the bus (DMA).
+pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+/// Command register bit masking the legacy INTx interrupt.
+pub const COMMAND_INTX_DISABLE: u16 = 1 << 10;
+
+/// A decoded base address register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    /// A memory window
+    Memory {
+        /// Physical start address
+        address: PhysAddr,
+        /// Size in bytes
+        size: u64,
+        /// Reads have no side effects, so the window may be cached
+        prefetchable: bool,
+    },
+    /// An I/O port window
+    Io {
+        /// First port
+        port: u16,
+        /// Number of ports
+        size: u16,
+    },
+}
+
+impl PciDevice {
+    /// Decodes BAR `index`.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the index is out of range for the header type, the BAR is
+    /// unimplemented or unassigned, or it is the upper half of a 64-bit BAR.
+    pub fn bar(&self, index: u8) -> Option<Bar> {
+        let count = match self.header_type {
+            0 => BAR_COUNT,
+            1 => 2,
+            _ => 0,
+        };
+        if index >= count {
+            return None;
+        }
+        let offset = BAR0 + u16::from(index) * 4;
+        let low = self.address.read_u32(offset);
+
+        if low & 1 != 0 {
+            let size_mask = self.size_bar(offset, low) | 0xffff_0000;
+            let size = (!(size_mask & !0x3)).wrapping_add(1);
+            let port = (low & !0x3) as u16;
+            return (port != 0 && size != 0).then_some(Bar::Io {
+                port,
+                size: size as u16,
+            });
+        }
+
+        let is_64bit = (low >> 1) & 0x3 == 0x2;
+        let prefetchable = low & (1 << 3) != 0;
+        let (address, size) = if is_64bit {
+            if index + 1 >= count {
+                return None;
+            }
+            let high = self.address.read_u32(offset + 4);
+            let size_low = self.size_bar(offset, low);
+            let size_high = self.size_bar(offset + 4, high);
+            let mask = (u64::from(size_high) << 32) | u64::from(size_low & !0xf);
+            let address = (u64::from(high) << 32) | u64::from(low & !0xf);
+            (address, (!mask).wrapping_add(1))
+        } else {
+            let mask = self.size_bar(offset, low) & !0xf;
+            (u64::from(low & !0xf), u64::from((!mask).wrapping_add(1)))
+        };
+        if address == 0 || size == 0 {
+            return None;
+        }
+        Some(Bar::Memory {
+            address: PhysAddr::try_new(address).ok()?,
+            size,
+            prefetchable,
+        })
+    }
+
+    /// Sets `bits` in the command register, e.g. to let the function decode
+    /// its windows and perform DMA.
+    pub fn enable(&self, bits: u16) {
+        let command = self.address.read_u16(COMMAND);
+        self.address.write_u16(COMMAND, command | bits);
+    }
+
+    /// Clears `bits` in the command register.
+    pub fn disable(&self, bits: u16) {
+        let command = self.address.read_u16(COMMAND);
+        self.address.write_u16(COMMAND, command & !bits);
+    }
+
+    /// Writes all ones to the BAR register at `offset`, reads back the size
+    /// mask, and restores `original`, with decoding off in between.
+    fn size_bar(&self, offset: u16, original: u32) -> u32 {
+        let command = self.address.read_u16(COMMAND);
+        self.address
+            .write_u16(COMMAND, command & !(COMMAND_IO | COMMAND_MEMORY));
+        self.address.write_u32(offset, u32::MAX);
+        let mask = self.address.read_u32(offset);
+        self.address.write_u32(offset, original);
+        self.address.write_u16(COMMAND, command);
+        mask
+    }
+}
@@ -0,0 +1,253 @@
+//! # Capability Lists
+//!
+//! Optional features of a function are described by capability structures
+//! chained into linked lists in configuration space:
+//!
+//! - The standard list starts at the capabilities pointer (offset `0x34`)
+//!   if the status register says it exists. Each entry starts with an 8-bit
+//!   ID and the offset of the next entry.
+//! - The PCIe extended list starts at offset 256 and needs ECAM. Each entry
+//!   starts with a 32-bit header holding a 16-bit ID, a version, and the
+//!   offset of the next entry.
+//!
+//! Both walks stop after as many entries as could fit, so a corrupt list
+//! that loops cannot hang the kernel.
+
+use super::config::{EXTENDED_CONFIG_SIZE, LEGACY_CONFIG_SIZE};
+use super::{PciAddress, PciDevice, STATUS};
+
+/// Offset of the capabilities pointer in type 0 and type 1 headers.
+const CAPABILITIES_POINTER: u16 = 0x34;
+
+/// Status register bit announcing the standard capability list.
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+
+/// First offset past the standard header, where capabilities may start.
+const FIRST_CAPABILITY: u16 = 0x40;
+
+/// Capability ID of power management.
+pub const POWER_MANAGEMENT: u8 = 0x01;
+/// Capability ID of Message Signaled Interrupts.
+pub const MSI: u8 = 0x05;
+/// Capability ID of vendor-specific capabilities.
+pub const VENDOR_SPECIFIC: u8 = 0x09;
+/// Capability ID of the PCI Express capability.
+pub const PCI_EXPRESS: u8 = 0x10;
+/// Capability ID of MSI-X.
+pub const MSIX: u8 = 0x11;
+
+/// An entry of the standard capability list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capability {
+    /// Capability ID
+    pub id: u8,
+    /// Offset of the entry in configuration space
+    pub offset: u16,
+}
+
+/// An entry of the PCIe extended capability list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExtendedCapability {
+    /// Extended capability ID
+    pub id: u16,
+    /// Structure version
+    pub version: u8,
+    /// Offset of the entry in configuration space
+    pub offset: u16,
+}
+
+/// Iterator over the standard capability list of a function.
+pub struct Capabilities {
+    /// Function whose list is walked
+    address: PciAddress,
+    /// Offset of the next entry, zero at the end
+    next: u16,
+    /// Entries that may still follow
+    remaining: u16,
+}
+
+impl Iterator for Capabilities {
+    type Item = Capability;
+
+    fn next(&mut self) -> Option<Capability> {
+        if self.next < FIRST_CAPABILITY || self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let offset = self.next;
+        let header = self.address.read_u16(offset);
+        // The low two bits of the pointer are reserved.
+        self.next = (header >> 8) & 0xfc;
+        Some(Capability {
+            id: header as u8,
+            offset,
+        })
+    }
+}
+
+/// Iterator over the PCIe extended capability list of a function.
+pub struct ExtendedCapabilities {
+    /// Function whose list is walked
+    address: PciAddress,
+    /// Offset of the next entry, zero at the end
+    next: u16,
+    /// Entries that may still follow
+    remaining: u16,
+}
+
+impl Iterator for ExtendedCapabilities {
+    type Item = ExtendedCapability;
+
+    fn next(&mut self) -> Option<ExtendedCapability> {
+        if self.next < LEGACY_CONFIG_SIZE || self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let offset = self.next;
+        let header = self.address.read_u32(offset);
+        // An empty list has a zero header; an unreachable one reads as ones.
+        if header == 0 || header == u32::MAX {
+            self.next = 0;
+            return None;
+        }
+        self.next = (header >> 20) as u16 & 0xffc;
+        Some(ExtendedCapability {
+            id: header as u16,
+            version: (header >> 16) as u8 & 0xf,
+            offset,
+        })
+    }
+}
+
+/// The MSI capability of a function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Msi {
+    /// Offset of the capability in configuration space
+    pub offset: u16,
+    /// Message control register
+    pub control: u16,
+}
+
+impl Msi {
+    /// Returns `true` if MSI is enabled.
+    pub fn is_enabled(&self) -> bool {
+        self.control & 1 != 0
+    }
+
+    /// Number of vectors the function can request, a power of two up to 32.
+    pub fn max_vectors(&self) -> u8 {
+        1 << ((self.control >> 1) & 0x7).min(5)
+    }
+
+    /// Returns `true` if the message address may lie above 4 GiB.
+    pub fn is_64bit(&self) -> bool {
+        self.control & (1 << 7) != 0
+    }
+
+    /// Returns `true` if individual vectors can be masked.
+    pub fn per_vector_masking(&self) -> bool {
+        self.control & (1 << 8) != 0
+    }
+}
+
+/// The MSI-X capability of a function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsiX {
+    /// Offset of the capability in configuration space
+    pub offset: u16,
+    /// Message control register
+    pub control: u16,
+    /// Index of the BAR holding the vector table
+    pub table_bar: u8,
+    /// Offset of the vector table within its BAR
+    pub table_offset: u32,
+    /// Index of the BAR holding the pending bit array
+    pub pba_bar: u8,
+    /// Offset of the pending bit array within its BAR
+    pub pba_offset: u32,
+}
+
+impl MsiX {
+    /// Returns `true` if MSI-X is enabled.
+    pub fn is_enabled(&self) -> bool {
+        self.control & (1 << 15) != 0
+    }
+
+    /// Number of entries in the vector table.
+    pub fn table_size(&self) -> u16 {
+        (self.control & 0x7ff) + 1
+    }
+}
+
+impl PciDevice {
+    /// Returns an iterator over the standard capability list.
+    ///
+    /// The list is empty if the function has none, or if its header type
+    /// has no capabilities pointer at the usual place (CardBus bridges).
+    pub fn capabilities(&self) -> Capabilities {
+        let has_list =
+            self.header_type <= 1 && self.address.read_u16(STATUS) & STATUS_CAPABILITIES != 0;
+        let next = if has_list {
+            u16::from(self.address.read_u8(CAPABILITIES_POINTER) & 0xfc)
+        } else {
+            0
+        };
+        Capabilities {
+            address: self.address,
+            next,
+            remaining: (LEGACY_CONFIG_SIZE - FIRST_CAPABILITY) / 4,
+        }
+    }
+
+    /// Returns an iterator over the PCIe extended capability list.
+    ///
+    /// The list is empty unless the extended configuration space is
+    /// reachable through ECAM.
+    pub fn extended_capabilities(&self) -> ExtendedCapabilities {
+        let next = if self.address.has_extended_config() {
+            LEGACY_CONFIG_SIZE
+        } else {
+            0
+        };
+        ExtendedCapabilities {
+            address: self.address,
+            next,
+            remaining: (EXTENDED_CONFIG_SIZE - LEGACY_CONFIG_SIZE) / 4,
+        }
+    }
+
+    /// Returns the first standard capability with `id`.
+    pub fn find_capability(&self, id: u8) -> Option<Capability> {
+        self.capabilities().find(|capability| capability.id == id)
+    }
+
+    /// Returns the first extended capability with `id`.
+    pub fn find_extended_capability(&self, id: u16) -> Option<ExtendedCapability> {
+        self.extended_capabilities()
+            .find(|capability| capability.id == id)
+    }
+
+    /// Returns the MSI capability, if the function has one.
+    pub fn msi(&self) -> Option<Msi> {
+        let capability = self.find_capability(MSI)?;
+        Some(Msi {
+            offset: capability.offset,
+            control: self.address.read_u16(capability.offset + 2),
+        })
+    }
+
+    /// Returns the MSI-X capability, if the function has one.
+    pub fn msix(&self) -> Option<MsiX> {
+        let capability = self.find_capability(MSIX)?;
+        let table = self.address.read_u32(capability.offset + 4);
+        let pba = self.address.read_u32(capability.offset + 8);
+        Some(MsiX {
+            offset: capability.offset,
+            control: self.address.read_u16(capability.offset + 2),
+            table_bar: (table & 0x7) as u8,
+            table_offset: table & !0x7,
+            pba_bar: (pba & 0x7) as u8,
+            pba_offset: pba & !0x7,
+        })
+    }
+}
@@ -0,0 +1,172 @@
+//! # Configuration Space Access
+//!
+//! Two mechanisms reach a function's configuration space:
+//!
+//! - **ECAM**: PCIe maps 4 KiB per function into memory, including the
+//!   extended space above offset 256. The windows come from the ACPI MCFG
+//!   table and are mapped once by [`init`]; accesses are plain volatile
+//!   loads and stores and need no lock.
+//! - **Legacy port I/O**: the address is written to `CONFIG_ADDRESS`, then
+//!   the data is transferred through `CONFIG_DATA`. This only reaches the
+//!   first 256 bytes of segment 0, and the two steps must not be
+//!   interleaved, so they run under a lock with interrupts disabled.
+//!
+//! Every access uses ECAM if a window covers the bus and legacy port I/O
+//! otherwise. Reads of unreachable registers return all ones, like reads
+//! from an absent device; writes to them are dropped.
+
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::{Port, PortRead, PortWrite};
+use x86_64::VirtAddr;
+
+use super::PciAddress;
+use crate::acpi::mcfg::EcamRegion;
+use crate::mm::mmio;
+
+/// I/O port selecting the register for legacy accesses.
+const CONFIG_ADDRESS: u16 = 0xcf8;
+
+/// I/O port through which legacy accesses transfer data.
+const CONFIG_DATA: u16 = 0xcfc;
+
+/// Size of a function's configuration space through ECAM.
+pub const EXTENDED_CONFIG_SIZE: u16 = 4096;
+
+/// Size of a function's configuration space through legacy port I/O.
+pub const LEGACY_CONFIG_SIZE: u16 = 256;
+
+/// Mapped ECAM windows; empty if the machine has none.
+static ECAM: OnceCell<Vec<EcamWindow>> = OnceCell::uninit();
+
+/// Serializes legacy address/data pairs.
+static LEGACY: Mutex<()> = Mutex::new(());
+
+/// A mapped ECAM window.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct EcamWindow {
+    /// PCI segment group
+    pub segment: u16,
+    /// First bus decoded by the window
+    pub start_bus: u8,
+    /// Last bus decoded by the window
+    pub end_bus: u8,
+    /// Virtual address of the configuration space of `start_bus`
+    base: VirtAddr,
+}
+
+/// A configuration register width.
+pub(super) trait Register: Copy + PortRead + PortWrite {
+    /// Value read from an absent device
+    const ALL_ONES: Self;
+}
+
+impl Register for u8 {
+    const ALL_ONES: Self = u8::MAX;
+}
+
+impl Register for u16 {
+    const ALL_ONES: Self = u16::MAX;
+}
+
+impl Register for u32 {
+    const ALL_ONES: Self = u32::MAX;
+}
+
+/// Maps the ECAM windows in `regions`; windows that cannot be mapped are
+/// left to legacy port I/O.
+///
+/// # Panics
+///
+/// Panics if called more than once.
+pub(super) fn init(regions: &[EcamRegion]) -> &'static [EcamWindow] {
+    let mapped = regions
+        .iter()
+        .filter_map(|region| {
+            let base = mmio::map(region.base, region.size()).ok()?;
+            Some(EcamWindow {
+                segment: region.segment,
+                start_bus: region.start_bus,
+                end_bus: region.end_bus,
+                base,
+            })
+        })
+        .collect();
+    ECAM.try_init_once(|| mapped)
+        .expect("PCI configuration space initialized twice");
+    windows()
+}
+
+/// Returns the mapped ECAM windows.
+pub(super) fn windows() -> &'static [EcamWindow] {
+    ECAM.get().map_or(&[], Vec::as_slice)
+}
+
+/// Returns `true` if the extended configuration space of `addr` is
+/// reachable, i.e. an ECAM window covers its bus.
+pub(super) fn has_extended(addr: PciAddress) -> bool {
+    window(addr).is_some()
+}
+
+/// Reads the register at `offset`, which must be aligned to its size.
+pub(super) fn read<T: Register>(addr: PciAddress, offset: u16) -> T {
+    debug_assert!((offset as usize).is_multiple_of(size_of::<T>()));
+    if let Some(window) = window(addr) {
+        if offset < EXTENDED_CONFIG_SIZE {
+            return unsafe { ecam_pointer::<T>(window, addr, offset).read_volatile() };
+        }
+    } else if addr.segment == 0 && offset < LEGACY_CONFIG_SIZE {
+        return interrupts::without_interrupts(|| {
+            let _guard = LEGACY.lock();
+            unsafe {
+                Port::new(CONFIG_ADDRESS).write(legacy_address(addr, offset));
+                Port::<T>::new(CONFIG_DATA + (offset & 3)).read()
+            }
+        });
+    }
+    T::ALL_ONES
+}
+
+/// Writes the register at `offset`, which must be aligned to its size.
+pub(super) fn write<T: Register>(addr: PciAddress, offset: u16, value: T) {
+    debug_assert!((offset as usize).is_multiple_of(size_of::<T>()));
+    if let Some(window) = window(addr) {
+        if offset < EXTENDED_CONFIG_SIZE {
+            unsafe { ecam_pointer::<T>(window, addr, offset).write_volatile(value) };
+        }
+    } else if addr.segment == 0 && offset < LEGACY_CONFIG_SIZE {
+        interrupts::without_interrupts(|| {
+            let _guard = LEGACY.lock();
+            unsafe {
+                Port::new(CONFIG_ADDRESS).write(legacy_address(addr, offset));
+                Port::<T>::new(CONFIG_DATA + (offset & 3)).write(value);
+            }
+        });
+    }
+}
+
+/// Returns the ECAM window covering the bus of `addr`, if any.
+fn window(addr: PciAddress) -> Option<&'static EcamWindow> {
+    windows().iter().find(|window| {
+        window.segment == addr.segment && (window.start_bus..=window.end_bus).contains(&addr.bus)
+    })
+}
+
+/// Returns the address of a register in an ECAM window.
+fn ecam_pointer<T>(window: &EcamWindow, addr: PciAddress, offset: u16) -> *mut T {
+    let function = (u64::from(addr.bus - window.start_bus) << 20)
+        | (u64::from(addr.device) << 15)
+        | (u64::from(addr.function) << 12);
+    (window.base + function + u64::from(offset)).as_mut_ptr()
+}
+
+/// Returns the `CONFIG_ADDRESS` value selecting the dword at `offset`.
+fn legacy_address(addr: PciAddress, offset: u16) -> u32 {
+    (1 << 31)
+        | (u32::from(addr.bus) << 16)
+        | (u32::from(addr.device) << 11)
+        | (u32::from(addr.function) << 8)
+        | u32::from(offset & 0xfc)
+}
@@ -0,0 +1,306 @@
+//! # PCI
+//!
+//! Enumerates the PCI and PCIe functions of the machine and gives drivers
+//! access to their configuration space.
+//!
+//! [`init`] reads the ECAM windows from the ACPI MCFG table and maps them,
+//! so configuration space up to 4 KiB per function is reachable as memory;
+//! buses not covered by a window, or every bus on machines without MCFG,
+//! fall back to legacy port I/O and its 256 bytes (see [`config`]). It then
+//! scans every bus by brute force and records each function it finds, see
+//! [`devices`].
+//!
+//! Drivers locate their features through the capability lists:
+//! [`PciDevice::capabilities`] walks the standard list, where MSI and MSI-X
+//! live, and [`PciDevice::extended_capabilities`] the PCIe extended list.
+//! [`PciDevice::bar`] decodes the windows through which the device's
+//! registers are reached.
+
+pub mod bar;
+pub mod capability;
+pub mod config;
+
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use core::fmt;
+
+use crate::acpi;
+use crate::println;
+
+pub use bar::Bar;
+pub use capability::{Capability, ExtendedCapability, Msi, MsiX};
+
+/// Offset of the vendor ID register.
+pub const VENDOR_ID: u16 = 0x00;
+/// Offset of the device ID register.
+pub const DEVICE_ID: u16 = 0x02;
+/// Offset of the command register.
+pub const COMMAND: u16 = 0x04;
+/// Offset of the status register.
+pub const STATUS: u16 = 0x06;
+/// Offset of the revision ID register.
+pub const REVISION_ID: u16 = 0x08;
+/// Offset of the programming interface register.
+pub const PROG_IF: u16 = 0x09;
+/// Offset of the subclass register.
+pub const SUBCLASS: u16 = 0x0a;
+/// Offset of the class code register.
+pub const CLASS: u16 = 0x0b;
+/// Offset of the header type register.
+pub const HEADER_TYPE: u16 = 0x0e;
+/// Offset of the subsystem ID register (type 0 headers).
+pub const SUBSYSTEM_ID: u16 = 0x2e;
+/// Offset of the interrupt line register, the legacy IRQ routed by the
+/// firmware.
+pub const INTERRUPT_LINE: u16 = 0x3c;
+
+/// Vendor ID read from a slot without a function.
+const NO_VENDOR: u16 = 0xffff;
+
+/// Header type bit marking a device with several functions.
+const MULTIFUNCTION: u8 = 0x80;
+
+/// Functions found by [`init`].
+static DEVICES: OnceCell<Vec<PciDevice>> = OnceCell::uninit();
+
+/// Location of a function: segment group, bus, device, and function number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PciAddress {
+    /// PCI segment group
+    pub segment: u16,
+    /// Bus number
+    pub bus: u8,
+    /// Device number, below 32
+    pub device: u8,
+    /// Function number, below 8
+    pub function: u8,
+}
+
+impl PciAddress {
+    /// Creates an address.
+    pub const fn new(segment: u16, bus: u8, device: u8, function: u8) -> Self {
+        PciAddress {
+            segment,
+            bus,
+            device,
+            function,
+        }
+    }
+
+    /// Reads the byte register at `offset`.
+    pub fn read_u8(self, offset: u16) -> u8 {
+        config::read(self, offset)
+    }
+
+    /// Reads the 16-bit register at `offset`, which must be 2-byte aligned.
+    pub fn read_u16(self, offset: u16) -> u16 {
+        config::read(self, offset)
+    }
+
+    /// Reads the 32-bit register at `offset`, which must be 4-byte aligned.
+    pub fn read_u32(self, offset: u16) -> u32 {
+        config::read(self, offset)
+    }
+
+    /// Writes the byte register at `offset`.
+    pub fn write_u8(self, offset: u16, value: u8) {
+        config::write(self, offset, value);
+    }
+
+    /// Writes the 16-bit register at `offset`, which must be 2-byte
+    /// aligned.
+    pub fn write_u16(self, offset: u16, value: u16) {
+        config::write(self, offset, value);
+    }
+
+    /// Writes the 32-bit register at `offset`, which must be 4-byte
+    /// aligned.
+    pub fn write_u32(self, offset: u16, value: u32) {
+        config::write(self, offset, value);
+    }
+
+    /// Returns `true` if the extended configuration space (offsets 256 to
+    /// 4095) of the function is reachable.
+    pub fn has_extended_config(self) -> bool {
+        config::has_extended(self)
+    }
+}
+
+impl fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04x}:{:02x}:{:02x}.{}",
+            self.segment, self.bus, self.device, self.function
+        )
+    }
+}
+
+/// A function found during enumeration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciDevice {
+    /// Where the function is
+    pub address: PciAddress,
+    /// Vendor ID
+    pub vendor_id: u16,
+    /// Device ID
+    pub device_id: u16,
+    /// Base class code
+    pub class: u8,
+    /// Subclass code
+    pub subclass: u8,
+    /// Programming interface
+    pub prog_if: u8,
+    /// Revision ID
+    pub revision: u8,
+    /// Header layout, without the multifunction bit
+    pub header_type: u8,
+}
+
+impl PciDevice {
+    /// Reads the identification registers of the function at `address`.
+    ///
+    /// # Returns
+    ///
+    /// `None` if there is no function at `address`.
+    pub fn probe(address: PciAddress) -> Option<Self> {
+        let vendor_id = address.read_u16(VENDOR_ID);
+        if vendor_id == NO_VENDOR {
+            return None;
+        }
+        Some(PciDevice {
+            address,
+            vendor_id,
+            device_id: address.read_u16(DEVICE_ID),
+            class: address.read_u8(CLASS),
+            subclass: address.read_u8(SUBCLASS),
+            prog_if: address.read_u8(PROG_IF),
+            revision: address.read_u8(REVISION_ID),
+            header_type: address.read_u8(HEADER_TYPE) & !MULTIFUNCTION,
+        })
+    }
+}
+
+/// How configuration space is reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigMechanism {
+    /// Memory-mapped through at least one ECAM window
+    Ecam,
+    /// Legacy port I/O only
+    Legacy,
+}
+
+/// Maps the ECAM windows, if the firmware describes any, and enumerates
+/// every function.
+///
+/// Must be called after memory management is initialized.
+///
+/// # Panics
+///
+/// Panics if called more than once.
+pub fn init() {
+    if let Err(err) = acpi::init() {
+        println!("acpi: no usable tables ({:?})", err);
+    }
+    if let Some(madt) = acpi::madt::madt() {
+        println!(
+            "acpi: {} CPUs, {} I/O APICs, {} IRQ overrides",
+            madt.cpu_count(),
+            madt.io_apics.len(),
+            madt.overrides.len()
+        );
+    }
+    let windows = config::init(&acpi::mcfg::ecam_regions());
+    for window in windows {
+        println!(
+            "pci: ECAM for segment {:04x}, buses {:02x}-{:02x}",
+            window.segment, window.start_bus, window.end_bus
+        );
+    }
+
+    let mut devices = Vec::new();
+    if windows.is_empty() {
+        scan_bus_range(0, 0..=u8::MAX, &mut devices);
+    } else {
+        for window in windows {
+            scan_bus_range(
+                window.segment,
+                window.start_bus..=window.end_bus,
+                &mut devices,
+            );
+        }
+    }
+    println!(
+        "pci: {} functions found via {:?}",
+        devices.len(),
+        mechanism()
+    );
+    DEVICES
+        .try_init_once(|| devices)
+        .expect("PCI initialized twice");
+}
+
+/// Returns how configuration space is reached.
+pub fn mechanism() -> ConfigMechanism {
+    if config::windows().is_empty() {
+        ConfigMechanism::Legacy
+    } else {
+        ConfigMechanism::Ecam
+    }
+}
+
+/// Returns every function found by [`init`], ordered by address.
+pub fn devices() -> &'static [PciDevice] {
+    DEVICES.get().map_or(&[], Vec::as_slice)
+}
+
+/// Returns the first function with the given vendor and device ID.
+pub fn find(vendor_id: u16, device_id: u16) -> Option<&'static PciDevice> {
+    devices()
+        .iter()
+        .find(|device| device.vendor_id == vendor_id && device.device_id == device_id)
+}
+
+/// Prints every function with its IDs, class, and capabilities.
+pub fn print_devices() {
+    for device in devices() {
+        println!(
+            "  {}  {:04x}:{:04x}  class {:02x}.{:02x}.{:02x}{}{}",
+            device.address,
+            device.vendor_id,
+            device.device_id,
+            device.class,
+            device.subclass,
+            device.prog_if,
+            if device.msi().is_some() { "  msi" } else { "" },
+            if device.msix().is_some() {
+                "  msi-x"
+            } else {
+                ""
+            }
+        );
+    }
+}
+
+/// Appends every function on the buses in `buses` of `segment`.
+fn scan_bus_range(
+    segment: u16,
+    buses: core::ops::RangeInclusive<u8>,
+    devices: &mut Vec<PciDevice>,
+) {
+    for bus in buses {
+        for device in 0..32 {
+            let Some(first) = PciDevice::probe(PciAddress::new(segment, bus, device, 0)) else {
+                continue;
+            };
+            devices.push(first);
+            let header_type = first.address.read_u8(HEADER_TYPE);
+            if header_type & MULTIFUNCTION == 0 {
+                continue;
+            }
+            devices.extend((1..8).filter_map(|function| {
+                PciDevice::probe(PciAddress::new(segment, bus, device, function))
+            }));
+        }
+    }
+}
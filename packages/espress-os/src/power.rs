@@ -0,0 +1,250 @@
+//! # Power Management
+//!
+//! Turning the machine off, restarting it, and suspending it to RAM.
+//!
+//! [`shutdown`] writes back the file systems and then calls [`power_off`],
+//! which enters ACPI sleep state S5 (soft off) as the FADT and DSDT
+//! describe it (see [`acpi::fadt`]). Where that is not possible, it falls
+//! back to the shutdown ports of the usual emulators: QEMU's ACPI device
+//! at `0x604`, Bochs and older QEMU at `0xb004`, and VirtualBox at
+//! `0x4004`. On anything else the CPU is halted.
+//!
+//! [`reboot`] likewise writes back the file systems and calls [`reset`],
+//! which tries the ACPI reset register, then the reset line of the 8042
+//! keyboard controller, and finally a triple fault, which resets every PC.
+//!
+//! [`suspend`] puts the machine into ACPI sleep state S3, in which only
+//! memory keeps its contents, and returns once it wakes up. The CPU state
+//! is saved and restored by `arch::suspend`, whose trampoline the firmware
+//! runs on wakeup; of the devices, the interrupt controllers, the interval
+//! timer, the serial port, and the wall clock are set up again. Other
+//! drivers are not told, and the `_PTS` and `_WAK` methods of the DSDT are
+//! not run, which is enough for QEMU: suspend there and wake the machine
+//! with the `system_wakeup` monitor command. The shell's `suspend` command
+//! calls [`suspend`].
+//!
+//! What the panic handler does is the [`PanicAction`], chosen with the
+//! `panic-poweroff` and `panic-reboot` features or [`set_panic_action`].
+
+use core::sync::atomic::{AtomicU8, Ordering};
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+use x86_64::instructions::tables::lidt;
+use x86_64::structures::DescriptorTablePointer;
+use x86_64::VirtAddr;
+
+use crate::acpi::fadt::{self, SleepError};
+use crate::arch::{pit, rtc, suspend as cpu};
+use crate::interrupts::{init_pics, PICS};
+use crate::serial::SERIAL1;
+use crate::timer::{self, clock};
+use crate::{println, serial_println};
+
+/// ACPI sleep state: suspend to RAM.
+const S3: u8 = 3;
+/// ACPI sleep state: soft off.
+const S5: u8 = 5;
+
+/// Emulator shutdown ports and the value each expects.
+const EMULATOR_PORTS: [(u16, u16); 3] = [(0x604, 0x2000), (0xb004, 0x2000), (0x4004, 0x3400)];
+
+/// 8042 keyboard controller status and command port.
+const KBC_COMMAND: u16 = 0x64;
+/// 8042 status: the input buffer is full.
+const KBC_INPUT_FULL: u8 = 1 << 1;
+/// 8042 command: pulse the CPU reset line.
+const KBC_PULSE_RESET: u8 = 0xfe;
+
+/// Times a reset method is given to take effect, in status port reads.
+const RESET_WAIT: u32 = 100_000;
+
+/// Errors that can occur while suspending to RAM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuspendError {
+    /// The sleep state could not be entered
+    Sleep(SleepError),
+    /// There is no FACS to write the waking vector to
+    NoFacs,
+    /// There is no free memory below 1 MiB for the wakeup code
+    NoLowMemory,
+    /// The machine did not go to sleep
+    DidNotSleep,
+}
+
+/// Why the last attempt to enter S3 failed, if it returned an error.
+static SLEEP_ERROR: Mutex<Option<SleepError>> = Mutex::new(None);
+
+/// What the panic handler does after reporting a panic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PanicAction {
+    /// Halt the CPU, leaving the panic on screen
+    Halt,
+    /// Power the machine off, which ends an emulator session
+    PowerOff,
+    /// Restart the machine
+    Reboot,
+}
+
+/// The panic action as a [`PanicAction`] discriminant.
+static PANIC_ACTION: AtomicU8 = AtomicU8::new(if cfg!(feature = "panic-reboot") {
+    PanicAction::Reboot as u8
+} else if cfg!(feature = "panic-poweroff") {
+    PanicAction::PowerOff as u8
+} else {
+    PanicAction::Halt as u8
+});
+
+/// Returns what the panic handler does.
+pub fn panic_action() -> PanicAction {
+    match PANIC_ACTION.load(Ordering::Relaxed) {
+        x if x == PanicAction::PowerOff as u8 => PanicAction::PowerOff,
+        x if x == PanicAction::Reboot as u8 => PanicAction::Reboot,
+        _ => PanicAction::Halt,
+    }
+}
+
+/// Sets what the panic handler does.
+pub fn set_panic_action(action: PanicAction) {
+    PANIC_ACTION.store(action as u8, Ordering::Relaxed);
+}
+
+/// Writes back the file systems and powers the machine off.
+///
+/// Must be called in thread context; see [`power_off`] for the panic
+/// handler.
+pub fn shutdown() -> ! {
+    println!("power: shutting down");
+    sync();
+    power_off()
+}
+
+/// Writes back the file systems and restarts the machine.
+///
+/// Must be called in thread context; see [`reset`] for the panic handler.
+pub fn reboot() -> ! {
+    println!("power: rebooting");
+    sync();
+    reset()
+}
+
+/// Writes back the file systems and suspends the machine to RAM.
+///
+/// Returns once the machine has woken up and the devices the kernel
+/// depends on are set up again. Must be called in thread context.
+///
+/// # Errors
+///
+/// Returns a [`SuspendError`] if the machine could not be suspended; it
+/// then keeps running as before.
+pub fn suspend() -> Result<(), SuspendError> {
+    println!("power: suspending to RAM");
+    sync();
+
+    let vector = cpu::prepare().ok_or(SuspendError::NoLowMemory)?;
+    fadt::sleep_types(S3).ok_or(SuspendError::Sleep(SleepError::NoSleepType))?;
+    if !fadt::set_waking_vector(vector) {
+        return Err(SuspendError::NoFacs);
+    }
+
+    *SLEEP_ERROR.lock() = None;
+    let resumed = interrupts::without_interrupts(|| {
+        let [primary, secondary] = unsafe { PICS.lock().read_masks() };
+        let resumed = unsafe { cpu::sleep(enter_s3) };
+        if resumed {
+            init_pics();
+            unsafe { PICS.lock().write_masks(primary, secondary) };
+            pit::set_frequency(timer::TICK_HZ);
+            SERIAL1.lock().init();
+        }
+        resumed
+    });
+    if !resumed {
+        return Err(SLEEP_ERROR
+            .lock()
+            .take()
+            .map_or(SuspendError::DidNotSleep, SuspendError::Sleep));
+    }
+
+    // Time stood still while the machine slept.
+    clock::set(Duration::from_secs(rtc::read().to_unix()));
+    println!("power: resumed");
+    Ok(())
+}
+
+/// Enters S3, called by `arch::suspend` with the CPU state saved.
+///
+/// Only returns if the machine did not go to sleep.
+extern "C" fn enter_s3() {
+    match fadt::enter_sleep_state(S3) {
+        Ok(()) => wait(),
+        Err(err) => *SLEEP_ERROR.lock() = Some(err),
+    }
+}
+
+/// Powers the machine off at once, without writing anything back.
+///
+/// Safe to call from any context, including the panic handler; it neither
+/// allocates nor takes locks other than the serial port's.
+pub fn power_off() -> ! {
+    interrupts::disable();
+    if let Err(err) = fadt::enter_sleep_state(S5) {
+        serial_println!("power: ACPI power off failed: {:?}", err);
+    }
+    for (port, value) in EMULATOR_PORTS {
+        unsafe { Port::<u16>::new(port).write(value) };
+    }
+    serial_println!("power: the machine could not be powered off; halting");
+    crate::hlt_loop()
+}
+
+/// Restarts the machine at once, without writing anything back.
+///
+/// Safe to call from any context, like [`power_off`].
+pub fn reset() -> ! {
+    interrupts::disable();
+    if fadt::reset() {
+        wait();
+    }
+
+    let mut kbc = Port::<u8>::new(KBC_COMMAND);
+    for _ in 0..RESET_WAIT {
+        if unsafe { kbc.read() } & KBC_INPUT_FULL == 0 {
+            break;
+        }
+    }
+    unsafe { kbc.write(KBC_PULSE_RESET) };
+    wait();
+
+    // With an empty IDT, the breakpoint faults, the fault double faults,
+    // and the double fault shuts the CPU down, which resets it.
+    let empty = DescriptorTablePointer {
+        limit: 0,
+        base: VirtAddr::zero(),
+    };
+    unsafe {
+        lidt(&empty);
+        core::arch::asm!("int3", options(nomem, nostack));
+    }
+    crate::hlt_loop()
+}
+
+/// Writes back the file systems, reporting failure.
+///
+/// Does nothing in a kernel built without the `fs` feature.
+fn sync() {
+    #[cfg(feature = "fs")]
+    if let Err(err) = crate::fs::sync() {
+        println!("power: sync failed: {:?}", err);
+    }
+}
+
+/// Gives a reset method or sleep state time to take effect.
+fn wait() {
+    let mut status = Port::<u8>::new(KBC_COMMAND);
+    for _ in 0..RESET_WAIT {
+        unsafe { status.read() };
+    }
+}
@@ -0,0 +1,276 @@
+//! # ELF Loader
+//!
+//! Loads statically linked x86_64 ELF executables (`ET_EXEC`) into an
+//! [`AddressSpace`]. Every `PT_LOAD` segment is mapped at its link address
+//! with the permissions from its flags; pages shared by two segments get
+//! the union of both. Other program headers are ignored.
+//!
+//! Images are limited to [`MAX_SEGMENTS`] loadable segments spanning at most
+//! [`MAX_IMAGE_SIZE`] bytes, so that a crafted file declaring a huge
+//! zero-filled segment cannot make the kernel map memory until it runs out.
+
+use alloc::vec::Vec;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+use crate::mm::address_space::{AddressSpace, UserMapError};
+use crate::mm::USER_SPACE_END;
+
+const PAGE_SIZE: u64 = 4096;
+
+/// Most memory the segments of one image may span, in bytes (64 MiB).
+pub const MAX_IMAGE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Most `PT_LOAD` segments one image may have.
+pub const MAX_SEGMENTS: usize = 64;
+
+/// `\x7fELF`
+const MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+/// `EI_CLASS` value for 64-bit objects
+const CLASS_64: u8 = 2;
+/// `EI_DATA` value for little-endian objects
+const DATA_LSB: u8 = 1;
+/// `e_type` of an executable
+const TYPE_EXEC: u16 = 2;
+/// `e_machine` for x86_64
+const MACHINE_X86_64: u16 = 62;
+/// `p_type` of a loadable segment
+const PT_LOAD: u32 = 1;
+/// Segment is executable
+const PF_X: u32 = 1;
+/// Segment is writable
+const PF_W: u32 = 2;
+
+/// Size of the ELF header.
+const EHDR_SIZE: usize = 64;
+/// Size of a program header.
+const PHDR_SIZE: usize = 56;
+
+/// Errors that can occur while loading an executable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    /// The image is not a 64-bit little-endian ELF file
+    NotElf,
+    /// The file is not a static x86_64 executable
+    Unsupported,
+    /// A header or segment lies outside the file or the user half
+    Malformed,
+    /// The image has more than [`MAX_SEGMENTS`] segments or spans more
+    /// than [`MAX_IMAGE_SIZE`] bytes
+    TooLarge,
+    /// Mapping the segments failed
+    Map(UserMapError),
+}
+
+/// A loadable segment.
+#[derive(Debug, Clone, Copy)]
+pub struct Segment {
+    /// Link address of the segment
+    pub vaddr: u64,
+    /// Size in memory; the part past `file_size` is zero-filled
+    pub mem_size: u64,
+    /// Offset of the segment's data in the file
+    pub offset: u64,
+    /// Bytes of data in the file
+    pub file_size: u64,
+    /// `PF_*` permission bits
+    pub flags: u32,
+}
+
+impl Segment {
+    /// Page table flags granting the segment's permissions.
+    pub fn page_flags(&self) -> PageTableFlags {
+        let mut flags = PageTableFlags::empty();
+        if self.flags & PF_W != 0 {
+            flags |= PageTableFlags::WRITABLE;
+        }
+        if self.flags & PF_X == 0 {
+            flags |= PageTableFlags::NO_EXECUTE;
+        }
+        flags
+    }
+
+    /// Start and end of the pages the segment occupies.
+    fn pages(&self) -> (u64, u64) {
+        let first = self.vaddr & !(PAGE_SIZE - 1);
+        let end = (self.vaddr + self.mem_size).next_multiple_of(PAGE_SIZE);
+        (first, end)
+    }
+}
+
+/// A parsed executable.
+#[derive(Debug)]
+pub struct Elf<'a> {
+    /// The whole file
+    data: &'a [u8],
+    /// Entry point
+    pub entry: VirtAddr,
+    /// `PT_LOAD` segments in file order
+    pub segments: Vec<Segment>,
+}
+
+impl<'a> Elf<'a> {
+    /// Parses and validates the headers of `data`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`ElfError`] if the file is not a well-formed static
+    /// x86_64 executable whose segments fit into the user half, or
+    /// [`ElfError::TooLarge`] if they exceed the limits on images.
+    pub fn parse(data: &'a [u8]) -> Result<Elf<'a>, ElfError> {
+        if data.len() < EHDR_SIZE || data[0..4] != MAGIC {
+            return Err(ElfError::NotElf);
+        }
+        if data[4] != CLASS_64 || data[5] != DATA_LSB {
+            return Err(ElfError::NotElf);
+        }
+        if u16_at(data, 16) != TYPE_EXEC || u16_at(data, 18) != MACHINE_X86_64 {
+            return Err(ElfError::Unsupported);
+        }
+
+        let entry = u64_at(data, 24);
+        let ph_offset = u64_at(data, 32) as usize;
+        let ph_size = u16_at(data, 54) as usize;
+        let ph_count = u16_at(data, 56) as usize;
+        if ph_size != PHDR_SIZE {
+            return Err(ElfError::Malformed);
+        }
+        let ph_end = ph_count
+            .checked_mul(PHDR_SIZE)
+            .and_then(|size| size.checked_add(ph_offset))
+            .ok_or(ElfError::Malformed)?;
+        if ph_end > data.len() {
+            return Err(ElfError::Malformed);
+        }
+
+        let mut segments = Vec::new();
+        for index in 0..ph_count {
+            let header = &data[ph_offset + index * PHDR_SIZE..][..PHDR_SIZE];
+            if u32_at(header, 0) != PT_LOAD {
+                continue;
+            }
+            let segment = Segment {
+                flags: u32_at(header, 4),
+                offset: u64_at(header, 8),
+                vaddr: u64_at(header, 16),
+                file_size: u64_at(header, 32),
+                mem_size: u64_at(header, 40),
+            };
+            let file_end = segment.offset.checked_add(segment.file_size);
+            if segment.file_size > segment.mem_size
+                || file_end.is_none_or(|end| end > data.len() as u64)
+                || segment
+                    .vaddr
+                    .checked_add(segment.mem_size)
+                    .is_none_or(|end| end > USER_SPACE_END)
+            {
+                return Err(ElfError::Malformed);
+            }
+            if segments.len() == MAX_SEGMENTS {
+                return Err(ElfError::TooLarge);
+            }
+            segments.push(segment);
+        }
+        let size: u64 = segments
+            .iter()
+            .map(|segment| {
+                let (first, end) = segment.pages();
+                end - first
+            })
+            .sum();
+        if size > MAX_IMAGE_SIZE {
+            return Err(ElfError::TooLarge);
+        }
+
+        let entry = VirtAddr::try_new(entry).map_err(|_| ElfError::Malformed)?;
+        let entry_mapped = segments.iter().any(|segment| {
+            segment.flags & PF_X != 0
+                && (segment.vaddr..segment.vaddr + segment.mem_size).contains(&entry.as_u64())
+        });
+        if !entry_mapped {
+            return Err(ElfError::Malformed);
+        }
+
+        Ok(Elf {
+            data,
+            entry,
+            segments,
+        })
+    }
+
+    /// Maps all segments into `space` and copies their contents.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ElfError::Map`] if a page cannot be mapped. Pages mapped
+    /// before the failure stay mapped; the caller is expected to discard
+    /// the address space.
+    pub fn load(&self, space: &AddressSpace) -> Result<(), ElfError> {
+        for (start, end, flags) in self.page_ranges() {
+            space
+                .map(VirtAddr::new(start), end - start, flags, &[])
+                .map_err(ElfError::Map)?;
+        }
+
+        for segment in &self.segments {
+            let data = &self.data[segment.offset as usize..][..segment.file_size as usize];
+            space
+                .load(VirtAddr::new(segment.vaddr), data)
+                .map_err(|_| ElfError::Malformed)?;
+        }
+        Ok(())
+    }
+
+    /// Page ranges to map, in address order, with their flags.
+    ///
+    /// The ranges are cut wherever a segment's pages start or end, so pages
+    /// shared by segments get the union of their permissions; adjacent
+    /// ranges with the same flags are joined.
+    fn page_ranges(&self) -> Vec<(u64, u64, PageTableFlags)> {
+        let mut bounds: Vec<u64> = self
+            .segments
+            .iter()
+            .flat_map(|segment| {
+                let (first, end) = segment.pages();
+                [first, end]
+            })
+            .collect();
+        bounds.sort_unstable();
+        bounds.dedup();
+
+        let mut ranges: Vec<(u64, u64, PageTableFlags)> = Vec::new();
+        for window in bounds.windows(2) {
+            let (start, end) = (window[0], window[1]);
+            let mut covering = self.segments.iter().filter(|segment| {
+                let (first, last) = segment.pages();
+                first <= start && end <= last
+            });
+            let Some(segment) = covering.next() else {
+                continue;
+            };
+            let mut flags = segment.page_flags();
+            for segment in covering {
+                let other = segment.page_flags();
+                flags |= other & PageTableFlags::WRITABLE;
+                flags &= other | !PageTableFlags::NO_EXECUTE;
+            }
+            match ranges.last_mut() {
+                Some(last) if last.1 == start && last.2 == flags => last.1 = end,
+                _ => ranges.push((start, end, flags)),
+            }
+        }
+        ranges
+    }
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
@@ -0,0 +1,144 @@
+//! # Program Images
+//!
+//! Builds the user half of a process from an ELF executable and its
+//! arguments, for both [`spawn`](super::spawn) and [`exec`](super::exec).
+//!
+//! ## Initial Stack
+//!
+//! Arguments are passed on the user stack the way the System V x86_64 ABI
+//! prescribes, so a conventional `_start` finds them:
+//!
+//! ```text
+//! USER_STACK_TOP | argument strings, NUL-terminated
+//!                | padding to 16 bytes
+//!                | auxiliary vector: AT_NULL, 0
+//!                | environment: NULL
+//!                | argv[argc] = NULL
+//!                | argv[0..argc]
+//! rsp ->         | argc
+//! ```
+//!
+//! Programs to run are looked up by name among the binaries registered
+//! with [`register_program`], typically images shipped inside the kernel.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+use super::elf::Elf;
+use super::{ProcessError, Vma, VmaKind, USER_STACK_SIZE, USER_STACK_TOP};
+use crate::mm::AddressSpace;
+
+/// Largest total size of the argument strings, including terminators.
+pub const ARG_MAX: usize = 16 * 1024;
+
+/// Largest number of arguments.
+pub const MAX_ARGS: usize = 256;
+
+/// Programs available to [`spawn_program`](super::spawn_program) and
+/// [`exec`](super::exec).
+static PROGRAMS: Mutex<BTreeMap<&'static str, &'static [u8]>> = Mutex::new(BTreeMap::new());
+
+/// Makes the ELF executable `image` available under `name`.
+///
+/// A program registered earlier under the same name is replaced.
+pub fn register_program(name: &'static str, image: &'static [u8]) {
+    PROGRAMS.lock().insert(name, image);
+}
+
+/// Returns the image of the program registered as `name`.
+pub fn find_program(name: &str) -> Option<&'static [u8]> {
+    PROGRAMS.lock().get(name).copied()
+}
+
+/// A program loaded into a fresh address space, ready to run.
+pub(super) struct LoadedImage {
+    /// Address space holding the program and its stack
+    pub address_space: AddressSpace,
+    /// Mapped areas, sorted by start address
+    pub vmas: Vec<Vma>,
+    /// Entry point
+    pub entry: VirtAddr,
+    /// Initial stack pointer, pointing at `argc`
+    pub stack_pointer: VirtAddr,
+}
+
+/// Loads `image` into a new address space and sets up its stack with
+/// `args`.
+pub(super) fn load(image: &[u8], args: &[&str]) -> Result<LoadedImage, ProcessError> {
+    let elf = Elf::parse(image).map_err(ProcessError::Elf)?;
+    let address_space = AddressSpace::new().map_err(ProcessError::AddressSpace)?;
+    elf.load(&address_space).map_err(ProcessError::Elf)?;
+
+    let stack_start = VirtAddr::new(USER_STACK_TOP - USER_STACK_SIZE);
+    let stack_flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    address_space
+        .map(stack_start, USER_STACK_SIZE, stack_flags, &[])
+        .map_err(ProcessError::Map)?;
+    let stack_pointer = push_arguments(&address_space, args)?;
+
+    let mut vmas: Vec<Vma> = elf
+        .segments
+        .iter()
+        .map(|segment| Vma {
+            start: VirtAddr::new(segment.vaddr),
+            end: VirtAddr::new(segment.vaddr + segment.mem_size),
+            flags: segment.page_flags(),
+            kind: VmaKind::Image,
+        })
+        .collect();
+    vmas.push(Vma {
+        start: stack_start,
+        end: VirtAddr::new(USER_STACK_TOP),
+        flags: stack_flags,
+        kind: VmaKind::Stack,
+    });
+    vmas.sort_by_key(|vma| vma.start);
+
+    Ok(LoadedImage {
+        address_space,
+        vmas,
+        entry: elf.entry,
+        stack_pointer,
+    })
+}
+
+/// Writes `args` to the top of the user stack in the layout described in
+/// the module documentation.
+///
+/// # Returns
+///
+/// The initial stack pointer.
+fn push_arguments(space: &AddressSpace, args: &[&str]) -> Result<VirtAddr, ProcessError> {
+    let strings_len: usize = args.iter().map(|arg| arg.len() + 1).sum();
+    if args.len() > MAX_ARGS || strings_len > ARG_MAX {
+        return Err(ProcessError::ArgumentsTooLong);
+    }
+
+    let strings_start = USER_STACK_TOP - strings_len as u64;
+    // argc, argv, NULL, envp NULL, AT_NULL pair
+    let words = 1 + args.len() + 1 + 1 + 2;
+    let stack_pointer = (strings_start - words as u64 * 8) & !0xf;
+
+    let mut frame = Vec::with_capacity((USER_STACK_TOP - stack_pointer) as usize);
+    frame.extend_from_slice(&(args.len() as u64).to_le_bytes());
+    let mut string = strings_start;
+    for arg in args {
+        frame.extend_from_slice(&string.to_le_bytes());
+        string += arg.len() as u64 + 1;
+    }
+    frame.resize(frame.len() + 4 * 8, 0);
+    frame.resize((strings_start - stack_pointer) as usize, 0);
+    for arg in args {
+        frame.extend_from_slice(arg.as_bytes());
+        frame.push(0);
+    }
+
+    let stack_pointer = VirtAddr::new(stack_pointer);
+    space
+        .write(stack_pointer, &frame)
+        .map_err(|_| ProcessError::ArgumentsTooLong)?;
+    Ok(stack_pointer)
+}
@@ -0,0 +1,135 @@
+//! # Futexes
+//!
+//! A futex ("fast userspace mutex") lets user programs build locks and
+//! condition variables that only enter the kernel under contention. The
+//! lock state is an aligned `u32` in user memory; a thread that finds it
+//! taken calls [`wait`] with the value it saw, and whoever changes the value
+//! calls [`wake`].
+//!
+//! Waiters are kept in a fixed hash table keyed by the *physical* address
+//! of the word, so threads sharing the word through different mappings
+//! still meet. [`wait`] compares the word with the expected value while
+//! holding the bucket lock that [`wake`] takes as well, so a wakeup between
+//! the program's check and going to sleep is never lost.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, Ordering};
+use spin::Mutex;
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::signal;
+use crate::mm::{self, AddressSpace};
+use crate::scheduler::{self, ThreadId};
+
+/// Number of hash buckets; a power of two.
+const BUCKETS: usize = 64;
+
+/// Waiting threads, hashed by futex address.
+static TABLE: [Mutex<Vec<Waiter>>; BUCKETS] = [const { Mutex::new(Vec::new()) }; BUCKETS];
+
+/// A thread blocked in [`wait`].
+struct Waiter {
+    /// Physical address of the futex word
+    key: PhysAddr,
+    /// The blocked thread
+    thread: ThreadId,
+}
+
+/// Errors returned by [`wait`] and [`wake`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FutexError {
+    /// The address is not mapped for user mode
+    BadAddress,
+    /// The address is not 4-byte aligned
+    Unaligned,
+    /// The word did not hold the expected value
+    WouldBlock,
+    /// A signal arrived while waiting
+    Interrupted,
+}
+
+/// Blocks the calling thread until [`wake`] is called on `addr`, provided
+/// the `u32` at `addr` still equals `expected`.
+///
+/// # Errors
+///
+/// Returns [`FutexError::WouldBlock`] without blocking if the value
+/// differs, [`FutexError::Interrupted`] if a signal arrives before the
+/// wakeup, and an address error if `addr` is unusable.
+///
+/// # Panics
+///
+/// Panics if called from outside a scheduler thread.
+pub fn wait(space: &AddressSpace, addr: VirtAddr, expected: u32) -> Result<(), FutexError> {
+    let key = resolve(space, addr)?;
+    let me = scheduler::current_id().expect("futex wait outside a thread");
+    let bucket = bucket(key);
+
+    {
+        let mut waiters = bucket.lock();
+        // The caller's reference keeps `space`, and so the frame, alive.
+        let word = unsafe { &*mm::phys_to_virt(key).as_ptr::<AtomicU32>() };
+        if word.load(Ordering::SeqCst) != expected {
+            return Err(FutexError::WouldBlock);
+        }
+        waiters.push(Waiter { key, thread: me });
+    }
+
+    // Unparks for other reasons are possible; only removal by `wake` or a
+    // signal ends the wait.
+    loop {
+        scheduler::park();
+        let interrupted = signal::interrupted();
+        let mut waiters = bucket.lock();
+        let Some(index) = waiters.iter().position(|waiter| waiter.thread == me) else {
+            return Ok(());
+        };
+        if interrupted {
+            waiters.remove(index);
+            return Err(FutexError::Interrupted);
+        }
+    }
+}
+
+/// Wakes up to `count` threads waiting on `addr`, oldest first.
+///
+/// # Returns
+///
+/// The number of threads woken.
+///
+/// # Errors
+///
+/// Returns an address error if `addr` is unusable.
+pub fn wake(space: &AddressSpace, addr: VirtAddr, count: usize) -> Result<usize, FutexError> {
+    let key = resolve(space, addr)?;
+    let mut woken = Vec::new();
+    {
+        let mut waiters = bucket(key).lock();
+        waiters.retain(|waiter| {
+            if waiter.key == key && woken.len() < count {
+                woken.push(waiter.thread);
+                false
+            } else {
+                true
+            }
+        });
+    }
+    for &thread in &woken {
+        scheduler::unpark(thread);
+    }
+    Ok(woken.len())
+}
+
+/// Translates a futex address to its key.
+fn resolve(space: &AddressSpace, addr: VirtAddr) -> Result<PhysAddr, FutexError> {
+    if !addr.is_aligned(4u64) {
+        return Err(FutexError::Unaligned);
+    }
+    space.translate(addr).map_err(|_| FutexError::BadAddress)
+}
+
+/// Returns the bucket for `key`.
+fn bucket(key: PhysAddr) -> &'static Mutex<Vec<Waiter>> {
+    let hash = (key.as_u64() >> 2).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    &TABLE[(hash >> (64 - BUCKETS.trailing_zeros())) as usize]
+}
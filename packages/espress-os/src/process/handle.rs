@@ -0,0 +1,112 @@
+//! # Handle Tables
+//!
+//! Every process refers to kernel objects it has opened through small
+//! integer handles (file descriptors). Handles 0, 1, and 2 start out as
+//! standard input, output, and error, all connected to the console.
+//!
+//! Closing a handle drops the table's reference to the object; objects such
+//! as pipe ends notice when their last handle is gone.
+
+#[cfg(any(feature = "fs", feature = "net"))]
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use super::pipe::{PipeReader, PipeWriter};
+#[cfg(feature = "fs")]
+use crate::fs::file::OpenFile;
+#[cfg(feature = "net")]
+use crate::net::socket::Socket;
+
+/// Standard input.
+pub const STDIN: usize = 0;
+/// Standard output.
+pub const STDOUT: usize = 1;
+/// Standard error.
+pub const STDERR: usize = 2;
+
+/// A kernel object a handle refers to.
+#[derive(Debug, Clone)]
+pub enum Handle {
+    /// The kernel console
+    Console,
+    /// The read end of a pipe
+    PipeReader(PipeReader),
+    /// The write end of a pipe
+    PipeWriter(PipeWriter),
+    /// A file, directory, or device opened through the VFS
+    #[cfg(feature = "fs")]
+    File(Arc<OpenFile>),
+    /// A network socket
+    #[cfg(feature = "net")]
+    Socket(Arc<Socket>),
+}
+
+/// Handles owned by a process, indexed by handle number.
+#[derive(Debug, Default)]
+pub struct HandleTable {
+    /// Open handles; closed slots are reused by [`insert`](Self::insert)
+    entries: Vec<Option<Handle>>,
+}
+
+impl HandleTable {
+    /// Creates a table with the three standard handles on the console.
+    pub fn with_stdio() -> Self {
+        HandleTable {
+            entries: alloc::vec![Some(Handle::Console); 3],
+        }
+    }
+
+    /// Returns the object behind handle `fd`.
+    pub fn get(&self, fd: usize) -> Option<&Handle> {
+        self.entries.get(fd)?.as_ref()
+    }
+
+    /// Adds `handle` under the lowest free number.
+    ///
+    /// # Returns
+    ///
+    /// The new handle number.
+    pub fn insert(&mut self, handle: Handle) -> usize {
+        match self.entries.iter().position(Option::is_none) {
+            Some(fd) => {
+                self.entries[fd] = Some(handle);
+                fd
+            }
+            None => {
+                self.entries.push(Some(handle));
+                self.entries.len() - 1
+            }
+        }
+    }
+
+    /// Makes `fd` refer to `handle`, growing the table if needed.
+    ///
+    /// # Returns
+    ///
+    /// The object `fd` referred to before, if any.
+    pub fn set(&mut self, fd: usize, handle: Handle) -> Option<Handle> {
+        if fd >= self.entries.len() {
+            self.entries.resize(fd + 1, None);
+        }
+        self.entries[fd].replace(handle)
+    }
+
+    /// Closes handle `fd`.
+    ///
+    /// # Returns
+    ///
+    /// The object it referred to, or `None` if `fd` was not open.
+    pub fn remove(&mut self, fd: usize) -> Option<Handle> {
+        self.entries.get_mut(fd)?.take()
+    }
+
+    /// Number of open handles.
+    pub fn len(&self) -> usize {
+        self.entries.iter().flatten().count()
+    }
+
+    /// Returns `true` if no handle is open.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
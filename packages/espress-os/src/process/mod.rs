@@ -0,0 +1,364 @@
+//! # Processes
+//!
+//! A [`Process`] is a user program together with everything it owns: an
+//! [`AddressSpace`] with the kernel half shared and a private user half, the
+//! list of memory areas mapped there ([`Vma`]), a [`HandleTable`], and its
+//! main thread. Processes are registered in a global table under their
+//! [`Pid`] from creation until they exit; [`processes`] enumerates it.
+//!
+//! [`spawn`] loads a static ELF executable (see [`elf`]), maps a stack
+//! below [`USER_STACK_TOP`] holding the program's arguments, and starts the
+//! main thread at the ELF entry point in user mode; [`exec`] replaces the
+//! program of a running process the same way. Programs shipped with the
+//! kernel are registered by name with [`register_program`]. The scheduler
+//! switches address spaces along with threads, so each process only ever
+//! sees its own user half.
+//!
+//! A process ends when its main thread calls `exit` or is killed by a
+//! fault; its handles are closed at that point, and [`Process::wait`]
+//! blocks until then and returns the exit code. Its memory is freed once
+//! the last reference to it is gone.
+//!
+//! Processes can be interrupted or asked to shut down with [`signal`]s.
+//! They talk to each other through [`pipe`]s, and threads sharing memory
+//! synchronize with [`futex`]es. A parent composes them
+//! by starting children with [`spawn_with_handles`], e.g. with one child's
+//! standard output connected to another's standard input.
+
+pub mod elf;
+mod exec;
+pub mod futex;
+pub mod handle;
+pub mod pipe;
+pub mod signal;
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+use x86_64::instructions::interrupts;
+
+use crate::mm::address_space::{AddressSpace, AddressSpaceError, UserMapError};
+use crate::mm::stack::StackError;
+use crate::scheduler::{self, ThreadId, WaitQueue};
+use crate::usermode;
+use elf::ElfError;
+use handle::HandleTable;
+use signal::{Signal, SignalState};
+
+pub use exec::{find_program, register_program, ARG_MAX, MAX_ARGS};
+
+/// Initial user stack pointer of a new process.
+pub const USER_STACK_TOP: u64 = 0x7fff_ffff_0000;
+
+/// Size of a process's user stack in bytes.
+pub const USER_STACK_SIZE: u64 = 64 * 1024;
+
+/// Exit code of a process killed because of a fault.
+pub const EXIT_KILLED: i32 = -1;
+
+/// Live processes by PID.
+static PROCESSES: Mutex<BTreeMap<Pid, Arc<Process>>> = Mutex::new(BTreeMap::new());
+
+/// Process identifier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Pid(u64);
+
+impl Pid {
+    /// Returns a fresh identifier; PIDs start at 1.
+    fn new() -> Self {
+        static NEXT_PID: AtomicU64 = AtomicU64::new(1);
+        Pid(NEXT_PID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// The identifier with number `pid`, which need not be live.
+    pub fn from_u64(pid: u64) -> Self {
+        Pid(pid)
+    }
+
+    /// The identifier as a number.
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+/// Errors that can occur while creating a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessError {
+    /// The address space could not be created
+    AddressSpace(AddressSpaceError),
+    /// The executable could not be loaded
+    Elf(ElfError),
+    /// The user stack could not be mapped
+    Map(UserMapError),
+    /// The main thread's kernel stack could not be allocated
+    Stack(StackError),
+    /// No program is registered under the requested name
+    NotFound,
+    /// The arguments exceed [`ARG_MAX`] or [`MAX_ARGS`]
+    ArgumentsTooLong,
+}
+
+/// What a memory area holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmaKind {
+    /// A segment of the executable
+    Image,
+    /// The main thread's user stack
+    Stack,
+}
+
+/// A mapped area of a process's user half.
+#[derive(Debug, Clone, Copy)]
+pub struct Vma {
+    /// First address of the area
+    pub start: VirtAddr,
+    /// One past the last address of the area
+    pub end: VirtAddr,
+    /// Permissions of the pages (`WRITABLE` and `NO_EXECUTE`)
+    pub flags: PageTableFlags,
+    /// What the area holds
+    pub kind: VmaKind,
+}
+
+/// A user program and the resources it owns.
+pub struct Process {
+    /// Identifier, unique for the kernel's lifetime
+    pid: Pid,
+    /// Name shown in diagnostics
+    name: String,
+    /// Page tables of the process, replaced by `exec`
+    address_space: Mutex<Arc<AddressSpace>>,
+    /// Mapped memory areas, sorted by start address
+    vmas: Mutex<Vec<Vma>>,
+    /// Open handles
+    handles: Mutex<HandleTable>,
+    /// Pending signals and their actions
+    signals: SignalState,
+    /// The thread running the program, once started
+    main_thread: Mutex<Option<ThreadId>>,
+    /// Exit code, once the process has exited
+    exit_code: Mutex<Option<i32>>,
+    /// Threads waiting for the process to exit
+    exited: WaitQueue,
+}
+
+impl Process {
+    /// Process identifier.
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    /// Name given at creation; `exec` does not change it.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Page tables of the process.
+    ///
+    /// The scheduler reads this while switching threads, so the lock is
+    /// only ever held with interrupts disabled.
+    pub fn address_space(&self) -> Arc<AddressSpace> {
+        interrupts::without_interrupts(|| self.address_space.lock().clone())
+    }
+
+    /// Snapshot of the mapped memory areas, sorted by address.
+    pub fn vmas(&self) -> Vec<Vma> {
+        self.vmas.lock().clone()
+    }
+
+    /// Runs `f` with the process's handle table.
+    pub fn with_handles<R>(&self, f: impl FnOnce(&mut HandleTable) -> R) -> R {
+        f(&mut self.handles.lock())
+    }
+
+    /// Pending signals and their actions; signals are sent with
+    /// [`send_signal`](Self::send_signal).
+    pub fn signals(&self) -> &SignalState {
+        &self.signals
+    }
+
+    /// Marks `signal` pending and wakes the main thread, so that a system
+    /// call it is blocked in returns early and the signal is acted upon.
+    pub fn send_signal(&self, signal: Signal) {
+        self.signals.raise(signal);
+        if let Some(thread) = self.main_thread() {
+            scheduler::unpark(thread);
+        }
+    }
+
+    /// ID of the main thread.
+    pub fn main_thread(&self) -> Option<ThreadId> {
+        *self.main_thread.lock()
+    }
+
+    /// Exit code, or `None` while the process is running.
+    pub fn exit_code(&self) -> Option<i32> {
+        *self.exit_code.lock()
+    }
+
+    /// Blocks until the process has exited.
+    ///
+    /// # Returns
+    ///
+    /// The exit code.
+    pub fn wait(&self) -> i32 {
+        self.exited.wait_until(|| self.exit_code().is_some());
+        self.exit_code().unwrap()
+    }
+}
+
+/// Loads the static ELF executable `image` into a new process and starts
+/// its main thread.
+///
+/// `args` become the program's `argv`; the process is named after the
+/// first one. Its standard handles are connected to the console.
+///
+/// # Errors
+///
+/// Returns a [`ProcessError`] if the image is invalid, the arguments are
+/// too long, or memory runs out; nothing is left behind in that case.
+pub fn spawn(image: &[u8], args: &[&str]) -> Result<Arc<Process>, ProcessError> {
+    spawn_with_handles(image, args, HandleTable::with_stdio())
+}
+
+/// Like [`spawn`], but the new process starts with `handles` instead of
+/// the standard console handles.
+///
+/// # Errors
+///
+/// See [`spawn`]. On failure `handles` is dropped, closing its objects.
+pub fn spawn_with_handles(
+    image: &[u8],
+    args: &[&str],
+    handles: HandleTable,
+) -> Result<Arc<Process>, ProcessError> {
+    let loaded = exec::load(image, args)?;
+    let process = Arc::new(Process {
+        pid: Pid::new(),
+        name: String::from(args.first().copied().unwrap_or("process")),
+        address_space: Mutex::new(Arc::new(loaded.address_space)),
+        vmas: Mutex::new(loaded.vmas),
+        handles: Mutex::new(handles),
+        signals: SignalState::new(),
+        main_thread: Mutex::new(None),
+        exit_code: Mutex::new(None),
+        exited: WaitQueue::new(),
+    });
+
+    // Registered first: the main thread may exit before spawn returns.
+    PROCESSES.lock().insert(process.pid, process.clone());
+    let (entry, stack_pointer) = (loaded.entry, loaded.stack_pointer);
+    let thread = scheduler::spawn_in(process.clone(), move || {
+        scheduler::enable_fpu();
+        unsafe { usermode::enter_usermode(entry, stack_pointer) }
+    });
+    match thread {
+        Ok(thread) => {
+            *process.main_thread.lock() = Some(thread.id());
+            Ok(process)
+        }
+        Err(err) => {
+            PROCESSES.lock().remove(&process.pid);
+            Err(ProcessError::Stack(err))
+        }
+    }
+}
+
+/// Starts the program registered as `name` in a new process.
+///
+/// See [`spawn`] and [`register_program`].
+///
+/// # Errors
+///
+/// Returns [`ProcessError::NotFound`] if no such program is registered.
+pub fn spawn_program(name: &str, args: &[&str]) -> Result<Arc<Process>, ProcessError> {
+    let image = find_program(name).ok_or(ProcessError::NotFound)?;
+    spawn(image, args)
+}
+
+/// Where a thread starts executing in user mode.
+#[derive(Debug, Clone, Copy)]
+pub struct UserEntry {
+    /// First instruction to execute
+    pub instruction_pointer: VirtAddr,
+    /// Initial stack pointer
+    pub stack_pointer: VirtAddr,
+}
+
+/// Replaces the calling process's program with the one registered as
+/// `name`, keeping its PID, handles, and pending signals. Signal handlers
+/// are reset, since their code is gone.
+///
+/// The new image is loaded completely before the old one is discarded, so
+/// on failure the caller continues unharmed. On success the old program is
+/// gone and the calling thread must continue at the returned entry in user
+/// mode.
+///
+/// # Errors
+///
+/// Returns a [`ProcessError`] if the program is unknown or cannot be
+/// loaded.
+///
+/// # Panics
+///
+/// Panics if the calling thread does not belong to a process.
+pub fn exec(name: &str, args: &[&str]) -> Result<UserEntry, ProcessError> {
+    let image = find_program(name).ok_or(ProcessError::NotFound)?;
+    let loaded = exec::load(image, args)?;
+
+    let process = current().expect("exec called outside a process");
+    let address_space = Arc::new(loaded.address_space);
+    let old = interrupts::without_interrupts(|| {
+        address_space.activate();
+        core::mem::replace(&mut *process.address_space.lock(), address_space)
+    });
+    *process.vmas.lock() = loaded.vmas;
+    process.signals.reset_handlers();
+    drop(old);
+
+    Ok(UserEntry {
+        instruction_pointer: loaded.entry,
+        stack_pointer: loaded.stack_pointer,
+    })
+}
+
+/// Returns the process the calling thread belongs to.
+pub fn current() -> Option<Arc<Process>> {
+    scheduler::current_process()
+}
+
+/// Returns the live process `pid`.
+pub fn get(pid: Pid) -> Option<Arc<Process>> {
+    PROCESSES.lock().get(&pid).cloned()
+}
+
+/// Returns all live processes, ordered by PID.
+pub fn processes() -> Vec<Arc<Process>> {
+    PROCESSES.lock().values().cloned().collect()
+}
+
+/// Ends the calling thread's process with `code`.
+///
+/// The process leaves the process table, its handles are closed, and its
+/// waiters are woken; its memory is freed once the last reference is
+/// dropped.
+///
+/// # Panics
+///
+/// Panics if the calling thread does not belong to a process.
+pub fn exit_current(code: i32) -> ! {
+    let process = current().expect("exit_current called outside a process");
+    let handles = core::mem::take(&mut *process.handles.lock());
+    drop(handles);
+    *process.exit_code.lock() = Some(code);
+    PROCESSES.lock().remove(&process.pid);
+    process.exited.notify_all();
+    drop(process);
+    scheduler::exit();
+}
@@ -0,0 +1,201 @@
+//! # Pipes
+//!
+//! A pipe is a one-way byte channel between a [`PipeWriter`] and a
+//! [`PipeReader`], backed by a fixed-size in-kernel ring buffer. Reading
+//! from an empty pipe blocks until data arrives and writing to a full one
+//! blocks until there is room.
+//!
+//! Either end may be cloned (e.g. when a handle is shared); the pipe keeps
+//! count of the open ends. Once every writer is gone, readers drain what is
+//! left and then see end of file; once every reader is gone, writes fail
+//! with [`PipeError::BrokenPipe`].
+//!
+//! A blocked read or write also gives up with [`PipeError::Interrupted`]
+//! when a signal arrives for the calling process, unless it has already
+//! transferred data.
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use spin::Mutex;
+
+use super::signal;
+use crate::scheduler::WaitQueue;
+
+/// Bytes a pipe buffers before writers block.
+pub const PIPE_CAPACITY: usize = 4096;
+
+/// Errors returned by pipe reads and writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipeError {
+    /// Written to a pipe without readers
+    BrokenPipe,
+    /// A signal arrived while blocked
+    Interrupted,
+}
+
+/// Shared state of both ends.
+struct Pipe {
+    /// Buffered data and open end counts
+    state: Mutex<PipeState>,
+    /// Readers waiting for data or end of file
+    readable: WaitQueue,
+    /// Writers waiting for room or for the readers to go away
+    writable: WaitQueue,
+}
+
+struct PipeState {
+    /// Bytes written but not read yet, at most [`PIPE_CAPACITY`]
+    buffer: VecDeque<u8>,
+    /// Open [`PipeReader`]s
+    readers: usize,
+    /// Open [`PipeWriter`]s
+    writers: usize,
+}
+
+/// Creates a pipe.
+///
+/// # Returns
+///
+/// The read and write end.
+pub fn pipe() -> (PipeReader, PipeWriter) {
+    let pipe = Arc::new(Pipe {
+        state: Mutex::new(PipeState {
+            buffer: VecDeque::with_capacity(PIPE_CAPACITY),
+            readers: 1,
+            writers: 1,
+        }),
+        readable: WaitQueue::new(),
+        writable: WaitQueue::new(),
+    });
+    (PipeReader(pipe.clone()), PipeWriter(pipe))
+}
+
+/// The read end of a pipe.
+pub struct PipeReader(Arc<Pipe>);
+
+impl PipeReader {
+    /// Reads up to `buf.len()` bytes, blocking while the pipe is empty and
+    /// a writer is still open.
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes read; `0` means end of file (or an empty `buf`).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`PipeError::Interrupted`] if a signal arrives while the pipe
+    /// is empty.
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, PipeError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let pipe = &self.0;
+        pipe.readable.wait_until(|| {
+            let state = pipe.state.lock();
+            !state.buffer.is_empty() || state.writers == 0 || signal::interrupted()
+        });
+
+        let mut state = pipe.state.lock();
+        if state.buffer.is_empty() && state.writers > 0 {
+            return Err(PipeError::Interrupted);
+        }
+        let count = buf.len().min(state.buffer.len());
+        for (dst, src) in buf.iter_mut().zip(state.buffer.drain(..count)) {
+            *dst = src;
+        }
+        drop(state);
+        if count > 0 {
+            pipe.writable.notify_all();
+        }
+        Ok(count)
+    }
+}
+
+impl Clone for PipeReader {
+    fn clone(&self) -> Self {
+        self.0.state.lock().readers += 1;
+        PipeReader(self.0.clone())
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock();
+        state.readers -= 1;
+        let last = state.readers == 0;
+        drop(state);
+        if last {
+            self.0.writable.notify_all();
+        }
+    }
+}
+
+impl core::fmt::Debug for PipeReader {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("PipeReader").finish_non_exhaustive()
+    }
+}
+
+/// The write end of a pipe.
+pub struct PipeWriter(Arc<Pipe>);
+
+impl PipeWriter {
+    /// Writes as much of `buf` as fits, blocking while the pipe is full and
+    /// a reader is still open.
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes written, at least one unless `buf` is empty.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`PipeError::BrokenPipe`] if no reader is open and
+    /// [`PipeError::Interrupted`] if a signal arrives while the pipe is full.
+    pub fn write(&self, buf: &[u8]) -> Result<usize, PipeError> {
+        let pipe = &self.0;
+        pipe.writable.wait_until(|| {
+            let state = pipe.state.lock();
+            state.buffer.len() < PIPE_CAPACITY || state.readers == 0 || signal::interrupted()
+        });
+
+        let mut state = pipe.state.lock();
+        if state.readers == 0 {
+            return Err(PipeError::BrokenPipe);
+        }
+        if state.buffer.len() == PIPE_CAPACITY && !buf.is_empty() {
+            return Err(PipeError::Interrupted);
+        }
+        let count = buf.len().min(PIPE_CAPACITY - state.buffer.len());
+        state.buffer.extend(&buf[..count]);
+        drop(state);
+        if count > 0 {
+            pipe.readable.notify_all();
+        }
+        Ok(count)
+    }
+}
+
+impl Clone for PipeWriter {
+    fn clone(&self) -> Self {
+        self.0.state.lock().writers += 1;
+        PipeWriter(self.0.clone())
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock();
+        state.writers -= 1;
+        let last = state.writers == 0;
+        drop(state);
+        if last {
+            self.0.readable.notify_all();
+        }
+    }
+}
+
+impl core::fmt::Debug for PipeWriter {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("PipeWriter").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,373 @@
+//! # Signals
+//!
+//! A signal is an asynchronous notification sent to a process, e.g. to
+//! interrupt it or ask it to shut down. Sending one only marks it pending in
+//! the target's [`SignalState`]; it is acted upon the next time the target
+//! returns to user mode:
+//!
+//! * on return from a system call, [`deliver`] runs the action of the lowest
+//!   pending signal;
+//! * on return from the timer interrupt, [`check_fatal`] terminates the
+//!   process if a pending signal has the default action, so that a program
+//!   that never makes system calls can still be stopped.
+//!
+//! [`Process::send_signal`](super::Process::send_signal) also wakes the
+//! target's thread. The blocking system calls (reading or writing a pipe,
+//! waiting on a futex, sleeping) check [`interrupted`] whenever they wake
+//! and give up with `EINTR` if a signal that is not ignored is pending, so
+//! that it is delivered without waiting for the call to complete.
+//!
+//! ## Actions
+//!
+//! Every signal starts out with [`Action::Default`], which terminates the
+//! process with exit code `128 + signal number`. A program may instead
+//! ignore it or register a handler; [`Signal::Kill`] always terminates.
+//!
+//! ## Handlers
+//!
+//! A handler is called like `handler(signal)` on the user stack, below the
+//! red zone. The registers saved by the system call entry are stored right
+//! above its return address, which points at a user-supplied *restorer*
+//! that must invoke `sigreturn` (with the stack pointer unchanged) to
+//! resume the interrupted code. Handlers must follow the System V ABI and
+//! therefore preserve all callee-saved registers; vector registers are not
+//! saved.
+
+use core::mem::size_of;
+use core::sync::atomic::{AtomicU32, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::VirtAddr;
+
+use crate::mm::USER_SPACE_END;
+use crate::syscall::SyscallFrame;
+use crate::usermode::USER_RFLAGS;
+
+/// Number of signal numbers, including the unused `0`.
+pub const NSIG: usize = 32;
+
+/// Bytes below the user stack pointer that leaf functions may use without
+/// adjusting it (System V red zone).
+const RED_ZONE: u64 = 128;
+
+/// `RFLAGS` bits user code may change through `sigreturn`: the arithmetic
+/// flags and the direction flag.
+const USER_CHANGEABLE_FLAGS: u64 = 0x0cd5;
+
+/// Signals understood by the kernel, numbered as on Linux.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Signal {
+    /// Hangup
+    Hangup = 1,
+    /// Interrupt, e.g. from the keyboard
+    Interrupt = 2,
+    /// Kill; cannot be caught or ignored
+    Kill = 9,
+    /// User-defined signal 1
+    User1 = 10,
+    /// Invalid memory reference
+    SegmentationFault = 11,
+    /// User-defined signal 2
+    User2 = 12,
+    /// Write to a pipe without readers
+    BrokenPipe = 13,
+    /// Termination request
+    Terminate = 15,
+}
+
+impl Signal {
+    /// Returns the signal with number `number`.
+    pub fn from_number(number: u64) -> Option<Self> {
+        Some(match number {
+            1 => Signal::Hangup,
+            2 => Signal::Interrupt,
+            9 => Signal::Kill,
+            10 => Signal::User1,
+            11 => Signal::SegmentationFault,
+            12 => Signal::User2,
+            13 => Signal::BrokenPipe,
+            15 => Signal::Terminate,
+            _ => return None,
+        })
+    }
+
+    /// The signal number.
+    pub fn number(self) -> u8 {
+        self as u8
+    }
+
+    /// Exit code of a process terminated by this signal.
+    pub fn exit_code(self) -> i32 {
+        128 + i32::from(self.number())
+    }
+
+    /// Bit of this signal in a pending mask.
+    fn bit(self) -> u32 {
+        1 << self.number()
+    }
+}
+
+/// What happens when a signal is delivered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Terminate the process
+    Default,
+    /// Discard the signal
+    Ignore,
+    /// Call a user function
+    Handler {
+        /// Function called with the signal number
+        handler: VirtAddr,
+        /// Return address of the handler; must call `sigreturn`
+        restorer: VirtAddr,
+    },
+}
+
+/// Errors returned by [`SignalState::set_action`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalError {
+    /// [`Signal::Kill`] cannot be caught or ignored
+    Uncatchable,
+    /// The handler or restorer is not a user address
+    BadAddress,
+}
+
+/// Pending signals and registered actions of a process.
+pub struct SignalState {
+    /// Bit `n` is set while signal `n` is pending
+    pending: AtomicU32,
+    /// Actions by signal number; only locked with interrupts disabled,
+    /// since the timer interrupt reads them
+    actions: Mutex<[Action; NSIG]>,
+}
+
+impl SignalState {
+    /// Creates a state with nothing pending and every action
+    /// [`Action::Default`].
+    pub const fn new() -> Self {
+        SignalState {
+            pending: AtomicU32::new(0),
+            actions: Mutex::new([Action::Default; NSIG]),
+        }
+    }
+
+    /// Marks `signal` pending.
+    pub fn raise(&self, signal: Signal) {
+        self.pending.fetch_or(signal.bit(), Ordering::AcqRel);
+    }
+
+    /// Returns `true` if `signal` is pending.
+    pub fn is_pending(&self, signal: Signal) -> bool {
+        self.pending.load(Ordering::Acquire) & signal.bit() != 0
+    }
+
+    /// Returns `true` if a signal is pending that is not ignored.
+    pub fn has_unignored(&self) -> bool {
+        let pending = self.pending.load(Ordering::Acquire);
+        (1..NSIG as u64)
+            .filter(|&number| pending & (1 << number) != 0)
+            .filter_map(Signal::from_number)
+            .any(|signal| signal == Signal::Kill || self.action(signal) != Action::Ignore)
+    }
+
+    /// Returns the action for `signal`.
+    pub fn action(&self, signal: Signal) -> Action {
+        interrupts::without_interrupts(|| self.actions.lock()[usize::from(signal.number())])
+    }
+
+    /// Sets the action for `signal`.
+    ///
+    /// # Returns
+    ///
+    /// The previous action.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`SignalError`] for [`Signal::Kill`] and for handlers
+    /// outside the user half.
+    pub fn set_action(&self, signal: Signal, action: Action) -> Result<Action, SignalError> {
+        if signal == Signal::Kill {
+            return Err(SignalError::Uncatchable);
+        }
+        if let Action::Handler { handler, restorer } = action {
+            if handler.as_u64() >= USER_SPACE_END || restorer.as_u64() >= USER_SPACE_END {
+                return Err(SignalError::BadAddress);
+            }
+        }
+        let index = usize::from(signal.number());
+        Ok(interrupts::without_interrupts(|| {
+            core::mem::replace(&mut self.actions.lock()[index], action)
+        }))
+    }
+
+    /// Resets every handler to [`Action::Default`], e.g. because `exec`
+    /// discarded the code they pointed to. Ignored signals stay ignored.
+    pub fn reset_handlers(&self) {
+        interrupts::without_interrupts(|| {
+            for action in self.actions.lock().iter_mut() {
+                if matches!(action, Action::Handler { .. }) {
+                    *action = Action::Default;
+                }
+            }
+        });
+    }
+
+    /// Removes the lowest pending signal from the pending mask.
+    fn take(&self) -> Option<Signal> {
+        let mut pending = self.pending.load(Ordering::Acquire);
+        loop {
+            if pending == 0 {
+                return None;
+            }
+            let number = pending.trailing_zeros();
+            match self.pending.compare_exchange_weak(
+                pending,
+                pending & !(1 << number),
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                // Unknown numbers are never raised.
+                Ok(_) => return Signal::from_number(u64::from(number)),
+                Err(actual) => pending = actual,
+            }
+        }
+    }
+
+    /// Returns a pending signal whose delivery terminates the process.
+    fn fatal(&self) -> Option<Signal> {
+        let pending = self.pending.load(Ordering::Acquire);
+        (1..NSIG as u64)
+            .filter(|&number| pending & (1 << number) != 0)
+            .filter_map(Signal::from_number)
+            .find(|&signal| signal == Signal::Kill || self.action(signal) == Action::Default)
+    }
+}
+
+impl Default for SignalState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Acts on the lowest pending signal of the calling process before the
+/// system call described by `frame` returns.
+///
+/// Ignored signals are discarded. A handler is entered by rewriting
+/// `frame`, so the system call returns into it; `frame.rax` must already
+/// hold the call's result, which [`restore`] brings back.
+///
+/// Does not return if the signal terminates the process, including when
+/// the handler frame cannot be written to the user stack.
+pub fn deliver(frame: &mut SyscallFrame) {
+    let Some(process) = super::current() else {
+        return;
+    };
+    while let Some(signal) = process.signals().take() {
+        let (handler, restorer) = match process.signals().action(signal) {
+            _ if signal == Signal::Kill => terminate(process, signal),
+            Action::Default => terminate(process, signal),
+            Action::Ignore => continue,
+            Action::Handler { handler, restorer } => (handler, restorer),
+        };
+
+        let saved = frame_bytes(frame);
+        let frame_top = (frame.rsp.wrapping_sub(RED_ZONE + saved.len() as u64)) & !0xf;
+        let stack = frame_top.wrapping_sub(8);
+        let written = VirtAddr::try_new(stack).is_ok_and(|stack| {
+            let space = process.address_space();
+            space.write(stack, &restorer.as_u64().to_le_bytes()).is_ok()
+                && space.write(stack + 8u64, saved).is_ok()
+        });
+        if !written || stack >= frame.rsp {
+            terminate(process, Signal::SegmentationFault);
+        }
+
+        frame.args[0] = u64::from(signal.number());
+        frame.rcx = handler.as_u64();
+        frame.r11 = USER_RFLAGS;
+        frame.rsp = stack;
+        return;
+    }
+}
+
+/// Returns `true` if the calling process has a pending signal that should
+/// interrupt a blocking system call. Always `false` in kernel threads.
+pub fn interrupted() -> bool {
+    super::current().is_some_and(|process| process.signals().has_unignored())
+}
+
+/// Undoes [`deliver`]: restores the registers saved above the handler's
+/// return address, which is where the user stack pointer points once the
+/// handler has returned into the restorer.
+///
+/// # Returns
+///
+/// The result of the interrupted system call, to be stored in `rax`, or
+/// `None` if the saved registers cannot be read or would return to a
+/// kernel address; `frame` is left unchanged then.
+pub fn restore(frame: &mut SyscallFrame) -> Option<u64> {
+    let process = super::current()?;
+    let mut saved = SyscallFrame {
+        rax: 0,
+        args: [0; 6],
+        r11: 0,
+        rcx: 0,
+        rsp: 0,
+    };
+    let source = VirtAddr::try_new(frame.rsp).ok()?;
+    process
+        .address_space()
+        .read(source, frame_bytes_mut(&mut saved))
+        .ok()?;
+    // `sysret` to a non-canonical address would fault in kernel mode.
+    if saved.rcx >= USER_SPACE_END {
+        return None;
+    }
+    saved.r11 = (saved.r11 & USER_CHANGEABLE_FLAGS) | USER_RFLAGS;
+    *frame = saved;
+    Some(frame.rax)
+}
+
+/// Terminates the calling process if it has a pending signal whose action
+/// is to terminate it.
+///
+/// Called by the timer interrupt handler when it interrupted user mode, so
+/// the current thread holds no locks.
+pub fn check_fatal() {
+    let Some(process) = super::current() else {
+        return;
+    };
+    if let Some(signal) = process.signals().fatal() {
+        interrupts::enable();
+        terminate(process, signal);
+    }
+}
+
+/// Ends the calling process because of `signal`.
+fn terminate(process: alloc::sync::Arc<super::Process>, signal: Signal) -> ! {
+    let code = signal.exit_code();
+    // Dropped here, since the reference would otherwise never be released.
+    drop(process);
+    super::exit_current(code);
+}
+
+/// The raw bytes of a saved register frame.
+fn frame_bytes(frame: &SyscallFrame) -> &[u8] {
+    unsafe {
+        core::slice::from_raw_parts(
+            (frame as *const SyscallFrame).cast::<u8>(),
+            size_of::<SyscallFrame>(),
+        )
+    }
+}
+
+/// The raw bytes of a register frame, for filling it in.
+fn frame_bytes_mut(frame: &mut SyscallFrame) -> &mut [u8] {
+    unsafe {
+        core::slice::from_raw_parts_mut(
+            (frame as *mut SyscallFrame).cast::<u8>(),
+            size_of::<SyscallFrame>(),
+        )
+    }
+}
@@ -0,0 +1,321 @@
+//! # Random Numbers
+//!
+//! Kernel-wide source of cryptographically secure random bytes, used for
+//! the KASLR slide, network sequence numbers, and `/dev/random`.
+//!
+//! ## Entropy
+//!
+//! Entropy is collected from every source the machine has:
+//!
+//! - RDSEED and RDRAND, when CPUID reports them;
+//! - jitter of the TSC while timing a short memory-bound loop;
+//! - the TSC at every hardware interrupt, see [`add_interrupt_timing`];
+//! - anything passed to [`add_entropy`], e.g. bytes from a
+//!   [virtio entropy device](crate::virtio::rng) or writes to `/dev/random`.
+//!
+//! Interrupt timings go into a small lock-free pool of atomics that
+//! handlers can stir without taking a lock. The pool is folded into the
+//! generator whenever [`RESEED_EVENTS`] events have arrived since the last
+//! reseed.
+//!
+//! ## Generator
+//!
+//! Output comes from ChaCha20 with fast key erasure: every request first
+//! produces one block from the current key, half of which replaces that
+//! key and the other half keys the output of this request. A later
+//! compromise of the state thus reveals nothing about earlier output, and
+//! requests are generated without holding the lock.
+//!
+//! The generator seeds itself on first use, so it is available before
+//! anything else is initialized, in particular to KASLR.
+
+use core::arch::asm;
+use core::arch::x86_64::__cpuid_count;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::random::RdRand;
+
+use crate::arch::tsc;
+use crate::println;
+
+/// Interrupt events after which the pool is folded into the generator.
+pub const RESEED_EVENTS: usize = 64;
+
+/// Words of the interrupt timing pool.
+const POOL_WORDS: usize = 4;
+
+/// Samples of TSC jitter taken when seeding.
+const JITTER_SAMPLES: usize = 32;
+
+/// Attempts of RDSEED before giving up on it, as it may run dry.
+const RDSEED_RETRIES: usize = 16;
+
+/// "expand 32-byte k", the ChaCha constants.
+const CHACHA_CONSTANTS: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+
+/// The interrupt timing pool.
+static POOL: [AtomicU64; POOL_WORDS] = [const { AtomicU64::new(0) }; POOL_WORDS];
+
+/// Events stirred into [`POOL`] since the last reseed.
+static POOL_EVENTS: AtomicUsize = AtomicUsize::new(0);
+
+/// The generator.
+static GENERATOR: Mutex<Generator> = Mutex::new(Generator {
+    key: [0; 8],
+    seeded: false,
+});
+
+/// State of the ChaCha20 generator.
+struct Generator {
+    /// Current key
+    key: [u32; 8],
+    /// Seeded at least once
+    seeded: bool,
+}
+
+/// Sources of entropy found on this machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sources {
+    /// RDSEED is available
+    pub rdseed: bool,
+    /// RDRAND is available
+    pub rdrand: bool,
+}
+
+impl Generator {
+    /// Mixes `words` into the key through one ChaCha20 block.
+    fn mix(&mut self, words: &[u64]) {
+        let mut input = self.key;
+        for (i, word) in words.iter().enumerate() {
+            input[(2 * i) % 8] ^= *word as u32;
+            input[(2 * i + 1) % 8] ^= (*word >> 32) as u32;
+        }
+        let block = chacha20_block(&input, 0, words.len() as u64);
+        self.key.copy_from_slice(&block[..8]);
+    }
+
+    /// Seeds the generator from the CPU and TSC jitter.
+    fn seed(&mut self) {
+        let mut words = [0u64; 8];
+        for word in &mut words {
+            *word = rdseed().or_else(rdrand).unwrap_or(0);
+        }
+        self.mix(&words);
+        for word in &mut words {
+            *word = jitter();
+        }
+        self.mix(&words);
+        self.seeded = true;
+    }
+
+    /// Folds the interrupt timing pool into the key, and more output of
+    /// the CPU if it has any.
+    fn reseed(&mut self) {
+        POOL_EVENTS.store(0, Ordering::Relaxed);
+        let mut words = [0u64; POOL_WORDS + 1];
+        for (word, pool) in words.iter_mut().zip(&POOL) {
+            *word = pool.swap(0, Ordering::Relaxed);
+        }
+        words[POOL_WORDS] = rdrand().unwrap_or_else(tsc::read);
+        self.mix(&words);
+    }
+
+    /// Replaces the key and returns a fresh key for one request.
+    fn next_key(&mut self) -> [u32; 8] {
+        if !self.seeded {
+            self.seed();
+        }
+        if POOL_EVENTS.load(Ordering::Relaxed) >= RESEED_EVENTS {
+            self.reseed();
+        }
+        let block = chacha20_block(&self.key, 0, 0);
+        self.key.copy_from_slice(&block[..8]);
+        let mut request = [0; 8];
+        request.copy_from_slice(&block[8..]);
+        request
+    }
+}
+
+/// Fills `buf` with random bytes.
+///
+/// Safe to call at any time, including from interrupt handlers and before
+/// [`init`].
+pub fn fill(buf: &mut [u8]) {
+    let key = interrupts::without_interrupts(|| GENERATOR.lock().next_key());
+    for (counter, chunk) in buf.chunks_mut(64).enumerate() {
+        let block = chacha20_block(&key, counter as u64, 0);
+        for (bytes, word) in chunk.chunks_mut(4).zip(block) {
+            bytes.copy_from_slice(&word.to_le_bytes()[..bytes.len()]);
+        }
+    }
+}
+
+/// Returns a random `u64`.
+pub fn next_u64() -> u64 {
+    let mut bytes = [0; 8];
+    fill(&mut bytes);
+    u64::from_le_bytes(bytes)
+}
+
+/// Returns a random `u32`.
+pub fn next_u32() -> u32 {
+    let mut bytes = [0; 4];
+    fill(&mut bytes);
+    u32::from_le_bytes(bytes)
+}
+
+/// Mixes `data` into the generator.
+///
+/// The data is not credited as entropy, so it cannot make the output more
+/// predictable even if an attacker chose it.
+pub fn add_entropy(data: &[u8]) {
+    interrupts::without_interrupts(|| {
+        let mut generator = GENERATOR.lock();
+        for chunk in data.chunks(64) {
+            let mut words = [0u64; 8];
+            for (word, bytes) in words.iter_mut().zip(chunk.chunks(8)) {
+                let mut buf = [0; 8];
+                buf[..bytes.len()].copy_from_slice(bytes);
+                *word = u64::from_le_bytes(buf);
+            }
+            generator.mix(&words);
+        }
+    });
+}
+
+/// Stirs the TSC at interrupt `irq` into the pool.
+///
+/// Called by hardware interrupt handlers; lock-free and cheap.
+pub fn add_interrupt_timing(irq: u8) {
+    let events = POOL_EVENTS.fetch_add(1, Ordering::Relaxed);
+    let word = &POOL[events % POOL_WORDS];
+    let sample = tsc::read() ^ (u64::from(irq) << 56);
+    let mixed = (word.load(Ordering::Relaxed) ^ sample)
+        .rotate_left(23)
+        .wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    word.store(mixed, Ordering::Relaxed);
+}
+
+/// Reports which hardware sources this machine has.
+pub fn sources() -> Sources {
+    Sources {
+        rdseed: has_rdseed(),
+        rdrand: RdRand::new().is_some(),
+    }
+}
+
+/// Seeds the generator, unless something already used it, and prints the
+/// sources found.
+pub fn init() {
+    interrupts::without_interrupts(|| {
+        let mut generator = GENERATOR.lock();
+        if !generator.seeded {
+            generator.seed();
+        }
+    });
+    let sources = sources();
+    println!(
+        "rand: seeded from{}{} TSC jitter and interrupt timings",
+        if sources.rdseed { " RDSEED," } else { "" },
+        if sources.rdrand { " RDRAND," } else { "" },
+    );
+}
+
+/// Returns `true` if CPUID reports RDSEED.
+fn has_rdseed() -> bool {
+    let max_leaf = __cpuid_count(0, 0).eax;
+    max_leaf >= 7 && __cpuid_count(7, 0).ebx & (1 << 18) != 0
+}
+
+/// Returns a value from RDSEED, or `None` if there is none or it ran dry.
+fn rdseed() -> Option<u64> {
+    if !has_rdseed() {
+        return None;
+    }
+    for _ in 0..RDSEED_RETRIES {
+        let value: u64;
+        let ok: u8;
+        unsafe {
+            asm!(
+                "rdseed {value}",
+                "setc {ok}",
+                value = out(reg) value,
+                ok = out(reg_byte) ok,
+                options(nomem, nostack),
+            );
+        }
+        if ok != 0 {
+            return Some(value);
+        }
+        core::hint::spin_loop();
+    }
+    None
+}
+
+/// Returns a value from RDRAND, or `None` if there is none.
+fn rdrand() -> Option<u64> {
+    RdRand::new().and_then(RdRand::get_u64)
+}
+
+/// Collects the jitter of timing a short loop over memory.
+fn jitter() -> u64 {
+    let mut scratch = [0u8; 256];
+    let mut acc = 0u64;
+    for sample in 0..JITTER_SAMPLES {
+        let start = tsc::read();
+        for i in 0..scratch.len() {
+            let index = (i * 167 + sample) % scratch.len();
+            scratch[index] = scratch[index].wrapping_add(i as u8);
+            core::hint::black_box(&scratch[index]);
+        }
+        let delta = tsc::read().wrapping_sub(start);
+        acc = (acc ^ delta).rotate_left(7);
+    }
+    acc
+}
+
+/// Computes one ChaCha20 block.
+///
+/// # Arguments
+///
+/// * `key` - The 256-bit key
+/// * `counter` - Block counter
+/// * `nonce` - Nonce distinguishing streams under the same key
+fn chacha20_block(key: &[u32; 8], counter: u64, nonce: u64) -> [u32; 16] {
+    let mut input = [0u32; 16];
+    input[..4].copy_from_slice(&CHACHA_CONSTANTS);
+    input[4..12].copy_from_slice(key);
+    input[12] = counter as u32;
+    input[13] = (counter >> 32) as u32;
+    input[14] = nonce as u32;
+    input[15] = (nonce >> 32) as u32;
+
+    let mut x = input;
+    for _ in 0..10 {
+        quarter_round(&mut x, 0, 4, 8, 12);
+        quarter_round(&mut x, 1, 5, 9, 13);
+        quarter_round(&mut x, 2, 6, 10, 14);
+        quarter_round(&mut x, 3, 7, 11, 15);
+        quarter_round(&mut x, 0, 5, 10, 15);
+        quarter_round(&mut x, 1, 6, 11, 12);
+        quarter_round(&mut x, 2, 7, 8, 13);
+        quarter_round(&mut x, 3, 4, 9, 14);
+    }
+    for (word, input) in x.iter_mut().zip(input) {
+        *word = word.wrapping_add(input);
+    }
+    x
+}
+
+/// The ChaCha quarter round on words `a`, `b`, `c`, and `d` of `x`.
+fn quarter_round(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(16);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(12);
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(8);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(7);
+}
@@ -0,0 +1,140 @@
+//! # Self-Relocation
+//!
+//! Lets the kernel run at whatever address the bootloader loaded it.
+//!
+//! The kernel is linked as a static PIE at `KERNEL_VIRT_BASE` (see
+//! `linker.ld`), with its dynamic relocations already applied for that
+//! address and kept in `.rela.dyn`. Code only addresses the image relative
+//! to the instruction pointer, so it runs anywhere; the pointers stored in
+//! data (vtables, string slices in statics, the GOT) are what tie it to the
+//! link address.
+//!
+//! [`relocate_self`] runs first thing at the entry point. It compares the
+//! runtime address of a marker static with the pointer to it stored in
+//! `.data`: if they differ, the image was loaded somewhere other than where
+//! its pointers say, and every `R_X86_64_RELATIVE` relocation is applied
+//! again for the actual load address. A loader that already relocated the
+//! image, or one that loaded it at its link address, leaves nothing to do.
+//!
+//! [`kaslr`](crate::kaslr) reuses [`apply`] to move the kernel again later.
+
+use core::arch::asm;
+use core::ptr;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::registers::control::{Cr0, Cr0Flags};
+
+use crate::mm::KERNEL_BASE;
+
+extern "C" {
+    static __kernel_start: u8;
+    static __rela_dyn_start: Elf64Rela;
+    static __rela_dyn_end: Elf64Rela;
+}
+
+/// `R_X86_64_RELATIVE`: `*offset = base + addend`
+pub(crate) const R_X86_64_RELATIVE: u32 = 8;
+
+/// Distance between the link address and where the kernel was loaded.
+static LOAD_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// A byte whose address is known both ways: at runtime from the
+/// instruction pointer, and as of the last relocation from [`MARKER_ADDRESS`].
+static MARKER: u8 = 0;
+
+/// Pointer to [`MARKER`], patched by every relocation of the image.
+static MARKER_ADDRESS: &u8 = &MARKER;
+
+/// An entry of the `.rela.dyn` section.
+#[repr(C)]
+struct Elf64Rela {
+    /// Link-time virtual address of the location to patch
+    offset: u64,
+    /// Relocation type (low 32 bits) and symbol index (high 32 bits)
+    info: u64,
+    /// Value to add to the load base
+    addend: i64,
+}
+
+/// Returns the offset of the loaded kernel from its link address.
+///
+/// This is the load address chosen by the bootloader; a later move by
+/// [`kaslr`](crate::kaslr) is not included.
+pub fn load_offset() -> u64 {
+    LOAD_OFFSET.load(Ordering::Relaxed)
+}
+
+/// Applies the kernel's relocations for the address it was loaded at.
+///
+/// Nothing before this may follow a pointer stored in the image, which
+/// rules out formatting, panicking, and calls through trait objects.
+///
+/// # Safety
+///
+/// Must be called once, by the entry point, before any other kernel code.
+pub unsafe fn relocate_self() {
+    let start: u64;
+    let marker: u64;
+    asm!(
+        "lea {start}, [rip + {kernel_start}]",
+        "lea {marker}, [rip + {marker_static}]",
+        start = out(reg) start,
+        marker = out(reg) marker,
+        kernel_start = sym __kernel_start,
+        marker_static = sym MARKER,
+        options(pure, nomem, nostack, preserves_flags)
+    );
+    let offset = start.wrapping_sub(KERNEL_BASE);
+    let stored = ptr::read_volatile(ptr::addr_of!(MARKER_ADDRESS)) as *const u8 as u64;
+    if stored != marker {
+        apply(offset);
+    }
+    LOAD_OFFSET.store(offset, Ordering::Relaxed);
+}
+
+/// Applies all dynamic relocations for a kernel `offset` bytes above its
+/// link address.
+///
+/// The locations are patched at `offset` as well, so the image must be
+/// mapped there. Only `R_X86_64_RELATIVE` entries are applied, the only
+/// kind a static PIE link produces.
+///
+/// # Returns
+///
+/// The number of entries of any other kind, which were skipped.
+///
+/// # Safety
+///
+/// The image must be mapped at `offset` from its link address, and nothing
+/// may use pointers into the image while they are being rewritten.
+pub(crate) unsafe fn apply(offset: u64) -> usize {
+    let mut entry: *const Elf64Rela;
+    let end: *const Elf64Rela;
+    asm!(
+        "lea {entry}, [rip + {rela_start}]",
+        "lea {end}, [rip + {rela_end}]",
+        entry = out(reg) entry,
+        end = out(reg) end,
+        rela_start = sym __rela_dyn_start,
+        rela_end = sym __rela_dyn_end,
+        options(pure, nomem, nostack, preserves_flags)
+    );
+
+    // Relocations may target pages the bootloader mapped read-only.
+    let cr0 = Cr0::read();
+    Cr0::write(cr0 - Cr0Flags::WRITE_PROTECT);
+
+    let mut skipped = 0;
+    while entry < end {
+        let rela = &*entry;
+        if (rela.info & 0xffff_ffff) as u32 == R_X86_64_RELATIVE {
+            let target = rela.offset.wrapping_add(offset) as *mut u64;
+            target.write_unaligned((rela.addend as u64).wrapping_add(offset));
+        } else {
+            skipped += 1;
+        }
+        entry = entry.add(1);
+    }
+
+    Cr0::write(cr0);
+    skipped
+}
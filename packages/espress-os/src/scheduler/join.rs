@@ -0,0 +1,136 @@
+//! # Joining Threads
+//!
+//! A spawned thread's closure is wrapped so that its return value lands in a
+//! shared packet. The [`JoinHandle`] waits for the packet to be filled,
+//! either by parking the calling thread or, as a future, by registering the
+//! task's waker.
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll};
+use futures_util::task::AtomicWaker;
+use spin::Mutex;
+
+use super::{Thread, ThreadId};
+
+/// Result slot shared between a thread and its [`JoinHandle`].
+struct Packet<T> {
+    /// Return value, present once the thread finished and until it is taken
+    result: Mutex<Option<T>>,
+    /// Set after `result` has been filled
+    done: AtomicBool,
+    /// Thread blocked in [`JoinHandle::join`], if any
+    joiner: Mutex<Option<ThreadId>>,
+    /// Task awaiting the handle, if any
+    waker: AtomicWaker,
+}
+
+impl<T> Packet<T> {
+    /// Stores the return value and wakes whoever waits for it.
+    fn complete(&self, value: T) {
+        *self.result.lock() = Some(value);
+        self.done.store(true, Ordering::Release);
+        self.waker.wake();
+        if let Some(joiner) = self.joiner.lock().take() {
+            super::unpark(joiner);
+        }
+    }
+
+    /// Takes the return value if the thread has finished.
+    fn take(&self) -> Option<T> {
+        if self.done.load(Ordering::Acquire) {
+            self.result.lock().take()
+        } else {
+            None
+        }
+    }
+}
+
+/// Owned permission to wait for a thread and collect its return value.
+///
+/// Dropping the handle detaches the thread; it keeps running and its return
+/// value is dropped when it finishes.
+pub struct JoinHandle<T> {
+    /// The thread being waited for
+    id: ThreadId,
+    /// Where the thread puts its return value
+    packet: Arc<Packet<T>>,
+}
+
+impl<T> JoinHandle<T> {
+    /// ID of the thread.
+    pub fn thread_id(&self) -> ThreadId {
+        self.id
+    }
+
+    /// Handle to the thread, e.g. to change its priority.
+    pub fn thread(&self) -> Thread {
+        Thread { id: self.id }
+    }
+
+    /// Returns `true` once the thread has returned.
+    pub fn is_finished(&self) -> bool {
+        self.packet.done.load(Ordering::Acquire)
+    }
+
+    /// Blocks the calling thread until the thread finishes.
+    ///
+    /// # Returns
+    ///
+    /// The thread's return value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from outside a scheduler thread.
+    pub fn join(self) -> T {
+        let me = super::current_id().expect("join called before the scheduler started");
+        loop {
+            if let Some(value) = self.packet.take() {
+                return value;
+            }
+            *self.packet.joiner.lock() = Some(me);
+            // Recheck: the thread may have finished before it could see us.
+            if let Some(value) = self.packet.take() {
+                return value;
+            }
+            super::park();
+        }
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<T> {
+        if let Some(value) = self.packet.take() {
+            return Poll::Ready(value);
+        }
+        self.packet.waker.register(context.waker());
+        match self.packet.take() {
+            Some(value) => Poll::Ready(value),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Wraps a thread's closure so its return value reaches the returned handle.
+pub(super) fn wrap<F, T>(id: ThreadId, main: F) -> (JoinHandle<T>, Box<dyn FnOnce() + Send>)
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let packet = Arc::new(Packet {
+        result: Mutex::new(None),
+        done: AtomicBool::new(false),
+        joiner: Mutex::new(None),
+        waker: AtomicWaker::new(),
+    });
+    let handle = JoinHandle {
+        id,
+        packet: packet.clone(),
+    };
+    (handle, Box::new(move || packet.complete(main())))
+}
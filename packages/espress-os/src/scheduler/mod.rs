@@ -0,0 +1,782 @@
+//! # Kernel Thread Scheduler
+//!
+//! A preemptive priority scheduler for kernel threads on a single CPU.
+//!
+//! Every thread runs on its own guarded [`KernelStack`]. Threads give up the
+//! CPU voluntarily with [`yield_now`] or are preempted by the timer
+//! interrupt once their time slice ([`TIME_SLICE_TICKS`]) is used up, or as
+//! soon as a thread of a higher [`Priority`] is ready. Threads of the same
+//! priority take turns round-robin; aging keeps low-priority threads from
+//! starving (see [`priority`]). [`Thread::set_priority`] changes a thread's
+//! class at any time. [`preempt_disable`] keeps the running thread on the
+//! CPU for the lifetime of a guard.
+//!
+//! The context that calls [`init`] becomes the first thread (`kernel`). It
+//! keeps running on the stack it already has.
+//!
+//! [`spawn`] returns a [`JoinHandle`] through which the spawner can wait for
+//! the thread, blocking with [`JoinHandle::join`] or asynchronously by
+//! awaiting the handle, and collect its return value. Blocked threads are
+//! parked outside the ready queue until [`unpark`]ed; drivers usually block
+//! through a [`WaitQueue`] instead of parking directly. When no thread is
+//! ready, the scheduler runs the idle thread, which puts the CPU into an
+//! [idle state](crate::arch::idle) until an interrupt makes one ready.
+//! [`stats`] reports how much of the time the CPU spent idle, and [`tasks`]
+//! lists every thread with its state, stack usage, and CPU time.
+//!
+//! CPU time is measured with the [TSC](crate::arch::tsc): every switch
+//! charges the cycles since the previous one to the thread leaving the CPU,
+//! so the idle thread's runtime is the CPU's idle time. Switches, blocks,
+//! and wakeups are also recorded in the [trace](crate::trace) buffer.
+//!
+//! An exited thread's stack is freed later by another thread, since a thread
+//! can never free the stack it is still running on. Freeing unmaps pages,
+//! which takes locks that a preempted thread may hold, so it happens in
+//! thread context with interrupts enabled: when a thread starts, yields, or
+//! parks, and in the idle thread.
+//!
+//! ## Switching
+//!
+//! Each thread control block holds an [`arch::context::Context`]; the switch
+//! itself is [`arch::context::switch_to`]. Registers it does not save are
+//! either saved by the compiler around the call or, for preemption, by the
+//! interrupt handler. Switches happen with interrupts disabled, and the
+//! scheduler lock is released before the contexts are swapped. Queuing and
+//! parking threads may allocate, which is safe there because the
+//! [heap](crate::mm::heap) is only locked with interrupts disabled. A new
+//! thread starts in [`thread_start`], which enables interrupts and calls its
+//! entry function. Kernel threads, including the idle thread and the adopted
+//! boot context, each get a [`TlsBlock`] for the kernel's `#[thread_local]`s.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::arch::context::{self, Context};
+use crate::arch::idle;
+use crate::arch::tls::{self, TlsBlock};
+use crate::arch::tsc;
+use crate::mm::stack::{KernelStack, StackError, DEFAULT_STACK_PAGES};
+use crate::process::Process;
+use crate::trace::{self, Event};
+use crate::{gdt, syscall};
+
+mod join;
+mod preempt;
+pub mod priority;
+mod tasks;
+mod wait;
+
+pub use join::JoinHandle;
+pub use preempt::{preempt_disable, preemptible, PreemptGuard};
+pub use priority::Priority;
+pub use tasks::{print_tasks, show_in_status_bar, tasks, TaskInfo, TaskState};
+pub use wait::WaitQueue;
+
+use priority::RunQueue;
+
+/// Timer ticks a thread may run before it is preempted.
+pub const TIME_SLICE_TICKS: u64 = 1;
+
+/// Global scheduler state, created by [`init`].
+static SCHEDULER: Mutex<Option<Scheduler>> = Mutex::new(None);
+
+/// Unique identifier of a kernel thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    /// Returns a fresh identifier.
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        ThreadId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// The identifier as a number.
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+/// Handle to a kernel thread, used to inspect and adjust it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Thread {
+    /// The thread this handle refers to
+    id: ThreadId,
+}
+
+impl Thread {
+    /// ID of the thread.
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    /// Returns the thread's priority class.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the thread has exited.
+    pub fn priority(&self) -> Option<Priority> {
+        with_control_block(self.id, |thread| thread.priority)
+    }
+
+    /// Changes the thread's priority class.
+    ///
+    /// Takes effect immediately for a queued thread; a running thread keeps
+    /// the CPU until its next switch. Threads that have exited are ignored.
+    pub fn set_priority(&self, priority: Priority) {
+        interrupts::without_interrupts(|| {
+            let mut guard = SCHEDULER.lock();
+            let Some(scheduler) = guard.as_mut() else {
+                return;
+            };
+            if let Some(mut thread) = scheduler.ready.remove(self.id) {
+                thread.priority = priority;
+                thread.effective_priority = priority;
+                let now = scheduler.ticks;
+                scheduler.ready.push(thread, now);
+            } else if let Some(thread) = scheduler.find_mut(self.id) {
+                thread.priority = priority;
+                thread.effective_priority = priority;
+            }
+        });
+    }
+}
+
+/// Returns a handle to the running thread.
+///
+/// # Panics
+///
+/// Panics if the scheduler has not been initialized.
+pub fn current() -> Thread {
+    Thread {
+        id: current_id().expect("scheduler not initialized"),
+    }
+}
+
+/// Runs `f` on the control block of the running or parked thread `id`, or
+/// of a queued one.
+fn with_control_block<R>(id: ThreadId, f: impl FnOnce(&mut ControlBlock) -> R) -> Option<R> {
+    interrupts::without_interrupts(|| {
+        let mut guard = SCHEDULER.lock();
+        let scheduler = guard.as_mut()?;
+        if let Some(thread) = scheduler.ready.find_mut(id) {
+            return Some(f(thread));
+        }
+        scheduler.find_mut(id).map(f)
+    })
+}
+
+/// Lifecycle state of a thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Running or waiting in the ready queue
+    Runnable,
+    /// Parked until another thread or an interrupt handler unparks it
+    Blocked,
+    /// Finished; its stack is freed once another thread runs
+    Exited,
+}
+
+/// Thread control block.
+struct ControlBlock {
+    /// Unique identifier
+    id: ThreadId,
+    /// Name, also used as the owner of the thread's stack
+    name: &'static str,
+    /// Saved execution state while the thread is not running
+    context: Context,
+    /// Lifecycle state
+    state: State,
+    /// Priority class chosen for the thread
+    priority: Priority,
+    /// Class the thread is queued in, raised above `priority` by aging
+    effective_priority: Priority,
+    /// Tick at which the thread was last put in the ready queue
+    enqueued_at: u64,
+    /// TSC cycles the thread has spent on the CPU, up to the last switch
+    /// or [`Scheduler::account`]
+    runtime: u64,
+    /// An unpark arrived while the thread was not parked; the next park
+    /// returns immediately
+    wake_pending: bool,
+    /// Stack the thread runs on; `None` for the adopted boot context
+    stack: Option<KernelStack>,
+    /// Process the thread belongs to; `None` for kernel threads
+    process: Option<Arc<Process>>,
+    /// Thread-local storage of a kernel thread; user threads manage their
+    /// own
+    tls: Option<TlsBlock>,
+}
+
+impl ControlBlock {
+    /// Creates a runnable control block of normal priority.
+    fn new(name: &'static str, context: Context, stack: Option<KernelStack>) -> Box<Self> {
+        Box::new(ControlBlock {
+            id: ThreadId::new(),
+            name,
+            context,
+            state: State::Runnable,
+            priority: Priority::Normal,
+            effective_priority: Priority::Normal,
+            enqueued_at: 0,
+            runtime: 0,
+            wake_pending: false,
+            stack,
+            process: None,
+            tls: None,
+        })
+    }
+
+    /// Gives a kernel thread that has not started yet its own copy of the
+    /// kernel's thread-locals.
+    fn with_tls(mut self: Box<Self>) -> Box<Self> {
+        let tls = TlsBlock::new();
+        self.context.set_fs_base(tls.thread_pointer());
+        self.tls = Some(tls);
+        self
+    }
+}
+
+/// Run queue and currently running thread.
+struct Scheduler {
+    /// The thread on the CPU
+    current: Box<ControlBlock>,
+    /// Threads waiting for the CPU
+    ready: RunQueue,
+    /// Parked threads
+    blocked: BTreeMap<ThreadId, Box<ControlBlock>>,
+    /// Exited threads whose stacks can be freed (boxed like the others, since
+    /// a switch stores the saved stack pointer after the thread is queued)
+    #[allow(clippy::vec_box)]
+    finished: Vec<Box<ControlBlock>>,
+    /// Ticks the current thread has run since it was scheduled
+    slice_ticks: u64,
+    /// The idle thread while it is not running
+    idle: Option<Box<ControlBlock>>,
+    /// ID of the idle thread
+    idle_id: ThreadId,
+    /// Timer ticks since the scheduler started
+    ticks: u64,
+    /// Timer ticks that found the idle thread running
+    idle_ticks: u64,
+    /// Number of context switches performed
+    switches: u64,
+    /// TSC when the scheduler started
+    started_at: u64,
+    /// TSC up to which the running thread's time has been accounted
+    accounted_at: u64,
+}
+
+/// Scheduler statistics, as returned by [`stats`].
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+    /// Timer ticks since the scheduler started
+    pub ticks: u64,
+    /// Ticks during which the CPU was idle
+    pub idle_ticks: u64,
+    /// Context switches performed
+    pub context_switches: u64,
+    /// Live threads, not counting the idle thread
+    pub threads: usize,
+    /// Time since the scheduler started
+    pub uptime: Duration,
+    /// Time the idle thread has been running
+    pub idle_time: Duration,
+}
+
+impl Stats {
+    /// Share of the time the CPU spent running threads, in percent.
+    ///
+    /// Falls back to counting ticks if the TSC is not calibrated.
+    pub fn cpu_utilization(&self) -> u64 {
+        if !self.uptime.is_zero() {
+            let idle = self.idle_time.min(self.uptime);
+            return ((self.uptime - idle).as_nanos() * 100 / self.uptime.as_nanos()) as u64;
+        }
+        match self.ticks {
+            0 => 0,
+            ticks => (ticks - self.idle_ticks.min(ticks)) * 100 / ticks,
+        }
+    }
+}
+
+impl Scheduler {
+    /// Current statistics; call [`account`](Self::account) first for
+    /// up-to-date times.
+    fn stats(&self) -> Stats {
+        let running = usize::from(self.current.id != self.idle_id);
+        let idle = match &self.idle {
+            Some(idle) => idle.runtime,
+            None => self.current.runtime,
+        };
+        Stats {
+            ticks: self.ticks,
+            idle_ticks: self.idle_ticks,
+            context_switches: self.switches,
+            threads: running + self.ready.len() + self.blocked.len(),
+            uptime: tsc::cycles_to_duration(self.accounted_at - self.started_at),
+            idle_time: tsc::cycles_to_duration(idle),
+        }
+    }
+
+    /// Charges the time since the last switch or account to the running
+    /// thread.
+    fn account(&mut self) {
+        let now = tsc::read();
+        self.current.runtime += now - self.accounted_at;
+        self.accounted_at = now;
+    }
+
+    /// Returns the running or parked thread `id`.
+    fn find_mut(&mut self, id: ThreadId) -> Option<&mut ControlBlock> {
+        if self.current.id == id {
+            return Some(&mut self.current);
+        }
+        self.blocked.get_mut(&id).map(|thread| &mut **thread)
+    }
+}
+
+/// Adopts the calling context as the first thread, creates the idle
+/// thread, and enables scheduling.
+///
+/// # Panics
+///
+/// Panics if called more than once or if the idle thread's stack cannot be
+/// allocated.
+pub fn init() {
+    let stack = KernelStack::new(DEFAULT_STACK_PAGES, "idle")
+        .expect("failed to allocate the idle thread's stack");
+    let context = unsafe { Context::new(stack.top().as_u64(), idle_main, 0) };
+    let mut idle = ControlBlock::new("idle", context, Some(stack)).with_tls();
+    idle.priority = Priority::Low;
+    idle.effective_priority = Priority::Low;
+
+    // The adopted boot context is already running, so its thread pointer
+    // goes straight into the CPU.
+    let mut current = ControlBlock::new("kernel", Context::empty(), None);
+    let tls = TlsBlock::new();
+    unsafe { tls::set_fs_base(tls.thread_pointer()) };
+    current.tls = Some(tls);
+
+    interrupts::without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        assert!(scheduler.is_none(), "scheduler initialized twice");
+        let now = tsc::read();
+        *scheduler = Some(Scheduler {
+            current,
+            ready: RunQueue::new(),
+            blocked: BTreeMap::new(),
+            finished: Vec::new(),
+            slice_ticks: 0,
+            idle_id: idle.id,
+            idle: Some(idle),
+            ticks: 0,
+            idle_ticks: 0,
+            switches: 0,
+            started_at: now,
+            accounted_at: now,
+        });
+    });
+}
+
+/// Returns scheduler statistics.
+///
+/// # Returns
+///
+/// `None` before the scheduler is initialized.
+pub fn stats() -> Option<Stats> {
+    interrupts::without_interrupts(|| {
+        let mut guard = SCHEDULER.lock();
+        let scheduler = guard.as_mut()?;
+        scheduler.account();
+        Some(scheduler.stats())
+    })
+}
+
+/// Starts a new kernel thread running `main`.
+///
+/// The thread is appended to the ready queue and exits when `main` returns;
+/// its return value is available through the returned handle.
+///
+/// # Errors
+///
+/// Returns a [`StackError`] if the thread's stack cannot be allocated.
+///
+/// # Panics
+///
+/// Panics if the scheduler has not been initialized.
+pub fn spawn<F, T>(main: F) -> Result<JoinHandle<T>, StackError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    spawn_named("thread", main)
+}
+
+/// Starts a new kernel thread with a name shown in diagnostics.
+///
+/// See [`spawn`].
+pub fn spawn_named<F, T>(name: &'static str, main: F) -> Result<JoinHandle<T>, StackError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    spawn_thread(name, None, main)
+}
+
+/// Starts a thread of `process`, running in its address space.
+///
+/// See [`spawn`]. `main` usually enters user mode and never returns.
+pub fn spawn_in<F, T>(process: Arc<Process>, main: F) -> Result<Thread, StackError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    spawn_thread("user", Some(process), main).map(|handle| handle.thread())
+}
+
+/// Creates a thread and appends it to the ready queue.
+fn spawn_thread<F, T>(
+    name: &'static str,
+    process: Option<Arc<Process>>,
+    main: F,
+) -> Result<JoinHandle<T>, StackError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let stack = KernelStack::new(DEFAULT_STACK_PAGES, name)?;
+    let mut thread = ControlBlock::new(name, Context::empty(), None);
+    let is_kernel_thread = process.is_none();
+    thread.process = process;
+    let (handle, main) = join::wrap(thread.id, main);
+
+    // Double boxing turns the closure into a thin pointer for the context.
+    let main: Box<Box<dyn FnOnce() + Send>> = Box::new(main);
+    let arg = Box::into_raw(main) as usize;
+    thread.context = unsafe { Context::new(stack.top().as_u64(), thread_start, arg) };
+    thread.stack = Some(stack);
+    if is_kernel_thread {
+        thread = thread.with_tls();
+    }
+
+    interrupts::without_interrupts(|| {
+        let mut guard = SCHEDULER.lock();
+        let scheduler = guard.as_mut().expect("scheduler not initialized");
+        // Every live thread, the new one and the running one included
+        let threads = scheduler.ready.len() + scheduler.blocked.len() + 2;
+        scheduler.ready.reserve(threads);
+        let now = scheduler.ticks;
+        scheduler.ready.push(thread, now);
+    });
+    Ok(handle)
+}
+
+/// Gives up the CPU to the next ready thread, if any.
+///
+/// The calling thread goes to the back of its priority class, so this only
+/// lets threads of the same or a higher class run; if none is ready, it
+/// returns at once.
+///
+/// # Panics
+///
+/// Panics in debug builds if preemption is disabled.
+#[track_caller]
+pub fn yield_now() {
+    preempt::assert_may_block();
+    interrupts::without_interrupts(|| {
+        // `switch` takes the highest ready class even if it is below the
+        // current thread's, which would let a lower class run first.
+        let contended = SCHEDULER.lock().as_ref().is_some_and(|scheduler| {
+            scheduler
+                .ready
+                .has_at_least(scheduler.current.effective_priority)
+        });
+        if contended {
+            switch();
+        }
+    });
+    reap();
+}
+
+/// Blocks the calling thread until [`unpark`] is called for it.
+///
+/// Returns immediately if an unpark arrived since the last park. Like
+/// `std::thread::park`, this may also return spuriously, so callers must
+/// recheck their condition in a loop.
+///
+/// # Panics
+///
+/// Panics in debug builds if preemption is disabled.
+#[track_caller]
+pub fn park() {
+    preempt::assert_may_block();
+    interrupts::without_interrupts(|| {
+        {
+            let mut guard = SCHEDULER.lock();
+            let Some(scheduler) = guard.as_mut() else {
+                return;
+            };
+            if core::mem::take(&mut scheduler.current.wake_pending) {
+                return;
+            }
+            scheduler.current.state = State::Blocked;
+        }
+        switch();
+    });
+    reap();
+}
+
+/// Makes the thread `id` runnable again if it is parked, or makes its next
+/// [`park`] return immediately otherwise.
+///
+/// Safe to call from interrupt handlers: the scheduler and the heap, which
+/// queuing the thread may allocate from, are only locked with interrupts
+/// disabled, so the interrupted code never holds either. Unknown or exited
+/// threads are ignored.
+pub fn unpark(id: ThreadId) {
+    interrupts::without_interrupts(|| {
+        let mut guard = SCHEDULER.lock();
+        let Some(scheduler) = guard.as_mut() else {
+            return;
+        };
+        if let Some(mut thread) = scheduler.blocked.remove(&id) {
+            trace::record(Event::Wake { thread: id });
+            thread.state = State::Runnable;
+            let now = scheduler.ticks;
+            scheduler.ready.push(thread, now);
+        } else if scheduler.current.id == id {
+            scheduler.current.wake_pending = true;
+        } else if let Some(thread) = scheduler.ready.find_mut(id) {
+            thread.wake_pending = true;
+        }
+    });
+}
+
+/// Saves and restores the calling thread's FPU/SSE registers from now on.
+///
+/// Kernel code never uses them, so threads only need this before running
+/// code that does, such as user programs.
+pub fn enable_fpu() {
+    interrupts::without_interrupts(|| {
+        if let Some(scheduler) = SCHEDULER.lock().as_mut() {
+            scheduler.current.context.enable_fpu();
+        }
+    });
+}
+
+/// Terminates the calling thread.
+///
+/// The thread's stack is freed once another thread runs.
+///
+/// # Panics
+///
+/// Panics in debug builds if preemption is disabled.
+#[track_caller]
+pub fn exit() -> ! {
+    preempt::assert_may_block();
+    interrupts::disable();
+    if let Some(scheduler) = SCHEDULER.lock().as_mut() {
+        scheduler.current.state = State::Exited;
+    }
+    switch();
+    unreachable!("exited thread was scheduled again");
+}
+
+/// Returns the ID of the running thread.
+///
+/// # Returns
+///
+/// `None` before the scheduler is initialized.
+pub fn current_id() -> Option<ThreadId> {
+    interrupts::without_interrupts(|| SCHEDULER.lock().as_ref().map(|s| s.current.id))
+}
+
+/// Returns the process the running thread belongs to.
+///
+/// # Returns
+///
+/// `None` for kernel threads and before the scheduler is initialized.
+pub fn current_process() -> Option<Arc<Process>> {
+    interrupts::without_interrupts(|| {
+        SCHEDULER
+            .lock()
+            .as_ref()
+            .and_then(|s| s.current.process.clone())
+    })
+}
+
+/// Returns the name of the running thread.
+pub fn current_name() -> Option<&'static str> {
+    interrupts::without_interrupts(|| SCHEDULER.lock().as_ref().map(|s| s.current.name))
+}
+
+/// Accounts a timer tick, ages waiting threads, and preempts the running
+/// thread when its time slice is used up or a higher-priority thread is
+/// ready, unless preemption is disabled (see [`preempt_disable`]).
+///
+/// Called from the timer interrupt handler after the end of interrupt has
+/// been signaled. If the scheduler is locked by the interrupted code, the
+/// tick is skipped.
+pub fn tick() {
+    let expired = match SCHEDULER.try_lock() {
+        Some(mut scheduler) => match scheduler.as_mut() {
+            Some(scheduler) => {
+                scheduler.ticks += 1;
+                scheduler.ready.age(scheduler.ticks);
+                tasks::tick(scheduler);
+                if scheduler.current.id == scheduler.idle_id {
+                    scheduler.idle_ticks += 1;
+                    return;
+                }
+                if scheduler.current.state != State::Runnable {
+                    return;
+                }
+                // A thread whose slice is up only makes way for threads of
+                // at least its own class.
+                let priority = scheduler.current.effective_priority;
+                scheduler.slice_ticks += 1;
+                scheduler.ready.has_above(priority)
+                    || (scheduler.slice_ticks >= TIME_SLICE_TICKS
+                        && scheduler.ready.has_at_least(priority))
+            }
+            None => false,
+        },
+        None => false,
+    };
+    if expired && preempt::may_preempt() {
+        switch();
+    }
+}
+
+/// Switches to the next ready thread.
+///
+/// Must be called with interrupts disabled. The next thread is the first of
+/// the highest non-empty priority class, even if that is lower than the
+/// current thread's; callers that want to keep a high-priority thread on the
+/// CPU must not switch. A runnable current thread goes to the back of its
+/// class, a blocked one to the parked threads, and an exited one is queued
+/// for reaping. If no other thread is ready, a runnable current thread keeps
+/// running; otherwise the idle thread takes over.
+fn switch() {
+    let (prev, next) = {
+        let mut guard = SCHEDULER.lock();
+        let Some(scheduler) = guard.as_mut() else {
+            return;
+        };
+        scheduler.slice_ticks = 0;
+
+        let next = match scheduler.ready.pop() {
+            Some(mut next) => {
+                next.effective_priority = next.priority;
+                next
+            }
+            None => match scheduler.current.state {
+                State::Runnable => return,
+                State::Blocked if core::mem::take(&mut scheduler.current.wake_pending) => {
+                    scheduler.current.state = State::Runnable;
+                    return;
+                }
+                State::Blocked | State::Exited => scheduler
+                    .idle
+                    .take()
+                    .expect("idle thread blocked or exited"),
+            },
+        };
+
+        scheduler.account();
+        let mut prev = core::mem::replace(&mut scheduler.current, next);
+        scheduler.switches += 1;
+        trace::record(Event::Switch {
+            from: prev.id,
+            to: scheduler.current.id,
+        });
+        // Control blocks live in boxes, so these pointers stay valid while
+        // the boxes move between queues.
+        let prev_context: *mut Context = &mut prev.context;
+        let next_context: *const Context = &scheduler.current.context;
+        if let Some(stack) = &scheduler.current.stack {
+            gdt::set_kernel_stack(stack.top());
+            syscall::set_kernel_stack(stack.top());
+        }
+        // Kernel threads run in whatever address space is loaded, since
+        // they only touch the shared kernel half.
+        if let Some(process) = &scheduler.current.process {
+            process.address_space().activate();
+        }
+        if prev.id == scheduler.idle_id {
+            scheduler.idle = Some(prev);
+        } else {
+            match prev.state {
+                State::Runnable => {
+                    let now = scheduler.ticks;
+                    scheduler.ready.push(prev, now);
+                }
+                State::Blocked => {
+                    trace::record(Event::Block { thread: prev.id });
+                    scheduler.blocked.insert(prev.id, prev);
+                }
+                State::Exited => scheduler.finished.push(prev),
+            }
+        }
+        (prev_context, next_context)
+    };
+
+    unsafe { context::switch_to(&mut *prev, &*next) };
+}
+
+/// Frees the stacks of exited threads.
+///
+/// Never runs on an exited thread's own stack. Does nothing with interrupts
+/// disabled, e.g. in the timer interrupt: unmapping a stack takes the page
+/// table and frame allocator locks, which the preempted thread may hold.
+fn reap() {
+    if !interrupts::are_enabled() {
+        return;
+    }
+    let finished = interrupts::without_interrupts(|| match SCHEDULER.lock().as_mut() {
+        Some(scheduler) => core::mem::take(&mut scheduler.finished),
+        None => Vec::new(),
+    });
+    drop(finished);
+}
+
+/// Body of the idle thread.
+///
+/// Runs only when no other thread is ready. Sleeps until an interrupt
+/// arrives and hands the CPU over as soon as that interrupt readied a
+/// thread. Checking the run queue and going to sleep happen with
+/// interrupts disabled up to [`idle::enter`], so a wakeup cannot slip in
+/// between.
+extern "C" fn idle_main(_: usize) -> ! {
+    loop {
+        reap();
+        interrupts::disable();
+        let ready = SCHEDULER
+            .lock()
+            .as_ref()
+            .is_some_and(|scheduler| !scheduler.ready.is_empty());
+        if ready {
+            switch();
+        } else {
+            idle::enter();
+        }
+    }
+}
+
+/// First function run by every spawned thread.
+///
+/// `main` is the double-boxed thread closure created by [`spawn_named`].
+extern "C" fn thread_start(main: usize) -> ! {
+    interrupts::enable();
+    reap();
+    let main = unsafe { Box::from_raw(main as *mut Box<dyn FnOnce() + Send>) };
+    main();
+    exit();
+}
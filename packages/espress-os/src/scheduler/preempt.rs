@@ -0,0 +1,88 @@
+//! # Preemption Control
+//!
+//! [`preempt_disable`] keeps the running thread on the CPU until the
+//! returned [`PreemptGuard`] is dropped, without masking interrupts:
+//! interrupt handlers still run, but the timer tick no longer switches
+//! threads. This is meant for short critical sections that must not be
+//! rescheduled, e.g. while holding a spinlock another thread would otherwise
+//! spin on for a whole time slice.
+//!
+//! Guards nest. A preemption that falls due while any guard is alive is
+//! remembered, and dropping the last one [yields](super::yield_now): the
+//! thread makes way if a thread of its own class or above is still ready,
+//! and keeps running otherwise.
+//!
+//! Giving up the CPU voluntarily (parking, yielding, exiting) while
+//! preemption is disabled would defeat the guard, and is a bug that debug
+//! builds catch with an assertion.
+
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Number of live [`PreemptGuard`]s. There is a single CPU, and the thread
+/// holding a guard cannot be switched away from, so one global count
+/// suffices.
+static DISABLED: AtomicUsize = AtomicUsize::new(0);
+
+/// A tick wanted to preempt while preemption was disabled.
+static PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Keeps preemption disabled while alive; see [`preempt_disable`].
+///
+/// Not `Send`: the guard belongs to the thread that created it.
+#[must_use = "preemption is enabled again when the guard is dropped"]
+pub struct PreemptGuard {
+    _not_send: PhantomData<*const ()>,
+}
+
+/// Disables preemption of the running thread until the returned guard is
+/// dropped.
+pub fn preempt_disable() -> PreemptGuard {
+    DISABLED.fetch_add(1, Ordering::Acquire);
+    PreemptGuard {
+        _not_send: PhantomData,
+    }
+}
+
+/// Returns `true` unless a [`PreemptGuard`] is alive.
+pub fn preemptible() -> bool {
+    DISABLED.load(Ordering::Relaxed) == 0
+}
+
+impl Drop for PreemptGuard {
+    /// Enables preemption again if this is the last guard, and then yields
+    /// if a preemption was deferred.
+    fn drop(&mut self) {
+        if DISABLED.fetch_sub(1, Ordering::Release) == 1 && PENDING.swap(false, Ordering::Relaxed) {
+            super::yield_now();
+        }
+    }
+}
+
+/// Called by the timer tick when the running thread is due to be
+/// preempted.
+///
+/// # Returns
+///
+/// `true` if the switch may happen now; otherwise it is deferred until
+/// preemption is enabled again.
+pub(super) fn may_preempt() -> bool {
+    if preemptible() {
+        return true;
+    }
+    PENDING.store(true, Ordering::Relaxed);
+    false
+}
+
+/// Asserts in debug builds that the caller may give up the CPU.
+///
+/// # Panics
+///
+/// Panics in debug builds if preemption is disabled.
+#[track_caller]
+pub(super) fn assert_may_block() {
+    debug_assert!(
+        preemptible(),
+        "blocking scheduler call with preemption disabled"
+    );
+}
@@ -0,0 +1,158 @@
+//! # Priorities and the Run Queue
+//!
+//! Ready threads wait in one FIFO queue per [`Priority`] class, and the
+//! scheduler always picks from the highest non-empty class. To keep a steady
+//! stream of high-priority work from starving everything else, threads age:
+//! a thread that has waited [`AGING_TICKS`] timer ticks at the front of its
+//! queue is promoted one class. The boost lasts until it next runs, after
+//! which it returns to its own priority.
+//!
+//! Every queue has room for all threads, reserved when a thread is spawned,
+//! so queuing a thread never allocates. Interrupt handlers can thus wake
+//! threads without touching the heap.
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+
+use super::{ControlBlock, ThreadId};
+
+/// Ticks a thread may wait at the front of its queue before it is promoted.
+pub const AGING_TICKS: u64 = 8;
+
+/// Number of priority classes.
+pub const PRIORITY_LEVELS: usize = 4;
+
+/// Scheduling class of a thread; higher classes run first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[repr(u8)]
+pub enum Priority {
+    /// Housekeeping that may run whenever nothing else needs the CPU
+    Low = 0,
+    /// Default class for new threads
+    #[default]
+    Normal = 1,
+    /// Latency-sensitive work such as input handling
+    High = 2,
+    /// Work that must preempt everything else
+    Realtime = 3,
+}
+
+impl Priority {
+    /// Lowercase name for listings.
+    pub fn name(self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+            Priority::Realtime => "realtime",
+        }
+    }
+
+    /// The class one level up, saturating at [`Priority::Realtime`].
+    fn raised(self) -> Priority {
+        match self {
+            Priority::Low => Priority::Normal,
+            Priority::Normal => Priority::High,
+            Priority::High | Priority::Realtime => Priority::Realtime,
+        }
+    }
+}
+
+/// Ready threads, one FIFO queue per priority class.
+pub(super) struct RunQueue {
+    /// Queues indexed by [`Priority`]
+    queues: [VecDeque<Box<ControlBlock>>; PRIORITY_LEVELS],
+}
+
+impl RunQueue {
+    pub(super) const fn new() -> Self {
+        RunQueue {
+            queues: [const { VecDeque::new() }; PRIORITY_LEVELS],
+        }
+    }
+
+    /// Appends a thread to the queue of its effective priority.
+    pub(super) fn push(&mut self, thread: Box<ControlBlock>, now: u64) {
+        let mut thread = thread;
+        thread.enqueued_at = now;
+        self.queues[thread.effective_priority as usize].push_back(thread);
+    }
+
+    /// Makes room for `threads` threads in every class, so that
+    /// [`push`](Self::push) does not allocate until there are more.
+    pub(super) fn reserve(&mut self, threads: usize) {
+        for queue in &mut self.queues {
+            queue.reserve(threads.saturating_sub(queue.len()));
+        }
+    }
+
+    /// Removes the first thread of the highest non-empty class.
+    pub(super) fn pop(&mut self) -> Option<Box<ControlBlock>> {
+        self.queues.iter_mut().rev().find_map(VecDeque::pop_front)
+    }
+
+    /// Returns `true` if no thread is waiting.
+    pub(super) fn is_empty(&self) -> bool {
+        self.queues.iter().all(VecDeque::is_empty)
+    }
+
+    /// Number of waiting threads.
+    pub(super) fn len(&self) -> usize {
+        self.queues.iter().map(VecDeque::len).sum()
+    }
+
+    /// Returns `true` if a thread of a class above `priority` is waiting.
+    pub(super) fn has_above(&self, priority: Priority) -> bool {
+        self.queues[priority as usize + 1..]
+            .iter()
+            .any(|queue| !queue.is_empty())
+    }
+
+    /// Returns `true` if a thread of class `priority` or above is waiting.
+    pub(super) fn has_at_least(&self, priority: Priority) -> bool {
+        self.queues[priority as usize..]
+            .iter()
+            .any(|queue| !queue.is_empty())
+    }
+
+    /// Promotes the front thread of every class that has waited too long.
+    ///
+    /// At most one thread per class moves up per call, which keeps the cost
+    /// per timer tick constant.
+    pub(super) fn age(&mut self, now: u64) {
+        for level in (0..PRIORITY_LEVELS - 1).rev() {
+            let starved = self.queues[level]
+                .front()
+                .is_some_and(|thread| now - thread.enqueued_at >= AGING_TICKS);
+            if starved {
+                let mut thread = self.queues[level].pop_front().unwrap();
+                thread.effective_priority = thread.effective_priority.raised();
+                self.push(thread, now);
+            }
+        }
+    }
+
+    /// Removes the thread `id`, wherever it is queued.
+    pub(super) fn remove(&mut self, id: ThreadId) -> Option<Box<ControlBlock>> {
+        self.queues.iter_mut().find_map(|queue| {
+            let index = queue.iter().position(|thread| thread.id == id)?;
+            queue.remove(index)
+        })
+    }
+
+    /// Returns the queued thread `id`.
+    pub(super) fn iter(&self) -> impl Iterator<Item = &ControlBlock> {
+        self.queues
+            .iter()
+            .flat_map(|queue| queue.iter())
+            .map(|thread| &**thread)
+    }
+
+    pub(super) fn find_mut(&mut self, id: ThreadId) -> Option<&mut ControlBlock> {
+        self.queues
+            .iter_mut()
+            .flat_map(|queue| queue.iter_mut())
+            .find(|thread| thread.id == id)
+            .map(|thread| &mut **thread)
+    }
+}
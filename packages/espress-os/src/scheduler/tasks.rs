@@ -0,0 +1,218 @@
+//! # Task Listing
+//!
+//! Snapshots of every thread the scheduler knows about, for diagnostics:
+//! [`tasks`] returns them as data, [`print_tasks`] prints a `ps`-style
+//! table, and [`show_in_status_bar`] keeps a one-line summary in the VGA
+//! status bar, refreshed once a second from the timer interrupt.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+use x86_64::instructions::interrupts;
+
+use super::{ControlBlock, Priority, Scheduler, ThreadId, SCHEDULER};
+use crate::arch::tsc;
+use crate::process::Pid;
+use crate::{println, timer};
+
+/// Timer ticks between two status bar refreshes.
+const STATUS_BAR_INTERVAL: u64 = timer::TICK_HZ as u64;
+
+/// Whether the task summary is shown in the VGA status bar.
+static STATUS_BAR: AtomicBool = AtomicBool::new(false);
+
+/// What a thread is doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    /// On the CPU
+    Running,
+    /// Waiting for the CPU
+    Ready,
+    /// Parked until something wakes it
+    Blocked,
+}
+
+impl TaskState {
+    /// Lowercase name for listings.
+    pub fn name(self) -> &'static str {
+        match self {
+            TaskState::Running => "running",
+            TaskState::Ready => "ready",
+            TaskState::Blocked => "blocked",
+        }
+    }
+}
+
+/// Snapshot of one thread.
+#[derive(Debug, Clone, Copy)]
+pub struct TaskInfo {
+    /// Thread identifier
+    pub id: ThreadId,
+    /// Thread name
+    pub name: &'static str,
+    /// Process the thread runs, `None` for kernel threads
+    pub pid: Option<Pid>,
+    /// Scheduling state
+    pub state: TaskState,
+    /// Priority class the thread was given (aging may temporarily raise it)
+    pub priority: Priority,
+    /// Most bytes of its kernel stack the thread has used so far
+    pub stack_used: u64,
+    /// Size of its kernel stack; `0` for the boot thread, whose stack the
+    /// scheduler does not own
+    pub stack_size: u64,
+    /// Time the thread has spent on the CPU
+    pub cpu_time: Duration,
+}
+
+impl TaskInfo {
+    /// Builds the snapshot of `thread`.
+    fn new(thread: &ControlBlock, state: TaskState) -> Self {
+        TaskInfo {
+            id: thread.id,
+            name: thread.name,
+            pid: thread.process.as_ref().map(|process| process.pid()),
+            state,
+            priority: thread.priority,
+            stack_used: thread.stack.as_ref().map_or(0, |stack| stack.peak_usage()),
+            stack_size: thread.stack.as_ref().map_or(0, |stack| stack.size()),
+            cpu_time: tsc::cycles_to_duration(thread.runtime),
+        }
+    }
+}
+
+/// Returns a snapshot of every live thread, including the idle thread,
+/// ordered by ID.
+pub fn tasks() -> Vec<TaskInfo> {
+    loop {
+        // Allocating with the scheduler locked could deadlock on the heap
+        // lock, so room is made first and filled afterwards.
+        let count = interrupts::without_interrupts(|| {
+            SCHEDULER.lock().as_ref().map_or(0, Scheduler::thread_count)
+        });
+        let mut tasks = Vec::with_capacity(count);
+        let complete = interrupts::without_interrupts(|| {
+            let mut guard = SCHEDULER.lock();
+            let Some(scheduler) = guard.as_mut() else {
+                return true;
+            };
+            if scheduler.thread_count() > tasks.capacity() {
+                return false;
+            }
+            scheduler.account();
+            let current = core::iter::once((&*scheduler.current, TaskState::Running));
+            let idle = scheduler
+                .idle
+                .as_deref()
+                .map(|idle| (idle, TaskState::Ready));
+            let ready = scheduler
+                .ready
+                .iter()
+                .map(|thread| (thread, TaskState::Ready));
+            let blocked = scheduler
+                .blocked
+                .values()
+                .map(|thread| (&**thread, TaskState::Blocked));
+            for (thread, state) in current.chain(idle).chain(ready).chain(blocked) {
+                tasks.push(TaskInfo::new(thread, state));
+            }
+            true
+        });
+        if complete {
+            tasks.sort_unstable_by_key(|task| task.id);
+            return tasks;
+        }
+    }
+}
+
+/// Prints every thread as a table.
+pub fn print_tasks() {
+    let tasks = tasks();
+    let total = tasks
+        .iter()
+        .map(|task| task.cpu_time)
+        .sum::<Duration>()
+        .as_micros()
+        .max(1);
+
+    println!(
+        "  {:>4}  {:>4}  {:<16}  {:<7}  {:<8}  {:>13}  {:>10}",
+        "tid", "pid", "name", "state", "priority", "stack (KiB)", "cpu (ms)"
+    );
+    for task in &tasks {
+        let pid = task.pid.map_or(0, Pid::as_u64);
+        println!(
+            "  {:>4}  {:>4}  {:<16}  {:<7}  {:<8}  {:>6}/{:<6}  {:>6} {:>2}%",
+            task.id.as_u64(),
+            pid,
+            task.name,
+            task.state.name(),
+            task.priority.name(),
+            task.stack_used / 1024,
+            task.stack_size / 1024,
+            task.cpu_time.as_millis(),
+            task.cpu_time.as_micros() * 100 / total
+        );
+    }
+}
+
+/// Enables or disables the task summary in the VGA status bar.
+///
+/// The status bar has room for one line; whichever subsystem updates it
+/// last is shown. Disabling it removes the status bar again.
+pub fn show_in_status_bar(enabled: bool) {
+    STATUS_BAR.store(enabled, Ordering::Relaxed);
+    if enabled {
+        interrupts::without_interrupts(|| {
+            if let Some(scheduler) = SCHEDULER.lock().as_mut() {
+                scheduler.account();
+                draw_status_bar(scheduler);
+            }
+        });
+    } else {
+        crate::WRITER.lock().clear_status();
+    }
+}
+
+/// Redraws the status bar if it is enabled and due.
+///
+/// Called from the timer interrupt with the scheduler locked; it neither
+/// allocates nor blocks.
+pub(super) fn tick(scheduler: &mut Scheduler) {
+    if STATUS_BAR.load(Ordering::Relaxed) && scheduler.ticks.is_multiple_of(STATUS_BAR_INTERVAL) {
+        scheduler.account();
+        draw_status_bar(scheduler);
+    }
+}
+
+/// Writes the one-line summary, unless the VGA writer is locked.
+fn draw_status_bar(scheduler: &Scheduler) {
+    let ready = scheduler.ready.len();
+    let blocked = scheduler.blocked.len();
+    let busiest = core::iter::once(&*scheduler.current)
+        .chain(scheduler.ready.iter())
+        .chain(scheduler.blocked.values().map(|thread| &**thread))
+        .filter(|thread| thread.id != scheduler.idle_id)
+        .max_by_key(|thread| thread.runtime)
+        .map_or("-", |thread| thread.name);
+    let stats = scheduler.stats();
+
+    if let Some(mut writer) = crate::WRITER.try_lock() {
+        writer.set_status(format_args!(
+            " tasks: {} ({} ready, {} blocked) | cpu {}% | busiest: {} | {} switches",
+            stats.threads,
+            ready,
+            blocked,
+            stats.cpu_utilization(),
+            busiest,
+            stats.context_switches
+        ));
+    }
+}
+
+impl Scheduler {
+    /// Number of live threads, including the idle thread.
+    fn thread_count(&self) -> usize {
+        1 + usize::from(self.idle.is_some()) + self.ready.len() + self.blocked.len()
+    }
+}
@@ -0,0 +1,151 @@
+//! # Wait Queues
+//!
+//! A [`WaitQueue`] lets threads sleep until some condition becomes true,
+//! typically one set by an interrupt handler. Waiters park themselves in the
+//! scheduler and are unparked by [`WaitQueue::notify_one`] or
+//! [`WaitQueue::notify_all`]; they always recheck their condition, so a
+//! notification that races with going to sleep is never lost (the pending
+//! unpark makes the next [`park`](super::park) return at once).
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use super::ThreadId;
+
+/// Threads waiting for an event, woken in FIFO order.
+pub struct WaitQueue {
+    /// Parked waiters, oldest first
+    waiters: Mutex<VecDeque<ThreadId>>,
+}
+
+impl WaitQueue {
+    /// Creates an empty wait queue.
+    pub const fn new() -> Self {
+        WaitQueue {
+            waiters: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Blocks the calling thread until `cond` returns `true`.
+    ///
+    /// `cond` is evaluated once up front and again after every wakeup. It
+    /// must not block and should be cheap; whoever makes it true must notify
+    /// the queue afterwards.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from outside a scheduler thread.
+    pub fn wait_until(&self, mut cond: impl FnMut() -> bool) {
+        if cond() {
+            return;
+        }
+        let me = super::current_id().expect("wait_until called before the scheduler started");
+        loop {
+            self.enqueue(me);
+            // Recheck: the event may have fired before we were queued.
+            if cond() {
+                break;
+            }
+            super::park();
+            if cond() {
+                break;
+            }
+        }
+        self.remove(me);
+    }
+
+    /// Like [`wait_until`](Self::wait_until), but gives up after `timeout`.
+    ///
+    /// # Returns
+    ///
+    /// `true` if `cond` became true, `false` if the time ran out first.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from outside a scheduler thread.
+    pub fn wait_until_timeout(&self, timeout: Duration, mut cond: impl FnMut() -> bool) -> bool {
+        if cond() {
+            return true;
+        }
+        let me = super::current_id().expect("wait_until called before the scheduler started");
+        let expired = Arc::new(AtomicBool::new(false));
+        let flag = expired.clone();
+        let alarm = crate::timer::Timer::oneshot(timeout, move || {
+            flag.store(true, Ordering::Release);
+            super::unpark(me);
+        });
+        let mut met = false;
+        self.wait_until(|| {
+            met = cond();
+            met || expired.load(Ordering::Acquire)
+        });
+        alarm.cancel();
+        met
+    }
+
+    /// Wakes the longest-waiting thread.
+    ///
+    /// Safe to call from interrupt handlers.
+    ///
+    /// # Returns
+    ///
+    /// `true` if a thread was woken.
+    pub fn notify_one(&self) -> bool {
+        let waiter = interrupts::without_interrupts(|| self.waiters.lock().pop_front());
+        match waiter {
+            Some(id) => {
+                super::unpark(id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Wakes every waiting thread.
+    ///
+    /// Safe to call from interrupt handlers.
+    ///
+    /// # Returns
+    ///
+    /// The number of threads woken.
+    pub fn notify_all(&self) -> usize {
+        let waiters = interrupts::without_interrupts(|| core::mem::take(&mut *self.waiters.lock()));
+        let count = waiters.len();
+        for id in waiters {
+            super::unpark(id);
+        }
+        count
+    }
+
+    /// Returns `true` if no thread is waiting.
+    pub fn is_empty(&self) -> bool {
+        interrupts::without_interrupts(|| self.waiters.lock().is_empty())
+    }
+
+    /// Adds `id` to the back of the queue unless it is already waiting.
+    ///
+    /// May allocate with interrupts disabled, which the heap allows for.
+    fn enqueue(&self, id: ThreadId) {
+        interrupts::without_interrupts(|| {
+            let mut waiters = self.waiters.lock();
+            if !waiters.contains(&id) {
+                waiters.push_back(id);
+            }
+        });
+    }
+
+    /// Drops `id` from the queue, e.g. after a wakeup it did not need.
+    fn remove(&self, id: ThreadId) {
+        interrupts::without_interrupts(|| self.waiters.lock().retain(|&waiter| waiter != id));
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,89 @@
+//! # Serial Port
+//!
+//! Driver for the first 16550 UART (COM1). Under QEMU this is usually wired
+//! to the host terminal (`-serial stdio`), which makes it the most reliable
+//! channel for boot logs and diagnostics.
+//!
+//! The second UART (COM2) is kept free of text, for binary streams such as
+//! [`netdump`](crate::net::netdump) captures; see [`write_com2`].
+
+use spin::Mutex;
+use uart_16550::SerialPort;
+use x86_64::instructions::port::PortReadOnly;
+
+/// I/O port base of COM1.
+const COM1_BASE: u16 = 0x3f8;
+
+/// I/O port base of COM2.
+const COM2_BASE: u16 = 0x2f8;
+
+/// Offset of the line status register.
+const LINE_STATUS: u16 = 5;
+
+/// Line status bit: a received byte is waiting.
+const LINE_STATUS_DATA_READY: u8 = 1;
+
+lazy_static::lazy_static! {
+    /// Global COM1 serial port, initialized on first use.
+    pub static ref SERIAL1: Mutex<SerialPort> = {
+        let mut serial_port = unsafe { SerialPort::new(COM1_BASE) };
+        serial_port.init();
+        Mutex::new(serial_port)
+    };
+
+    /// Global COM2 serial port, initialized on first use.
+    pub static ref SERIAL2: Mutex<SerialPort> = {
+        let mut serial_port = unsafe { SerialPort::new(COM2_BASE) };
+        serial_port.init();
+        Mutex::new(serial_port)
+    };
+}
+
+/// Prints formatted text to the serial port without a newline.
+#[macro_export]
+macro_rules! serial_print {
+    ($($arg:tt)*) => ($crate::serial::_print(format_args!($($arg)*)));
+}
+
+/// Prints formatted text to the serial port with a newline.
+#[macro_export]
+macro_rules! serial_println {
+    () => ($crate::serial_print!("\n"));
+    ($($arg:tt)*) => ($crate::serial_print!("{}\n", format_args!($($arg)*)));
+}
+
+/// Internal function backing the serial print macros.
+#[doc(hidden)]
+pub fn _print(args: core::fmt::Arguments) {
+    use core::fmt::Write;
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        SERIAL1
+            .lock()
+            .write_fmt(args)
+            .expect("printing to serial failed");
+    });
+}
+
+/// Returns the next byte received on COM1, or `None` if none is waiting.
+pub fn try_receive() -> Option<u8> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut serial = SERIAL1.lock();
+        let mut status = PortReadOnly::<u8>::new(COM1_BASE + LINE_STATUS);
+        if unsafe { status.read() } & LINE_STATUS_DATA_READY == 0 {
+            return None;
+        }
+        Some(serial.receive())
+    })
+}
+
+/// Writes `bytes` to COM2 as they are, without the translation of
+/// backspace and delete that text output gets.
+///
+/// Interrupts stay enabled, since the port can take a while to drain;
+/// COM2 must therefore not be used from interrupt handlers.
+pub fn write_com2(bytes: &[u8]) {
+    let mut serial = SERIAL2.lock();
+    for &byte in bytes {
+        serial.send_raw(byte);
+    }
+}
@@ -0,0 +1,124 @@
+//! # Shell Commands
+//!
+//! The commands of the kernel [shell](super), in the order `help` lists
+//! them. Each one prints to the console and ignores arguments it has no
+//! use for.
+
+use super::Command;
+use crate::boot::progress;
+use crate::{console, power, print, println, scheduler, smbios};
+
+/// The commands besides the built-in `help`.
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: "echo",
+        help: "Print the arguments",
+        run: echo,
+    },
+    Command {
+        name: "clear",
+        help: "Clear the screen",
+        run: clear,
+    },
+    Command {
+        name: "dmesg",
+        help: "Print the kernel log",
+        run: dmesg,
+    },
+    Command {
+        name: "stages",
+        help: "List the boot stages and their times",
+        run: stages,
+    },
+    Command {
+        name: "sched",
+        help: "Show scheduler statistics",
+        run: sched,
+    },
+    Command {
+        name: "smbios",
+        help: "Describe the machine from its SMBIOS tables",
+        run: smbios,
+    },
+    Command {
+        name: "suspend",
+        help: "Suspend to RAM until woken up",
+        run: suspend,
+    },
+    Command {
+        name: "reboot",
+        help: "Restart the machine",
+        run: reboot,
+    },
+    Command {
+        name: "poweroff",
+        help: "Turn the machine off",
+        run: poweroff,
+    },
+];
+
+/// Prints the arguments separated by spaces.
+fn echo(args: &[&str]) {
+    println!("{}", args.join(" "));
+}
+
+/// Clears the screen and moves the cursor to the top.
+fn clear(_: &[&str]) {
+    print!("\x1b[2J\x1b[H");
+}
+
+/// Prints the [console log](console::log).
+fn dmesg(_: &[&str]) {
+    print!("{}", console::log());
+}
+
+/// Lists the boot stages with their outcome and time.
+fn stages(_: &[&str]) {
+    for stage in progress::stages() {
+        let micros = stage.duration().as_micros();
+        println!(
+            "  {} {:<24} {:>4}.{:03} ms",
+            if stage.ok { " OK " } else { "FAIL" },
+            stage.name,
+            micros / 1000,
+            micros % 1000
+        );
+    }
+    let micros = progress::elapsed().as_micros();
+    println!("  total {}.{:03} ms", micros / 1000, micros % 1000);
+}
+
+/// Prints the scheduler statistics.
+fn sched(_: &[&str]) {
+    let Some(stats) = scheduler::stats() else {
+        println!("sched: scheduler not running");
+        return;
+    };
+    println!("threads:           {}", stats.threads);
+    println!("ticks:             {}", stats.ticks);
+    println!("context switches:  {}", stats.context_switches);
+    println!("uptime:            {} ms", stats.uptime.as_millis());
+    println!("CPU utilization:   {}%", stats.cpu_utilization());
+}
+
+/// Prints everything decoded from the SMBIOS tables.
+fn smbios(_: &[&str]) {
+    smbios::print_report();
+}
+
+/// Suspends the machine to RAM and returns once it wakes up.
+fn suspend(_: &[&str]) {
+    if let Err(err) = power::suspend() {
+        println!("suspend: {:?}", err);
+    }
+}
+
+/// Writes back the file systems and restarts the machine.
+fn reboot(_: &[&str]) {
+    power::reboot();
+}
+
+/// Writes back the file systems and turns the machine off.
+fn poweroff(_: &[&str]) {
+    power::shutdown();
+}
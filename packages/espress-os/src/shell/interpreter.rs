@@ -0,0 +1,109 @@
+//! # Command Interpreter
+//!
+//! Line editing and command dispatch for the [shell](super). A line is
+//! split into words, and the [`Command`] named by the first one runs with
+//! the rest as arguments; `help` is built in and lists the table.
+//!
+//! Nothing but the console is used here, so the WebAssembly demo compiles
+//! this file as it is and runs it with a table of its own. Colors and the
+//! taken-back characters of a backspace are written as terminal control
+//! codes, which the VGA text engine and serial terminals both understand.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::{print, println};
+
+/// Shown before every line, with the name in bright green.
+pub const PROMPT: &str = "\x1b[92mespress\x1b[0m> ";
+
+/// Most characters of a line, so that it fits on one row of the VGA text
+/// screen after the prompt.
+pub const MAX_LINE: usize = 64;
+
+/// A shell command.
+#[derive(Debug, Clone, Copy)]
+pub struct Command {
+    /// Name that runs the command as the first word of a line
+    pub name: &'static str,
+    /// What the command does, in one line for `help`
+    pub help: &'static str,
+    /// Runs the command with the words after its name
+    pub run: fn(&[&str]),
+}
+
+/// Line editor and dispatcher over a table of commands.
+#[derive(Debug)]
+pub struct Shell {
+    /// The commands besides `help`
+    commands: &'static [Command],
+    /// The line typed so far, printable ASCII only
+    line: String,
+}
+
+impl Shell {
+    /// Creates a shell running `commands`.
+    pub const fn new(commands: &'static [Command]) -> Shell {
+        Shell {
+            commands,
+            line: String::new(),
+        }
+    }
+
+    /// Tells how to get help and shows the first prompt.
+    pub fn start(&self) {
+        println!("Type `help` for a list of commands.");
+        print!("{}", PROMPT);
+    }
+
+    /// Handles a typed character.
+    ///
+    /// Printable ASCII is echoed and appended to the line, up to
+    /// [`MAX_LINE`] characters; backspace and delete take back the last
+    /// one. A newline runs the line and shows a new prompt. Anything else
+    /// is ignored.
+    pub fn key(&mut self, character: char) {
+        match character {
+            '\n' | '\r' => {
+                println!();
+                let line = core::mem::take(&mut self.line);
+                self.execute(&line);
+                print!("{}", PROMPT);
+            }
+            '\u{8}' | '\u{7f}' if !self.line.is_empty() => {
+                self.line.pop();
+                print!("\u{8} \u{8}");
+            }
+            ' '..='~' if self.line.len() < MAX_LINE => {
+                self.line.push(character);
+                print!("{}", character);
+            }
+            _ => {}
+        }
+    }
+
+    /// Runs the command named by the first word of `line`, with the other
+    /// words as its arguments. Blank lines do nothing.
+    pub fn execute(&self, line: &str) {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&name, args)) = words.split_first() else {
+            return;
+        };
+        if name == "help" {
+            self.help();
+            return;
+        }
+        match self.commands.iter().find(|command| command.name == name) {
+            Some(command) => (command.run)(args),
+            None => println!("{}: command not found; type `help` for a list", name),
+        }
+    }
+
+    /// Lists the commands.
+    fn help(&self) {
+        println!("  {:<8} List the commands", "help");
+        for command in self.commands {
+            println!("  {:<8} {}", command.name, command.help);
+        }
+    }
+}
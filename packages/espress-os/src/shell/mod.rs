@@ -0,0 +1,33 @@
+//! # Kernel Shell
+//!
+//! A command line on the console. [`run`] reads the PS/2 keyboard, echoes
+//! what is typed after a prompt, and on Enter runs the command the line
+//! names (see [`commands`]). Output goes to every console backend, so the
+//! shell shows on the screen and the serial port alike.
+//!
+//! The line editing and dispatch in [`interpreter`] only use the console;
+//! the WebAssembly demo runs the same code in the browser.
+
+pub mod commands;
+pub mod interpreter;
+
+pub use interpreter::{Command, Shell};
+
+use pc_keyboard::DecodedKey;
+
+use crate::task::keyboard;
+
+/// Runs the shell on keyboard input; never finishes.
+///
+/// Takes over the keyboard's [scancode stream](keyboard::ScancodeStream),
+/// so it may only run once.
+pub async fn run() {
+    let mut shell = Shell::new(commands::COMMANDS);
+    shell.start();
+    keyboard::for_each_key(|key| {
+        if let DecodedKey::Unicode(character) = key {
+            shell.key(character);
+        }
+    })
+    .await;
+}
@@ -0,0 +1,447 @@
+//! # SMBIOS
+//!
+//! Reads the firmware's SMBIOS (DMI) tables, which describe the machine:
+//! who made it and what it is called, the BIOS version, and the memory
+//! modules installed. Behavior that differs between real machines is much
+//! easier to chase with this at hand, so [`init`] logs a summary at boot
+//! and [`print_report`] lists everything, as the shell's `smbios` command
+//! does.
+//!
+//! UEFI firmware gives the address of the entry point, which the UEFI
+//! loader passes on (see [`boot::smbios`](crate::boot::smbios)). Otherwise
+//! the entry point is searched for in the BIOS area at `0xf0000`, on
+//! 16-byte boundaries. Both the 32-bit entry point of SMBIOS 2.1 (`_SM_`)
+//! and the 64-bit one of SMBIOS 3.0 (`_SM3_`) are understood; the latter
+//! is preferred.
+//!
+//! The table is a sequence of structures, each a formatted area followed
+//! by its strings, which the formatted area refers to by one-based index.
+//! Only the structures listed in [`Smbios`] are decoded.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use spin::Once;
+use x86_64::PhysAddr;
+
+use crate::{boot, mm, println};
+
+/// Start and end of the BIOS area searched for the entry point.
+const BIOS_AREA: (u64, u64) = (0xf_0000, 0x10_0000);
+
+/// Anchor of the SMBIOS 2.1 entry point.
+const ANCHOR_32: &[u8; 4] = b"_SM_";
+/// Anchor of the SMBIOS 3.0 entry point.
+const ANCHOR_64: &[u8; 5] = b"_SM3_";
+/// Anchor of the intermediate entry point inside the 2.1 one.
+const ANCHOR_DMI: &[u8; 5] = b"_DMI_";
+
+/// Size of the SMBIOS 2.1 entry point.
+const ENTRY_32_SIZE: usize = 31;
+/// Size of the SMBIOS 3.0 entry point.
+const ENTRY_64_SIZE: usize = 24;
+
+/// Largest table read, against corrupt entry points.
+const MAX_TABLE_SIZE: usize = 1024 * 1024;
+
+/// Structure type: BIOS information.
+const TYPE_BIOS: u8 = 0;
+/// Structure type: system information.
+const TYPE_SYSTEM: u8 = 1;
+/// Structure type: baseboard information.
+const TYPE_BASEBOARD: u8 = 2;
+/// Structure type: memory device.
+const TYPE_MEMORY_DEVICE: u8 = 17;
+/// Structure type: end of table.
+const TYPE_END: u8 = 127;
+
+/// Size of a structure header: type, length, and handle.
+const HEADER_SIZE: usize = 4;
+
+/// Memory device size: unknown.
+const SIZE_UNKNOWN: u16 = 0xffff;
+/// Memory device size: given by the extended size field.
+const SIZE_EXTENDED: u16 = 0x7fff;
+/// Memory device size bit: the size is in KiB rather than MiB.
+const SIZE_IN_KIB: u16 = 1 << 15;
+
+/// The decoded tables.
+static SMBIOS: Once<Smbios> = Once::new();
+
+/// Errors that can occur while reading the SMBIOS tables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmbiosError {
+    /// No valid entry point was found
+    NoEntryPoint,
+    /// The structure table lies outside memory
+    BadTable,
+}
+
+/// BIOS information (type 0).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Bios {
+    /// Vendor of the firmware
+    pub vendor: String,
+    /// Firmware version
+    pub version: String,
+    /// Release date, usually `mm/dd/yyyy`
+    pub release_date: String,
+}
+
+/// System information (type 1).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct System {
+    /// Maker of the machine
+    pub manufacturer: String,
+    /// Product name
+    pub product: String,
+    /// Product version
+    pub version: String,
+    /// Serial number
+    pub serial: String,
+}
+
+/// Baseboard information (type 2).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Baseboard {
+    /// Maker of the board
+    pub manufacturer: String,
+    /// Product name of the board
+    pub product: String,
+}
+
+/// A memory device (type 17), i.e. a memory slot.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryDevice {
+    /// Label of the slot, e.g. `DIMM 0`
+    pub locator: String,
+    /// Label of the bank the slot is in
+    pub bank: String,
+    /// Size of the installed module in KiB; zero if the slot is empty,
+    /// `None` if unknown
+    pub size_kib: Option<u64>,
+    /// Memory type, e.g. `DDR4`
+    pub kind: &'static str,
+    /// Speed in MT/s, zero if unknown
+    pub speed: u16,
+    /// Maker of the module
+    pub manufacturer: String,
+    /// Part number of the module
+    pub part_number: String,
+}
+
+/// What the SMBIOS tables say about the machine.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Smbios {
+    /// Major and minor SMBIOS version
+    pub version: (u8, u8),
+    /// The firmware
+    pub bios: Bios,
+    /// The machine
+    pub system: System,
+    /// The mainboard
+    pub baseboard: Baseboard,
+    /// The memory slots, in table order
+    pub memory: Vec<MemoryDevice>,
+}
+
+/// Finds and decodes the SMBIOS tables and logs a summary.
+///
+/// Must be called after memory management is initialized.
+///
+/// # Errors
+///
+/// Returns an [`SmbiosError`] if the firmware provides no usable tables.
+pub fn init() -> Result<(), SmbiosError> {
+    let (version, table) = find_table()?;
+    let smbios = SMBIOS.call_once(|| parse(version, table));
+    println!(
+        "smbios: {}.{}, {} {}, BIOS {} {} ({})",
+        smbios.version.0,
+        smbios.version.1,
+        smbios.system.manufacturer,
+        smbios.system.product,
+        smbios.bios.vendor,
+        smbios.bios.version,
+        smbios.bios.release_date
+    );
+    let installed: Vec<_> = smbios
+        .memory
+        .iter()
+        .filter(|device| device.size_kib != Some(0))
+        .collect();
+    let total: u64 = installed.iter().filter_map(|device| device.size_kib).sum();
+    println!(
+        "smbios: {} of {} memory slots used, {} MiB",
+        installed.len(),
+        smbios.memory.len(),
+        total / 1024
+    );
+    Ok(())
+}
+
+/// Returns the decoded tables, or `None` before [`init`] or without
+/// SMBIOS.
+pub fn info() -> Option<&'static Smbios> {
+    SMBIOS.get()
+}
+
+/// Prints everything decoded from the tables, like `dmidecode`; run by
+/// the shell's `smbios` command.
+pub fn print_report() {
+    let Some(smbios) = info() else {
+        println!("smbios: no tables");
+        return;
+    };
+    println!("SMBIOS {}.{}", smbios.version.0, smbios.version.1);
+    println!("BIOS");
+    println!("  vendor:        {}", smbios.bios.vendor);
+    println!("  version:       {}", smbios.bios.version);
+    println!("  release date:  {}", smbios.bios.release_date);
+    println!("System");
+    println!("  manufacturer:  {}", smbios.system.manufacturer);
+    println!("  product:       {}", smbios.system.product);
+    println!("  version:       {}", smbios.system.version);
+    println!("  serial:        {}", smbios.system.serial);
+    println!("Baseboard");
+    println!("  manufacturer:  {}", smbios.baseboard.manufacturer);
+    println!("  product:       {}", smbios.baseboard.product);
+    for device in &smbios.memory {
+        println!("Memory device {} ({})", device.locator, device.bank);
+        match device.size_kib {
+            Some(0) => {
+                println!("  empty");
+                continue;
+            }
+            Some(size) if size % 1024 == 0 => println!("  size:          {} MiB", size / 1024),
+            Some(size) => println!("  size:          {} KiB", size),
+            None => println!("  size:          unknown"),
+        }
+        println!("  type:          {}", device.kind);
+        if device.speed != 0 {
+            println!("  speed:         {} MT/s", device.speed);
+        }
+        println!("  manufacturer:  {}", device.manufacturer);
+        println!("  part number:   {}", device.part_number);
+    }
+}
+
+/// Returns the SMBIOS version and the structure table.
+fn find_table() -> Result<((u8, u8), &'static [u8]), SmbiosError> {
+    let entry = match boot::smbios() {
+        Some(addr) => phys_bytes(addr.as_u64(), ENTRY_32_SIZE).and_then(valid_entry),
+        None => (BIOS_AREA.0..BIOS_AREA.1)
+            .step_by(16)
+            .filter_map(|addr| phys_bytes(addr, ENTRY_32_SIZE))
+            .filter_map(valid_entry)
+            .max_by_key(|entry| entry.starts_with(ANCHOR_64)),
+    }
+    .ok_or(SmbiosError::NoEntryPoint)?;
+
+    let (version, addr, len) = if entry.starts_with(ANCHOR_64) {
+        let len = read_u32(entry, 12) as usize;
+        ((entry[7], entry[8]), read_u64(entry, 16), len)
+    } else {
+        let len = usize::from(read_u16(entry, 22));
+        ((entry[6], entry[7]), u64::from(read_u32(entry, 24)), len)
+    };
+    let table = phys_bytes(addr, len.min(MAX_TABLE_SIZE)).ok_or(SmbiosError::BadTable)?;
+    Ok((version, table))
+}
+
+/// Returns the entry point in `candidate`, trimmed to its size, if the
+/// anchor and checksums are right.
+fn valid_entry(candidate: &'static [u8]) -> Option<&'static [u8]> {
+    if candidate.starts_with(ANCHOR_64) {
+        let len = usize::from(candidate[6]).max(ENTRY_64_SIZE);
+        let entry = candidate.get(..len)?;
+        return checksum_ok(entry).then_some(entry);
+    }
+    if candidate.starts_with(ANCHOR_32) {
+        let len = usize::from(candidate[5]).max(ENTRY_32_SIZE);
+        let entry = candidate.get(..len)?;
+        let intermediate = &entry[16..ENTRY_32_SIZE];
+        let valid =
+            checksum_ok(entry) && intermediate.starts_with(ANCHOR_DMI) && checksum_ok(intermediate);
+        return valid.then_some(entry);
+    }
+    None
+}
+
+/// Decodes the structures of `table`.
+fn parse(version: (u8, u8), table: &[u8]) -> Smbios {
+    let mut smbios = Smbios {
+        version,
+        ..Smbios::default()
+    };
+    for structure in structures(table) {
+        match structure.kind {
+            TYPE_BIOS => {
+                smbios.bios = Bios {
+                    vendor: structure.string_at(4),
+                    version: structure.string_at(5),
+                    release_date: structure.string_at(8),
+                }
+            }
+            TYPE_SYSTEM => {
+                smbios.system = System {
+                    manufacturer: structure.string_at(4),
+                    product: structure.string_at(5),
+                    version: structure.string_at(6),
+                    serial: structure.string_at(7),
+                }
+            }
+            TYPE_BASEBOARD => {
+                smbios.baseboard = Baseboard {
+                    manufacturer: structure.string_at(4),
+                    product: structure.string_at(5),
+                }
+            }
+            TYPE_MEMORY_DEVICE => smbios.memory.push(memory_device(&structure)),
+            _ => {}
+        }
+    }
+    smbios
+}
+
+/// Decodes a memory device structure.
+fn memory_device(structure: &Structure) -> MemoryDevice {
+    let size_kib = match structure.u16_at(0x0c) {
+        None | Some(SIZE_UNKNOWN) => None,
+        Some(SIZE_EXTENDED) => structure
+            .u32_at(0x1c)
+            .map(|mib| u64::from(mib & 0x7fff_ffff) * 1024),
+        Some(size) if size & SIZE_IN_KIB != 0 => Some(u64::from(size & !SIZE_IN_KIB)),
+        Some(size) => Some(u64::from(size) * 1024),
+    };
+    MemoryDevice {
+        locator: structure.string_at(0x10),
+        bank: structure.string_at(0x11),
+        size_kib,
+        kind: memory_type_name(structure.byte_at(0x12).unwrap_or(0)),
+        speed: structure.u16_at(0x15).unwrap_or(0),
+        manufacturer: structure.string_at(0x17),
+        part_number: structure.string_at(0x1a),
+    }
+}
+
+/// Returns the name of a memory device type.
+fn memory_type_name(kind: u8) -> &'static str {
+    match kind {
+        0x03 => "DRAM",
+        0x07 => "RAM",
+        0x0f => "SDRAM",
+        0x12 => "DDR",
+        0x13 => "DDR2",
+        0x18 => "DDR3",
+        0x1a => "DDR4",
+        0x1b => "LPDDR",
+        0x1c => "LPDDR2",
+        0x1d => "LPDDR3",
+        0x1e => "LPDDR4",
+        0x22 => "DDR5",
+        0x23 => "LPDDR5",
+        _ => "other",
+    }
+}
+
+/// One structure of the table.
+struct Structure<'a> {
+    /// Structure type
+    kind: u8,
+    /// The formatted area, header included
+    formatted: &'a [u8],
+    /// The strings, each NUL-terminated
+    strings: &'a [u8],
+}
+
+impl Structure<'_> {
+    /// Reads the byte at `offset` of the formatted area.
+    fn byte_at(&self, offset: usize) -> Option<u8> {
+        self.formatted.get(offset).copied()
+    }
+
+    /// Reads a `u16` at `offset` of the formatted area.
+    fn u16_at(&self, offset: usize) -> Option<u16> {
+        self.formatted
+            .get(offset..offset + 2)
+            .map(|_| read_u16(self.formatted, offset))
+    }
+
+    /// Reads a `u32` at `offset` of the formatted area.
+    fn u32_at(&self, offset: usize) -> Option<u32> {
+        self.formatted
+            .get(offset..offset + 4)
+            .map(|_| read_u32(self.formatted, offset))
+    }
+
+    /// Returns the string whose index is at `offset` of the formatted area,
+    /// or an empty string if there is none.
+    fn string_at(&self, offset: usize) -> String {
+        let index = usize::from(self.byte_at(offset).unwrap_or(0));
+        if index == 0 {
+            return String::new();
+        }
+        self.strings
+            .split(|&byte| byte == 0)
+            .nth(index - 1)
+            .map(|bytes| String::from_utf8_lossy(bytes).trim().into())
+            .unwrap_or_default()
+    }
+}
+
+/// Iterates over the structures of `table` up to the end-of-table
+/// structure or a malformed one.
+fn structures(table: &[u8]) -> impl Iterator<Item = Structure<'_>> {
+    let mut rest = table;
+    core::iter::from_fn(move || {
+        let header = rest.get(..HEADER_SIZE)?;
+        let len = usize::from(header[1]);
+        if header[0] == TYPE_END || len < HEADER_SIZE {
+            return None;
+        }
+        let formatted = rest.get(..len)?;
+        let strings_end = rest[len..].windows(2).position(|pair| pair == [0, 0])?;
+        let structure = Structure {
+            kind: header[0],
+            formatted,
+            strings: &rest[len..len + strings_end],
+        };
+        rest = &rest[len + strings_end + 2..];
+        Some(structure)
+    })
+}
+
+/// Returns `len` bytes of physical memory at `addr`, if they lie inside the
+/// linear mapping.
+fn phys_bytes(addr: u64, len: usize) -> Option<&'static [u8]> {
+    let end = addr.checked_add(len as u64)?;
+    if end > mm::phys_memory_end() {
+        return None;
+    }
+    let virt = mm::phys_to_virt(PhysAddr::new(addr));
+    Some(unsafe { core::slice::from_raw_parts(virt.as_ptr(), len) })
+}
+
+/// Returns `true` if the bytes sum to zero.
+fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
+
+/// Reads a little-endian `u16` at `offset`.
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+/// Reads a little-endian `u32` at `offset`.
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut value = [0; 4];
+    value.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(value)
+}
+
+/// Reads a little-endian `u64` at `offset`.
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut value = [0; 8];
+    value.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(value)
+}
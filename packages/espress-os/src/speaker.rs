@@ -0,0 +1,100 @@
+//! # PC Speaker
+//!
+//! Beeps through the speaker wired to channel 2 of the PIT, which produces
+//! a square wave of the requested frequency while port `0x61` connects it.
+//! Useful to signal boot or a panic on a machine without a visible console.
+//!
+//! [`beep`] and [`play`] sleep while the tone sounds and need a scheduler
+//! thread; [`beep_spin`] busy-waits on the TSC instead and works anywhere,
+//! even with interrupts disabled. Only one tone sounds at a time: starting
+//! one replaces whatever is playing.
+
+use core::time::Duration;
+
+use crate::arch::{pit, tsc};
+use crate::timer;
+
+/// A tone of a tune.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Note {
+    /// Frequency in Hz, zero for a rest
+    pub freq_hz: u32,
+    /// How long the note lasts
+    pub duration: Duration,
+}
+
+impl Note {
+    /// Creates a note of `freq_hz` lasting `ms` milliseconds.
+    pub const fn new(freq_hz: u32, ms: u64) -> Self {
+        Note {
+            freq_hz,
+            duration: Duration::from_millis(ms),
+        }
+    }
+
+    /// Creates a rest lasting `ms` milliseconds.
+    pub const fn rest(ms: u64) -> Self {
+        Self::new(0, ms)
+    }
+}
+
+/// A rising C major arpeggio for signaling a completed boot.
+pub const BOOT_TUNE: &[Note] = &[
+    Note::new(523, 90),
+    Note::new(659, 90),
+    Note::new(784, 90),
+    Note::new(1047, 180),
+];
+
+/// Starts a tone of `freq_hz` that sounds until [`silence`].
+///
+/// # Returns
+///
+/// The frequency actually produced, which the PIT can only approximate.
+pub fn tone(freq_hz: u32) -> u32 {
+    pit::start_tone(freq_hz)
+}
+
+/// Stops the tone.
+pub fn silence() {
+    pit::stop_tone();
+}
+
+/// Sounds `freq_hz` for `duration`, sleeping meanwhile.
+///
+/// # Panics
+///
+/// Panics if called from outside a scheduler thread.
+pub fn beep(freq_hz: u32, duration: Duration) {
+    tone(freq_hz);
+    timer::sleep(duration);
+    silence();
+}
+
+/// Sounds `freq_hz` for `duration`, busy-waiting meanwhile.
+///
+/// Before the TSC is calibrated the duration cannot be measured, and the
+/// tone is cut short at once.
+pub fn beep_spin(freq_hz: u32, duration: Duration) {
+    let cycles = (duration.as_nanos() * u128::from(tsc::frequency()) / 1_000_000_000) as u64;
+    tone(freq_hz);
+    let start = tsc::read();
+    while tsc::read().wrapping_sub(start) < cycles {
+        core::hint::spin_loop();
+    }
+    silence();
+}
+
+/// Plays `tune` note by note, sleeping meanwhile.
+///
+/// # Panics
+///
+/// Panics if called from outside a scheduler thread.
+pub fn play(tune: &[Note]) {
+    for note in tune {
+        match note.freq_hz {
+            0 => timer::sleep(note.duration),
+            freq_hz => beep(freq_hz, note.duration),
+        }
+    }
+}
@@ -0,0 +1,194 @@
+//! # File System Calls
+//!
+//! The system calls on files, directories, and devices of the
+//! [VFS](crate::fs), and the parts of `read` and `write` that reach files.
+//! Left out of kernels built without the `fs` feature.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use x86_64::VirtAddr;
+
+use super::handlers::{read_c_string, CHUNK};
+use super::{Errno, SyscallFrame, SyscallResult};
+use crate::fs::file::{self, OpenFile, Whence};
+use crate::fs::{self, FsError, NodeKind};
+use crate::mm::AddressSpace;
+use crate::process;
+use crate::process::handle::Handle;
+
+/// Most bytes of directory records returned by one `readdir`, enough for
+/// the record of the longest name.
+const READDIR_MAX: usize = 4096;
+
+/// Copies `len` bytes of user memory at `buf` into a file.
+///
+/// # Errors
+///
+/// The error of the file if nothing was written.
+pub(super) fn write_file(
+    space: &AddressSpace,
+    file: &OpenFile,
+    buf: VirtAddr,
+    len: u64,
+) -> SyscallResult {
+    if !file.is_writable() {
+        return Err(Errno::EBADF);
+    }
+    let mut chunk = [0u8; CHUNK];
+    let mut written = 0;
+    while written < len {
+        let count = (len - written).min(CHUNK as u64) as usize;
+        space
+            .read(buf + written, &mut chunk[..count])
+            .map_err(|_| Errno::EFAULT)?;
+        match file.write(&chunk[..count]) {
+            Ok(n) if n < count => return Ok(written + n as u64),
+            Ok(_) => written += count as u64,
+            Err(_) if written > 0 => return Ok(written),
+            Err(err) => return Err(fs_errno(err)),
+        }
+    }
+    Ok(len)
+}
+
+/// Reads up to `len` bytes from a file into user memory at `buf`.
+pub(super) fn read_file(
+    space: &AddressSpace,
+    file: &OpenFile,
+    buf: VirtAddr,
+    len: u64,
+) -> SyscallResult {
+    if !file.is_readable() {
+        return Err(Errno::EBADF);
+    }
+    let mut chunk = [0u8; CHUNK];
+    let mut read = 0;
+    while read < len {
+        let count = (len - read).min(CHUNK as u64) as usize;
+        let count_read = match file.read(&mut chunk[..count]) {
+            Ok(n) => n,
+            Err(_) if read > 0 => break,
+            Err(err) => return Err(fs_errno(err)),
+        };
+        space
+            .write(buf + read, &chunk[..count_read])
+            .map_err(|_| Errno::EFAULT)?;
+        read += count_read as u64;
+        if count_read < count {
+            break;
+        }
+    }
+    Ok(read)
+}
+
+/// `open(path, flags)`
+pub(super) fn open(frame: &mut SyscallFrame) -> SyscallResult {
+    let [path, flags, ..] = frame.args;
+    let process = process::current().ok_or(Errno::EINVAL)?;
+    let path =
+        read_c_string(&process.address_space(), path, fs::PATH_MAX).map_err(|err| match err {
+            Errno::E2BIG => Errno::ENAMETOOLONG,
+            err => err,
+        })?;
+    let flags = u32::try_from(flags).map_err(|_| Errno::EINVAL)?;
+    let file = file::open(&path, flags).map_err(fs_errno)?;
+    let fd = process.with_handles(|handles| handles.insert(Handle::File(file)));
+    Ok(fd as u64)
+}
+
+/// `seek(fd, offset, whence)`
+///
+/// Pipes and the console cannot seek.
+pub(super) fn seek(frame: &mut SyscallFrame) -> SyscallResult {
+    let [fd, offset, whence, ..] = frame.args;
+    let whence = match whence {
+        0 => Whence::Set,
+        1 => Whence::Current,
+        2 => Whence::End,
+        _ => return Err(Errno::EINVAL),
+    };
+    let file = file_handle(fd)?;
+    file.seek(offset as i64, whence).map_err(fs_errno)
+}
+
+/// `readdir(fd, buf, len)`
+///
+/// # Errors
+///
+/// [`Errno::EINVAL`] if the next record does not fit into an empty `buf`.
+pub(super) fn readdir(frame: &mut SyscallFrame) -> SyscallResult {
+    let [fd, buf, len, ..] = frame.args;
+    let buf = VirtAddr::try_new(buf).map_err(|_| Errno::EFAULT)?;
+    let file = file_handle(fd)?;
+    let space = process::current().ok_or(Errno::EINVAL)?.address_space();
+
+    let mut records = Vec::new();
+    let limit = len.min(READDIR_MAX as u64) as usize;
+    let mut too_small = false;
+    file.read_dir(|entry| {
+        let start = records.len();
+        let size = (3 + entry.name.len() + 1).next_multiple_of(8);
+        if start + size > limit {
+            too_small = start == 0;
+            return false;
+        }
+        let kind: u8 = match entry.kind {
+            NodeKind::File => 1,
+            NodeKind::Directory => 2,
+            NodeKind::Symlink => 3,
+            NodeKind::CharDevice => 4,
+            NodeKind::BlockDevice => 5,
+        };
+        records.extend_from_slice(&(size as u16).to_le_bytes());
+        records.push(kind);
+        records.extend_from_slice(entry.name.as_bytes());
+        records.resize(start + size, 0);
+        true
+    })
+    .map_err(fs_errno)?;
+    if too_small {
+        return Err(Errno::EINVAL);
+    }
+    space.write(buf, &records).map_err(|_| Errno::EFAULT)?;
+    Ok(records.len() as u64)
+}
+
+/// `sync()`
+pub(super) fn sync(_frame: &mut SyscallFrame) -> SyscallResult {
+    fs::sync().map_err(fs_errno)?;
+    Ok(0)
+}
+
+/// Returns the open file behind handle `fd` of the calling process.
+///
+/// # Errors
+///
+/// [`Errno::EBADF`] if `fd` is not open and [`Errno::ESPIPE`] if it is
+/// not a file.
+fn file_handle(fd: u64) -> Result<Arc<OpenFile>, Errno> {
+    let process = process::current().ok_or(Errno::EINVAL)?;
+    match process.with_handles(|handles| handles.get(fd as usize).cloned()) {
+        Some(Handle::File(file)) => Ok(file),
+        Some(_) => Err(Errno::ESPIPE),
+        None => Err(Errno::EBADF),
+    }
+}
+
+/// Maps a filesystem error to its error number.
+fn fs_errno(err: FsError) -> Errno {
+    match err {
+        FsError::NotFound => Errno::ENOENT,
+        FsError::NotDirectory => Errno::ENOTDIR,
+        FsError::IsDirectory => Errno::EISDIR,
+        FsError::AlreadyExists => Errno::EEXIST,
+        FsError::NotEmpty => Errno::ENOTEMPTY,
+        FsError::InvalidPath => Errno::ENOENT,
+        FsError::InvalidArgument | FsError::NotSupported => Errno::EINVAL,
+        FsError::BadAccess => Errno::EBADF,
+        FsError::ReadOnly => Errno::EROFS,
+        FsError::Busy => Errno::EBUSY,
+        FsError::CrossDevice => Errno::EXDEV,
+        FsError::NoSpace => Errno::ENOSPC,
+        FsError::Corrupted | FsError::Io(_) => Errno::EIO,
+    }
+}
@@ -0,0 +1,421 @@
+//! # System Call Handlers
+//!
+//! One function per entry of the dispatch table, except for the file and
+//! socket calls, which live in `files` and `sockets` so they can be left
+//! out with their subsystems. Arguments arrive as raw register values;
+//! pointers are only dereferenced through the calling process's
+//! [`AddressSpace`], which validates them.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::time::Duration;
+use x86_64::VirtAddr;
+
+#[cfg(feature = "fs")]
+use super::files;
+#[cfg(feature = "net")]
+use super::sockets;
+use super::{Errno, SyscallFrame, SyscallResult};
+use crate::arch::tls;
+use crate::mm::{AddressSpace, USER_SPACE_END};
+use crate::process::futex::{self, FutexError};
+use crate::process::handle::Handle;
+use crate::process::pipe::{self, PipeError, PipeReader, PipeWriter};
+use crate::process::signal::{self, Action, Signal, SignalError};
+use crate::process::{Pid, ProcessError, ARG_MAX, MAX_ARGS};
+use crate::scheduler::WaitQueue;
+use crate::usermode::USER_RFLAGS;
+use crate::{print, process};
+
+/// Bytes copied between user memory and the kernel per step of `read`,
+/// `write`.
+pub(super) const CHUNK: usize = 256;
+
+/// Longest program path accepted by `exec`, including the terminator.
+const PATH_MAX: usize = 256;
+
+/// Stands in for the system calls of a subsystem left out of the build.
+#[cfg(not(all(feature = "fs", feature = "net")))]
+pub(super) fn unsupported(_frame: &mut SyscallFrame) -> SyscallResult {
+    Err(Errno::ENOSYS)
+}
+
+/// `exit(code)`
+pub(super) fn exit(frame: &mut SyscallFrame) -> SyscallResult {
+    let args = frame.args;
+    process::exit_current(args[0] as i32);
+}
+
+/// `write(fd, buf, len)`
+///
+/// Writes to the object behind handle `fd`. Console output with invalid
+/// UTF-8 is printed with replacement characters; writes to a pipe block
+/// until all of `buf` is buffered; writing to a pipe without readers
+/// raises [`Signal::BrokenPipe`]. Files are written at their position, and
+/// a short count means the file could take no more. Sockets send like
+/// `send` to their peer.
+pub(super) fn write(frame: &mut SyscallFrame) -> SyscallResult {
+    let args = frame.args;
+    let [fd, buf, len, ..] = args;
+    let process = process::current().ok_or(Errno::EINVAL)?;
+    let handle = process
+        .with_handles(|handles| handles.get(fd as usize).cloned())
+        .ok_or(Errno::EBADF)?;
+    let buf = VirtAddr::try_new(buf).map_err(|_| Errno::EFAULT)?;
+
+    let space = process.address_space();
+    drop(process);
+
+    let result = match handle {
+        Handle::Console => write_console(&space, buf, len),
+        Handle::PipeWriter(writer) => write_pipe(&space, &writer, buf, len),
+        #[cfg(feature = "fs")]
+        Handle::File(file) => files::write_file(&space, &file, buf, len),
+        #[cfg(feature = "net")]
+        Handle::Socket(socket) => sockets::write_socket(&space, &socket, buf, len, None),
+        Handle::PipeReader(_) => Err(Errno::EBADF),
+    };
+    if result == Err(Errno::EPIPE) {
+        if let Some(process) = process::current() {
+            process.signals().raise(Signal::BrokenPipe);
+        }
+    }
+    result
+}
+
+/// Prints `len` bytes of user memory at `buf` to the console.
+fn write_console(space: &AddressSpace, buf: VirtAddr, len: u64) -> SyscallResult {
+    let mut chunk = [0u8; CHUNK];
+    // Bytes of a character split across two chunks, kept at the front.
+    let mut carried = 0;
+    let mut written = 0;
+    while written < len {
+        let count = (len - written).min((CHUNK - carried) as u64) as usize;
+        let filled = carried + count;
+        space
+            .read(buf + written, &mut chunk[carried..filled])
+            .map_err(|_| Errno::EFAULT)?;
+        written += count as u64;
+
+        carried = 0;
+        let mut pieces = chunk[..filled].utf8_chunks().peekable();
+        while let Some(piece) = pieces.next() {
+            print!("{}", piece.valid());
+            let invalid = piece.invalid();
+            if invalid.is_empty() {
+                continue;
+            }
+            // Possibly a truncated character; decode it again with the
+            // bytes that follow.
+            if pieces.peek().is_none() && written < len {
+                carried = invalid.len();
+            } else {
+                print!("{}", char::REPLACEMENT_CHARACTER);
+            }
+        }
+        chunk.copy_within(filled - carried..filled, 0);
+    }
+    Ok(len)
+}
+
+/// Copies `len` bytes of user memory at `buf` into a pipe.
+///
+/// # Errors
+///
+/// [`Errno::EPIPE`] if the pipe has no reader and nothing was written, and
+/// [`Errno::EINTR`] if a signal arrived before anything was written.
+fn write_pipe(space: &AddressSpace, writer: &PipeWriter, buf: VirtAddr, len: u64) -> SyscallResult {
+    let mut chunk = [0u8; CHUNK];
+    let mut written = 0;
+    while written < len {
+        let count = (len - written).min(CHUNK as u64) as usize;
+        space
+            .read(buf + written, &mut chunk[..count])
+            .map_err(|_| Errno::EFAULT)?;
+        let mut sent = 0;
+        while sent < count {
+            match writer.write(&chunk[sent..count]) {
+                Ok(n) => sent += n,
+                Err(_) if written + sent as u64 > 0 => return Ok(written + sent as u64),
+                Err(PipeError::BrokenPipe) => return Err(Errno::EPIPE),
+                Err(PipeError::Interrupted) => return Err(Errno::EINTR),
+            }
+        }
+        written += count as u64;
+    }
+    Ok(len)
+}
+
+/// `read(fd, buf, len)`
+///
+/// Reads from the object behind handle `fd`. A pipe blocks until data is
+/// available and may return fewer bytes than requested; the console has no
+/// input and is always at end of file. Files are read from their position
+/// and return fewer bytes only at the end of the file or when a device has
+/// no more ready. Sockets receive like `recv`.
+pub(super) fn read(frame: &mut SyscallFrame) -> SyscallResult {
+    let [fd, buf, len, ..] = frame.args;
+    let process = process::current().ok_or(Errno::EINVAL)?;
+    let handle = process
+        .with_handles(|handles| handles.get(fd as usize).cloned())
+        .ok_or(Errno::EBADF)?;
+    let buf = VirtAddr::try_new(buf).map_err(|_| Errno::EFAULT)?;
+    let space = process.address_space();
+    drop(process);
+
+    match handle {
+        Handle::Console => Ok(0),
+        Handle::PipeReader(reader) => read_pipe(&space, &reader, buf, len),
+        #[cfg(feature = "fs")]
+        Handle::File(file) => files::read_file(&space, &file, buf, len),
+        #[cfg(feature = "net")]
+        Handle::Socket(socket) => {
+            sockets::read_socket(&space, &socket, buf, len).map(|(len, _)| len)
+        }
+        Handle::PipeWriter(_) => Err(Errno::EBADF),
+    }
+}
+
+/// Reads up to `len` bytes from a pipe into user memory at `buf`.
+///
+/// # Errors
+///
+/// [`Errno::EINTR`] if a signal arrived while the pipe was empty.
+fn read_pipe(space: &AddressSpace, reader: &PipeReader, buf: VirtAddr, len: u64) -> SyscallResult {
+    let mut chunk = [0u8; CHUNK];
+    let count = len.min(CHUNK as u64) as usize;
+    let count = reader.read(&mut chunk[..count]).map_err(|_| Errno::EINTR)?;
+    space
+        .write(buf, &chunk[..count])
+        .map_err(|_| Errno::EFAULT)?;
+    Ok(count as u64)
+}
+
+/// `pipe(fds)`
+pub(super) fn pipe(frame: &mut SyscallFrame) -> SyscallResult {
+    let fds = VirtAddr::try_new(frame.args[0]).map_err(|_| Errno::EFAULT)?;
+    let process = process::current().ok_or(Errno::EINVAL)?;
+    let (reader, writer) = pipe::pipe();
+    let (read_fd, write_fd) = process.with_handles(|handles| {
+        (
+            handles.insert(Handle::PipeReader(reader)),
+            handles.insert(Handle::PipeWriter(writer)),
+        )
+    });
+
+    let mut out = [0u8; 8];
+    out[..4].copy_from_slice(&(read_fd as u32).to_le_bytes());
+    out[4..].copy_from_slice(&(write_fd as u32).to_le_bytes());
+    if process.address_space().write(fds, &out).is_err() {
+        process.with_handles(|handles| {
+            handles.remove(read_fd);
+            handles.remove(write_fd);
+        });
+        return Err(Errno::EFAULT);
+    }
+    Ok(0)
+}
+
+/// `close(fd)`
+pub(super) fn close(frame: &mut SyscallFrame) -> SyscallResult {
+    let process = process::current().ok_or(Errno::EINVAL)?;
+    let handle = process
+        .with_handles(|handles| handles.remove(frame.args[0] as usize))
+        .ok_or(Errno::EBADF)?;
+    drop(process);
+    drop(handle);
+    Ok(0)
+}
+
+/// `kill(pid, signal)`
+pub(super) fn kill(frame: &mut SyscallFrame) -> SyscallResult {
+    let [pid, number, ..] = frame.args;
+    let target = process::get(Pid::from_u64(pid)).ok_or(Errno::ESRCH)?;
+    if number == 0 {
+        return Ok(0);
+    }
+    let signal = Signal::from_number(number).ok_or(Errno::EINVAL)?;
+    target.send_signal(signal);
+    Ok(0)
+}
+
+/// `signal(signal, handler, restorer)`
+pub(super) fn signal(frame: &mut SyscallFrame) -> SyscallResult {
+    let [number, handler, restorer, ..] = frame.args;
+    let signal = Signal::from_number(number).ok_or(Errno::EINVAL)?;
+    let action = match handler {
+        0 => Action::Default,
+        1 => Action::Ignore,
+        _ => Action::Handler {
+            handler: VirtAddr::try_new(handler).map_err(|_| Errno::EFAULT)?,
+            restorer: VirtAddr::try_new(restorer).map_err(|_| Errno::EFAULT)?,
+        },
+    };
+    let process = process::current().ok_or(Errno::EINVAL)?;
+    let previous = process
+        .signals()
+        .set_action(signal, action)
+        .map_err(|err| match err {
+            SignalError::Uncatchable => Errno::EINVAL,
+            SignalError::BadAddress => Errno::EFAULT,
+        })?;
+    Ok(match previous {
+        Action::Default => 0,
+        Action::Ignore => 1,
+        Action::Handler { handler, .. } => handler.as_u64(),
+    })
+}
+
+/// `sigreturn()`
+///
+/// Returns the result of the system call the signal interrupted. If the
+/// saved registers are corrupt the process is killed, as there is nothing
+/// sensible to return to.
+pub(super) fn sigreturn(frame: &mut SyscallFrame) -> SyscallResult {
+    match signal::restore(frame) {
+        Some(rax) => Ok(rax),
+        None => process::exit_current(Signal::SegmentationFault.exit_code()),
+    }
+}
+
+/// `futex_wait(addr, expected)`
+pub(super) fn futex_wait(frame: &mut SyscallFrame) -> SyscallResult {
+    let [addr, expected, ..] = frame.args;
+    let addr = VirtAddr::try_new(addr).map_err(|_| Errno::EFAULT)?;
+    let space = process::current().ok_or(Errno::EINVAL)?.address_space();
+    futex::wait(&space, addr, expected as u32).map_err(futex_errno)?;
+    Ok(0)
+}
+
+/// `futex_wake(addr, count)`
+pub(super) fn futex_wake(frame: &mut SyscallFrame) -> SyscallResult {
+    let [addr, count, ..] = frame.args;
+    let addr = VirtAddr::try_new(addr).map_err(|_| Errno::EFAULT)?;
+    let space = process::current().ok_or(Errno::EINVAL)?.address_space();
+    let woken = futex::wake(&space, addr, count as usize).map_err(futex_errno)?;
+    Ok(woken as u64)
+}
+
+/// Maps a futex error to its error number.
+fn futex_errno(err: FutexError) -> Errno {
+    match err {
+        FutexError::BadAddress => Errno::EFAULT,
+        FutexError::Unaligned => Errno::EINVAL,
+        FutexError::WouldBlock => Errno::EAGAIN,
+        FutexError::Interrupted => Errno::EINTR,
+    }
+}
+
+/// `set_tls(pointer)`
+///
+/// The kernel never dereferences the pointer, but it must be a user
+/// address.
+pub(super) fn set_tls(frame: &mut SyscallFrame) -> SyscallResult {
+    let pointer = frame.args[0];
+    if pointer >= USER_SPACE_END {
+        return Err(Errno::EINVAL);
+    }
+    // Saved by the next context switch away from this thread.
+    unsafe { tls::set_fs_base(pointer) };
+    Ok(0)
+}
+
+/// `sleep_ms(ms)`
+///
+/// Returns [`Errno::EINTR`] early if a signal arrives.
+pub(super) fn sleep_ms(frame: &mut SyscallFrame) -> SyscallResult {
+    let args = frame.args;
+    // Nothing notifies the queue; only the timeout or a signal ends the wait.
+    let interrupted =
+        WaitQueue::new().wait_until_timeout(Duration::from_millis(args[0]), signal::interrupted);
+    if interrupted {
+        return Err(Errno::EINTR);
+    }
+    Ok(0)
+}
+
+/// `getpid()`
+pub(super) fn getpid(_frame: &mut SyscallFrame) -> SyscallResult {
+    process::current()
+        .map(|process| process.pid().as_u64())
+        .ok_or(Errno::EINVAL)
+}
+
+/// `exec(path, argv)`
+///
+/// On success the caller returns into the new program, with all registers
+/// but the stack pointer cleared and no thread pointer set.
+pub(super) fn exec(frame: &mut SyscallFrame) -> SyscallResult {
+    let args = frame.args;
+    let process = process::current().ok_or(Errno::EINVAL)?;
+    let space = process.address_space();
+    drop(process);
+
+    let path = read_c_string(&space, args[0], PATH_MAX)?;
+    let mut argv = Vec::new();
+    let mut total = 0;
+    for index in 0..=MAX_ARGS as u64 {
+        let mut pointer = [0u8; 8];
+        let slot = VirtAddr::try_new(args[1].wrapping_add(index * 8)).map_err(|_| Errno::EFAULT)?;
+        space.read(slot, &mut pointer).map_err(|_| Errno::EFAULT)?;
+        let pointer = u64::from_le_bytes(pointer);
+        if pointer == 0 {
+            break;
+        }
+        if index == MAX_ARGS as u64 {
+            return Err(Errno::E2BIG);
+        }
+        let arg = read_c_string(&space, pointer, ARG_MAX - total)?;
+        total += arg.len() + 1;
+        argv.push(arg);
+    }
+    drop(space);
+
+    let argv: Vec<&str> = argv.iter().map(String::as_str).collect();
+    let entry = process::exec(&path, &argv).map_err(|err| match err {
+        ProcessError::NotFound => Errno::ENOENT,
+        ProcessError::ArgumentsTooLong => Errno::E2BIG,
+        ProcessError::Elf(_) => Errno::ENOEXEC,
+        _ => Errno::ENOMEM,
+    })?;
+
+    unsafe { tls::set_fs_base(0) };
+    *frame = SyscallFrame {
+        rax: 0,
+        args: [0; 6],
+        r11: USER_RFLAGS,
+        rcx: entry.instruction_pointer.as_u64(),
+        rsp: entry.stack_pointer.as_u64(),
+    };
+    Ok(0)
+}
+
+/// Reads a NUL-terminated string of at most `max` bytes (including the
+/// terminator) from user memory.
+///
+/// # Errors
+///
+/// [`Errno::EFAULT`] if the memory is inaccessible, [`Errno::E2BIG`] if no
+/// terminator is found within `max` bytes, and [`Errno::EINVAL`] if the
+/// string is not UTF-8.
+pub(super) fn read_c_string(space: &AddressSpace, addr: u64, max: usize) -> Result<String, Errno> {
+    let mut addr = VirtAddr::try_new(addr).map_err(|_| Errno::EFAULT)?;
+    let mut bytes = Vec::new();
+    loop {
+        // Never read across a page boundary the string may not extend to.
+        let chunk = (4096 - usize::from(addr.page_offset())).min(max - bytes.len());
+        if chunk == 0 {
+            return Err(Errno::E2BIG);
+        }
+        let start = bytes.len();
+        bytes.resize(start + chunk, 0);
+        space
+            .read(addr, &mut bytes[start..])
+            .map_err(|_| Errno::EFAULT)?;
+        if let Some(end) = bytes[start..].iter().position(|&byte| byte == 0) {
+            bytes.truncate(start + end);
+            return String::from_utf8(bytes).map_err(|_| Errno::EINVAL);
+        }
+        addr += chunk as u64;
+    }
+}
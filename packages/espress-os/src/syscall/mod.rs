@@ -0,0 +1,390 @@
+//! # System Calls
+//!
+//! User programs enter the kernel with the `syscall` instruction. The CPU
+//! loads the kernel code segment from `STAR`, jumps to [`syscall_entry`]
+//! (`LSTAR`), and clears the `RFLAGS` bits in `SFMASK`, which includes the
+//! interrupt flag; it does not switch stacks.
+//!
+//! ## Calling Convention
+//!
+//! | Register                          | Meaning                          |
+//! |-----------------------------------|----------------------------------|
+//! | `rax`                             | system call number (see [`nr`])  |
+//! | `rdi`, `rsi`, `rdx`, `r10`, `r8`, `r9` | arguments 0 to 5            |
+//! | `rax` on return                   | result, or `-errno` on failure   |
+//!
+//! `rcx` and `r11` are clobbered by the instructions themselves; every
+//! other register is preserved.
+//!
+//! ## Entry
+//!
+//! With interrupts still masked, the entry stub switches to the calling
+//! thread's kernel stack (published by the scheduler with
+//! [`set_kernel_stack`]), saves the user stack pointer, return address,
+//! flags, and argument registers there as a [`SyscallFrame`], and only then
+//! reenables interrupts and calls [`dispatch`]. A system call may therefore
+//! block or be preempted like any other kernel code.
+//!
+//! Before returning, [`dispatch`] delivers pending
+//! [signals](crate::process::signal), which may redirect the return to a
+//! signal handler.
+
+#[cfg(feature = "fs")]
+mod files;
+mod handlers;
+#[cfg(feature = "net")]
+mod sockets;
+
+use core::arch::naked_asm;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::registers::model_specific::{Efer, EferFlags, LStar, SFMask, Star};
+use x86_64::registers::rflags::RFlags;
+use x86_64::VirtAddr;
+
+use crate::gdt;
+use crate::process::signal;
+
+/// System call numbers.
+pub mod nr {
+    /// `exit(code)`: terminates the calling program
+    pub const EXIT: u64 = 0;
+    /// `write(fd, buf, len)`: writes to handle `fd`
+    pub const WRITE: u64 = 1;
+    /// `sleep_ms(ms)`: blocks for at least `ms` milliseconds, or until a
+    /// signal arrives
+    pub const SLEEP_MS: u64 = 2;
+    /// `getpid()`: returns the caller's process ID
+    pub const GETPID: u64 = 3;
+    /// `exec(path, argv)`: replaces the caller's program; `path` and the
+    /// entries of the NULL-terminated `argv` are NUL-terminated strings
+    pub const EXEC: u64 = 4;
+    /// `read(fd, buf, len)`: reads from handle `fd`; `0` means end of file
+    pub const READ: u64 = 5;
+    /// `pipe(fds)`: creates a pipe and stores its read and write handle
+    /// as two `u32`s at `fds`
+    pub const PIPE: u64 = 6;
+    /// `close(fd)`: closes handle `fd`
+    pub const CLOSE: u64 = 7;
+    /// `kill(pid, signal)`: sends `signal` to process `pid`; signal `0`
+    /// only checks that the process exists
+    pub const KILL: u64 = 8;
+    /// `signal(signal, handler, restorer)`: sets the action for `signal`
+    /// to the default (`handler` 0), ignoring it (1), or calling `handler`
+    /// returning into `restorer`; returns the previous handler in the same
+    /// encoding
+    pub const SIGNAL: u64 = 9;
+    /// `sigreturn()`: called by a signal restorer to resume the code the
+    /// signal interrupted
+    pub const SIGRETURN: u64 = 10;
+    /// `futex_wait(addr, expected)`: blocks until woken if the `u32` at
+    /// `addr` equals `expected`
+    pub const FUTEX_WAIT: u64 = 11;
+    /// `futex_wake(addr, count)`: wakes up to `count` waiters on `addr` and
+    /// returns how many were woken
+    pub const FUTEX_WAKE: u64 = 12;
+    /// `set_tls(pointer)`: sets the calling thread's `FS` base, the thread
+    /// pointer of user runtimes
+    pub const SET_TLS: u64 = 13;
+    /// `open(path, flags)`: opens the NUL-terminated absolute `path` with
+    /// the `O_*` [`flags`](crate::fs::file) and returns the new handle
+    pub const OPEN: u64 = 14;
+    /// `seek(fd, offset, whence)`: moves the position of file handle `fd`
+    /// to `offset` from the start (`whence` 0), the current position (1),
+    /// or the end (2) and returns the new position
+    pub const SEEK: u64 = 15;
+    /// `readdir(fd, buf, len)`: fills `buf` with records for the next
+    /// entries of directory handle `fd` and returns the bytes used; `0`
+    /// means no entries are left. A record is a `u16` record length, a
+    /// `u8` kind (1 file, 2 directory, 3 symbolic link, 4 character
+    /// device, 5 block device), and the NUL-terminated name, padded to a
+    /// multiple of 8 bytes
+    pub const READDIR: u64 = 16;
+    /// `sync()`: writes all buffered file data to the disks
+    pub const SYNC: u64 = 17;
+    /// `socket(domain, type)`: creates a socket of `type` (1 stream, 2
+    /// datagram) for `domain` (2 IPv4, 10 IPv6) and returns its handle;
+    /// either way the socket speaks both versions of IP
+    pub const SOCKET: u64 = 18;
+    /// `bind(fd, addr, addr_len)`: binds socket `fd` to the port of the
+    /// `sockaddr_in` or `sockaddr_in6` at `addr`
+    pub const BIND: u64 = 19;
+    /// `connect(fd, addr, addr_len)`: connects socket `fd` to `addr`; for
+    /// datagram sockets, sets the default peer
+    pub const CONNECT: u64 = 20;
+    /// `listen(fd, backlog)`: makes stream socket `fd` accept connections;
+    /// `backlog` is ignored
+    pub const LISTEN: u64 = 21;
+    /// `accept(fd, addr, addr_len)`: waits for a connection to listening
+    /// socket `fd` and returns its handle; if `addr` is not null, stores
+    /// the peer address there, truncated to the `u32` at `addr_len`,
+    /// which is set to its full size
+    pub const ACCEPT: u64 = 22;
+    /// `send(fd, buf, len, addr, addr_len)`: sends from socket `fd` to
+    /// `addr`, or its peer if `addr` is null, and returns the bytes sent
+    pub const SEND: u64 = 23;
+    /// `recv(fd, buf, len, addr, addr_len)`: waits for data on socket `fd`
+    /// and returns the bytes read; stores the sender at `addr` like
+    /// `accept`
+    pub const RECV: u64 = 24;
+}
+
+/// Error numbers returned (negated) in `rax`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i64)]
+pub enum Errno {
+    /// No such file or directory
+    ENOENT = 2,
+    /// No such process
+    ESRCH = 3,
+    /// Interrupted by a signal
+    EINTR = 4,
+    /// I/O error
+    EIO = 5,
+    /// Argument list too long
+    E2BIG = 7,
+    /// Exec format error
+    ENOEXEC = 8,
+    /// Bad file descriptor
+    EBADF = 9,
+    /// Try again
+    EAGAIN = 11,
+    /// Out of memory
+    ENOMEM = 12,
+    /// Bad address
+    EFAULT = 14,
+    /// Device or resource busy
+    EBUSY = 16,
+    /// File exists
+    EEXIST = 17,
+    /// Cross-device link
+    EXDEV = 18,
+    /// Not a directory
+    ENOTDIR = 20,
+    /// Is a directory
+    EISDIR = 21,
+    /// Invalid argument
+    EINVAL = 22,
+    /// No space left on device
+    ENOSPC = 28,
+    /// Illegal seek
+    ESPIPE = 29,
+    /// Read-only file system
+    EROFS = 30,
+    /// Broken pipe
+    EPIPE = 32,
+    /// File name too long
+    ENAMETOOLONG = 36,
+    /// Function not implemented
+    ENOSYS = 38,
+    /// Directory not empty
+    ENOTEMPTY = 39,
+    /// Socket operation on non-socket
+    ENOTSOCK = 88,
+    /// Message too long
+    EMSGSIZE = 90,
+    /// Socket type not supported
+    ESOCKTNOSUPPORT = 94,
+    /// Operation not supported on the socket
+    EOPNOTSUPP = 95,
+    /// Address family not supported
+    EAFNOSUPPORT = 97,
+    /// Address already in use
+    EADDRINUSE = 98,
+    /// Cannot assign the requested address
+    EADDRNOTAVAIL = 99,
+    /// Network is unreachable
+    ENETUNREACH = 101,
+    /// Connection reset by peer
+    ECONNRESET = 104,
+    /// No buffer space available
+    ENOBUFS = 105,
+    /// Socket is already connected
+    EISCONN = 106,
+    /// Socket is not connected
+    ENOTCONN = 107,
+    /// Connection timed out
+    ETIMEDOUT = 110,
+    /// Connection refused
+    ECONNREFUSED = 111,
+}
+
+/// Result of a system call handler.
+pub type SyscallResult = Result<u64, Errno>;
+
+/// A system call handler; receives the saved user registers, which it may
+/// change to resume the caller elsewhere.
+type Handler = fn(&mut SyscallFrame) -> SyscallResult;
+
+/// Handlers indexed by system call number.
+///
+/// The file and socket calls fail with [`Errno::ENOSYS`] in a kernel built
+/// without the `fs` or `net` feature.
+static TABLE: [Handler; 25] = [
+    handlers::exit,
+    handlers::write,
+    handlers::sleep_ms,
+    handlers::getpid,
+    handlers::exec,
+    handlers::read,
+    handlers::pipe,
+    handlers::close,
+    handlers::kill,
+    handlers::signal,
+    handlers::sigreturn,
+    handlers::futex_wait,
+    handlers::futex_wake,
+    handlers::set_tls,
+    #[cfg(feature = "fs")]
+    files::open,
+    #[cfg(not(feature = "fs"))]
+    handlers::unsupported,
+    #[cfg(feature = "fs")]
+    files::seek,
+    #[cfg(not(feature = "fs"))]
+    handlers::unsupported,
+    #[cfg(feature = "fs")]
+    files::readdir,
+    #[cfg(not(feature = "fs"))]
+    handlers::unsupported,
+    #[cfg(feature = "fs")]
+    files::sync,
+    #[cfg(not(feature = "fs"))]
+    handlers::unsupported,
+    #[cfg(feature = "net")]
+    sockets::socket,
+    #[cfg(not(feature = "net"))]
+    handlers::unsupported,
+    #[cfg(feature = "net")]
+    sockets::bind,
+    #[cfg(not(feature = "net"))]
+    handlers::unsupported,
+    #[cfg(feature = "net")]
+    sockets::connect,
+    #[cfg(not(feature = "net"))]
+    handlers::unsupported,
+    #[cfg(feature = "net")]
+    sockets::listen,
+    #[cfg(not(feature = "net"))]
+    handlers::unsupported,
+    #[cfg(feature = "net")]
+    sockets::accept,
+    #[cfg(not(feature = "net"))]
+    handlers::unsupported,
+    #[cfg(feature = "net")]
+    sockets::send,
+    #[cfg(not(feature = "net"))]
+    handlers::unsupported,
+    #[cfg(feature = "net")]
+    sockets::recv,
+    #[cfg(not(feature = "net"))]
+    handlers::unsupported,
+];
+
+/// Kernel stack top of the running thread, loaded by [`syscall_entry`].
+static KERNEL_RSP: AtomicU64 = AtomicU64::new(0);
+
+/// Scratch slot for the user stack pointer while the entry stub switches
+/// stacks.
+static USER_RSP: AtomicU64 = AtomicU64::new(0);
+
+/// Register state saved by [`syscall_entry`], lowest address first.
+#[derive(Debug)]
+#[repr(C)]
+pub struct SyscallFrame {
+    /// System call number on entry, result on return
+    pub rax: u64,
+    /// Argument registers in calling convention order
+    pub args: [u64; 6],
+    /// User `RFLAGS`, restored by `sysret`
+    pub r11: u64,
+    /// User return address, restored by `sysret`
+    pub rcx: u64,
+    /// User stack pointer
+    pub rsp: u64,
+}
+
+/// Enables `syscall`/`sysret` and points them at the entry stub.
+///
+/// # Panics
+///
+/// Panics if the GDT layout does not match what `sysret` expects, which
+/// [`gdt::init`] guarantees.
+pub fn init() {
+    let selectors = gdt::selectors();
+    Star::write(
+        selectors.user_code,
+        selectors.user_data,
+        selectors.kernel_code,
+        selectors.kernel_data,
+    )
+    .expect("GDT layout unsuitable for syscall/sysret");
+    LStar::write(VirtAddr::new(syscall_entry as *const () as u64));
+    SFMask::write(
+        RFlags::INTERRUPT_FLAG
+            | RFlags::TRAP_FLAG
+            | RFlags::DIRECTION_FLAG
+            | RFlags::ALIGNMENT_CHECK,
+    );
+    unsafe { Efer::update(|flags| flags.insert(EferFlags::SYSTEM_CALL_EXTENSIONS)) };
+}
+
+/// Sets the stack [`syscall_entry`] switches to.
+///
+/// The scheduler calls this with the kernel stack of every thread it
+/// switches to.
+pub fn set_kernel_stack(top: VirtAddr) {
+    KERNEL_RSP.store(top.as_u64(), Ordering::Relaxed);
+}
+
+/// Entry point of the `syscall` instruction.
+///
+/// The frame pushed here is ten words, so the stack is 16-byte aligned at
+/// the call into [`dispatch`].
+#[unsafe(naked)]
+unsafe extern "C" fn syscall_entry() {
+    naked_asm!(
+        "mov [rip + {user_rsp}], rsp",
+        "mov rsp, [rip + {kernel_rsp}]",
+        "push qword ptr [rip + {user_rsp}]",
+        "push rcx",
+        "push r11",
+        "push r9",
+        "push r8",
+        "push r10",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push rax",
+        "mov rdi, rsp",
+        "sti",
+        "call {dispatch}",
+        "cli",
+        "pop rax",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop r10",
+        "pop r8",
+        "pop r9",
+        "pop r11",
+        "pop rcx",
+        "pop rsp",
+        "sysretq",
+        user_rsp = sym USER_RSP,
+        kernel_rsp = sym KERNEL_RSP,
+        dispatch = sym dispatch,
+    );
+}
+
+/// Runs the handler selected by `frame.rax`, stores its result there, and
+/// delivers pending signals.
+extern "C" fn dispatch(frame: &mut SyscallFrame) {
+    let result = TABLE
+        .get(frame.rax as usize)
+        .map_or(Err(Errno::ENOSYS), |handler| handler(frame));
+    frame.rax = match result {
+        Ok(value) => value,
+        Err(errno) => (-(errno as i64)) as u64,
+    };
+    signal::deliver(frame);
+}
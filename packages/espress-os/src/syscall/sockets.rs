@@ -0,0 +1,292 @@
+//! # Socket System Calls
+//!
+//! The BSD-style socket calls on the [network stack](crate::net), and the
+//! parts of `read` and `write` that reach sockets. Left out of kernels built
+//! without the `net` feature.
+
+use alloc::sync::Arc;
+use alloc::vec;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use x86_64::VirtAddr;
+
+use super::handlers::CHUNK;
+use super::{Errno, SyscallFrame, SyscallResult};
+use crate::mm::AddressSpace;
+use crate::net::socket::{Socket, SocketType};
+use crate::net::NetError;
+use crate::process;
+use crate::process::handle::Handle;
+
+/// Largest datagram `send` accepts and `recv` returns.
+const DATAGRAM_MAX: usize = 65535;
+
+/// Address family: IPv4.
+const AF_INET: u64 = 2;
+/// Address family: IPv6.
+const AF_INET6: u64 = 10;
+
+/// Socket type: stream.
+const SOCK_STREAM: u64 = 1;
+/// Socket type: datagram.
+const SOCK_DGRAM: u64 = 2;
+
+/// Size of a `sockaddr_in`: family, port, address, and 8 bytes of zeros.
+const SOCKADDR_IN_LEN: usize = 16;
+/// Size of a `sockaddr_in6`: family, port, flow info, address, and scope.
+const SOCKADDR_IN6_LEN: usize = 28;
+
+/// `socket(domain, type)`
+pub(super) fn socket(frame: &mut SyscallFrame) -> SyscallResult {
+    let [domain, kind, ..] = frame.args;
+    if domain != AF_INET && domain != AF_INET6 {
+        return Err(Errno::EAFNOSUPPORT);
+    }
+    let kind = match kind {
+        SOCK_STREAM => SocketType::Stream,
+        SOCK_DGRAM => SocketType::Datagram,
+        _ => return Err(Errno::ESOCKTNOSUPPORT),
+    };
+    let process = process::current().ok_or(Errno::EINVAL)?;
+    let socket = Arc::new(Socket::new(kind));
+    let fd = process.with_handles(|handles| handles.insert(Handle::Socket(socket)));
+    Ok(fd as u64)
+}
+
+/// `bind(fd, addr, addr_len)`
+pub(super) fn bind(frame: &mut SyscallFrame) -> SyscallResult {
+    let [fd, addr, addr_len, ..] = frame.args;
+    let (socket, space) = socket_handle(fd)?;
+    let local = read_sockaddr(&space, addr, addr_len)?;
+    socket.bind(local).map_err(net_errno)?;
+    Ok(0)
+}
+
+/// `connect(fd, addr, addr_len)`
+pub(super) fn connect(frame: &mut SyscallFrame) -> SyscallResult {
+    let [fd, addr, addr_len, ..] = frame.args;
+    let (socket, space) = socket_handle(fd)?;
+    let remote = read_sockaddr(&space, addr, addr_len)?;
+    drop(space);
+    socket.connect(remote).map_err(net_errno)?;
+    Ok(0)
+}
+
+/// `listen(fd, backlog)`
+pub(super) fn listen(frame: &mut SyscallFrame) -> SyscallResult {
+    let (socket, _) = socket_handle(frame.args[0])?;
+    socket.listen().map_err(net_errno)?;
+    Ok(0)
+}
+
+/// `accept(fd, addr, addr_len)`
+pub(super) fn accept(frame: &mut SyscallFrame) -> SyscallResult {
+    let [fd, addr, addr_len, ..] = frame.args;
+    let (socket, space) = socket_handle(fd)?;
+    let (connection, peer) = socket.accept().map_err(net_errno)?;
+    let process = process::current().ok_or(Errno::EINVAL)?;
+    let fd = process.with_handles(|handles| handles.insert(Handle::Socket(Arc::new(connection))));
+    if let Err(err) = write_sockaddr(&space, addr, addr_len, peer) {
+        process.with_handles(|handles| handles.remove(fd));
+        return Err(err);
+    }
+    Ok(fd as u64)
+}
+
+/// `send(fd, buf, len, addr, addr_len)`
+///
+/// Stream sockets block until all of `buf` is queued, unless the
+/// connection fails after some of it was; the destination is ignored.
+/// Datagram sockets send `buf` as one datagram.
+pub(super) fn send(frame: &mut SyscallFrame) -> SyscallResult {
+    let [fd, buf, len, addr, addr_len, ..] = frame.args;
+    let (socket, space) = socket_handle(fd)?;
+    let buf = VirtAddr::try_new(buf).map_err(|_| Errno::EFAULT)?;
+    let dst = match addr {
+        0 => None,
+        addr => Some(read_sockaddr(&space, addr, addr_len)?),
+    };
+    write_socket(&space, &socket, buf, len, dst)
+}
+
+/// `recv(fd, buf, len, addr, addr_len)`
+///
+/// Stream sockets return whatever has arrived, 0 once the peer has closed
+/// its end. Datagram sockets return one datagram, cut to `len` bytes; a
+/// connected one only accepts datagrams from its peer.
+pub(super) fn recv(frame: &mut SyscallFrame) -> SyscallResult {
+    let [fd, buf, len, addr, addr_len, ..] = frame.args;
+    let (socket, space) = socket_handle(fd)?;
+    let buf = VirtAddr::try_new(buf).map_err(|_| Errno::EFAULT)?;
+    let (len, src) = read_socket(&space, &socket, buf, len)?;
+    write_sockaddr(&space, addr, addr_len, src)?;
+    Ok(len)
+}
+
+/// Sends `len` bytes of user memory at `buf` from a socket.
+///
+/// # Errors
+///
+/// [`Errno::EMSGSIZE`] if a datagram is too long, and the error of the
+/// socket if nothing was sent.
+pub(super) fn write_socket(
+    space: &AddressSpace,
+    socket: &Socket,
+    buf: VirtAddr,
+    len: u64,
+    dst: Option<SocketAddr>,
+) -> SyscallResult {
+    if socket.kind() == SocketType::Datagram {
+        if len > DATAGRAM_MAX as u64 {
+            return Err(Errno::EMSGSIZE);
+        }
+        let mut datagram = vec![0; len as usize];
+        space.read(buf, &mut datagram).map_err(|_| Errno::EFAULT)?;
+        return socket
+            .send_to(&datagram, dst)
+            .map(|sent| sent as u64)
+            .map_err(net_errno);
+    }
+    let mut chunk = [0u8; CHUNK];
+    let mut written = 0;
+    while written < len {
+        let count = (len - written).min(CHUNK as u64) as usize;
+        space
+            .read(buf + written, &mut chunk[..count])
+            .map_err(|_| Errno::EFAULT)?;
+        let mut sent = 0;
+        while sent < count {
+            match socket.send_to(&chunk[sent..count], None) {
+                Ok(n) => sent += n,
+                Err(_) if written + sent as u64 > 0 => return Ok(written + sent as u64),
+                Err(err) => return Err(net_errno(err)),
+            }
+        }
+        written += count as u64;
+    }
+    Ok(len)
+}
+
+/// Receives up to `len` bytes from a socket into user memory at `buf`.
+///
+/// # Returns
+///
+/// The number of bytes read, and the sender.
+pub(super) fn read_socket(
+    space: &AddressSpace,
+    socket: &Socket,
+    buf: VirtAddr,
+    len: u64,
+) -> Result<(u64, SocketAddr), Errno> {
+    let mut data = vec![0; len.min(DATAGRAM_MAX as u64) as usize];
+    let (count, src) = socket.recv_from(&mut data).map_err(net_errno)?;
+    space
+        .write(buf, &data[..count])
+        .map_err(|_| Errno::EFAULT)?;
+    Ok((count as u64, src))
+}
+
+/// Returns the socket behind handle `fd` of the calling process, and the
+/// process's address space.
+///
+/// # Errors
+///
+/// [`Errno::EBADF`] if `fd` is not open and [`Errno::ENOTSOCK`] if it is
+/// not a socket.
+fn socket_handle(fd: u64) -> Result<(Arc<Socket>, Arc<AddressSpace>), Errno> {
+    let process = process::current().ok_or(Errno::EINVAL)?;
+    match process.with_handles(|handles| handles.get(fd as usize).cloned()) {
+        Some(Handle::Socket(socket)) => Ok((socket, process.address_space())),
+        Some(_) => Err(Errno::ENOTSOCK),
+        None => Err(Errno::EBADF),
+    }
+}
+
+/// Reads a `sockaddr_in` or `sockaddr_in6` of `len` bytes from user
+/// memory at `addr`.
+///
+/// # Errors
+///
+/// [`Errno::EFAULT`] if the memory is inaccessible,
+/// [`Errno::EAFNOSUPPORT`] for other families, and [`Errno::EINVAL`] if
+/// `len` is too short for the family.
+fn read_sockaddr(space: &AddressSpace, addr: u64, len: u64) -> Result<SocketAddr, Errno> {
+    let addr = VirtAddr::try_new(addr).map_err(|_| Errno::EFAULT)?;
+    let mut raw = [0u8; SOCKADDR_IN6_LEN];
+    let len = len.min(SOCKADDR_IN6_LEN as u64) as usize;
+    if len < 2 {
+        return Err(Errno::EINVAL);
+    }
+    space
+        .read(addr, &mut raw[..len])
+        .map_err(|_| Errno::EFAULT)?;
+    let port = u16::from_be_bytes([raw[2], raw[3]]);
+    match u64::from(u16::from_le_bytes([raw[0], raw[1]])) {
+        AF_INET if len >= SOCKADDR_IN_LEN => {
+            let ip = Ipv4Addr::new(raw[4], raw[5], raw[6], raw[7]);
+            Ok(SocketAddr::new(IpAddr::V4(ip), port))
+        }
+        AF_INET6 if len >= SOCKADDR_IN6_LEN => {
+            let ip: [u8; 16] = raw[8..24].try_into().unwrap();
+            Ok(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(ip)), port))
+        }
+        AF_INET | AF_INET6 => Err(Errno::EINVAL),
+        _ => Err(Errno::EAFNOSUPPORT),
+    }
+}
+
+/// Stores `sockaddr` at `addr` in user memory, cut to the `u32` at
+/// `len_addr`, and replaces that with its full size. Does nothing if
+/// `addr` is null.
+fn write_sockaddr(
+    space: &AddressSpace,
+    addr: u64,
+    len_addr: u64,
+    sockaddr: SocketAddr,
+) -> Result<(), Errno> {
+    if addr == 0 {
+        return Ok(());
+    }
+    let addr = VirtAddr::try_new(addr).map_err(|_| Errno::EFAULT)?;
+    let len_addr = VirtAddr::try_new(len_addr).map_err(|_| Errno::EFAULT)?;
+    let mut raw = [0u8; SOCKADDR_IN6_LEN];
+    raw[2..4].copy_from_slice(&sockaddr.port().to_be_bytes());
+    let size = match sockaddr.ip() {
+        IpAddr::V4(ip) => {
+            raw[..2].copy_from_slice(&(AF_INET as u16).to_le_bytes());
+            raw[4..8].copy_from_slice(&ip.octets());
+            SOCKADDR_IN_LEN
+        }
+        IpAddr::V6(ip) => {
+            raw[..2].copy_from_slice(&(AF_INET6 as u16).to_le_bytes());
+            raw[8..24].copy_from_slice(&ip.octets());
+            SOCKADDR_IN6_LEN
+        }
+    };
+    let mut room = [0u8; 4];
+    space.read(len_addr, &mut room).map_err(|_| Errno::EFAULT)?;
+    let room = (u32::from_le_bytes(room) as usize).min(size);
+    space.write(addr, &raw[..room]).map_err(|_| Errno::EFAULT)?;
+    space
+        .write(len_addr, &(size as u32).to_le_bytes())
+        .map_err(|_| Errno::EFAULT)
+}
+
+/// Maps a network error to its error number.
+fn net_errno(err: NetError) -> Errno {
+    match err {
+        NetError::FrameTooLong | NetError::PacketTooLong => Errno::EMSGSIZE,
+        NetError::NoBuffer => Errno::ENOBUFS,
+        NetError::Device | NetError::Rejected => Errno::EIO,
+        NetError::NoRoute => Errno::ENETUNREACH,
+        NetError::NoAddress => Errno::EADDRNOTAVAIL,
+        NetError::Timeout => Errno::ETIMEDOUT,
+        NetError::AddressInUse => Errno::EADDRINUSE,
+        NetError::ConnectionRefused => Errno::ECONNREFUSED,
+        NetError::ConnectionReset => Errno::ECONNRESET,
+        NetError::NotConnected => Errno::ENOTCONN,
+        NetError::NotFound => Errno::ENOENT,
+        NetError::AlreadyConnected => Errno::EISCONN,
+        NetError::InvalidState => Errno::EINVAL,
+        NetError::NotSupported => Errno::EOPNOTSUPP,
+    }
+}
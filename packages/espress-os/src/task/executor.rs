@@ -0,0 +1,126 @@
+//! # Task Executor
+//!
+//! A single-CPU executor that keeps a FIFO queue of tasks ready to run.
+//! Each task gets a waker that pushes its ID back onto the queue; wakers may
+//! fire from interrupt handlers, so the queue is a lock-free fixed-capacity
+//! array and waking never allocates.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use core::task::{Context, Poll, Waker};
+use crossbeam_queue::ArrayQueue;
+use x86_64::instructions::interrupts;
+
+use super::{Task, TaskId};
+
+/// Maximum number of task IDs queued for polling at once.
+const QUEUE_CAPACITY: usize = 100;
+
+/// Runs tasks to completion, polling them as they are woken.
+pub struct Executor {
+    /// All tasks that have not finished yet
+    tasks: BTreeMap<TaskId, Task>,
+    /// IDs of tasks ready to be polled, in wake-up order
+    ready_queue: Arc<ArrayQueue<TaskId>>,
+    /// Waker of each task, created on its first poll
+    waker_cache: BTreeMap<TaskId, Waker>,
+}
+
+impl Executor {
+    /// Creates an executor without tasks.
+    pub fn new() -> Self {
+        Executor {
+            tasks: BTreeMap::new(),
+            ready_queue: Arc::new(ArrayQueue::new(QUEUE_CAPACITY)),
+            waker_cache: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a task; it is polled for the first time on the next run.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the ready queue is full.
+    pub fn spawn(&mut self, task: Task) {
+        let id = task.id;
+        assert!(self.tasks.insert(id, task).is_none(), "task ID reused");
+        self.ready_queue.push(id).expect("ready queue full");
+    }
+
+    /// Runs all tasks forever, halting the CPU whenever none is ready.
+    pub fn run(&mut self) -> ! {
+        loop {
+            self.run_ready_tasks();
+            self.sleep_if_idle();
+        }
+    }
+
+    /// Polls every task in the ready queue once, in FIFO order.
+    fn run_ready_tasks(&mut self) {
+        while let Some(id) = self.ready_queue.pop() {
+            let Some(task) = self.tasks.get_mut(&id) else {
+                // The task finished after this wake-up was queued.
+                continue;
+            };
+            let waker = self
+                .waker_cache
+                .entry(id)
+                .or_insert_with(|| TaskWaker::waker(id, self.ready_queue.clone()));
+            let mut context = Context::from_waker(waker);
+
+            if let Poll::Ready(()) = task.poll(&mut context) {
+                self.tasks.remove(&id);
+                self.waker_cache.remove(&id);
+            }
+        }
+    }
+
+    /// Halts until the next interrupt if no task is ready.
+    ///
+    /// Interrupts are disabled while checking the queue, so a wake-up from
+    /// an interrupt handler cannot slip in between the check and the `hlt`.
+    fn sleep_if_idle(&self) {
+        interrupts::disable();
+        if self.ready_queue.is_empty() {
+            interrupts::enable_and_hlt();
+        } else {
+            interrupts::enable();
+        }
+    }
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Waker that requeues its task on the executor's ready queue.
+struct TaskWaker {
+    /// Task to requeue
+    id: TaskId,
+    /// The executor's ready queue
+    ready_queue: Arc<ArrayQueue<TaskId>>,
+}
+
+impl TaskWaker {
+    /// Creates a [`Waker`] for the task `id`.
+    fn waker(id: TaskId, ready_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
+        Waker::from(Arc::new(TaskWaker { id, ready_queue }))
+    }
+
+    fn wake_task(&self) {
+        self.ready_queue.push(self.id).expect("ready queue full");
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_task();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wake_task();
+    }
+}
@@ -0,0 +1,124 @@
+//! # Keyboard Input
+//!
+//! The keyboard interrupt handler only queues raw scancodes with
+//! [`add_scancode`]; decoding happens in task context by consuming a
+//! [`ScancodeStream`].
+
+use conquer_once::spin::OnceCell;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use crossbeam_queue::ArrayQueue;
+use futures_util::stream::{Stream, StreamExt};
+use futures_util::task::AtomicWaker;
+use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
+
+use crate::print;
+
+/// Number of scancodes buffered before new ones are dropped.
+const SCANCODE_QUEUE_CAPACITY: usize = 100;
+
+/// Scancodes received from the interrupt handler, not yet consumed.
+static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+
+/// Waker of the task waiting on the [`ScancodeStream`].
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Queues a scancode and wakes the stream's consumer.
+///
+/// Called from the keyboard interrupt handler, so it must not block or
+/// allocate. Scancodes are dropped (with a warning) if the queue is full or
+/// no [`ScancodeStream`] has been created yet.
+pub(crate) fn add_scancode(scancode: u8) {
+    match SCANCODE_QUEUE.try_get() {
+        Ok(queue) => {
+            if queue.push(scancode).is_err() {
+                crate::serial_println!("WARNING: scancode queue full; dropping keyboard input");
+            } else {
+                WAKER.wake();
+            }
+        }
+        Err(_) => {
+            crate::serial_println!("WARNING: scancode queue uninitialized");
+        }
+    }
+}
+
+/// Asynchronous stream of raw keyboard scancodes.
+///
+/// Only one stream may exist, since all scancodes go to a single queue.
+pub struct ScancodeStream {
+    _private: (),
+}
+
+impl ScancodeStream {
+    /// Creates the stream and the scancode queue behind it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called more than once.
+    pub fn new() -> Self {
+        SCANCODE_QUEUE
+            .try_init_once(|| ArrayQueue::new(SCANCODE_QUEUE_CAPACITY))
+            .expect("ScancodeStream::new should only be called once");
+        ScancodeStream { _private: () }
+    }
+}
+
+impl Default for ScancodeStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stream for ScancodeStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<u8>> {
+        let queue = SCANCODE_QUEUE
+            .try_get()
+            .expect("scancode queue not initialized");
+
+        // Fast path, then register the waker and check again so a scancode
+        // arriving in between is not missed.
+        if let Some(scancode) = queue.pop() {
+            return Poll::Ready(Some(scancode));
+        }
+        WAKER.register(context.waker());
+        match queue.pop() {
+            Some(scancode) => {
+                WAKER.take();
+                Poll::Ready(Some(scancode))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Decodes keyboard input and calls `handle` with every key pressed.
+///
+/// Creates the [`ScancodeStream`], so it may only run once.
+pub async fn for_each_key(mut handle: impl FnMut(DecodedKey)) {
+    let mut scancodes = ScancodeStream::new();
+    let mut keyboard = Keyboard::new(
+        ScancodeSet1::new(),
+        layouts::Us104Key,
+        HandleControl::Ignore,
+    );
+
+    while let Some(scancode) = scancodes.next().await {
+        if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
+            if let Some(key) = keyboard.process_keyevent(key_event) {
+                handle(key);
+            }
+        }
+    }
+}
+
+/// Decodes keyboard input and echoes it to the console.
+pub async fn print_keypresses() {
+    for_each_key(|key| match key {
+        DecodedKey::Unicode(character) => print!("{}", character),
+        DecodedKey::RawKey(key) => print!("{:?}", key),
+    })
+    .await;
+}
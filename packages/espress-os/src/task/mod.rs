@@ -0,0 +1,55 @@
+//! # Cooperative Tasks
+//!
+//! Lets kernel code be written as `async` functions. A [`Task`] wraps a
+//! pinned, heap-allocated future; the [`executor::Executor`] polls tasks in
+//! FIFO order whenever their waker fires and halts the CPU while nothing is
+//! ready, so interrupt-driven sources such as the
+//! [`keyboard::ScancodeStream`] can be consumed without busy-waiting.
+
+use alloc::boxed::Box;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll};
+
+pub mod executor;
+pub mod keyboard;
+
+/// A unit of cooperative work: a future that runs to completion.
+pub struct Task {
+    /// Unique identifier, used to route wake-ups
+    id: TaskId,
+    /// The future driven by the executor
+    future: Pin<Box<dyn Future<Output = ()>>>,
+}
+
+impl Task {
+    /// Wraps a future into a task.
+    ///
+    /// # Arguments
+    ///
+    /// * `future` - Future to run; it must not borrow anything short-lived
+    pub fn new(future: impl Future<Output = ()> + 'static) -> Task {
+        Task {
+            id: TaskId::new(),
+            future: Box::pin(future),
+        }
+    }
+
+    /// Polls the task's future once.
+    fn poll(&mut self, context: &mut Context) -> Poll<()> {
+        self.future.as_mut().poll(context)
+    }
+}
+
+/// Unique identifier of a [`Task`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TaskId(u64);
+
+impl TaskId {
+    /// Returns a fresh identifier.
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
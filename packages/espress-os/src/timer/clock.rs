@@ -0,0 +1,123 @@
+//! # Wall Clock
+//!
+//! Real-world time in UTC, as the time since the Unix epoch. [`init`] sets
+//! it from the CMOS RTC at boot, to the second; NTP (see
+//! [`net::sntp`](crate::net::sntp)) corrects it with [`set`]. In between,
+//! it advances with the kernel tick.
+
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use crate::arch::rtc;
+
+/// Days from 0000-03-01 to 1970-01-01 in the proleptic Gregorian calendar.
+const EPOCH_DAYS: i64 = 719_468;
+
+/// Days in a 400-year cycle of the calendar.
+const DAYS_PER_ERA: i64 = 146_097;
+
+/// Seconds in a day.
+const SECS_PER_DAY: u64 = 86_400;
+
+/// Wall-clock time at tick 0, in microseconds since the epoch.
+static BOOT_TIME: AtomicU64 = AtomicU64::new(0);
+
+/// A calendar date and time of day, in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    /// Year, e.g. 2024
+    pub year: u16,
+    /// Month, 1 to 12
+    pub month: u8,
+    /// Day of the month, 1 to 31
+    pub day: u8,
+    /// Hour, 0 to 23
+    pub hour: u8,
+    /// Minute, 0 to 59
+    pub minute: u8,
+    /// Second, 0 to 59
+    pub second: u8,
+}
+
+impl DateTime {
+    /// The date and time `secs` seconds after the epoch.
+    pub fn from_unix(secs: u64) -> Self {
+        let days = (secs / SECS_PER_DAY) as i64 + EPOCH_DAYS;
+        let time = secs % SECS_PER_DAY;
+        // Years start in March, so the leap day ends them.
+        let era = days / DAYS_PER_ERA;
+        let day_of_era = days - era * DAYS_PER_ERA;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_from_march = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+        let month = if month_from_march < 10 {
+            month_from_march + 3
+        } else {
+            month_from_march - 9
+        };
+        let year = era * 400 + year_of_era + i64::from(month <= 2);
+        DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (time / 3600) as u8,
+            minute: (time / 60 % 60) as u8,
+            second: (time % 60) as u8,
+        }
+    }
+
+    /// Seconds from the epoch to the date and time, 0 for anything
+    /// earlier.
+    pub fn to_unix(&self) -> u64 {
+        let (month, day) = (i64::from(self.month), i64::from(self.day));
+        let year = i64::from(self.year) - i64::from(month <= 2);
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let month_from_march = (month + 9) % 12;
+        let day_of_year = (153 * month_from_march + 2) / 5 + day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * DAYS_PER_ERA + day_of_era - EPOCH_DAYS;
+        let time =
+            u64::from(self.hour) * 3600 + u64::from(self.minute) * 60 + u64::from(self.second);
+        u64::try_from(days).map_or(0, |days| days * SECS_PER_DAY + time)
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// Returns the time since the epoch.
+pub fn now() -> Duration {
+    Duration::from_micros(BOOT_TIME.load(Ordering::Relaxed)) + super::uptime()
+}
+
+/// Returns the current date and time.
+pub fn date_time() -> DateTime {
+    DateTime::from_unix(now().as_secs())
+}
+
+/// Sets the clock to `now`, the time since the epoch.
+pub fn set(now: Duration) {
+    let boot = now.saturating_sub(super::uptime());
+    BOOT_TIME.store(boot.as_micros() as u64, Ordering::Relaxed);
+}
+
+/// Writes the clock back to the CMOS RTC, so it survives a reboot.
+pub fn write_rtc() {
+    rtc::write(&date_time());
+}
+
+/// Sets the clock from the CMOS RTC.
+pub(super) fn init() {
+    set(Duration::from_secs(rtc::read().to_unix()));
+}
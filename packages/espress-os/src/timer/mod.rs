@@ -0,0 +1,274 @@
+//! # Kernel Timers
+//!
+//! Keeps the kernel's notion of time and runs callbacks after a delay, once
+//! ([`Timer::oneshot`]) or repeatedly ([`Timer::periodic`]), and lets
+//! threads [`sleep`].
+//!
+//! The PIT is programmed to [`TICK_HZ`]; every tick bumps a global counter.
+//! Pending timers live in a hashed timing wheel of [`WHEEL_SLOTS`] slots
+//! indexed by deadline tick, so arming a timer is constant time and each
+//! tick only looks at one slot. Timers further out than one revolution share
+//! slots with nearer ones and are skipped until their round comes.
+//!
+//! Callbacks do not run in interrupt context: the tick handler only wakes
+//! the `timer` thread, which expires due timers and calls their callbacks
+//! with no locks held. Callbacks may therefore allocate, take locks, and arm
+//! or cancel timers, but should return quickly since they delay every other
+//! timer.
+//!
+//! Real-world time is kept apart from the tick, by the wall [`clock`].
+
+pub mod clock;
+
+pub use clock::DateTime;
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
+use spin::Mutex;
+
+use crate::arch::pit;
+use crate::scheduler::{self, Priority, WaitQueue};
+
+/// Frequency of the timer interrupt.
+pub const TICK_HZ: u32 = 100;
+
+/// Number of slots in the timing wheel.
+pub const WHEEL_SLOTS: usize = 256;
+
+/// Ticks since [`init`].
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Earliest deadline of any armed timer, `u64::MAX` if none.
+static NEXT_DEADLINE: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// Where the timer thread waits for the next deadline.
+static EXPIRED: WaitQueue = WaitQueue::new();
+
+/// Armed timers.
+static WHEEL: Mutex<Wheel> = Mutex::new(Wheel::new());
+
+/// Programs the PIT, sets the wall clock, and starts the timer thread.
+///
+/// Must be called after [`scheduler::init`] and before interrupts are
+/// enabled.
+///
+/// # Panics
+///
+/// Panics if the timer thread cannot be started.
+pub fn init() {
+    pit::set_frequency(TICK_HZ);
+    clock::init();
+    let thread = scheduler::spawn_named("timer", run).expect("failed to start the timer thread");
+    thread.thread().set_priority(Priority::High);
+}
+
+/// Accounts a timer interrupt and wakes the timer thread if a timer is due.
+///
+/// Called from the timer interrupt handler.
+pub fn tick() {
+    let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    if now >= NEXT_DEADLINE.load(Ordering::Relaxed) {
+        EXPIRED.notify_one();
+    }
+}
+
+/// Returns the number of ticks since the timer was started.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Returns the time since the timer was started, at tick resolution.
+pub fn uptime() -> Duration {
+    ticks_to_duration(ticks())
+}
+
+/// Converts a duration to ticks, rounding up.
+pub fn duration_to_ticks(duration: Duration) -> u64 {
+    let nanos = duration.as_nanos() * TICK_HZ as u128;
+    nanos.div_ceil(1_000_000_000).min(u64::MAX as u128) as u64
+}
+
+/// Converts ticks to a duration.
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    Duration::from_nanos(ticks.saturating_mul(1_000_000_000 / TICK_HZ as u64))
+}
+
+/// Blocks the calling thread for at least `duration`.
+///
+/// # Panics
+///
+/// Panics if called from outside a scheduler thread.
+pub fn sleep(duration: Duration) {
+    let me = scheduler::current_id().expect("sleep called before the scheduler started");
+    let expired = Arc::new(AtomicBool::new(false));
+    let flag = expired.clone();
+    Timer::oneshot(duration, move || {
+        flag.store(true, Ordering::Release);
+        scheduler::unpark(me);
+    });
+    while !expired.load(Ordering::Acquire) {
+        scheduler::park();
+    }
+}
+
+/// Handle to an armed timer.
+///
+/// Dropping the handle leaves the timer armed; use [`cancel`](Self::cancel)
+/// to stop it.
+#[derive(Clone)]
+pub struct Timer {
+    /// Shared with the wheel entry; set to disarm it
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Timer {
+    /// Runs `callback` once after `delay`.
+    ///
+    /// The delay is rounded up to whole ticks, and is at least one tick.
+    pub fn oneshot<F>(delay: Duration, callback: F) -> Timer
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let mut callback = Some(callback);
+        arm(delay, None, move || {
+            if let Some(callback) = callback.take() {
+                callback();
+            }
+        })
+    }
+
+    /// Runs `callback` every `period`, starting one period from now.
+    ///
+    /// If the timer thread falls behind, missed runs are skipped rather than
+    /// made up in a burst.
+    pub fn periodic<F>(period: Duration, callback: F) -> Timer
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let period = duration_to_ticks(period).max(1);
+        arm(ticks_to_duration(period), Some(period), callback)
+    }
+
+    /// Disarms the timer.
+    ///
+    /// A callback that is already running finishes; it is not called again.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns `true` if the timer was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// Arms a timer and returns its handle.
+fn arm(delay: Duration, period: Option<u64>, callback: impl FnMut() + Send + 'static) -> Timer {
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let entry = Entry {
+        deadline: ticks() + duration_to_ticks(delay).max(1),
+        period,
+        callback: Box::new(callback),
+        cancelled: cancelled.clone(),
+    };
+    WHEEL.lock().insert(entry);
+    Timer { cancelled }
+}
+
+/// An armed timer in the wheel.
+struct Entry {
+    /// Tick at which the callback is due
+    deadline: u64,
+    /// Ticks between runs of a periodic timer
+    period: Option<u64>,
+    /// Function to call
+    callback: Box<dyn FnMut() + Send>,
+    /// Set by [`Timer::cancel`]
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Entry {
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// Hashed timing wheel.
+struct Wheel {
+    /// Timers indexed by `deadline % WHEEL_SLOTS`
+    slots: [Vec<Entry>; WHEEL_SLOTS],
+    /// Last tick whose slot has been expired
+    processed: u64,
+}
+
+impl Wheel {
+    const fn new() -> Self {
+        Wheel {
+            slots: [const { Vec::new() }; WHEEL_SLOTS],
+            processed: 0,
+        }
+    }
+
+    /// Adds a timer.
+    ///
+    /// A deadline the wheel has already moved past is pulled forward to the
+    /// next tick it expires, so the timer is not missed for a revolution.
+    fn insert(&mut self, mut entry: Entry) {
+        entry.deadline = entry.deadline.max(self.processed + 1);
+        NEXT_DEADLINE.fetch_min(entry.deadline, Ordering::Relaxed);
+        self.slots[entry.deadline as usize % WHEEL_SLOTS].push(entry);
+    }
+
+    /// Removes all timers due at `now`, in deadline order, and updates
+    /// [`NEXT_DEADLINE`]. Cancelled timers are dropped on the way.
+    fn expire(&mut self, now: u64) -> Vec<Entry> {
+        let mut due = Vec::new();
+        let pending = (now.saturating_sub(self.processed)).min(WHEEL_SLOTS as u64);
+        for tick in now + 1 - pending..=now {
+            let slot = &mut self.slots[tick as usize % WHEEL_SLOTS];
+            due.extend(slot.extract_if(.., |entry| entry.deadline <= now || entry.is_cancelled()));
+        }
+        self.processed = self.processed.max(now);
+        due.retain(|entry| !entry.is_cancelled());
+        due.sort_by_key(|entry| entry.deadline);
+
+        let next = self
+            .slots
+            .iter()
+            .flatten()
+            .map(|entry| entry.deadline)
+            .min()
+            .unwrap_or(u64::MAX);
+        NEXT_DEADLINE.store(next, Ordering::Relaxed);
+        due
+    }
+}
+
+/// Body of the timer thread: runs callbacks as their deadlines pass.
+fn run() {
+    loop {
+        EXPIRED.wait_until(|| ticks() >= NEXT_DEADLINE.load(Ordering::Relaxed));
+        let due = WHEEL.lock().expire(ticks());
+
+        for mut entry in due {
+            if entry.is_cancelled() {
+                continue;
+            }
+            (entry.callback)();
+            if let Some(period) = entry.period {
+                if entry.is_cancelled() {
+                    continue;
+                }
+                let now = ticks();
+                entry.deadline += period;
+                if entry.deadline <= now {
+                    entry.deadline = now + period - (now - entry.deadline) % period;
+                }
+                WHEEL.lock().insert(entry);
+            }
+        }
+    }
+}
@@ -0,0 +1,234 @@
+//! # Event Tracing
+//!
+//! A fixed-size ring buffer of timestamped kernel events, for finding out
+//! after the fact what the kernel was doing, e.g. why a thread did not run.
+//! The scheduler records every context switch, every thread that blocks,
+//! and every thread that is woken.
+//!
+//! Recording neither allocates nor blocks and may happen in interrupt
+//! handlers and with the scheduler locked. Once the buffer is full, each
+//! new event overwrites the oldest one.
+//!
+//! [`dump`] writes the buffer to the serial port in the Chrome trace event
+//! format, which Perfetto (<https://ui.perfetto.dev>) and `chrome://tracing`
+//! display as a per-thread timeline:
+//!
+//! ```text
+//! qemu-system-x86_64 ... -serial file:serial.log
+//! sed -n '/^{"traceEvents"/p' serial.log > trace.json
+//! ```
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::arch::tsc;
+use crate::scheduler::{self, ThreadId};
+use crate::{serial_print, serial_println};
+
+/// Number of events the buffer holds.
+pub const TRACE_CAPACITY: usize = 4096;
+
+/// Whether events are recorded.
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// The recorded events. Locked with interrupts disabled, since events are
+/// recorded from interrupt handlers.
+static BUFFER: Mutex<Ring> = Mutex::new(Ring::new());
+
+/// Something that happened in the kernel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// The CPU switched from one thread to another
+    Switch {
+        /// Thread that left the CPU
+        from: ThreadId,
+        /// Thread that got the CPU
+        to: ThreadId,
+    },
+    /// A thread parked and left the CPU
+    Block {
+        /// The parked thread
+        thread: ThreadId,
+    },
+    /// A parked thread was made ready
+    Wake {
+        /// The woken thread
+        thread: ThreadId,
+    },
+}
+
+/// An event and when it happened.
+#[derive(Debug, Clone, Copy)]
+pub struct Record {
+    /// TSC value at the time of the event
+    pub timestamp: u64,
+    /// What happened
+    pub event: Event,
+}
+
+/// Ring buffer of the latest [`TRACE_CAPACITY`] records.
+struct Ring {
+    /// Record storage, indexed by sequence number modulo the capacity
+    records: [Option<Record>; TRACE_CAPACITY],
+    /// Records written so far, including overwritten ones
+    written: u64,
+}
+
+impl Ring {
+    /// Creates an empty buffer.
+    const fn new() -> Self {
+        Ring {
+            records: [None; TRACE_CAPACITY],
+            written: 0,
+        }
+    }
+
+    /// Appends `record`, overwriting the oldest one if the buffer is full.
+    fn push(&mut self, record: Record) {
+        self.records[(self.written % TRACE_CAPACITY as u64) as usize] = Some(record);
+        self.written += 1;
+    }
+
+    /// Iterates over the records, oldest first.
+    fn iter(&self) -> impl Iterator<Item = &Record> {
+        let start = self.written.saturating_sub(TRACE_CAPACITY as u64);
+        (start..self.written)
+            .filter_map(|seq| self.records[(seq % TRACE_CAPACITY as u64) as usize].as_ref())
+    }
+}
+
+/// Records `event` with the current time, if tracing is enabled.
+///
+/// Safe to call from interrupt handlers.
+pub fn record(event: Event) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let timestamp = tsc::read();
+    interrupts::without_interrupts(|| BUFFER.lock().push(Record { timestamp, event }));
+}
+
+/// Enables or disables recording; events recorded so far are kept.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns `true` if events are recorded.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Discards all recorded events.
+pub fn clear() {
+    // Only the count is reset: building a fresh ring would need the whole
+    // buffer on the stack.
+    interrupts::without_interrupts(|| BUFFER.lock().written = 0);
+}
+
+/// Number of events lost because the buffer was full.
+pub fn overwritten() -> u64 {
+    let written = interrupts::without_interrupts(|| BUFFER.lock().written);
+    written.saturating_sub(TRACE_CAPACITY as u64)
+}
+
+/// Returns a copy of the recorded events, oldest first.
+pub fn snapshot() -> Vec<Record> {
+    // Room is made before locking: allocating with interrupts disabled
+    // could deadlock on the heap lock.
+    let mut records = Vec::with_capacity(TRACE_CAPACITY);
+    interrupts::without_interrupts(|| records.extend(BUFFER.lock().iter().copied()));
+    records
+}
+
+/// Writes the recorded events to the serial port as one line of Chrome
+/// trace event JSON.
+///
+/// The time a thread spent on the CPU becomes a slice on that thread's
+/// track; blocks and wakeups become instant events. Timestamps are in
+/// microseconds since the oldest event. Live threads are labeled with
+/// their names, exited ones only with their IDs.
+pub fn dump() {
+    let records = snapshot();
+    let names: BTreeMap<u64, &'static str> = scheduler::tasks()
+        .iter()
+        .map(|task| (task.id.as_u64(), task.name))
+        .collect();
+    let base = records.first().map_or(0, |record| record.timestamp);
+    let micros =
+        |timestamp: u64| Micros(tsc::cycles_to_duration(timestamp.saturating_sub(base)).as_nanos());
+
+    let mut events = TraceWriter::new();
+    for (tid, name) in &names {
+        events.event(format_args!(
+            r#""name":"thread_name","ph":"M","pid":0,"tid":{tid},"args":{{"name":"{name}"}}"#
+        ));
+    }
+    // The thread on the CPU and since when, once known.
+    let mut running: Option<(ThreadId, u64)> = None;
+    for record in &records {
+        let ts = micros(record.timestamp);
+        match record.event {
+            Event::Switch { from, to } => {
+                if let Some((thread, since)) = running.filter(|(thread, _)| *thread == from) {
+                    let start = micros(since);
+                    let dur = Micros(ts.0.saturating_sub(start.0));
+                    events.event(format_args!(
+                        r#""name":"running","ph":"X","pid":0,"tid":{},"ts":{start},"dur":{dur}"#,
+                        thread.as_u64()
+                    ));
+                }
+                running = Some((to, record.timestamp));
+            }
+            Event::Block { thread } => events.instant("block", thread, ts),
+            Event::Wake { thread } => events.instant("wake", thread, ts),
+        }
+    }
+    events.finish();
+}
+
+/// A time in nanoseconds, displayed as microseconds with three decimals.
+#[derive(Clone, Copy)]
+struct Micros(u128);
+
+impl core::fmt::Display for Micros {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{}.{:03}", self.0 / 1000, self.0 % 1000)
+    }
+}
+
+/// Writes the `traceEvents` array to the serial port, one event at a time.
+struct TraceWriter {
+    /// No event has been written yet
+    first: bool,
+}
+
+impl TraceWriter {
+    /// Opens the array.
+    fn new() -> Self {
+        serial_print!(r#"{{"traceEvents":["#);
+        TraceWriter { first: true }
+    }
+
+    /// Writes an event object with the given fields.
+    fn event(&mut self, fields: core::fmt::Arguments) {
+        let separator = if self.first { "" } else { "," };
+        self.first = false;
+        serial_print!("{separator}{{{fields}}}");
+    }
+
+    /// Writes an instant event on the track of `thread`.
+    fn instant(&mut self, name: &str, thread: ThreadId, ts: Micros) {
+        self.event(format_args!(
+            r#""name":"{name}","ph":"i","s":"t","pid":0,"tid":{},"ts":{ts}"#,
+            thread.as_u64()
+        ));
+    }
+
+    /// Closes the array.
+    fn finish(self) {
+        serial_println!("]}}");
+    }
+}
@@ -0,0 +1,102 @@
+//! # User Mode
+//!
+//! Runs code at privilege level 3. [`enter_usermode`] drops the calling
+//! kernel thread into user mode with `iretq`; from then on the thread only
+//! comes back into the kernel through interrupts and exceptions, which the
+//! CPU delivers on the thread's kernel stack (`RSP0` in the TSS, kept up to
+//! date by the scheduler).
+//!
+//! Programs call into the kernel with `syscall` (see [`crate::syscall`]).
+//! A fault raised by user code does not bring the kernel down: the fault
+//! handlers call [`kill_current`], which reports the fault and terminates
+//! the offending process. User programs run as [processes](crate::process).
+
+use core::arch::asm;
+use core::fmt;
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::VirtAddr;
+
+use crate::{gdt, process, scheduler, vga_println};
+
+/// `RFLAGS` for user mode: interrupts enabled, reserved bit 1 set.
+pub const USER_RFLAGS: u64 = 0x202;
+
+/// Switches the calling thread to user mode at `entry` with stack `stack`.
+///
+/// All general purpose registers are cleared so no kernel data leaks to
+/// user mode; interrupts are enabled there.
+///
+/// # Safety
+///
+/// `entry` and `stack` must point into user-accessible mappings, the first
+/// executable and the second writable. The calling thread must have a
+/// kernel stack registered as `RSP0` and must not hold any locks.
+pub unsafe fn enter_usermode(entry: VirtAddr, stack: VirtAddr) -> ! {
+    let selectors = gdt::selectors();
+    asm!(
+        "push {ss}",
+        "push {rsp}",
+        "push {rflags}",
+        "push {cs}",
+        "push {rip}",
+        "xor eax, eax",
+        "xor ebx, ebx",
+        "xor ecx, ecx",
+        "xor edx, edx",
+        "xor esi, esi",
+        "xor edi, edi",
+        "xor ebp, ebp",
+        "xor r8d, r8d",
+        "xor r9d, r9d",
+        "xor r10d, r10d",
+        "xor r11d, r11d",
+        "xor r12d, r12d",
+        "xor r13d, r13d",
+        "xor r14d, r14d",
+        "xor r15d, r15d",
+        "iretq",
+        ss = in(reg) u64::from(selectors.user_data.0),
+        rsp = in(reg) stack.as_u64(),
+        rflags = in(reg) USER_RFLAGS,
+        cs = in(reg) u64::from(selectors.user_code.0),
+        rip = in(reg) entry.as_u64(),
+        options(noreturn)
+    );
+}
+
+/// Returns `true` if the exception described by `frame` was raised in user
+/// mode.
+pub fn is_user_frame(frame: &InterruptStackFrame) -> bool {
+    frame.code_segment & 3 == 3
+}
+
+/// Reports a fault in user mode and terminates the current thread.
+///
+/// Called from exception handlers, on the thread's kernel stack, which
+/// makes it safe to reenable interrupts here. The thread's process exits
+/// with [`process::EXIT_KILLED`].
+pub fn kill_current(fault: fmt::Arguments, frame: &InterruptStackFrame) -> ! {
+    // User code holds no kernel locks, but a preempted kernel thread may;
+    // let it run so the locks below can be taken.
+    x86_64::instructions::interrupts::enable();
+    let ip = frame.instruction_pointer.as_u64();
+    let Some(process) = process::current() else {
+        vga_println!(
+            "user: killed `{}` after {} at {:#x}",
+            scheduler::current_name().unwrap_or("?"),
+            fault,
+            ip
+        );
+        scheduler::exit();
+    };
+    vga_println!(
+        "user: killed process {} (`{}`) after {} at {:#x}",
+        process.pid().as_u64(),
+        process.name(),
+        fault,
+        ip
+    );
+    // Dropped here, since the reference would otherwise never be released.
+    drop(process);
+    process::exit_current(process::EXIT_KILLED);
+}
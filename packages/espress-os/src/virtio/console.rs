@@ -0,0 +1,264 @@
+//! # Virtio Console
+//!
+//! Driver for the virtio console (`virtio-serial` in QEMU, with a
+//! `virtconsole` port), a paravirtual byte channel to the host that is far
+//! cheaper than an emulated 16550: a whole string travels in one request
+//! instead of one port write per byte.
+//!
+//! Only the first port is used, through receive queue 0 and transmit
+//! queue 1. The device is driven by polling, so it works with interrupts
+//! disabled:
+//!
+//! - output is registered as the console backend `hvc0`; every write is
+//!   copied into a DMA buffer and waited for;
+//! - input arrives in a ring of receive buffers that are collected and
+//!   handed back to the device whenever the console is read, through
+//!   [`console::try_read`] or `/dev/hvc0`.
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+#[cfg(feature = "fs")]
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use super::{Buffer, Transport, VirtQueue, VirtioError, DEVICE_CONSOLE};
+use crate::console::{self, ConsoleBackend};
+#[cfg(feature = "fs")]
+use crate::fs::devfs::{self, CharDevice};
+#[cfg(feature = "fs")]
+use crate::fs::FsError;
+use crate::mm::dma::{self, DmaBuffer};
+use crate::{pci, println};
+
+/// Feature bit: the configuration holds the size of the console.
+const F_SIZE: u64 = 1 << 0;
+
+/// Configuration: number of columns.
+const CONFIG_COLS: u16 = 0;
+/// Configuration: number of rows.
+const CONFIG_ROWS: u16 = 2;
+
+/// Index of the receive queue of port 0.
+const RECEIVE_QUEUE: u16 = 0;
+/// Index of the transmit queue of port 0.
+const TRANSMIT_QUEUE: u16 = 1;
+
+/// Largest queue size requested from the device.
+const QUEUE_SIZE: u16 = 16;
+
+/// Number of receive buffers kept with the device.
+const RX_BUFFERS: usize = 8;
+/// Size of each receive buffer.
+const RX_BUFFER_SIZE: usize = 64;
+
+/// Size of the transmit buffer; longer writes are split.
+const TX_BUFFER_SIZE: usize = 4096;
+
+/// The console, once found.
+static CONSOLE: OnceCell<VirtioConsole> = OnceCell::uninit();
+
+/// A virtio console device.
+struct VirtioConsole {
+    /// Registers of the device
+    transport: Box<dyn Transport>,
+    /// Queues and buffers
+    state: Mutex<State>,
+}
+
+/// Queues and buffers of the console.
+struct State {
+    /// Receive queue
+    rx: VirtQueue,
+    /// Transmit queue
+    tx: VirtQueue,
+    /// Memory of all receive buffers, back to back
+    rx_buffers: DmaBuffer,
+    /// Receive buffer index by descriptor token
+    rx_tokens: Vec<Option<usize>>,
+    /// Memory of the transmit buffer
+    tx_buffer: DmaBuffer,
+    /// Bytes received but not yet read
+    pending: VecDeque<u8>,
+}
+
+impl State {
+    /// Hands receive buffer `index` to the device.
+    fn post_rx(&mut self, index: usize) -> Result<(), VirtioError> {
+        let addr = self.rx_buffers.phys_addr() + (index * RX_BUFFER_SIZE) as u64;
+        let token = self
+            .rx
+            .add(&[Buffer::writable(addr, RX_BUFFER_SIZE as u32)])?;
+        self.rx_tokens[usize::from(token)] = Some(index);
+        Ok(())
+    }
+
+    /// Moves everything the device has received into `pending` and returns
+    /// the buffers to it.
+    fn collect_rx(&mut self, transport: &dyn Transport) {
+        let mut posted = false;
+        while let Some((token, len)) = self.rx.pop_used() {
+            let Some(index) = self.rx_tokens[usize::from(token)].take() else {
+                continue;
+            };
+            let len = (len as usize).min(RX_BUFFER_SIZE);
+            let data = &self.rx_buffers.as_slice()[index * RX_BUFFER_SIZE..][..len];
+            self.pending.extend(data);
+            posted |= self.post_rx(index).is_ok();
+        }
+        if posted {
+            self.rx.kick(transport);
+        }
+    }
+
+    /// Sends `bytes` and waits until the device has taken them.
+    fn send(&mut self, transport: &dyn Transport, bytes: &[u8]) {
+        for chunk in bytes.chunks(TX_BUFFER_SIZE) {
+            self.tx_buffer.as_mut_slice()[..chunk.len()].copy_from_slice(chunk);
+            let buffer = Buffer::readable(self.tx_buffer.phys_addr(), chunk.len() as u32);
+            if self.tx.add(&[buffer]).is_err() {
+                return;
+            }
+            self.tx.kick(transport);
+            while self.tx.pop_used().is_none() {
+                core::hint::spin_loop();
+            }
+        }
+    }
+}
+
+impl VirtioConsole {
+    /// Sets up the device behind `device`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`VirtioError`] if the transport, the queues, or the
+    /// buffers cannot be set up.
+    fn new(device: &pci::PciDevice) -> Result<Self, VirtioError> {
+        let transport = super::pci::transport(device)?;
+        let features = super::negotiate(&*transport, F_SIZE)?;
+        let rx = VirtQueue::new(&*transport, RECEIVE_QUEUE, QUEUE_SIZE)?;
+        let tx = VirtQueue::new(&*transport, TRANSMIT_QUEUE, QUEUE_SIZE)?;
+        let rx_buffers =
+            dma::alloc_coherent(RX_BUFFERS * RX_BUFFER_SIZE, 8).map_err(VirtioError::Dma)?;
+        let tx_buffer = dma::alloc_coherent(TX_BUFFER_SIZE, 8).map_err(VirtioError::Dma)?;
+
+        let mut state = State {
+            rx_tokens: alloc::vec![None; usize::from(rx.size())],
+            rx,
+            tx,
+            rx_buffers,
+            tx_buffer,
+            pending: VecDeque::new(),
+        };
+        for index in 0..RX_BUFFERS.min(usize::from(state.rx.size())) {
+            state.post_rx(index)?;
+        }
+        super::finish_init(&*transport);
+        state.rx.kick(&*transport);
+
+        if features & F_SIZE != 0 {
+            let mut cols = [0; 2];
+            let mut rows = [0; 2];
+            transport.read_config(CONFIG_COLS, &mut cols);
+            transport.read_config(CONFIG_ROWS, &mut rows);
+            println!(
+                "virtio-console: {}x{}",
+                u16::from_le_bytes(cols),
+                u16::from_le_bytes(rows)
+            );
+        }
+        Ok(VirtioConsole {
+            transport,
+            state: Mutex::new(state),
+        })
+    }
+
+    /// Sends `bytes` to the host.
+    fn write(&self, bytes: &[u8]) {
+        // `print!` may use the console from any thread with interrupts
+        // disabled, so the lock must not be held across a preemption.
+        interrupts::without_interrupts(|| self.state.lock().send(&*self.transport, bytes));
+    }
+
+    /// Returns the next byte from the host, or `None` if none is waiting.
+    fn try_read(&self) -> Option<u8> {
+        interrupts::without_interrupts(|| {
+            let mut state = self.state.lock();
+            if state.pending.is_empty() {
+                state.collect_rx(&*self.transport);
+            }
+            state.pending.pop_front()
+        })
+    }
+}
+
+impl ConsoleBackend for VirtioConsole {
+    fn name(&self) -> &'static str {
+        "hvc0"
+    }
+
+    fn write_str(&self, s: &str) {
+        self.write(s.as_bytes());
+    }
+
+    fn try_read(&self) -> Option<u8> {
+        VirtioConsole::try_read(self)
+    }
+
+    unsafe fn force_unlock(&self) {
+        self.state.force_unlock();
+    }
+}
+
+/// `/dev/hvc0`: raw bytes to and from the virtio console.
+#[cfg(feature = "fs")]
+struct Hvc;
+
+#[cfg(feature = "fs")]
+impl CharDevice for Hvc {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, FsError> {
+        let console = CONSOLE.get().ok_or(FsError::NotSupported)?;
+        let mut read = 0;
+        while read < buf.len() {
+            match console.try_read() {
+                Some(byte) => buf[read] = byte,
+                None => break,
+            }
+            read += 1;
+        }
+        Ok(read)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, FsError> {
+        let console = CONSOLE.get().ok_or(FsError::NotSupported)?;
+        console.write(buf);
+        Ok(buf.len())
+    }
+}
+
+/// Sets up the first virtio console, if there is one, and registers it as
+/// the console backend and, with the `fs` feature, the character device
+/// `hvc0`.
+///
+/// Must be called after PCI enumeration.
+pub fn init() {
+    let Some(device) = pci::devices()
+        .iter()
+        .find(|device| super::pci::device_type(device) == Some(DEVICE_CONSOLE))
+    else {
+        return;
+    };
+    match VirtioConsole::new(device) {
+        Ok(console) => {
+            CONSOLE.init_once(|| console);
+            console::register(CONSOLE.get().expect("virtio console not initialized"));
+            #[cfg(feature = "fs")]
+            devfs::register("hvc0", Arc::new(Hvc));
+            println!("virtio-console: hvc0 at {}", device.address);
+        }
+        Err(err) => println!("virtio-console: {} failed: {:?}", device.address, err),
+    }
+}
@@ -0,0 +1,183 @@
+//! # Virtio
+//!
+//! Shared machinery for virtio devices, the paravirtualized devices of QEMU
+//! and most hypervisors. A driver for a particular device class (block,
+//! network, entropy, ...) only deals with its own requests; this module
+//! provides the rest:
+//!
+//! - [`Transport`]: how the driver reaches the device's registers. Both
+//!   PCI transports are supported (see [`pci`]): the legacy one of virtio
+//!   0.9.5, which uses an I/O BAR, and the modern one of virtio 1.0, which
+//!   describes memory-mapped register blocks in vendor capabilities.
+//! - [`VirtQueue`]: a split virtqueue, the ring buffers through which
+//!   requests travel to the device and back.
+//! - [`negotiate`] and [`finish_init`]: the status handshake that resets
+//!   the device and agrees on features.
+//!
+//! ## Initialization
+//!
+//! ```ignore
+//! let transport = virtio::pci::transport(device)?;
+//! let features = virtio::negotiate(&*transport, SUPPORTED_FEATURES)?;
+//! let queue = VirtQueue::new(&*transport, 0, 128)?;
+//! virtio::finish_init(&*transport);
+//! ```
+
+pub mod console;
+pub mod pci;
+pub mod queue;
+pub mod rng;
+
+pub use queue::{Buffer, VirtQueue};
+
+use crate::mm::dma::DmaError;
+use crate::mm::mmio::MmioError;
+
+/// Device status: the driver has noticed the device.
+pub const STATUS_ACKNOWLEDGE: u8 = 1;
+/// Device status: the driver knows how to drive the device.
+pub const STATUS_DRIVER: u8 = 2;
+/// Device status: the driver is ready, the device may be used.
+pub const STATUS_DRIVER_OK: u8 = 4;
+/// Device status: feature negotiation is complete.
+pub const STATUS_FEATURES_OK: u8 = 8;
+/// Device status: the device hit an error and needs a reset.
+pub const STATUS_DEVICE_NEEDS_RESET: u8 = 64;
+/// Device status: the driver gave up on the device.
+pub const STATUS_FAILED: u8 = 128;
+
+/// Feature bit: descriptors may point to tables of descriptors.
+pub const F_INDIRECT_DESC: u64 = 1 << 28;
+/// Feature bit: the device complies with virtio 1.0 or later.
+pub const F_VERSION_1: u64 = 1 << 32;
+
+/// Device type of network cards.
+pub const DEVICE_NET: u16 = 1;
+/// Device type of block devices.
+pub const DEVICE_BLOCK: u16 = 2;
+/// Device type of consoles.
+pub const DEVICE_CONSOLE: u16 = 3;
+/// Device type of entropy sources.
+pub const DEVICE_ENTROPY: u16 = 4;
+
+/// Errors that can occur while setting up a virtio device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VirtioError {
+    /// The PCI function is not a virtio device
+    NotVirtio,
+    /// A BAR or capability the transport needs is missing or invalid
+    BadTransport,
+    /// Register windows could not be mapped
+    Mmio(MmioError),
+    /// The device rejected the negotiated features
+    FeaturesRejected,
+    /// The queue does not exist or is already in use
+    BadQueue,
+    /// Memory for the rings could not be allocated
+    Dma(DmaError),
+    /// The queue has too few free descriptors for the request
+    QueueFull,
+    /// The request has no buffers
+    EmptyRequest,
+}
+
+/// Access to the registers of a virtio device.
+///
+/// Implementations only translate register accesses; the protocol is
+/// driven by [`negotiate`], [`VirtQueue`], and the drivers.
+pub trait Transport: Send + Sync {
+    /// Virtio device type, e.g. [`DEVICE_BLOCK`].
+    fn device_type(&self) -> u16;
+
+    /// Returns `true` for a virtio 1.0 transport.
+    fn is_modern(&self) -> bool;
+
+    /// Features the device offers.
+    fn device_features(&self) -> u64;
+
+    /// Tells the device which features the driver uses.
+    fn set_driver_features(&self, features: u64);
+
+    /// Reads the device status register.
+    fn status(&self) -> u8;
+
+    /// Writes the device status register; writing zero resets the device.
+    fn set_status(&self, status: u8);
+
+    /// Largest size of queue `index`, or zero if the queue does not exist.
+    fn max_queue_size(&self, index: u16) -> u16;
+
+    /// Hands the rings of queue `index` to the device and enables it.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`VirtioError::BadQueue`] if the layout cannot be used with
+    /// this transport.
+    fn set_queue(&self, index: u16, layout: &queue::RingLayout) -> Result<(), VirtioError>;
+
+    /// Tells the device that queue `index` has new buffers.
+    fn notify(&self, index: u16);
+
+    /// Reads and thereby clears the interrupt status; bit 0 means a queue
+    /// was used, bit 1 that the configuration changed.
+    fn ack_interrupt(&self) -> u8;
+
+    /// Reads the device-specific configuration at `offset` into `buf`.
+    fn read_config(&self, offset: u16, buf: &mut [u8]);
+
+    /// Writes `buf` to the device-specific configuration at `offset`.
+    fn write_config(&self, offset: u16, buf: &[u8]);
+}
+
+/// Resets the device and negotiates features.
+///
+/// Afterwards the driver sets up its queues and calls [`finish_init`].
+/// Modern transports always negotiate [`F_VERSION_1`].
+///
+/// # Arguments
+///
+/// * `transport` - The device
+/// * `supported` - Features the driver can use
+///
+/// # Returns
+///
+/// The features both sides use.
+///
+/// # Errors
+///
+/// Returns [`VirtioError::FeaturesRejected`] if the device does not accept
+/// the features; the device is marked as failed.
+pub fn negotiate(transport: &dyn Transport, supported: u64) -> Result<u64, VirtioError> {
+    transport.set_status(0);
+    // A modern device may take a moment to finish the reset.
+    while transport.status() != 0 {
+        core::hint::spin_loop();
+    }
+    let mut status = STATUS_ACKNOWLEDGE;
+    transport.set_status(status);
+    status |= STATUS_DRIVER;
+    transport.set_status(status);
+
+    let mut supported = supported;
+    if transport.is_modern() {
+        supported |= F_VERSION_1;
+    }
+    let features = transport.device_features() & supported;
+    transport.set_driver_features(features);
+
+    // Legacy devices have no FEATURES_OK handshake.
+    if transport.is_modern() {
+        status |= STATUS_FEATURES_OK;
+        transport.set_status(status);
+        if transport.status() & STATUS_FEATURES_OK == 0 || features & F_VERSION_1 == 0 {
+            transport.set_status(status | STATUS_FAILED);
+            return Err(VirtioError::FeaturesRejected);
+        }
+    }
+    Ok(features)
+}
+
+/// Tells the device that the driver is ready, after its queues are set up.
+pub fn finish_init(transport: &dyn Transport) {
+    transport.set_status(transport.status() | STATUS_DRIVER_OK);
+}
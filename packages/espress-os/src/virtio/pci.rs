@@ -0,0 +1,413 @@
+//! # Virtio over PCI
+//!
+//! Virtio devices have vendor ID `0x1af4`. Two PCI transports exist:
+//!
+//! - **Legacy** (device IDs `0x1000`-`0x103f`, the device type is in the
+//!   subsystem ID): every register is in the I/O window of BAR 0, queues
+//!   are given as a single page frame number, and features are 32 bits.
+//! - **Modern** (device IDs from `0x1040`, device type = ID - `0x1040`):
+//!   vendor-specific capabilities point at register blocks inside memory
+//!   BARs: common configuration, notification, interrupt status, and
+//!   device-specific configuration.
+//!
+//! Transitional devices offer both; [`transport`] prefers the modern one.
+
+use alloc::boxed::Box;
+use x86_64::instructions::port::{Port, PortRead, PortWrite};
+use x86_64::VirtAddr;
+
+use super::queue::RingLayout;
+use super::{Transport, VirtioError};
+use crate::mm::mmio;
+use crate::pci::bar::{Bar, COMMAND_BUS_MASTER, COMMAND_IO, COMMAND_MEMORY};
+use crate::pci::capability::VENDOR_SPECIFIC;
+use crate::pci::{PciDevice, SUBSYSTEM_ID};
+
+/// PCI vendor ID of virtio devices.
+pub const VIRTIO_VENDOR: u16 = 0x1af4;
+
+/// First device ID of transitional (legacy-capable) devices.
+const LEGACY_DEVICE_FIRST: u16 = 0x1000;
+/// Last device ID of transitional devices.
+const LEGACY_DEVICE_LAST: u16 = 0x103f;
+/// Device ID of modern devices of type zero.
+const MODERN_DEVICE_BASE: u16 = 0x1040;
+
+/// Legacy register: device features.
+const LEGACY_DEVICE_FEATURES: u16 = 0x00;
+/// Legacy register: driver features.
+const LEGACY_DRIVER_FEATURES: u16 = 0x04;
+/// Legacy register: page frame number of the selected queue.
+const LEGACY_QUEUE_PFN: u16 = 0x08;
+/// Legacy register: size of the selected queue.
+const LEGACY_QUEUE_SIZE: u16 = 0x0c;
+/// Legacy register: queue selector.
+const LEGACY_QUEUE_SELECT: u16 = 0x0e;
+/// Legacy register: queue notification.
+const LEGACY_QUEUE_NOTIFY: u16 = 0x10;
+/// Legacy register: device status.
+const LEGACY_STATUS: u16 = 0x12;
+/// Legacy register: interrupt status.
+const LEGACY_ISR: u16 = 0x13;
+/// Start of the legacy device configuration, as long as MSI-X is off.
+const LEGACY_CONFIG: u16 = 0x14;
+
+/// Modern capability type: common configuration.
+const CAP_COMMON: u8 = 1;
+/// Modern capability type: notification area.
+const CAP_NOTIFY: u8 = 2;
+/// Modern capability type: interrupt status.
+const CAP_ISR: u8 = 3;
+/// Modern capability type: device-specific configuration.
+const CAP_DEVICE: u8 = 4;
+
+/// Common configuration: device feature word selector.
+const COMMON_DEVICE_FEATURE_SELECT: u64 = 0x00;
+/// Common configuration: selected device feature word.
+const COMMON_DEVICE_FEATURE: u64 = 0x04;
+/// Common configuration: driver feature word selector.
+const COMMON_DRIVER_FEATURE_SELECT: u64 = 0x08;
+/// Common configuration: selected driver feature word.
+const COMMON_DRIVER_FEATURE: u64 = 0x0c;
+/// Common configuration: number of queues.
+const COMMON_NUM_QUEUES: u64 = 0x12;
+/// Common configuration: device status.
+const COMMON_STATUS: u64 = 0x14;
+/// Common configuration: queue selector.
+const COMMON_QUEUE_SELECT: u64 = 0x16;
+/// Common configuration: size of the selected queue.
+const COMMON_QUEUE_SIZE: u64 = 0x18;
+/// Common configuration: enable flag of the selected queue.
+const COMMON_QUEUE_ENABLE: u64 = 0x1c;
+/// Common configuration: notification offset of the selected queue.
+const COMMON_QUEUE_NOTIFY_OFF: u64 = 0x1e;
+/// Common configuration: descriptor table of the selected queue.
+const COMMON_QUEUE_DESC: u64 = 0x20;
+/// Common configuration: available ring of the selected queue.
+const COMMON_QUEUE_DRIVER: u64 = 0x28;
+/// Common configuration: used ring of the selected queue.
+const COMMON_QUEUE_DEVICE: u64 = 0x30;
+
+/// Returns the virtio device type of a PCI function.
+///
+/// # Returns
+///
+/// `None` if the function is not a virtio device.
+pub fn device_type(device: &PciDevice) -> Option<u16> {
+    if device.vendor_id != VIRTIO_VENDOR {
+        return None;
+    }
+    match device.device_id {
+        LEGACY_DEVICE_FIRST..=LEGACY_DEVICE_LAST => Some(device.address.read_u16(SUBSYSTEM_ID)),
+        id if id >= MODERN_DEVICE_BASE => Some(id - MODERN_DEVICE_BASE),
+        _ => None,
+    }
+}
+
+/// Sets up the transport of a virtio PCI function, preferring the modern
+/// interface, and enables DMA for the function.
+///
+/// # Errors
+///
+/// Returns a [`VirtioError`] if the function is not a virtio device or its
+/// registers cannot be reached.
+pub fn transport(device: &PciDevice) -> Result<Box<dyn Transport>, VirtioError> {
+    let device_type = device_type(device).ok_or(VirtioError::NotVirtio)?;
+    let transport: Box<dyn Transport> = match ModernTransport::new(device, device_type) {
+        Ok(modern) => Box::new(modern),
+        Err(_) if device.device_id <= LEGACY_DEVICE_LAST => {
+            Box::new(LegacyTransport::new(device, device_type)?)
+        }
+        Err(err) => return Err(err),
+    };
+    device.enable(COMMAND_BUS_MASTER);
+    Ok(transport)
+}
+
+/// The legacy transport, using the I/O window of BAR 0.
+pub struct LegacyTransport {
+    /// First port of the register window
+    base: u16,
+    /// Virtio device type
+    device_type: u16,
+}
+
+impl LegacyTransport {
+    /// Uses BAR 0 of `device` as the register window.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`VirtioError::BadTransport`] if BAR 0 is not an I/O window.
+    pub fn new(device: &PciDevice, device_type: u16) -> Result<Self, VirtioError> {
+        let Some(Bar::Io { port, .. }) = device.bar(0) else {
+            return Err(VirtioError::BadTransport);
+        };
+        device.enable(COMMAND_IO);
+        Ok(LegacyTransport {
+            base: port,
+            device_type,
+        })
+    }
+
+    /// Reads the register at `offset`.
+    fn read<T: PortRead>(&self, offset: u16) -> T {
+        unsafe { Port::new(self.base + offset).read() }
+    }
+
+    /// Writes the register at `offset`.
+    fn write<T: PortWrite>(&self, offset: u16, value: T) {
+        unsafe { Port::new(self.base + offset).write(value) };
+    }
+}
+
+impl Transport for LegacyTransport {
+    fn device_type(&self) -> u16 {
+        self.device_type
+    }
+
+    fn is_modern(&self) -> bool {
+        false
+    }
+
+    fn device_features(&self) -> u64 {
+        u64::from(self.read::<u32>(LEGACY_DEVICE_FEATURES))
+    }
+
+    fn set_driver_features(&self, features: u64) {
+        self.write(LEGACY_DRIVER_FEATURES, features as u32);
+    }
+
+    fn status(&self) -> u8 {
+        self.read(LEGACY_STATUS)
+    }
+
+    fn set_status(&self, status: u8) {
+        self.write(LEGACY_STATUS, status);
+    }
+
+    fn max_queue_size(&self, index: u16) -> u16 {
+        self.write(LEGACY_QUEUE_SELECT, index);
+        self.read(LEGACY_QUEUE_SIZE)
+    }
+
+    fn set_queue(&self, index: u16, layout: &RingLayout) -> Result<(), VirtioError> {
+        let pfn = layout.desc.as_u64() >> 12;
+        if !layout.is_legacy() || layout.size != self.max_queue_size(index) || pfn > 0xffff_ffff {
+            return Err(VirtioError::BadQueue);
+        }
+        self.write(LEGACY_QUEUE_SELECT, index);
+        if self.read::<u32>(LEGACY_QUEUE_PFN) != 0 {
+            return Err(VirtioError::BadQueue);
+        }
+        self.write(LEGACY_QUEUE_PFN, pfn as u32);
+        Ok(())
+    }
+
+    fn notify(&self, index: u16) {
+        self.write(LEGACY_QUEUE_NOTIFY, index);
+    }
+
+    fn ack_interrupt(&self) -> u8 {
+        self.read(LEGACY_ISR)
+    }
+
+    fn read_config(&self, offset: u16, buf: &mut [u8]) {
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = self.read(LEGACY_CONFIG + offset + i as u16);
+        }
+    }
+
+    fn write_config(&self, offset: u16, buf: &[u8]) {
+        for (i, &byte) in buf.iter().enumerate() {
+            self.write(LEGACY_CONFIG + offset + i as u16, byte);
+        }
+    }
+}
+
+/// The modern transport, using register blocks found through vendor
+/// capabilities.
+pub struct ModernTransport {
+    /// Common configuration block
+    common: VirtAddr,
+    /// Start of the notification area
+    notify: VirtAddr,
+    /// Bytes between the notification addresses of consecutive offsets
+    notify_multiplier: u32,
+    /// Interrupt status register
+    isr: VirtAddr,
+    /// Device-specific configuration, if the device has one
+    device: Option<VirtAddr>,
+    /// Virtio device type
+    device_type: u16,
+}
+
+impl ModernTransport {
+    /// Finds and maps the register blocks of `device`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`VirtioError::BadTransport`] if a required block is missing
+    /// and [`VirtioError::Mmio`] if one cannot be mapped.
+    pub fn new(device: &PciDevice, device_type: u16) -> Result<Self, VirtioError> {
+        let mut common = None;
+        let mut notify = None;
+        let mut isr = None;
+        let mut config = None;
+        let mut notify_multiplier = 0;
+        for capability in device.capabilities() {
+            if capability.id != VENDOR_SPECIFIC {
+                continue;
+            }
+            let cfg_type = device.address.read_u8(capability.offset + 3);
+            let slot = match cfg_type {
+                CAP_COMMON => &mut common,
+                CAP_NOTIFY => &mut notify,
+                CAP_ISR => &mut isr,
+                CAP_DEVICE => &mut config,
+                _ => continue,
+            };
+            // The first capability of each type is the preferred one.
+            if slot.is_some() {
+                continue;
+            }
+            let bar = device.address.read_u8(capability.offset + 4);
+            let offset = device.address.read_u32(capability.offset + 8);
+            let length = device.address.read_u32(capability.offset + 12);
+            *slot = Some((bar, offset, length));
+            if cfg_type == CAP_NOTIFY {
+                notify_multiplier = device.address.read_u32(capability.offset + 16);
+            }
+        }
+
+        let map = |block: Option<(u8, u32, u32)>| -> Result<VirtAddr, VirtioError> {
+            let (bar, offset, length) = block.ok_or(VirtioError::BadTransport)?;
+            let Some(Bar::Memory { address, size, .. }) = device.bar(bar) else {
+                return Err(VirtioError::BadTransport);
+            };
+            if u64::from(offset) + u64::from(length) > size {
+                return Err(VirtioError::BadTransport);
+            }
+            mmio::map(address + u64::from(offset), u64::from(length)).map_err(VirtioError::Mmio)
+        };
+        let transport = ModernTransport {
+            common: map(common)?,
+            notify: map(notify)?,
+            notify_multiplier,
+            isr: map(isr)?,
+            device: config.map(|block| map(Some(block))).transpose()?,
+            device_type,
+        };
+        device.enable(COMMAND_MEMORY);
+        Ok(transport)
+    }
+
+    /// Reads the common configuration register at `offset`.
+    fn read<T>(&self, offset: u64) -> T {
+        unsafe { (self.common + offset).as_ptr::<T>().read_volatile() }
+    }
+
+    /// Writes the common configuration register at `offset`.
+    fn write<T>(&self, offset: u64, value: T) {
+        unsafe {
+            (self.common + offset)
+                .as_mut_ptr::<T>()
+                .write_volatile(value)
+        };
+    }
+
+    /// Writes a 64-bit common configuration register as two 32-bit halves,
+    /// the access size devices must support.
+    fn write_u64(&self, offset: u64, value: u64) {
+        self.write(offset, value as u32);
+        self.write(offset + 4, (value >> 32) as u32);
+    }
+}
+
+impl Transport for ModernTransport {
+    fn device_type(&self) -> u16 {
+        self.device_type
+    }
+
+    fn is_modern(&self) -> bool {
+        true
+    }
+
+    fn device_features(&self) -> u64 {
+        self.write(COMMON_DEVICE_FEATURE_SELECT, 0u32);
+        let low = self.read::<u32>(COMMON_DEVICE_FEATURE);
+        self.write(COMMON_DEVICE_FEATURE_SELECT, 1u32);
+        let high = self.read::<u32>(COMMON_DEVICE_FEATURE);
+        (u64::from(high) << 32) | u64::from(low)
+    }
+
+    fn set_driver_features(&self, features: u64) {
+        self.write(COMMON_DRIVER_FEATURE_SELECT, 0u32);
+        self.write(COMMON_DRIVER_FEATURE, features as u32);
+        self.write(COMMON_DRIVER_FEATURE_SELECT, 1u32);
+        self.write(COMMON_DRIVER_FEATURE, (features >> 32) as u32);
+    }
+
+    fn status(&self) -> u8 {
+        self.read(COMMON_STATUS)
+    }
+
+    fn set_status(&self, status: u8) {
+        self.write(COMMON_STATUS, status);
+    }
+
+    fn max_queue_size(&self, index: u16) -> u16 {
+        if index >= self.read::<u16>(COMMON_NUM_QUEUES) {
+            return 0;
+        }
+        self.write(COMMON_QUEUE_SELECT, index);
+        self.read(COMMON_QUEUE_SIZE)
+    }
+
+    fn set_queue(&self, index: u16, layout: &RingLayout) -> Result<(), VirtioError> {
+        let max = self.max_queue_size(index);
+        if layout.size == 0 || layout.size > max {
+            return Err(VirtioError::BadQueue);
+        }
+        if self.read::<u16>(COMMON_QUEUE_ENABLE) != 0 {
+            return Err(VirtioError::BadQueue);
+        }
+        self.write(COMMON_QUEUE_SIZE, layout.size);
+        self.write_u64(COMMON_QUEUE_DESC, layout.desc.as_u64());
+        self.write_u64(COMMON_QUEUE_DRIVER, layout.avail.as_u64());
+        self.write_u64(COMMON_QUEUE_DEVICE, layout.used.as_u64());
+        self.write(COMMON_QUEUE_ENABLE, 1u16);
+        Ok(())
+    }
+
+    fn notify(&self, index: u16) {
+        self.write(COMMON_QUEUE_SELECT, index);
+        let offset = self.read::<u16>(COMMON_QUEUE_NOTIFY_OFF);
+        let addr = self.notify + u64::from(offset) * u64::from(self.notify_multiplier);
+        unsafe { addr.as_mut_ptr::<u16>().write_volatile(index) };
+    }
+
+    fn ack_interrupt(&self) -> u8 {
+        unsafe { self.isr.as_ptr::<u8>().read_volatile() }
+    }
+
+    fn read_config(&self, offset: u16, buf: &mut [u8]) {
+        let Some(config) = self.device else {
+            buf.fill(0);
+            return;
+        };
+        for (i, byte) in buf.iter_mut().enumerate() {
+            let addr = config + u64::from(offset) + i as u64;
+            *byte = unsafe { addr.as_ptr::<u8>().read_volatile() };
+        }
+    }
+
+    fn write_config(&self, offset: u16, buf: &[u8]) {
+        let Some(config) = self.device else {
+            return;
+        };
+        for (i, &byte) in buf.iter().enumerate() {
+            let addr = config + u64::from(offset) + i as u64;
+            unsafe { addr.as_mut_ptr::<u8>().write_volatile(byte) };
+        }
+    }
+}
@@ -0,0 +1,354 @@
+//! # Split Virtqueues
+//!
+//! A split virtqueue consists of three rings in memory shared with the
+//! device:
+//!
+//! - the **descriptor table**, where each entry points at one buffer and
+//!   may chain to a next entry, so one request can span several buffers;
+//! - the **available ring**, where the driver publishes the heads of the
+//!   chains it hands to the device;
+//! - the **used ring**, where the device returns the heads of finished
+//!   chains together with the number of bytes it wrote.
+//!
+//! All three live in one physically contiguous DMA buffer laid out the way
+//! the legacy interface demands (used ring on the next page boundary after
+//! the available ring), which satisfies the modern interface as well.
+//! Each index is a free-running 16-bit counter; ring positions are taken
+//! modulo the queue size, which is a power of two.
+
+use core::sync::atomic::{fence, Ordering};
+use x86_64::PhysAddr;
+
+use super::{Transport, VirtioError};
+use crate::mm::dma::{self, DmaBuffer};
+
+/// Descriptor flag: the chain continues at `next`.
+const DESC_F_NEXT: u16 = 1;
+/// Descriptor flag: the device writes to the buffer.
+const DESC_F_WRITE: u16 = 2;
+
+/// Used ring flag: the device does not need notifications.
+const USED_F_NO_NOTIFY: u16 = 1;
+
+/// Alignment of the used ring in the legacy layout.
+const LEGACY_ALIGN: usize = 4096;
+
+/// Largest queue size the specification allows.
+pub const MAX_QUEUE_SIZE: u16 = 32768;
+
+/// Size of a descriptor table entry.
+const DESCRIPTOR_SIZE: usize = 16;
+
+/// Size of a used ring element.
+const USED_ELEM_SIZE: usize = 8;
+
+/// One buffer of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Buffer {
+    /// Physical address of the buffer
+    pub addr: PhysAddr,
+    /// Length in bytes
+    pub len: u32,
+    /// The device writes the buffer (a response) rather than reading it
+    pub device_writable: bool,
+}
+
+impl Buffer {
+    /// A buffer the device reads.
+    pub fn readable(addr: PhysAddr, len: u32) -> Self {
+        Buffer {
+            addr,
+            len,
+            device_writable: false,
+        }
+    }
+
+    /// A buffer the device writes.
+    pub fn writable(addr: PhysAddr, len: u32) -> Self {
+        Buffer {
+            addr,
+            len,
+            device_writable: true,
+        }
+    }
+}
+
+/// Where the rings of a queue are, as handed to the transport.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RingLayout {
+    /// Number of descriptors
+    pub size: u16,
+    /// Physical address of the descriptor table
+    pub desc: PhysAddr,
+    /// Physical address of the available ring
+    pub avail: PhysAddr,
+    /// Physical address of the used ring
+    pub used: PhysAddr,
+}
+
+impl RingLayout {
+    /// Returns `true` if the rings are arranged as the legacy interface
+    /// expects, which then needs only the address of the table.
+    pub fn is_legacy(&self) -> bool {
+        let (avail_offset, used_offset, _) = offsets(self.size);
+        self.desc.is_aligned(LEGACY_ALIGN as u64)
+            && self.avail == self.desc + avail_offset as u64
+            && self.used == self.desc + used_offset as u64
+    }
+}
+
+/// A split virtqueue.
+///
+/// Requests are added with [`add`](Self::add) and handed to the device with
+/// [`kick`](Self::kick); finished ones are collected with
+/// [`pop_used`](Self::pop_used). The buffers of a request must stay valid
+/// until the device has returned it.
+pub struct VirtQueue {
+    /// Queue index within the device
+    index: u16,
+    /// Ring memory
+    memory: DmaBuffer,
+    /// Where the rings are
+    layout: RingLayout,
+    /// Offset of the available ring in `memory`
+    avail_offset: usize,
+    /// Offset of the used ring in `memory`
+    used_offset: usize,
+    /// First descriptor of the free list
+    free_head: u16,
+    /// Number of descriptors on the free list
+    num_free: u16,
+    /// Next available ring index to publish
+    avail_idx: u16,
+    /// Next used ring index to collect
+    last_used: u16,
+}
+
+impl VirtQueue {
+    /// Allocates the rings of queue `index` and hands them to the device.
+    ///
+    /// Modern devices get the largest power of two up to `max_size` the
+    /// device supports; legacy devices dictate their queue size.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`VirtioError::BadQueue`] if the device has no such queue or
+    /// rejects it, and [`VirtioError::Dma`] if the rings cannot be
+    /// allocated.
+    pub fn new(transport: &dyn Transport, index: u16, max_size: u16) -> Result<Self, VirtioError> {
+        let device_max = transport.max_queue_size(index).min(MAX_QUEUE_SIZE);
+        let size = if transport.is_modern() {
+            let size = device_max.min(max_size);
+            match size {
+                0 => 0,
+                size => 1 << (15 - size.leading_zeros()),
+            }
+        } else {
+            device_max
+        };
+        if size == 0 || !size.is_power_of_two() {
+            return Err(VirtioError::BadQueue);
+        }
+
+        let (avail_offset, used_offset, total) = offsets(size);
+        let memory = dma::alloc_coherent(total, LEGACY_ALIGN).map_err(VirtioError::Dma)?;
+        let desc = memory.phys_addr();
+        let layout = RingLayout {
+            size,
+            desc,
+            avail: desc + avail_offset as u64,
+            used: desc + used_offset as u64,
+        };
+        let mut queue = VirtQueue {
+            index,
+            memory,
+            layout,
+            avail_offset,
+            used_offset,
+            free_head: 0,
+            num_free: size,
+            avail_idx: 0,
+            last_used: 0,
+        };
+        // Chain every descriptor into the free list.
+        for i in 0..size {
+            queue.write_desc(i, 0, 0, 0, i.wrapping_add(1) % size);
+        }
+        transport.set_queue(index, &layout)?;
+        Ok(queue)
+    }
+
+    /// Queue index within the device.
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    /// Number of descriptors.
+    pub fn size(&self) -> u16 {
+        self.layout.size
+    }
+
+    /// Number of descriptors not in use, i.e. the most buffers the next
+    /// request may have.
+    pub fn num_free(&self) -> u16 {
+        self.num_free
+    }
+
+    /// Adds a request consisting of `buffers` to the available ring.
+    ///
+    /// By convention the buffers the device reads come before the ones it
+    /// writes. The device only sees the request after [`kick`](Self::kick).
+    ///
+    /// # Returns
+    ///
+    /// The token [`pop_used`](Self::pop_used) reports when the request is
+    /// done.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`VirtioError::EmptyRequest`] without buffers and
+    /// [`VirtioError::QueueFull`] if too few descriptors are free.
+    pub fn add(&mut self, buffers: &[Buffer]) -> Result<u16, VirtioError> {
+        if buffers.is_empty() {
+            return Err(VirtioError::EmptyRequest);
+        }
+        if buffers.len() > usize::from(self.num_free) {
+            return Err(VirtioError::QueueFull);
+        }
+
+        let head = self.free_head;
+        let mut index = head;
+        for (i, buffer) in buffers.iter().enumerate() {
+            let next = self.desc_next(index);
+            let mut flags = if buffer.device_writable {
+                DESC_F_WRITE
+            } else {
+                0
+            };
+            if i + 1 < buffers.len() {
+                flags |= DESC_F_NEXT;
+            }
+            self.write_desc(index, buffer.addr.as_u64(), buffer.len, flags, next);
+            if i + 1 < buffers.len() {
+                index = next;
+            } else {
+                self.free_head = next;
+            }
+        }
+        self.num_free -= buffers.len() as u16;
+
+        let size = self.layout.size;
+        let slot = self.avail_offset + 4 + 2 * usize::from(self.avail_idx % size);
+        unsafe { self.ptr::<u16>(slot).write_volatile(head) };
+        self.avail_idx = self.avail_idx.wrapping_add(1);
+        // The descriptors and ring entry must be visible before the index.
+        fence(Ordering::SeqCst);
+        unsafe {
+            self.ptr::<u16>(self.avail_offset + 2)
+                .write_volatile(self.avail_idx)
+        };
+        Ok(head)
+    }
+
+    /// Notifies the device of new requests, unless it asked not to be.
+    pub fn kick(&self, transport: &dyn Transport) {
+        // The index update must be visible before the flags are checked.
+        fence(Ordering::SeqCst);
+        let flags = unsafe { self.ptr::<u16>(self.used_offset).read_volatile() };
+        if flags & USED_F_NO_NOTIFY == 0 {
+            transport.notify(self.index);
+        }
+    }
+
+    /// Returns `true` if the device has returned a request not yet
+    /// collected.
+    pub fn has_used(&self) -> bool {
+        self.used_idx() != self.last_used
+    }
+
+    /// Collects a request the device has finished and frees its
+    /// descriptors.
+    ///
+    /// # Returns
+    ///
+    /// The token from [`add`](Self::add) and the number of bytes the device
+    /// wrote, or `None` if no request is finished.
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        if !self.has_used() {
+            return None;
+        }
+        // The element must not be read before the index.
+        fence(Ordering::SeqCst);
+        let size = self.layout.size;
+        let elem = self.used_offset + 4 + USED_ELEM_SIZE * usize::from(self.last_used % size);
+        let (id, len) = unsafe {
+            (
+                self.ptr::<u32>(elem).read_volatile(),
+                self.ptr::<u32>(elem + 4).read_volatile(),
+            )
+        };
+        self.last_used = self.last_used.wrapping_add(1);
+
+        let head = id as u16;
+        let mut tail = head;
+        self.num_free += 1;
+        while self.desc_flags(tail) & DESC_F_NEXT != 0 {
+            tail = self.desc_next(tail);
+            self.num_free += 1;
+        }
+        self.write_desc(tail, 0, 0, 0, self.free_head);
+        self.free_head = head;
+        Some((head, len))
+    }
+
+    /// Index of the next used ring entry the device will write.
+    fn used_idx(&self) -> u16 {
+        unsafe { self.ptr::<u16>(self.used_offset + 2).read_volatile() }
+    }
+
+    /// Writes descriptor `index`.
+    fn write_desc(&mut self, index: u16, addr: u64, len: u32, flags: u16, next: u16) {
+        let offset = DESCRIPTOR_SIZE * usize::from(index);
+        unsafe {
+            self.ptr::<u64>(offset).write_volatile(addr);
+            self.ptr::<u32>(offset + 8).write_volatile(len);
+            self.ptr::<u16>(offset + 12).write_volatile(flags);
+            self.ptr::<u16>(offset + 14).write_volatile(next);
+        }
+    }
+
+    /// Flags of descriptor `index`.
+    fn desc_flags(&self, index: u16) -> u16 {
+        unsafe {
+            self.ptr::<u16>(DESCRIPTOR_SIZE * usize::from(index) + 12)
+                .read_volatile()
+        }
+    }
+
+    /// Successor of descriptor `index` in its chain or the free list.
+    fn desc_next(&self, index: u16) -> u16 {
+        unsafe {
+            self.ptr::<u16>(DESCRIPTOR_SIZE * usize::from(index) + 14)
+                .read_volatile()
+        }
+    }
+
+    /// Pointer to the ring memory at `offset`.
+    fn ptr<T>(&self, offset: usize) -> *mut T {
+        debug_assert!(offset + size_of::<T>() <= self.memory.len());
+        (self.memory.virt_addr() + offset as u64).as_mut_ptr()
+    }
+}
+
+/// Returns the offsets of the available and used rings and the total size
+/// of the rings of a queue of `size` descriptors, in the legacy layout.
+fn offsets(size: u16) -> (usize, usize, usize) {
+    let size = usize::from(size);
+    let avail_offset = DESCRIPTOR_SIZE * size;
+    // flags, idx, ring, used_event
+    let avail_end = avail_offset + 2 * (3 + size);
+    let used_offset = avail_end.next_multiple_of(LEGACY_ALIGN);
+    // flags, idx, ring, avail_event
+    let used_end = used_offset + 2 * 3 + USED_ELEM_SIZE * size;
+    (avail_offset, used_offset, used_end)
+}
@@ -0,0 +1,119 @@
+//! # Virtio Entropy Source
+//!
+//! Driver for the virtio entropy device (`virtio-rng`), through which the
+//! host hands out random bytes from its own generator. In a VM this is
+//! often the only source the guest can trust: RDRAND may be masked by the
+//! hypervisor, and interrupt timings of emulated devices are predictable.
+//!
+//! The device has a single queue into which the driver posts buffers that
+//! the device fills. The bytes are mixed into the kernel generator with
+//! [`rand::add_entropy`]: once while booting, then every
+//! [`REFILL_INTERVAL`] by the `virtio-rng` thread.
+
+use alloc::boxed::Box;
+use core::time::Duration;
+
+use super::{Buffer, Transport, VirtQueue, VirtioError, DEVICE_ENTROPY};
+use crate::mm::dma::{self, DmaBuffer};
+use crate::{pci, println, rand, scheduler, timer};
+
+/// Index of the request queue.
+const REQUEST_QUEUE: u16 = 0;
+
+/// Largest queue size requested from the device.
+const QUEUE_SIZE: u16 = 4;
+
+/// Bytes requested at a time.
+const REQUEST_SIZE: usize = 64;
+
+/// Time between two refills of the generator.
+pub const REFILL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A virtio entropy device.
+struct VirtioRng {
+    /// Registers of the device
+    transport: Box<dyn Transport>,
+    /// Request queue
+    queue: VirtQueue,
+    /// Buffer the device fills
+    buffer: DmaBuffer,
+}
+
+impl VirtioRng {
+    /// Sets up the device behind `device`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`VirtioError`] if the transport, the queue, or the buffer
+    /// cannot be set up.
+    fn new(device: &pci::PciDevice) -> Result<Self, VirtioError> {
+        let transport = super::pci::transport(device)?;
+        super::negotiate(&*transport, 0)?;
+        let queue = VirtQueue::new(&*transport, REQUEST_QUEUE, QUEUE_SIZE)?;
+        let buffer = dma::alloc_coherent(REQUEST_SIZE, 8).map_err(VirtioError::Dma)?;
+        super::finish_init(&*transport);
+        Ok(VirtioRng {
+            transport,
+            queue,
+            buffer,
+        })
+    }
+
+    /// Asks the device for random bytes and mixes them into the
+    /// generator, yielding the CPU while the device works.
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes the device provided.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`VirtioError`] if the request cannot be queued.
+    fn refill(&mut self) -> Result<usize, VirtioError> {
+        let buffer = Buffer::writable(self.buffer.phys_addr(), REQUEST_SIZE as u32);
+        self.queue.add(&[buffer])?;
+        self.queue.kick(&*self.transport);
+        let len = loop {
+            if let Some((_, len)) = self.queue.pop_used() {
+                break (len as usize).min(REQUEST_SIZE);
+            }
+            scheduler::yield_now();
+        };
+        rand::add_entropy(&self.buffer.as_slice()[..len]);
+        self.buffer.as_mut_slice().fill(0);
+        Ok(len)
+    }
+}
+
+/// Sets up the first virtio entropy device, if there is one, mixes its
+/// first bytes into the generator, and starts the `virtio-rng` thread.
+///
+/// Must be called after the scheduler is initialized.
+pub fn init() {
+    let Some(device) = pci::devices()
+        .iter()
+        .find(|device| super::pci::device_type(device) == Some(DEVICE_ENTROPY))
+    else {
+        return;
+    };
+    let mut rng = match VirtioRng::new(device) {
+        Ok(rng) => rng,
+        Err(err) => {
+            println!("virtio-rng: {} failed: {:?}", device.address, err);
+            return;
+        }
+    };
+    match rng.refill() {
+        Ok(len) => println!("virtio-rng: {}, {} bytes mixed in", device.address, len),
+        Err(err) => println!("virtio-rng: {} failed: {:?}", device.address, err),
+    }
+    let spawned = scheduler::spawn_named("virtio-rng", move || loop {
+        timer::sleep(REFILL_INTERVAL);
+        if let Err(err) = rng.refill() {
+            println!("virtio-rng: refill failed: {:?}", err);
+        }
+    });
+    if spawned.is_err() {
+        println!("virtio-rng: failed to start the refill thread");
+    }
+}
@@ -0,0 +1,146 @@
+//! # Workqueue
+//!
+//! Defers work from interrupt handlers to thread context. A handler does
+//! the minimum with the hardware, queues the rest with [`schedule`], and
+//! returns; the `workqueue` thread later runs it with interrupts enabled and
+//! no locks held, where it may allocate, take sleeping locks, or block.
+//!
+//! Interrupt handlers must neither allocate nor wait for a lock, so what
+//! they queue is a statically allocated [`Work`] item. Queuing an item that
+//! is already pending does nothing: like an interrupt, several requests
+//! before the work runs are served by one run. Thread context may also
+//! queue closures with [`schedule_fn`].
+//!
+//! ```ignore
+//! static FLUSH: Work = Work::new(flush_buffers);
+//!
+//! extern "x86-interrupt" fn handler(_frame: InterruptStackFrame) {
+//!     // ... acknowledge the device ...
+//!     workqueue::schedule(&FLUSH);
+//! }
+//! ```
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use conquer_once::spin::OnceCell;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use crossbeam_queue::ArrayQueue;
+use spin::Mutex;
+
+use crate::scheduler::{self, Priority, WaitQueue};
+
+/// Number of distinct [`Work`] items that can be pending at once.
+pub const WORK_QUEUE_CAPACITY: usize = 256;
+
+/// Pending work items queued by [`schedule`].
+static ITEMS: OnceCell<ArrayQueue<&'static Work>> = OnceCell::uninit();
+
+/// Pending closures queued by [`schedule_fn`].
+static CLOSURES: Mutex<VecDeque<Box<dyn FnOnce() + Send>>> = Mutex::new(VecDeque::new());
+
+/// Where the worker waits for something to do.
+static PENDING: WaitQueue = WaitQueue::new();
+
+/// Work items dropped because the queue was full.
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// A function to run in thread context, queued with [`schedule`].
+pub struct Work {
+    /// Function to run
+    func: fn(),
+    /// The item is in the queue and has not started running yet
+    queued: AtomicBool,
+}
+
+impl Work {
+    /// Creates a work item running `func`.
+    pub const fn new(func: fn()) -> Self {
+        Work {
+            func,
+            queued: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns `true` while the item waits to run.
+    pub fn is_pending(&self) -> bool {
+        self.queued.load(Ordering::Acquire)
+    }
+}
+
+/// Creates the queue and starts the worker thread.
+///
+/// Must be called after the scheduler is initialized.
+///
+/// # Panics
+///
+/// Panics if called more than once or if the worker cannot be started.
+pub fn init() {
+    ITEMS
+        .try_init_once(|| ArrayQueue::new(WORK_QUEUE_CAPACITY))
+        .expect("workqueue initialized twice");
+    let worker = scheduler::spawn_named("workqueue", run).expect("failed to start the workqueue");
+    worker.thread().set_priority(Priority::High);
+}
+
+/// Queues `work` to run once in the worker thread.
+///
+/// Safe to call from interrupt handlers: it neither allocates nor blocks.
+/// Waking the worker does not allocate either, since the run queue has room
+/// for every thread (see [`scheduler::priority`]).
+/// An item that is pending already is not queued a second time; one that is
+/// running is queued again, so it runs once more afterwards.
+///
+/// # Returns
+///
+/// `true` if the item was queued, `false` if it was pending already or
+/// could not be queued (before [`init`] or with the queue full).
+pub fn schedule(work: &'static Work) -> bool {
+    if work.queued.swap(true, Ordering::AcqRel) {
+        return false;
+    }
+    let queued = ITEMS.try_get().is_ok_and(|items| items.push(work).is_ok());
+    if !queued {
+        work.queued.store(false, Ordering::Release);
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+        return false;
+    }
+    PENDING.notify_one();
+    true
+}
+
+/// Queues `f` to run once in the worker thread.
+///
+/// Allocates, so it must not be called from interrupt handlers; use
+/// [`schedule`] there.
+pub fn schedule_fn(f: impl FnOnce() + Send + 'static) {
+    let f: Box<dyn FnOnce() + Send> = Box::new(f);
+    CLOSURES.lock().push_back(f);
+    PENDING.notify_one();
+}
+
+/// Number of work items dropped because the queue was full.
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// Body of the worker thread: runs queued work in FIFO order, items before
+/// closures.
+fn run() {
+    let items = ITEMS.get().expect("workqueue not initialized");
+    loop {
+        PENDING.wait_until(|| !items.is_empty() || !CLOSURES.lock().is_empty());
+        while let Some(work) = items.pop() {
+            // Cleared first, so the item can be queued again while it runs.
+            work.queued.store(false, Ordering::Release);
+            (work.func)();
+        }
+        // Taken one at a time, so no lock is held while a closure runs.
+        loop {
+            let next = CLOSURES.lock().pop_front();
+            match next {
+                Some(f) => f(),
+                None => break,
+            }
+        }
+    }
+}
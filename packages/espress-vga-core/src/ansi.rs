@@ -0,0 +1,154 @@
+//! # ANSI Escape Sequences
+//!
+//! Splits a byte stream into plain bytes and the control sequences
+//! introduced by `ESC [` (CSI), one byte at a time, so sequences may be
+//! split across writes. [`TextWriter`](crate::TextWriter) carries out the
+//! sequences it knows; everything else that starts with `ESC` is consumed
+//! and dropped.
+
+/// Number of parameters kept of one sequence; later ones are ignored.
+pub const MAX_PARAMS: usize = 8;
+
+/// The escape character that starts every sequence.
+const ESC: u8 = 0x1b;
+
+/// A control sequence, `ESC [` followed by parameters and a final byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Csi {
+    /// Numeric parameters, 0 where omitted
+    params: [u16; MAX_PARAMS],
+    /// Number of parameters given; beyond [`MAX_PARAMS`], the rest were
+    /// dropped
+    count: usize,
+    /// Whether the parameters start with `?`, marking a private sequence
+    pub private: bool,
+    /// Final byte, which selects the function
+    pub action: u8,
+}
+
+impl Csi {
+    const fn new() -> Self {
+        Csi {
+            params: [0; MAX_PARAMS],
+            count: 0,
+            private: false,
+            action: 0,
+        }
+    }
+
+    /// The parameters given, in order.
+    pub fn params(&self) -> &[u16] {
+        &self.params[..self.count.min(MAX_PARAMS)]
+    }
+
+    /// Parameter `index`, or `default` if it was omitted or 0.
+    pub fn param(&self, index: usize, default: u16) -> u16 {
+        match self.params().get(index) {
+            Some(&value) if value != 0 => value,
+            _ => default,
+        }
+    }
+}
+
+/// What a byte of the stream completes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    /// Nothing yet: the byte is part of a sequence
+    Nothing,
+    /// A byte outside any sequence
+    Byte(u8),
+    /// A complete control sequence
+    Sequence(Csi),
+}
+
+/// Where the parser is in a sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum State {
+    /// Outside any sequence
+    Ground,
+    /// After `ESC`
+    Escape,
+    /// In the parameters of a control sequence
+    Parameters,
+}
+
+/// Incremental parser of ANSI escape sequences.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Parser {
+    /// Where the parser is
+    state: State,
+    /// The control sequence being parsed
+    sequence: Csi,
+}
+
+impl Parser {
+    /// Creates a parser outside any sequence.
+    pub const fn new() -> Self {
+        Parser {
+            state: State::Ground,
+            sequence: Csi::new(),
+        }
+    }
+
+    /// Feeds the next byte of the stream.
+    pub fn advance(&mut self, byte: u8) -> Output {
+        match (self.state, byte) {
+            (State::Ground, ESC) => {
+                self.state = State::Escape;
+                Output::Nothing
+            }
+            (State::Ground, byte) => Output::Byte(byte),
+            (State::Escape, b'[') => {
+                self.state = State::Parameters;
+                self.sequence = Csi::new();
+                Output::Nothing
+            }
+            // Sequences other than CSI are two bytes long and unsupported.
+            (State::Escape, _) => {
+                self.state = State::Ground;
+                Output::Nothing
+            }
+            (State::Parameters, b'0'..=b'9') => {
+                let sequence = &mut self.sequence;
+                if sequence.count == 0 {
+                    sequence.count = 1;
+                }
+                if let Some(param) = sequence.params.get_mut(sequence.count - 1) {
+                    *param = param
+                        .saturating_mul(10)
+                        .saturating_add(u16::from(byte - b'0'));
+                }
+                Output::Nothing
+            }
+            (State::Parameters, b';') => {
+                let sequence = &mut self.sequence;
+                sequence.count = (sequence.count.max(1) + 1).min(MAX_PARAMS + 1);
+                Output::Nothing
+            }
+            (State::Parameters, b'?') if self.sequence.count == 0 => {
+                self.sequence.private = true;
+                Output::Nothing
+            }
+            (State::Parameters, 0x40..=0x7e) => {
+                self.state = State::Ground;
+                self.sequence.action = byte;
+                Output::Sequence(self.sequence)
+            }
+            // Intermediate bytes change nothing the writer supports.
+            (State::Parameters, 0x20..=0x3f) => Output::Nothing,
+            (State::Parameters, _) => {
+                self.state = State::Ground;
+                Output::Nothing
+            }
+        }
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Parser::new()
+    }
+}
@@ -0,0 +1,720 @@
+//! # VGA Text Mode Engine
+//!
+//! The text output logic of the VGA text mode, shared by the EspressOS
+//! kernel, which drives the real buffer at `0xb8000`, and the WebAssembly
+//! emulator, which keeps the screen in memory for the browser.
+//!
+//! A [`TextWriter`] does everything that is not about where the character
+//! cells live: colors, line wrapping, scrolling, the replacement of bytes
+//! the screen cannot show, the status bar, and the mouse pointer. The cells
+//! themselves are reached through a [`TextBuffer`], which each user
+//! implements for its own storage, so both show exactly the same output.
+//!
+//! Text starts at the bottom row, and every new line there scrolls the
+//! screen up by one row, like the kernel always did. Strings may contain
+//! ANSI escape sequences for colors, cursor movement, and clearing (see
+//! [`TextWriter::write_string`]), so one stream shows the same on the
+//! screen as on a serial terminal.
+
+#![no_std]
+
+mod ansi;
+pub mod font;
+
+use core::fmt;
+use core::ops::Range;
+
+use ansi::{Csi, Output, Parser};
+
+/// Byte shown for characters outside printable ASCII: `■` in code page 437.
+pub const REPLACEMENT: u8 = 0xfe;
+
+/// Text color palette.
+///
+/// The 16 colors of the VGA text mode palette; the values are the VGA
+/// color numbers.
+///
+/// # Color Values
+///
+/// - 0-7: Normal intensity colors
+/// - 8-15: High intensity/bright colors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(u8)]
+pub enum Color {
+    /// Black color (RGB: 0, 0, 0)
+    Black = 0,
+    /// Blue color (RGB: 0, 0, 170)
+    Blue = 1,
+    /// Green color (RGB: 0, 170, 0)
+    Green = 2,
+    /// Cyan color (RGB: 0, 170, 170)
+    Cyan = 3,
+    /// Red color (RGB: 170, 0, 0)
+    Red = 4,
+    /// Magenta color (RGB: 170, 0, 170)
+    Magenta = 5,
+    /// Brown color (RGB: 170, 85, 0)
+    Brown = 6,
+    /// Light Gray color (RGB: 170, 170, 170)
+    LightGray = 7,
+    /// Dark Gray color (RGB: 85, 85, 85)
+    DarkGray = 8,
+    /// Light Blue color (RGB: 85, 85, 255)
+    LightBlue = 9,
+    /// Light Green color (RGB: 85, 255, 85)
+    LightGreen = 10,
+    /// Light Cyan color (RGB: 85, 255, 255)
+    LightCyan = 11,
+    /// Light Red color (RGB: 255, 85, 85)
+    LightRed = 12,
+    /// Pink color (RGB: 255, 85, 255)
+    Pink = 13,
+    /// Yellow color (RGB: 255, 255, 85)
+    Yellow = 14,
+    /// White color (RGB: 255, 255, 255)
+    White = 15,
+}
+
+impl Color {
+    /// All colors, in the order of their numbers.
+    pub const ALL: [Color; 16] = [
+        Color::Black,
+        Color::Blue,
+        Color::Green,
+        Color::Cyan,
+        Color::Red,
+        Color::Magenta,
+        Color::Brown,
+        Color::LightGray,
+        Color::DarkGray,
+        Color::LightBlue,
+        Color::LightGreen,
+        Color::LightCyan,
+        Color::LightRed,
+        Color::Pink,
+        Color::Yellow,
+        Color::White,
+    ];
+
+    /// Returns the color with VGA color number `value`; only the low four
+    /// bits are used.
+    pub const fn from_u8(value: u8) -> Color {
+        Color::ALL[(value & 0xf) as usize]
+    }
+
+    /// Returns the color with ANSI color number `value` (0-7 normal, 8-15
+    /// bright); only the low four bits are used.
+    ///
+    /// ANSI orders the low three bits red, green, blue, the reverse of the
+    /// VGA palette.
+    pub const fn from_ansi(value: u8) -> Color {
+        Color::from_u8((value & 0b001) << 2 | value & 0b1010 | (value & 0b100) >> 2)
+    }
+}
+
+/// RGB values of the colors on VGA hardware, by color number.
+pub const PALETTE: [[u8; 3]; 16] = [
+    [0x00, 0x00, 0x00],
+    [0x00, 0x00, 0xaa],
+    [0x00, 0xaa, 0x00],
+    [0x00, 0xaa, 0xaa],
+    [0xaa, 0x00, 0x00],
+    [0xaa, 0x00, 0xaa],
+    [0xaa, 0x55, 0x00],
+    [0xaa, 0xaa, 0xaa],
+    [0x55, 0x55, 0x55],
+    [0x55, 0x55, 0xff],
+    [0x55, 0xff, 0x55],
+    [0x55, 0xff, 0xff],
+    [0xff, 0x55, 0x55],
+    [0xff, 0x55, 0xff],
+    [0xff, 0xff, 0x55],
+    [0xff, 0xff, 0xff],
+];
+
+/// VGA color code representation.
+///
+/// Combines foreground and background colors into a single byte value
+/// that can be written to VGA memory. The format follows the VGA standard:
+/// - Bits 0-3: Foreground color
+/// - Bits 4-7: Background color
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(transparent)]
+pub struct ColorCode(u8);
+
+impl ColorCode {
+    /// Creates a new color code from foreground and background colors.
+    ///
+    /// # Arguments
+    ///
+    /// * `foreground` - The color for text characters
+    /// * `background` - The color for the background behind text
+    pub const fn new(foreground: Color, background: Color) -> ColorCode {
+        ColorCode((background as u8) << 4 | (foreground as u8))
+    }
+
+    /// Color of the characters.
+    pub const fn foreground(self) -> Color {
+        Color::from_u8(self.0)
+    }
+
+    /// Color behind the characters.
+    pub const fn background(self) -> Color {
+        Color::from_u8(self.0 >> 4)
+    }
+
+    /// Returns the color code with foreground and background swapped.
+    pub const fn inverted(self) -> ColorCode {
+        ColorCode(self.0.rotate_left(4))
+    }
+
+    /// Returns the color code with the foreground replaced.
+    pub const fn with_foreground(self, foreground: Color) -> ColorCode {
+        ColorCode(self.0 & 0xf0 | foreground as u8)
+    }
+
+    /// Returns the color code with the background replaced.
+    pub const fn with_background(self, background: Color) -> ColorCode {
+        ColorCode(self.0 & 0x0f | (background as u8) << 4)
+    }
+}
+
+/// A single character cell in the VGA text buffer.
+///
+/// # Memory Layout
+///
+/// The struct uses `#[repr(C)]` to ensure the fields are laid out in memory
+/// exactly as expected by the VGA hardware:
+/// - Byte 0: Code page 437 character code
+/// - Byte 1: Color code (foreground + background)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+pub struct ScreenChar {
+    /// The character to display
+    pub ascii_character: u8,
+    /// Combined foreground and background color information
+    pub color_code: ColorCode,
+}
+
+impl ScreenChar {
+    /// Returns the character the cell shows.
+    ///
+    /// Printable ASCII is itself and [`REPLACEMENT`] is `■`; the writer
+    /// puts nothing else into cells, and other bytes are shown as a space.
+    pub fn character(self) -> char {
+        match self.ascii_character {
+            byte @ 0x20..=0x7e => char::from(byte),
+            REPLACEMENT => '■',
+            _ => ' ',
+        }
+    }
+}
+
+/// Storage of the character cells of a text screen.
+///
+/// Rows are numbered from the top and columns from the left, both from 0.
+pub trait TextBuffer {
+    /// Number of columns.
+    fn width(&self) -> usize;
+
+    /// Number of rows.
+    fn height(&self) -> usize;
+
+    /// Reads the cell at `row` and `column`, both within the screen.
+    fn read(&self, row: usize, column: usize) -> ScreenChar;
+
+    /// Writes the cell at `row` and `column`, both within the screen.
+    fn write(&mut self, row: usize, column: usize, cell: ScreenChar);
+
+    /// Called with the top text row just before scrolling drops it from
+    /// the screen, for buffers that keep a history. Does nothing by default.
+    fn scroll_off(&mut self, _row: usize) {}
+
+    /// Moves every row below `top` up by one, overwriting row `top` and
+    /// leaving the bottom row as it was.
+    ///
+    /// The default copies cell by cell; buffers in plain memory can move
+    /// the rows at once.
+    fn scroll_up(&mut self, top: usize) {
+        for row in top + 1..self.height() {
+            for col in 0..self.width() {
+                let character = self.read(row, col);
+                self.write(row - 1, col, character);
+            }
+        }
+    }
+}
+
+impl<B: TextBuffer + ?Sized> TextBuffer for &mut B {
+    fn width(&self) -> usize {
+        (**self).width()
+    }
+
+    fn height(&self) -> usize {
+        (**self).height()
+    }
+
+    fn read(&self, row: usize, column: usize) -> ScreenChar {
+        (**self).read(row, column)
+    }
+
+    fn write(&mut self, row: usize, column: usize, cell: ScreenChar) {
+        (**self).write(row, column, cell);
+    }
+
+    fn scroll_off(&mut self, row: usize) {
+        (**self).scroll_off(row);
+    }
+
+    fn scroll_up(&mut self, top: usize) {
+        (**self).scroll_up(top);
+    }
+}
+
+/// Text writer over a [`TextBuffer`].
+///
+/// Handles cursor management, line wrapping, scrolling, and color
+/// formatting, and optionally keeps a status bar on the top row and draws a
+/// mouse pointer.
+///
+/// With the `serde` feature, the writer serializes along with its buffer,
+/// all but the pointer, which follows the mouse rather than the text.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TextWriter<B> {
+    /// The character cells
+    buffer: B,
+    /// Current row of the cursor
+    row: usize,
+    /// Current column position of the cursor
+    column_position: usize,
+    /// Current color code for new text
+    color_code: ColorCode,
+    /// Color code the writer was created with, restored by `ESC [ 0 m`
+    default_color: ColorCode,
+    /// Whether `ESC [ 1 m` selected bright foreground colors
+    bold: bool,
+    /// Escape sequence state of the text written so far
+    parser: Parser,
+    /// Whether the top row is reserved for the status bar
+    status_bar: bool,
+    /// Row and column of the mouse pointer, drawn with inverted colors
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pointer: Option<(usize, usize)>,
+}
+
+impl<B: TextBuffer> TextWriter<B> {
+    /// Creates a writer that writes to `buffer` in `color_code`.
+    ///
+    /// The buffer is left as it is; the cursor starts at the beginning of
+    /// the bottom row.
+    pub fn new(buffer: B, color_code: ColorCode) -> Self {
+        TextWriter {
+            row: buffer.height() - 1,
+            buffer,
+            column_position: 0,
+            color_code,
+            default_color: color_code,
+            bold: false,
+            parser: Parser::new(),
+            status_bar: false,
+            pointer: None,
+        }
+    }
+
+    /// The character cells.
+    pub fn buffer(&self) -> &B {
+        &self.buffer
+    }
+
+    /// The character cells, for bookkeeping of the buffer's own; cells
+    /// written through it may end up under the mouse pointer.
+    pub fn buffer_mut(&mut self) -> &mut B {
+        &mut self.buffer
+    }
+
+    /// Row and column of the next character.
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.row, self.column_position)
+    }
+
+    /// Moves the cursor, keeping it within the text area.
+    ///
+    /// # Arguments
+    ///
+    /// * `row` - Row of the next character
+    /// * `column` - Column of the next character; the width of the buffer
+    ///   means the line is full
+    pub fn set_cursor(&mut self, row: usize, column: usize) {
+        self.row = row.clamp(self.first_text_row(), self.buffer.height() - 1);
+        self.column_position = column.min(self.buffer.width());
+    }
+
+    /// Color code of new text.
+    pub fn color_code(&self) -> ColorCode {
+        self.color_code
+    }
+
+    /// Sets the color code of new text.
+    pub fn set_color_code(&mut self, color_code: ColorCode) {
+        self.color_code = color_code;
+    }
+
+    /// Writes a single byte.
+    ///
+    /// `\n` moves to the next line; any other byte is written to the cursor
+    /// position as a character, wrapping to the next line when the current
+    /// line is full.
+    ///
+    /// # Arguments
+    ///
+    /// * `byte` - The byte to write to the screen
+    pub fn write_byte(&mut self, byte: u8) {
+        match byte {
+            b'\n' => self.new_line(),
+            byte => {
+                if self.column_position >= self.buffer.width() {
+                    self.new_line();
+                }
+
+                let row = self.row;
+                let col = self.column_position;
+
+                let mut color_code = self.color_code;
+                if self.pointer == Some((row, col)) {
+                    color_code = color_code.inverted();
+                }
+                self.buffer.write(
+                    row,
+                    col,
+                    ScreenChar {
+                        ascii_character: byte,
+                        color_code,
+                    },
+                );
+                self.column_position += 1;
+            }
+        }
+    }
+
+    /// Writes a string.
+    ///
+    /// # Arguments
+    ///
+    /// * `s` - The string to write to the screen
+    ///
+    /// # Character Handling
+    ///
+    /// - ASCII printable characters (0x20-0x7E): Written as-is
+    /// - Newline character (`\n`): Triggers line advance
+    /// - Backspace (0x08): Moves back one column
+    /// - ANSI escape sequences: Carried out, see below
+    /// - Other bytes, including every byte of a non-ASCII character:
+    ///   Replaced with [`REPLACEMENT`]
+    ///
+    /// # Escape Sequences
+    ///
+    /// Sequences may be split across calls. Rows are counted from the top
+    /// of the text area, below the status bar; rows and columns start at 1.
+    ///
+    /// - `ESC [ n m` (SGR): 0 resets the colors, 1 and 22 switch bright
+    ///   foreground colors on and off, 30-37 and 90-97 select the
+    ///   foreground, 40-47 and 100-107 the background, and 39 and 49 the
+    ///   default ones
+    /// - `ESC [ n A`, `B`, `C`, `D`: Move the cursor up, down, right, left
+    /// - `ESC [ row ; col H` (or `f`): Move the cursor to a position
+    /// - `ESC [ col G`: Move the cursor to a column
+    /// - `ESC [ n J`: Clear to the end (0), the start (1), or all (2) of the
+    ///   screen
+    /// - `ESC [ n K`: Clear to the end (0), the start (1), or all (2) of the
+    ///   line
+    ///
+    /// Other sequences are dropped.
+    pub fn write_string(&mut self, s: &str) {
+        for byte in s.bytes() {
+            match self.parser.advance(byte) {
+                Output::Nothing => {}
+                // printable ASCII byte or newline
+                Output::Byte(byte @ (0x20..=0x7e | b'\n')) => self.write_byte(byte),
+                Output::Byte(0x08) => self.backspace(),
+                // not part of printable ASCII range
+                Output::Byte(_) => self.write_byte(REPLACEMENT),
+                Output::Sequence(sequence) => {
+                    self.toggle_pointer();
+                    self.execute(&sequence);
+                    self.toggle_pointer();
+                }
+            }
+        }
+    }
+
+    /// Moves the cursor back one column, staying on the current line.
+    fn backspace(&mut self) {
+        self.column_position = self.column_position.saturating_sub(1);
+    }
+
+    /// Writes a string in the given text color, keeping the background.
+    ///
+    /// The previous color is restored afterwards.
+    ///
+    /// # Arguments
+    ///
+    /// * `s` - The string to write
+    /// * `foreground` - The color for its characters
+    pub fn write_colored(&mut self, s: &str, foreground: Color) {
+        let previous = self.color_code;
+        self.color_code = previous.with_foreground(foreground);
+        self.write_string(s);
+        self.color_code = previous;
+    }
+
+    /// Advances to the start of the next line, scrolling the screen at the
+    /// bottom.
+    ///
+    /// Scrolling moves all lines up by one position and clears the bottom
+    /// line. The status bar row, if shown, is left in place.
+    pub fn new_line(&mut self) {
+        self.column_position = 0;
+        if self.row + 1 < self.buffer.height() {
+            self.row += 1;
+            return;
+        }
+
+        // The pointer stays where it is while the text moves beneath it.
+        self.toggle_pointer();
+        self.buffer.scroll_off(self.first_text_row());
+        self.buffer.scroll_up(self.first_text_row());
+        self.clear_row(self.buffer.height() - 1);
+        self.toggle_pointer();
+    }
+
+    /// Blanks every row but the status bar and moves the cursor to the
+    /// start of the bottom row.
+    pub fn clear(&mut self) {
+        self.toggle_pointer();
+        for row in self.first_text_row()..self.buffer.height() {
+            self.clear_row(row);
+        }
+        self.row = self.buffer.height() - 1;
+        self.column_position = 0;
+        self.toggle_pointer();
+    }
+
+    /// Shows a line of text in the status bar on the top row, in black on
+    /// light gray.
+    ///
+    /// The first call reserves the top row: from then on it no longer takes
+    /// part in scrolling. Text longer than the screen width is cut off, and
+    /// the rest of the row is blanked.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - Formatted status text created by `format_args!`
+    pub fn set_status(&mut self, args: fmt::Arguments) {
+        use fmt::Write;
+
+        self.toggle_pointer();
+        self.status_bar = true;
+        self.row = self.row.max(1).min(self.buffer.height() - 1);
+        let mut line = StatusLine {
+            buffer: &mut self.buffer,
+            column: 0,
+        };
+        let _ = line.write_fmt(args);
+        line.fill();
+        self.toggle_pointer();
+    }
+
+    /// Removes the status bar and returns the top row to normal text output.
+    pub fn clear_status(&mut self) {
+        if self.status_bar {
+            self.toggle_pointer();
+            self.status_bar = false;
+            self.clear_row(0);
+            self.toggle_pointer();
+        }
+    }
+
+    /// Shows the mouse pointer on a cell, or hides it.
+    ///
+    /// The pointer cell is drawn with foreground and background swapped.
+    /// Positions outside the screen hide the pointer.
+    ///
+    /// # Arguments
+    ///
+    /// * `position` - Row and column of the pointer, or `None` to hide it
+    pub fn set_pointer(&mut self, position: Option<(usize, usize)>) {
+        self.toggle_pointer();
+        let (height, width) = (self.buffer.height(), self.buffer.width());
+        self.pointer = position.filter(|&(row, col)| row < height && col < width);
+        self.toggle_pointer();
+    }
+
+    /// Swaps the colors of the pointer cell, drawing or erasing the pointer.
+    fn toggle_pointer(&mut self) {
+        if let Some((row, col)) = self.pointer {
+            let mut character = self.buffer.read(row, col);
+            character.color_code = character.color_code.inverted();
+            self.buffer.write(row, col, character);
+        }
+    }
+
+    /// Carries out a control sequence; the pointer must be hidden.
+    fn execute(&mut self, sequence: &Csi) {
+        if sequence.private {
+            return;
+        }
+        let (width, height) = (self.buffer.width(), self.buffer.height());
+        let top = self.first_text_row();
+        let count = usize::from(sequence.param(0, 1));
+        match sequence.action {
+            b'm' => self.select_graphic_rendition(sequence),
+            b'A' => self.row = self.row.saturating_sub(count).max(top),
+            b'B' => self.row = (self.row + count).min(height - 1),
+            b'C' => self.column_position = (self.column_position + count).min(width - 1),
+            b'D' => {
+                self.column_position = self.column_position.min(width - 1).saturating_sub(count)
+            }
+            b'G' => self.column_position = (count - 1).min(width - 1),
+            b'H' | b'f' => {
+                self.row = (top + count - 1).min(height - 1);
+                self.column_position = usize::from(sequence.param(1, 1) - 1).min(width - 1);
+            }
+            b'J' => {
+                let (row, col) = (self.row, self.column_position.min(width));
+                match sequence.param(0, 0) {
+                    0 => {
+                        self.clear_cells(row, col..width);
+                        (row + 1..height).for_each(|row| self.clear_row(row));
+                    }
+                    1 => {
+                        (top..row).for_each(|row| self.clear_row(row));
+                        self.clear_cells(row, 0..(col + 1).min(width));
+                    }
+                    _ => (top..height).for_each(|row| self.clear_row(row)),
+                }
+            }
+            b'K' => {
+                let col = self.column_position.min(width);
+                match sequence.param(0, 0) {
+                    0 => self.clear_cells(self.row, col..width),
+                    1 => self.clear_cells(self.row, 0..(col + 1).min(width)),
+                    _ => self.clear_row(self.row),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Carries out `ESC [ ... m`, which sets the colors.
+    fn select_graphic_rendition(&mut self, sequence: &Csi) {
+        let default = self.default_color;
+        let intensity = |bold: bool| if bold { 8 } else { 0 };
+        if sequence.params().is_empty() {
+            self.color_code = default;
+            self.bold = false;
+        }
+        for &param in sequence.params() {
+            let code = self.color_code;
+            self.color_code = match param {
+                0 => {
+                    self.bold = false;
+                    default
+                }
+                1 => {
+                    self.bold = true;
+                    code.with_foreground(Color::from_u8(code.foreground() as u8 | 8))
+                }
+                22 => {
+                    self.bold = false;
+                    code.with_foreground(Color::from_u8(code.foreground() as u8 & 7))
+                }
+                30..=37 => code
+                    .with_foreground(Color::from_ansi((param - 30) as u8 | intensity(self.bold))),
+                39 => code.with_foreground(Color::from_u8(
+                    default.foreground() as u8 | intensity(self.bold),
+                )),
+                40..=47 => code.with_background(Color::from_ansi((param - 40) as u8)),
+                49 => code.with_background(default.background()),
+                90..=97 => code.with_foreground(Color::from_ansi((param - 90) as u8 | 8)),
+                100..=107 => code.with_background(Color::from_ansi((param - 100) as u8 | 8)),
+                _ => code,
+            };
+        }
+    }
+
+    /// Fills `row` with blanks in the current color.
+    fn clear_row(&mut self, row: usize) {
+        self.clear_cells(row, 0..self.buffer.width());
+    }
+
+    /// Fills the cells `columns` of `row` with blanks in the current color.
+    fn clear_cells(&mut self, row: usize, columns: Range<usize>) {
+        let blank = ScreenChar {
+            ascii_character: b' ',
+            color_code: self.color_code,
+        };
+        for col in columns {
+            self.buffer.write(row, col, blank);
+        }
+    }
+
+    /// Index of the topmost row used for scrolling text.
+    fn first_text_row(&self) -> usize {
+        if self.status_bar {
+            1
+        } else {
+            0
+        }
+    }
+}
+
+impl<B: TextBuffer> fmt::Write for TextWriter<B> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_string(s);
+        Ok(())
+    }
+}
+
+/// Writer for the status bar row.
+struct StatusLine<'a, B> {
+    /// The character cells
+    buffer: &'a mut B,
+    /// Next column to write
+    column: usize,
+}
+
+impl<B: TextBuffer> StatusLine<'_, B> {
+    /// Blanks the row from the current column to the end.
+    fn fill(&mut self) {
+        while self.column < self.buffer.width() {
+            self.put(b' ');
+        }
+    }
+
+    fn put(&mut self, byte: u8) {
+        if self.column >= self.buffer.width() {
+            return;
+        }
+        let cell = ScreenChar {
+            ascii_character: byte,
+            color_code: ColorCode::new(Color::Black, Color::LightGray),
+        };
+        self.buffer.write(0, self.column, cell);
+        self.column += 1;
+    }
+}
+
+impl<B: TextBuffer> fmt::Write for StatusLine<'_, B> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            match byte {
+                0x20..=0x7e => self.put(byte),
+                _ => self.put(REPLACEMENT),
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+// Simulated boot for the website demo: the kernel's boot log as a list of
+// timed events, so the page can write it line by line instead of all at
+// once. The stages and their order follow the x86_64 kernel; as there,
+// stages before the TSC is calibrated report no time.
+
+use wasm_bindgen::prelude::*;
+
+use crate::{SerialEmulator, VgaEmulator};
+
+// VGA colors of the events
+const WHITE: u8 = 15;
+const LIGHT_GRAY: u8 = 7;
+const YELLOW: u8 = 14;
+
+// Stages in boot order: name, milliseconds before the line appears, and the
+// time the line reports in microseconds, if the clock is known by then
+const STAGES: &[(&str, u32, Option<u32>)] = &[
+    ("Kernel heap", 120, None),
+    ("Page protection", 80, None),
+    ("FPU", 40, None),
+    ("GDT and TSS", 60, None),
+    ("System calls", 40, None),
+    ("IDT", 50, None),
+    ("PIC", 40, None),
+    ("Kernel address space", 150, None),
+    ("PCI", 250, None),
+    ("SMBIOS", 90, None),
+    ("CPU topology", 70, None),
+    ("TSC", 300, Some(10_482)),
+    ("Idle states", 50, Some(37)),
+    ("Random numbers", 60, Some(212)),
+    ("Scheduler", 70, Some(58)),
+    ("Timer", 90, Some(1_305)),
+    ("Work queues", 40, Some(21)),
+    ("Block cache", 60, Some(96)),
+    ("Mouse", 180, Some(4_870)),
+    ("Virtio console", 80, Some(143)),
+    ("Virtio RNG", 70, Some(118)),
+    ("Network", 350, Some(8_641)),
+    ("File systems", 220, Some(3_377)),
+];
+
+// One line of the boot log: after `delay_ms`, `text` is written in `color`
+// (a VGA color on black). `text` may contain ANSI escape sequences, as the
+// colored `[ OK ]` markers do
+#[wasm_bindgen]
+#[derive(Clone)]
+pub struct BootEvent {
+    #[wasm_bindgen(getter_with_clone)]
+    pub stage: String,
+    pub delay_ms: u32,
+    #[wasm_bindgen(getter_with_clone)]
+    pub text: String,
+    pub color: u8,
+}
+
+impl BootEvent {
+    fn new(stage: &str, delay_ms: u32, text: String, color: u8) -> BootEvent {
+        BootEvent {
+            stage: stage.to_string(),
+            delay_ms,
+            text,
+            color,
+        }
+    }
+}
+
+#[wasm_bindgen]
+pub struct BootSimulation {
+    events: Vec<BootEvent>,
+    // Index of the next event in `events`
+    next: usize,
+}
+
+#[wasm_bindgen]
+impl BootSimulation {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> BootSimulation {
+        let mut events = vec![
+            BootEvent::new(
+                "Splash",
+                0,
+                "\n  Welcome to EspressOS!\n".to_string(),
+                YELLOW,
+            ),
+            BootEvent::new(
+                "Splash",
+                200,
+                "  EspressOS 0.1.0 (x86_64)\n\n".to_string(),
+                WHITE,
+            ),
+            BootEvent::new(
+                "Memory map",
+                300,
+                "Physical memory: 127 MiB usable in 4 regions\n".to_string(),
+                LIGHT_GRAY,
+            ),
+        ];
+
+        let mut total = 0;
+        for &(name, delay_ms, micros) in STAGES {
+            let mut text = format!("[\x1b[92m OK \x1b[39m] {}", name);
+            if let Some(micros) = micros {
+                text.push_str(&format!("  ({}.{:03} ms)", micros / 1000, micros % 1000));
+                total += micros;
+            }
+            text.push('\n');
+            events.push(BootEvent::new(name, delay_ms, text, WHITE));
+        }
+
+        events.push(BootEvent::new(
+            "Ready",
+            400,
+            format!(
+                "EspressOS ready after {} boot stages ({}.{:03} ms)\n",
+                STAGES.len(),
+                total / 1000,
+                total % 1000
+            ),
+            WHITE,
+        ));
+
+        BootSimulation { events, next: 0 }
+    }
+
+    // The next event, or `undefined` once the boot is over
+    #[wasm_bindgen]
+    pub fn next_event(&mut self) -> Option<BootEvent> {
+        self.next()
+    }
+
+    // Writes the next event to `emulator` right away; returns the delay
+    // before the one after it should be written, or `undefined` when none
+    // is left
+    #[wasm_bindgen]
+    pub fn step(&mut self, emulator: &mut VgaEmulator) -> Option<u32> {
+        let event = self.next()?;
+        emulator.set_color(event.color, 0);
+        emulator.write(&event.text);
+        self.events.get(self.next).map(|next| next.delay_ms)
+    }
+
+    // Like `step`, and also sends the event to `serial`, as the kernel
+    // logs to the screen and COM1 at once
+    #[wasm_bindgen]
+    pub fn step_with_serial(
+        &mut self,
+        emulator: &mut VgaEmulator,
+        serial: &mut SerialEmulator,
+    ) -> Option<u32> {
+        if let Some(event) = self.events.get(self.next) {
+            serial.write_colored(&event.text, event.color);
+        }
+        self.step(emulator)
+    }
+
+    #[wasm_bindgen]
+    pub fn is_done(&self) -> bool {
+        self.next == self.events.len()
+    }
+
+    // Starts the boot over
+    #[wasm_bindgen]
+    pub fn reset(&mut self) {
+        self.next = 0;
+    }
+}
+
+impl Default for BootSimulation {
+    fn default() -> Self {
+        BootSimulation::new()
+    }
+}
+
+impl Iterator for BootSimulation {
+    type Item = BootEvent;
+
+    fn next(&mut self) -> Option<BootEvent> {
+        let event = self.events.get(self.next)?.clone();
+        self.next += 1;
+        Some(event)
+    }
+}
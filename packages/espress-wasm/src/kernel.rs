@@ -0,0 +1,220 @@
+// Kernel modules compiled for the browser from the kernel's own sources,
+// with stand-ins for the parts of the kernel they use: the console collects
+// text that ends up on a `VgaEmulator`, the command line is empty, and the
+// timer counts microseconds of the browser's clock. The boot banner, the
+// boot stage log, the shell's command interpreter, and the scheduler's run
+// queue run this way. The scheduler itself switches CPU contexts, which
+// WebAssembly has no counterpart for, so the scheduler demo drives the run
+// queue tick by tick the way the kernel's timer tick does.
+
+use std::cell::RefCell;
+use std::mem;
+
+use wasm_bindgen::prelude::*;
+
+use crate::VgaEmulator;
+use priority::{Priority, RunQueue, AGING_TICKS};
+
+pub mod arch {
+    pub const NAME: &str = "wasm32";
+
+    pub mod timer {
+        use core::time::Duration;
+
+        use js_sys::Date;
+
+        // Microseconds since the Unix epoch, at the clock's millisecond
+        // resolution
+        pub fn ticks() -> u64 {
+            (Date::now() * 1000.0) as u64
+        }
+
+        pub fn frequency() -> u64 {
+            1_000_000
+        }
+
+        pub fn ticks_to_duration(ticks: u64) -> Duration {
+            Duration::from_micros(ticks)
+        }
+    }
+}
+
+pub mod console {
+    use std::fmt::Write;
+
+    pub use espress_vga_core::Color;
+
+    use super::OUTPUT;
+    use crate::serial::ansi_color;
+
+    // Colors `s` with the SGR sequences the emulator understands, leaving
+    // the background as it is
+    pub fn print_colored(s: &str, color: Color) {
+        OUTPUT.with_borrow_mut(|output| {
+            let _ = write!(output, "\x1b[{}m{}\x1b[39m", ansi_color(color), s);
+        });
+    }
+
+    #[doc(hidden)]
+    pub fn _print(args: core::fmt::Arguments) {
+        OUTPUT.with_borrow_mut(|output| {
+            let _ = output.write_fmt(args);
+        });
+    }
+}
+
+pub mod cmdline {
+    pub fn flag(_name: &str) -> bool {
+        false
+    }
+}
+
+// The kernel reads more of the stage log than the demo does
+#[allow(dead_code)]
+#[path = "../../espress-os/src/boot/progress.rs"]
+pub mod progress;
+
+#[path = "../../espress-os/src/boot/splash.rs"]
+pub mod splash;
+
+// The demo edits lines itself, with the cursor keys the kernel's line
+// editor lacks
+#[allow(dead_code)]
+#[path = "../../espress-os/src/shell/interpreter.rs"]
+pub mod interpreter;
+
+// The kernel reads more of the run queue than the demo does
+#[allow(dead_code)]
+#[path = "../../espress-os/src/scheduler/priority.rs"]
+pub mod priority;
+
+// What the run queue sees of a thread; the kernel's control block also
+// holds its stack and saved registers
+struct ControlBlock {
+    id: ThreadId,
+    priority: Priority,
+    effective_priority: Priority,
+    enqueued_at: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ThreadId(usize);
+
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::kernel::console::_print(format_args!($($arg)*)));
+}
+
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+pub(crate) use {print, println};
+
+// Columns the boot banner needs
+const BANNER_WIDTH: usize = 61;
+
+// Threads of the scheduler demo, none of which ever blocks
+const DEMO_THREADS: [(&str, Priority); 4] = [
+    ("input", Priority::High),
+    ("render", Priority::High),
+    ("shell", Priority::Normal),
+    ("logger", Priority::Low),
+];
+
+// Timer ticks the scheduler demo runs for
+const DEMO_TICKS: u64 = 48;
+
+// The kernel's `scheduler::TIME_SLICE_TICKS`
+const TIME_SLICE_TICKS: u64 = 1;
+
+thread_local! {
+    // Console output not yet written to an emulator
+    static OUTPUT: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+// Runs the kernel's boot banner and boot stage log on `emulator`, ending
+// with the line the kernel prints when it is ready. As in the kernel, the
+// stage log is never reset, so later runs count the earlier stages too
+#[wasm_bindgen]
+pub fn run_kernel_boot(emulator: &mut VgaEmulator) {
+    let width = emulator.get_width();
+    splash::show();
+    progress::stage("Console", || ());
+    let _ = progress::stage("Screen size", || {
+        if width >= BANNER_WIDTH {
+            Ok(())
+        } else {
+            Err("too narrow for the banner")
+        }
+    });
+
+    let time = progress::elapsed().as_micros();
+    println!(
+        "EspressOS ready after {} boot stages ({}.{:03} ms)",
+        progress::stages().len(),
+        time / 1000,
+        time % 1000
+    );
+
+    emulator.write(&OUTPUT.take());
+}
+
+// Writes the console output of the kernel code run so far to `emulator`
+pub(crate) fn flush(emulator: &mut VgaEmulator) {
+    emulator.write(&OUTPUT.take());
+}
+
+// Schedules the demo threads on the kernel's run queue and prints which one
+// had the CPU at each tick: `#` in its own priority class, `+` after aging
+// promoted it. As in the kernel's timer tick, the queue ages every tick,
+// and the running thread makes way for a waiting thread of a higher class,
+// or of its own once its time slice is up, going back to its own class
+pub fn scheduler_demo() {
+    let mut queue = RunQueue::new();
+    queue.reserve(DEMO_THREADS.len());
+    for (index, &(_, priority)) in DEMO_THREADS.iter().enumerate() {
+        let thread = ControlBlock {
+            id: ThreadId(index),
+            priority,
+            effective_priority: priority,
+            enqueued_at: 0,
+        };
+        queue.push(Box::new(thread), 0);
+    }
+
+    let mut timeline = vec![String::new(); DEMO_THREADS.len()];
+    let mut current = queue.pop().expect("no demo threads");
+    let mut boosted = false;
+    let mut slice_ticks = 0;
+    for now in 1..=DEMO_TICKS {
+        for (index, line) in timeline.iter_mut().enumerate() {
+            line.push(match (index == current.id.0, boosted) {
+                (false, _) => '.',
+                (true, false) => '#',
+                (true, true) => '+',
+            });
+        }
+
+        queue.age(now);
+        slice_ticks += 1;
+        let priority = current.effective_priority;
+        if queue.has_above(priority)
+            || (slice_ticks >= TIME_SLICE_TICKS && queue.has_at_least(priority))
+        {
+            let mut next = queue.pop().expect("queue emptied");
+            boosted = next.effective_priority > next.priority;
+            next.effective_priority = next.priority;
+            queue.push(mem::replace(&mut current, next), now);
+            slice_ticks = 0;
+        }
+    }
+
+    println!(
+        "{} ticks, promotion after {} ticks waiting:",
+        DEMO_TICKS, AGING_TICKS
+    );
+    for (&(name, priority), line) in DEMO_THREADS.iter().zip(&timeline) {
+        println!("  {:<7} {:<7} {}", name, priority.name(), line);
+    }
+}
@@ -1,10 +1,39 @@
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::mem;
+use std::rc::Rc;
+
+use espress_vga_core::font::{GLYPHS, GLYPH_HEIGHT, GLYPH_WIDTH};
+use espress_vga_core::{Color, ColorCode, ScreenChar, TextBuffer, TextWriter};
+use js_sys::{Function, Uint8Array};
+use serde::{Deserialize, Serialize};
+use session::{Operation, Recording, Replay};
 use wasm_bindgen::prelude::*;
 
+extern crate alloc;
+
+mod boot;
+mod kernel;
+mod serial;
+mod session;
+mod shell;
+
+// Paths the kernel modules in `kernel` reach the kernel's own by
+use kernel::{arch, console, print, println};
+
+pub use boot::{BootEvent, BootSimulation};
+pub use kernel::run_kernel_boot;
+pub use serial::SerialEmulator;
+pub use shell::ShellDemo;
+
 // Import the `console.log` function from the browser's console API
 #[wasm_bindgen]
 extern "C" {
     #[wasm_bindgen(js_namespace = console)]
     fn log(s: &str);
+
+    #[wasm_bindgen(js_name = queueMicrotask)]
+    fn queue_microtask(callback: &JsValue);
 }
 
 // Define a macro to print to the browser's console
@@ -12,37 +41,160 @@ macro_rules! console_log {
     ($($t:tt)*) => (log(&format_args!($($t)*).to_string()))
 }
 
-// VGA Color definitions (simplified version from espress-os)
-#[wasm_bindgen]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[repr(u8)]
-pub enum Color {
-    Black = 0,
-    Blue = 1,
-    Green = 2,
-    Cyan = 3,
-    Red = 4,
-    Magenta = 5,
-    Brown = 6,
-    LightGray = 7,
-    DarkGray = 8,
-    LightBlue = 9,
-    LightGreen = 10,
-    LightCyan = 11,
-    LightRed = 12,
-    Pink = 13,
-    Yellow = 14,
-    White = 15,
+// Frames the cursor stays on, then off; VGA hardware toggles it every 16
+// vertical retraces
+const BLINK_FRAMES: u32 = 16;
+
+// Lines kept after they scroll off the top of the screen
+const HISTORY_LINES: usize = 1000;
+
+// Virtual terminals, one for each of the Alt+F1 to Alt+F4 consoles the
+// kernel is going to have
+const TERMINALS: usize = 4;
+
+// RGB values of the 16 colors on VGA hardware, by color number
+const VGA_PALETTE: [[u8; 3]; 16] = espress_vga_core::PALETTE;
+
+/// Character cells of the emulated screen, kept in memory row by row
+#[derive(Serialize, Deserialize)]
+struct Cells {
+    cells: Vec<ScreenChar>,
+    width: usize,
+    height: usize,
+    // Lines that scrolled off, oldest first, at most HISTORY_LINES
+    history: VecDeque<Vec<ScreenChar>>,
+    // Per row of the view, whether it changed since `take_dirty_rows`
+    #[serde(skip)]
+    dirty: Vec<bool>,
 }
 
-// VGA Text Mode Emulator for the web
+impl Cells {
+    fn mark_all_dirty(&mut self) {
+        self.dirty = vec![true; self.height];
+    }
+
+    // Resizes to `width` x `height`, cropping or padding lines with `blank`
+    // on the right and keeping row `cursor_row` on screen; returns the row
+    // it ends up in. Shrinking drops the rows below the cursor first, then
+    // moves rows off the top into the history; growing brings them back
+    fn resize(
+        &mut self,
+        width: usize,
+        height: usize,
+        cursor_row: usize,
+        blank: ScreenChar,
+    ) -> usize {
+        let fit = |mut line: Vec<ScreenChar>| {
+            line.resize(width, blank);
+            line
+        };
+        let mut rows: Vec<Vec<ScreenChar>> = self
+            .cells
+            .chunks(self.width)
+            .map(|row| fit(row.to_vec()))
+            .collect();
+        self.history = mem::take(&mut self.history).into_iter().map(fit).collect();
+
+        let mut cursor_row = cursor_row;
+        while rows.len() > height {
+            if cursor_row + 1 < rows.len() {
+                rows.pop();
+            } else {
+                if self.history.len() == HISTORY_LINES {
+                    self.history.pop_front();
+                }
+                self.history.push_back(rows.remove(0));
+                cursor_row -= 1;
+            }
+        }
+        while rows.len() < height {
+            let row = self
+                .history
+                .pop_back()
+                .unwrap_or_else(|| vec![blank; width]);
+            rows.insert(0, row);
+            cursor_row += 1;
+        }
+
+        self.cells = rows.concat();
+        self.width = width;
+        self.height = height;
+        self.mark_all_dirty();
+        cursor_row
+    }
+}
+
+impl TextBuffer for Cells {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn read(&self, row: usize, column: usize) -> ScreenChar {
+        self.cells[row * self.width + column]
+    }
+
+    fn write(&mut self, row: usize, column: usize, cell: ScreenChar) {
+        self.cells[row * self.width + column] = cell;
+        self.dirty[row] = true;
+    }
+
+    fn scroll_off(&mut self, row: usize) {
+        if self.history.len() == HISTORY_LINES {
+            self.history.pop_front();
+        }
+        let start = row * self.width;
+        self.history
+            .push_back(self.cells[start..start + self.width].to_vec());
+    }
+
+    fn scroll_up(&mut self, top: usize) {
+        let width = self.width;
+        self.cells.copy_within((top + 1) * width.., top * width);
+        self.dirty[top..].fill(true);
+    }
+}
+
+// VGA Text Mode Emulator for the web, writing through the same engine as
+// the kernel's VGA writer (espress-vga-core), so it shows text the same way
 #[wasm_bindgen]
+#[derive(Serialize, Deserialize)]
 pub struct VgaEmulator {
-    buffer: Vec<Vec<(char, u8, u8)>>, // character, foreground, background
-    width: usize,
-    height: usize,
-    cursor_x: usize,
-    cursor_y: usize,
+    writer: TextWriter<Cells>,
+    // The virtual terminals by number; the active one's slot is empty
+    // while `writer` holds it
+    #[serde(default)]
+    terminals: Vec<Option<TextWriter<Cells>>>,
+    #[serde(default)]
+    active_terminal: usize,
+    // Number of history lines shown above the screen, scrolling it down
+    view_offset: usize,
+    cursor_visible: bool,
+    // Frames since the cursor last turned on, below 2 * BLINK_FRAMES
+    blink_frame: u32,
+    // Called with the new width and height after `resize`
+    #[serde(skip)]
+    on_resize: Option<Function>,
+    // Called once after the changes to the screen, cursor, or palette made
+    // in one go
+    #[serde(skip)]
+    on_change: Option<Function>,
+    // Whether a call of `on_change` is already queued
+    #[serde(skip)]
+    change_pending: Rc<Cell<bool>>,
+    // First and last selected cell of the view as `y * width + x`
+    #[serde(skip)]
+    selection: Option<(usize, usize)>,
+    // RGB value the page shows for each color number
+    #[serde(default = "vga_palette")]
+    palette: [[u8; 3]; 16],
+    #[serde(skip)]
+    recording: Option<Recording>,
+    #[serde(skip)]
+    replay: Option<Replay>,
 }
 
 #[wasm_bindgen]
@@ -50,107 +202,660 @@ impl VgaEmulator {
     #[wasm_bindgen(constructor)]
     pub fn new(width: usize, height: usize) -> VgaEmulator {
         console_log!("Initializing VGA Emulator {}x{}", width, height);
-        
-        let mut buffer = Vec::new();
-        for _ in 0..height {
-            let mut row = Vec::new();
-            for _ in 0..width {
-                row.push((' ', Color::White as u8, Color::Black as u8));
-            }
-            buffer.push(row);
-        }
-        
+
+        let (width, height) = (width.max(1), height.max(1));
+        let mut terminals: Vec<_> = (0..TERMINALS)
+            .map(|_| Some(blank_terminal(width, height)))
+            .collect();
+
         VgaEmulator {
-            buffer,
-            width,
-            height,
-            cursor_x: 0,
-            cursor_y: 0,
+            writer: terminals[0].take().expect("terminal 0 was just created"),
+            terminals,
+            active_terminal: 0,
+            view_offset: 0,
+            cursor_visible: true,
+            blink_frame: 0,
+            on_resize: None,
+            on_change: None,
+            change_pending: Rc::default(),
+            selection: None,
+            palette: VGA_PALETTE,
+            recording: None,
+            replay: None,
         }
     }
 
+    // The whole state as JSON: cells, colors, cursor, history, and view, so
+    // a session can be saved and later restored with `deserialize`
     #[wasm_bindgen]
-    pub fn write_string(&mut self, s: &str, fg_color: u8, bg_color: u8) {
-        for ch in s.chars() {
-            if ch == '\n' {
-                self.new_line();
-            } else if ch as u32 >= 32 && (ch as u32) < 127 {
-                self.write_char(ch, fg_color, bg_color);
-            }
-        }
+    pub fn serialize(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("emulator state is always serializable")
     }
 
+    // Restores an emulator from the bytes of `serialize`, failing on
+    // malformed JSON or a state the emulator could not have been in
     #[wasm_bindgen]
-    pub fn write_char(&mut self, ch: char, fg_color: u8, bg_color: u8) {
-        if self.cursor_x >= self.width {
-            self.new_line();
+    pub fn deserialize(bytes: &[u8]) -> Result<VgaEmulator, JsError> {
+        let mut emulator: VgaEmulator = serde_json::from_slice(bytes)?;
+        // Snapshots from before virtual terminals have just the one
+        if emulator.terminals.is_empty() {
+            let buffer = emulator.writer.buffer();
+            let (width, height) = (buffer.width, buffer.height);
+            emulator.terminals = (0..TERMINALS)
+                .map(|_| Some(blank_terminal(width, height)))
+                .collect();
+            emulator.terminals[0] = None;
         }
-        
-        if self.cursor_y < self.height {
-            self.buffer[self.cursor_y][self.cursor_x] = (ch, fg_color, bg_color);
-            self.cursor_x += 1;
+        if !emulator.is_consistent() {
+            return Err(JsError::new("inconsistent emulator state"));
         }
+        emulator.writer.buffer_mut().mark_all_dirty();
+        Ok(emulator)
+    }
+
+    #[wasm_bindgen]
+    pub fn write_string(&mut self, s: &str, fg_color: u8, bg_color: u8) {
+        self.set_color(fg_color, bg_color);
+        self.write(s);
+    }
+
+    // Writes in the current colors, which ANSI escape sequences in `s`
+    // change the same way they do on the kernel's screen
+    #[wasm_bindgen]
+    pub fn write(&mut self, s: &str) {
+        self.record(|| Operation::Write {
+            text: s.to_string(),
+        });
+        self.show_screen();
+        self.writer.write_string(s);
+        self.notify_change();
+    }
+
+    #[wasm_bindgen]
+    pub fn write_char(&mut self, ch: char, fg_color: u8, bg_color: u8) {
+        self.write_string(ch.encode_utf8(&mut [0; 4]), fg_color, bg_color);
     }
 
     #[wasm_bindgen]
     pub fn new_line(&mut self) {
-        self.cursor_x = 0;
-        self.cursor_y += 1;
-        
-        if self.cursor_y >= self.height {
-            // Scroll up
-            for y in 1..self.height {
-                for x in 0..self.width {
-                    self.buffer[y - 1][x] = self.buffer[y][x];
-                }
-            }
-            
-            // Clear last line
-            for x in 0..self.width {
-                self.buffer[self.height - 1][x] = (' ', Color::White as u8, Color::Black as u8);
-            }
-            
-            self.cursor_y = self.height - 1;
-        }
+        self.record(|| Operation::NewLine);
+        self.show_screen();
+        self.writer.new_line();
+        self.notify_change();
     }
 
     #[wasm_bindgen]
     pub fn clear(&mut self) {
-        for y in 0..self.height {
-            for x in 0..self.width {
-                self.buffer[y][x] = (' ', Color::White as u8, Color::Black as u8);
-            }
+        self.record(|| Operation::Clear);
+        self.show_screen();
+        self.writer.clear();
+        self.notify_change();
+    }
+
+    // Scrolls the view `offset` lines back into the history, 0 showing the
+    // screen itself; any write scrolls back to 0, and scrolling ends the
+    // selection. Returns the offset used, which is at most `history_len()`
+    #[wasm_bindgen]
+    pub fn scroll_view(&mut self, offset: usize) -> usize {
+        let offset = offset.min(self.history_len());
+        if offset != self.view_offset {
+            self.view_offset = offset;
+            self.selection = None;
+            self.writer.buffer_mut().mark_all_dirty();
+            self.notify_change();
         }
-        self.cursor_x = 0;
-        self.cursor_y = 0;
+        offset
+    }
+
+    #[wasm_bindgen]
+    pub fn history_len(&self) -> usize {
+        self.writer.buffer().history.len()
+    }
+
+    // Rows of the view that changed since the last call, in order, so a
+    // renderer can repaint just those; everything starts out changed
+    #[wasm_bindgen]
+    pub fn take_dirty_rows(&mut self) -> Vec<usize> {
+        let dirty = &mut self.writer.buffer_mut().dirty;
+        let rows = (0..dirty.len()).filter(|&row| dirty[row]).collect();
+        dirty.fill(false);
+        rows
     }
 
+    // The functions below show the view, which is the screen unless
+    // `scroll_view` moved it into the history
     #[wasm_bindgen]
     pub fn get_buffer_as_string(&self) -> String {
         let mut result = String::new();
-        for row in &self.buffer {
-            for &(ch, _fg, _bg) in row {
-                result.push(ch);
-            }
+        for row in self.view_rows() {
+            result.extend(row.iter().map(|cell| cell.character()));
             result.push('\n');
         }
         result
     }
 
+    // The view as the page shows it, selection included, as a `<pre>` of
+    // one `<span>` per run of cells in the same colors, styled with the
+    // palette, to embed in a web page
+    #[wasm_bindgen]
+    pub fn to_html(&self) -> String {
+        let rgb = |color: Color| {
+            let [r, g, b] = self.palette[color as usize];
+            format!("#{:02x}{:02x}{:02x}", r, g, b)
+        };
+        let mut html = format!(
+            "<pre style=\"background-color:{};font-family:monospace\">",
+            rgb(Color::Black)
+        );
+        for y in 0..self.get_height() {
+            let row = self.shown_row(y);
+            for run in row.chunk_by(|a, b| a.color_code == b.color_code) {
+                let colors = run[0].color_code;
+                html.push_str(&format!(
+                    "<span style=\"color:{};background-color:{}\">",
+                    rgb(colors.foreground()),
+                    rgb(colors.background())
+                ));
+                for cell in run {
+                    match cell.character() {
+                        '&' => html.push_str("&amp;"),
+                        '<' => html.push_str("&lt;"),
+                        '>' => html.push_str("&gt;"),
+                        ch => html.push(ch),
+                    }
+                }
+                html.push_str("</span>");
+            }
+            html.push('\n');
+        }
+        html.push_str("</pre>");
+        html
+    }
+
+    // The view as the page shows it, selection and cursor included, as a PNG
+    // image of GLYPH_WIDTH by GLYPH_HEIGHT pixels per cell in the palette's
+    // colors, for screenshots that do not depend on the browser's fonts
+    #[wasm_bindgen]
+    pub fn to_png(&self) -> Vec<u8> {
+        let (width, height) = (self.get_width(), self.get_height());
+        let stride = width * GLYPH_WIDTH;
+        let mut pixels = vec![[0; 4]; stride * height * GLYPH_HEIGHT];
+        for y in 0..height {
+            for (x, cell) in self.shown_row(y).into_iter().enumerate() {
+                let glyph = GLYPHS[usize::from(cell.ascii_character)];
+                let fg = self.palette[cell.color_code.foreground() as usize];
+                let bg = self.palette[cell.color_code.background() as usize];
+                for (line, bits) in glyph.iter().enumerate() {
+                    let start = (y * GLYPH_HEIGHT + line) * stride + x * GLYPH_WIDTH;
+                    let pixels = &mut pixels[start..start + GLYPH_WIDTH];
+                    for (column, pixel) in pixels.iter_mut().enumerate() {
+                        let [r, g, b] = if bits & (0x80 >> column) != 0 { fg } else { bg };
+                        *pixel = [r, g, b, 0xff];
+                    }
+                }
+            }
+        }
+
+        // The cursor is an underline in the color of the text under it,
+        // as on VGA hardware
+        let (row, column) = self.writer.cursor();
+        let (row, column) = (row + self.view_offset, column.min(width - 1));
+        if self.cursor_shown() && row < height {
+            let cell = self.shown_row(row)[column];
+            let [r, g, b] = self.palette[cell.color_code.foreground() as usize];
+            for line in GLYPH_HEIGHT - 2..GLYPH_HEIGHT {
+                let start = (row * GLYPH_HEIGHT + line) * stride + column * GLYPH_WIDTH;
+                pixels[start..start + GLYPH_WIDTH].fill([r, g, b, 0xff]);
+            }
+        }
+
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(
+            &mut png,
+            (width * GLYPH_WIDTH) as u32,
+            (height * GLYPH_HEIGHT) as u32,
+        );
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(pixels.as_flattened()))
+            .expect("encoding into memory cannot fail");
+        png
+    }
+
+    // The cell at column `x` and row `y` of the view as `{ch, fg, bg}`, or
+    // `undefined` outside of it
+    #[wasm_bindgen]
+    pub fn get_cell(&self, x: usize, y: usize) -> JsValue {
+        let Some(cell) = self.view_rows().nth(y).and_then(|row| row.get(x)) else {
+            return JsValue::UNDEFINED;
+        };
+        let cell = self.shown_cell(y, x, *cell);
+        let cell = CellInfo {
+            ch: cell.character(),
+            fg: cell.color_code.foreground() as u8,
+            bg: cell.color_code.background() as u8,
+        };
+        serde_wasm_bindgen::to_value(&cell).expect("cells are always serializable")
+    }
+
+    /// @deprecated Use `get_cell`: this "char:fg:bg" string is ambiguous
+    /// when the character is itself `:`.
     #[wasm_bindgen]
     pub fn get_char_at(&self, x: usize, y: usize) -> String {
-        if x < self.width && y < self.height {
-            let (ch, fg, bg) = self.buffer[y][x];
-            format!("{}:{}:{}", ch, fg, bg)
+        if let Some(cell) = self.view_rows().nth(y).and_then(|row| row.get(x)) {
+            format!(
+                "{}:{}:{}",
+                cell.character(),
+                cell.color_code.foreground() as u8,
+                cell.color_code.background() as u8
+            )
         } else {
             " :15:0".to_string()
         }
     }
 
+    // The whole screen as (character, foreground, background) byte
+    // triples, row by row; characters are code page 437, so bytes outside
+    // printable ASCII show as 0xfe. Selected cells have their colors swapped
+    #[wasm_bindgen]
+    pub fn get_cells(&self) -> Uint8Array {
+        let cells: Vec<ScreenChar> = (0..self.writer.buffer().height)
+            .flat_map(|y| self.shown_row(y))
+            .collect();
+        Uint8Array::from(&cell_bytes(&cells)[..])
+    }
+
+    // The screen's cells as they are stored, (character, color code) byte
+    // pairs row by row, without copying. The array views the module's
+    // memory directly, so it shows the screen rather than the scrolled view
+    // or the selection, and is only valid until the next call into the
+    // module
+    #[wasm_bindgen]
+    pub fn get_cells_view(&self) -> Uint8Array {
+        let cells = &self.writer.buffer().cells;
+        // ScreenChar is two bytes without padding (`repr(C)`), and the
+        // caller is told not to keep the view past any change to `cells`.
+        unsafe {
+            let bytes = std::slice::from_raw_parts(
+                cells.as_ptr().cast::<u8>(),
+                mem::size_of_val(cells.as_slice()),
+            );
+            Uint8Array::view(bytes)
+        }
+    }
+
+    // Row `y` in the layout of `get_cells`, empty if there is no such row
+    #[wasm_bindgen]
+    pub fn get_row_cells(&self, y: usize) -> Uint8Array {
+        Uint8Array::from(&cell_bytes(&self.shown_row(y))[..])
+    }
+
+    #[wasm_bindgen]
+    pub fn get_width(&self) -> usize {
+        self.writer.buffer().width
+    }
+
+    #[wasm_bindgen]
+    pub fn get_height(&self) -> usize {
+        self.writer.buffer().height
+    }
+
     #[wasm_bindgen]
     pub fn get_cursor_position(&self) -> String {
-        format!("{}:{}", self.cursor_x, self.cursor_y)
+        let (row, column) = self.writer.cursor();
+        format!("{}:{}", column, row)
+    }
+
+    // Changes the screen size, keeping the text around the cursor: lines
+    // are cropped or padded on the right, and rows that no longer fit go to
+    // the history. Every row becomes dirty, and the resize callback runs
+    #[wasm_bindgen]
+    pub fn resize(&mut self, width: usize, height: usize) {
+        let (width, height) = (width.max(1), height.max(1));
+        let buffer = self.writer.buffer();
+        if (width, height) == (buffer.width, buffer.height) {
+            return;
+        }
+        self.record(|| Operation::Resize { width, height });
+
+        self.view_offset = 0;
+        resize_terminal(&mut self.writer, width, height);
+        for terminal in self.terminals.iter_mut().flatten() {
+            resize_terminal(terminal, width, height);
+        }
+
+        if let Some(callback) = &self.on_resize {
+            let _ = callback.call2(&JsValue::NULL, &width.into(), &height.into());
+        }
+        self.notify_change();
+    }
+
+    // Shows virtual terminal `n`, counting from 0, like Alt+F1 to Alt+F4 on
+    // the kernel's console; each has its own screen, history, cursor, and
+    // colors, and all have the size of the emulator
+    #[wasm_bindgen]
+    pub fn switch_terminal(&mut self, n: usize) -> Result<(), JsError> {
+        if n >= TERMINALS {
+            return Err(JsError::new(&format!(
+                "there are only {} virtual terminals",
+                TERMINALS
+            )));
+        }
+        if n == self.active_terminal {
+            return Ok(());
+        }
+        self.record(|| Operation::SwitchTerminal { terminal: n });
+
+        self.show_screen();
+        let terminal = self.terminals[n]
+            .take()
+            .expect("inactive terminals are kept");
+        let previous = mem::replace(&mut self.writer, terminal);
+        self.terminals[self.active_terminal] = Some(previous);
+        self.active_terminal = n;
+        self.writer.buffer_mut().mark_all_dirty();
+        self.notify_change();
+        Ok(())
+    }
+
+    #[wasm_bindgen]
+    pub fn active_terminal(&self) -> usize {
+        self.active_terminal
+    }
+
+    #[wasm_bindgen]
+    pub fn terminal_count(&self) -> usize {
+        TERMINALS
+    }
+
+    // Selects the cells of the view from column `x0` of row `y0` to column
+    // `x1` of row `y1`, both included and in either order, running on from
+    // the end of one row to the start of the next like text; positions
+    // outside the view are moved to its edge. Output ends the selection
+    #[wasm_bindgen]
+    pub fn set_selection(&mut self, x0: usize, y0: usize, x1: usize, y1: usize) {
+        let buffer = self.writer.buffer();
+        let (width, height) = (buffer.width, buffer.height);
+        let index = |x: usize, y: usize| y.min(height - 1) * width + x.min(width - 1);
+        let (a, b) = (index(x0, y0), index(x1, y1));
+        self.selection = Some((a.min(b), a.max(b)));
+        self.writer.buffer_mut().mark_all_dirty();
+        self.notify_change();
+    }
+
+    #[wasm_bindgen]
+    pub fn clear_selection(&mut self) {
+        if self.selection.take().is_some() {
+            self.writer.buffer_mut().mark_all_dirty();
+            self.notify_change();
+        }
+    }
+
+    // The selected text, one line per row without trailing spaces, empty if
+    // nothing is selected
+    #[wasm_bindgen]
+    pub fn get_selected_text(&self) -> String {
+        let Some((first, last)) = self.selection else {
+            return String::new();
+        };
+        let width = self.writer.buffer().width;
+        let (first_row, last_row) = (first / width, last / width);
+        let lines: Vec<String> = self
+            .view_rows()
+            .enumerate()
+            .filter(|&(y, _)| (first_row..=last_row).contains(&y))
+            .map(|(y, row)| {
+                let start = if y == first_row { first % width } else { 0 };
+                let end = if y == last_row {
+                    last % width + 1
+                } else {
+                    width
+                };
+                let line: String = row[start..end]
+                    .iter()
+                    .copied()
+                    .map(ScreenChar::character)
+                    .collect();
+                line.trim_end().to_string()
+            })
+            .collect();
+        lines.join("\n")
+    }
+
+    // Registers `callback()` to run when the screen, the cursor, or the
+    // palette changed, once per batch of changes: it runs in a microtask,
+    // after the code making them. `undefined` unregisters it
+    #[wasm_bindgen]
+    pub fn on_change(&mut self, callback: Option<Function>) {
+        self.on_change = callback;
+    }
+
+    // Registers `callback(width, height)` to run after every resize
+    #[wasm_bindgen]
+    pub fn set_resize_callback(&mut self, callback: Function) {
+        self.on_resize = Some(callback);
+    }
+
+    // Shows color number `index` (low four bits) as `r`, `g`, `b`, for
+    // themes; cells keep their color numbers, and every row becomes dirty
+    #[wasm_bindgen]
+    pub fn set_palette_color(&mut self, index: u8, r: u8, g: u8, b: u8) {
+        self.palette[usize::from(index & 0xf)] = [r, g, b];
+        self.writer.buffer_mut().mark_all_dirty();
+        self.notify_change();
+    }
+
+    // The 16 colors as (red, green, blue) byte triples, by color number
+    #[wasm_bindgen]
+    pub fn get_palette(&self) -> Uint8Array {
+        Uint8Array::from(&self.palette.concat()[..])
+    }
+
+    // Goes back to the colors of VGA hardware
+    #[wasm_bindgen]
+    pub fn reset_palette(&mut self) {
+        self.palette = VGA_PALETTE;
+        self.writer.buffer_mut().mark_all_dirty();
+        self.notify_change();
     }
+
+    #[wasm_bindgen]
+    pub fn set_cursor_visible(&mut self, visible: bool) {
+        self.cursor_visible = visible;
+        self.notify_change();
+    }
+
+    // Advances the blink by one frame; call it once per displayed frame.
+    // Returns whether the cursor block should be drawn now
+    #[wasm_bindgen]
+    pub fn cursor_blink_tick(&mut self) -> bool {
+        let shown = self.cursor_shown();
+        self.blink_frame = (self.blink_frame + 1) % (2 * BLINK_FRAMES);
+        if self.cursor_shown() != shown {
+            self.notify_change();
+        }
+        self.cursor_shown()
+    }
+
+    // The cursor as "x:y:visible:shown", where `visible` is whether it is
+    // enabled at all and `shown` whether the blink has it on right now
+    // (both 0 or 1); x is kept on screen after a full line. y counts rows
+    // of the view, and the cursor is not shown while scrolled out of it
+    #[wasm_bindgen]
+    pub fn get_cursor_state(&self) -> String {
+        let (row, column) = self.writer.cursor();
+        let column = column.min(self.writer.buffer().width - 1);
+        let row = row + self.view_offset;
+        let shown = self.cursor_shown() && row < self.writer.buffer().height;
+        format!(
+            "{}:{}:{}:{}",
+            column,
+            row,
+            u8::from(self.cursor_visible),
+            u8::from(shown)
+        )
+    }
+
+    // Returns the view to the screen and ends the selection, as output does
+    fn show_screen(&mut self) {
+        self.scroll_view(0);
+        self.clear_selection();
+    }
+
+    // Queues a call of the change callback, unless one is queued already
+    fn notify_change(&self) {
+        let Some(callback) = &self.on_change else {
+            return;
+        };
+        if self.change_pending.replace(true) {
+            return;
+        }
+        let (callback, pending) = (callback.clone(), Rc::clone(&self.change_pending));
+        queue_microtask(&Closure::once_into_js(move || {
+            pending.set(false);
+            let _ = callback.call0(&JsValue::NULL);
+        }));
+    }
+
+    fn cursor_shown(&self) -> bool {
+        self.cursor_visible && self.blink_frame < BLINK_FRAMES
+    }
+
+    fn is_consistent(&self) -> bool {
+        let buffer = self.writer.buffer();
+        let same_size = |terminal: &TextWriter<Cells>| {
+            let other = terminal.buffer();
+            (other.width, other.height) == (buffer.width, buffer.height)
+        };
+        terminal_is_consistent(&self.writer)
+            && self.view_offset <= buffer.history.len()
+            && self.blink_frame < 2 * BLINK_FRAMES
+            && self.terminals.len() == TERMINALS
+            && self
+                .terminals
+                .iter()
+                .enumerate()
+                .all(|(n, terminal)| match terminal {
+                    None => n == self.active_terminal,
+                    Some(terminal) => terminal_is_consistent(terminal) && same_size(terminal),
+                })
+    }
+
+    // `cell` at row `y` and column `x` of the view as the page shows it,
+    // with swapped colors if it is selected
+    fn shown_cell(&self, y: usize, x: usize, cell: ScreenChar) -> ScreenChar {
+        let index = y * self.writer.buffer().width + x;
+        match self.selection {
+            Some((first, last)) if (first..=last).contains(&index) => ScreenChar {
+                color_code: cell.color_code.inverted(),
+                ..cell
+            },
+            _ => cell,
+        }
+    }
+
+    // Row `y` of the view as the page shows it, empty if there is none
+    fn shown_row(&self, y: usize) -> Vec<ScreenChar> {
+        let row = self.view_rows().nth(y).unwrap_or(&[]);
+        row.iter()
+            .enumerate()
+            .map(|(x, &cell)| self.shown_cell(y, x, cell))
+            .collect()
+    }
+
+    // Rows of the view, top to bottom
+    fn view_rows(&self) -> impl Iterator<Item = &[ScreenChar]> {
+        let buffer = self.writer.buffer();
+        let history = buffer.history.len();
+        buffer
+            .history
+            .range(history - self.view_offset..)
+            .map(Vec::as_slice)
+            .chain(buffer.cells.chunks(buffer.width))
+            .take(buffer.height)
+    }
+
+    fn set_color(&mut self, fg_color: u8, bg_color: u8) {
+        self.record(|| Operation::Color {
+            fg: fg_color,
+            bg: bg_color,
+        });
+        self.writer.set_color_code(ColorCode::new(
+            Color::from_u8(fg_color),
+            Color::from_u8(bg_color),
+        ));
+    }
+}
+
+// A cell as `get_cell` returns it to JavaScript
+#[derive(Serialize)]
+struct CellInfo {
+    ch: char,
+    fg: u8,
+    bg: u8,
+}
+
+// A virtual terminal of `width` x `height` blank cells, white on black
+fn blank_terminal(width: usize, height: usize) -> TextWriter<Cells> {
+    let color_code = ColorCode::new(Color::White, Color::Black);
+    let blank = ScreenChar {
+        ascii_character: b' ',
+        color_code,
+    };
+    let cells = Cells {
+        cells: vec![blank; width * height],
+        width,
+        height,
+        history: VecDeque::new(),
+        dirty: vec![true; height],
+    };
+    TextWriter::new(cells, color_code)
+}
+
+// Resizes the screen of `terminal` as `Cells::resize` does, keeping the
+// cursor on the same text and padding with blanks in the current colors
+fn resize_terminal(terminal: &mut TextWriter<Cells>, width: usize, height: usize) {
+    terminal.set_pointer(None);
+    let (row, column) = terminal.cursor();
+    let blank = ScreenChar {
+        ascii_character: b' ',
+        color_code: terminal.color_code(),
+    };
+    let row = terminal.buffer_mut().resize(width, height, row, blank);
+    terminal.set_cursor(row, column);
+}
+
+fn terminal_is_consistent(terminal: &TextWriter<Cells>) -> bool {
+    let buffer = terminal.buffer();
+    let (row, column) = terminal.cursor();
+    buffer.width > 0
+        && buffer.height > 0
+        && buffer.cells.len() == buffer.width * buffer.height
+        && buffer.history.len() <= HISTORY_LINES
+        && buffer.history.iter().all(|line| line.len() == buffer.width)
+        && row < buffer.height
+        && column <= buffer.width
+}
+
+// Palette of snapshots that have none, taken before it could change
+fn vga_palette() -> [[u8; 3]; 16] {
+    VGA_PALETTE
+}
+
+// Flattens cells into (character, foreground, background) byte triples
+fn cell_bytes(cells: &[ScreenChar]) -> Vec<u8> {
+    cells
+        .iter()
+        .flat_map(|cell| {
+            [
+                cell.ascii_character,
+                cell.color_code.foreground() as u8,
+                cell.color_code.background() as u8,
+            ]
+        })
+        .collect()
 }
 
 // Initialize function called when the WASM module loads
@@ -158,20 +863,3 @@ impl VgaEmulator {
 pub fn main() {
     console_log!("EspressOS WASM module loaded!");
 }
-
-// Demo function that simulates the OS welcome message
-#[wasm_bindgen]
-pub fn simulate_os_boot() -> String {
-    console_log!("Simulating OS boot sequence...");
-    
-    let mut output = String::new();
-    output.push_str("Hello World!\n");
-    output.push_str("Welcome to EspressOS!\n");
-    output.push_str("\n");
-    output.push_str("EspressOS WebAssembly Demo\n");
-    output.push_str("==========================\n");
-    output.push_str("This is a web version of the EspressOS kernel output.\n");
-    output.push_str("The actual kernel runs on bare metal x86_64 hardware.\n");
-    
-    output
-}
\ No newline at end of file
@@ -0,0 +1,102 @@
+// COM1 for the website demo: collects what the kernel's serial console
+// would send, next to what `VgaEmulator` shows, and holds input injected by
+// the page until it is received. Bytes go out as the kernel sends them,
+// colors as ANSI escape sequences, so a terminal widget can show them.
+
+use std::collections::VecDeque;
+
+use espress_vga_core::Color;
+use wasm_bindgen::prelude::*;
+
+// Bytes of output kept until read; older ones are dropped
+const OUTPUT_SIZE: usize = 64 * 1024;
+
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct SerialEmulator {
+    // Bytes sent and not read yet, oldest first
+    output: VecDeque<u8>,
+    // Bytes injected and not received yet, oldest first
+    input: VecDeque<u8>,
+}
+
+#[wasm_bindgen]
+impl SerialEmulator {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> SerialEmulator {
+        SerialEmulator::default()
+    }
+
+    // Sends `s` like the kernel's serial console; backspace and DEL go out
+    // as backspace, space, backspace, as the UART driver sends them
+    #[wasm_bindgen]
+    pub fn write(&mut self, s: &str) {
+        for byte in s.bytes() {
+            match byte {
+                0x08 | 0x7f => self.send(b"\x08 \x08"),
+                byte => self.send(&[byte]),
+            }
+        }
+    }
+
+    // Sends `s` in VGA color `color`, wrapped in the SGR sequences the
+    // kernel's serial console uses
+    #[wasm_bindgen]
+    pub fn write_colored(&mut self, s: &str, color: u8) {
+        self.write(&format!(
+            "\x1b[{}m{}\x1b[0m",
+            ansi_color(Color::from_u8(color)),
+            s
+        ));
+    }
+
+    // Everything sent since the last read, as text
+    #[wasm_bindgen]
+    pub fn take_output(&mut self) -> String {
+        String::from_utf8_lossy(&self.take_output_bytes()).into_owned()
+    }
+
+    // Everything sent since the last read, as bytes
+    #[wasm_bindgen]
+    pub fn take_output_bytes(&mut self) -> Vec<u8> {
+        self.output.drain(..).collect()
+    }
+
+    #[wasm_bindgen]
+    pub fn output_len(&self) -> usize {
+        self.output.len()
+    }
+
+    // Queues `s` as if typed on the terminal at the other end of the line
+    #[wasm_bindgen]
+    pub fn inject_input(&mut self, s: &str) {
+        self.input.extend(s.bytes());
+    }
+
+    // The next received byte, like the kernel's `serial::try_receive`, or
+    // `undefined` if none is waiting
+    #[wasm_bindgen]
+    pub fn try_receive(&mut self) -> Option<u8> {
+        self.input.pop_front()
+    }
+
+    #[wasm_bindgen]
+    pub fn has_input(&self) -> bool {
+        !self.input.is_empty()
+    }
+
+    fn send(&mut self, bytes: &[u8]) {
+        self.output.extend(bytes);
+        let excess = self.output.len().saturating_sub(OUTPUT_SIZE);
+        self.output.drain(..excess);
+    }
+}
+
+// The ANSI SGR parameter selecting `color` as the text color, as the
+// kernel's `console::ansi_color` computes it
+pub(crate) fn ansi_color(color: Color) -> u8 {
+    let vga = color as u8;
+    let rgb = (vga & 0b001) << 2 | vga & 0b010 | (vga & 0b100) >> 2;
+    let bright = if vga & 0b1000 != 0 { 60 } else { 0 };
+    30 + bright + rgb
+}
@@ -0,0 +1,205 @@
+// Recording and replay of what is written to a `VgaEmulator`, so a demo
+// can play back a canned session, like asciinema, instead of a video.
+// Sessions are JSON: the screen size and the operations with the time they
+// happened, in milliseconds since the recording started.
+
+use js_sys::Date;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::{VgaEmulator, TERMINALS};
+
+// A call on the emulator that changes the screen
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub(crate) enum Operation {
+    Write { text: String },
+    Color { fg: u8, bg: u8 },
+    NewLine,
+    Clear,
+    Resize { width: usize, height: usize },
+    SwitchTerminal { terminal: usize },
+}
+
+#[derive(Serialize, Deserialize)]
+struct Event {
+    time: f64,
+    #[serde(flatten)]
+    operation: Operation,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Session {
+    width: usize,
+    height: usize,
+    // Sessions recorded before the start state was kept have none
+    #[serde(default)]
+    start: Option<Start>,
+    events: Vec<Event>,
+}
+
+// What the operations of a session build on besides the screen size
+#[derive(Serialize, Deserialize)]
+struct Start {
+    terminal: usize,
+    fg: u8,
+    bg: u8,
+    row: usize,
+    column: usize,
+}
+
+pub(crate) struct Recording {
+    // `Date::now()` when the recording started
+    start: f64,
+    session: Session,
+}
+
+pub(crate) struct Replay {
+    session: Session,
+    // Index of the next event to apply
+    next: usize,
+    // `Date::now()` when the replay started
+    start: f64,
+    speed: f64,
+}
+
+impl VgaEmulator {
+    // Adds the operation `operation()` returns to the recording, if one is
+    // running and no replay is, so a replay does not record itself
+    pub(crate) fn record(&mut self, operation: impl FnOnce() -> Operation) {
+        if self.replay.is_some() {
+            return;
+        }
+        if let Some(recording) = &mut self.recording {
+            recording.session.events.push(Event {
+                time: Date::now() - recording.start,
+                operation: operation(),
+            });
+        }
+    }
+
+    fn apply(&mut self, operation: Operation) {
+        match operation {
+            Operation::Write { text } => self.write(&text),
+            Operation::Color { fg, bg } => self.set_color(fg, bg),
+            Operation::NewLine => self.new_line(),
+            Operation::Clear => self.clear(),
+            Operation::Resize { width, height } => self.resize(width, height),
+            Operation::SwitchTerminal { terminal } => {
+                let _ = self.switch_terminal(terminal);
+            }
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl VgaEmulator {
+    // Starts recording the writes, color changes, clears, resizes, and
+    // terminal switches, in place of any recording already running
+    #[wasm_bindgen]
+    pub fn start_recording(&mut self) {
+        let buffer = self.writer.buffer();
+        let color_code = self.writer.color_code();
+        let (row, column) = self.writer.cursor();
+        self.recording = Some(Recording {
+            start: Date::now(),
+            session: Session {
+                width: buffer.width,
+                height: buffer.height,
+                start: Some(Start {
+                    terminal: self.active_terminal,
+                    fg: color_code.foreground() as u8,
+                    bg: color_code.background() as u8,
+                    row,
+                    column,
+                }),
+                events: Vec::new(),
+            },
+        });
+    }
+
+    // Ends the recording and returns it as JSON for `replay`; empty if
+    // none was running
+    #[wasm_bindgen]
+    pub fn stop_recording(&mut self) -> Vec<u8> {
+        self.recording.take().map_or_else(Vec::new, |recording| {
+            serde_json::to_vec(&recording.session).expect("sessions are always serializable")
+        })
+    }
+
+    #[wasm_bindgen]
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    // Starts replaying a session from `stop_recording` on a cleared screen
+    // of the recorded size, in the virtual terminal, colors, and cursor
+    // position the recording started with, `speed` times as fast as it was
+    // recorded. `replay_tick` then applies the operations as their time
+    // comes; a recording that is running leaves them out
+    #[wasm_bindgen]
+    pub fn replay(&mut self, data: &[u8], speed: f64) -> Result<(), JsError> {
+        if speed.is_nan() || speed <= 0.0 {
+            return Err(JsError::new("replay speed must be positive"));
+        }
+        let mut session: Session = serde_json::from_slice(data)?;
+        let start = session.start.take();
+        if matches!(&start, Some(start) if start.terminal >= TERMINALS) {
+            return Err(JsError::new("session starts in a missing terminal"));
+        }
+        let (width, height) = (session.width, session.height);
+        self.replay = Some(Replay {
+            session,
+            next: 0,
+            start: Date::now(),
+            speed,
+        });
+
+        if let Some(start) = &start {
+            self.switch_terminal(start.terminal)?;
+        }
+        self.resize(width, height);
+        if let Some(start) = &start {
+            self.set_color(start.fg, start.bg);
+        }
+        self.clear();
+        if let Some(start) = start {
+            self.writer.set_cursor(start.row, start.column);
+        }
+        Ok(())
+    }
+
+    // Applies the operations of the replay that are due; call it once per
+    // displayed frame. Returns whether the replay is still running
+    #[wasm_bindgen]
+    pub fn replay_tick(&mut self) -> bool {
+        let Some(replay) = &mut self.replay else {
+            return false;
+        };
+        let now = (Date::now() - replay.start) * replay.speed;
+        let mut due = Vec::new();
+        while let Some(event) = replay.session.events.get(replay.next) {
+            if event.time > now {
+                break;
+            }
+            due.push(event.operation.clone());
+            replay.next += 1;
+        }
+        let running = replay.next < replay.session.events.len();
+
+        // The replay stays set while its operations run, so they are not
+        // recorded
+        for operation in due {
+            self.apply(operation);
+        }
+        if !running {
+            self.replay = None;
+        }
+        running
+    }
+
+    #[wasm_bindgen]
+    pub fn stop_replay(&mut self) {
+        self.replay = None;
+    }
+}
@@ -0,0 +1,168 @@
+// Interactive shell for the website demo: reads keys from the browser and
+// edits a line after the prompt, with the cursor keys the kernel's line
+// editor lacks, then runs it through the kernel's command interpreter (see
+// `kernel`), whose output is written to a `VgaEmulator` the page renders.
+// Lines are redrawn with the same ANSI sequences the kernel's writer
+// understands.
+
+use espress_vga_core::{Color, TextBuffer};
+use wasm_bindgen::prelude::*;
+
+use crate::kernel::interpreter::{Command, Shell, PROMPT};
+use crate::{kernel, VgaEmulator};
+
+// Columns the prompt takes on screen, without its escape sequences
+const PROMPT_WIDTH: usize = 9;
+
+// The commands besides the interpreter's built-in `help`
+const COMMANDS: &[Command] = &[
+    Command {
+        name: "clear",
+        help: "Clear the screen",
+        run: clear,
+    },
+    Command {
+        name: "about",
+        help: "Tell what EspressOS is",
+        run: about,
+    },
+    Command {
+        name: "colors",
+        help: "Show the 16 VGA colors",
+        run: colors,
+    },
+    Command {
+        name: "sched",
+        help: "Run the kernel's scheduler on demo threads",
+        run: sched,
+    },
+];
+
+#[wasm_bindgen]
+pub struct ShellDemo {
+    shell: Shell,
+    // The line being edited, printable ASCII only
+    line: String,
+    // Position of the cursor in `line`
+    cursor: usize,
+}
+
+#[wasm_bindgen]
+impl ShellDemo {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> ShellDemo {
+        ShellDemo {
+            shell: Shell::new(COMMANDS),
+            line: String::new(),
+            cursor: 0,
+        }
+    }
+
+    // Greets the user and shows the first prompt
+    #[wasm_bindgen]
+    pub fn start(&mut self, emulator: &mut VgaEmulator) {
+        emulator.write("Welcome to EspressOS! Type `help` for a list of commands.\n");
+        self.prompt(emulator);
+    }
+
+    // Handles a key, named as in `KeyboardEvent.key`; returns whether the
+    // shell used it, so the page can keep the browser from acting on it
+    #[wasm_bindgen]
+    pub fn handle_key(&mut self, emulator: &mut VgaEmulator, key: &str) -> bool {
+        match key {
+            "Enter" => {
+                emulator.write("\n");
+                let line = std::mem::take(&mut self.line);
+                self.cursor = 0;
+                self.run(emulator, &line);
+                self.prompt(emulator);
+                return true;
+            }
+            "Backspace" if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.line.remove(self.cursor);
+            }
+            "Delete" if self.cursor < self.line.len() => {
+                self.line.remove(self.cursor);
+            }
+            "ArrowLeft" => self.cursor = self.cursor.saturating_sub(1),
+            "ArrowRight" => self.cursor = (self.cursor + 1).min(self.line.len()),
+            "Home" => self.cursor = 0,
+            "End" => self.cursor = self.line.len(),
+            "Backspace" | "Delete" => {}
+            _ => {
+                let mut chars = key.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(ch @ ' '..='~'), None) => {
+                        // The line stays on one screen row, with room for
+                        // the cursor after it.
+                        let width = emulator.writer.buffer().width();
+                        if PROMPT_WIDTH + self.line.len() + 1 >= width {
+                            return true;
+                        }
+                        self.line.insert(self.cursor, ch);
+                        self.cursor += 1;
+                    }
+                    _ => return false,
+                }
+            }
+        }
+        self.redraw(emulator);
+        true
+    }
+
+    fn prompt(&self, emulator: &mut VgaEmulator) {
+        if emulator.writer.cursor().1 != 0 {
+            emulator.write("\n");
+        }
+        emulator.write(PROMPT);
+    }
+
+    // Rewrites the line after the prompt and puts the cursor back in it
+    fn redraw(&self, emulator: &mut VgaEmulator) {
+        let start = PROMPT_WIDTH + 1;
+        emulator.write(&format!(
+            "\x1b[{}G{}\x1b[K\x1b[{}G",
+            start,
+            self.line,
+            start + self.cursor
+        ));
+    }
+
+    fn run(&self, emulator: &mut VgaEmulator, line: &str) {
+        self.shell.execute(line);
+        kernel::flush(emulator);
+    }
+}
+
+impl Default for ShellDemo {
+    fn default() -> Self {
+        ShellDemo::new()
+    }
+}
+
+fn clear(_: &[&str]) {
+    print!("\x1b[2J\x1b[H");
+}
+
+fn about(_: &[&str]) {
+    println!("EspressOS is a hobby operating system kernel written in Rust.");
+    println!("This demo runs its VGA text engine, shell, and scheduler in the");
+    println!("browser through WebAssembly.");
+}
+
+fn colors(_: &[&str]) {
+    for ansi in 0..16u8 {
+        // SGR 40-47 selects the normal background colors, 100-107 the bright
+        let code = if ansi < 8 { 40 + ansi } else { 100 + ansi - 8 };
+        let name = format!("{:?}", Color::from_ansi(ansi));
+        print!("\x1b[{}m  \x1b[0m {:<13}", code, name);
+        if ansi % 4 == 3 {
+            println!();
+        }
+    }
+}
+
+fn sched(_: &[&str]) {
+    kernel::scheduler_demo();
+}